@@ -1,6 +1,7 @@
 #![no_main]
 use libfuzzer_sys::fuzz_target;
-use reference_node::network::protocol::ProtocolParser;
+use reference_node::network::netaddr::{NetAddr, Network};
+use reference_node::network::protocol::{AddrV2Message, ProtocolMessage, ProtocolParser};
 
 fuzz_target!(|data: &[u8]| {
     // Fuzz protocol message parsing with malformed/corrupted data
@@ -108,4 +109,28 @@ fuzz_target!(|data: &[u8]| {
         let all_ff = vec![0xFFu8; data.len().min(1000)];
         let _all_ff = ProtocolParser::parse_message(&all_ff);
     }
+
+    // 11. Raw addrv2 payloads (BIP155): whatever decodes must survive a
+    // wire round trip unchanged, on every network type
+    if let Ok(addrv2) = AddrV2Message::decode(data) {
+        let message = ProtocolMessage::AddrV2(addrv2.clone());
+        let serialized =
+            ProtocolParser::serialize_message(&message).expect("addrv2 must serialize");
+        match ProtocolParser::parse_message(&serialized) {
+            Ok(ProtocolMessage::AddrV2(parsed)) => assert_eq!(parsed, addrv2),
+            other => panic!("addrv2 round trip failed: {:?}", other),
+        }
+
+        for address in &addrv2.addresses {
+            // CJDNS addresses print as IPv6, everything else parses back
+            if address.addr.network() != Network::Cjdns {
+                let parsed: NetAddr = address
+                    .addr
+                    .to_string()
+                    .parse()
+                    .expect("address string must parse");
+                assert_eq!(parsed, address.addr);
+            }
+        }
+    }
 });
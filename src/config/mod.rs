@@ -4,6 +4,8 @@
 
 #[cfg(feature = "fibre")]
 use crate::network::fibre;
+use crate::network::netaddr::Network;
+use crate::network::socks5::{ProxySettings, Socks5Proxy};
 use crate::network::transport::TransportPreference;
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
@@ -154,6 +156,11 @@ pub struct RequestTimeoutConfig {
     /// Timeout for RPC operations (seconds)
     #[serde(default = "default_rpc_timeout")]
     pub rpc_timeout_seconds: u64,
+
+    /// How long a peer may hold up the block download window before it is
+    /// disconnected (seconds)
+    #[serde(default = "default_block_stall_timeout")]
+    pub block_stall_timeout_seconds: u64,
 }
 
 fn default_async_request_timeout() -> u64 {
@@ -184,6 +191,10 @@ fn default_rpc_timeout() -> u64 {
     60 // 60 seconds
 }
 
+fn default_block_stall_timeout() -> u64 {
+    10 // 10 seconds
+}
+
 impl Default for RequestTimeoutConfig {
     fn default() -> Self {
         Self {
@@ -194,6 +205,7 @@ impl Default for RequestTimeoutConfig {
             storage_timeout_seconds: 10,
             network_timeout_seconds: 30,
             rpc_timeout_seconds: 60,
+            block_stall_timeout_seconds: 10,
         }
     }
 }
@@ -324,6 +336,16 @@ pub struct NodeConfig {
     #[serde(default = "default_true")]
     pub enable_self_advertisement: bool,
 
+    /// Offer BIP324 v2 encrypted transport to TCP peers (falls back to v1)
+    #[serde(default = "default_true")]
+    pub v2_transport: bool,
+
+    /// SOCKS5 proxy configuration for outbound connections
+    pub proxy: Option<ProxyConfig>,
+
+    /// Tor control port configuration (publishes the listener as an onion service)
+    pub tor_control: Option<TorControlConfig>,
+
     /// DoS protection configuration
     pub dos_protection: Option<DosProtectionConfig>,
 
@@ -334,7 +356,7 @@ pub struct NodeConfig {
     #[cfg(feature = "fibre")]
     pub fibre: Option<fibre::FibreConfig>,
 
-    /// Address database configuration
+    /// Address manager configuration
     pub address_database: Option<AddressDatabaseConfig>,
 
     /// Dandelion++ privacy relay configuration
@@ -438,6 +460,9 @@ impl Default for NodeConfig {
             storage: None,
             persistent_peers: Vec::new(),
             enable_self_advertisement: true,
+            v2_transport: true,
+            proxy: None,
+            tor_control: None,
             dos_protection: None,
             relay: None,
             #[cfg(feature = "fibre")]
@@ -1053,6 +1078,70 @@ pub struct StorageConfig {
     /// Transaction indexing configuration
     #[serde(default)]
     pub indexing: Option<IndexingConfig>,
+
+    /// Block body storage (database trees or flat block files)
+    #[serde(default)]
+    pub block_storage: BlockStorageConfig,
+
+    /// Rebuild chain state from stored blocks at startup
+    #[serde(default)]
+    pub reindex: ReindexConfig,
+}
+
+/// Block body storage configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockStorageConfig {
+    /// Where block bodies and undo data are written
+    #[serde(default)]
+    pub backend: BlockStorageBackendConfig,
+
+    /// Maximum size of each `blk*.dat` file (MB, flat files only)
+    #[serde(default = "default_max_block_file_mb")]
+    pub max_block_file_mb: u64,
+}
+
+/// Block body storage backend
+///
+/// Bodies already written by the other backend stay readable, so the
+/// backend can be changed on an existing data directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockStorageBackendConfig {
+    /// Store bodies and undo data in database trees
+    #[default]
+    Database,
+    /// Append bodies and undo data to `blocks/blk*.dat` and `blocks/rev*.dat`,
+    /// keeping only their positions in the database
+    FlatFiles,
+}
+
+/// Startup reindex mode
+///
+/// Like Bitcoin Core's `-reindex` flags this runs on every start while set,
+/// so it should be turned off again once the node has reindexed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReindexConfig {
+    #[default]
+    Off,
+    /// Rebuild the block index, chain state, UTXO set and transaction
+    /// indexes from the stored block bodies
+    Reindex,
+    /// Replay the active chain into a fresh UTXO set and chain state
+    ReindexChainstate,
+}
+
+fn default_max_block_file_mb() -> u64 {
+    128
+}
+
+impl Default for BlockStorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: BlockStorageBackendConfig::Database,
+            max_block_file_mb: 128,
+        }
+    }
 }
 
 /// Database backend configuration
@@ -1089,6 +1178,12 @@ pub struct StorageCacheConfig {
     /// Header cache size (MB)
     #[serde(default = "default_header_cache_mb")]
     pub header_cache_mb: usize,
+
+    /// Maximum time between UTXO cache flushes (seconds)
+    ///
+    /// The cache is also flushed whenever it grows past `utxo_cache_mb`.
+    #[serde(default = "default_utxo_flush_interval_secs")]
+    pub utxo_flush_interval_secs: u64,
 }
 
 fn default_block_cache_mb() -> usize {
@@ -1103,12 +1198,17 @@ fn default_header_cache_mb() -> usize {
     10
 }
 
+fn default_utxo_flush_interval_secs() -> u64 {
+    3600
+}
+
 impl Default for StorageCacheConfig {
     fn default() -> Self {
         Self {
             block_cache_mb: 100,
             utxo_cache_mb: 50,
             header_cache_mb: 10,
+            utxo_flush_interval_secs: 3600,
         }
     }
 }
@@ -1121,6 +1221,8 @@ impl Default for StorageConfig {
             pruning: None,
             cache: None,
             indexing: None,
+            block_storage: BlockStorageConfig::default(),
+            reindex: ReindexConfig::default(),
         }
     }
 }
@@ -1155,10 +1257,20 @@ pub struct IndexingConfig {
     #[serde(default)]
     pub enable_compression: bool,
 
-    /// Background indexing: index in background thread (non-blocking)
-    /// Improves block processing speed but requires async support
+    /// Background indexing: update indexes only from the background sync task,
+    /// not in the same batch as each connected block
+    /// Improves block processing speed; indexes trail the tip slightly
     #[serde(default)]
     pub background_indexing: bool,
+
+    /// Enable the BIP158 basic block filter index (getblockfilter)
+    #[serde(default)]
+    pub enable_block_filter_index: bool,
+
+    /// Enable the UTXO set statistics index (gettxoutsetinfo at any block
+    /// without scanning the UTXO set)
+    #[serde(default)]
+    pub enable_coin_stats_index: bool,
 }
 
 fn default_indexing_strategy() -> IndexingStrategy {
@@ -1188,6 +1300,8 @@ impl Default for IndexingConfig {
             max_indexed_addresses: 0,
             enable_compression: false,
             background_indexing: false,
+            enable_block_filter_index: false,
+            enable_coin_stats_index: false,
         }
     }
 }
@@ -1377,16 +1491,24 @@ impl Default for RelayConfig {
     }
 }
 
-/// Address database configuration
+/// Address manager configuration
+///
+/// IP addresses are kept in fixed-size new/tried bucket tables (saved to
+/// `peers.dat`); the size and expiration limits apply to Iroh node IDs.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AddressDatabaseConfig {
-    /// Maximum number of addresses to store
+    /// Maximum number of Iroh node IDs to store
     #[serde(default = "default_address_db_max_addresses")]
     pub max_addresses: usize,
 
-    /// Address expiration time in seconds
+    /// Iroh node ID expiration time in seconds
     #[serde(default = "default_address_db_expiration")]
     pub expiration_seconds: u64,
+
+    /// Seconds between feeler connections that test addresses before they
+    /// are promoted to the tried table (0 disables feelers)
+    #[serde(default = "default_feeler_interval_seconds")]
+    pub feeler_interval_seconds: u64,
 }
 
 fn default_address_db_max_addresses() -> usize {
@@ -1397,11 +1519,103 @@ fn default_address_db_expiration() -> u64 {
     24 * 60 * 60 // 24 hours
 }
 
+fn default_feeler_interval_seconds() -> u64 {
+    120 // 2 minutes, as Bitcoin Core
+}
+
 impl Default for AddressDatabaseConfig {
     fn default() -> Self {
         Self {
             max_addresses: 10000,
             expiration_seconds: 24 * 60 * 60,
+            feeler_interval_seconds: 120,
+        }
+    }
+}
+
+/// SOCKS5 proxy configuration
+///
+/// To run over Tor only, set `proxy` to Tor's SOCKS port and `only_networks`
+/// to `["onion"]`, or leave `only_networks` empty to also reach IPv4 and
+/// IPv6 peers through Tor. DNS seeds are not queried while IPv4 or IPv6 go
+/// through a proxy, since the lookups would bypass it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// SOCKS5 proxy for IPv4, IPv6 and onion connections (e.g. Tor at 127.0.0.1:9050)
+    pub proxy: Option<SocketAddr>,
+
+    /// Per-network proxies, overriding `proxy` (e.g. `onion = "127.0.0.1:9050"`)
+    #[serde(default)]
+    pub network_proxies: std::collections::HashMap<Network, SocketAddr>,
+
+    /// Authenticate each connection with random credentials, so Tor puts
+    /// every peer on its own circuit
+    #[serde(default = "default_true")]
+    pub randomize_credentials: bool,
+
+    /// Only make outbound connections to these networks (empty for all reachable)
+    #[serde(default)]
+    pub only_networks: Vec<Network>,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            network_proxies: std::collections::HashMap::new(),
+            randomize_credentials: true,
+            only_networks: Vec::new(),
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// Proxy to use for each network
+    pub fn proxy_settings(&self) -> ProxySettings {
+        let mut settings = ProxySettings::new();
+        let proxy = |addr: SocketAddr| {
+            Socks5Proxy::new(addr).with_randomized_credentials(self.randomize_credentials)
+        };
+        if let Some(addr) = self.proxy {
+            for network in [Network::Ipv4, Network::Ipv6, Network::Onion] {
+                settings.set_proxy(network, proxy(addr));
+            }
+        }
+        for (network, addr) in &self.network_proxies {
+            settings.set_proxy(*network, proxy(*addr));
+        }
+        settings
+    }
+}
+
+/// Tor control port configuration
+///
+/// On startup the node asks Tor for an ephemeral v3 onion service that
+/// forwards to the P2P listener, and advertises its address to peers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TorControlConfig {
+    /// Tor control port address
+    #[serde(default = "default_tor_control_addr")]
+    pub control_addr: SocketAddr,
+
+    /// Control port password (HashedControlPassword); without one, cookie or
+    /// no authentication is used, whichever Tor offers
+    pub password: Option<String>,
+
+    /// Port peers reach the onion service on (defaults to the listening port)
+    pub onion_port: Option<u16>,
+}
+
+fn default_tor_control_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 9051))
+}
+
+impl Default for TorControlConfig {
+    fn default() -> Self {
+        Self {
+            control_addr: default_tor_control_addr(),
+            password: None,
+            onion_port: None,
         }
     }
 }
@@ -1548,6 +1762,16 @@ pub struct RbfConfig {
     /// Prevents rapid-fire replacements
     #[serde(default = "default_rbf_cooldown_seconds")]
     pub cooldown_seconds: u64,
+
+    /// Allow replacing transactions that do not signal BIP125
+    /// replaceability (full RBF)
+    #[serde(default = "default_rbf_full_rbf")]
+    pub full_rbf: bool,
+
+    /// Require a replacement to improve the fee rate diagram of the
+    /// clusters it changes
+    #[serde(default = "default_rbf_fee_rate_diagram_check")]
+    pub fee_rate_diagram_check: bool,
 }
 
 fn default_rbf_mode() -> RbfMode {
@@ -1578,6 +1802,14 @@ fn default_rbf_cooldown_seconds() -> u64 {
     60
 }
 
+fn default_rbf_full_rbf() -> bool {
+    true
+}
+
+fn default_rbf_fee_rate_diagram_check() -> bool {
+    true
+}
+
 impl Default for RbfConfig {
     fn default() -> Self {
         Self {
@@ -1588,6 +1820,8 @@ impl Default for RbfConfig {
             allow_package_replacements: false,
             max_replacements_per_tx: 10,
             cooldown_seconds: 60,
+            full_rbf: true,
+            fee_rate_diagram_check: true,
         }
     }
 }
@@ -1604,6 +1838,8 @@ impl RbfConfig {
                 allow_package_replacements: false,
                 max_replacements_per_tx: 0,
                 cooldown_seconds: u64::MAX,
+                full_rbf: false,
+                fee_rate_diagram_check: true,
             },
             RbfMode::Conservative => Self {
                 mode: RbfMode::Conservative,
@@ -1613,6 +1849,8 @@ impl RbfConfig {
                 allow_package_replacements: false,
                 max_replacements_per_tx: 3,
                 cooldown_seconds: 300,
+                full_rbf: false,
+                fee_rate_diagram_check: true,
             },
             RbfMode::Standard => Self::default(),
             RbfMode::Aggressive => Self {
@@ -1623,6 +1861,8 @@ impl RbfConfig {
                 allow_package_replacements: true,
                 max_replacements_per_tx: 10,
                 cooldown_seconds: 60,
+                full_rbf: true,
+                fee_rate_diagram_check: true,
             },
         }
     }
@@ -1687,6 +1927,44 @@ pub struct MempoolPolicyConfig {
     #[serde(default = "default_max_descendant_size")]
     pub max_descendant_size: u64,
 
+    /// Maximum cluster count (connected transactions, in either direction)
+    #[serde(default = "default_max_cluster_count")]
+    pub max_cluster_count: usize,
+
+    /// Maximum cluster size in vbytes
+    #[serde(default = "default_max_cluster_size")]
+    pub max_cluster_size: u64,
+
+    /// Apply TRUC (topologically restricted until confirmation) rules to
+    /// version 3 transactions
+    #[serde(default = "default_true")]
+    pub truc_enabled: bool,
+
+    /// Maximum size of a TRUC transaction in vbytes
+    #[serde(default = "default_truc_max_vsize")]
+    pub truc_max_vsize: u64,
+
+    /// Maximum size of a TRUC transaction with an unconfirmed parent in vbytes
+    #[serde(default = "default_truc_child_max_vsize")]
+    pub truc_child_max_vsize: u64,
+
+    /// Accept zero-fee transactions with one zero-value (ephemeral anchor)
+    /// output, as long as a child in the same package spends it
+    #[serde(default = "default_true")]
+    pub ephemeral_anchors_enabled: bool,
+
+    /// Maximum number of orphan transactions (with unknown parents) kept
+    #[serde(default = "default_max_orphan_txs")]
+    pub max_orphan_txs: usize,
+
+    /// Maximum number of orphan transactions kept from any one peer
+    #[serde(default = "default_max_orphans_per_peer")]
+    pub max_orphans_per_peer: usize,
+
+    /// Orphan transaction expiry time in seconds
+    #[serde(default = "default_orphan_expiry_secs")]
+    pub orphan_expiry_secs: u64,
+
     /// Transaction eviction strategy
     #[serde(default = "default_eviction_strategy")]
     pub eviction_strategy: EvictionStrategy,
@@ -1702,6 +1980,11 @@ pub struct MempoolPolicyConfig {
     /// Mempool persistence file path
     #[serde(default = "default_mempool_persistence_path")]
     pub mempool_persistence_path: String,
+
+    /// Seconds between periodic mempool saves when persistence is enabled
+    /// (0 saves only at shutdown)
+    #[serde(default = "default_mempool_save_interval_secs")]
+    pub mempool_save_interval_secs: u64,
 }
 
 fn default_max_mempool_mb() -> u64 {
@@ -1740,6 +2023,34 @@ fn default_max_descendant_size() -> u64 {
     101_000 // 101 kB (Bitcoin Core default)
 }
 
+fn default_max_cluster_count() -> usize {
+    64
+}
+
+fn default_max_cluster_size() -> u64 {
+    101_000 // 101 kvB (Bitcoin Core default)
+}
+
+fn default_truc_max_vsize() -> u64 {
+    10_000 // BIP431
+}
+
+fn default_truc_child_max_vsize() -> u64 {
+    1_000 // BIP431
+}
+
+fn default_max_orphan_txs() -> usize {
+    100 // Bitcoin Core default
+}
+
+fn default_max_orphans_per_peer() -> usize {
+    25
+}
+
+fn default_orphan_expiry_secs() -> u64 {
+    1200 // 20 minutes
+}
+
 fn default_eviction_strategy() -> EvictionStrategy {
     EvictionStrategy::LowestFeeRate
 }
@@ -1752,6 +2063,10 @@ fn default_mempool_persistence_path() -> String {
     "data/mempool.dat".to_string()
 }
 
+fn default_mempool_save_interval_secs() -> u64 {
+    900 // 15 minutes
+}
+
 impl Default for MempoolPolicyConfig {
     fn default() -> Self {
         Self {
@@ -1764,10 +2079,20 @@ impl Default for MempoolPolicyConfig {
             max_ancestor_size: 101_000,
             max_descendant_count: 25,
             max_descendant_size: 101_000,
+            max_cluster_count: 64,
+            max_cluster_size: 101_000,
+            truc_enabled: true,
+            truc_max_vsize: 10_000,
+            truc_child_max_vsize: 1_000,
+            ephemeral_anchors_enabled: true,
+            max_orphan_txs: 100,
+            max_orphans_per_peer: 25,
+            orphan_expiry_secs: 1200,
             eviction_strategy: EvictionStrategy::LowestFeeRate,
             mempool_expiry_hours: 336,
             persist_mempool: false,
             mempool_persistence_path: "data/mempool.dat".to_string(),
+            mempool_save_interval_secs: 900,
         }
     }
 }
@@ -233,7 +233,7 @@ impl BlockchainApi {
             let outpoint = outpoint.clone();
             move || {
                 storage
-                    .utxos()
+                    .utxo_cache()
                     .get_utxo(&outpoint)
                     .map_err(|e| ModuleError::OperationError(format!("Failed to get UTXO: {e}")))
             }
@@ -248,7 +248,7 @@ impl BlockchainApi {
             let storage = Arc::clone(&self.storage);
             let outpoint = outpoint.clone();
             move || {
-                storage.utxos().has_utxo(&outpoint).map_err(|e| {
+                storage.utxo_cache().has_utxo(&outpoint).map_err(|e| {
                     ModuleError::OperationError(format!("Failed to check UTXO existence: {e}"))
                 })
             }
@@ -165,7 +165,7 @@ impl NodeAPI for NodeApiImpl {
             let storage = Arc::clone(&self.storage);
             move || {
                 storage
-                    .utxos()
+                    .utxo_cache()
                     .get_utxo(&outpoint_clone)
                     .map_err(|e| ModuleError::OperationError(format!("Failed to get UTXO: {e}")))
             }
@@ -0,0 +1,1040 @@
+//! Address manager for peer discovery
+//!
+//! Keeps known peer addresses in two bucketed tables, following Bitcoin
+//! Core's addrman:
+//!
+//! - **new**: addresses we have heard about but never connected to. The
+//!   bucket depends on the group of the address *and* of the peer that told
+//!   us about it, so a single source can only fill a few buckets.
+//! - **tried**: addresses we have successfully connected to. The bucket
+//!   depends on the address group only.
+//!
+//! Bucket positions are keyed by a secret chosen when the tables are created
+//! and saved with them, so an attacker can't predict which entries their
+//! addresses would displace. When a newly good address lands on an occupied
+//! tried slot the current occupant is tested first (test-before-evict) and
+//! only replaced if it turns out to be unreachable.
+//!
+//! Entries can be on any BIP155 network (see [`NetAddr`]); overlay network
+//! addresses are stored even when we can't reach them, so they can still be
+//! relayed to peers that can. Iroh node IDs have no network group and are
+//! kept in a separate, flat map.
+
+use crate::network::netaddr::{NetAddr, Network, ServiceAddr};
+use crate::network::protocol::{NetworkAddress, NetworkAddressV2};
+use crate::utils::current_timestamp;
+use anyhow::{bail, Result};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::path::Path;
+use tracing::debug;
+
+#[cfg(feature = "iroh")]
+use iroh::PublicKey;
+
+/// File the address tables are saved to, in the data directory
+pub const PEERS_FILE: &str = "peers.dat";
+
+const PEERS_FILE_MAGIC: &[u8; 4] = b"BLPA";
+const PEERS_FILE_VERSION: u32 = 2;
+
+/// Number of buckets in the new table
+pub const NEW_BUCKET_COUNT: usize = 1024;
+/// Number of buckets in the tried table
+pub const TRIED_BUCKET_COUNT: usize = 256;
+/// Slots per bucket
+pub const BUCKET_SIZE: usize = 64;
+/// New buckets a single source group can spread its addresses over
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 64;
+/// Tried buckets a single address group can occupy
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+/// Pending tried collisions kept for test-before-evict
+const MAX_TRIED_COLLISIONS: usize = 10;
+
+/// Addresses not seen for this long are terrible
+const HORIZON_SECS: u64 = 30 * 24 * 60 * 60;
+/// Never-connected addresses are terrible after this many failed attempts
+const MAX_RETRIES: u32 = 3;
+/// Previously good addresses are terrible after this many failures...
+const MAX_FAILURES: u32 = 10;
+/// ...if they haven't succeeded in this long
+const MIN_FAIL_SECS: u64 = 7 * 24 * 60 * 60;
+/// A tried entry that succeeded this recently is never evicted
+const REPLACEMENT_SECS: u64 = 4 * 60 * 60;
+/// Evict without a test if the collision has waited this long
+const TEST_WINDOW_SECS: u64 = 40 * 60;
+
+/// Address with its connection history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddrInfo {
+    /// Address and port
+    pub addr: ServiceAddr,
+    /// Advertised services
+    pub services: u64,
+    /// Address of the peer that told us about the address
+    pub source: NetAddr,
+    /// Unix timestamp the address was last advertised or connected to
+    pub time: u64,
+    /// Unix timestamp of the last connection attempt
+    pub last_try: u64,
+    /// Unix timestamp of the last successful connection
+    pub last_success: u64,
+    /// Failed attempts since the last success
+    pub attempts: u32,
+    /// Whether the address is in the tried table
+    pub in_tried: bool,
+    /// Slot in the new table, if the address is there
+    #[serde(skip)]
+    new_slot: Option<usize>,
+}
+
+impl AddrInfo {
+    fn new(addr: ServiceAddr, services: u64, source: NetAddr, now: u64) -> Self {
+        Self {
+            addr,
+            services,
+            source,
+            time: now,
+            last_try: 0,
+            last_success: 0,
+            attempts: 0,
+            in_tried: false,
+            new_slot: None,
+        }
+    }
+
+    /// Whether the address is not worth keeping or connecting to
+    pub fn is_terrible(&self, now: u64) -> bool {
+        // Never remove things tried in the last minute
+        if self.last_try != 0 && now.saturating_sub(self.last_try) <= 60 {
+            return false;
+        }
+        // Timestamps from the future are bogus
+        if self.time > now + 10 * 60 {
+            return true;
+        }
+        if now.saturating_sub(self.time) > HORIZON_SECS {
+            return true;
+        }
+        if self.last_success == 0 && self.attempts >= MAX_RETRIES {
+            return true;
+        }
+        now.saturating_sub(self.last_success) > MIN_FAIL_SECS && self.attempts >= MAX_FAILURES
+    }
+
+    /// Relative chance of choosing the address, lowered by recent attempts
+    /// and failures
+    pub fn chance(&self, now: u64) -> f64 {
+        let mut chance = 1.0;
+        if now.saturating_sub(self.last_try) < 10 * 60 {
+            chance *= 0.01;
+        }
+        chance * 0.66f64.powi(self.attempts.min(8) as i32)
+    }
+
+    /// The address as relayed in addr and addrv2 messages
+    pub fn to_network_address(&self) -> NetworkAddressV2 {
+        NetworkAddressV2 {
+            time: self.time.min(u32::MAX as u64) as u32,
+            services: self.services,
+            addr: self.addr.addr,
+            port: self.addr.port,
+        }
+    }
+}
+
+/// New and tried counts for one network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetworkCounts {
+    /// Addresses in the new table
+    pub new: usize,
+    /// Addresses in the tried table
+    pub tried: usize,
+}
+
+/// Address manager statistics
+#[derive(Debug, Clone, Default)]
+pub struct AddrManInfo {
+    /// Counts per network, in [`Network::ALL`] order
+    pub networks: Vec<(Network, NetworkCounts)>,
+    /// New buckets holding at least one address
+    pub new_buckets_used: usize,
+    /// Tried buckets holding at least one address
+    pub tried_buckets_used: usize,
+    /// Addresses waiting for test-before-evict
+    pub tried_collisions: usize,
+}
+
+impl NetworkCounts {
+    fn adjust(&mut self, tried: bool, added: bool) {
+        let count = if tried {
+            &mut self.tried
+        } else {
+            &mut self.new
+        };
+        if added {
+            *count += 1;
+        } else {
+            *count -= 1;
+        }
+    }
+}
+
+impl AddrManInfo {
+    /// Counts across all networks
+    pub fn total(&self) -> NetworkCounts {
+        self.networks
+            .iter()
+            .fold(NetworkCounts::default(), |total, (_, counts)| {
+                NetworkCounts {
+                    new: total.new + counts.new,
+                    tried: total.tried + counts.tried,
+                }
+            })
+    }
+}
+
+/// Iroh node ID entry
+#[cfg(feature = "iroh")]
+#[derive(Debug, Clone)]
+struct IrohEntry {
+    last_seen: u64,
+    services: u64,
+}
+
+/// Contents of [`PEERS_FILE`]
+#[derive(Serialize, Deserialize)]
+struct SavedAddrMan {
+    key: [u8; 32],
+    entries: Vec<AddrInfo>,
+}
+
+/// Bucketed address manager
+pub struct AddrMan {
+    /// Secret that keys bucket placement
+    key: [u8; 32],
+    entries: HashMap<u64, AddrInfo>,
+    ids: HashMap<ServiceAddr, u64>,
+    next_id: u64,
+    /// `NEW_BUCKET_COUNT * BUCKET_SIZE` slots
+    new_table: Vec<Option<u64>>,
+    /// `TRIED_BUCKET_COUNT * BUCKET_SIZE` slots
+    tried_table: Vec<Option<u64>>,
+    new_count: usize,
+    tried_count: usize,
+    /// New and tried counts per network
+    network_counts: HashMap<Network, NetworkCounts>,
+    /// Entries that are good but whose tried slot is taken
+    tried_collisions: BTreeSet<u64>,
+    /// Map from Iroh PublicKey to entry (for Iroh peers)
+    #[cfg(feature = "iroh")]
+    iroh_addresses: HashMap<PublicKey, IrohEntry>,
+    /// Maximum number of Iroh node IDs to store
+    #[cfg(feature = "iroh")]
+    max_iroh_addresses: usize,
+    /// Iroh node ID expiration time in seconds
+    #[cfg(feature = "iroh")]
+    iroh_expiration_seconds: u64,
+}
+
+impl AddrMan {
+    /// Create an empty address manager with a random key
+    pub fn new() -> Self {
+        Self::with_key(rand::thread_rng().gen())
+    }
+
+    /// Create an empty address manager with a fixed key
+    pub fn with_key(key: [u8; 32]) -> Self {
+        Self {
+            key,
+            entries: HashMap::new(),
+            ids: HashMap::new(),
+            next_id: 0,
+            new_table: vec![None; NEW_BUCKET_COUNT * BUCKET_SIZE],
+            tried_table: vec![None; TRIED_BUCKET_COUNT * BUCKET_SIZE],
+            new_count: 0,
+            tried_count: 0,
+            network_counts: HashMap::new(),
+            tried_collisions: BTreeSet::new(),
+            #[cfg(feature = "iroh")]
+            iroh_addresses: HashMap::new(),
+            #[cfg(feature = "iroh")]
+            max_iroh_addresses: 10000,
+            #[cfg(feature = "iroh")]
+            iroh_expiration_seconds: 24 * 60 * 60,
+        }
+    }
+
+    /// Limit the Iroh node IDs kept and how long they stay fresh
+    #[cfg(feature = "iroh")]
+    pub fn with_iroh_limits(mut self, max_addresses: usize, expiration_seconds: u64) -> Self {
+        self.max_iroh_addresses = max_addresses;
+        self.iroh_expiration_seconds = expiration_seconds;
+        self
+    }
+
+    /// Add an address learned from `source`
+    ///
+    /// Returns true if the address was not known before and got a slot in
+    /// the new table. Known addresses get their services and timestamp
+    /// refreshed.
+    pub fn add_address(&mut self, addr: NetworkAddressV2, source: NetAddr) -> bool {
+        self.add_at(addr.service(), addr.services, source, current_timestamp())
+    }
+
+    /// Add several addresses learned from `source`, returning how many were new
+    pub fn add_addresses(&mut self, addresses: Vec<NetworkAddressV2>, source: NetAddr) -> usize {
+        let now = current_timestamp();
+        addresses
+            .into_iter()
+            .filter(|addr| self.add_at(addr.service(), addr.services, source, now))
+            .count()
+    }
+
+    fn add_at(&mut self, addr: ServiceAddr, services: u64, source: NetAddr, now: u64) -> bool {
+        if !is_routable(&addr) {
+            return false;
+        }
+        if let Some(&id) = self.ids.get(&addr) {
+            let info = self.entries.get_mut(&id).expect("indexed entry exists");
+            info.services |= services;
+            info.time = info.time.max(now);
+            return false;
+        }
+
+        let info = AddrInfo::new(addr, services, source, now);
+        let slot = self.new_slot(&info);
+        if let Some(existing) = self.new_table[slot] {
+            if !self.entries[&existing].is_terrible(now) {
+                return false;
+            }
+            self.delete(existing);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.insert_new(id, info, slot);
+        true
+    }
+
+    /// Record a connection attempt to an address
+    pub fn attempt(&mut self, addr: &ServiceAddr) {
+        self.attempt_at(addr, current_timestamp());
+    }
+
+    fn attempt_at(&mut self, addr: &ServiceAddr, now: u64) {
+        if let Some(info) = self.ids.get(addr).and_then(|id| self.entries.get_mut(id)) {
+            info.last_try = now;
+            info.attempts += 1;
+        }
+    }
+
+    /// Record a successful connection, moving the address to the tried table
+    ///
+    /// If its tried slot is taken the move waits for the current occupant
+    /// to be tested (see [`Self::resolve_collisions`]). Returns true if the
+    /// address is in the tried table afterwards.
+    pub fn good(&mut self, addr: &ServiceAddr) -> bool {
+        self.good_at(addr, current_timestamp())
+    }
+
+    fn good_at(&mut self, addr: &ServiceAddr, now: u64) -> bool {
+        let Some(&id) = self.ids.get(addr) else {
+            return false;
+        };
+        let info = self.entries.get_mut(&id).expect("indexed entry exists");
+        info.last_success = now;
+        info.last_try = now;
+        info.attempts = 0;
+        info.time = now;
+        if info.in_tried {
+            return true;
+        }
+
+        let slot = self.tried_slot(&self.entries[&id]);
+        if self.tried_table[slot].is_some() {
+            if self.tried_collisions.len() < MAX_TRIED_COLLISIONS {
+                self.tried_collisions.insert(id);
+            }
+            return false;
+        }
+        self.make_tried(id);
+        true
+    }
+
+    /// Settle pending tried collisions
+    ///
+    /// A collision replaces the tried entry in its slot if that entry was
+    /// tested and failed, or if no test happened within the test window.
+    /// It is dropped if the tried entry has succeeded recently.
+    pub fn resolve_collisions(&mut self) {
+        self.resolve_collisions_at(current_timestamp());
+    }
+
+    fn resolve_collisions_at(&mut self, now: u64) {
+        let pending: Vec<u64> = self.tried_collisions.iter().copied().collect();
+        for id in pending {
+            let Some(info) = self.entries.get(&id) else {
+                self.tried_collisions.remove(&id);
+                continue;
+            };
+            if info.in_tried {
+                self.tried_collisions.remove(&id);
+                continue;
+            }
+            let new_success = info.last_success;
+            let Some(old_id) = self.tried_table[self.tried_slot(info)] else {
+                self.make_tried(id);
+                self.tried_collisions.remove(&id);
+                continue;
+            };
+            let old = &self.entries[&old_id];
+            let resolved = if now.saturating_sub(old.last_success) < REPLACEMENT_SECS {
+                // The current entry is still good
+                true
+            } else if now.saturating_sub(old.last_try) < REPLACEMENT_SECS {
+                // Tested and failed: give the test a minute to complete
+                if now.saturating_sub(old.last_try) > 60 {
+                    debug!("Replacing unreachable tried address {}", old.addr);
+                    self.make_tried(id);
+                    true
+                } else {
+                    false
+                }
+            } else if now.saturating_sub(new_success) > TEST_WINDOW_SECS {
+                self.make_tried(id);
+                true
+            } else {
+                false
+            };
+            if resolved {
+                self.tried_collisions.remove(&id);
+            }
+        }
+    }
+
+    /// A tried address blocking a pending collision, to test with a feeler
+    pub fn select_tried_collision(&self) -> Option<ServiceAddr> {
+        let ids: Vec<u64> = self.tried_collisions.iter().copied().collect();
+        let id = ids.choose(&mut rand::thread_rng())?;
+        let info = self.entries.get(id)?;
+        let old_id = self.tried_table[self.tried_slot(info)]?;
+        Some(self.entries[&old_id].addr)
+    }
+
+    /// Choose an address on one of `networks` to connect to
+    ///
+    /// Tried and new entries are equally likely unless `new_only` is set
+    /// (for feelers). Within a table, addresses that failed recently are
+    /// less likely to be chosen.
+    pub fn select(&self, new_only: bool, networks: &[Network]) -> Option<ServiceAddr> {
+        self.select_at(new_only, networks, current_timestamp())
+    }
+
+    fn select_at(&self, new_only: bool, networks: &[Network], now: u64) -> Option<ServiceAddr> {
+        let counts = networks
+            .iter()
+            .filter_map(|network| self.network_counts.get(network))
+            .fold(NetworkCounts::default(), |total, counts| NetworkCounts {
+                new: total.new + counts.new,
+                tried: total.tried + counts.tried,
+            });
+        if counts.new == 0 && (new_only || counts.tried == 0) {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        let use_tried = !new_only && counts.tried > 0 && (counts.new == 0 || rng.gen_bool(0.5));
+        let (table, bucket_count) = if use_tried {
+            (&self.tried_table, TRIED_BUCKET_COUNT)
+        } else {
+            (&self.new_table, NEW_BUCKET_COUNT)
+        };
+
+        let mut factor = 1.0;
+        loop {
+            let bucket = rng.gen_range(0..bucket_count);
+            let start = rng.gen_range(0..BUCKET_SIZE);
+            let Some(id) = (0..BUCKET_SIZE)
+                .find_map(|i| table[bucket * BUCKET_SIZE + (start + i) % BUCKET_SIZE])
+            else {
+                continue;
+            };
+            let info = &self.entries[&id];
+            if !networks.contains(&info.addr.addr.network()) {
+                continue;
+            }
+            if rng.gen::<f64>() < factor * info.chance(now) {
+                return Some(info.addr);
+            }
+            factor *= 1.2;
+        }
+    }
+
+    /// Random addresses for a getaddr response
+    ///
+    /// Returns at most `max_count` addresses and at most `max_pct` percent
+    /// of the table, skipping terrible ones and, if `network` is given,
+    /// addresses on other networks.
+    pub fn get_addresses(
+        &self,
+        max_count: usize,
+        max_pct: usize,
+        network: Option<Network>,
+    ) -> Vec<NetworkAddressV2> {
+        let now = current_timestamp();
+        let limit = max_count.min(self.entries.len() * max_pct / 100);
+        let mut addresses: Vec<NetworkAddressV2> = self
+            .entries
+            .values()
+            .filter(|info| network.is_none_or(|network| info.addr.addr.network() == network))
+            .filter(|info| !info.is_terrible(now))
+            .map(AddrInfo::to_network_address)
+            .collect();
+        addresses.shuffle(&mut rand::thread_rng());
+        addresses.truncate(limit);
+        addresses
+    }
+
+    /// Look up an address
+    pub fn get(&self, addr: &ServiceAddr) -> Option<&AddrInfo> {
+        self.ids.get(addr).and_then(|id| self.entries.get(id))
+    }
+
+    /// Remove an address
+    pub fn remove_address(&mut self, addr: &ServiceAddr) {
+        if let Some(&id) = self.ids.get(addr) {
+            self.delete(id);
+        }
+    }
+
+    /// Bucket statistics
+    pub fn info(&self) -> AddrManInfo {
+        let networks = Network::ALL
+            .into_iter()
+            .map(|network| {
+                let counts = self.network_counts.get(&network).copied();
+                (network, counts.unwrap_or_default())
+            })
+            .collect();
+        let buckets_used = |table: &[Option<u64>]| {
+            table
+                .chunks(BUCKET_SIZE)
+                .filter(|bucket| bucket.iter().any(Option::is_some))
+                .count()
+        };
+        AddrManInfo {
+            networks,
+            new_buckets_used: buckets_used(&self.new_table),
+            tried_buckets_used: buckets_used(&self.tried_table),
+            tried_collisions: self.tried_collisions.len(),
+        }
+    }
+
+    /// Addresses in the new table
+    pub fn new_count(&self) -> usize {
+        self.new_count
+    }
+
+    /// Addresses in the tried table
+    pub fn tried_count(&self) -> usize {
+        self.tried_count
+    }
+
+    /// Get address count (SocketAddr only)
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if there are no addresses
+    pub fn is_empty(&self) -> bool {
+        #[cfg(feature = "iroh")]
+        {
+            self.entries.is_empty() && self.iroh_addresses.is_empty()
+        }
+        #[cfg(not(feature = "iroh"))]
+        {
+            self.entries.is_empty()
+        }
+    }
+
+    /// Save the tables with their key
+    ///
+    /// The file is versioned, ends with a double-SHA256 checksum and is
+    /// replaced atomically.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        use std::io::Write;
+
+        let saved = SavedAddrMan {
+            key: self.key,
+            entries: self.entries.values().cloned().collect(),
+        };
+        let payload = bincode::serialize(&saved)?;
+        let checksum = Sha256::digest(Sha256::digest(&payload));
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("new");
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(PEERS_FILE_MAGIC)?;
+        file.write_all(&PEERS_FILE_VERSION.to_le_bytes())?;
+        file.write_all(&payload)?;
+        file.write_all(&checksum)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+
+        debug!(
+            "Saved {} addresses to {}",
+            saved.entries.len(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Replace the tables with ones saved by [`Self::save`]
+    ///
+    /// Returns false, keeping the current tables, if there is no file.
+    /// Entries are placed again using the saved key; any that no longer fit
+    /// are dropped.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<bool> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(false);
+        }
+        let data = std::fs::read(path)?;
+        let header_len = PEERS_FILE_MAGIC.len() + 4;
+        if data.len() < header_len + 32 || &data[..4] != PEERS_FILE_MAGIC {
+            bail!("{} is not a peers file", path.display());
+        }
+        let version = u32::from_le_bytes(data[4..8].try_into()?);
+        if version != PEERS_FILE_VERSION {
+            bail!("Unsupported peers file version {}", version);
+        }
+        let (payload, checksum) = data[header_len..].split_at(data.len() - header_len - 32);
+        if Sha256::digest(Sha256::digest(payload)).as_slice() != checksum {
+            bail!(
+                "Peers file {} is corrupt (checksum mismatch)",
+                path.display()
+            );
+        }
+        let saved: SavedAddrMan = bincode::deserialize(payload)?;
+
+        let mut loaded = Self::with_key(saved.key);
+        #[cfg(feature = "iroh")]
+        {
+            loaded.iroh_addresses = std::mem::take(&mut self.iroh_addresses);
+            loaded.max_iroh_addresses = self.max_iroh_addresses;
+            loaded.iroh_expiration_seconds = self.iroh_expiration_seconds;
+        }
+        // Tried entries first, so they keep their slots
+        let (tried, new): (Vec<_>, Vec<_>) =
+            saved.entries.into_iter().partition(|info| info.in_tried);
+        for mut info in tried.into_iter().chain(new) {
+            if !is_routable(&info.addr) || loaded.ids.contains_key(&info.addr) {
+                continue;
+            }
+            let id = loaded.next_id;
+            if info.in_tried {
+                let slot = loaded.tried_slot(&info);
+                if loaded.tried_table[slot].is_none() {
+                    loaded.next_id += 1;
+                    loaded.insert_tried(id, info, slot);
+                    continue;
+                }
+                info.in_tried = false;
+            }
+            let slot = loaded.new_slot(&info);
+            if loaded.new_table[slot].is_none() {
+                loaded.next_id += 1;
+                loaded.insert_new(id, info, slot);
+            }
+        }
+        *self = loaded;
+
+        debug!(
+            "Loaded {} new and {} tried addresses from {}",
+            self.new_count,
+            self.tried_count,
+            path.display()
+        );
+        Ok(true)
+    }
+
+    /// Add an Iroh PublicKey
+    #[cfg(feature = "iroh")]
+    pub fn add_iroh_address(&mut self, public_key: PublicKey, services: u64) {
+        let now = current_timestamp();
+        match self.iroh_addresses.get_mut(&public_key) {
+            Some(entry) => {
+                entry.last_seen = now;
+                entry.services |= services;
+            }
+            None => {
+                if self.iroh_addresses.len() >= self.max_iroh_addresses {
+                    // Evict the stalest node ID
+                    if let Some(oldest) = self
+                        .iroh_addresses
+                        .iter()
+                        .min_by_key(|(_, entry)| entry.last_seen)
+                        .map(|(key, _)| *key)
+                    {
+                        self.iroh_addresses.remove(&oldest);
+                    }
+                }
+                self.iroh_addresses.insert(
+                    public_key,
+                    IrohEntry {
+                        last_seen: now,
+                        services,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Get fresh Iroh PublicKeys, most recently seen first
+    #[cfg(feature = "iroh")]
+    pub fn get_fresh_iroh_addresses(&self, count: usize) -> Vec<PublicKey> {
+        let now = current_timestamp();
+        let mut fresh: Vec<_> = self
+            .iroh_addresses
+            .iter()
+            .filter(|(_, entry)| now.saturating_sub(entry.last_seen) < self.iroh_expiration_seconds)
+            .map(|(public_key, entry)| (entry.last_seen, *public_key))
+            .collect();
+        fresh.sort_by(|a, b| b.0.cmp(&a.0));
+        fresh
+            .into_iter()
+            .map(|(_, public_key)| public_key)
+            .take(count)
+            .collect()
+    }
+
+    /// Keyed hash of `parts`, as a bucket or position index source
+    fn hash(&self, parts: &[&[u8]]) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.update(self.key);
+        for part in parts {
+            hasher.update(part);
+        }
+        let digest = hasher.finalize();
+        u64::from_le_bytes(digest[..8].try_into().expect("8 bytes"))
+    }
+
+    fn new_slot(&self, info: &AddrInfo) -> usize {
+        let source_group = info.source.group();
+        let hash1 =
+            self.hash(&[&info.addr.addr.group(), &source_group]) % NEW_BUCKETS_PER_SOURCE_GROUP;
+        let bucket = self.hash(&[&source_group, &hash1.to_le_bytes()]) % NEW_BUCKET_COUNT as u64;
+        bucket as usize * BUCKET_SIZE + self.position(b'N', bucket, &info.addr)
+    }
+
+    fn tried_slot(&self, info: &AddrInfo) -> usize {
+        let hash1 = self.hash(&[&info.addr.key()]) % TRIED_BUCKETS_PER_GROUP;
+        let bucket =
+            self.hash(&[&info.addr.addr.group(), &hash1.to_le_bytes()]) % TRIED_BUCKET_COUNT as u64;
+        bucket as usize * BUCKET_SIZE + self.position(b'K', bucket, &info.addr)
+    }
+
+    fn position(&self, table: u8, bucket: u64, addr: &ServiceAddr) -> usize {
+        (self.hash(&[&[table], &bucket.to_le_bytes(), &addr.key()]) % BUCKET_SIZE as u64) as usize
+    }
+
+    /// Update the new or tried count of `addr`'s network
+    fn count(&mut self, addr: &ServiceAddr, tried: bool, added: bool) {
+        if tried {
+            self.tried_count = if added {
+                self.tried_count + 1
+            } else {
+                self.tried_count - 1
+            };
+        } else {
+            self.new_count = if added {
+                self.new_count + 1
+            } else {
+                self.new_count - 1
+            };
+        }
+        self.network_counts
+            .entry(addr.addr.network())
+            .or_default()
+            .adjust(tried, added);
+    }
+
+    fn insert_new(&mut self, id: u64, mut info: AddrInfo, slot: usize) {
+        info.in_tried = false;
+        info.new_slot = Some(slot);
+        self.count(&info.addr, false, true);
+        self.ids.insert(info.addr, id);
+        self.entries.insert(id, info);
+        self.new_table[slot] = Some(id);
+    }
+
+    fn insert_tried(&mut self, id: u64, mut info: AddrInfo, slot: usize) {
+        info.in_tried = true;
+        info.new_slot = None;
+        self.count(&info.addr, true, true);
+        self.ids.insert(info.addr, id);
+        self.entries.insert(id, info);
+        self.tried_table[slot] = Some(id);
+    }
+
+    /// Remove an entry from whichever table holds it
+    fn delete(&mut self, id: u64) {
+        let Some(info) = self.entries.remove(&id) else {
+            return;
+        };
+        self.ids.remove(&info.addr);
+        self.tried_collisions.remove(&id);
+        if info.in_tried {
+            let slot = self.tried_slot(&info);
+            self.tried_table[slot] = None;
+            self.count(&info.addr, true, false);
+        } else if let Some(slot) = info.new_slot {
+            self.new_table[slot] = None;
+            self.count(&info.addr, false, false);
+        }
+    }
+
+    /// Move a new entry to its tried slot, sending any occupant back to new
+    fn make_tried(&mut self, id: u64) {
+        let mut info = self.entries.remove(&id).expect("entry exists");
+        if let Some(slot) = info.new_slot.take() {
+            self.new_table[slot] = None;
+            self.count(&info.addr, false, false);
+        }
+
+        let slot = self.tried_slot(&info);
+        if let Some(old_id) = self.tried_table[slot].take() {
+            let mut old = self.entries.remove(&old_id).expect("entry exists");
+            self.count(&old.addr, true, false);
+            old.in_tried = false;
+            let new_slot = self.new_slot(&old);
+            if let Some(displaced) = self.new_table[new_slot] {
+                self.delete(displaced);
+            }
+            old.new_slot = Some(new_slot);
+            self.count(&old.addr, false, true);
+            self.entries.insert(old_id, old);
+            self.new_table[new_slot] = Some(old_id);
+        }
+
+        info.in_tried = true;
+        self.count(&info.addr, true, true);
+        self.entries.insert(id, info);
+        self.tried_table[slot] = Some(id);
+    }
+}
+
+impl Default for AddrMan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Convert NetworkAddress to SocketAddr
+pub fn network_addr_to_socket(addr: &NetworkAddress) -> SocketAddr {
+    SocketAddr::new(
+        NetAddr::from_legacy(addr.ip)
+            .to_ip()
+            .expect("legacy address is IP"),
+        addr.port,
+    )
+}
+
+/// Convert SocketAddr to NetworkAddress
+pub fn socket_to_network_addr(socket: SocketAddr, services: u64) -> NetworkAddress {
+    NetworkAddress {
+        services,
+        ip: NetAddr::from(socket.ip()).to_legacy().expect("IP address"),
+        port: socket.port(),
+    }
+}
+
+/// Whether an address can be stored and connected to
+fn is_routable(addr: &ServiceAddr) -> bool {
+    addr.port != 0 && addr.addr.is_routable()
+}
+
+/// Check if address is banned
+///
+/// Bans are by IP, so addresses on overlay networks are never banned.
+pub fn is_banned(addr: &ServiceAddr, ban_list: &HashMap<SocketAddr, u64>) -> bool {
+    let Some(socket_addr) = addr.to_socket() else {
+        return false;
+    };
+    match ban_list.get(&socket_addr) {
+        Some(unban_timestamp) => {
+            *unban_timestamp == u64::MAX || current_timestamp() < *unban_timestamp
+        }
+        None => false,
+    }
+}
+
+/// Filter addresses (exclude unroutable, banned, already connected)
+pub fn filter_addresses(
+    addresses: Vec<NetworkAddressV2>,
+    ban_list: &HashMap<SocketAddr, u64>,
+    connected_peers: &[SocketAddr],
+) -> Vec<NetworkAddressV2> {
+    addresses
+        .into_iter()
+        .filter(|addr| {
+            let service = addr.service();
+            is_routable(&service)
+                && !is_banned(&service, ban_list)
+                && !service
+                    .to_socket()
+                    .is_some_and(|socket| connected_peers.contains(&socket))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn addr(ip: [u8; 4], port: u16) -> ServiceAddr {
+        ServiceAddr::new(NetAddr::Ipv4(ip), port)
+    }
+
+    fn source(ip: [u8; 4]) -> NetAddr {
+        NetAddr::Ipv4(ip)
+    }
+
+    fn info(addr: ServiceAddr, source: NetAddr) -> AddrInfo {
+        AddrInfo::new(addr, 1, source, NOW)
+    }
+
+    /// Two addresses that share a tried slot
+    fn tried_collision(addrman: &AddrMan) -> (ServiceAddr, ServiceAddr) {
+        let first = addr([1, 2, 3, 4], 8333);
+        let first_slot = addrman.tried_slot(&info(first, source([0; 4])));
+        let second = (1u16..)
+            .map(|port| addr([1, 2, 3, 4], 8333 + port))
+            .find(|candidate| addrman.tried_slot(&info(*candidate, source([0; 4]))) == first_slot)
+            .unwrap();
+        (first, second)
+    }
+
+    #[test]
+    fn test_new_bucket_depends_on_source_group() {
+        let addrman = AddrMan::with_key([7; 32]);
+        let from = |src| info(addr([1, 2, 3, 4], 8333), source(src));
+        // Same /16 source, same bucket
+        assert_eq!(
+            addrman.new_slot(&from([5, 6, 1, 1])) / BUCKET_SIZE,
+            addrman.new_slot(&from([5, 6, 2, 2])) / BUCKET_SIZE
+        );
+        // A source group can only reach a few buckets for one address group
+        let buckets: BTreeSet<usize> = (0..=255u8)
+            .map(|i| {
+                let info = info(addr([1, 2, i, 1], 8333), source([5, 6, 0, 0]));
+                addrman.new_slot(&info) / BUCKET_SIZE
+            })
+            .collect();
+        assert!(buckets.len() <= NEW_BUCKETS_PER_SOURCE_GROUP as usize);
+    }
+
+    #[test]
+    fn test_tried_collision_resolves_after_failed_test() {
+        let mut addrman = AddrMan::with_key([7; 32]);
+        let (first, second) = tried_collision(&addrman);
+        assert!(addrman.add_at(first, 1, source([9, 9, 9, 9]), NOW));
+        assert!(addrman.add_at(second, 1, source([9, 9, 9, 9]), NOW));
+        assert!(addrman.good_at(&first, NOW));
+
+        // The second address waits for the first to be tested
+        assert!(!addrman.good_at(&second, NOW));
+        assert_eq!(addrman.info().tried_collisions, 1);
+        assert_eq!(addrman.select_tried_collision(), Some(first));
+
+        // A recent success keeps the current entry
+        addrman.resolve_collisions_at(NOW + 60);
+        assert_eq!(addrman.info().tried_collisions, 0);
+        assert!(addrman.get(&first).unwrap().in_tried);
+
+        // A failed test evicts it back to the new table
+        let later = NOW + REPLACEMENT_SECS + 1;
+        addrman.good_at(&second, later);
+        addrman.attempt_at(&first, later + 10);
+        addrman.resolve_collisions_at(later + 30);
+        assert_eq!(addrman.info().tried_collisions, 1);
+        addrman.resolve_collisions_at(later + 100);
+        assert!(addrman.get(&second).unwrap().in_tried);
+        assert!(!addrman.get(&first).unwrap().in_tried);
+        assert_eq!((addrman.new_count(), addrman.tried_count()), (1, 1));
+    }
+
+    #[test]
+    fn test_untested_collision_evicts_after_window() {
+        let mut addrman = AddrMan::with_key([7; 32]);
+        let (first, second) = tried_collision(&addrman);
+        addrman.add_at(first, 1, source([9, 9, 9, 9]), NOW);
+        addrman.add_at(second, 1, source([9, 9, 9, 9]), NOW);
+        addrman.good_at(&first, NOW);
+
+        let later = NOW + REPLACEMENT_SECS + 1;
+        addrman.good_at(&second, later);
+        addrman.resolve_collisions_at(later + 60);
+        assert!(!addrman.get(&second).unwrap().in_tried);
+        addrman.resolve_collisions_at(later + TEST_WINDOW_SECS + 1);
+        assert!(addrman.get(&second).unwrap().in_tried);
+    }
+
+    #[test]
+    fn test_terrible_entries() {
+        let mut info = info(addr([1, 2, 3, 4], 8333), source([0; 4]));
+        assert!(!info.is_terrible(NOW));
+        assert!(info.is_terrible(NOW + HORIZON_SECS + 1));
+        info.attempts = MAX_RETRIES;
+        info.last_try = NOW;
+        // Not while the last attempt is recent
+        assert!(!info.is_terrible(NOW + 30));
+        assert!(info.is_terrible(NOW + 120));
+        assert!(info.chance(NOW + 120) < info.chance(NOW + 3600));
+    }
+
+    #[test]
+    fn test_terrible_entry_gives_up_new_slot() {
+        let mut addrman = AddrMan::with_key([7; 32]);
+        let first = addr([1, 2, 3, 4], 8333);
+        let src = source([9, 9, 9, 9]);
+        let slot = addrman.new_slot(&info(first, src));
+        let second = (1u16..)
+            .map(|port| addr([1, 2, 3, 4], 8333 + port))
+            .find(|candidate| addrman.new_slot(&info(*candidate, src)) == slot)
+            .unwrap();
+
+        assert!(addrman.add_at(first, 1, src, NOW));
+        assert!(!addrman.add_at(second, 1, src, NOW));
+        assert!(addrman.add_at(second, 1, src, NOW + HORIZON_SECS + 1));
+        assert!(addrman.get(&first).is_none());
+        assert_eq!(addrman.len(), 1);
+    }
+
+    #[test]
+    fn test_select_only_reachable_networks() {
+        let mut addrman = AddrMan::with_key([7; 32]);
+        let onion = ServiceAddr::new(NetAddr::TorV3([3; 32]), 8333);
+        assert!(addrman.add_at(onion, 1, source([9, 9, 9, 9]), NOW));
+        assert!(addrman.select_at(false, &[Network::Ipv4], NOW).is_none());
+        assert_eq!(
+            addrman.select_at(false, &[Network::Ipv4, Network::Onion], NOW),
+            Some(onion)
+        );
+
+        addrman.add_at(addr([1, 2, 3, 4], 8333), 1, source([9, 9, 9, 9]), NOW);
+        for _ in 0..20 {
+            assert_eq!(
+                addrman.select_at(false, &[Network::Ipv4], NOW),
+                Some(addr([1, 2, 3, 4], 8333))
+            );
+        }
+    }
+}
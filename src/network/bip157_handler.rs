@@ -66,7 +66,9 @@ pub fn handle_getcfilters(
                         let mut prev_scripts = Vec::new();
                         for tx in &block.transactions {
                             for input in &tx.inputs {
-                                if let Ok(Some(utxo)) = storage.utxos().get_utxo(&input.prevout) {
+                                if let Ok(Some(utxo)) =
+                                    storage.utxo_cache().get_utxo(&input.prevout)
+                                {
                                     prev_scripts.push(utxo.script_pubkey);
                                 }
                             }
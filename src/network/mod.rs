@@ -3,7 +3,7 @@
 //! This module provides P2P networking, peer management, and Bitcoin protocol
 //! message handling for communication with other Bitcoin nodes.
 
-pub mod address_db;
+pub mod addrman;
 pub mod ban_list_merging;
 pub mod ban_list_signing;
 pub mod chain_access;
@@ -12,13 +12,17 @@ pub mod dos_protection;
 pub mod inventory;
 pub mod message_bridge;
 pub mod module_registry_extensions;
+pub mod netaddr;
 pub mod peer;
 pub mod protocol;
 pub mod protocol_adapter;
 pub mod protocol_extensions;
 pub mod relay;
+pub mod socks5;
 pub mod tcp_transport;
+pub mod tor_control;
 pub mod transport;
+pub mod v2_transport;
 
 #[cfg(feature = "quinn")]
 pub mod quinn_transport;
@@ -50,13 +54,16 @@ pub mod package_relay; // BIP 331 Package Relay
 pub mod package_relay_handler; // BIP 331 handlers
 pub mod txhash; // Non-consensus hashing helpers for relay
 
-use crate::network::protocol::{AddrMessage, NetworkAddress, ProtocolMessage, ProtocolParser};
+use crate::network::netaddr::{NetAddr, Network, ServiceAddr};
+use crate::network::protocol::{
+    AddrMessage, AddrV2Message, NetworkAddress, NetworkAddressV2, ProtocolMessage, ProtocolParser,
+};
 use crate::node::mempool::MempoolManager;
 use crate::storage::Storage;
 use crate::utils::current_timestamp;
 use anyhow::Result;
 use bllvm_protocol::mempool::Mempool;
-use bllvm_protocol::{BitcoinProtocolEngine, ConsensusProof, UtxoSet};
+use bllvm_protocol::{BitcoinProtocolEngine, BlockHeader, ConsensusProof, UtxoSet};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::{
@@ -66,10 +73,13 @@ use std::sync::{
 use tokio::sync::{mpsc, Mutex, RwLock};
 use tracing::{debug, error, info, warn};
 
-use crate::network::tcp_transport::TcpTransport;
 use crate::network::transport::{Transport, TransportAddr, TransportListener, TransportPreference};
+use crate::network::v2_transport::V2Transport;
 use std::collections::HashSet;
 
+/// How long a feeler connection may take, including the transport handshake
+const FEELER_TIMEOUT_SECS: u64 = 30;
+
 /// Network I/O operations for testing
 /// Note: This is deprecated - use TcpTransport instead
 pub struct NetworkIO;
@@ -334,7 +344,8 @@ impl ConnectionManager {
 /// Supports multiple transports (TCP, Quinn, Iroh) based on configuration.
 pub struct NetworkManager {
     peer_manager: Arc<Mutex<PeerManager>>,
-    tcp_transport: TcpTransport,
+    /// TCP transport, offering BIP324 v2 encryption with v1 fallback
+    tcp_transport: V2Transport,
     #[cfg(feature = "quinn")]
     quinn_transport: Option<crate::network::quinn_transport::QuinnTransport>,
     #[cfg(feature = "iroh")]
@@ -342,6 +353,9 @@ pub struct NetworkManager {
     transport_preference: TransportPreference,
     peer_tx: mpsc::UnboundedSender<NetworkMessage>,
     peer_rx: mpsc::UnboundedReceiver<NetworkMessage>,
+    /// Headers received from peers, for the node's headers-first sync
+    headers_tx: mpsc::UnboundedSender<(SocketAddr, Vec<BlockHeader>)>,
+    headers_rx: mpsc::UnboundedReceiver<(SocketAddr, Vec<BlockHeader>)>,
     /// Block filter service for BIP157/158
     filter_service: crate::network::filter_service::BlockFilterService,
     /// Consensus engine for mempool acceptance
@@ -408,13 +422,22 @@ pub struct NetworkManager {
     /// Governance message relay configuration
     #[cfg(feature = "governance")]
     governance_config: Option<crate::config::GovernanceConfig>,
-    /// Address database for peer discovery
-    /// Read-heavy: many reads to query addresses, fewer writes when adding addresses
-    address_database: Arc<RwLock<address_db::AddressDatabase>>,
+    /// Bucketed address manager for peer discovery
+    address_manager: Arc<RwLock<addrman::AddrMan>>,
+    /// Seconds between feeler connections (0 disables them)
+    feeler_interval_seconds: u64,
     /// Last time we sent addr message (Unix timestamp)
     last_addr_sent: Arc<Mutex<u64>>,
     /// Enable self-advertisement (send own address to peers)
     enable_self_advertisement: bool,
+    /// Networks outbound connections are limited to (empty for all reachable)
+    only_networks: Vec<Network>,
+    /// Tor control port configuration, for publishing an onion service
+    tor_control_config: Option<crate::config::TorControlConfig>,
+    /// Tor control connection; the onion service lives as long as it's open
+    tor_control: Option<tor_control::TorControl>,
+    /// Our onion service address, advertised to peers
+    onion_service: Option<ServiceAddr>,
     /// Request timeout configuration
     request_timeout_config: Arc<crate::config::RequestTimeoutConfig>,
     /// Peer reconnection queue (exponential backoff)
@@ -495,6 +518,7 @@ impl NetworkManager {
         config: Option<&crate::config::NodeConfig>,
     ) -> Self {
         let (peer_tx, peer_rx) = mpsc::unbounded_channel();
+        let (headers_tx, headers_rx) = mpsc::unbounded_channel();
 
         // Use config for DoS protection
         let dos_config_default = crate::config::DosProtectionConfig::default();
@@ -511,16 +535,19 @@ impl NetworkManager {
             dos_config.ban_duration_seconds,
         ));
 
-        // Use config for address database
+        // Use config for address manager
         let addr_db_config_default = crate::config::AddressDatabaseConfig::default();
         let addr_db_config = config
             .and_then(|c| c.address_database.as_ref())
             .unwrap_or(&addr_db_config_default);
 
-        let address_database = Arc::new(RwLock::new(address_db::AddressDatabase::with_expiration(
+        let address_manager = addrman::AddrMan::new();
+        #[cfg(feature = "iroh")]
+        let address_manager = address_manager.with_iroh_limits(
             addr_db_config.max_addresses,
             addr_db_config.expiration_seconds,
-        )));
+        );
+        let address_manager = Arc::new(RwLock::new(address_manager));
 
         // Use config for request timeouts
         let timeout_config_default = crate::config::RequestTimeoutConfig::default();
@@ -529,10 +556,18 @@ impl NetworkManager {
             .unwrap_or(&timeout_config_default);
         let request_timeout_config = Arc::new(timeout_config.clone());
 
+        // Use config for proxies
+        let proxy_config_default = crate::config::ProxyConfig::default();
+        let proxy_config = config
+            .and_then(|c| c.proxy.as_ref())
+            .unwrap_or(&proxy_config_default);
+
         Self {
             peer_manager: Arc::new(Mutex::new(PeerManager::new(max_peers))),
             peer_diversity: Arc::new(Mutex::new(HashMap::new())),
-            tcp_transport: TcpTransport::new(),
+            tcp_transport: V2Transport::new(crate::network::protocol::BITCOIN_MAGIC_MAINNET)
+                .with_v2(config.map(|c| c.v2_transport).unwrap_or(true))
+                .with_proxies(proxy_config.proxy_settings()),
             #[cfg(feature = "quinn")]
             quinn_transport: None,
             #[cfg(feature = "iroh")]
@@ -540,6 +575,8 @@ impl NetworkManager {
             transport_preference: preference,
             peer_tx,
             peer_rx,
+            headers_tx,
+            headers_rx,
             filter_service: crate::network::filter_service::BlockFilterService::new(),
             consensus: ConsensusProof::new(),
             utxo_set: Arc::new(Mutex::new(UtxoSet::new())),
@@ -570,9 +607,14 @@ impl NetworkManager {
             ban_list_sharing_config: config.and_then(|c| c.ban_list_sharing.clone()),
             #[cfg(feature = "governance")]
             governance_config: config.and_then(|c| c.governance.clone()),
-            address_database,
+            address_manager,
+            feeler_interval_seconds: addr_db_config.feeler_interval_seconds,
             last_addr_sent: Arc::new(Mutex::new(0)),
             enable_self_advertisement: config.map(|c| c.enable_self_advertisement).unwrap_or(true),
+            only_networks: proxy_config.only_networks.clone(),
+            tor_control_config: config.and_then(|c| c.tor_control.clone()),
+            tor_control: None,
+            onion_service: None,
             request_timeout_config,
             peer_reconnection_queue: Arc::new(Mutex::new(HashMap::new())),
         }
@@ -1168,11 +1210,12 @@ impl NetworkManager {
         let addresses = dns_seeds::resolve_dns_seeds(seeds, port, max_addresses).await;
         let address_count = addresses.len();
 
-        // Add discovered addresses to database
+        // Add discovered addresses to the new table, each as its own source
         {
-            let mut db = self.address_database.write().await;
+            let mut manager = self.address_manager.write().await;
             for addr in addresses {
-                db.add_address(addr, 0); // Services will be updated on connection
+                let source = NetAddr::from_legacy(addr.ip);
+                manager.add_address(addr.into(), source); // Services will be updated on connection
             }
         }
 
@@ -1247,8 +1290,8 @@ impl NetworkManager {
 
         // Get fresh Iroh NodeIds from database
         let node_ids = {
-            let db = self.address_database.read().await;
-            db.get_fresh_iroh_addresses(needed * 2) // Get 2x needed for retries
+            let manager = self.address_manager.read().await;
+            manager.get_fresh_iroh_addresses(needed * 2) // Get 2x needed for retries
         };
 
         if node_ids.is_empty() {
@@ -1340,32 +1383,47 @@ impl NetworkManager {
             pm.peer_socket_addresses()
         };
 
-        let addresses: Vec<_> = {
-            let db = self.address_database.read().await;
-            let fresh = db.get_fresh_addresses(needed * 3); // Get 3x needed for retries
-            db.filter_addresses(fresh, &ban_list, &connected_peers)
+        // Select from the address manager, at most one address per network group
+        let networks = self.reachable_networks();
+        let candidates: Vec<ServiceAddr> = {
+            let mut manager = self.address_manager.write().await;
+            manager.resolve_collisions();
+            let mut groups: HashSet<Vec<u8>> = connected_peers
+                .iter()
+                .map(|peer| NetAddr::from(peer.ip()).group())
+                .collect();
+            let mut candidates = Vec::new();
+            // Get 2x needed for retries, giving up after 100 selections
+            for _ in 0..100 {
+                if candidates.len() >= needed * 2 {
+                    break;
+                }
+                let Some(addr) = manager.select(false, &networks) else {
+                    break;
+                };
+                if addr
+                    .to_socket()
+                    .is_some_and(|socket| connected_peers.contains(&socket))
+                    || addrman::is_banned(&addr, &ban_list)
+                    || !groups.insert(addr.addr.group())
+                {
+                    continue;
+                }
+                candidates.push(addr);
+            }
+            candidates
         };
 
-        if addresses.is_empty() {
-            warn!("No fresh addresses available in database");
+        if candidates.is_empty() {
+            warn!("No usable addresses available in address manager");
             return Ok(0);
         }
 
-        // Convert addresses to SocketAddrs first
-        let sockets: Vec<SocketAddr> = {
-            let db = self.address_database.read().await;
-            addresses
-                .iter()
-                .take(needed * 2)
-                .map(|addr| db.network_addr_to_socket(addr))
-                .collect()
-        };
-
         // Try to connect to addresses
         let mut connected = 0;
-        for socket in sockets {
-            if let Err(e) = self.connect_to_peer(socket).await {
-                debug!("Failed to connect to {}: {}", socket, e);
+        for addr in candidates {
+            if let Err(e) = self.connect_to_service(&addr).await {
+                debug!("Failed to connect to {}: {}", addr, e);
                 // Continue trying other addresses
             } else {
                 connected += 1;
@@ -1390,7 +1448,7 @@ impl NetworkManager {
     /// Initialize peer connections after startup
     ///
     /// This is automatically called by `start()` to:
-    /// 1. Discover peers from DNS seeds (for TCP/Quinn transports)
+    /// 1. Discover peers from DNS seeds (for TCP/Quinn transports, unless proxied)
     /// 2. Connect to persistent peers from config
     /// 3. Discover Iroh peers (if Iroh is enabled) - uses Iroh's DERP servers and gossip
     /// 4. Connect to peers from address database to reach target count
@@ -1419,7 +1477,12 @@ impl NetworkManager {
                 false
             }
         };
-        if should_discover_dns {
+        // Lookups would bypass the proxy and reveal us to the resolver
+        let proxied = self.proxies().proxy_for(Network::Ipv4).is_some()
+            || self.proxies().proxy_for(Network::Ipv6).is_some();
+        if should_discover_dns && proxied {
+            info!("Skipping DNS seeds while connections go through a proxy");
+        } else if should_discover_dns {
             if let Err(e) = self.discover_peers_from_dns(network, port, config).await {
                 warn!("DNS seed discovery failed: {}", e);
             }
@@ -1508,6 +1571,11 @@ impl NetworkManager {
             let mut tcp_listener = self.tcp_transport.listen(listen_addr).await?;
             info!("TCP listener started on {}", listen_addr);
 
+            // Publish the listener as a Tor onion service (with graceful degradation)
+            if let Err(e) = self.start_onion_service(tcp_listener.local_addr()?).await {
+                warn!("Failed to create Tor onion service: {}", e);
+            }
+
             // Start TCP accept loop
             let peer_tx = self.peer_tx.clone();
             let dos_protection = Arc::clone(&self.dos_protection);
@@ -1749,7 +1817,7 @@ impl NetworkManager {
                     let peer_tx = self.peer_tx.clone();
                     let peer_manager = Arc::clone(&self.peer_manager);
                     let dos_protection = Arc::clone(&self.dos_protection);
-                    let address_database = Arc::clone(&self.address_database);
+                    let address_manager = Arc::clone(&self.address_manager);
                     let socket_to_transport = Arc::clone(&self.socket_to_transport);
                     tokio::spawn(async move {
                         loop {
@@ -1795,7 +1863,7 @@ impl NetworkManager {
                                     let iroh_addr_clone = iroh_addr.clone();
                                     let socket_to_transport_clone =
                                         Arc::clone(&socket_to_transport);
-                                    let address_database_clone = Arc::clone(&address_database);
+                                    let address_manager_clone = Arc::clone(&address_manager);
                                     tokio::spawn(async move {
                                         // For Iroh, we need a SocketAddr for Peer::from_transport_connection
                                         // Generate a unique placeholder based on key hash for lookups
@@ -1856,11 +1924,12 @@ impl NetworkManager {
                                                 if let Ok(public_key) =
                                                     PublicKey::from_bytes(&key_array)
                                                 {
-                                                    let address_db_clone =
-                                                        address_database_clone.clone();
+                                                    let address_manager_clone =
+                                                        address_manager_clone.clone();
                                                     tokio::spawn(async move {
-                                                        let mut db = address_db_clone.write().await;
-                                                        db.add_iroh_address(public_key, 0);
+                                                        let mut manager =
+                                                            address_manager_clone.write().await;
+                                                        manager.add_iroh_address(public_key, 0);
                                                         // Services will be updated on version exchange
                                                     });
                                                 }
@@ -1903,6 +1972,9 @@ impl NetworkManager {
         // Start peer reconnection task
         self.start_peer_reconnection_task();
 
+        // Start feeler connections to test addresses
+        self.start_feeler_task();
+
         // Note: Peer connection initialization (DNS seeds, persistent peers, etc.)
         // should be called separately via initialize_peer_connections() after start()
         // This allows the caller to provide config, network type, and target peer count
@@ -1910,6 +1982,35 @@ impl NetworkManager {
         Ok(())
     }
 
+    /// Publish the TCP listener as a Tor onion service, if configured
+    ///
+    /// Keeps the control connection open, since Tor removes the ephemeral
+    /// service when it closes.
+    async fn start_onion_service(&mut self, listen_addr: SocketAddr) -> Result<()> {
+        let Some(config) = self.tor_control_config.clone() else {
+            return Ok(());
+        };
+        // Tor forwards to the listener, over loopback if it listens on all interfaces
+        let target = match listen_addr.ip() {
+            std::net::IpAddr::V4(ip) if ip.is_unspecified() => {
+                SocketAddr::from(([127, 0, 0, 1], listen_addr.port()))
+            }
+            std::net::IpAddr::V6(ip) if ip.is_unspecified() => {
+                SocketAddr::new(std::net::Ipv6Addr::LOCALHOST.into(), listen_addr.port())
+            }
+            _ => listen_addr,
+        };
+
+        let mut control = tor_control::TorControl::connect(config.control_addr).await?;
+        control.authenticate(config.password.as_deref()).await?;
+        let service = control
+            .add_onion(config.onion_port.unwrap_or(listen_addr.port()), target)
+            .await?;
+        self.onion_service = Some(service);
+        self.tor_control = Some(control);
+        Ok(())
+    }
+
     /// Generate a new request ID for async request-response patterns
     ///
     /// Optimization: Uses AtomicU64 for lock-free operation (no async locks needed)
@@ -2306,6 +2407,68 @@ impl NetworkManager {
         });
     }
 
+    /// Start periodic feeler connections
+    ///
+    /// Each feeler either tests the tried entry blocking a pending collision
+    /// (test-before-evict) or a random new-table address, and disconnects
+    /// right after the transport handshake. Reachable addresses are promoted
+    /// to the tried table.
+    fn start_feeler_task(&self) {
+        if self.feeler_interval_seconds == 0 || !self.transport_preference.allows_tcp() {
+            return;
+        }
+        let address_manager = Arc::clone(&self.address_manager);
+        let peer_manager = Arc::clone(&self.peer_manager);
+        let ban_list = Arc::clone(&self.ban_list);
+        let tcp_transport = self.tcp_transport.clone();
+        let feeler_interval = self.feeler_interval_seconds;
+        let networks = self.reachable_networks();
+
+        tokio::spawn(async move {
+            use crate::network::transport::TransportConnection;
+
+            let mut interval =
+                tokio::time::interval(tokio::time::Duration::from_secs(feeler_interval));
+            // The first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+
+                let candidate = {
+                    let mut manager = address_manager.write().await;
+                    manager.resolve_collisions();
+                    manager
+                        .select_tried_collision()
+                        .or_else(|| manager.select(true, &networks))
+                };
+                let Some(addr) = candidate else {
+                    continue;
+                };
+                let connected = peer_manager.lock().await.peer_socket_addresses();
+                let banned = addrman::is_banned(&addr, &ban_list.read().await);
+                if banned
+                    || addr
+                        .to_socket()
+                        .is_some_and(|socket| connected.contains(&socket))
+                {
+                    continue;
+                }
+
+                address_manager.write().await.attempt(&addr);
+                let timeout = tokio::time::Duration::from_secs(FEELER_TIMEOUT_SECS);
+                match tokio::time::timeout(timeout, tcp_transport.connect_service(&addr)).await {
+                    Ok(Ok(mut conn)) => {
+                        debug!("Feeler connection to {} succeeded", addr);
+                        address_manager.write().await.good(&addr);
+                        let _ = conn.close().await;
+                    }
+                    Ok(Err(e)) => debug!("Feeler connection to {} failed: {}", addr, e),
+                    Err(_) => debug!("Feeler connection to {} timed out", addr),
+                }
+            }
+        });
+    }
+
     /// Get the number of connected peers
     pub fn peer_count(&self) -> usize {
         // Use block_in_place to avoid blocking async runtime
@@ -2559,6 +2722,7 @@ impl NetworkManager {
         }
 
         let mut last_error = None;
+        self.address_manager.write().await.attempt(&addr.into());
 
         // Try transports in preference order with graceful degradation
         let transports_to_try = self.get_transports_for_connection();
@@ -2571,6 +2735,7 @@ impl NetworkManager {
                         let mut pm = self.peer_manager.lock().await;
                         pm.add_peer(transport_addr.clone(), peer)?;
                     }
+                    self.address_manager.write().await.good(&addr.into());
 
                     // Note: Peer handler is managed by Peer::from_transport_connection
                     // No need to spawn additional handler task
@@ -2596,6 +2761,49 @@ impl NetworkManager {
         Err(last_error.unwrap_or_else(|| anyhow::anyhow!("All transport attempts failed")))
     }
 
+    /// Connect to a peer on any network
+    ///
+    /// IP addresses go through [`connect_to_peer`](Self::connect_to_peer).
+    /// Onion and I2P addresses are reached over TCP through their network's
+    /// proxy; without a socket address, the peer is identified by the local
+    /// end of the proxy connection.
+    pub async fn connect_to_service(&self, addr: &ServiceAddr) -> Result<()> {
+        use crate::network::transport::TransportConnection;
+
+        if let Some(socket) = addr.to_socket() {
+            return self.connect_to_peer(socket).await;
+        }
+        if !self.proxies().is_reachable(addr.addr.network()) {
+            return Err(anyhow::anyhow!(
+                "No proxy configured to reach {} address {}",
+                addr.addr.network(),
+                addr
+            ));
+        }
+
+        self.address_manager.write().await.attempt(addr);
+        let conn = self.tcp_transport.connect_service(addr).await?;
+        let transport_addr = conn.peer_addr();
+        #[allow(irrefutable_let_patterns)]
+        let TransportAddr::Tcp(socket) = transport_addr.clone() else {
+            return Err(anyhow::anyhow!("Proxied connection has no TCP address"));
+        };
+        let peer = peer::Peer::from_transport_connection(
+            conn,
+            socket,
+            transport_addr.clone(),
+            self.peer_tx.clone(),
+        );
+        {
+            let mut pm = self.peer_manager.lock().await;
+            pm.add_peer(transport_addr.clone(), peer)?;
+        }
+        self.address_manager.write().await.good(addr);
+
+        info!("Successfully connected to {} (as {})", addr, socket);
+        Ok(())
+    }
+
     /// Helper: Get list of transports to try for a connection
     fn get_transports_for_connection(&self) -> Vec<crate::network::transport::TransportType> {
         let mut transports = Vec::new();
@@ -2768,6 +2976,14 @@ impl NetworkManager {
         }
     }
 
+    /// Try to receive headers sent by a peer (non-blocking)
+    ///
+    /// Returns the peer and its headers, for the node to add to the block
+    /// index.
+    pub fn try_recv_headers(&mut self) -> Option<(SocketAddr, Vec<BlockHeader>)> {
+        self.headers_rx.try_recv().ok()
+    }
+
     /// Process incoming network messages
     pub async fn process_messages(&mut self) -> Result<()> {
         // Track message queue size manually (unbounded channel doesn't have len())
@@ -2860,6 +3076,11 @@ impl NetworkManager {
                             "Added peer {} to reconnection queue (quality: {:.2})",
                             socket_addr, quality_score
                         );
+
+                        // Its orphans' parents will not arrive from it now
+                        if let Some(ref mempool_manager) = self.mempool_manager {
+                            mempool_manager.remove_orphans_for_peer(socket_addr);
+                        }
                     }
 
                     // Clean up per-IP connection count (only for TCP/Quinn, not Iroh)
@@ -3366,6 +3587,18 @@ impl NetworkManager {
             ProtocolMessage::Addr(msg) => {
                 return self.handle_addr(peer_addr, msg).await;
             }
+            ProtocolMessage::SendAddrV2 => {
+                return self.handle_send_addrv2(peer_addr).await;
+            }
+            // Headers-first sync: the node adds headers to the block index
+            ProtocolMessage::Headers(ref msg) => {
+                if !msg.headers.is_empty() {
+                    let _ = self.headers_tx.send((peer_addr, msg.headers.clone()));
+                }
+            }
+            ProtocolMessage::AddrV2(msg) => {
+                return self.handle_addrv2(peer_addr, msg).await;
+            }
             // Module Registry
             ProtocolMessage::GetModule(msg) => {
                 return self.handle_get_module(peer_addr, msg).await;
@@ -3399,11 +3632,23 @@ impl NetworkManager {
                 Arc::clone(mempool_manager),
             );
 
-            // Get UTXO set and height
-            let utxo_set = storage
-                .utxos()
-                .get_all_utxos()
-                .map_err(|e| anyhow::anyhow!("Failed to get UTXO set: {}", e))?;
+            // Get the coins spent by a relayed transaction, the mempool and the
+            // orphan pool (other messages need none), and the height
+            let utxo_set = match &parsed {
+                ProtocolMessage::Tx(tx_msg) => {
+                    let prevouts: Vec<_> = mempool_manager
+                        .prevouts()
+                        .into_iter()
+                        .chain(mempool_manager.orphan_prevouts())
+                        .chain(tx_msg.transaction.inputs.iter().map(|input| input.prevout.clone()))
+                        .collect();
+                    storage
+                        .utxo_cache()
+                        .fetch_coins(&prevouts)
+                        .map_err(|e| anyhow::anyhow!("Failed to get UTXO set: {}", e))?
+                }
+                _ => UtxoSet::new(),
+            };
             let height = storage
                 .chain()
                 .get_height()
@@ -3422,6 +3667,12 @@ impl NetworkManager {
                     }
                 }
 
+                // Offer addrv2 before our verack (BIP155)
+                let sendaddrv2 = ProtocolParser::serialize_message(&ProtocolMessage::SendAddrV2)?;
+                if let Err(e) = self.send_to_peer(peer_addr, sendaddrv2).await {
+                    debug!("Failed to send sendaddrv2 to {}: {}", peer_addr, e);
+                }
+
                 if version_msg.supports_fibre() {
                     // Register FIBRE-capable peer
                     #[cfg(feature = "fibre")]
@@ -3445,6 +3696,57 @@ impl NetworkManager {
                 }
             }
 
+            // Add the transaction to the mempool, along with the orphans it
+            // lets in. Transactions with unknown parents are kept as orphans
+            // and the parents fetched from the peer that sent them
+            if let ProtocolMessage::Tx(tx_msg) = &parsed {
+                use crate::node::mempool::TxSubmission;
+                match mempool_manager
+                    .submit_transaction(
+                        tx_msg.transaction.clone(),
+                        &utxo_set,
+                        height + 1,
+                        Some(peer_addr),
+                    )
+                    .await
+                {
+                    Ok(TxSubmission::Accepted { reprocessed }) => {
+                        if !reprocessed.is_empty() {
+                            debug!(
+                                "Transaction from {} let {} orphans into the mempool",
+                                peer_addr,
+                                reprocessed.len()
+                            );
+                        }
+                    }
+                    Ok(TxSubmission::Orphan { missing_parents }) => {
+                        debug!(
+                            "Orphan transaction from {}, requesting {} missing parents",
+                            peer_addr,
+                            missing_parents.len()
+                        );
+                        if let Err(e) = self.request_transactions(peer_addr, &missing_parents).await
+                        {
+                            warn!("Failed to request orphan parents from {}: {}", peer_addr, e);
+                        }
+                    }
+                    Ok(TxSubmission::Rejected) => {
+                        debug!("Transaction from {} rejected by the mempool", peer_addr);
+                    }
+                    Err(e) => warn!("Failed to submit transaction from {}: {}", peer_addr, e),
+                }
+            }
+
+            // A peer asking for a transaction means it has been relayed
+            if let ProtocolMessage::GetData(getdata) = &parsed {
+                use crate::network::inventory::MSG_TX;
+                for item in &getdata.inventory {
+                    if item.inv_type == MSG_TX {
+                        mempool_manager.remove_unbroadcast(&item.hash);
+                    }
+                }
+            }
+
             // Process message with protocol layer - lock peer_states only during synchronous processing
             let response = {
                 let mut peer_states = self.peer_states.write().await;
@@ -3494,6 +3796,81 @@ impl NetworkManager {
         Ok(())
     }
 
+    /// Request transactions from a peer by txid (getdata)
+    pub async fn request_transactions(
+        &self,
+        peer_addr: SocketAddr,
+        txids: &[bllvm_protocol::Hash],
+    ) -> Result<()> {
+        use crate::network::inventory::MSG_TX;
+        use crate::network::protocol::{GetDataMessage, InventoryItem};
+
+        if txids.is_empty() {
+            return Ok(());
+        }
+        let inventory = txids
+            .iter()
+            .map(|txid| InventoryItem {
+                inv_type: MSG_TX,
+                hash: *txid,
+            })
+            .collect();
+        let wire_msg =
+            ProtocolParser::serialize_message(&ProtocolMessage::GetData(GetDataMessage {
+                inventory,
+            }))?;
+        self.send_to_peer(peer_addr, wire_msg).await
+    }
+
+    /// Request blocks from a peer by hash (getdata)
+    pub async fn request_blocks(
+        &self,
+        peer_addr: SocketAddr,
+        hashes: &[bllvm_protocol::Hash],
+    ) -> Result<()> {
+        use crate::network::inventory::MSG_BLOCK;
+        use crate::network::protocol::{GetDataMessage, InventoryItem};
+
+        if hashes.is_empty() {
+            return Ok(());
+        }
+        let inventory = hashes
+            .iter()
+            .map(|hash| InventoryItem {
+                inv_type: MSG_BLOCK,
+                hash: *hash,
+            })
+            .collect();
+        let wire_msg =
+            ProtocolParser::serialize_message(&ProtocolMessage::GetData(GetDataMessage {
+                inventory,
+            }))?;
+        self.send_to_peer(peer_addr, wire_msg).await
+    }
+
+    /// Disconnect a peer
+    ///
+    /// Closes the connection and removes the peer; the rest of the cleanup
+    /// happens when the disconnection is processed. Returns false if no such
+    /// peer is connected.
+    pub async fn disconnect_peer(&self, addr: SocketAddr) -> bool {
+        let transport_addr = {
+            let mut pm = self.peer_manager.lock().await;
+            let Some(transport_addr) = pm.find_transport_addr_by_socket(addr) else {
+                return false;
+            };
+            if let Some(mut peer) = pm.remove_peer(&transport_addr) {
+                peer.disconnect();
+            }
+            transport_addr
+        };
+        info!("Disconnecting peer {}", addr);
+        let _ = self
+            .peer_tx
+            .send(NetworkMessage::PeerDisconnected(transport_addr));
+        true
+    }
+
     #[cfg(feature = "utxo-commitments")]
     /// Handle GetUTXOSet request from a peer
     async fn handle_get_utxo_set_request(
@@ -3963,50 +4340,57 @@ impl NetworkManager {
 
     /// Handle GetAddr request - return known addresses
     async fn handle_get_addr(&self, peer_addr: SocketAddr) -> Result<()> {
-        use crate::network::protocol::{AddrMessage, ProtocolMessage, ProtocolParser};
-
-        // Get fresh addresses from database (up to 2500, Bitcoin Core limit)
+        // Get a random sample of known addresses (up to 2500 or 23% of them, as Bitcoin Core)
         let ban_list = self.ban_list.read().await.clone();
         let connected_peers: Vec<SocketAddr> = {
             let pm = self.peer_manager.lock().await;
             pm.peer_socket_addresses()
         };
 
-        let addresses = {
-            let db = self.address_database.write().await;
-            let fresh = db.get_fresh_addresses(2500);
-            db.filter_addresses(fresh, &ban_list, &connected_peers)
+        let mut addresses = {
+            let manager = self.address_manager.read().await;
+            let known = manager.get_addresses(2500, 23, None);
+            addrman::filter_addresses(known, &ban_list, &connected_peers)
         };
 
-        // Create Addr message
-        let addr_msg = AddrMessage { addresses };
-        let response = ProtocolMessage::Addr(addr_msg);
-        let wire_msg = ProtocolParser::serialize_message(&response)?;
+        // Include our onion service, which addrv2 peers pass on
+        if let Some(onion) = self.onion_service_address(self.local_services()) {
+            addresses.insert(0, onion);
+            addresses.truncate(2500);
+        }
 
         // Send response
-        self.send_to_peer(peer_addr, wire_msg).await?;
+        if let Some(wire_msg) = self.addr_message_for(peer_addr, &addresses).await? {
+            self.send_to_peer(peer_addr, wire_msg).await?;
+        }
         Ok(())
     }
 
     /// Handle Addr message - store addresses and optionally relay
     async fn handle_addr(&self, peer_addr: SocketAddr, msg: AddrMessage) -> Result<()> {
-        // AddrMessage is already in scope as parameter, NetworkAddress is available from top-level import
-
-        // Get peer services from peer state
-        let peer_services = {
-            let peer_states = self.peer_states.read().await;
-            peer_states
-                .get(&peer_addr)
-                .map(|state| state.services)
-                .unwrap_or(0)
-        };
+        let addresses = msg.addresses.into_iter().map(Into::into).collect();
+        self.handle_addrv2(peer_addr, AddrV2Message { addresses }).await
+    }
 
-        // Store addresses in database
+    /// Handle SendAddrV2 message - relay addresses to the peer as addrv2
+    async fn handle_send_addrv2(&self, peer_addr: SocketAddr) -> Result<()> {
+        let mut pm = self.peer_manager.lock().await;
+        if let Some(peer) = pm
+            .find_transport_addr_by_socket(peer_addr)
+            .and_then(|transport_addr| pm.get_peer_mut(&transport_addr))
         {
-            let mut db = self.address_database.write().await;
-            for addr in &msg.addresses {
-                db.add_address(addr.clone(), peer_services);
-            }
+            peer.set_wants_addrv2();
+            debug!("Peer {} negotiated addrv2", peer_addr);
+        }
+        Ok(())
+    }
+
+    /// Handle AddrV2 message - store addresses and optionally relay
+    async fn handle_addrv2(&self, peer_addr: SocketAddr, msg: AddrV2Message) -> Result<()> {
+        // Store addresses in the new table, bucketed by the sending peer's group
+        {
+            let mut manager = self.address_manager.write().await;
+            manager.add_addresses(msg.addresses.clone(), NetAddr::from(peer_addr.ip()));
         }
 
         // Relay addresses to other peers (with rate limiting)
@@ -4015,13 +4399,48 @@ impl NetworkManager {
         Ok(())
     }
 
+    /// Serialize addresses for a peer
+    ///
+    /// Peers that sent sendaddrv2 get an addrv2 message; others get an addr
+    /// message with only the IPv4 and IPv6 addresses. Returns None if there
+    /// is nothing the peer can receive.
+    async fn addr_message_for(
+        &self,
+        peer_addr: SocketAddr,
+        addresses: &[NetworkAddressV2],
+    ) -> Result<Option<Vec<u8>>> {
+        let wants_addrv2 = {
+            let pm = self.peer_manager.lock().await;
+            pm.find_transport_addr_by_socket(peer_addr)
+                .and_then(|transport_addr| pm.get_peer(&transport_addr))
+                .is_some_and(|peer| peer.wants_addrv2())
+        };
+        let message = if wants_addrv2 {
+            if addresses.is_empty() {
+                return Ok(None);
+            }
+            ProtocolMessage::AddrV2(AddrV2Message {
+                addresses: addresses.to_vec(),
+            })
+        } else {
+            let addresses: Vec<NetworkAddress> = addresses
+                .iter()
+                .filter_map(NetworkAddressV2::to_v1)
+                .collect();
+            if addresses.is_empty() {
+                return Ok(None);
+            }
+            ProtocolMessage::Addr(AddrMessage { addresses })
+        };
+        Ok(Some(ProtocolParser::serialize_message(&message)?))
+    }
+
     /// Relay addresses to other peers (excluding sender)
     async fn relay_addresses(
         &self,
         sender_addr: SocketAddr,
-        addresses: &[NetworkAddress],
+        addresses: &[NetworkAddressV2],
     ) -> Result<()> {
-        use crate::network::protocol::{AddrMessage, ProtocolMessage, ProtocolParser};
         // Rate limiting: don't send addr messages too frequently (Bitcoin Core: ~every 2.4 hours)
         let now = current_timestamp();
         let min_interval = 2 * 60 * 60 + 24 * 60; // 2.4 hours in seconds
@@ -4041,26 +4460,19 @@ impl NetworkManager {
             pm.peer_socket_addresses()
         };
 
-        let filtered = {
-            let db = self.address_database.read().await;
-            db.filter_addresses(addresses.to_vec(), &ban_list, &connected_peers)
-        };
+        let filtered = addrman::filter_addresses(addresses.to_vec(), &ban_list, &connected_peers);
 
         if filtered.is_empty() {
             return Ok(());
         }
 
         // Limit to 1000 addresses per message (Bitcoin Core limit)
-        let addresses_to_relay: Vec<NetworkAddress> = filtered.into_iter().take(1000).collect();
-
-        // Create Addr message
-        let addr_msg = AddrMessage {
-            addresses: addresses_to_relay,
-        };
-        let relay_msg = ProtocolMessage::Addr(addr_msg);
-        let wire_msg = ProtocolParser::serialize_message(&relay_msg)?;
+        let addresses_to_relay: Vec<NetworkAddressV2> = filtered
+            .into_iter()
+            .take(crate::network::protocol::MAX_ADDR_TO_SEND)
+            .collect();
 
-        // Send to all peers except sender
+        // Send to all peers except sender, in the format each one negotiated
         let peer_addrs: Vec<SocketAddr> = {
             let pm = self.peer_manager.lock().await;
             pm.peer_socket_addresses()
@@ -4070,7 +4482,13 @@ impl NetworkManager {
         };
 
         for peer_addr in peer_addrs {
-            if let Err(e) = self.send_to_peer(peer_addr, wire_msg.clone()).await {
+            let Some(wire_msg) = self
+                .addr_message_for(peer_addr, &addresses_to_relay)
+                .await?
+            else {
+                continue;
+            };
+            if let Err(e) = self.send_to_peer(peer_addr, wire_msg).await {
                 warn!("Failed to relay addresses to {}: {}", peer_addr, e);
             }
         }
@@ -4082,63 +4500,50 @@ impl NetworkManager {
     }
 
     /// Send our own address to peers (self-advertisement)
+    ///
+    /// Advertises `listen_addr` and, to peers that negotiated addrv2, our
+    /// onion service.
     pub async fn advertise_self(&self, listen_addr: SocketAddr, services: u64) -> Result<()> {
         if !self.enable_self_advertisement {
             return Ok(()); // Self-advertisement disabled
         }
 
-        use crate::network::protocol::{
-            AddrMessage, NetworkAddress, ProtocolMessage, ProtocolParser,
-        };
-        use std::net::IpAddr;
-
-        // Convert SocketAddr to NetworkAddress
-        let ip_bytes = match listen_addr.ip() {
-            IpAddr::V4(ipv4) => {
-                // IPv4-mapped IPv6 format
-                let mut bytes = [0u8; 16];
-                bytes[10] = 0xff;
-                bytes[11] = 0xff;
-                bytes[12..16].copy_from_slice(&ipv4.octets());
-                bytes
-            }
-            IpAddr::V6(ipv6) => ipv6.octets(),
-        };
-
-        let our_addr = NetworkAddress {
+        let mut addresses = vec![NetworkAddressV2 {
+            time: current_timestamp() as u32,
             services,
-            ip: ip_bytes,
+            addr: NetAddr::from(listen_addr.ip()),
             port: listen_addr.port(),
-        };
-
-        // Create Addr message with just our address
-        let addr_msg = AddrMessage {
-            addresses: vec![our_addr.clone()],
-        };
-        let relay_msg = ProtocolMessage::Addr(addr_msg);
-        let wire_msg = ProtocolParser::serialize_message(&relay_msg)?;
+        }];
+        addresses.extend(self.onion_service_address(services));
 
-        // Send to all connected peers
+        // Send to all connected peers, in the format each one negotiated
         let peer_addrs: Vec<SocketAddr> = {
             let pm = self.peer_manager.lock().await;
             pm.peer_socket_addresses()
         };
 
         for peer_addr in peer_addrs {
-            if let Err(e) = self.send_to_peer(peer_addr, wire_msg.clone()).await {
+            let Some(wire_msg) = self.addr_message_for(peer_addr, &addresses).await? else {
+                continue;
+            };
+            if let Err(e) = self.send_to_peer(peer_addr, wire_msg).await {
                 warn!("Failed to advertise self to {}: {}", peer_addr, e);
             }
         }
 
-        // Also store our own address in database
-        {
-            let mut db = self.address_database.write().await;
-            db.add_address(our_addr, services);
-        }
-
         Ok(())
     }
 
+    /// Our onion service as an addrv2 entry, if Tor has published it
+    fn onion_service_address(&self, services: u64) -> Option<NetworkAddressV2> {
+        self.onion_service.map(|service| NetworkAddressV2 {
+            time: current_timestamp() as u32,
+            services,
+            addr: service.addr,
+            port: service.port,
+        })
+    }
+
     /// Get list of banned peers
     pub fn get_banned_peers(&self) -> Vec<(SocketAddr, u64)> {
         // Use block_in_place to avoid blocking async runtime
@@ -4398,6 +4803,35 @@ impl NetworkManager {
         &self.dos_protection
     }
 
+    /// Get the address manager (for persistence and RPC statistics)
+    pub fn address_manager(&self) -> &Arc<RwLock<addrman::AddrMan>> {
+        &self.address_manager
+    }
+
+    /// Networks we can open outbound connections on
+    ///
+    /// IPv4 and IPv6, plus the overlay networks a proxy is configured for,
+    /// limited to `only_networks` if set. Addresses on other networks are
+    /// still stored and relayed.
+    pub fn reachable_networks(&self) -> Vec<Network> {
+        let proxies = self.tcp_transport.proxies();
+        Network::ALL
+            .into_iter()
+            .filter(|network| proxies.is_reachable(*network))
+            .filter(|network| self.only_networks.is_empty() || self.only_networks.contains(network))
+            .collect()
+    }
+
+    /// Proxies used for outbound connections
+    pub fn proxies(&self) -> &socks5::ProxySettings {
+        self.tcp_transport.proxies()
+    }
+
+    /// Our onion service address, once Tor has published it
+    pub fn onion_service(&self) -> Option<&ServiceAddr> {
+        self.onion_service.as_ref()
+    }
+
     /// Get network statistics
     pub async fn get_network_stats(&self) -> crate::node::metrics::NetworkMetrics {
         let sent = self.bytes_sent.load(Ordering::Relaxed);
@@ -4444,9 +4878,7 @@ impl NetworkManager {
         })
     }
 
-    /// Create version message with service flags
-    ///
-    /// Creates version message with service flags for all supported features
+    /// Service flags we offer
     ///
     /// Sets service flags based on:
     /// - Standard Bitcoin flags:
@@ -4458,18 +4890,8 @@ impl NetworkManager {
     /// - Dandelion: NODE_DANDELION (if feature enabled)
     /// - Package Relay: NODE_PACKAGE_RELAY (always enabled)
     /// - FIBRE: NODE_FIBRE (always enabled)
-    pub fn create_version_message(
-        &self,
-        version: i32,
-        services: u64,
-        timestamp: i64,
-        addr_recv: crate::network::protocol::NetworkAddress,
-        addr_from: crate::network::protocol::NetworkAddress,
-        nonce: u64,
-        user_agent: String,
-        start_height: i32,
-        relay: bool,
-    ) -> crate::network::protocol::VersionMessage {
+    /// - BIP324: NODE_P2P_V2 (if v2 transport enabled)
+    pub fn local_services(&self) -> u64 {
         use bllvm_protocol::bip157::NODE_COMPACT_FILTERS;
         use bllvm_protocol::service_flags::standard;
 
@@ -4488,9 +4910,6 @@ impl NetworkManager {
             }
         }
 
-        // Add any additional services passed in (for future extensibility)
-        services_with_filters |= services;
-
         // BIP157 Compact Block Filters (always enabled if filter service exists)
         services_with_filters |= NODE_COMPACT_FILTERS;
 
@@ -4523,9 +4942,33 @@ impl NetworkManager {
         // FIBRE - always enabled
         services_with_filters |= crate::network::protocol::NODE_FIBRE;
 
+        // BIP324 v2 transport (if enabled)
+        if self.tcp_transport.v2_enabled() {
+            services_with_filters |= crate::network::protocol::NODE_P2P_V2;
+        }
+
+        services_with_filters
+    }
+
+    /// Create version message with service flags
+    ///
+    /// Creates version message with service flags for all supported features:
+    /// our [`local_services`](Self::local_services) plus any `services` passed in
+    pub fn create_version_message(
+        &self,
+        version: i32,
+        services: u64,
+        timestamp: i64,
+        addr_recv: crate::network::protocol::NetworkAddress,
+        addr_from: crate::network::protocol::NetworkAddress,
+        nonce: u64,
+        user_agent: String,
+        start_height: i32,
+        relay: bool,
+    ) -> crate::network::protocol::VersionMessage {
         crate::network::protocol::VersionMessage {
             version,
-            services: services_with_filters,
+            services: self.local_services() | services,
             timestamp,
             addr_recv,
             addr_from,
@@ -0,0 +1,429 @@
+//! Network addresses of every BIP155 network
+//!
+//! Legacy `addr` messages can only carry 16-byte IPv6-mapped addresses.
+//! [`NetAddr`] covers the networks that addrv2 adds: Tor v3 onion
+//! services, I2P and CJDNS. [`ServiceAddr`] pairs one with a port and is
+//! what the address manager and relay key addresses by.
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+/// BIP155 network ID of IPv4
+pub const BIP155_IPV4: u8 = 1;
+/// BIP155 network ID of IPv6
+pub const BIP155_IPV6: u8 = 2;
+/// BIP155 network ID of Tor v2 (deprecated, never stored)
+pub const BIP155_TORV2: u8 = 3;
+/// BIP155 network ID of Tor v3
+pub const BIP155_TORV3: u8 = 4;
+/// BIP155 network ID of I2P
+pub const BIP155_I2P: u8 = 5;
+/// BIP155 network ID of CJDNS
+pub const BIP155_CJDNS: u8 = 6;
+
+/// Tor v3 onion address version byte
+const TORV3_VERSION: u8 = 3;
+const ONION_SUFFIX: &str = ".onion";
+const I2P_SUFFIX: &str = ".b32.i2p";
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Network an address belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Ipv4,
+    Ipv6,
+    Onion,
+    I2p,
+    Cjdns,
+}
+
+impl Network {
+    /// Every network, in reporting order
+    pub const ALL: [Network; 5] = [
+        Network::Ipv4,
+        Network::Ipv6,
+        Network::Onion,
+        Network::I2p,
+        Network::Cjdns,
+    ];
+
+    /// Name used in RPC results and parameters
+    pub fn name(&self) -> &'static str {
+        match self {
+            Network::Ipv4 => "ipv4",
+            Network::Ipv6 => "ipv6",
+            Network::Onion => "onion",
+            Network::I2p => "i2p",
+            Network::Cjdns => "cjdns",
+        }
+    }
+
+    /// Look up a network by name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|network| network.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Address on one of the BIP155 networks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum NetAddr {
+    Ipv4([u8; 4]),
+    Ipv6([u8; 16]),
+    /// Tor v3 onion service public key
+    TorV3([u8; 32]),
+    /// SHA256 of an I2P destination
+    I2p([u8; 32]),
+    Cjdns([u8; 16]),
+}
+
+impl NetAddr {
+    /// Decode an address from its BIP155 network ID and bytes
+    ///
+    /// Returns `Ok(None)` for addresses that must be ignored: unknown
+    /// networks, Tor v2 and IPv4-mapped IPv6. A known network with the
+    /// wrong address length is an error.
+    pub fn from_bip155(network_id: u8, bytes: &[u8]) -> Result<Option<Self>> {
+        let expected = match network_id {
+            BIP155_IPV4 => 4,
+            BIP155_IPV6 | BIP155_CJDNS => 16,
+            BIP155_TORV2 => 10,
+            BIP155_TORV3 | BIP155_I2P => 32,
+            _ => return Ok(None),
+        };
+        if bytes.len() != expected {
+            bail!(
+                "BIP155 network {} address has {} bytes, expected {}",
+                network_id,
+                bytes.len(),
+                expected
+            );
+        }
+        Ok(match network_id {
+            BIP155_IPV4 => Some(NetAddr::Ipv4(bytes.try_into()?)),
+            BIP155_IPV6 => {
+                let ip = Ipv6Addr::from(<[u8; 16]>::try_from(bytes)?);
+                // Embedded IPv4 must be sent as IPv4
+                ip.to_ipv4_mapped()
+                    .is_none()
+                    .then(|| NetAddr::Ipv6(ip.octets()))
+            }
+            BIP155_TORV3 => Some(NetAddr::TorV3(bytes.try_into()?)),
+            BIP155_I2P => Some(NetAddr::I2p(bytes.try_into()?)),
+            BIP155_CJDNS => Some(NetAddr::Cjdns(bytes.try_into()?)),
+            _ => None,
+        })
+    }
+
+    /// BIP155 network ID
+    pub fn bip155_id(&self) -> u8 {
+        match self {
+            NetAddr::Ipv4(_) => BIP155_IPV4,
+            NetAddr::Ipv6(_) => BIP155_IPV6,
+            NetAddr::TorV3(_) => BIP155_TORV3,
+            NetAddr::I2p(_) => BIP155_I2P,
+            NetAddr::Cjdns(_) => BIP155_CJDNS,
+        }
+    }
+
+    /// Raw address bytes, as sent in addrv2
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            NetAddr::Ipv4(bytes) => bytes,
+            NetAddr::Ipv6(bytes) | NetAddr::Cjdns(bytes) => bytes,
+            NetAddr::TorV3(bytes) | NetAddr::I2p(bytes) => bytes,
+        }
+    }
+
+    /// Network the address belongs to
+    pub fn network(&self) -> Network {
+        match self {
+            NetAddr::Ipv4(_) => Network::Ipv4,
+            NetAddr::Ipv6(_) => Network::Ipv6,
+            NetAddr::TorV3(_) => Network::Onion,
+            NetAddr::I2p(_) => Network::I2p,
+            NetAddr::Cjdns(_) => Network::Cjdns,
+        }
+    }
+
+    /// Convert from the 16-byte form used by legacy `addr` messages
+    pub fn from_legacy(ip: [u8; 16]) -> Self {
+        Self::from(IpAddr::V6(Ipv6Addr::from(ip)))
+    }
+
+    /// The 16-byte legacy form, if the address is IPv4 or IPv6
+    pub fn to_legacy(&self) -> Option<[u8; 16]> {
+        match self.to_ip()? {
+            IpAddr::V4(ipv4) => Some(ipv4.to_ipv6_mapped().octets()),
+            IpAddr::V6(ipv6) => Some(ipv6.octets()),
+        }
+    }
+
+    /// The IP address, if the address is IPv4 or IPv6
+    pub fn to_ip(&self) -> Option<IpAddr> {
+        match self {
+            NetAddr::Ipv4(bytes) => Some(IpAddr::V4(Ipv4Addr::from(*bytes))),
+            NetAddr::Ipv6(bytes) => Some(IpAddr::V6(Ipv6Addr::from(*bytes))),
+            _ => None,
+        }
+    }
+
+    /// Check if the address is loopback, private or otherwise local
+    ///
+    /// Overlay network addresses are never local.
+    pub fn is_local(&self) -> bool {
+        match self.to_ip() {
+            Some(IpAddr::V4(ipv4)) => {
+                ipv4.is_loopback()
+                    || ipv4.is_private()
+                    || ipv4.is_link_local()
+                    || ipv4.is_broadcast()
+                    || ipv4 == Ipv4Addr::UNSPECIFIED
+            }
+            Some(IpAddr::V6(ipv6)) => {
+                let octets = ipv6.octets();
+                ipv6.is_loopback()
+                    || ipv6.is_unspecified()
+                    || (octets[0] == 0xfe && (octets[1] & 0xc0) == 0x80) // Link-local (fe80::/10)
+                    || octets[0] == 0xfc || octets[0] == 0xfd // Unique local (fc00::/7)
+                    || octets[0] == 0xff // Multicast (ff00::/8)
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the address can be relayed and connected to
+    pub fn is_routable(&self) -> bool {
+        match self {
+            // CJDNS addresses all live in fc00::/8
+            NetAddr::Cjdns(bytes) => bytes[0] == 0xfc,
+            _ => !self.is_local(),
+        }
+    }
+
+    /// Group of addresses assumed to be under the same control
+    ///
+    /// The /16 for IPv4, the /32 for IPv6, and the first four bits of the
+    /// key (or the first twelve after the fc prefix for CJDNS) for overlay
+    /// networks, where addresses cost nothing to generate.
+    pub fn group(&self) -> Vec<u8> {
+        match self {
+            NetAddr::Ipv4(bytes) => vec![BIP155_IPV4, bytes[0], bytes[1]],
+            NetAddr::Ipv6(bytes) => {
+                let mut group = vec![BIP155_IPV6];
+                group.extend_from_slice(&bytes[..4]);
+                group
+            }
+            NetAddr::TorV3(bytes) | NetAddr::I2p(bytes) => {
+                vec![self.bip155_id(), bytes[0] & 0xf0]
+            }
+            NetAddr::Cjdns(bytes) => vec![BIP155_CJDNS, bytes[1], bytes[2] & 0xf0],
+        }
+    }
+}
+
+impl From<IpAddr> for NetAddr {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ipv4) => NetAddr::Ipv4(ipv4.octets()),
+            IpAddr::V6(ipv6) => match ipv6.to_ipv4_mapped() {
+                Some(ipv4) => NetAddr::Ipv4(ipv4.octets()),
+                None => NetAddr::Ipv6(ipv6.octets()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for NetAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetAddr::Ipv4(bytes) => write!(f, "{}", Ipv4Addr::from(*bytes)),
+            NetAddr::Ipv6(bytes) | NetAddr::Cjdns(bytes) => {
+                write!(f, "{}", Ipv6Addr::from(*bytes))
+            }
+            NetAddr::TorV3(pubkey) => {
+                let mut data = pubkey.to_vec();
+                data.extend_from_slice(&onion_checksum(pubkey));
+                data.push(TORV3_VERSION);
+                write!(f, "{}{}", base32_encode(&data), ONION_SUFFIX)
+            }
+            NetAddr::I2p(hash) => write!(f, "{}{}", base32_encode(hash), I2P_SUFFIX),
+        }
+    }
+}
+
+impl FromStr for NetAddr {
+    type Err = anyhow::Error;
+
+    /// Parse an IP address, a v3 `.onion` or a `.b32.i2p` host name
+    fn from_str(s: &str) -> Result<Self> {
+        let lower = s.to_ascii_lowercase();
+        if let Some(encoded) = lower.strip_suffix(ONION_SUFFIX) {
+            let data = base32_decode(encoded)
+                .filter(|data| data.len() == 35)
+                .ok_or_else(|| anyhow!("Invalid onion address: {}", s))?;
+            let pubkey: [u8; 32] = data[..32].try_into()?;
+            if data[34] != TORV3_VERSION || data[32..34] != onion_checksum(&pubkey) {
+                bail!("Invalid onion address checksum or version: {}", s);
+            }
+            return Ok(NetAddr::TorV3(pubkey));
+        }
+        if let Some(encoded) = lower.strip_suffix(I2P_SUFFIX) {
+            let hash = base32_decode(encoded)
+                .and_then(|data| <[u8; 32]>::try_from(data).ok())
+                .ok_or_else(|| anyhow!("Invalid I2P address: {}", s))?;
+            return Ok(NetAddr::I2p(hash));
+        }
+        s.parse::<IpAddr>()
+            .map(NetAddr::from)
+            .map_err(|_| anyhow!("Invalid network address: {}", s))
+    }
+}
+
+/// Address and port of a node on any network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ServiceAddr {
+    pub addr: NetAddr,
+    pub port: u16,
+}
+
+impl ServiceAddr {
+    pub fn new(addr: NetAddr, port: u16) -> Self {
+        Self { addr, port }
+    }
+
+    /// The socket address, if the address is IPv4 or IPv6
+    pub fn to_socket(&self) -> Option<SocketAddr> {
+        self.addr.to_ip().map(|ip| SocketAddr::new(ip, self.port))
+    }
+
+    /// Address and port, identifying an address manager entry
+    pub fn key(&self) -> Vec<u8> {
+        let mut key = vec![self.addr.bip155_id()];
+        key.extend_from_slice(self.addr.as_bytes());
+        key.extend_from_slice(&self.port.to_be_bytes());
+        key
+    }
+}
+
+impl From<SocketAddr> for ServiceAddr {
+    fn from(socket: SocketAddr) -> Self {
+        Self::new(NetAddr::from(socket.ip()), socket.port())
+    }
+}
+
+impl fmt::Display for ServiceAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.addr {
+            NetAddr::Ipv6(_) | NetAddr::Cjdns(_) => write!(f, "[{}]:{}", self.addr, self.port),
+            _ => write!(f, "{}:{}", self.addr, self.port),
+        }
+    }
+}
+
+impl FromStr for ServiceAddr {
+    type Err = anyhow::Error;
+
+    /// Parse `host:port`, with IPv6 hosts in brackets
+    fn from_str(s: &str) -> Result<Self> {
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("Missing port in address: {}", s))?;
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        let port = port
+            .parse()
+            .map_err(|_| anyhow!("Invalid port in address: {}", s))?;
+        Ok(Self::new(host.parse()?, port))
+    }
+}
+
+/// First two bytes of SHA3-256(".onion checksum" || pubkey || version)
+fn onion_checksum(pubkey: &[u8; 32]) -> [u8; 2] {
+    let mut hasher = Sha3_256::new();
+    hasher.update(b".onion checksum");
+    hasher.update(pubkey);
+    hasher.update([TORV3_VERSION]);
+    let digest = hasher.finalize();
+    [digest[0], digest[1]]
+}
+
+/// Lowercase RFC 4648 base32 without padding
+fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Decode lowercase base32 without padding, rejecting non-zero trailing bits
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in encoded.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|&a| a == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    (bits < 5 && buffer & ((1 << bits) - 1) == 0).then_some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base32_round_trip() {
+        for len in 0..40 {
+            let data: Vec<u8> = (0..len as u8).map(|i| i.wrapping_mul(37)).collect();
+            assert_eq!(base32_decode(&base32_encode(&data)), Some(data));
+        }
+        assert_eq!(base32_encode(b"foobar"), "mzxw6ytboi");
+        assert!(base32_decode("mzxw6ytbo1").is_none());
+        // Leftover bits must be zero
+        assert!(base32_decode("mzxw6ytboj").is_none());
+    }
+
+    #[test]
+    fn test_known_onion_address() {
+        // Tor Project's onion service
+        let host = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion";
+        let addr: NetAddr = host.parse().unwrap();
+        assert_eq!(addr.network(), Network::Onion);
+        assert_eq!(addr.to_string(), host);
+
+        // A single flipped character breaks the checksum
+        let broken = host.replacen('2', "3", 1);
+        assert!(broken.parse::<NetAddr>().is_err());
+    }
+}
@@ -8,7 +8,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
 use tracing::{debug, info, warn};
 
-use super::transport::{TransportAddr, TransportConnection};
+use super::transport::{TransportAddr, TransportConnection, TransportProtocol};
 use super::NetworkMessage;
 
 /// Peer connection state
@@ -43,6 +43,14 @@ pub struct Peer {
     last_block_received: Option<u64>,
     /// Last successful transaction received (Unix timestamp)
     last_tx_received: Option<u64>,
+    /// Transport protocol of the connection (v1 or BIP324 v2)
+    transport_protocol: TransportProtocol,
+    /// BIP324 session ID (v2 connections only)
+    session_id: Option<[u8; 32]>,
+    /// Whether the peer sent sendaddrv2 and gets addresses as addrv2 (BIP155)
+    wants_addrv2: bool,
+    /// Read task; aborting it, and dropping `send_tx`, closes the connection
+    read_task: tokio::task::AbortHandle,
 }
 
 impl Peer {
@@ -61,6 +69,8 @@ impl Peer {
 
         let transport_addr_clone = transport_addr.clone();
         let message_tx_clone = message_tx.clone();
+        let transport_protocol = conn.transport_protocol();
+        let session_id = conn.session_id();
 
         // Wrap connection in Arc<Mutex> to share between read and write tasks
         use std::sync::Arc;
@@ -70,7 +80,7 @@ impl Peer {
         let conn_write = Arc::clone(&conn);
 
         // Spawn read task using TransportConnection::recv
-        tokio::spawn(async move {
+        let read_task = tokio::spawn(async move {
             loop {
                 let data = {
                     let mut conn_guard = conn_read.lock().await;
@@ -98,7 +108,8 @@ impl Peer {
                 };
                 let _ = message_tx_clone.send(NetworkMessage::RawMessageReceived(data, peer_addr));
             }
-        });
+        })
+        .abort_handle();
 
         // Spawn write task using TransportConnection::send
         tokio::spawn(async move {
@@ -143,6 +154,10 @@ impl Peer {
             avg_response_time_ms: 0.0,
             last_block_received: None,
             last_tx_received: None,
+            transport_protocol,
+            session_id,
+            wants_addrv2: false,
+            read_task,
         }
     }
 
@@ -251,6 +266,14 @@ impl Peer {
         Ok(())
     }
 
+    /// Stop reading from the peer
+    ///
+    /// The connection closes once the peer is dropped, which ends the write task.
+    pub fn disconnect(&mut self) {
+        self.read_task.abort();
+        self.connected = false;
+    }
+
     /// Check if peer is connected
     pub fn is_connected(&self) -> bool {
         self.connected
@@ -308,4 +331,24 @@ impl Peer {
     pub fn conntime(&self) -> u64 {
         self.conntime
     }
+
+    /// Get the connection's transport protocol (v1 or BIP324 v2)
+    pub fn transport_protocol(&self) -> TransportProtocol {
+        self.transport_protocol
+    }
+
+    /// Get the BIP324 session ID (v2 connections only)
+    pub fn session_id(&self) -> Option<[u8; 32]> {
+        self.session_id
+    }
+
+    /// Whether addresses should be relayed to the peer as addrv2
+    pub fn wants_addrv2(&self) -> bool {
+        self.wants_addrv2
+    }
+
+    /// Record that the peer sent sendaddrv2
+    pub fn set_wants_addrv2(&mut self) {
+        self.wants_addrv2 = true;
+    }
 }
@@ -2,6 +2,7 @@
 //!
 //! Implements Bitcoin P2P protocol message serialization and deserialization.
 
+use crate::network::netaddr::{NetAddr, ServiceAddr};
 use crate::network::transport::TransportType;
 use anyhow::Result;
 use bllvm_protocol::{Block, BlockHeader, Hash, Transaction};
@@ -27,6 +28,8 @@ pub const NODE_UTXO_COMMITMENTS: u64 = 1 << 27;
 pub const NODE_BAN_LIST_SHARING: u64 = 1 << 28;
 /// Governance message relay support (EconomicNodeRegistration, EconomicNodeVeto, EconomicNodeStatus)
 pub const NODE_GOVERNANCE: u64 = 1 << 29;
+/// BIP324 v2 encrypted transport support
+pub const NODE_P2P_V2: u64 = 1 << 11;
 
 /// Allowed Bitcoin protocol commands
 pub const ALLOWED_COMMANDS: &[&str] = &[
@@ -44,6 +47,9 @@ pub const ALLOWED_COMMANDS: &[&str] = &[
     "notfound",
     "getaddr",
     "addr",
+    // Address relay (BIP155)
+    "sendaddrv2",
+    "addrv2",
     "mempool",
     "reject",
     "feefilter",
@@ -146,6 +152,8 @@ pub enum ProtocolMessage {
     // Address relay
     GetAddr,
     Addr(AddrMessage),
+    SendAddrV2,
+    AddrV2(AddrV2Message),
     // Module Registry
     GetModule(GetModuleMessage),
     Module(ModuleMessage),
@@ -198,6 +206,11 @@ impl VersionMessage {
         (self.services & NODE_FIBRE) != 0
     }
 
+    /// Check if peer supports the BIP324 v2 transport
+    pub fn supports_v2_transport(&self) -> bool {
+        (self.services & NODE_P2P_V2) != 0
+    }
+
     #[cfg(feature = "dandelion")]
     /// Check if peer supports Dandelion
     pub fn supports_dandelion(&self) -> bool {
@@ -902,6 +915,8 @@ impl ProtocolParser {
             )?)),
             "getaddr" => Ok(ProtocolMessage::GetAddr),
             "addr" => Ok(ProtocolMessage::Addr(bincode::deserialize(payload)?)),
+            "sendaddrv2" => Ok(ProtocolMessage::SendAddrV2),
+            "addrv2" => Ok(ProtocolMessage::AddrV2(AddrV2Message::decode(payload)?)),
             // Module Registry
             "getmodule" => Ok(ProtocolMessage::GetModule(bincode::deserialize(payload)?)),
             "module" => Ok(ProtocolMessage::Module(bincode::deserialize(payload)?)),
@@ -1014,6 +1029,8 @@ impl ProtocolParser {
             // Address relay
             ProtocolMessage::GetAddr => ("getaddr", vec![]),
             ProtocolMessage::Addr(msg) => ("addr", bincode::serialize(msg)?),
+            ProtocolMessage::SendAddrV2 => ("sendaddrv2", vec![]),
+            ProtocolMessage::AddrV2(msg) => ("addrv2", msg.encode()),
             // Module Registry
             ProtocolMessage::GetModule(msg) => ("getmodule", bincode::serialize(msg)?),
             ProtocolMessage::Module(msg) => ("module", bincode::serialize(msg)?),
@@ -1199,3 +1216,151 @@ pub struct AddrMessage {
     /// List of network addresses
     pub addresses: Vec<NetworkAddress>,
 }
+
+/// Maximum number of addresses in an addr or addrv2 message
+pub const MAX_ADDR_TO_SEND: usize = 1000;
+
+/// Maximum length of an address in an addrv2 message (BIP155)
+pub const MAX_ADDRV2_SIZE: usize = 512;
+
+/// Address on any BIP155 network, as carried by addrv2
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NetworkAddressV2 {
+    /// Unix timestamp the address was last seen
+    pub time: u32,
+    pub services: u64,
+    pub addr: NetAddr,
+    pub port: u16,
+}
+
+impl NetworkAddressV2 {
+    /// Address and port
+    pub fn service(&self) -> ServiceAddr {
+        ServiceAddr::new(self.addr, self.port)
+    }
+
+    /// Legacy form for addr messages, if the address is IPv4 or IPv6
+    pub fn to_v1(&self) -> Option<NetworkAddress> {
+        Some(NetworkAddress {
+            services: self.services,
+            ip: self.addr.to_legacy()?,
+            port: self.port,
+        })
+    }
+}
+
+impl From<NetworkAddress> for NetworkAddressV2 {
+    fn from(addr: NetworkAddress) -> Self {
+        Self {
+            time: 0,
+            services: addr.services,
+            addr: NetAddr::from_legacy(addr.ip),
+            port: addr.port,
+        }
+    }
+}
+
+/// AddrV2 message - Peer addresses with variable-length network IDs (BIP155)
+///
+/// Unlike the other messages this one uses the Bitcoin wire encoding, so it
+/// interoperates with other implementations.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddrV2Message {
+    pub addresses: Vec<NetworkAddressV2>,
+}
+
+impl AddrV2Message {
+    /// Encode the message payload
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        write_compact_size(&mut payload, self.addresses.len() as u64);
+        for entry in &self.addresses {
+            payload.extend_from_slice(&entry.time.to_le_bytes());
+            write_compact_size(&mut payload, entry.services);
+            payload.push(entry.addr.bip155_id());
+            let bytes = entry.addr.as_bytes();
+            write_compact_size(&mut payload, bytes.len() as u64);
+            payload.extend_from_slice(bytes);
+            payload.extend_from_slice(&entry.port.to_be_bytes());
+        }
+        payload
+    }
+
+    /// Decode a message payload
+    ///
+    /// Addresses on networks we don't know are skipped, as BIP155 requires.
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        let mut cursor = payload;
+        let count = read_compact_size(&mut cursor)? as usize;
+        if count > MAX_ADDR_TO_SEND {
+            return Err(anyhow::anyhow!("addrv2 message has {} addresses", count));
+        }
+        let mut addresses = Vec::with_capacity(count);
+        for _ in 0..count {
+            let time = u32::from_le_bytes(take(&mut cursor, 4)?.try_into()?);
+            let services = read_compact_size(&mut cursor)?;
+            let network_id = take(&mut cursor, 1)?[0];
+            let len = read_compact_size(&mut cursor)? as usize;
+            if len > MAX_ADDRV2_SIZE {
+                return Err(anyhow::anyhow!("addrv2 address too long: {} bytes", len));
+            }
+            let bytes = take(&mut cursor, len)?;
+            let port = u16::from_be_bytes(take(&mut cursor, 2)?.try_into()?);
+            if let Some(addr) = NetAddr::from_bip155(network_id, bytes)? {
+                addresses.push(NetworkAddressV2 {
+                    time,
+                    services,
+                    addr,
+                    port,
+                });
+            }
+        }
+        if !cursor.is_empty() {
+            return Err(anyhow::anyhow!("Trailing data after addrv2 message"));
+        }
+        Ok(Self { addresses })
+    }
+}
+
+/// Append a Bitcoin CompactSize integer
+fn write_compact_size(out: &mut Vec<u8>, value: u64) {
+    if value < 0xfd {
+        out.push(value as u8);
+    } else if value <= 0xffff {
+        out.push(0xfd);
+        out.extend_from_slice(&(value as u16).to_le_bytes());
+    } else if value <= 0xffff_ffff {
+        out.push(0xfe);
+        out.extend_from_slice(&(value as u32).to_le_bytes());
+    } else {
+        out.push(0xff);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Read a canonically encoded Bitcoin CompactSize integer
+fn read_compact_size(cursor: &mut &[u8]) -> Result<u64> {
+    let (len, min) = match take(cursor, 1)?[0] {
+        0xfd => (2, 0xfd),
+        0xfe => (4, 0x1_0000),
+        0xff => (8, 0x1_0000_0000),
+        byte => return Ok(byte as u64),
+    };
+    let mut bytes = [0u8; 8];
+    bytes[..len].copy_from_slice(take(cursor, len)?);
+    let value = u64::from_le_bytes(bytes);
+    if value < min {
+        return Err(anyhow::anyhow!("Non-canonical CompactSize"));
+    }
+    Ok(value)
+}
+
+/// Split `len` bytes off the front of `cursor`
+fn take<'a>(cursor: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if cursor.len() < len {
+        return Err(anyhow::anyhow!("Unexpected end of message"));
+    }
+    let (head, rest) = cursor.split_at(len);
+    *cursor = rest;
+    Ok(head)
+}
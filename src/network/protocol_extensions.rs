@@ -40,7 +40,7 @@ pub async fn handle_get_utxo_set(
     };
 
     // Get UTXO set from storage
-    let utxo_set = storage.utxos().get_all_utxos()?;
+    let utxo_set = storage.utxo_cache().get_all_utxos()?;
     let utxo_count = utxo_set.len() as u64;
 
     // Get block hash and height
@@ -0,0 +1,259 @@
+//! SOCKS5 proxy client
+//!
+//! Routes outbound P2P connections through a SOCKS5 proxy such as Tor
+//! (RFC 1928, with username/password authentication from RFC 1929). Onion
+//! and I2P targets are sent to the proxy as host names, so they never need
+//! to be resolved locally.
+//!
+//! With credential randomization, every connection authenticates with a
+//! fresh username and password. Tor isolates streams by SOCKS credentials,
+//! so each peer gets its own circuit.
+
+use crate::network::netaddr::{NetAddr, Network, ServiceAddr};
+use anyhow::{bail, Result};
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+/// How long connecting to the proxy and the SOCKS5 handshake may take
+const SOCKS5_TIMEOUT: Duration = Duration::from_secs(20);
+
+const SOCKS5_VERSION: u8 = 0x05;
+/// Version of the username/password subnegotiation (RFC 1929)
+const AUTH_VERSION: u8 = 0x01;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USER_PASS: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+
+const CMD_CONNECT: u8 = 0x01;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// SOCKS5 proxy for outbound connections
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    addr: SocketAddr,
+    randomize_credentials: bool,
+}
+
+impl Socks5Proxy {
+    /// Proxy at `addr`, with credential randomization enabled
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            randomize_credentials: true,
+        }
+    }
+
+    /// Enable or disable random credentials for stream isolation
+    pub fn with_randomized_credentials(mut self, enabled: bool) -> Self {
+        self.randomize_credentials = enabled;
+        self
+    }
+
+    /// Address of the proxy
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Whether each connection authenticates with random credentials
+    pub fn randomizes_credentials(&self) -> bool {
+        self.randomize_credentials
+    }
+
+    /// Connect to `target` through the proxy
+    ///
+    /// Returns the stream once the proxy reports the connection established;
+    /// from then on it carries the target's traffic.
+    pub async fn connect(&self, target: &ServiceAddr) -> Result<TcpStream> {
+        let credentials = self.randomize_credentials.then(random_credentials);
+        tokio::time::timeout(SOCKS5_TIMEOUT, async {
+            let mut stream = TcpStream::connect(self.addr).await?;
+            handshake(&mut stream, target, credentials.as_ref()).await?;
+            Ok(stream)
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("SOCKS5 connection to {} timed out", target))?
+    }
+}
+
+/// Random username and password, so the proxy puts the stream on its own circuit
+fn random_credentials() -> (String, String) {
+    let mut rng = rand::thread_rng();
+    (
+        format!("{:016x}", rng.gen::<u64>()),
+        format!("{:016x}", rng.gen::<u64>()),
+    )
+}
+
+/// Negotiate authentication and send the CONNECT request
+async fn handshake(
+    stream: &mut TcpStream,
+    target: &ServiceAddr,
+    credentials: Option<&(String, String)>,
+) -> Result<()> {
+    // Offer username/password only when we have credentials to send
+    let greeting: &[u8] = match credentials {
+        Some(_) => &[SOCKS5_VERSION, 2, METHOD_NO_AUTH, METHOD_USER_PASS],
+        None => &[SOCKS5_VERSION, 1, METHOD_NO_AUTH],
+    };
+    stream.write_all(greeting).await?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice[0] != SOCKS5_VERSION {
+        bail!("Proxy replied with SOCKS version {}", choice[0]);
+    }
+    match (choice[1], credentials) {
+        (METHOD_NO_AUTH, _) => {}
+        (METHOD_USER_PASS, Some((username, password))) => {
+            authenticate(stream, username, password).await?
+        }
+        (METHOD_NONE_ACCEPTABLE, _) => bail!("Proxy accepted none of our authentication methods"),
+        (method, _) => bail!("Proxy chose unoffered authentication method {}", method),
+    }
+
+    let mut request = vec![SOCKS5_VERSION, CMD_CONNECT, 0x00];
+    match target.addr {
+        NetAddr::Ipv4(bytes) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&bytes);
+        }
+        // CJDNS addresses are IPv6 addresses in fc00::/8
+        NetAddr::Ipv6(bytes) | NetAddr::Cjdns(bytes) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&bytes);
+        }
+        NetAddr::TorV3(_) | NetAddr::I2p(_) => {
+            let host = target.addr.to_string();
+            request.push(ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&target.port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS5_VERSION {
+        bail!("Proxy replied with SOCKS version {}", reply[0]);
+    }
+    if reply[1] != 0x00 {
+        bail!(
+            "Proxy failed to connect to {}: {}",
+            target,
+            reply_error(reply[1])
+        );
+    }
+    if reply[2] != 0x00 {
+        bail!("Malformed SOCKS5 reply");
+    }
+    // Skip the bound address, which we have no use for
+    let bound_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        atyp => bail!("Malformed SOCKS5 reply address type {}", atyp),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    debug!("SOCKS5 proxy connected to {}", target);
+    Ok(())
+}
+
+/// Username/password subnegotiation (RFC 1929)
+async fn authenticate(stream: &mut TcpStream, username: &str, password: &str) -> Result<()> {
+    if username.len() > 255 || password.len() > 255 {
+        bail!("SOCKS5 credentials are limited to 255 bytes");
+    }
+    let mut request = vec![AUTH_VERSION, username.len() as u8];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password.len() as u8);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != AUTH_VERSION || reply[1] != 0x00 {
+        bail!("Proxy rejected our credentials");
+    }
+    Ok(())
+}
+
+/// Description of a SOCKS5 reply code
+fn reply_error(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        // Tor extensions for onion services
+        0xf0 => "onion service descriptor not found",
+        0xf1 => "onion service descriptor invalid",
+        0xf2 => "onion service introduction failed",
+        0xf3 => "onion service rendezvous failed",
+        0xf4 => "onion service missing client authorization",
+        0xf5 => "onion service wrong client authorization",
+        0xf6 => "invalid onion service address",
+        0xf7 => "onion service introduction timed out",
+        _ => "unknown error",
+    }
+}
+
+/// Proxies to use per network
+///
+/// Networks without a proxy are connected to directly. Only IPv4 and IPv6
+/// can be; the overlay networks are reachable only through a proxy.
+#[derive(Debug, Clone, Default)]
+pub struct ProxySettings {
+    proxies: HashMap<Network, Socks5Proxy>,
+}
+
+impl ProxySettings {
+    /// No proxies: every connection is direct
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Route connections to `network` through `proxy`
+    pub fn with_proxy(mut self, network: Network, proxy: Socks5Proxy) -> Self {
+        self.set_proxy(network, proxy);
+        self
+    }
+
+    /// Route connections to `network` through `proxy`
+    pub fn set_proxy(&mut self, network: Network, proxy: Socks5Proxy) {
+        self.proxies.insert(network, proxy);
+    }
+
+    /// Proxy for connections to `network`, if any
+    pub fn proxy_for(&self, network: Network) -> Option<&Socks5Proxy> {
+        self.proxies.get(&network)
+    }
+
+    /// Whether any network uses a proxy
+    pub fn is_empty(&self) -> bool {
+        self.proxies.is_empty()
+    }
+
+    /// Whether addresses on `network` can be connected to
+    pub fn is_reachable(&self, network: Network) -> bool {
+        match network {
+            Network::Ipv4 | Network::Ipv6 => true,
+            Network::Onion | Network::I2p | Network::Cjdns => self.proxies.contains_key(&network),
+        }
+    }
+}
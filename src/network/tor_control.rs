@@ -0,0 +1,260 @@
+//! Tor control port client
+//!
+//! Publishes the P2P listener as an ephemeral v3 onion service through Tor's
+//! control protocol: PROTOCOLINFO to learn the authentication methods,
+//! AUTHENTICATE, then ADD_ONION. The service exists as long as the control
+//! connection stays open, so [`TorControl`] must be kept alive.
+
+use crate::network::netaddr::{NetAddr, ServiceAddr};
+use anyhow::{bail, Result};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::{debug, info};
+
+/// How long each control command may take
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest reply line we accept from the control port
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Reply to a control port command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorReply {
+    /// Status code; 250 is success
+    pub code: u16,
+    /// Reply lines without status codes, data lines included
+    pub lines: Vec<String>,
+}
+
+impl TorReply {
+    /// Value of the first `KEY=value` line with the given key
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.lines
+            .iter()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+    }
+}
+
+/// Connection to a Tor control port
+pub struct TorControl {
+    stream: BufReader<TcpStream>,
+}
+
+impl TorControl {
+    /// Connect to the control port at `addr`
+    pub async fn connect(addr: SocketAddr) -> Result<Self> {
+        let stream = tokio::time::timeout(COMMAND_TIMEOUT, TcpStream::connect(addr))
+            .await
+            .map_err(|_| anyhow::anyhow!("Connecting to Tor control port {} timed out", addr))??;
+        debug!("Connected to Tor control port {}", addr);
+        Ok(Self {
+            stream: BufReader::new(stream),
+        })
+    }
+
+    /// Send a command and read its reply
+    pub async fn command(&mut self, command: &str) -> Result<TorReply> {
+        tokio::time::timeout(COMMAND_TIMEOUT, async {
+            self.stream
+                .get_mut()
+                .write_all(format!("{}\r\n", command).as_bytes())
+                .await?;
+            self.read_reply().await
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("Tor control command timed out"))?
+    }
+
+    /// Send a command, failing unless Tor replies 250
+    async fn command_ok(&mut self, command: &str) -> Result<TorReply> {
+        let reply = self.command(command).await?;
+        if reply.code != 250 {
+            // Don't echo arguments, which may include a password
+            let name = command.split(' ').next().unwrap_or(command);
+            bail!(
+                "Tor control command {} failed: {} {}",
+                name,
+                reply.code,
+                reply.lines.join(" ")
+            );
+        }
+        Ok(reply)
+    }
+
+    /// Read a reply: `NNN-` and `NNN+` lines continue it (the latter followed
+    /// by data lines ending in "."), an `NNN ` line ends it
+    async fn read_reply(&mut self) -> Result<TorReply> {
+        let mut lines = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            if line.len() < 4 || !line.is_char_boundary(4) {
+                bail!("Malformed Tor control reply: {:?}", line);
+            }
+            let code: u16 = line[..3]
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Malformed Tor control reply: {:?}", line))?;
+            let (separator, text) = (line.as_bytes()[3], &line[4..]);
+            lines.push(text.to_string());
+            match separator {
+                b' ' => return Ok(TorReply { code, lines }),
+                b'-' => {}
+                b'+' => loop {
+                    let data = self.read_line().await?;
+                    if data == "." {
+                        break;
+                    }
+                    // Leading dots are escaped by doubling
+                    let data = data.strip_prefix('.').unwrap_or(&data);
+                    lines.push(data.to_string());
+                },
+                _ => bail!("Malformed Tor control reply: {:?}", line),
+            }
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        let read = (&mut self.stream)
+            .take(MAX_LINE_LEN as u64)
+            .read_line(&mut line)
+            .await?;
+        if read == 0 {
+            bail!("Tor control connection closed");
+        }
+        if !line.ends_with('\n') {
+            bail!("Tor control reply line too long");
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Authenticate with whichever method Tor offers
+    ///
+    /// Tries no authentication, then the password if one is given, then the
+    /// cookie file Tor names.
+    pub async fn authenticate(&mut self, password: Option<&str>) -> Result<()> {
+        let reply = self.command_ok("PROTOCOLINFO 1").await?;
+        let auth = reply
+            .lines
+            .iter()
+            .find_map(|line| line.strip_prefix("AUTH "))
+            .ok_or_else(|| anyhow::anyhow!("Tor didn't list its authentication methods"))?;
+        let methods: Vec<&str> = auth_field(auth, "METHODS")
+            .map(|methods| methods.split(',').collect())
+            .unwrap_or_default();
+        debug!("Tor control authentication methods: {:?}", methods);
+
+        let command = if methods.contains(&"NULL") {
+            "AUTHENTICATE".to_string()
+        } else if let (Some(password), true) = (password, methods.contains(&"HASHEDPASSWORD")) {
+            format!("AUTHENTICATE {}", quote(password))
+        } else if methods.contains(&"COOKIE") {
+            let path = auth_field(auth, "COOKIEFILE")
+                .map(unquote)
+                .ok_or_else(|| anyhow::anyhow!("Tor didn't name its cookie file"))?;
+            let cookie = tokio::fs::read(&path)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to read Tor cookie file {}: {}", path, e))?;
+            format!("AUTHENTICATE {}", hex::encode(cookie))
+        } else if methods.contains(&"HASHEDPASSWORD") {
+            bail!("Tor control port requires a password");
+        } else {
+            bail!("No supported Tor authentication method in {:?}", methods);
+        };
+        self.command_ok(&command).await?;
+        Ok(())
+    }
+
+    /// Create an ephemeral v3 onion service forwarding `port` to `target`
+    ///
+    /// The service key is discarded, so the onion address changes every time.
+    pub async fn add_onion(&mut self, port: u16, target: SocketAddr) -> Result<ServiceAddr> {
+        let reply = self
+            .command_ok(&format!(
+                "ADD_ONION NEW:ED25519-V3 Flags=DiscardPK Port={},{}",
+                port, target
+            ))
+            .await?;
+        let service_id = reply
+            .value("ServiceID")
+            .ok_or_else(|| anyhow::anyhow!("Tor didn't return an onion service ID"))?;
+        let addr: NetAddr = format!("{}.onion", service_id).parse()?;
+        let service = ServiceAddr::new(addr, port);
+        info!("Created onion service {} for {}", service, target);
+        Ok(service)
+    }
+}
+
+/// Value of `KEY=value` in a space-separated PROTOCOLINFO AUTH line
+///
+/// Quoted values may contain spaces.
+fn auth_field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(&format!("{}=", key))? + key.len() + 1;
+    let rest = &line[start..];
+    let end = if let Some(quoted) = rest.strip_prefix('"') {
+        let mut escaped = false;
+        let close = quoted.char_indices().find_map(|(i, c)| {
+            let found = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            found.then_some(i)
+        })?;
+        close + 2
+    } else {
+        rest.find(' ').unwrap_or(rest.len())
+    };
+    Some(&rest[..end])
+}
+
+/// Quote a string for the control protocol
+fn quote(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
+
+/// Undo [`quote`]; unquoted values are returned as they are
+fn unquote(value: &str) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    else {
+        return value.to_string();
+    };
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                unquoted.push(escaped);
+            }
+        } else {
+            unquoted.push(c);
+        }
+    }
+    unquoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_fields() {
+        let line =
+            r#"METHODS=COOKIE,SAFECOOKIE COOKIEFILE="/var/lib/tor/my \"dir\"/control_auth_cookie""#;
+        assert_eq!(auth_field(line, "METHODS"), Some("COOKIE,SAFECOOKIE"));
+        let cookie_file = auth_field(line, "COOKIEFILE").unwrap();
+        assert_eq!(
+            unquote(cookie_file),
+            r#"/var/lib/tor/my "dir"/control_auth_cookie"#
+        );
+        assert_eq!(auth_field("METHODS=NULL", "COOKIEFILE"), None);
+    }
+
+    #[test]
+    fn test_quote_round_trip() {
+        let password = r#"pass "word" \ with escapes"#;
+        assert_eq!(unquote(&quote(password)), password);
+        assert_eq!(unquote("unquoted"), "unquoted");
+    }
+}
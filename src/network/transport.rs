@@ -153,6 +153,38 @@ pub trait TransportConnection: Send + Sync {
 
     /// Close the connection
     async fn close(&mut self) -> Result<()>;
+
+    /// Bitcoin P2P transport protocol spoken on the connection
+    ///
+    /// Default is v1 (plaintext framing); BIP324 connections report v2.
+    fn transport_protocol(&self) -> TransportProtocol {
+        TransportProtocol::V1
+    }
+
+    /// BIP324 session ID, for v2 connections
+    fn session_id(&self) -> Option<[u8; 32]> {
+        None
+    }
+}
+
+/// Bitcoin P2P transport protocol of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransportProtocol {
+    /// Plaintext v1 framing
+    #[default]
+    V1,
+    /// BIP324 v2 encrypted transport
+    V2,
+}
+
+impl TransportProtocol {
+    /// Name as shown in getpeerinfo (`transport_protocol_type`)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::V1 => "v1",
+            Self::V2 => "v2",
+        }
+    }
 }
 
 /// Transport listener - abstraction for accepting incoming connections
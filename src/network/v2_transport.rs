@@ -0,0 +1,1129 @@
+//! BIP324 v2 encrypted transport
+//!
+//! Runs the Bitcoin P2P protocol over TCP with an ElligatorSwift key exchange
+//! and ChaCha20-Poly1305 packet encryption. Connections fall back to the v1
+//! framing of [`TcpTransport`](crate::network::tcp_transport::TcpTransport)
+//! when the peer doesn't speak v2: outbound connections reconnect as v1 if the
+//! peer drops the key exchange, and the listener recognizes a v1 `version`
+//! message in place of a public key.
+//!
+//! Outbound connections go through the SOCKS5 proxy configured for the
+//! target's network, if any, which also makes onion addresses reachable.
+//!
+//! Callers keep sending and receiving v1 wire messages (magic, command, length,
+//! checksum, payload); the connection converts them to and from v2 packets,
+//! using the short message IDs BIP324 assigns.
+
+use crate::network::netaddr::ServiceAddr;
+use crate::network::protocol::{
+    ProtocolParser, BITCOIN_MAGIC_MAINNET, MAX_PROTOCOL_MESSAGE_LENGTH,
+};
+use crate::network::socks5::ProxySettings;
+use crate::network::tcp_transport::TcpConnection;
+use crate::network::transport::{
+    Transport, TransportAddr, TransportConnection, TransportListener, TransportProtocol,
+    TransportType,
+};
+use anyhow::Result;
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use chacha20::ChaCha20;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand::{Rng, RngCore};
+use secp256k1::ellswift::{ElligatorSwift, ElligatorSwiftParty};
+use secp256k1::{Secp256k1, SecretKey};
+use sha2::Sha256;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener as TokioTcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use zeroize::Zeroize;
+
+/// Length of an ElligatorSwift-encoded public key
+const ELLSWIFT_KEY_LEN: usize = 64;
+
+/// Maximum garbage sent after the public key
+const MAX_GARBAGE_LEN: usize = 4095;
+
+/// Length of the garbage terminators
+const GARBAGE_TERMINATOR_LEN: usize = 16;
+
+/// Length of a packet's encrypted length field
+const LENGTH_FIELD_LEN: usize = 3;
+
+/// Length of the Poly1305 tag of a packet
+const TAG_LEN: usize = 16;
+
+/// Largest contents a packet's 3-byte length field can describe
+const MAX_PACKET_CONTENTS_LEN: usize = (1 << 24) - 1;
+
+/// Header bit marking a packet (decoy) the receiver must ignore
+const IGNORE_BIT: u8 = 0x80;
+
+/// Packets (and length fields) encrypted under one key before rekeying
+const REKEY_INTERVAL: u32 = 224;
+
+/// Length of a v1 message header: magic, command, length and checksum
+const V1_HEADER_LEN: usize = 24;
+
+/// Bytes of a v1 peer's first message the listener checks: its 4-byte length
+/// prefix, the network magic and the 12-byte `version` command
+const V1_PREFIX_LEN: usize = 20;
+
+/// Time allowed for each stage of the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Completed handshakes waiting for `accept`
+const ACCEPT_QUEUE_LEN: usize = 64;
+
+/// BIP324 short message IDs: the command with ID `n` is at index `n - 1`
+const SHORT_MESSAGE_IDS: [&str; 28] = [
+    "addr",
+    "block",
+    "blocktxn",
+    "cmpctblock",
+    "feefilter",
+    "filteradd",
+    "filterclear",
+    "filterload",
+    "getblocks",
+    "getblocktxn",
+    "getdata",
+    "getheaders",
+    "headers",
+    "inv",
+    "mempool",
+    "merkleblock",
+    "notfound",
+    "ping",
+    "pong",
+    "sendcmpct",
+    "tx",
+    "getcfilters",
+    "cfilter",
+    "getcfheaders",
+    "cfheaders",
+    "getcfcheckpt",
+    "cfcheckpt",
+    "addrv2",
+];
+
+/// ChaCha20 keystream for packet lengths, rekeyed every [`REKEY_INTERVAL`] chunks
+struct FsChaCha20 {
+    stream: ChaCha20,
+    chunk_counter: u32,
+    rekey_counter: u64,
+}
+
+impl FsChaCha20 {
+    fn new(key: &[u8; 32]) -> Self {
+        Self {
+            stream: Self::keystream(key, 0),
+            chunk_counter: 0,
+            rekey_counter: 0,
+        }
+    }
+
+    /// Keystream of a rekey interval: nonce is 32 zero bits, then the rekey counter
+    fn keystream(key: &[u8; 32], rekey_counter: u64) -> ChaCha20 {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&rekey_counter.to_le_bytes());
+        ChaCha20::new(key.into(), &nonce.into())
+    }
+
+    fn crypt(&mut self, chunk: &mut [u8]) {
+        self.stream.apply_keystream(chunk);
+        self.chunk_counter += 1;
+        if self.chunk_counter == REKEY_INTERVAL {
+            // The next 32 bytes of keystream become the key
+            let mut key = [0u8; 32];
+            self.stream.apply_keystream(&mut key);
+            self.chunk_counter = 0;
+            self.rekey_counter += 1;
+            self.stream = Self::keystream(&key, self.rekey_counter);
+            key.zeroize();
+        }
+    }
+}
+
+/// ChaCha20-Poly1305 for packet contents, rekeyed every [`REKEY_INTERVAL`] packets
+struct FsChaCha20Poly1305 {
+    key: [u8; 32],
+    aead: ChaCha20Poly1305,
+    packet_counter: u32,
+    rekey_counter: u64,
+}
+
+impl FsChaCha20Poly1305 {
+    fn new(key: [u8; 32]) -> Self {
+        Self {
+            aead: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            key,
+            packet_counter: 0,
+            rekey_counter: 0,
+        }
+    }
+
+    /// Nonce of a packet: 32-bit packet counter, then the rekey counter
+    fn nonce(&self, packet_counter: u32) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..4].copy_from_slice(&packet_counter.to_le_bytes());
+        nonce[4..].copy_from_slice(&self.rekey_counter.to_le_bytes());
+        nonce
+    }
+
+    fn encrypt(&mut self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = self.nonce(self.packet_counter);
+        let ciphertext = self
+            .aead
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .expect("packet contents are within the ChaCha20-Poly1305 size limit");
+        self.next_packet();
+        ciphertext
+    }
+
+    fn decrypt(&mut self, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.nonce(self.packet_counter);
+        let plaintext = self
+            .aead
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Packet authentication failed"));
+        self.next_packet();
+        plaintext
+    }
+
+    fn next_packet(&mut self) {
+        self.packet_counter += 1;
+        if self.packet_counter == REKEY_INTERVAL {
+            // The new key is the first 32 bytes the AEAD would encrypt zeros to
+            // under a nonce no packet uses: the keystream from block 1, as
+            // block 0 makes the Poly1305 key
+            let nonce = self.nonce(u32::MAX);
+            let mut key = [0u8; 32];
+            let mut stream = ChaCha20::new((&self.key).into(), &nonce.into());
+            stream.seek(64u64);
+            stream.apply_keystream(&mut key);
+            self.key.zeroize();
+            self.key = key;
+            key.zeroize();
+            self.aead = ChaCha20Poly1305::new(Key::from_slice(&self.key));
+            self.packet_counter = 0;
+            self.rekey_counter += 1;
+        }
+    }
+}
+
+impl Drop for FsChaCha20Poly1305 {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// Ciphers and handshake values of a v2 session, from one side's view
+struct V2Cipher {
+    send_length: FsChaCha20,
+    send_packet: FsChaCha20Poly1305,
+    recv_length: FsChaCha20,
+    recv_packet: FsChaCha20Poly1305,
+    send_garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+    recv_garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+    session_id: [u8; 32],
+}
+
+impl V2Cipher {
+    /// Derive the session from the ECDH secret with HKDF-SHA256, salted with
+    /// the network magic
+    fn new(shared_secret: &[u8; 32], magic: [u8; 4], initiator: bool) -> Self {
+        let mut salt = b"bitcoin_v2_shared_secret".to_vec();
+        salt.extend_from_slice(&magic);
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared_secret);
+        let expand = |info: &str| {
+            let mut okm = [0u8; 32];
+            hkdf.expand(info.as_bytes(), &mut okm)
+                .expect("32 bytes is a valid HKDF-SHA256 output length");
+            okm
+        };
+
+        let (send, recv) = if initiator {
+            ("initiator", "responder")
+        } else {
+            ("responder", "initiator")
+        };
+        // The initiator's terminator comes first
+        let garbage_terminators = expand("garbage_terminators");
+        let (first, second) = garbage_terminators.split_at(GARBAGE_TERMINATOR_LEN);
+        let (send_terminator, recv_terminator) = if initiator {
+            (first, second)
+        } else {
+            (second, first)
+        };
+
+        let mut send_length_key = expand(&format!("{send}_L"));
+        let mut recv_length_key = expand(&format!("{recv}_L"));
+        let cipher = Self {
+            send_length: FsChaCha20::new(&send_length_key),
+            send_packet: FsChaCha20Poly1305::new(expand(&format!("{send}_P"))),
+            recv_length: FsChaCha20::new(&recv_length_key),
+            recv_packet: FsChaCha20Poly1305::new(expand(&format!("{recv}_P"))),
+            send_garbage_terminator: send_terminator.try_into().unwrap(),
+            recv_garbage_terminator: recv_terminator.try_into().unwrap(),
+            session_id: expand("session_id"),
+        };
+        send_length_key.zeroize();
+        recv_length_key.zeroize();
+        cipher
+    }
+
+    /// Encrypt a packet: the encrypted 3-byte contents length, then the header
+    /// byte and contents, authenticated with `aad`
+    fn encrypt_packet(&mut self, contents: &[u8], aad: &[u8], ignore: bool) -> Vec<u8> {
+        let mut length: [u8; LENGTH_FIELD_LEN] = (contents.len() as u32).to_le_bytes()
+            [..LENGTH_FIELD_LEN]
+            .try_into()
+            .unwrap();
+        self.send_length.crypt(&mut length);
+
+        let mut plaintext = Vec::with_capacity(1 + contents.len());
+        plaintext.push(if ignore { IGNORE_BIT } else { 0 });
+        plaintext.extend_from_slice(contents);
+
+        let mut packet = length.to_vec();
+        packet.extend(self.send_packet.encrypt(aad, &plaintext));
+        packet
+    }
+
+    fn decrypt_length(&mut self, mut length: [u8; LENGTH_FIELD_LEN]) -> usize {
+        self.recv_length.crypt(&mut length);
+        u32::from_le_bytes([length[0], length[1], length[2], 0]) as usize
+    }
+
+    /// Decrypt a packet's header and contents; the flag is the ignore bit
+    fn decrypt_packet(&mut self, ciphertext: &[u8], aad: &[u8]) -> Result<(bool, Vec<u8>)> {
+        let mut plaintext = self.recv_packet.decrypt(aad, ciphertext)?;
+        if plaintext.is_empty() {
+            return Err(anyhow::anyhow!("Packet without header"));
+        }
+        let contents = plaintext.split_off(1);
+        Ok((plaintext[0] & IGNORE_BIT != 0, contents))
+    }
+}
+
+/// v2 packet contents for a v1 wire message: a short message ID, or a zero
+/// byte and the 12-byte command, then the payload
+fn encode_contents(message: &[u8]) -> Result<Vec<u8>> {
+    if message.len() < V1_HEADER_LEN {
+        return Err(anyhow::anyhow!("Message too short"));
+    }
+    let command = &message[4..16];
+    let payload = &message[V1_HEADER_LEN..];
+    let name = std::str::from_utf8(command)
+        .unwrap_or_default()
+        .trim_end_matches('\0');
+
+    let mut contents = Vec::with_capacity(1 + command.len() + payload.len());
+    match SHORT_MESSAGE_IDS.iter().position(|&id| id == name) {
+        Some(index) => contents.push(index as u8 + 1),
+        None => {
+            contents.push(0);
+            contents.extend_from_slice(command);
+        }
+    }
+    contents.extend_from_slice(payload);
+    Ok(contents)
+}
+
+/// v1 wire message for v2 packet contents
+fn decode_contents(contents: &[u8], magic: [u8; 4]) -> Result<Vec<u8>> {
+    let (&id, rest) = contents
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("Empty message"))?;
+    let mut command = [0u8; 12];
+    let payload = if id == 0 {
+        if rest.len() < command.len() {
+            return Err(anyhow::anyhow!("Message too short"));
+        }
+        let (name, payload) = rest.split_at(command.len());
+        command.copy_from_slice(name);
+        payload
+    } else {
+        let name = SHORT_MESSAGE_IDS
+            .get(id as usize - 1)
+            .ok_or_else(|| anyhow::anyhow!("Unknown short message ID {}", id))?;
+        command[..name.len()].copy_from_slice(name.as_bytes());
+        rest
+    };
+
+    let mut message = Vec::with_capacity(V1_HEADER_LEN + payload.len());
+    message.extend_from_slice(&magic);
+    message.extend_from_slice(&command);
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(&ProtocolParser::calculate_checksum(payload));
+    message.extend_from_slice(payload);
+    Ok(message)
+}
+
+/// Read one packet, returning its ignore bit and contents
+async fn read_packet(
+    stream: &mut BufReader<TcpStream>,
+    cipher: &mut V2Cipher,
+    aad: &[u8],
+) -> Result<(bool, Vec<u8>)> {
+    let mut length = [0u8; LENGTH_FIELD_LEN];
+    stream.read_exact(&mut length).await?;
+    let length = cipher.decrypt_length(length);
+    // Checked before allocating for the packet
+    if length > MAX_PROTOCOL_MESSAGE_LENGTH {
+        return Err(anyhow::anyhow!("Packet too large: {} bytes", length));
+    }
+    let mut ciphertext = vec![0u8; 1 + length + TAG_LEN];
+    stream.read_exact(&mut ciphertext).await?;
+    cipher.decrypt_packet(&ciphertext, aad)
+}
+
+/// Read the peer's garbage up to and including its terminator; returns the garbage
+async fn read_garbage(
+    stream: &mut BufReader<TcpStream>,
+    terminator: &[u8; GARBAGE_TERMINATOR_LEN],
+) -> Result<Vec<u8>> {
+    let mut received = vec![0u8; GARBAGE_TERMINATOR_LEN];
+    stream.read_exact(&mut received).await?;
+    while received[received.len() - GARBAGE_TERMINATOR_LEN..] != terminator[..] {
+        if received.len() == MAX_GARBAGE_LEN + GARBAGE_TERMINATOR_LEN {
+            return Err(anyhow::anyhow!("Garbage terminator not found"));
+        }
+        received.push(stream.read_u8().await?);
+    }
+    received.truncate(received.len() - GARBAGE_TERMINATOR_LEN);
+    Ok(received)
+}
+
+fn is_eof(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::UnexpectedEof)
+}
+
+/// Our side of a key exchange in progress
+struct Handshake {
+    secret: SecretKey,
+    public: ElligatorSwift,
+    garbage: Vec<u8>,
+}
+
+impl Handshake {
+    /// Fresh ephemeral key and a random amount of garbage
+    fn new() -> Self {
+        let mut rng = rand::thread_rng();
+        let secret = loop {
+            let mut bytes = [0u8; 32];
+            rng.fill_bytes(&mut bytes);
+            if let Ok(secret) = SecretKey::from_slice(&bytes) {
+                break secret;
+            }
+        };
+        let mut aux_rand = [0u8; 32];
+        rng.fill_bytes(&mut aux_rand);
+        let public = ElligatorSwift::from_seckey(&Secp256k1::new(), secret, Some(aux_rand));
+
+        let mut garbage = vec![0u8; rng.gen_range(0..=MAX_GARBAGE_LEN)];
+        rng.fill_bytes(&mut garbage);
+        Self {
+            secret,
+            public,
+            garbage,
+        }
+    }
+
+    /// Our public key followed by our garbage, sent first
+    fn key_and_garbage(&self) -> Vec<u8> {
+        let mut bytes = self.public.to_array().to_vec();
+        bytes.extend_from_slice(&self.garbage);
+        bytes
+    }
+
+    /// Finish the handshake once the peer's key is known: send our garbage
+    /// terminator and version packet, then read the peer's
+    async fn complete(
+        self,
+        mut stream: BufReader<TcpStream>,
+        their_key: [u8; ELLSWIFT_KEY_LEN],
+        magic: [u8; 4],
+        initiator: bool,
+    ) -> Result<(BufReader<TcpStream>, V2Cipher)> {
+        let theirs = ElligatorSwift::from_array(their_key);
+        let shared_secret = if initiator {
+            ElligatorSwift::shared_secret(
+                self.public,
+                theirs,
+                self.secret,
+                ElligatorSwiftParty::A,
+                None,
+            )
+        } else {
+            ElligatorSwift::shared_secret(
+                theirs,
+                self.public,
+                self.secret,
+                ElligatorSwiftParty::B,
+                None,
+            )
+        };
+        let mut cipher = V2Cipher::new(&shared_secret.to_secret_bytes(), magic, initiator);
+
+        // The first packet authenticates the garbage sent before it
+        let mut bytes = cipher.send_garbage_terminator.to_vec();
+        bytes.extend(cipher.encrypt_packet(&[], &self.garbage, false));
+        stream.write_all(&bytes).await?;
+
+        let mut aad = read_garbage(&mut stream, &cipher.recv_garbage_terminator).await?;
+        // Decoys may precede the version packet, whose contents are reserved
+        // for future extensions
+        loop {
+            let (ignore, _contents) = read_packet(&mut stream, &mut cipher, &aad).await?;
+            aad.clear();
+            if !ignore {
+                break;
+            }
+        }
+        Ok((stream, cipher))
+    }
+}
+
+/// BIP324 v2 transport over TCP
+///
+/// Offers v2 to every peer and falls back to v1 framing for peers that don't
+/// support it. With v2 disabled it behaves as the plain TCP transport.
+#[derive(Debug, Clone)]
+pub struct V2Transport {
+    magic: [u8; 4],
+    v2_enabled: bool,
+    proxies: ProxySettings,
+}
+
+impl V2Transport {
+    /// Create a v2 transport for the network with the given magic
+    pub fn new(magic: [u8; 4]) -> Self {
+        Self {
+            magic,
+            v2_enabled: true,
+            proxies: ProxySettings::new(),
+        }
+    }
+
+    /// Enable or disable offering v2 to peers
+    pub fn with_v2(mut self, enabled: bool) -> Self {
+        self.v2_enabled = enabled;
+        self
+    }
+
+    /// Whether v2 is offered to peers
+    pub fn v2_enabled(&self) -> bool {
+        self.v2_enabled
+    }
+
+    /// Route outbound connections through per-network proxies
+    pub fn with_proxies(mut self, proxies: ProxySettings) -> Self {
+        self.proxies = proxies;
+        self
+    }
+
+    /// Proxies used for outbound connections
+    pub fn proxies(&self) -> &ProxySettings {
+        &self.proxies
+    }
+
+    /// Open a stream to `target`, through its network's proxy if it has one
+    ///
+    /// Also returns the address identifying the peer. Targets without a
+    /// socket address (onion and I2P) are identified by the local end of the
+    /// proxy connection, as inbound connections through Tor are identified by
+    /// the Tor daemon's end.
+    async fn open(&self, target: &ServiceAddr) -> Result<(TcpStream, SocketAddr)> {
+        let network = target.addr.network();
+        if let Some(proxy) = self.proxies.proxy_for(network) {
+            let stream = proxy.connect(target).await?;
+            let peer_addr = match target.to_socket() {
+                Some(socket_addr) => socket_addr,
+                None => stream.local_addr()?,
+            };
+            return Ok((stream, peer_addr));
+        }
+        let Some(socket_addr) = target.to_socket() else {
+            return Err(anyhow::anyhow!(
+                "No proxy configured to reach {} address {}",
+                network,
+                target
+            ));
+        };
+        Ok((TcpStream::connect(socket_addr).await?, socket_addr))
+    }
+
+    /// Connect with v1 framing
+    async fn connect_v1(&self, target: &ServiceAddr) -> Result<V2Connection> {
+        let (stream, peer_addr) = self.open(target).await?;
+        Ok(V2Connection::v1(stream, peer_addr, None))
+    }
+
+    /// Connect to `target`, offering v2 if enabled
+    ///
+    /// Unlike [`Transport::connect`] this also takes onion and I2P targets,
+    /// which are reached through their network's proxy.
+    pub async fn connect_service(&self, target: &ServiceAddr) -> Result<V2Connection> {
+        if !self.v2_enabled {
+            return self.connect_v1(target).await;
+        }
+
+        let handshake = Handshake::new();
+        let (stream, peer_addr) = self.open(target).await?;
+        let mut stream = BufReader::new(stream);
+        let their_key = match tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            Self::exchange_keys(&mut stream, &handshake),
+        )
+        .await
+        {
+            Ok(Ok(their_key)) => their_key,
+            Ok(Err(e)) => {
+                debug!(
+                    "v2 key exchange with {} failed ({}), retrying as v1",
+                    target, e
+                );
+                return self.connect_v1(target).await;
+            }
+            Err(_) => {
+                debug!("v2 key exchange with {} timed out, retrying as v1", target);
+                return self.connect_v1(target).await;
+            }
+        };
+
+        let (stream, cipher) = tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            handshake.complete(stream, their_key, self.magic, true),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("v2 handshake with {} timed out", target))??;
+        Ok(V2Connection::v2(stream, peer_addr, cipher, self.magic))
+    }
+
+    /// Send our key and read the peer's; a v1 peer drops the connection instead
+    async fn exchange_keys(
+        stream: &mut BufReader<TcpStream>,
+        handshake: &Handshake,
+    ) -> Result<[u8; ELLSWIFT_KEY_LEN]> {
+        stream.write_all(&handshake.key_and_garbage()).await?;
+        let mut their_key = [0u8; ELLSWIFT_KEY_LEN];
+        stream.read_exact(&mut their_key).await?;
+        Ok(their_key)
+    }
+
+    /// Answer an inbound connection, as v2 unless it opens with a v1 version message
+    async fn respond(&self, mut stream: TcpStream) -> Result<V2Connection> {
+        let peer_addr = stream.peer_addr()?;
+        if !self.v2_enabled {
+            return Ok(V2Connection::v1(stream, peer_addr, None));
+        }
+
+        let mut prefix = [0u8; V1_PREFIX_LEN];
+        stream.read_exact(&mut prefix).await?;
+        let mut v1_prefix = self.magic.to_vec();
+        v1_prefix.extend_from_slice(b"version\0\0\0\0\0");
+        if prefix[4..] == v1_prefix[..] {
+            // Read the rest of the version message for the first recv
+            let length = u32::from_be_bytes(prefix[..4].try_into().unwrap()) as usize;
+            if !(V1_HEADER_LEN..=MAX_PROTOCOL_MESSAGE_LENGTH).contains(&length) {
+                return Err(anyhow::anyhow!("Invalid message length: {}", length));
+            }
+            let mut message = v1_prefix;
+            message.resize(length, 0);
+            stream.read_exact(&mut message[V1_PREFIX_LEN - 4..]).await?;
+            debug!("Peer {} uses the v1 transport", peer_addr);
+            return Ok(V2Connection::v1(stream, peer_addr, Some(message)));
+        }
+
+        let mut their_key = [0u8; ELLSWIFT_KEY_LEN];
+        their_key[..V1_PREFIX_LEN].copy_from_slice(&prefix);
+        stream.read_exact(&mut their_key[V1_PREFIX_LEN..]).await?;
+
+        let handshake = Handshake::new();
+        let mut stream = BufReader::new(stream);
+        stream.write_all(&handshake.key_and_garbage()).await?;
+        let (stream, cipher) = handshake
+            .complete(stream, their_key, self.magic, false)
+            .await?;
+        Ok(V2Connection::v2(stream, peer_addr, cipher, self.magic))
+    }
+}
+
+impl Default for V2Transport {
+    fn default() -> Self {
+        Self::new(BITCOIN_MAGIC_MAINNET)
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for V2Transport {
+    type Connection = V2Connection;
+    type Listener = V2Listener;
+
+    fn transport_type(&self) -> TransportType {
+        TransportType::Tcp
+    }
+
+    async fn listen(&self, addr: SocketAddr) -> Result<Self::Listener> {
+        let listener = TokioTcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let (connection_tx, connection_rx) = mpsc::channel(ACCEPT_QUEUE_LEN);
+        let transport = self.clone();
+
+        let accept_task = tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept TCP connection: {}", e);
+                        continue;
+                    }
+                };
+                // Handshakes run on their own so a slow peer can't hold up others
+                let connection_tx = connection_tx.clone();
+                let transport = transport.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, transport.respond(stream)).await {
+                        Ok(Ok(conn)) => {
+                            let _ = connection_tx.send((conn, TransportAddr::Tcp(addr))).await;
+                        }
+                        Ok(Err(e)) => debug!("Handshake with {} failed: {}", addr, e),
+                        Err(_) => debug!("Handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+
+        Ok(V2Listener {
+            local_addr,
+            connections: connection_rx,
+            accept_task,
+        })
+    }
+
+    async fn connect(&self, addr: TransportAddr) -> Result<Self::Connection> {
+        #[allow(irrefutable_let_patterns)]
+        let TransportAddr::Tcp(socket_addr) = addr
+        else {
+            return Err(anyhow::anyhow!(
+                "TCP transport can only connect to TCP addresses"
+            ));
+        };
+        self.connect_service(&socket_addr.into()).await
+    }
+}
+
+/// v2 listener; handshakes complete in the background before `accept` returns them
+pub struct V2Listener {
+    local_addr: SocketAddr,
+    connections: mpsc::Receiver<(V2Connection, TransportAddr)>,
+    accept_task: JoinHandle<()>,
+}
+
+#[async_trait::async_trait]
+impl TransportListener for V2Listener {
+    type Connection = V2Connection;
+
+    async fn accept(&mut self) -> Result<(Self::Connection, TransportAddr)> {
+        let (conn, addr) = self
+            .connections
+            .recv()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Listener closed"))?;
+        debug!(
+            "Accepted {} connection from {}",
+            conn.transport_protocol().as_str(),
+            addr
+        );
+        Ok((conn, addr))
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+impl Drop for V2Listener {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+/// Connection of a [`V2Transport`], encrypted or downgraded to v1
+pub struct V2Connection {
+    peer_addr: TransportAddr,
+    session: Session,
+}
+
+enum Session {
+    /// v1 framing; `pending` is a message read while detecting the protocol
+    V1 {
+        conn: TcpConnection,
+        pending: Option<Vec<u8>>,
+    },
+    V2 {
+        stream: BufReader<TcpStream>,
+        cipher: Box<V2Cipher>,
+        magic: [u8; 4],
+        connected: bool,
+    },
+}
+
+impl V2Connection {
+    fn v1(stream: TcpStream, peer_addr: SocketAddr, pending: Option<Vec<u8>>) -> Self {
+        let peer_addr = TransportAddr::Tcp(peer_addr);
+        Self {
+            peer_addr: peer_addr.clone(),
+            session: Session::V1 {
+                conn: TcpConnection {
+                    stream,
+                    peer_addr,
+                    connected: true,
+                },
+                pending,
+            },
+        }
+    }
+
+    fn v2(
+        stream: BufReader<TcpStream>,
+        peer_addr: SocketAddr,
+        cipher: V2Cipher,
+        magic: [u8; 4],
+    ) -> Self {
+        let peer_addr = TransportAddr::Tcp(peer_addr);
+        debug!(
+            "v2 session {} established with {}",
+            hex::encode(cipher.session_id),
+            peer_addr
+        );
+        Self {
+            peer_addr,
+            session: Session::V2 {
+                stream,
+                cipher: Box::new(cipher),
+                magic,
+                connected: true,
+            },
+        }
+    }
+
+    /// Send a decoy packet of random contents, which the peer ignores
+    ///
+    /// Does nothing on a v1 connection.
+    pub async fn send_decoy(&mut self, length: usize) -> Result<()> {
+        if let Session::V2 { stream, cipher, .. } = &mut self.session {
+            let length = length.min(MAX_PACKET_CONTENTS_LEN);
+            let mut contents = vec![0u8; length];
+            rand::thread_rng().fill_bytes(&mut contents);
+            let packet = cipher.encrypt_packet(&contents, &[], true);
+            stream.write_all(&packet).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl TransportConnection for V2Connection {
+    async fn send(&mut self, data: &[u8]) -> Result<()> {
+        match &mut self.session {
+            Session::V1 { conn, .. } => conn.send(data).await,
+            Session::V2 {
+                stream,
+                cipher,
+                connected,
+                ..
+            } => {
+                if !*connected {
+                    return Err(anyhow::anyhow!("Connection closed"));
+                }
+                let contents = encode_contents(data)?;
+                if contents.len() > MAX_PACKET_CONTENTS_LEN {
+                    return Err(anyhow::anyhow!(
+                        "Message too large for a v2 packet: {} bytes",
+                        contents.len()
+                    ));
+                }
+                let packet = cipher.encrypt_packet(&contents, &[], false);
+                stream.write_all(&packet).await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn recv(&mut self) -> Result<Vec<u8>> {
+        match &mut self.session {
+            Session::V1 { conn, pending } => match pending.take() {
+                Some(message) => Ok(message),
+                None => conn.recv().await,
+            },
+            Session::V2 {
+                stream,
+                cipher,
+                magic,
+                connected,
+            } => {
+                if !*connected {
+                    return Ok(Vec::new()); // Graceful close
+                }
+                loop {
+                    let (ignore, contents) = match read_packet(stream, cipher, &[]).await {
+                        Ok(packet) => packet,
+                        Err(e) if is_eof(&e) => {
+                            *connected = false;
+                            return Ok(Vec::new()); // Graceful close
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    // Decoys are dropped
+                    if !ignore {
+                        return decode_contents(&contents, *magic);
+                    }
+                }
+            }
+        }
+    }
+
+    fn peer_addr(&self) -> TransportAddr {
+        self.peer_addr.clone()
+    }
+
+    fn is_connected(&self) -> bool {
+        match &self.session {
+            Session::V1 { conn, .. } => conn.is_connected(),
+            Session::V2 { connected, .. } => *connected,
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        match &mut self.session {
+            Session::V1 { conn, .. } => conn.close().await,
+            Session::V2 {
+                stream, connected, ..
+            } => {
+                if *connected {
+                    stream.shutdown().await?;
+                    *connected = false;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn transport_protocol(&self) -> TransportProtocol {
+        match self.session {
+            Session::V1 { .. } => TransportProtocol::V1,
+            Session::V2 { .. } => TransportProtocol::V2,
+        }
+    }
+
+    fn session_id(&self) -> Option<[u8; 32]> {
+        match &self.session {
+            Session::V1 { .. } => None,
+            Session::V2 { cipher, .. } => Some(cipher.session_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher_pair() -> (V2Cipher, V2Cipher) {
+        let secret = [7u8; 32];
+        (
+            V2Cipher::new(&secret, BITCOIN_MAGIC_MAINNET, true),
+            V2Cipher::new(&secret, BITCOIN_MAGIC_MAINNET, false),
+        )
+    }
+
+    fn decrypt(cipher: &mut V2Cipher, packet: &[u8], aad: &[u8]) -> Result<(bool, Vec<u8>)> {
+        let length = cipher.decrypt_length(packet[..LENGTH_FIELD_LEN].try_into().unwrap());
+        assert_eq!(packet.len(), LENGTH_FIELD_LEN + 1 + length + TAG_LEN);
+        cipher.decrypt_packet(&packet[LENGTH_FIELD_LEN..], aad)
+    }
+
+    fn hex32(s: &str) -> [u8; 32] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    /// Ciphertext of packet `index` sent by one side of a BIP324 test vector
+    /// session, after `index` empty packets
+    fn vector_packet(
+        shared_secret: &str,
+        initiating: bool,
+        index: usize,
+        contents: &[u8],
+        aad: &[u8],
+        ignore: bool,
+    ) -> Vec<u8> {
+        let mut cipher = V2Cipher::new(&hex32(shared_secret), BITCOIN_MAGIC_MAINNET, initiating);
+        for _ in 0..index {
+            cipher.encrypt_packet(&[], &[], false);
+        }
+        cipher.encrypt_packet(contents, aad, ignore)
+    }
+
+    #[test]
+    fn test_bip324_packet_vectors() {
+        // packet_encoding_test_vectors.csv from BIP324, from the shared secret on
+        let shared_secret = "c6992a117f5edbea70c3f511d32d26b9798be4b81a62eaee1a5acaa8459a3592";
+        let cipher = V2Cipher::new(&hex32(shared_secret), BITCOIN_MAGIC_MAINNET, true);
+        assert_eq!(
+            hex::encode(cipher.send_garbage_terminator),
+            "faef555dfcdb936425d84aba524758f3"
+        );
+        assert_eq!(
+            hex::encode(cipher.recv_garbage_terminator),
+            "02cb8ff24307a6e27de3b4e7ea3fa65b"
+        );
+        assert_eq!(
+            hex::encode(cipher.session_id),
+            "ce72dffb015da62b0d0f5474cab8bc72605225b0cee3f62312ec680ec5f41ba5"
+        );
+        assert_eq!(
+            hex::encode(vector_packet(shared_secret, true, 1, &[0x8e], &[], false)),
+            "7530d2a18720162ac09c25329a60d75adf36eda3c3"
+        );
+    }
+
+    #[test]
+    fn test_packet_rekey_follows_bip324() {
+        // The new key is the first 32 bytes of the AEAD encryption of 32 zero
+        // bytes under nonce 0xffffffff || rekey counter
+        let key = [0x42u8; 32];
+        let mut cipher = FsChaCha20Poly1305::new(key);
+        for _ in 0..REKEY_INTERVAL {
+            cipher.encrypt(&[], b"packet");
+        }
+        let mut nonce = [0xffu8; 12];
+        nonce[4..].copy_from_slice(&0u64.to_le_bytes());
+        let expected = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .encrypt(Nonce::from_slice(&nonce), &[0u8; 32][..])
+            .unwrap();
+        assert_eq!(cipher.key[..], expected[..32]);
+        assert_eq!(cipher.rekey_counter, 1);
+
+        // Packets after the rekey use the new key and rekey counter
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&1u64.to_le_bytes());
+        let expected = ChaCha20Poly1305::new(Key::from_slice(&expected[..32]))
+            .encrypt(Nonce::from_slice(&nonce), &b"packet"[..])
+            .unwrap();
+        assert_eq!(cipher.encrypt(&[], b"packet"), expected);
+    }
+
+    #[test]
+    fn test_session_derivation_is_symmetric() {
+        let (initiator, responder) = cipher_pair();
+        assert_eq!(initiator.session_id, responder.session_id);
+        assert_eq!(
+            initiator.send_garbage_terminator,
+            responder.recv_garbage_terminator
+        );
+        assert_eq!(
+            initiator.recv_garbage_terminator,
+            responder.send_garbage_terminator
+        );
+        assert_ne!(
+            initiator.send_garbage_terminator,
+            initiator.recv_garbage_terminator
+        );
+
+        // Other networks derive other sessions
+        let other = V2Cipher::new(&[7u8; 32], [0xfa, 0xbf, 0xb5, 0xda], true);
+        assert_ne!(other.session_id, initiator.session_id);
+    }
+
+    #[test]
+    fn test_packets_round_trip_across_rekeys() {
+        let (mut initiator, mut responder) = cipher_pair();
+        // Several rekey intervals, in both directions
+        for i in 0..(3 * REKEY_INTERVAL as usize + 5) {
+            let contents = vec![i as u8; i % 300];
+            let aad = if i == 0 {
+                b"garbage".to_vec()
+            } else {
+                Vec::new()
+            };
+            let packet = initiator.encrypt_packet(&contents, &aad, i % 7 == 0);
+            assert_eq!(
+                decrypt(&mut responder, &packet, &aad).unwrap(),
+                (i % 7 == 0, contents.clone())
+            );
+
+            let packet = responder.encrypt_packet(&contents, &[], false);
+            assert_eq!(
+                decrypt(&mut initiator, &packet, &[]).unwrap(),
+                (false, contents)
+            );
+        }
+    }
+
+    #[test]
+    fn test_tampered_packets_are_rejected() {
+        let (mut initiator, mut responder) = cipher_pair();
+        let mut packet = initiator.encrypt_packet(b"contents", &[], false);
+        let last = packet.len() - 1;
+        packet[last] ^= 1;
+        assert!(decrypt(&mut responder, &packet, &[]).is_err());
+
+        // Wrong associated data
+        let (mut initiator, mut responder) = cipher_pair();
+        let packet = initiator.encrypt_packet(b"contents", b"garbage", false);
+        assert!(decrypt(&mut responder, &packet, b"other").is_err());
+    }
+
+    #[test]
+    fn test_message_contents_conversion() {
+        let payload = [1u8, 2, 3];
+        let mut ping = BITCOIN_MAGIC_MAINNET.to_vec();
+        ping.extend_from_slice(b"ping\0\0\0\0\0\0\0\0");
+        ping.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        ping.extend_from_slice(&ProtocolParser::calculate_checksum(&payload));
+        ping.extend_from_slice(&payload);
+
+        // "ping" has short ID 18
+        let contents = encode_contents(&ping).unwrap();
+        assert_eq!(contents, [18, 1, 2, 3]);
+        assert_eq!(
+            decode_contents(&contents, BITCOIN_MAGIC_MAINNET).unwrap(),
+            ping
+        );
+
+        // Commands without a short ID are sent in full
+        let mut version = ping.clone();
+        version[4..16].copy_from_slice(b"version\0\0\0\0\0");
+        let contents = encode_contents(&version).unwrap();
+        assert_eq!(contents[0], 0);
+        assert_eq!(&contents[1..13], b"version\0\0\0\0\0");
+        assert_eq!(
+            decode_contents(&contents, BITCOIN_MAGIC_MAINNET).unwrap(),
+            version
+        );
+
+        assert!(decode_contents(&[29], BITCOIN_MAGIC_MAINNET).is_err());
+        assert!(decode_contents(&[0, 1, 2], BITCOIN_MAGIC_MAINNET).is_err());
+    }
+}
@@ -0,0 +1,464 @@
+//! Parallel block download for initial block download
+//!
+//! Blocks on the best header chain are requested from several peers at once,
+//! inside a moving window that starts at the next block to connect. Each peer
+//! has a limit on requests in flight, and blocks arriving out of order are
+//! buffered until the blocks before them have arrived, so they are handed out
+//! for connection in chain order. Once the buffered blocks reach a size limit,
+//! only the blocks needed to drain the buffer are requested.
+//!
+//! Requests that go unanswered for too long are given to other peers. When
+//! the window cannot move because one peer has not delivered the block at its
+//! start, that peer is stalling the download: its requests are reassigned and
+//! it is reported for disconnection.
+
+use crate::config::RequestTimeoutConfig;
+use bllvm_protocol::Hash;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Number of blocks past the next block to connect that may be downloaded
+pub const BLOCK_DOWNLOAD_WINDOW: u64 = 1024;
+
+/// Maximum number of blocks requested from a single peer at a time
+pub const MAX_BLOCKS_IN_FLIGHT_PER_PEER: usize = 16;
+
+/// Size of the blocks held back for connection past which only the blocks
+/// needed to drain them are requested
+pub const MAX_BUFFERED_BLOCK_BYTES: usize = 256 * 1024 * 1024;
+
+/// Block download limits and timeouts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDownloadConfig {
+    /// Number of blocks past the next block to connect that may be downloaded
+    pub window_size: u64,
+    /// Maximum number of blocks requested from a single peer at a time
+    pub max_blocks_in_flight_per_peer: usize,
+    /// Size of the received blocks held back for connection past which no
+    /// blocks above them are requested
+    pub max_buffered_bytes: usize,
+    /// How long a peer has to deliver a requested block
+    pub request_timeout: Duration,
+    /// How long a peer may hold up the download window
+    pub stall_timeout: Duration,
+}
+
+impl Default for BlockDownloadConfig {
+    fn default() -> Self {
+        Self::from_timeouts(&RequestTimeoutConfig::default())
+    }
+}
+
+impl BlockDownloadConfig {
+    /// Default limits with the block request and stall timeouts from `timeouts`
+    pub fn from_timeouts(timeouts: &RequestTimeoutConfig) -> Self {
+        Self {
+            window_size: BLOCK_DOWNLOAD_WINDOW,
+            max_blocks_in_flight_per_peer: MAX_BLOCKS_IN_FLIGHT_PER_PEER,
+            max_buffered_bytes: MAX_BUFFERED_BLOCK_BYTES,
+            request_timeout: Duration::from_secs(timeouts.async_request_timeout_seconds),
+            stall_timeout: Duration::from_secs(timeouts.block_stall_timeout_seconds),
+        }
+    }
+}
+
+/// Outstanding block request
+#[derive(Debug, Clone)]
+struct BlockRequest {
+    peer: SocketAddr,
+    height: u64,
+    requested_at: Instant,
+}
+
+/// Schedules block requests across peers and reorders the blocks received
+pub struct BlockDownloader {
+    config: BlockDownloadConfig,
+    /// Height of the next block to hand out for connection
+    next_height: u64,
+    /// Hashes of the blocks to download, from `next_height` to the best header
+    pending: VecDeque<Hash>,
+    /// Outstanding requests by block hash
+    in_flight: HashMap<Hash, BlockRequest>,
+    /// Number of outstanding requests per peer
+    peers: HashMap<SocketAddr, usize>,
+    /// Blocks received ahead of `next_height`, by height
+    buffered: BTreeMap<u64, Vec<u8>>,
+    /// Total size of the buffered blocks
+    buffered_bytes: usize,
+    /// Peers whose request for a block timed out, so it goes to another peer
+    timed_out: HashMap<Hash, SocketAddr>,
+    /// Peer holding up the window, and when that was first noticed
+    stalling: Option<(SocketAddr, Instant)>,
+}
+
+impl Default for BlockDownloader {
+    fn default() -> Self {
+        Self::new(BlockDownloadConfig::default())
+    }
+}
+
+impl BlockDownloader {
+    /// Create a downloader with nothing to download
+    pub fn new(config: BlockDownloadConfig) -> Self {
+        Self {
+            config,
+            next_height: 0,
+            pending: VecDeque::new(),
+            in_flight: HashMap::new(),
+            peers: HashMap::new(),
+            buffered: BTreeMap::new(),
+            buffered_bytes: 0,
+            timed_out: HashMap::new(),
+            stalling: None,
+        }
+    }
+
+    /// Limits and timeouts in use
+    pub fn config(&self) -> &BlockDownloadConfig {
+        &self.config
+    }
+
+    /// Height of the next block to hand out for connection
+    pub fn next_height(&self) -> u64 {
+        self.next_height
+    }
+
+    /// Height just past the last block to download
+    pub fn end_height(&self) -> u64 {
+        self.next_height + self.pending.len() as u64
+    }
+
+    /// Height just past the last block that may currently be requested
+    pub fn window_end(&self) -> u64 {
+        (self.next_height + self.config.window_size).min(self.end_height())
+    }
+
+    /// Hash of the block to download at `height`
+    pub fn hash_at(&self, height: u64) -> Option<&Hash> {
+        let index = height.checked_sub(self.next_height)?;
+        self.pending.get(usize::try_from(index).ok()?)
+    }
+
+    /// Whether there are no blocks left to download or connect
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Number of requests outstanding
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Number of requests outstanding to `peer`
+    pub fn peer_in_flight(&self, peer: SocketAddr) -> usize {
+        self.peers.get(&peer).copied().unwrap_or(0)
+    }
+
+    /// Number of blocks received but not yet handed out for connection
+    pub fn buffered_count(&self) -> usize {
+        self.buffered.len()
+    }
+
+    /// Total size of the blocks received but not yet handed out
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    /// Forget everything and start again at `next_height`
+    pub fn reset(&mut self, next_height: u64) {
+        self.next_height = next_height;
+        self.pending.clear();
+        self.in_flight.clear();
+        self.buffered.clear();
+        self.buffered_bytes = 0;
+        self.timed_out.clear();
+        self.stalling = None;
+        for count in self.peers.values_mut() {
+            *count = 0;
+        }
+    }
+
+    /// Follow the active chain to `next_height`
+    ///
+    /// Blocks below it were connected some other way and are dropped. If the
+    /// chain moved back, e.g. because a block was rejected, everything is
+    /// dropped and the download starts again from there.
+    pub fn advance_to(&mut self, next_height: u64) {
+        if next_height < self.next_height {
+            debug!(
+                "Block download restarting at height {} (was {})",
+                next_height, self.next_height
+            );
+            self.reset(next_height);
+            return;
+        }
+        if next_height > self.end_height() {
+            self.reset(next_height);
+            return;
+        }
+        while self.next_height < next_height {
+            if let Some(hash) = self.pending.pop_front() {
+                self.cancel(&hash);
+                self.timed_out.remove(&hash);
+            }
+            self.unbuffer(self.next_height);
+            self.next_height += 1;
+            self.stalling = None;
+        }
+    }
+
+    /// Replace the blocks to download from `start_height` onward with `hashes`
+    ///
+    /// Used when the best header chain grows or switches branches above
+    /// `start_height`. Requests for the replaced blocks are dropped.
+    pub fn replace_from(&mut self, start_height: u64, hashes: Vec<Hash>) {
+        if start_height < self.next_height || start_height > self.end_height() {
+            warn!(
+                "Ignoring blocks to download from height {} (downloading {} to {})",
+                start_height,
+                self.next_height,
+                self.end_height()
+            );
+            return;
+        }
+        let keep = (start_height - self.next_height) as usize;
+        for hash in self.pending.split_off(keep) {
+            self.cancel(&hash);
+            self.timed_out.remove(&hash);
+        }
+        for (_, data) in self.buffered.split_off(&start_height) {
+            self.buffered_bytes -= data.len();
+        }
+        self.pending.extend(hashes);
+    }
+
+    /// Assign blocks in the window to `peers`
+    ///
+    /// Peers that are no longer listed are forgotten and their requests
+    /// reassigned. Blocks go to the peer with the fewest requests outstanding,
+    /// lowest height first, other than a peer whose request for the block
+    /// timed out. While the buffered blocks are over the size limit, only
+    /// blocks below the highest buffered one are requested. Returns the hashes
+    /// to request from each peer.
+    pub fn schedule(&mut self, peers: &[SocketAddr], now: Instant) -> Vec<(SocketAddr, Vec<Hash>)> {
+        let gone: Vec<SocketAddr> = self
+            .peers
+            .keys()
+            .filter(|peer| !peers.contains(peer))
+            .copied()
+            .collect();
+        for peer in gone {
+            self.remove_peer(peer);
+        }
+        for peer in peers {
+            self.peers.entry(*peer).or_insert(0);
+        }
+
+        let mut requests: Vec<(SocketAddr, Vec<Hash>)> = Vec::new();
+        for height in self.next_height..self.request_end() {
+            if self.buffered.contains_key(&height) {
+                continue;
+            }
+            let Some(hash) = self.hash_at(height).copied() else {
+                break;
+            };
+            if self.in_flight.contains_key(&hash) {
+                continue;
+            }
+            // Ties go to the peer listed first
+            let timed_out = self
+                .timed_out
+                .get(&hash)
+                .copied()
+                .filter(|_| peers.len() > 1);
+            let Some(peer) = peers
+                .iter()
+                .copied()
+                .filter(|peer| {
+                    self.peer_in_flight(*peer) < self.config.max_blocks_in_flight_per_peer
+                        && timed_out != Some(*peer)
+                })
+                .min_by_key(|peer| self.peer_in_flight(*peer))
+            else {
+                continue;
+            };
+            self.in_flight.insert(
+                hash,
+                BlockRequest {
+                    peer,
+                    height,
+                    requested_at: now,
+                },
+            );
+            *self.peers.entry(peer).or_insert(0) += 1;
+            match requests.iter_mut().find(|(p, _)| *p == peer) {
+                Some((_, hashes)) => hashes.push(hash),
+                None => requests.push((peer, vec![hash])),
+            }
+        }
+        requests
+    }
+
+    /// Whether `hash` is one of the blocks being downloaded
+    pub fn is_downloading(&self, hash: &Hash) -> bool {
+        self.height_of(hash).is_some()
+    }
+
+    /// Take a received block
+    ///
+    /// Returns false if the block is not one being downloaded, in which case
+    /// it should be processed as an unsolicited block. Blocks being
+    /// downloaded are buffered until [`pop_ready`](Self::pop_ready) hands
+    /// them out in order.
+    pub fn block_received(&mut self, hash: &Hash, data: Vec<u8>) -> bool {
+        let Some(height) = self.height_of(hash) else {
+            return false;
+        };
+        self.cancel(hash);
+        self.timed_out.remove(hash);
+        self.buffered_bytes += data.len();
+        if let Some(old) = self.buffered.insert(height, data) {
+            self.buffered_bytes -= old.len();
+        }
+        true
+    }
+
+    /// Hand out the block at the next height, if it has been received
+    ///
+    /// The downloader then moves on to the following block; if this one turns
+    /// out not to connect, [`advance_to`](Self::advance_to) with the active
+    /// chain's next height starts the download again from there.
+    pub fn pop_ready(&mut self) -> Option<(u64, Vec<u8>)> {
+        let data = self.buffered.remove(&self.next_height)?;
+        self.buffered_bytes -= data.len();
+        let height = self.next_height;
+        self.pending.pop_front();
+        self.next_height += 1;
+        self.stalling = None;
+        Some((height, data))
+    }
+
+    /// Find the peer holding up the download
+    ///
+    /// Requests outstanding for longer than the request timeout are dropped,
+    /// so the blocks go to other peers. A peer stalls the download when every
+    /// block in the window has been requested or received, more blocks wait
+    /// beyond it, and the peer has still not delivered the block at the start
+    /// of the window within the stall timeout. Its requests are reassigned and
+    /// it is returned for disconnection.
+    pub fn check_timeouts(&mut self, now: Instant) -> Vec<SocketAddr> {
+        let expired: Vec<(Hash, SocketAddr, u64)> = self
+            .in_flight
+            .iter()
+            .filter(|(_, request)| {
+                now.saturating_duration_since(request.requested_at) >= self.config.request_timeout
+            })
+            .map(|(hash, request)| (*hash, request.peer, request.height))
+            .collect();
+        for (hash, peer, height) in expired {
+            debug!(
+                "Peer {} did not deliver block at height {} in time",
+                peer, height
+            );
+            self.cancel(&hash);
+            self.timed_out.insert(hash, peer);
+        }
+
+        let mut stallers: Vec<SocketAddr> = Vec::new();
+
+        let staller = self
+            .window_blocked()
+            .then(|| self.pending.front())
+            .flatten()
+            .and_then(|hash| self.in_flight.get(hash))
+            .map(|request| request.peer)
+            .filter(|_| self.peers.len() > 1);
+        match (staller, self.stalling) {
+            (Some(peer), Some((stalling, since))) if peer == stalling => {
+                if now.saturating_duration_since(since) >= self.config.stall_timeout {
+                    warn!(
+                        "Peer {} is stalling block download at height {}",
+                        peer, self.next_height
+                    );
+                    stallers.push(peer);
+                }
+            }
+            (Some(peer), _) => self.stalling = Some((peer, now)),
+            (None, _) => self.stalling = None,
+        }
+
+        for peer in &stallers {
+            self.remove_peer(*peer);
+        }
+        stallers
+    }
+
+    /// Forget `peer`, making its requests available to other peers
+    pub fn remove_peer(&mut self, peer: SocketAddr) {
+        self.in_flight.retain(|_, request| request.peer != peer);
+        self.peers.remove(&peer);
+        if matches!(self.stalling, Some((stalling, _)) if stalling == peer) {
+            self.stalling = None;
+        }
+    }
+
+    /// Whether the window is full: nothing left in it to request, with more
+    /// blocks waiting past its end
+    fn window_blocked(&self) -> bool {
+        let request_end = self.request_end();
+        request_end < self.end_height()
+            && (self.next_height..request_end).all(|height| {
+                self.buffered.contains_key(&height)
+                    || self
+                        .hash_at(height)
+                        .is_some_and(|hash| self.in_flight.contains_key(hash))
+            })
+    }
+
+    /// Height just past the last block [`schedule`](Self::schedule) may
+    /// request: the end of the window, or the highest buffered block while
+    /// the buffered blocks are over the size limit
+    fn request_end(&self) -> u64 {
+        let window_end = self.next_height + self.config.window_size;
+        if self.buffered_bytes < self.config.max_buffered_bytes {
+            return window_end.min(self.end_height());
+        }
+        self.buffered
+            .keys()
+            .next_back()
+            .map_or(self.next_height, |height| window_end.min(*height))
+    }
+
+    /// Remove the buffered block at `height`, if any
+    fn unbuffer(&mut self, height: u64) {
+        if let Some(data) = self.buffered.remove(&height) {
+            self.buffered_bytes -= data.len();
+        }
+    }
+
+    /// Height of a block being downloaded
+    ///
+    /// A block whose request timed out may still arrive, so the window is
+    /// searched as well as the requests.
+    fn height_of(&self, hash: &Hash) -> Option<u64> {
+        if let Some(request) = self.in_flight.get(hash) {
+            return Some(request.height);
+        }
+        (self.next_height..self.window_end()).find(|height| self.hash_at(*height) == Some(hash))
+    }
+
+    /// Drop the request for `hash`, if any
+    fn cancel(&mut self, hash: &Hash) {
+        if let Some(request) = self.in_flight.remove(hash) {
+            self.release(request.peer);
+        }
+    }
+
+    fn release(&mut self, peer: SocketAddr) {
+        if let Some(count) = self.peers.get_mut(&peer) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
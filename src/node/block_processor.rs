@@ -3,13 +3,15 @@
 //! Handles parsing blocks from wire format, storing witnesses, and validating
 //! blocks with proper witness data and median time-past.
 
-use crate::storage::blockstore::BlockStore;
+use crate::storage::blockstore::{BlockStore, BlockUndo};
+use crate::storage::database::WriteBatch;
+use crate::storage::utxocache::UtxoView;
 use crate::storage::Storage;
 use anyhow::Result;
 use bllvm_protocol::serialization::deserialize_block_with_witnesses;
 use bllvm_protocol::validation::ProtocolValidationContext;
 use bllvm_protocol::{
-    segwit::Witness, BitcoinProtocolEngine, Block, BlockHeader, UtxoSet, ValidationResult,
+    segwit::Witness, BitcoinProtocolEngine, Block, BlockHeader, Hash, UtxoSet, ValidationResult,
 };
 use std::sync::Arc;
 
@@ -19,6 +21,13 @@ pub fn parse_block_from_wire(data: &[u8]) -> Result<(Block, Vec<Witness>)> {
         .map_err(|e| anyhow::anyhow!("Failed to parse block from wire format: {}", e))
 }
 
+/// Hash the header of a block in wire format without parsing the rest
+///
+/// Returns `None` if the data is shorter than a block header.
+pub fn block_hash_from_wire(data: &[u8]) -> Option<Hash> {
+    data.get(..80).map(crate::storage::hashing::double_sha256)
+}
+
 /// Store a block with its witnesses and update recent headers
 pub fn store_block_with_context(
     blockstore: &BlockStore,
@@ -26,27 +35,17 @@ pub fn store_block_with_context(
     witnesses: &[Witness],
     height: u64,
 ) -> Result<()> {
-    // Store block
-    blockstore.store_block(block)?;
-
-    // Store witnesses if present
-    if !witnesses.is_empty() {
-        let block_hash = blockstore.get_block_hash(block);
-        blockstore.store_witness(&block_hash, witnesses)?;
-    }
-
-    // Store header for median time-past calculation
-    blockstore.store_recent_header(height, &block.header)?;
-
-    // Update height index
-    let block_hash = blockstore.get_block_hash(block);
-    blockstore.store_height(height, &block_hash)?;
-
-    Ok(())
+    // Block, witnesses, median time-past header and height index in one batch
+    let mut batch = WriteBatch::new();
+    blockstore.stage_connected_block(&mut batch, block, witnesses, height)?;
+    blockstore.apply_batch(&batch)
 }
 
-/// Store a block with its witnesses, update recent headers, and index transactions
-/// This is the preferred method when Storage is available for transaction indexing
+/// Store a block with its witnesses and update recent headers, then bring the
+/// background indexes up to the active tip
+///
+/// Indexes only cover connected blocks; one that cannot be synced fails the call
+/// but keeps its locator, so it resumes from the same block next time.
 pub fn store_block_with_context_and_index(
     blockstore: &BlockStore,
     storage: Option<&Arc<Storage>>,
@@ -54,21 +53,49 @@ pub fn store_block_with_context_and_index(
     witnesses: &[Witness],
     height: u64,
 ) -> Result<()> {
-    // Store block
     store_block_with_context(blockstore, block, witnesses, height)?;
 
-    // Index transactions if storage is available
     if let Some(storage) = storage {
-        let block_hash = blockstore.get_block_hash(block);
-        if let Err(e) = storage.index_block(block, &block_hash, height) {
-            // Log error but don't fail block storage if indexing fails
-            tracing::warn!("Failed to index block transactions: {}", e);
-        }
+        storage.sync_indexes()?;
     }
 
     Ok(())
 }
 
+/// Atomically commit a validated block as the new tip, together with its undo data
+///
+/// The block is applied to `utxos` first, so that with `Storage` (whose UTXO
+/// cache should be the view) a due cache flush is part of the same batch as the
+/// transaction index and chain tip (see [`Storage::connect_block`]). If the
+/// commit fails the block is reverted from `utxos` again.
+pub fn commit_connected_block(
+    blockstore: &BlockStore,
+    storage: Option<&Arc<Storage>>,
+    block: &Block,
+    witnesses: &[Witness],
+    height: u64,
+    undo: &BlockUndo,
+    utxos: &mut dyn UtxoView,
+) -> Result<()> {
+    let block_hash = blockstore.get_block_hash(block);
+    utxos.apply_block(block, &block_hash, height)?;
+
+    let committed = if let Some(storage) = storage {
+        storage.connect_block(block, witnesses, height, undo)
+    } else {
+        let mut batch = WriteBatch::new();
+        blockstore
+            .stage_connected_block(&mut batch, block, witnesses, height)
+            .and_then(|_| blockstore.stage_undo(&mut batch, &block_hash, undo))
+            .and_then(|_| blockstore.apply_batch(&batch))
+    };
+    if let Err(e) = committed {
+        utxos.revert_block(block, height, undo)?;
+        return Err(e);
+    }
+    Ok(())
+}
+
 /// Retrieve witnesses and headers for block validation
 pub fn prepare_block_validation_context(
     blockstore: &BlockStore,
@@ -108,6 +135,28 @@ pub fn validate_block_with_context(
         .ok()
         .filter(|headers| !headers.is_empty());
 
+    validate_block_with_headers(
+        protocol,
+        block,
+        witnesses,
+        utxo_set,
+        height,
+        recent_headers.as_deref(),
+    )
+}
+
+/// Validate a block against explicitly supplied median time-past headers
+///
+/// For blocks that do not extend the active tip, whose headers window
+/// [`validate_block_with_context`] would take from the wrong chain.
+pub fn validate_block_with_headers(
+    protocol: &BitcoinProtocolEngine,
+    block: &Block,
+    witnesses: &[Witness],
+    utxo_set: &mut UtxoSet,
+    height: u64,
+    recent_headers: Option<&[BlockHeader]>,
+) -> Result<ValidationResult> {
     // Create protocol validation context
     let context = ProtocolValidationContext::new(protocol.get_protocol_version(), height)?;
 
@@ -117,7 +166,7 @@ pub fn validate_block_with_context(
         witnesses,
         utxo_set,
         height,
-        recent_headers.as_deref(),
+        recent_headers,
         &context,
     )?;
 
@@ -0,0 +1,596 @@
+//! Cluster mempool
+//!
+//! Mempool transactions connected by spends (in either direction) form a
+//! cluster. Each cluster is linearized, a topological order chosen for fee
+//! rate, and the linearization is cut into chunks: consecutive runs of
+//! transactions whose combined fee rates never increase from one chunk to the
+//! next. A chunk is mined or evicted as a unit, so block assembly takes the
+//! best chunks across all clusters, eviction drops the worst, and a
+//! replacement is judged by whether it improves the fee-rate diagram of the
+//! clusters it touches.
+
+use bllvm_protocol::Hash;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+/// A fee and a size, compared by fee rate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeFrac {
+    /// Fee in satoshis
+    pub fee: u64,
+    /// Size in vbytes
+    pub size: u64,
+}
+
+impl FeeFrac {
+    pub fn new(fee: u64, size: u64) -> Self {
+        Self { fee, size }
+    }
+
+    /// Compare fee rates; an empty fraction ranks below any other
+    pub fn cmp_rate(&self, other: &Self) -> Ordering {
+        match (self.size, other.size) {
+            (0, 0) => Ordering::Equal,
+            (0, _) => Ordering::Less,
+            (_, 0) => Ordering::Greater,
+            _ => (self.fee as u128 * other.size as u128)
+                .cmp(&(other.fee as u128 * self.size as u128)),
+        }
+    }
+
+    /// Fee rate in satoshis per 1000 vbytes
+    pub fn rate_per_kvb(&self) -> u64 {
+        if self.size == 0 {
+            0
+        } else {
+            (self.fee as u128 * 1000 / self.size as u128) as u64
+        }
+    }
+}
+
+impl std::ops::Add for FeeFrac {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.fee + other.fee, self.size + other.size)
+    }
+}
+
+impl std::ops::AddAssign for FeeFrac {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+/// Transactions mined or evicted together, in topological order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub txids: Vec<Hash>,
+    pub feefrac: FeeFrac,
+}
+
+/// Limits on the clusters a transaction may create or join
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClusterLimits {
+    /// Maximum number of transactions in a cluster
+    pub max_count: usize,
+    /// Maximum total size of a cluster in vbytes
+    pub max_size: u64,
+}
+
+/// A transaction as seen by linearization
+#[derive(Debug, Clone)]
+pub struct ClusterTx {
+    pub feefrac: FeeFrac,
+    /// In-mempool transactions this one spends outputs of
+    pub parents: HashSet<Hash>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    tx: ClusterTx,
+    children: HashSet<Hash>,
+    cluster: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Cluster {
+    members: HashSet<Hash>,
+    chunks: Vec<Chunk>,
+}
+
+/// The mempool's dependency graph, grouped into linearized clusters
+#[derive(Debug, Clone, Default)]
+pub struct ClusterMempool {
+    entries: HashMap<Hash, Entry>,
+    clusters: HashMap<u64, Cluster>,
+    next_cluster: u64,
+}
+
+impl ClusterMempool {
+    /// Create an empty cluster mempool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of transactions
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no transactions
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of clusters
+    pub fn cluster_count(&self) -> usize {
+        self.clusters.len()
+    }
+
+    /// Whether a transaction is tracked
+    pub fn contains(&self, txid: &Hash) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    /// Fee and size of a transaction
+    pub fn feefrac(&self, txid: &Hash) -> Option<FeeFrac> {
+        self.entries.get(txid).map(|entry| entry.tx.feefrac)
+    }
+
+    /// In-mempool parents of a transaction
+    pub fn parents(&self, txid: &Hash) -> Option<&HashSet<Hash>> {
+        self.entries.get(txid).map(|entry| &entry.tx.parents)
+    }
+
+    /// In-mempool children of a transaction
+    pub fn children(&self, txid: &Hash) -> Option<&HashSet<Hash>> {
+        self.entries.get(txid).map(|entry| &entry.children)
+    }
+
+    /// A transaction and all of its in-mempool descendants
+    pub fn with_descendants(&self, txid: &Hash) -> HashSet<Hash> {
+        let mut found = HashSet::new();
+        let mut stack = vec![*txid];
+        while let Some(next) = stack.pop() {
+            if let Some(entry) = self.entries.get(&next) {
+                if found.insert(next) {
+                    stack.extend(entry.children.iter().copied());
+                }
+            }
+        }
+        found
+    }
+
+    /// Chunks of the cluster containing a transaction, best first
+    pub fn cluster_chunks(&self, txid: &Hash) -> Option<&[Chunk]> {
+        let entry = self.entries.get(txid)?;
+        Some(&self.clusters[&entry.cluster].chunks)
+    }
+
+    /// Number of transactions and total size of the cluster a new
+    /// transaction with `parents` and `size` would belong to
+    pub fn merged_cluster_size(&self, parents: &HashSet<Hash>, size: u64) -> (usize, u64) {
+        let clusters: HashSet<u64> = parents
+            .iter()
+            .filter_map(|parent| self.entries.get(parent).map(|entry| entry.cluster))
+            .collect();
+        clusters
+            .iter()
+            .flat_map(|id| self.clusters[id].members.iter())
+            .fold((1, size), |(count, total), txid| {
+                (count + 1, total + self.entries[txid].tx.feefrac.size)
+            })
+    }
+
+    /// Whether adding a transaction would exceed the cluster limits
+    pub fn exceeds_limits(
+        &self,
+        parents: &HashSet<Hash>,
+        size: u64,
+        limits: ClusterLimits,
+    ) -> bool {
+        let (count, total) = self.merged_cluster_size(parents, size);
+        count > limits.max_count || total > limits.max_size
+    }
+
+    /// Add a transaction, merging the clusters of its parents
+    ///
+    /// Parents that are not tracked are ignored.
+    pub fn insert(&mut self, txid: Hash, feefrac: FeeFrac, parents: HashSet<Hash>) {
+        if self.entries.contains_key(&txid) {
+            return;
+        }
+        let parents: HashSet<Hash> = parents
+            .into_iter()
+            .filter(|parent| self.entries.contains_key(parent))
+            .collect();
+
+        let mut members = HashSet::from([txid]);
+        for parent in &parents {
+            let cluster = self.entries[parent].cluster;
+            if let Some(cluster) = self.clusters.remove(&cluster) {
+                members.extend(cluster.members);
+            }
+        }
+        for parent in &parents {
+            if let Some(entry) = self.entries.get_mut(parent) {
+                entry.children.insert(txid);
+            }
+        }
+        self.entries.insert(
+            txid,
+            Entry {
+                tx: ClusterTx { feefrac, parents },
+                children: HashSet::new(),
+                cluster: 0,
+            },
+        );
+        self.add_cluster(members);
+    }
+
+    /// Remove a transaction, splitting its cluster if it connected others
+    ///
+    /// Descendants stay in the mempool; the caller removes them if they can
+    /// no longer be mined.
+    pub fn remove(&mut self, txid: &Hash) -> bool {
+        let Some(entry) = self.entries.remove(txid) else {
+            return false;
+        };
+        for parent in &entry.tx.parents {
+            if let Some(parent) = self.entries.get_mut(parent) {
+                parent.children.remove(txid);
+            }
+        }
+        for child in &entry.children {
+            if let Some(child) = self.entries.get_mut(child) {
+                child.tx.parents.remove(txid);
+            }
+        }
+        if let Some(mut cluster) = self.clusters.remove(&entry.cluster) {
+            cluster.members.remove(txid);
+            for component in components(&cluster.members, |txid| self.neighbours(txid)) {
+                self.add_cluster(component);
+            }
+        }
+        true
+    }
+
+    /// Update transaction fees, relinearizing the clusters that changed
+    pub fn set_fees(&mut self, fees: &HashMap<Hash, u64>) {
+        let mut changed = HashSet::new();
+        for (txid, fee) in fees {
+            if let Some(entry) = self.entries.get_mut(txid) {
+                if entry.tx.feefrac.fee != *fee {
+                    entry.tx.feefrac.fee = *fee;
+                    changed.insert(entry.cluster);
+                }
+            }
+        }
+        for id in changed {
+            let chunks = self.linearize_members(&self.clusters[&id].members);
+            if let Some(cluster) = self.clusters.get_mut(&id) {
+                cluster.chunks = chunks;
+            }
+        }
+    }
+
+    /// Remove everything
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.clusters.clear();
+    }
+
+    /// Every chunk, best fee rate first
+    ///
+    /// Chunks of one cluster keep their order, so each chunk comes after the
+    /// chunks holding its ancestors.
+    pub fn chunks_by_feerate(&self) -> Vec<&Chunk> {
+        let mut chunks: Vec<&Chunk> = self
+            .clusters
+            .values()
+            .flat_map(|cluster| cluster.chunks.iter())
+            .collect();
+        // Stable, and chunk rates within a cluster never increase
+        chunks.sort_by(|a, b| b.feefrac.cmp_rate(&a.feefrac));
+        chunks
+    }
+
+    /// The lowest fee rate chunk that no other transaction depends on
+    ///
+    /// Only the last chunk of a cluster qualifies; it is always the cluster's
+    /// worst, and removing it leaves no transaction without its parents.
+    pub fn worst_chunk(&self) -> Option<&Chunk> {
+        self.last_chunks().min_by(|a, b| {
+            a.feefrac
+                .cmp_rate(&b.feefrac)
+                .then_with(|| a.txids.cmp(&b.txids))
+        })
+    }
+
+    /// The last chunk of every cluster
+    pub fn last_chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.clusters
+            .values()
+            .filter_map(|cluster| cluster.chunks.last())
+    }
+
+    /// Fee-rate diagrams of the clusters touched by a replacement, before and
+    /// after it
+    ///
+    /// `conflicts` and all their descendants are removed, and `replacements`
+    /// (in topological order) are added.
+    pub fn replacement_diagrams(
+        &self,
+        conflicts: &HashSet<Hash>,
+        replacements: &[(Hash, ClusterTx)],
+    ) -> (Vec<FeeFrac>, Vec<FeeFrac>) {
+        let mut removed = HashSet::new();
+        for conflict in conflicts {
+            removed.extend(self.with_descendants(conflict));
+        }
+
+        let mut touched = HashSet::new();
+        for txid in removed
+            .iter()
+            .chain(replacements.iter().flat_map(|(_, tx)| tx.parents.iter()))
+        {
+            if let Some(entry) = self.entries.get(txid) {
+                touched.insert(entry.cluster);
+            }
+        }
+
+        let old_chunks: Vec<Chunk> = touched
+            .iter()
+            .flat_map(|id| self.clusters[id].chunks.iter().cloned())
+            .collect();
+
+        let mut graph: HashMap<Hash, ClusterTx> = touched
+            .iter()
+            .flat_map(|id| self.clusters[id].members.iter())
+            .filter(|txid| !removed.contains(*txid))
+            .map(|txid| (*txid, self.entries[txid].tx.clone()))
+            .collect();
+        for tx in graph.values_mut() {
+            tx.parents.retain(|parent| !removed.contains(parent));
+        }
+        for (txid, tx) in replacements {
+            let mut tx = tx.clone();
+            tx.parents.retain(|parent| graph.contains_key(parent));
+            graph.insert(*txid, tx);
+        }
+
+        let members: HashSet<Hash> = graph.keys().copied().collect();
+        let children = children_of(&graph);
+        let mut new_chunks = Vec::new();
+        for component in components(&members, |txid| {
+            graph[txid]
+                .parents
+                .iter()
+                .chain(children.get(txid).into_iter().flatten())
+                .copied()
+                .collect()
+        }) {
+            let order = linearize(&component, &graph);
+            new_chunks.extend(chunk(&order, &graph));
+        }
+
+        (diagram(old_chunks), diagram(new_chunks))
+    }
+
+    fn neighbours(&self, txid: &Hash) -> Vec<Hash> {
+        let entry = &self.entries[txid];
+        entry
+            .tx
+            .parents
+            .iter()
+            .chain(entry.children.iter())
+            .copied()
+            .collect()
+    }
+
+    fn add_cluster(&mut self, members: HashSet<Hash>) {
+        let id = self.next_cluster;
+        self.next_cluster += 1;
+        for txid in &members {
+            if let Some(entry) = self.entries.get_mut(txid) {
+                entry.cluster = id;
+            }
+        }
+        let chunks = self.linearize_members(&members);
+        self.clusters.insert(id, Cluster { members, chunks });
+    }
+
+    fn linearize_members(&self, members: &HashSet<Hash>) -> Vec<Chunk> {
+        let graph: HashMap<Hash, ClusterTx> = members
+            .iter()
+            .map(|txid| (*txid, self.entries[txid].tx.clone()))
+            .collect();
+        let order = linearize(members, &graph);
+        chunk(&order, &graph)
+    }
+}
+
+/// Order a cluster's transactions topologically, best fee rate first
+///
+/// Repeatedly takes the remaining transaction whose ancestor set (among the
+/// remaining transactions) has the highest fee rate, and appends that set,
+/// parents first. Ties go to the smaller set, then the lower txid, so the
+/// result is deterministic.
+pub fn linearize(members: &HashSet<Hash>, graph: &HashMap<Hash, ClusterTx>) -> Vec<Hash> {
+    let mut remaining = members.clone();
+    let mut order = Vec::with_capacity(members.len());
+    while !remaining.is_empty() {
+        let mut best: Option<(Hash, HashSet<Hash>, FeeFrac)> = None;
+        let mut candidates: Vec<&Hash> = remaining.iter().collect();
+        candidates.sort();
+        for txid in candidates {
+            let set = ancestors_within(txid, &remaining, graph);
+            let feefrac = set.iter().fold(FeeFrac::default(), |total, txid| {
+                total + graph[txid].feefrac
+            });
+            let better = match &best {
+                None => true,
+                Some((_, best_set, best_feefrac)) => match feefrac.cmp_rate(best_feefrac) {
+                    Ordering::Greater => true,
+                    Ordering::Equal => set.len() < best_set.len(),
+                    Ordering::Less => false,
+                },
+            };
+            if better {
+                best = Some((*txid, set, feefrac));
+            }
+        }
+        let (_, set, _) = best.expect("remaining is not empty");
+
+        // Within an ancestor set, ancestors have strictly fewer ancestors
+        let mut chosen: Vec<(usize, Hash)> = set
+            .iter()
+            .map(|txid| (ancestors_within(txid, &set, graph).len(), *txid))
+            .collect();
+        chosen.sort();
+        for (_, txid) in chosen {
+            remaining.remove(&txid);
+            order.push(txid);
+        }
+    }
+    order
+}
+
+/// Cut a linearization into chunks of non-increasing fee rate
+pub fn chunk(order: &[Hash], graph: &HashMap<Hash, ClusterTx>) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    for txid in order {
+        chunks.push(Chunk {
+            txids: vec![*txid],
+            feefrac: graph[txid].feefrac,
+        });
+        while chunks.len() >= 2 {
+            let last = &chunks[chunks.len() - 1];
+            let prev = &chunks[chunks.len() - 2];
+            if last.feefrac.cmp_rate(&prev.feefrac) != Ordering::Greater {
+                break;
+            }
+            let last = chunks.pop().expect("two chunks");
+            let prev = chunks.last_mut().expect("two chunks");
+            prev.txids.extend(last.txids);
+            prev.feefrac += last.feefrac;
+        }
+    }
+    chunks
+}
+
+/// Cumulative fee-rate diagram of a set of chunks, starting at zero
+pub fn diagram(mut chunks: Vec<Chunk>) -> Vec<FeeFrac> {
+    chunks.sort_by(|a, b| b.feefrac.cmp_rate(&a.feefrac));
+    let mut points = vec![FeeFrac::default()];
+    let mut total = FeeFrac::default();
+    for chunk in chunks {
+        total += chunk.feefrac;
+        points.push(total);
+    }
+    points
+}
+
+/// Whether diagram `new` is nowhere below `old` and somewhere above it
+///
+/// Both diagrams are compared at every point of either; past its end a
+/// diagram stays at its total fee.
+pub fn improves_diagram(new: &[FeeFrac], old: &[FeeFrac]) -> bool {
+    let mut strictly_better = false;
+    for point in old {
+        match compare_at(new, point) {
+            Ordering::Less => return false,
+            Ordering::Greater => strictly_better = true,
+            Ordering::Equal => {}
+        }
+    }
+    for point in new {
+        match compare_at(old, point) {
+            Ordering::Greater => return false,
+            Ordering::Less => strictly_better = true,
+            Ordering::Equal => {}
+        }
+    }
+    strictly_better
+}
+
+/// Compare the fee of `diagram` at `point.size` with `point.fee`
+fn compare_at(diagram: &[FeeFrac], point: &FeeFrac) -> Ordering {
+    for pair in diagram.windows(2) {
+        let (start, end) = (pair[0], pair[1]);
+        if point.size > end.size {
+            continue;
+        }
+        if point.size < start.size {
+            break;
+        }
+        // fee(x) = start.fee + (end.fee - start.fee) * (x - start.size) / width
+        let width = (end.size - start.size) as u128;
+        if width == 0 {
+            return end.fee.cmp(&point.fee);
+        }
+        let fee_scaled = start.fee as u128 * width
+            + (end.fee - start.fee) as u128 * (point.size - start.size) as u128;
+        return fee_scaled.cmp(&(point.fee as u128 * width));
+    }
+    let total = diagram.last().map_or(0, |last| last.fee);
+    total.cmp(&point.fee)
+}
+
+/// `txid` and its ancestors among `within`
+fn ancestors_within(
+    txid: &Hash,
+    within: &HashSet<Hash>,
+    graph: &HashMap<Hash, ClusterTx>,
+) -> HashSet<Hash> {
+    let mut found = HashSet::from([*txid]);
+    let mut stack = vec![*txid];
+    while let Some(next) = stack.pop() {
+        for parent in &graph[&next].parents {
+            if within.contains(parent) && found.insert(*parent) {
+                stack.push(*parent);
+            }
+        }
+    }
+    found
+}
+
+fn children_of(graph: &HashMap<Hash, ClusterTx>) -> HashMap<Hash, Vec<Hash>> {
+    let mut children: HashMap<Hash, Vec<Hash>> = HashMap::new();
+    for (txid, tx) in graph {
+        for parent in &tx.parents {
+            children.entry(*parent).or_default().push(*txid);
+        }
+    }
+    children
+}
+
+/// Connected components of `members`
+fn components(
+    members: &HashSet<Hash>,
+    neighbours: impl Fn(&Hash) -> Vec<Hash>,
+) -> Vec<HashSet<Hash>> {
+    let mut seen = HashSet::new();
+    let mut result = Vec::new();
+    for start in members {
+        if seen.contains(start) {
+            continue;
+        }
+        let mut component = HashSet::new();
+        let mut stack = vec![*start];
+        while let Some(next) = stack.pop() {
+            if !seen.insert(next) {
+                continue;
+            }
+            component.insert(next);
+            for neighbour in neighbours(&next) {
+                if members.contains(&neighbour) && !seen.contains(&neighbour) {
+                    stack.push(neighbour);
+                }
+            }
+        }
+        result.push(component);
+    }
+    result
+}
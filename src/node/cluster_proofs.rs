@@ -0,0 +1,124 @@
+//! Kani proofs for cluster mempool linearization
+//!
+//! This module provides formal verification of cluster linearization and
+//! chunking using Kani model checking.
+//!
+//! Mathematical Specifications:
+//! - Topological order: ∀ tx ∈ linearize(C), ∀ p ∈ parents(tx) ∩ C: index(p) < index(tx)
+//! - Chunk monotonicity: ∀ i: feerate(chunk[i]) ≥ feerate(chunk[i + 1])
+//! - Chunk conservation: Σ chunk.fee = Σ tx.fee ∧ Σ chunk.size = Σ tx.size
+//! - Diagram reflexivity: ¬improves_diagram(D, D)
+
+#[cfg(kani)]
+mod kani_proofs {
+    use crate::node::cluster::{chunk, diagram, improves_diagram, linearize, ClusterTx, FeeFrac};
+    use bllvm_protocol::Hash;
+    use kani::*;
+    use std::cmp::Ordering;
+    use std::collections::{HashMap, HashSet};
+
+    /// Proof limits for cluster operations
+    mod proof_limits {
+        pub const MAX_CLUSTER_TXS_FOR_PROOF: usize = 3;
+        pub const MAX_FEE: u64 = 1_000_000;
+        pub const MAX_SIZE: u64 = 100_000;
+    }
+
+    /// Unwind bounds for cluster operations
+    mod unwind_bounds {
+        pub const SMALL_CLUSTER: u32 = 6;
+    }
+
+    /// Helper to create a bounded cluster
+    ///
+    /// Transaction `i` may only spend transaction `j < i`, so the graph is
+    /// acyclic as a mempool's always is.
+    fn create_bounded_cluster(count: usize) -> (HashSet<Hash>, HashMap<Hash, ClusterTx>) {
+        let mut graph = HashMap::new();
+        for i in 0..count {
+            let fee: u64 = kani::any();
+            kani::assume(fee <= proof_limits::MAX_FEE);
+            let size: u64 = kani::any();
+            kani::assume(size >= 1 && size <= proof_limits::MAX_SIZE);
+
+            let mut parents = HashSet::new();
+            for j in 0..i {
+                if kani::any::<bool>() {
+                    parents.insert([j as u8; 32]);
+                }
+            }
+            graph.insert(
+                [i as u8; 32],
+                ClusterTx {
+                    feefrac: FeeFrac::new(fee, size),
+                    parents,
+                },
+            );
+        }
+        (graph.keys().copied().collect(), graph)
+    }
+
+    /// Verify linearization is a topological order of the whole cluster
+    ///
+    /// Mathematical Specification:
+    /// ∀ tx ∈ linearize(C), ∀ p ∈ parents(tx): index(p) < index(tx)
+    #[kani::proof]
+    #[kani::unwind(unwind_bounds::SMALL_CLUSTER)]
+    fn verify_linearization_topological() {
+        let count = kani::any::<usize>();
+        kani::assume(count >= 1 && count <= proof_limits::MAX_CLUSTER_TXS_FOR_PROOF);
+        let (members, graph) = create_bounded_cluster(count);
+
+        let order = linearize(&members, &graph);
+        assert_eq!(order.len(), members.len());
+        for (position, txid) in order.iter().enumerate() {
+            for parent in &graph[txid].parents {
+                let parent_position = order.iter().position(|t| t == parent).unwrap();
+                assert!(parent_position < position);
+            }
+        }
+    }
+
+    /// Verify chunk fee rates never increase and chunking conserves totals
+    ///
+    /// Mathematical Specification:
+    /// ∀ i: feerate(chunk[i]) ≥ feerate(chunk[i + 1])
+    /// Σ chunk.feefrac = Σ tx.feefrac
+    #[kani::proof]
+    #[kani::unwind(unwind_bounds::SMALL_CLUSTER)]
+    fn verify_chunk_feerates_non_increasing() {
+        let count = kani::any::<usize>();
+        kani::assume(count >= 1 && count <= proof_limits::MAX_CLUSTER_TXS_FOR_PROOF);
+        let (members, graph) = create_bounded_cluster(count);
+
+        let order = linearize(&members, &graph);
+        let chunks = chunk(&order, &graph);
+        for pair in chunks.windows(2) {
+            assert!(pair[0].feefrac.cmp_rate(&pair[1].feefrac) != Ordering::Less);
+        }
+
+        let chunked = chunks
+            .iter()
+            .fold(FeeFrac::default(), |total, chunk| total + chunk.feefrac);
+        let total = graph
+            .values()
+            .fold(FeeFrac::default(), |total, tx| total + tx.feefrac);
+        assert_eq!(chunked, total);
+    }
+
+    /// Verify a diagram never strictly improves on itself
+    ///
+    /// Mathematical Specification:
+    /// ¬improves_diagram(D, D)
+    #[kani::proof]
+    #[kani::unwind(unwind_bounds::SMALL_CLUSTER)]
+    fn verify_diagram_not_self_improving() {
+        let count = kani::any::<usize>();
+        kani::assume(count >= 1 && count <= proof_limits::MAX_CLUSTER_TXS_FOR_PROOF);
+        let (members, graph) = create_bounded_cluster(count);
+
+        let order = linearize(&members, &graph);
+        let points = diagram(chunk(&order, &graph));
+        assert!(!improves_diagram(&points, &points));
+    }
+}
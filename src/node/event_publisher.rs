@@ -8,6 +8,7 @@ use tracing::{debug, warn};
 use crate::module::api::events::EventManager;
 use crate::module::ipc::protocol::EventPayload;
 use crate::module::traits::EventType;
+use crate::node::mempool::MempoolRemovalReason;
 #[cfg(feature = "zmq")]
 use crate::zmq::ZmqPublisher;
 use crate::{Block, Hash, Transaction};
@@ -110,6 +111,24 @@ impl EventPublisher {
         }
     }
 
+    /// Publish a transaction leaving the mempool
+    ///
+    /// Publishes a ZMQ `sequence` removal notification with the reason (if enabled).
+    pub async fn publish_mempool_removal(&self, tx_hash: &Hash, reason: MempoolRemovalReason) {
+        debug!(
+            "Publishing mempool removal of tx {:?} ({})",
+            tx_hash,
+            reason.as_str()
+        );
+
+        #[cfg(feature = "zmq")]
+        if let Some(ref zmq) = self.zmq_publisher {
+            if let Err(e) = zmq.publish_mempool_removal(tx_hash, reason.as_str()).await {
+                warn!("Failed to publish ZMQ mempool removal notification: {}", e);
+            }
+        }
+    }
+
     /// Publish block disconnected event (chain reorg)
     pub async fn publish_block_disconnected(&self, hash: &Hash, height: u64) {
         debug!(
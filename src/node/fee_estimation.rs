@@ -0,0 +1,586 @@
+//! Fee estimation from confirmation times
+//!
+//! Records the height at which each mempool transaction entered and the
+//! height of the block that confirmed it, grouped into exponentially spaced
+//! fee rate buckets. Counts decay every block, so recent blocks weigh most.
+//! The estimate for a target of N blocks is the median fee rate of the
+//! cheapest bucket range in which enough transactions confirmed within N
+//! blocks.
+//!
+//! As in Bitcoin Core, three horizons are kept: a short one tracking up to
+//! 12 blocks that decays fast, a medium one up to 48 blocks and a long one
+//! up to 1008 blocks that decays slowly. Transactions that leave the mempool
+//! unconfirmed, or stay in it longer than the target, count as failures.
+
+use crate::utils::current_timestamp;
+use anyhow::Result;
+use bllvm_protocol::Hash;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+use tracing::{debug, info};
+
+/// File the estimator state is saved to, in the data directory
+pub const FEE_ESTIMATES_FILE: &str = "fee_estimates.dat";
+
+/// Highest confirmation target that can be estimated
+pub const MAX_CONFIRMATION_TARGET: u64 = 1008;
+
+/// Lowest and highest bucket boundaries (sat/vB), and the spacing between them
+const MIN_BUCKET_FEERATE: f64 = 1.0;
+const MAX_BUCKET_FEERATE: f64 = 10_000.0;
+const FEE_SPACING: f64 = 1.05;
+
+/// Horizons: periods tracked, blocks per period, and decay per block
+const SHORT_PERIODS: usize = 12;
+const SHORT_SCALE: u64 = 1;
+const SHORT_DECAY: f64 = 0.962;
+const MED_PERIODS: usize = 24;
+const MED_SCALE: u64 = 2;
+const MED_DECAY: f64 = 0.9952;
+const LONG_PERIODS: usize = 42;
+const LONG_SCALE: u64 = 24;
+const LONG_DECAY: f64 = 0.99931;
+
+/// Success rates required for half, actual and double targets
+const HALF_SUCCESS_PCT: f64 = 0.6;
+const SUCCESS_PCT: f64 = 0.85;
+const DOUBLE_SUCCESS_PCT: f64 = 0.95;
+
+/// Transactions per block a bucket range needs before it is trusted
+const SUFFICIENT_FEETXS: f64 = 0.1;
+const SUFFICIENT_TXS_SHORT: f64 = 0.5;
+
+/// Saved state format version
+const FEE_ESTIMATES_VERSION: u32 = 1;
+
+/// Saved state older than this is too stale to use (seconds)
+const MAX_FILE_AGE: u64 = 60 * 60 * 60;
+
+/// How an estimate trades fee against certainty
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EstimateMode {
+    /// Responds faster to falling fee rates, using the shorter horizons
+    Economical,
+    /// Also requires the fee rate to have worked over the long horizon
+    #[default]
+    Conservative,
+}
+
+impl EstimateMode {
+    /// Parse an `estimate_mode` RPC parameter ("unset" is conservative)
+    pub fn from_rpc(mode: &str) -> Option<Self> {
+        match mode.to_ascii_lowercase().as_str() {
+            "unset" | "conservative" => Some(EstimateMode::Conservative),
+            "economical" => Some(EstimateMode::Economical),
+            _ => None,
+        }
+    }
+}
+
+/// A fee rate estimate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeEstimate {
+    /// Fee rate in sat/vB
+    pub fee_rate: f64,
+    /// Target the estimate is for, which may differ from the one requested
+    pub blocks: u64,
+}
+
+/// Decayed confirmation counts for one horizon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ConfirmStats {
+    /// Blocks per period
+    scale: u64,
+    decay: f64,
+    /// Transactions per block a bucket range needs before it is trusted
+    sufficient_txs: f64,
+    /// Transactions confirmed within `(period + 1) * scale` blocks, by period
+    /// and bucket
+    conf_avg: Vec<Vec<f64>>,
+    /// Transactions that left the mempool unconfirmed after waiting at least
+    /// `(period + 1) * scale` blocks, by period and bucket
+    fail_avg: Vec<Vec<f64>>,
+    /// Confirmed transactions by bucket
+    tx_ct_avg: Vec<f64>,
+    /// Sum of the confirmed transactions' fee rates by bucket
+    fee_rate_avg: Vec<f64>,
+}
+
+impl ConfirmStats {
+    fn new(periods: usize, scale: u64, decay: f64, sufficient_txs: f64, buckets: usize) -> Self {
+        Self {
+            scale,
+            decay,
+            sufficient_txs,
+            conf_avg: vec![vec![0.0; buckets]; periods],
+            fail_avg: vec![vec![0.0; buckets]; periods],
+            tx_ct_avg: vec![0.0; buckets],
+            fee_rate_avg: vec![0.0; buckets],
+        }
+    }
+
+    fn max_confirms(&self) -> u64 {
+        self.scale * self.conf_avg.len() as u64
+    }
+
+    fn buckets(&self) -> usize {
+        self.tx_ct_avg.len()
+    }
+
+    fn decay(&mut self) {
+        let decay = self.decay;
+        for row in self.conf_avg.iter_mut().chain(self.fail_avg.iter_mut()) {
+            row.iter_mut().for_each(|count| *count *= decay);
+        }
+        self.tx_ct_avg.iter_mut().for_each(|count| *count *= decay);
+        self.fee_rate_avg.iter_mut().for_each(|sum| *sum *= decay);
+    }
+
+    fn record_confirmed(&mut self, blocks: u64, bucket: usize, fee_rate: f64) {
+        let periods = blocks.div_ceil(self.scale) as usize;
+        for row in self.conf_avg.iter_mut().skip(periods.saturating_sub(1)) {
+            row[bucket] += 1.0;
+        }
+        self.tx_ct_avg[bucket] += 1.0;
+        self.fee_rate_avg[bucket] += fee_rate;
+    }
+
+    fn record_failed(&mut self, blocks_waited: u64, bucket: usize) {
+        let periods = (blocks_waited / self.scale) as usize;
+        for row in self.fail_avg.iter_mut().take(periods) {
+            row[bucket] += 1.0;
+        }
+    }
+
+    /// Median fee rate of the cheapest bucket range that confirmed within
+    /// `target` blocks at `success_pct`
+    ///
+    /// Buckets are grouped from the highest fee rate down until a range has
+    /// enough transactions to judge. `unconfirmed` counts, by bucket, the
+    /// transactions still waiting after `target` blocks.
+    fn estimate_median(&self, target: u64, success_pct: f64, unconfirmed: &[f64]) -> Option<f64> {
+        let period = target.div_ceil(self.scale) as usize;
+        if period == 0 || period > self.conf_avg.len() {
+            return None;
+        }
+        let conf_row = &self.conf_avg[period - 1];
+        let fail_row = &self.fail_avg[period - 1];
+        let sufficient = self.sufficient_txs / (1.0 - self.decay);
+
+        let (mut confirmed, mut total, mut failed, mut extra) = (0.0, 0.0, 0.0, 0.0);
+        let mut range_top = self.buckets() - 1;
+        let mut new_range = true;
+        let mut best: Option<(usize, usize)> = None;
+        for bucket in (0..self.buckets()).rev() {
+            if new_range {
+                range_top = bucket;
+                new_range = false;
+            }
+            confirmed += conf_row[bucket];
+            total += self.tx_ct_avg[bucket];
+            failed += fail_row[bucket];
+            extra += unconfirmed[bucket];
+            if total < sufficient {
+                continue;
+            }
+            // A failing range keeps growing into lower buckets
+            if confirmed / (total + failed + extra) < success_pct {
+                continue;
+            }
+            best = Some((bucket, range_top));
+            (confirmed, total, failed, extra) = (0.0, 0.0, 0.0, 0.0);
+            new_range = true;
+        }
+
+        let (low, high) = best?;
+        let mut half = self.tx_ct_avg[low..=high].iter().sum::<f64>() / 2.0;
+        for bucket in low..=high {
+            let count = self.tx_ct_avg[bucket];
+            if count < half {
+                half -= count;
+            } else if count > 0.0 {
+                return Some(self.fee_rate_avg[bucket] / count);
+            }
+        }
+        None
+    }
+}
+
+/// A mempool transaction waiting to confirm
+#[derive(Debug, Clone, Copy)]
+struct TrackedTx {
+    height: u64,
+    bucket: usize,
+    fee_rate: f64,
+}
+
+#[derive(Debug)]
+struct EstimatorState {
+    best_height: u64,
+    /// Height of the first block recorded, to tell how much history there is
+    first_height: Option<u64>,
+    /// Upper fee rate boundary of each bucket (sat/vB)
+    bounds: Vec<f64>,
+    short: ConfirmStats,
+    medium: ConfirmStats,
+    long: ConfirmStats,
+    tracked: HashMap<Hash, TrackedTx>,
+}
+
+/// Estimator state as saved to disk
+#[derive(Serialize, Deserialize)]
+struct SavedEstimates {
+    version: u32,
+    saved_at: u64,
+    best_height: u64,
+    first_height: Option<u64>,
+    short: ConfirmStats,
+    medium: ConfirmStats,
+    long: ConfirmStats,
+}
+
+impl EstimatorState {
+    fn new() -> Self {
+        let mut bounds = Vec::new();
+        let mut bound = MIN_BUCKET_FEERATE;
+        while bound <= MAX_BUCKET_FEERATE {
+            bounds.push(bound);
+            bound *= FEE_SPACING;
+        }
+        bounds.push(f64::INFINITY);
+        let buckets = bounds.len();
+        Self {
+            best_height: 0,
+            first_height: None,
+            bounds,
+            short: ConfirmStats::new(
+                SHORT_PERIODS,
+                SHORT_SCALE,
+                SHORT_DECAY,
+                SUFFICIENT_TXS_SHORT,
+                buckets,
+            ),
+            medium: ConfirmStats::new(
+                MED_PERIODS,
+                MED_SCALE,
+                MED_DECAY,
+                SUFFICIENT_FEETXS,
+                buckets,
+            ),
+            long: ConfirmStats::new(
+                LONG_PERIODS,
+                LONG_SCALE,
+                LONG_DECAY,
+                SUFFICIENT_FEETXS,
+                buckets,
+            ),
+            tracked: HashMap::new(),
+        }
+    }
+
+    fn bucket(&self, fee_rate: f64) -> usize {
+        self.bounds.partition_point(|bound| *bound < fee_rate)
+    }
+
+    fn horizons_mut(&mut self) -> [&mut ConfirmStats; 3] {
+        [&mut self.short, &mut self.medium, &mut self.long]
+    }
+
+    fn record_failed(&mut self, tx: TrackedTx) {
+        let waited = self.best_height.saturating_sub(tx.height);
+        for stats in self.horizons_mut() {
+            stats.record_failed(waited, tx.bucket);
+        }
+    }
+
+    /// Tracked transactions still waiting after `target` blocks, by bucket
+    fn unconfirmed(&self, target: u64) -> Vec<f64> {
+        let mut counts = vec![0.0; self.bounds.len()];
+        for tx in self.tracked.values() {
+            if self.best_height.saturating_sub(tx.height) >= target {
+                counts[tx.bucket] += 1.0;
+            }
+        }
+        counts
+    }
+
+    fn estimate(&self, stats: &ConfirmStats, target: u64, success_pct: f64) -> Option<f64> {
+        stats.estimate_median(target, success_pct, &self.unconfirmed(target))
+    }
+
+    /// Estimate from the shortest horizon covering `target`, or a lower
+    /// one from the maximum target of a shorter horizon
+    fn estimate_combined(&self, target: u64, success_pct: f64, check_shorter: bool) -> Option<f64> {
+        if target == 0 || target > self.long.max_confirms() {
+            return None;
+        }
+        let stats = if target <= self.short.max_confirms() {
+            &self.short
+        } else if target <= self.medium.max_confirms() {
+            &self.medium
+        } else {
+            &self.long
+        };
+        let mut estimate = self.estimate(stats, target, success_pct);
+        if check_shorter {
+            for shorter in [&self.medium, &self.short] {
+                let max = shorter.max_confirms();
+                if target <= max {
+                    continue;
+                }
+                if let Some(shorter_estimate) = self.estimate(shorter, max, success_pct) {
+                    estimate = Some(estimate.map_or(shorter_estimate, |e| e.min(shorter_estimate)));
+                }
+            }
+        }
+        estimate
+    }
+
+    /// Highest estimate over the medium and long horizons at the double
+    /// target's strict success rate
+    fn estimate_conservative(&self, double_target: u64) -> Option<f64> {
+        let mut estimate = None;
+        if double_target <= self.short.max_confirms() {
+            estimate = self.estimate(&self.medium, double_target, DOUBLE_SUCCESS_PCT);
+        }
+        if double_target <= self.medium.max_confirms() {
+            if let Some(long) = self.estimate(&self.long, double_target, DOUBLE_SUCCESS_PCT) {
+                estimate = Some(estimate.map_or(long, |e: f64| e.max(long)));
+            }
+        }
+        estimate
+    }
+
+    /// Highest target there is enough history to answer
+    fn max_usable_target(&self) -> u64 {
+        let span = self
+            .first_height
+            .map_or(0, |first| self.best_height.saturating_sub(first));
+        (span / 2).min(self.long.max_confirms())
+    }
+}
+
+/// Fee estimator fed by mempool entries and connected blocks
+///
+/// Uses RwLock for interior mutability so it can be shared between the
+/// mempool, the node and the RPC handlers.
+#[derive(Debug)]
+pub struct FeeEstimator {
+    state: RwLock<EstimatorState>,
+}
+
+impl FeeEstimator {
+    /// Create an estimator with no history
+    pub fn new() -> Self {
+        Self {
+            state: RwLock::new(EstimatorState::new()),
+        }
+    }
+
+    /// Height of the last block processed
+    pub fn best_height(&self) -> u64 {
+        self.state.read().unwrap().best_height
+    }
+
+    /// Number of mempool transactions waiting to confirm
+    pub fn tracked_count(&self) -> usize {
+        self.state.read().unwrap().tracked.len()
+    }
+
+    /// Fee rate (sat/vB) and bucket boundary recorded for a transaction
+    /// waiting to confirm
+    ///
+    /// The boundary is the upper fee rate of the bucket the transaction is
+    /// counted in.
+    pub fn tracked_fee_rate(&self, txid: &Hash) -> Option<(f64, f64)> {
+        let state = self.state.read().unwrap();
+        state
+            .tracked
+            .get(txid)
+            .map(|tx| (tx.fee_rate, state.bounds[tx.bucket]))
+    }
+
+    /// Record a transaction entering the mempool at the current height
+    ///
+    /// Ignored until a block has been processed, since the entry height is
+    /// not known before then.
+    pub fn process_transaction(&self, txid: Hash, fee: u64, vsize: u64) {
+        let mut state = self.state.write().unwrap();
+        if state.best_height == 0 || vsize == 0 || state.tracked.contains_key(&txid) {
+            return;
+        }
+        let fee_rate = fee as f64 / vsize as f64;
+        let tx = TrackedTx {
+            height: state.best_height,
+            bucket: state.bucket(fee_rate),
+            fee_rate,
+        };
+        state.tracked.insert(txid, tx);
+    }
+
+    /// Record a transaction leaving the mempool without confirming
+    /// (replaced, evicted or expired)
+    pub fn remove_transaction(&self, txid: &Hash) {
+        let mut state = self.state.write().unwrap();
+        if let Some(tx) = state.tracked.remove(txid) {
+            state.record_failed(tx);
+        }
+    }
+
+    /// Record a connected block and the transactions it confirmed
+    ///
+    /// Blocks at or below the best height seen (reorgs, reprocessing) are
+    /// ignored.
+    pub fn process_block(&self, height: u64, txids: &[Hash]) {
+        let mut state = self.state.write().unwrap();
+        if height <= state.best_height {
+            return;
+        }
+        state.best_height = height;
+        if state.first_height.is_none() {
+            state.first_height = Some(height);
+        }
+        for stats in state.horizons_mut() {
+            stats.decay();
+        }
+
+        let mut confirmed = 0;
+        for txid in txids {
+            let Some(tx) = state.tracked.remove(txid) else {
+                continue;
+            };
+            let blocks = height.saturating_sub(tx.height);
+            if blocks == 0 {
+                continue;
+            }
+            for stats in state.horizons_mut() {
+                stats.record_confirmed(blocks, tx.bucket, tx.fee_rate);
+            }
+            confirmed += 1;
+        }
+
+        // Give up on transactions that outlived the longest horizon
+        let max_confirms = state.long.max_confirms();
+        let expired: Vec<Hash> = state
+            .tracked
+            .iter()
+            .filter(|(_, tx)| height - tx.height > max_confirms)
+            .map(|(txid, _)| *txid)
+            .collect();
+        for txid in &expired {
+            if let Some(tx) = state.tracked.remove(txid) {
+                state.record_failed(tx);
+            }
+        }
+
+        debug!(
+            "Fee estimator: block {} confirmed {} tracked transactions, {} still waiting",
+            height,
+            confirmed,
+            state.tracked.len()
+        );
+    }
+
+    /// Estimate the fee rate for confirmation within `target` blocks
+    ///
+    /// The target is raised to 2 and capped by the history available, so
+    /// the estimate may be for a different target. Returns `None` without
+    /// enough data.
+    pub fn estimate_smart_fee(&self, target: u64, mode: EstimateMode) -> Option<FeeEstimate> {
+        let state = self.state.read().unwrap();
+        if target == 0 || target > MAX_CONFIRMATION_TARGET {
+            return None;
+        }
+        let target = target.max(2).min(state.max_usable_target());
+        if target <= 1 {
+            return None;
+        }
+
+        let half = state.estimate_combined(target / 2, HALF_SUCCESS_PCT, true);
+        let actual = state.estimate_combined(target, SUCCESS_PCT, true);
+        let double_target = (target * 2).min(state.long.max_confirms());
+        let double = state.estimate_combined(
+            double_target,
+            DOUBLE_SUCCESS_PCT,
+            mode == EstimateMode::Economical,
+        );
+        let mut estimate = [half, actual, double]
+            .into_iter()
+            .flatten()
+            .reduce(f64::max);
+        if mode == EstimateMode::Conservative || estimate.is_none() {
+            if let Some(conservative) = state.estimate_conservative(double_target) {
+                estimate = Some(estimate.map_or(conservative, |e| e.max(conservative)));
+            }
+        }
+        estimate.map(|fee_rate| FeeEstimate {
+            fee_rate,
+            blocks: target,
+        })
+    }
+
+    /// Save the decayed statistics (not the transactions being tracked)
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let saved = {
+            let state = self.state.read().unwrap();
+            SavedEstimates {
+                version: FEE_ESTIMATES_VERSION,
+                saved_at: current_timestamp(),
+                best_height: state.best_height,
+                first_height: state.first_height,
+                short: state.short.clone(),
+                medium: state.medium.clone(),
+                long: state.long.clone(),
+            }
+        };
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, bincode::serialize(&saved)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Load statistics saved by [`Self::save`]
+    ///
+    /// Returns false, keeping the current state, if there is no file or it
+    /// is from another version or too old to reflect current fee rates.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(false);
+        }
+        let saved: SavedEstimates = bincode::deserialize(&std::fs::read(path)?)?;
+        let mut state = self.state.write().unwrap();
+        let buckets = state.bounds.len();
+        if saved.version != FEE_ESTIMATES_VERSION
+            || [&saved.short, &saved.medium, &saved.long]
+                .iter()
+                .any(|stats| stats.buckets() != buckets)
+        {
+            info!(
+                "Ignoring fee estimates in {}: unknown format",
+                path.display()
+            );
+            return Ok(false);
+        }
+        if current_timestamp().saturating_sub(saved.saved_at) > MAX_FILE_AGE {
+            info!("Ignoring fee estimates in {}: too old", path.display());
+            return Ok(false);
+        }
+        state.best_height = saved.best_height;
+        state.first_height = saved.first_height;
+        state.short = saved.short;
+        state.medium = saved.medium;
+        state.long = saved.long;
+        state.tracked.clear();
+        Ok(true)
+    }
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,487 @@
+//! Import blocks from a Bitcoin Core `blocks` directory
+//!
+//! Bitcoin Core appends blocks to `blk?????.dat` files in the order they were
+//! downloaded, each record being the network magic, a little-endian length and
+//! the serialized block. With headers-first download that order is not chain
+//! order, and the files also hold stale blocks. The importer therefore first
+//! scans the files for block headers, then picks the most-work chain extending
+//! the local tip and connects its blocks in height order through the regular
+//! validation path ([`ReorgEngine::connect_block`], which validates with
+//! [`validate_block_with_context`](crate::node::block_processor::validate_block_with_context)).
+//!
+//! Progress is checkpointed in the `block_import` tree as the earliest file
+//! position still needed, so an interrupted import (or a later one, after
+//! Bitcoin Core has synced further) only rescans from there.
+
+use crate::network::protocol::{
+    BITCOIN_MAGIC_MAINNET, BITCOIN_MAGIC_REGTEST, BITCOIN_MAGIC_TESTNET,
+};
+use crate::node::block_processor::parse_block_from_wire;
+use crate::node::reorg::ReorgEngine;
+use crate::storage::chainwork::ChainWork;
+use crate::storage::hashing::double_sha256;
+use crate::storage::Storage;
+use anyhow::{anyhow, Context, Result};
+use bllvm_protocol::{BitcoinProtocolEngine, Hash, ProtocolVersion};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, info};
+
+/// Tree holding the import checkpoint
+pub const BLOCK_IMPORT_TREE: &str = "block_import";
+
+const CHECKPOINT_KEY: &[u8] = b"checkpoint";
+
+/// Obfuscation key file written by Bitcoin Core 28 and later
+const XOR_KEY_FILE: &str = "xor.dat";
+
+/// Size of a serialized block header
+const HEADER_SIZE: usize = 80;
+
+/// Magic plus length preceding every block in a `blk` file
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// Largest serialized block (MAX_BLOCK_SERIALIZED_SIZE)
+const MAX_BLOCK_SIZE: usize = 4_000_000;
+
+/// Blocks connected between checkpoint writes
+const CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// Network magic that prefixes block records in Bitcoin Core's block files
+pub fn network_magic(protocol_version: ProtocolVersion) -> [u8; 4] {
+    match protocol_version {
+        ProtocolVersion::BitcoinV1 => BITCOIN_MAGIC_MAINNET,
+        ProtocolVersion::Testnet3 => BITCOIN_MAGIC_TESTNET,
+        ProtocolVersion::Regtest => BITCOIN_MAGIC_REGTEST,
+    }
+}
+
+/// Position of a block record in Bitcoin Core's block files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CoreFilePosition {
+    /// `blk` file number
+    pub file: u32,
+    /// Offset of the record (its magic) within the file
+    pub offset: u64,
+}
+
+/// Where to resume importing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportCheckpoint {
+    /// Earliest record that may still be needed
+    pub position: CoreFilePosition,
+    /// Local tip when the checkpoint was written; the checkpoint is only used
+    /// while this block is still in the active chain
+    pub tip_hash: Hash,
+}
+
+/// Import phase reported to progress callbacks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportPhase {
+    /// Reading block headers from the files
+    Scanning,
+    /// Validating and connecting blocks
+    Connecting,
+}
+
+/// Progress of an import
+#[derive(Debug, Clone)]
+pub struct ImportProgress {
+    pub phase: ImportPhase,
+    /// File being scanned, or holding the block being connected
+    pub file: u32,
+    /// Number of `blk` files found
+    pub files: u32,
+    pub blocks_scanned: u64,
+    pub blocks_connected: u64,
+    /// Height of the local tip
+    pub height: Option<u64>,
+    /// Height the local tip will reach once the import completes
+    pub target_height: Option<u64>,
+}
+
+/// Result of an import
+#[derive(Debug, Clone, Default)]
+pub struct ImportSummary {
+    /// Checkpoint the scan resumed from, if any
+    pub resumed_from: Option<CoreFilePosition>,
+    pub blocks_scanned: u64,
+    pub blocks_connected: u64,
+    /// Scanned blocks not on the imported chain (already connected, stale or
+    /// missing a parent)
+    pub blocks_skipped: u64,
+    /// Height of the local tip after the import
+    pub tip_height: Option<u64>,
+}
+
+/// Header fields of a block found while scanning
+struct ScannedBlock {
+    prev_hash: Hash,
+    bits: u64,
+    position: CoreFilePosition,
+    len: u32,
+}
+
+/// Reader for a Bitcoin Core `blocks` directory
+pub struct CoreBlockImporter {
+    blocks_dir: PathBuf,
+    magic: [u8; 4],
+    xor_key: Option<[u8; 8]>,
+    progress_interval: u64,
+}
+
+impl CoreBlockImporter {
+    /// Importer for the block files of a network
+    pub fn new<P: AsRef<Path>>(blocks_dir: P, protocol_version: ProtocolVersion) -> Result<Self> {
+        Self::with_magic(blocks_dir, network_magic(protocol_version))
+    }
+
+    /// Importer for block files with an explicit network magic
+    pub fn with_magic<P: AsRef<Path>>(blocks_dir: P, magic: [u8; 4]) -> Result<Self> {
+        let blocks_dir = blocks_dir.as_ref().to_path_buf();
+        if !blocks_dir.is_dir() {
+            return Err(anyhow!(
+                "Block directory {} does not exist",
+                blocks_dir.display()
+            ));
+        }
+
+        let xor_key = match fs::read(blocks_dir.join(XOR_KEY_FILE)) {
+            Ok(data) => {
+                let key: [u8; 8] = data
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| anyhow!("Invalid {} (expected 8 bytes)", XOR_KEY_FILE))?;
+                // An all-zero key means the files are not obfuscated
+                Some(key).filter(|key| key.iter().any(|b| *b != 0))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e).context(format!("Failed to read {}", XOR_KEY_FILE)),
+        };
+
+        Ok(Self {
+            blocks_dir,
+            magic,
+            xor_key,
+            progress_interval: 10_000,
+        })
+    }
+
+    /// Report progress every `blocks` connected blocks (scanning reports once
+    /// per file)
+    pub fn with_progress_interval(mut self, blocks: u64) -> Self {
+        self.progress_interval = blocks.max(1);
+        self
+    }
+
+    /// Path of a `blk` file
+    pub fn block_file_path(&self, file: u32) -> PathBuf {
+        self.blocks_dir.join(format!("blk{:05}.dat", file))
+    }
+
+    /// Number of consecutive `blk` files, starting at `blk00000.dat`
+    pub fn file_count(&self) -> u32 {
+        let mut count = 0;
+        while self.block_file_path(count).exists() {
+            count += 1;
+        }
+        count
+    }
+
+    /// Read a `blk` file, removing the obfuscation if any
+    fn read_file(&self, file: u32) -> Result<Vec<u8>> {
+        let path = self.block_file_path(file);
+        let mut data =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        self.deobfuscate(&mut data, 0);
+        Ok(data)
+    }
+
+    /// Remove the obfuscation from bytes read at `offset` in a `blk` file
+    fn deobfuscate(&self, data: &mut [u8], offset: u64) {
+        if let Some(key) = self.xor_key {
+            for (i, byte) in data.iter_mut().enumerate() {
+                *byte ^= key[((offset + i as u64) % key.len() as u64) as usize];
+            }
+        }
+    }
+
+    /// Find the block records in a file from `start` on
+    ///
+    /// Bytes that do not start a record (such as the zero padding Bitcoin Core
+    /// preallocates at the end of a file) are skipped one at a time, as Bitcoin
+    /// Core does when reindexing. A record cut off at the end of the file
+    /// ends the scan. Returns the number of records found and the offset just
+    /// past the last of them, where Bitcoin Core writes its next block.
+    fn scan_file(
+        &self,
+        file: u32,
+        start: u64,
+        out: &mut HashMap<Hash, ScannedBlock>,
+    ) -> Result<(u64, u64)> {
+        let data = self.read_file(file)?;
+        let mut pos = start as usize;
+        let mut found = 0;
+        let mut end = start;
+        while pos + RECORD_HEADER_SIZE <= data.len() {
+            if data[pos..pos + 4] != self.magic {
+                pos += 1;
+                continue;
+            }
+            let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            if !(HEADER_SIZE..=MAX_BLOCK_SIZE).contains(&len) {
+                pos += 1;
+                continue;
+            }
+            let body = pos + RECORD_HEADER_SIZE;
+            if body + len > data.len() {
+                break;
+            }
+
+            let header = &data[body..body + HEADER_SIZE];
+            let mut prev_hash = [0u8; 32];
+            prev_hash.copy_from_slice(&header[4..36]);
+            let bits = u32::from_le_bytes(header[72..76].try_into().unwrap()) as u64;
+            out.entry(double_sha256(header)).or_insert(ScannedBlock {
+                prev_hash,
+                bits,
+                position: CoreFilePosition {
+                    file,
+                    offset: pos as u64,
+                },
+                len: len as u32,
+            });
+            found += 1;
+            pos = body + len;
+            end = pos as u64;
+        }
+        Ok((found, end))
+    }
+
+    /// Read the serialized block of a scanned record
+    fn read_block(&self, block: &ScannedBlock) -> Result<Vec<u8>> {
+        let path = self.block_file_path(block.position.file);
+        let start = block.position.offset + RECORD_HEADER_SIZE as u64;
+        let mut file =
+            File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut data = vec![0u8; block.len as usize];
+        file.read_exact(&mut data).map_err(|e| {
+            if e.kind() == ErrorKind::UnexpectedEof {
+                anyhow!(
+                    "Block record in blk{:05}.dat is truncated",
+                    block.position.file
+                )
+            } else {
+                anyhow!("Failed to read {}: {}", path.display(), e)
+            }
+        })?;
+        self.deobfuscate(&mut data, start);
+        Ok(data)
+    }
+
+    /// Import the blocks extending the local tip into `storage`
+    ///
+    /// Only the most-work chain of scanned blocks descending from the local tip
+    /// (or from a genesis block, for an empty chain) is connected. A block that
+    /// fails validation stops the import with an error; the blocks connected
+    /// before it stay connected.
+    pub fn import<F>(
+        &self,
+        storage: &Arc<Storage>,
+        protocol: &BitcoinProtocolEngine,
+        mut progress: F,
+    ) -> Result<ImportSummary>
+    where
+        F: FnMut(&ImportProgress),
+    {
+        let blocks = storage.blocks();
+        let tip_hash = storage.chain().get_tip_hash()?;
+        let tip_height = storage.chain().get_height()?;
+
+        let mut summary = ImportSummary {
+            tip_height,
+            ..Default::default()
+        };
+
+        // Resume from the checkpoint if the chain it was written for is still active
+        let start = match load_checkpoint(storage)? {
+            Some(checkpoint) if blocks.is_in_main_chain(&checkpoint.tip_hash)? => {
+                summary.resumed_from = Some(checkpoint.position);
+                checkpoint.position
+            }
+            _ => CoreFilePosition { file: 0, offset: 0 },
+        };
+
+        let files = self.file_count();
+        if let Some(resumed) = summary.resumed_from {
+            info!(
+                "Importing blocks from {} ({} files), resuming at blk{:05}.dat offset {}",
+                self.blocks_dir.display(),
+                files,
+                resumed.file,
+                resumed.offset
+            );
+        } else {
+            info!(
+                "Importing blocks from {} ({} files)",
+                self.blocks_dir.display(),
+                files
+            );
+        }
+
+        let mut report = ImportProgress {
+            phase: ImportPhase::Scanning,
+            file: start.file,
+            files,
+            blocks_scanned: 0,
+            blocks_connected: 0,
+            height: tip_height,
+            target_height: None,
+        };
+
+        // Scan every file for headers
+        let mut scanned = HashMap::new();
+        let mut end = start;
+        for file in start.file..files {
+            let offset = if file == start.file { start.offset } else { 0 };
+            let (found, offset) = self.scan_file(file, offset, &mut scanned)?;
+            debug!("Scanned {} blocks in blk{:05}.dat", found, file);
+            report.file = file;
+            report.blocks_scanned += found;
+            progress(&report);
+            end = CoreFilePosition { file, offset };
+        }
+        summary.blocks_scanned = report.blocks_scanned;
+
+        let path = best_chain(&scanned, tip_hash);
+        summary.blocks_skipped = summary.blocks_scanned - path.len() as u64;
+        let first_height = tip_height.map_or(0, |height| height + 1);
+        report.phase = ImportPhase::Connecting;
+        report.target_height = if path.is_empty() {
+            tip_height
+        } else {
+            Some(first_height + path.len() as u64 - 1)
+        };
+        progress(&report);
+
+        // Earliest position among the blocks from each index on, which is where
+        // to resume once the blocks before that index are connected
+        let mut resume_at = vec![end; path.len() + 1];
+        for i in (0..path.len()).rev() {
+            resume_at[i] = resume_at[i + 1].min(scanned[&path[i]].position);
+        }
+
+        let engine = ReorgEngine::new(&blocks, protocol, Some(storage));
+        let mut utxos = storage.utxo_cache();
+        for (i, hash) in path.iter().enumerate() {
+            let record = &scanned[hash];
+            let height = first_height + i as u64;
+            let data = self.read_block(record)?;
+            let (block, witnesses) = parse_block_from_wire(&data)?;
+            if blocks.get_block_hash(&block) != *hash {
+                return Err(anyhow!(
+                    "Block in blk{:05}.dat at offset {} does not match its header",
+                    record.position.file,
+                    record.position.offset
+                ));
+            }
+            if !engine.connect_block(&block, &witnesses, height, &mut utxos)? {
+                return Err(anyhow!(
+                    "Block {} at height {} failed validation",
+                    hex::encode(hash),
+                    height
+                ));
+            }
+
+            report.file = record.position.file;
+            report.blocks_connected += 1;
+            report.height = Some(height);
+            summary.blocks_connected += 1;
+            summary.tip_height = Some(height);
+            if report.blocks_connected % CHECKPOINT_INTERVAL == 0 {
+                store_checkpoint(storage, resume_at[i + 1], hash)?;
+            }
+            if report.blocks_connected % self.progress_interval == 0 {
+                progress(&report);
+            }
+        }
+
+        storage.flush()?;
+        if let Some(tip_hash) = storage.chain().get_tip_hash()? {
+            store_checkpoint(storage, resume_at[path.len()], &tip_hash)?;
+        }
+        progress(&report);
+        info!(
+            "Imported {} blocks ({} scanned), tip height {:?}",
+            summary.blocks_connected, summary.blocks_scanned, summary.tip_height
+        );
+        Ok(summary)
+    }
+}
+
+/// The stored import checkpoint, if any
+pub fn load_checkpoint(storage: &Storage) -> Result<Option<ImportCheckpoint>> {
+    match storage.open_tree(BLOCK_IMPORT_TREE)?.get(CHECKPOINT_KEY)? {
+        Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+        None => Ok(None),
+    }
+}
+
+fn store_checkpoint(storage: &Storage, position: CoreFilePosition, tip_hash: &Hash) -> Result<()> {
+    let checkpoint = ImportCheckpoint {
+        position,
+        tip_hash: *tip_hash,
+    };
+    storage
+        .open_tree(BLOCK_IMPORT_TREE)?
+        .insert(CHECKPOINT_KEY, &bincode::serialize(&checkpoint)?)
+}
+
+/// Hashes of the most-work chain of scanned blocks extending `tip`, in order
+///
+/// Without a tip the chain starts at a block whose parent is the zero hash.
+/// Ties go to the block that was stored first.
+fn best_chain(scanned: &HashMap<Hash, ScannedBlock>, tip: Option<Hash>) -> Vec<Hash> {
+    let mut children: HashMap<Hash, Vec<Hash>> = HashMap::new();
+    for (hash, block) in scanned {
+        children.entry(block.prev_hash).or_default().push(*hash);
+    }
+    for siblings in children.values_mut() {
+        siblings.sort_by_key(|hash| scanned[hash].position);
+    }
+
+    let root = tip.unwrap_or([0u8; 32]);
+    let mut best: Option<(ChainWork, Hash)> = None;
+    let mut stack: Vec<(Hash, ChainWork)> = children
+        .get(&root)
+        .into_iter()
+        .flatten()
+        .rev()
+        .map(|hash| (*hash, ChainWork::from_bits(scanned[hash].bits)))
+        .collect();
+    while let Some((hash, work)) = stack.pop() {
+        if best.map_or(true, |(best_work, _)| work > best_work) {
+            best = Some((work, hash));
+        }
+        for child in children.get(&hash).into_iter().flatten().rev() {
+            stack.push((*child, work + ChainWork::from_bits(scanned[child].bits)));
+        }
+    }
+
+    let mut path = Vec::new();
+    let mut current = best.map(|(_, hash)| hash);
+    while let Some(hash) = current {
+        if hash == root {
+            break;
+        }
+        path.push(hash);
+        current = scanned.get(&hash).map(|block| block.prev_hash);
+    }
+    path.reverse();
+    path
+}
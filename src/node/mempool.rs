@@ -3,17 +3,33 @@
 //! Handles transaction mempool management, validation, and relay.
 
 use crate::config::{MempoolPolicyConfig, RbfConfig};
-use anyhow::Result;
-use bllvm_protocol::mempool::{has_conflict_with_tx, replacement_checks, signals_rbf, Mempool};
-use bllvm_protocol::{Hash, OutPoint, Transaction, UtxoSet};
-use std::cmp::Reverse;
-use std::collections::{BTreeMap, HashMap, HashSet};
-use std::sync::RwLock;
+use crate::network::package_relay::{
+    PackageError, PackageRejectReason, PackageRelay, TransactionPackage,
+};
+use crate::node::cluster::{
+    improves_diagram, Chunk, ClusterLimits, ClusterMempool, ClusterTx, FeeFrac,
+};
+use crate::node::fee_estimation::FeeEstimator;
+use crate::node::truc::{
+    check_ephemeral_outputs, check_ephemeral_spends, check_truc, ephemeral_anchors,
+    PolicyViolation, TrucLimits, UnconfirmedParent,
+};
+use anyhow::{bail, Result};
+use bllvm_protocol::mempool::{has_conflict_with_tx, signals_rbf, Mempool};
+use bllvm_protocol::{Block, Hash, OutPoint, Transaction, UtxoSet};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::{debug, info, warn};
 
 /// RBF tracking information for a transaction
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct RbfTracking {
     /// Number of times this transaction has been replaced
     replacement_count: u32,
@@ -23,24 +39,318 @@ struct RbfTracking {
     original_tx_hash: Hash,
 }
 
+/// Most mempool transactions a replacement may evict (BIP125 rule 5)
+const MAX_REPLACEMENT_CANDIDATES: usize = 100;
+
+/// Blocks a coinbase output must wait before it can be spent
+const COINBASE_MATURITY: u64 = 100;
+
+/// Lock times below this are block heights, above it Unix times
+const LOCKTIME_THRESHOLD: u64 = 500_000_000;
+
+/// Input sequence that opts out of lock time
+const SEQUENCE_FINAL: u64 = 0xffff_ffff;
+
+/// Saved mempool file magic and format version
+///
+/// Version 1 was an unversioned list of raw transactions.
+const MEMPOOL_FILE_MAGIC: &[u8; 4] = b"BLMP";
+const MEMPOOL_FILE_VERSION: u32 = 2;
+
+/// A mempool entry as saved to disk
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedEntry {
+    /// Serialized transaction
+    tx: Vec<u8>,
+    /// When the transaction entered the mempool (Unix seconds)
+    entry_time: u64,
+    /// prioritisetransaction fee delta in satoshis
+    fee_delta: i64,
+    rbf: Option<RbfTracking>,
+}
+
+/// Saved mempool contents, parents before children
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedMempool {
+    entries: Vec<SavedEntry>,
+    /// Fee deltas of transactions not in the mempool
+    fee_deltas: Vec<(Hash, i64)>,
+    /// Transactions not yet relayed to any peer
+    unbroadcast: Option<Vec<Hash>>,
+}
+
+/// Outcome of loading a saved mempool
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MempoolLoadStats {
+    /// Entries added to the mempool
+    pub accepted: usize,
+    /// Entries that no longer validate against the current tip
+    pub failed: usize,
+    /// Entries older than the mempool expiry
+    pub expired: usize,
+    /// Entries already in the mempool
+    pub already_there: usize,
+    /// Accepted entries still waiting to be relayed
+    pub unbroadcast: usize,
+}
+
+/// Why a transaction left the mempool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MempoolRemovalReason {
+    /// Included in a connected block
+    Block,
+    /// Spends an output also spent by a connected block, or descends from
+    /// such a transaction
+    Conflict,
+    /// No longer valid at the tip after blocks were disconnected: not final,
+    /// spending an immature coinbase or spending an output that is gone
+    Reorg,
+}
+
+impl MempoolRemovalReason {
+    /// Name used in notifications
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MempoolRemovalReason::Block => "block",
+            MempoolRemovalReason::Conflict => "conflict",
+            MempoolRemovalReason::Reorg => "reorg",
+        }
+    }
+}
+
+/// Mempool changes made to follow the chain tip
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MempoolUpdate {
+    /// Transactions from disconnected blocks put back into the mempool
+    pub added: Vec<Hash>,
+    /// Transactions removed, with the reason
+    pub removed: Vec<(Hash, MempoolRemovalReason)>,
+}
+
+/// Mempool transactions a replacement evicts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Replacement {
+    /// Mempool transactions spending the same outputs
+    pub conflicts: Vec<Hash>,
+    /// Everything evicted: the conflicts and all their descendants
+    pub replaced: Vec<Hash>,
+}
+
+/// Outcome for one transaction of a package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageTxResult {
+    pub txid: Hash,
+    /// Size in vbytes
+    pub vsize: u64,
+    /// Fee in satoshis, once its inputs are resolved
+    pub fee: Option<u64>,
+    /// Whether the transaction is (or would be) in the mempool
+    pub allowed: bool,
+    /// Whether it was in the mempool before the package
+    pub already_in_mempool: bool,
+    pub reject_reason: Option<String>,
+}
+
+/// Outcome of evaluating a package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageAcceptance {
+    /// One result per package transaction, in package order
+    pub tx_results: Vec<PackageTxResult>,
+    /// Fee rate (sat/kvB) of the transactions accepted as a package rather
+    /// than individually, if any
+    pub package_feerate: Option<u64>,
+    /// Mempool transactions evicted by the package
+    pub replaced: Vec<Hash>,
+    /// Why the whole package was rejected
+    pub reject_reason: Option<String>,
+}
+
+impl PackageAcceptance {
+    fn accepted(
+        tx_results: Vec<PackageTxResult>,
+        package_feerate: Option<u64>,
+        replaced: Vec<Hash>,
+    ) -> Self {
+        Self {
+            tx_results,
+            package_feerate,
+            replaced,
+            reject_reason: None,
+        }
+    }
+
+    fn rejected(mut tx_results: Vec<PackageTxResult>, reason: &str) -> Self {
+        for result in tx_results.iter_mut() {
+            result.allowed = result.already_in_mempool;
+        }
+        Self {
+            tx_results,
+            package_feerate: None,
+            replaced: Vec::new(),
+            reject_reason: Some(reason.to_string()),
+        }
+    }
+
+    /// Whether every transaction of the package is in the mempool
+    pub fn all_allowed(&self) -> bool {
+        self.tx_results.iter().all(|result| result.allowed)
+    }
+}
+
+/// Largest orphan transaction kept, in bytes
+const MAX_ORPHAN_TX_SIZE: u64 = 100_000;
+
+/// Outcome of submitting a transaction whose parents may be unknown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxSubmission {
+    /// Accepted, along with the orphans it let in
+    Accepted { reprocessed: Vec<Hash> },
+    /// Kept in the orphan pool until these parents arrive
+    Orphan { missing_parents: Vec<Hash> },
+    /// Rejected, by the mempool or the orphan pool limits
+    Rejected,
+}
+
+/// Orphan transaction with the peer that announced it
+#[derive(Debug, Clone)]
+struct OrphanEntry {
+    tx: Transaction,
+    peer: Option<SocketAddr>,
+    size: u64,
+    expires_at: u64,
+}
+
+/// Transactions spending outputs that are neither confirmed nor in the
+/// mempool, kept while their parents are fetched
+///
+/// The pool is bounded in total and per peer; when full, a random orphan is
+/// evicted, so a peer cannot choose which orphans survive.
+#[derive(Debug, Default)]
+struct OrphanPool {
+    orphans: HashMap<Hash, OrphanEntry>,
+    /// Orphans by the txid of each parent they spend
+    by_parent: HashMap<Hash, HashSet<Hash>>,
+    bytes: u64,
+}
+
+impl OrphanPool {
+    fn add(
+        &mut self,
+        txid: Hash,
+        entry: OrphanEntry,
+        policy: &MempoolPolicyConfig,
+        now: u64,
+    ) -> bool {
+        if self.orphans.contains_key(&txid) || entry.size > MAX_ORPHAN_TX_SIZE {
+            return false;
+        }
+        self.remove_expired(now);
+
+        if let Some(peer) = entry.peer {
+            if policy.max_orphans_per_peer == 0 {
+                return false;
+            }
+            let from_peer: Vec<Hash> = self
+                .orphans
+                .iter()
+                .filter(|(_, orphan)| orphan.peer == Some(peer))
+                .map(|(hash, _)| *hash)
+                .collect();
+            if from_peer.len() >= policy.max_orphans_per_peer {
+                self.evict_random(&from_peer);
+            }
+        }
+
+        for input in &entry.tx.inputs {
+            self.by_parent
+                .entry(input.prevout.hash)
+                .or_default()
+                .insert(txid);
+        }
+        self.bytes += entry.size;
+        self.orphans.insert(txid, entry);
+
+        while self.orphans.len() > policy.max_orphan_txs {
+            let all: Vec<Hash> = self.orphans.keys().copied().collect();
+            self.evict_random(&all);
+        }
+        self.orphans.contains_key(&txid)
+    }
+
+    fn remove(&mut self, txid: &Hash) -> Option<Transaction> {
+        let entry = self.orphans.remove(txid)?;
+        for input in &entry.tx.inputs {
+            if let Some(children) = self.by_parent.get_mut(&input.prevout.hash) {
+                children.remove(txid);
+                if children.is_empty() {
+                    self.by_parent.remove(&input.prevout.hash);
+                }
+            }
+        }
+        self.bytes -= entry.size;
+        Some(entry.tx)
+    }
+
+    fn evict_random(&mut self, candidates: &[Hash]) {
+        if let Some(txid) = candidates.choose(&mut rand::thread_rng()) {
+            debug!("Evicting orphan transaction {}", hex::encode(txid));
+            self.remove(txid);
+        }
+    }
+
+    fn remove_where(&mut self, predicate: impl Fn(&OrphanEntry) -> bool) -> usize {
+        let matching: Vec<Hash> = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| predicate(orphan))
+            .map(|(hash, _)| *hash)
+            .collect();
+        for txid in &matching {
+            self.remove(txid);
+        }
+        matching.len()
+    }
+
+    fn remove_expired(&mut self, now: u64) -> usize {
+        self.remove_where(|orphan| orphan.expires_at <= now)
+    }
+
+    fn children_of(&self, parent: &Hash) -> Vec<Hash> {
+        let mut children: Vec<Hash> = self
+            .by_parent
+            .get(parent)
+            .map(|children| children.iter().copied().collect())
+            .unwrap_or_default();
+        children.sort();
+        children
+    }
+
+    fn clear(&mut self) {
+        self.orphans.clear();
+        self.by_parent.clear();
+        self.bytes = 0;
+    }
+}
+
 /// Mempool manager
 pub struct MempoolManager {
     /// Transaction mempool - stores full transactions by hash
-    pub(crate) transactions: HashMap<Hash, Transaction>,
+    /// Uses RwLock for interior mutability so the node, network and RPC can
+    /// all change the mempool through the shared Arc
+    pub(crate) transactions: RwLock<HashMap<Hash, Transaction>>,
     /// Legacy mempool (HashSet of hashes) for compatibility
     #[allow(dead_code)]
-    mempool: Mempool,
-    #[allow(dead_code)]
-    utxo_set: UtxoSet,
+    mempool: RwLock<Mempool>,
     /// Track spent outputs to detect conflicts
-    pub(crate) spent_outputs: HashSet<OutPoint>,
-    /// Sorted index by fee rate (descending) - Reverse<u64> for descending order
-    /// Maps fee_rate -> Vec<Hash> (multiple transactions can have same fee rate)
-    /// Uses RwLock for interior mutability to allow &self methods
-    fee_index: RwLock<BTreeMap<Reverse<u64>, Vec<Hash>>>,
-    /// Cache fee rates per transaction hash
+    /// Uses RwLock for interior mutability
+    pub(crate) spent_outputs: RwLock<HashSet<OutPoint>>,
+    /// Held while a change is checked and applied, so concurrent changes
+    /// don't interleave. Only the public entry points take it.
+    update_lock: Mutex<()>,
+    /// Dependency graph grouped into linearized clusters, with each
+    /// transaction's fee and size
     /// Uses RwLock for interior mutability to allow &self methods
-    fee_cache: RwLock<HashMap<Hash, u64>>,
+    clusters: RwLock<ClusterMempool>,
     /// RBF configuration (optional)
     /// Uses RwLock for interior mutability to allow setting config after Arc sharing
     rbf_config: RwLock<Option<RbfConfig>>,
@@ -53,55 +363,63 @@ pub struct MempoolManager {
     /// Transaction timestamps: when each transaction was added
     /// Uses RwLock for interior mutability
     tx_timestamps: RwLock<HashMap<Hash, u64>>,
-    /// Transaction dependency graph: child -> parent relationships
-    /// Maps transaction hash to set of parent transaction hashes (transactions it depends on)
-    /// Uses RwLock for interior mutability
-    tx_dependencies: RwLock<HashMap<Hash, HashSet<Hash>>>,
-    /// Reverse dependency graph: parent -> children relationships
-    /// Maps transaction hash to set of child transaction hashes (transactions that depend on it)
-    /// Uses RwLock for interior mutability
-    tx_descendants: RwLock<HashMap<Hash, HashSet<Hash>>>,
     /// UTXO set hash for change detection (optimization: only recalculate when UTXO set changes)
     /// Uses RwLock for interior mutability
     utxo_set_hash: RwLock<Option<u64>>,
+    /// Orphan transactions waiting for their parents
+    /// Uses RwLock for interior mutability so peers' orphans can be kept
+    /// when MempoolManager is in an Arc
+    orphans: RwLock<OrphanPool>,
+    /// Fee estimator, told about transactions entering and leaving the
+    /// mempool; shared so the node can feed it connected blocks
+    fee_estimator: Arc<FeeEstimator>,
+    /// prioritisetransaction fee deltas, kept whether or not the
+    /// transaction is in the mempool
+    /// Uses RwLock for interior mutability
+    fee_deltas: RwLock<HashMap<Hash, i64>>,
+    /// Mempool transactions not yet relayed to any peer
+    /// Uses RwLock for interior mutability
+    unbroadcast: RwLock<HashSet<Hash>>,
 }
 
 impl MempoolManager {
     /// Create a new mempool manager
     pub fn new() -> Self {
         Self {
-            transactions: HashMap::new(),
-            mempool: Mempool::new(),
-            utxo_set: HashMap::new(),
-            spent_outputs: HashSet::new(),
-            fee_index: RwLock::new(BTreeMap::new()),
-            fee_cache: RwLock::new(HashMap::new()),
+            transactions: RwLock::new(HashMap::new()),
+            mempool: RwLock::new(Mempool::new()),
+            spent_outputs: RwLock::new(HashSet::new()),
+            update_lock: Mutex::new(()),
+            clusters: RwLock::new(ClusterMempool::new()),
             rbf_config: RwLock::new(None),
             policy_config: RwLock::new(None),
             rbf_tracking: RwLock::new(HashMap::new()),
             tx_timestamps: RwLock::new(HashMap::new()),
-            tx_dependencies: RwLock::new(HashMap::new()),
-            tx_descendants: RwLock::new(HashMap::new()),
             utxo_set_hash: RwLock::new(None),
+            orphans: RwLock::new(OrphanPool::default()),
+            fee_estimator: Arc::new(FeeEstimator::new()),
+            fee_deltas: RwLock::new(HashMap::new()),
+            unbroadcast: RwLock::new(HashSet::new()),
         }
     }
 
     /// Create a new mempool manager with RBF configuration
     pub fn with_rbf_config(rbf_config: Option<RbfConfig>) -> Self {
         Self {
-            transactions: HashMap::new(),
-            mempool: Mempool::new(),
-            utxo_set: HashMap::new(),
-            spent_outputs: HashSet::new(),
-            fee_index: RwLock::new(BTreeMap::new()),
-            fee_cache: RwLock::new(HashMap::new()),
+            transactions: RwLock::new(HashMap::new()),
+            mempool: RwLock::new(Mempool::new()),
+            spent_outputs: RwLock::new(HashSet::new()),
+            update_lock: Mutex::new(()),
+            clusters: RwLock::new(ClusterMempool::new()),
             rbf_config: RwLock::new(rbf_config),
             policy_config: RwLock::new(None),
             rbf_tracking: RwLock::new(HashMap::new()),
             tx_timestamps: RwLock::new(HashMap::new()),
-            tx_dependencies: RwLock::new(HashMap::new()),
-            tx_descendants: RwLock::new(HashMap::new()),
             utxo_set_hash: RwLock::new(None),
+            orphans: RwLock::new(OrphanPool::default()),
+            fee_estimator: Arc::new(FeeEstimator::new()),
+            fee_deltas: RwLock::new(HashMap::new()),
+            unbroadcast: RwLock::new(HashSet::new()),
         }
     }
 
@@ -187,6 +505,15 @@ impl MempoolManager {
 
     /// Clean up old transactions
     async fn cleanup_old_transactions(&mut self) -> Result<()> {
+        let expired = self
+            .orphans
+            .write()
+            .unwrap()
+            .remove_expired(Self::current_timestamp());
+        if expired > 0 {
+            debug!("Removed {} expired orphan transactions", expired);
+        }
+
         // Remove transactions that are too old
         let expiry_time = {
             if let Some(ref policy) = *self.policy_config.read().unwrap() {
@@ -199,7 +526,7 @@ impl MempoolManager {
 
         let current_time = Self::current_timestamp();
         // Optimization: Pre-allocate with estimated capacity
-        let estimated_removals = self.transactions.len() / 100; // Estimate ~1% will expire
+        let estimated_removals = self.size() / 100; // Estimate ~1% will expire
         let mut to_remove = Vec::with_capacity(estimated_removals);
 
         {
@@ -213,7 +540,7 @@ impl MempoolManager {
 
         for hash in to_remove {
             debug!("Removing expired transaction {}", hex::encode(hash));
-            self.remove_transaction(&hash);
+            self.remove_entry(&hash);
         }
 
         // Check mempool size limits and evict if necessary
@@ -231,7 +558,7 @@ impl MempoolManager {
 
         // Calculate current mempool size
         let current_size_mb = self.calculate_mempool_size_mb();
-        let current_tx_count = self.transactions.len();
+        let current_tx_count = self.size();
 
         // Check if we need to evict
         let needs_eviction =
@@ -282,6 +609,8 @@ impl MempoolManager {
 
         let total_bytes: usize = self
             .transactions
+            .read()
+            .unwrap()
             .values()
             .map(|tx| serialize_transaction(tx).len())
             .sum();
@@ -290,60 +619,18 @@ impl MempoolManager {
         (total_bytes as u64) / 1_048_576
     }
 
-    /// Evict transactions with lowest fee rate
+    /// Evict the lowest fee rate chunks first
+    ///
+    /// Chunks are evicted whole, so a low-fee parent kept alive by a high-fee
+    /// child is valued as the pair.
     async fn evict_lowest_fee_rate(
         &mut self,
         target_size_mb: u64,
         target_tx_count: usize,
     ) -> Result<()> {
-        use bllvm_protocol::serialization::transaction::serialize_transaction;
-
-        // Get all transactions sorted by fee rate (ascending - lowest first)
-        let mut tx_fee_rates: Vec<(Hash, u64, usize)> = self
-            .transactions
-            .iter()
-            .map(|(hash, tx)| {
-                let fee_rate = self
-                    .fee_cache
-                    .read()
-                    .unwrap()
-                    .get(hash)
-                    .copied()
-                    .unwrap_or(0);
-                let size = serialize_transaction(tx).len();
-                (*hash, fee_rate, size)
-            })
-            .collect();
-
-        // Sort by fee rate (ascending) - lowest fee rate first
-        tx_fee_rates.sort_by_key(|(_, fee_rate, _)| *fee_rate);
-
-        // Evict until we're under limits
-        let mut current_size_mb = self.calculate_mempool_size_mb();
-        let mut current_tx_count = self.transactions.len();
-
-        for (hash, _fee_rate, size) in tx_fee_rates {
-            if current_size_mb <= target_size_mb && current_tx_count <= target_tx_count {
-                break;
-            }
-
-            // Don't evict if it has descendants (would orphan them)
-            let has_descendants = {
-                let descendants = self.tx_descendants.read().unwrap();
-                descendants
-                    .get(&hash)
-                    .map(|d| !d.is_empty())
-                    .unwrap_or(false)
-            };
-
-            if !has_descendants {
-                debug!("Evicting low fee rate transaction {}", hex::encode(hash));
-                self.remove_transaction(&hash);
-                current_size_mb = current_size_mb.saturating_sub((size as u64) / 1_048_576);
-                current_tx_count -= 1;
-            }
-        }
-
+        self.evict_chunks(target_size_mb, target_tx_count, |clusters, _| {
+            clusters.worst_chunk().cloned()
+        });
         Ok(())
     }
 
@@ -359,6 +646,8 @@ impl MempoolManager {
         let mut tx_ages: Vec<(Hash, u64, usize)> = {
             let timestamps = self.tx_timestamps.read().unwrap();
             self.transactions
+                .read()
+                .unwrap()
                 .iter()
                 .filter_map(|(hash, tx)| {
                     timestamps.get(hash).map(|&timestamp| {
@@ -374,7 +663,7 @@ impl MempoolManager {
 
         // Evict until we're under limits
         let mut current_size_mb = self.calculate_mempool_size_mb();
-        let mut current_tx_count = self.transactions.len();
+        let mut current_tx_count = self.size();
 
         for (hash, _timestamp, size) in tx_ages {
             if current_size_mb <= target_size_mb && current_tx_count <= target_tx_count {
@@ -382,17 +671,9 @@ impl MempoolManager {
             }
 
             // Don't evict if it has descendants
-            let has_descendants = {
-                let descendants = self.tx_descendants.read().unwrap();
-                descendants
-                    .get(&hash)
-                    .map(|d| !d.is_empty())
-                    .unwrap_or(false)
-            };
-
-            if !has_descendants {
+            if !self.has_descendants(&hash) {
                 debug!("Evicting old transaction {}", hex::encode(hash));
-                self.remove_transaction(&hash);
+                self.remove_entry(&hash);
                 current_size_mb = current_size_mb.saturating_sub((size as u64) / 1_048_576);
                 current_tx_count -= 1;
             }
@@ -412,6 +693,8 @@ impl MempoolManager {
         // Get all transactions sorted by size (descending - largest first)
         let mut tx_sizes: Vec<(Hash, usize)> = self
             .transactions
+            .read()
+            .unwrap()
             .iter()
             .map(|(hash, tx)| {
                 let size = serialize_transaction(tx).len();
@@ -424,7 +707,7 @@ impl MempoolManager {
 
         // Evict until we're under limits
         let mut current_size_mb = self.calculate_mempool_size_mb();
-        let mut current_tx_count = self.transactions.len();
+        let mut current_tx_count = self.size();
 
         for (hash, size) in tx_sizes {
             if current_size_mb <= target_size_mb && current_tx_count <= target_tx_count {
@@ -432,21 +715,13 @@ impl MempoolManager {
             }
 
             // Don't evict if it has descendants
-            let has_descendants = {
-                let descendants = self.tx_descendants.read().unwrap();
-                descendants
-                    .get(&hash)
-                    .map(|d| !d.is_empty())
-                    .unwrap_or(false)
-            };
-
-            if !has_descendants {
+            if !self.has_descendants(&hash) {
                 debug!(
                     "Evicting large transaction {} ({} bytes)",
                     hex::encode(hash),
                     size
                 );
-                self.remove_transaction(&hash);
+                self.remove_entry(&hash);
                 current_size_mb = current_size_mb.saturating_sub((size as u64) / 1_048_576);
                 current_tx_count -= 1;
             }
@@ -456,82 +731,41 @@ impl MempoolManager {
     }
 
     /// Evict transactions with no descendants first (safest)
+    ///
+    /// The last chunk of a cluster is exactly such a set, so this evicts the
+    /// lowest fee rate last chunk first, the same as [`Self::evict_lowest_fee_rate`].
     async fn evict_no_descendants_first(
         &mut self,
         target_size_mb: u64,
         target_tx_count: usize,
     ) -> Result<()> {
-        use bllvm_protocol::serialization::transaction::serialize_transaction;
-
-        // Get all transactions with no descendants, sorted by fee rate (lowest first)
-        let mut tx_no_descendants: Vec<(Hash, u64, usize)> = {
-            let descendants = self.tx_descendants.read().unwrap();
-            let fee_cache = self.fee_cache.read().unwrap();
-
-            self.transactions
-                .iter()
-                .filter_map(|(hash, tx)| {
-                    let has_descendants = descendants
-                        .get(hash)
-                        .map(|d| !d.is_empty())
-                        .unwrap_or(false);
-
-                    if !has_descendants {
-                        let fee_rate = fee_cache.get(hash).copied().unwrap_or(0);
-                        let size = serialize_transaction(tx).len();
-                        Some((*hash, fee_rate, size))
-                    } else {
-                        None
-                    }
-                })
-                .collect()
-        };
-
-        // Sort by fee rate (ascending) - lowest fee rate first
-        tx_no_descendants.sort_by_key(|(_, fee_rate, _)| *fee_rate);
-
-        // Evict until we're under limits
-        let mut current_size_mb = self.calculate_mempool_size_mb();
-        let mut current_tx_count = self.transactions.len();
-
-        for (hash, _fee_rate, size) in tx_no_descendants {
-            if current_size_mb <= target_size_mb && current_tx_count <= target_tx_count {
-                break;
-            }
-
-            debug!(
-                "Evicting transaction with no descendants {}",
-                hex::encode(hash)
-            );
-            self.remove_transaction(&hash);
-            current_size_mb = current_size_mb.saturating_sub((size as u64) / 1_048_576);
-            current_tx_count -= 1;
-        }
-
-        Ok(())
+        self.evict_lowest_fee_rate(target_size_mb, target_tx_count)
+            .await
     }
 
-    /// Hybrid eviction: combine fee rate and age
+    /// Hybrid eviction: combine chunk fee rate and age
     async fn evict_hybrid(&mut self, target_size_mb: u64, target_tx_count: usize) -> Result<()> {
-        use bllvm_protocol::serialization::transaction::serialize_transaction;
-
         // Calculate score: lower fee rate + older age = higher eviction priority
         let current_time = Self::current_timestamp();
-        let mut tx_scores: Vec<(Hash, u64, usize)> = {
-            let timestamps = self.tx_timestamps.read().unwrap();
-            let fee_cache = self.fee_cache.read().unwrap();
-
-            self.transactions
-                .iter()
-                .map(|(hash, tx)| {
-                    let fee_rate = fee_cache.get(hash).copied().unwrap_or(0);
-                    let age = timestamps
-                        .get(hash)
-                        .map(|&t| current_time.saturating_sub(t))
+        self.evict_chunks(target_size_mb, target_tx_count, |clusters, timestamps| {
+            clusters
+                .last_chunks()
+                .max_by_key(|chunk| {
+                    let fee_rate = chunk.feefrac.rate_per_kvb();
+                    // Age of the chunk's newest transaction
+                    let age = chunk
+                        .txids
+                        .iter()
+                        .map(|txid| {
+                            timestamps
+                                .get(txid)
+                                .map(|&t| current_time.saturating_sub(t))
+                                .unwrap_or(0)
+                        })
+                        .min()
                         .unwrap_or(0);
 
                     // Score: normalize fee rate (lower = higher score) + age weight
-                    // Use inverse fee rate (higher for lower fees) + age in seconds
                     // Normalize fee rate: use 1 / (fee_rate + 1) to avoid division by zero
                     let fee_score = if fee_rate > 0 {
                         1_000_000 / (fee_rate + 1) // Higher score for lower fee
@@ -543,52 +777,76 @@ impl MempoolManager {
                     let age_score = age / 3600;
 
                     // Combined score (higher = evict first)
-                    let score = fee_score + age_score;
-
-                    let size = serialize_transaction(tx).len();
-                    (*hash, score, size)
+                    (
+                        fee_score + age_score,
+                        std::cmp::Reverse(chunk.txids.clone()),
+                    )
                 })
-                .collect()
-        };
-
-        // Sort by score (descending) - highest score (most evictable) first
-        tx_scores.sort_by_key(|(_, score, _)| std::cmp::Reverse(*score));
+                .cloned()
+        });
+        Ok(())
+    }
 
-        // Evict until we're under limits
-        let mut current_size_mb = self.calculate_mempool_size_mb();
-        let mut current_tx_count = self.transactions.len();
+    /// Evict whole chunks until the mempool is within its limits
+    ///
+    /// `pick` chooses the next chunk from the clusters, given each
+    /// transaction's arrival time. Only the last chunk of a cluster should be
+    /// picked, so no remaining transaction loses a parent.
+    fn evict_chunks(
+        &mut self,
+        target_size_mb: u64,
+        target_tx_count: usize,
+        pick: impl Fn(&ClusterMempool, &HashMap<Hash, u64>) -> Option<Chunk>,
+    ) {
+        use bllvm_protocol::serialization::transaction::serialize_transaction;
 
-        for (hash, _score, size) in tx_scores {
-            if current_size_mb <= target_size_mb && current_tx_count <= target_tx_count {
-                break;
-            }
+        let mut total_bytes: u64 = self
+            .transactions
+            .read()
+            .unwrap()
+            .values()
+            .map(|tx| serialize_transaction(tx).len() as u64)
+            .sum();
 
-            // Don't evict if it has descendants
-            let has_descendants = {
-                let descendants = self.tx_descendants.read().unwrap();
-                descendants
-                    .get(&hash)
-                    .map(|d| !d.is_empty())
-                    .unwrap_or(false)
+        while total_bytes / 1_048_576 > target_size_mb || self.size() > target_tx_count {
+            let chunk = {
+                let clusters = self.clusters.read().unwrap();
+                let timestamps = self.tx_timestamps.read().unwrap();
+                match pick(&clusters, &timestamps) {
+                    Some(chunk) => chunk,
+                    None => break,
+                }
             };
-
-            if !has_descendants {
-                debug!(
-                    "Evicting transaction (hybrid strategy) {}",
-                    hex::encode(hash)
-                );
-                self.remove_transaction(&hash);
-                current_size_mb = current_size_mb.saturating_sub((size as u64) / 1_048_576);
-                current_tx_count -= 1;
+            debug!(
+                "Evicting chunk of {} transactions at {} sat/kvB",
+                chunk.txids.len(),
+                chunk.feefrac.rate_per_kvb()
+            );
+            // Children first, so every remaining transaction keeps its parents
+            for txid in chunk.txids.iter().rev() {
+                if let Some(tx) = self.transactions.read().unwrap().get(txid) {
+                    total_bytes =
+                        total_bytes.saturating_sub(serialize_transaction(tx).len() as u64);
+                }
+                self.remove_entry(txid);
             }
         }
+    }
 
-        Ok(())
+    /// Whether any mempool transaction spends an output of this one
+    fn has_descendants(&self, hash: &Hash) -> bool {
+        self.clusters
+            .read()
+            .unwrap()
+            .children(hash)
+            .is_some_and(|children| !children.is_empty())
     }
 
     /// Check if a transaction can replace an existing one (RBF)
     ///
-    /// This wraps the consensus layer replacement_checks with RBF mode-specific logic
+    /// Applies the same rules as [`Self::check_replacement`], with
+    /// `existing_tx` as the only conflict. If it is not in the mempool it is
+    /// evaluated as if it were.
     ///
     /// `storage` is optional - if provided, can be used for conservative mode confirmation checks
     pub fn check_rbf_replacement(
@@ -599,174 +857,164 @@ impl MempoolManager {
         storage: Option<&crate::storage::Storage>,
     ) -> Result<bool> {
         use bllvm_protocol::block::calculate_tx_id;
+        use bllvm_protocol::serialization::transaction::serialize_transaction;
 
-        let rbf_config = match self.rbf_config.read().unwrap().as_ref() {
-            Some(config) => config.clone(),
-            None => {
-                // No RBF config - use default BIP125 behavior
-                return replacement_checks(new_tx, existing_tx, utxo_set, &self.mempool)
-                    .map_err(|e| anyhow::anyhow!("RBF check failed: {}", e));
-            }
-        };
-
-        // Check if RBF is disabled
-        if matches!(rbf_config.mode, crate::config::RbfMode::Disabled) {
-            return Ok(false);
-        }
-
-        // Use the cloned config for the rest of the function
-        let rbf_config = &rbf_config;
-
-        // Check if existing transaction signals RBF
-        if !signals_rbf(existing_tx) {
+        // Must spend at least one input of the existing transaction
+        if !has_conflict_with_tx(new_tx, existing_tx) {
             return Ok(false);
         }
 
-        let existing_tx_hash = calculate_tx_id(existing_tx);
-        let _new_tx_hash = calculate_tx_id(new_tx);
+        self.update_fees(utxo_set);
+        let existing_hash = calculate_tx_id(existing_tx);
+        let guard = self.clusters.read().unwrap();
+        let with_existing;
+        let clusters = if self.contains(&existing_hash) {
+            &*guard
+        } else {
+            let mut clusters = guard.clone();
+            clusters.insert(
+                existing_hash,
+                FeeFrac::new(
+                    self.calculate_transaction_fee(existing_tx, utxo_set),
+                    serialize_transaction(existing_tx).len() as u64,
+                ),
+                self.mempool_parents(existing_tx),
+            );
+            with_existing = clusters;
+            &with_existing
+        };
 
-        // Check replacement count limit
-        if let Some(tracking) = self.rbf_tracking.read().unwrap().get(&existing_tx_hash) {
-            if tracking.replacement_count >= rbf_config.max_replacements_per_tx {
+        let conflicts = HashSet::from([existing_hash]);
+        match self.evaluate_replacement(clusters, new_tx, &conflicts, utxo_set, storage) {
+            Ok(_) => Ok(true),
+            Err(reason) => {
                 warn!(
-                    "RBF replacement rejected: max replacements ({}) exceeded for tx {}",
-                    rbf_config.max_replacements_per_tx,
-                    hex::encode(existing_tx_hash)
+                    "RBF replacement of tx {} rejected: {}",
+                    hex::encode(existing_hash),
+                    reason
                 );
-                return Ok(false);
+                Ok(false)
             }
+        }
+    }
 
-            // Check cooldown period
-            let current_time = Self::current_timestamp();
-            let time_since_last = current_time.saturating_sub(tracking.last_replacement_time);
-            if time_since_last < rbf_config.cooldown_seconds {
-                warn!(
-                    "RBF replacement rejected: cooldown period not met ({}s remaining) for tx {}",
-                    rbf_config.cooldown_seconds - time_since_last,
-                    hex::encode(existing_tx_hash)
-                );
-                return Ok(false);
-            }
+    /// Check a transaction against the replacement rules
+    ///
+    /// Finds the mempool transactions spending the same outputs and returns
+    /// everything the transaction would evict: those conflicts and all their
+    /// descendants. A transaction without conflicts evicts nothing. Otherwise
+    /// the replacement must
+    /// - be allowed by the RBF mode, and signalled by every conflict unless
+    ///   full RBF is on
+    /// - not spend an output of a transaction it evicts, nor add unconfirmed
+    ///   inputs the conflicts did not have
+    /// - evict at most 100 transactions
+    /// - pay a higher fee rate than each conflict, by `min_fee_rate_multiplier`
+    /// - pay at least the fees of everything it evicts, plus
+    ///   `incremental_relay_fee` for its own size (and at least
+    ///   `min_fee_bump_satoshis` more)
+    /// - improve the fee rate diagram of the affected clusters, if
+    ///   `fee_rate_diagram_check` is on
+    ///
+    /// Returns the reject reason if not.
+    pub fn check_replacement(
+        &self,
+        tx: &Transaction,
+        utxo_set: &UtxoSet,
+        storage: Option<&crate::storage::Storage>,
+    ) -> Result<Replacement, &'static str> {
+        let spends: HashSet<&OutPoint> = tx.inputs.iter().map(|input| &input.prevout).collect();
+        let conflicts: HashSet<Hash> = self
+            .transactions
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, existing)| {
+                existing
+                    .inputs
+                    .iter()
+                    .any(|input| spends.contains(&input.prevout))
+            })
+            .map(|(hash, _)| *hash)
+            .collect();
+        if conflicts.is_empty() {
+            return Ok(Replacement::default());
         }
 
-        // Calculate fees and fee rates using the same method as MempoolManager
-        let new_fee = self.calculate_transaction_fee(new_tx, utxo_set) as i64;
-        let existing_fee = self.calculate_transaction_fee(existing_tx, utxo_set) as i64;
+        self.update_fees(utxo_set);
+        let clusters = self.clusters.read().unwrap();
+        let replaced = self.evaluate_replacement(&clusters, tx, &conflicts, utxo_set, storage)?;
+        let mut conflicts: Vec<Hash> = conflicts.into_iter().collect();
+        conflicts.sort();
+        let mut replaced: Vec<Hash> = replaced.into_iter().collect();
+        replaced.sort();
+        Ok(Replacement {
+            conflicts,
+            replaced,
+        })
+    }
 
-        // Calculate transaction sizes (simplified - use serialization length)
+    /// Apply the replacement rules to one transaction and its `conflicts`
+    /// in `clusters`, returning everything it would evict
+    fn evaluate_replacement(
+        &self,
+        clusters: &ClusterMempool,
+        tx: &Transaction,
+        conflicts: &HashSet<Hash>,
+        utxo_set: &UtxoSet,
+        storage: Option<&crate::storage::Storage>,
+    ) -> Result<HashSet<Hash>, &'static str> {
+        use bllvm_protocol::block::calculate_tx_id;
         use bllvm_protocol::serialization::transaction::serialize_transaction;
-        let new_tx_size = serialize_transaction(new_tx).len();
-        let existing_tx_size = serialize_transaction(existing_tx).len();
-
-        if new_tx_size == 0 || existing_tx_size == 0 {
-            return Ok(false);
-        }
-
-        // Check fee rate multiplier (mode-specific)
-        let new_fee_scaled = (new_fee as u128)
-            .checked_mul(existing_tx_size as u128)
-            .ok_or_else(|| anyhow::anyhow!("Fee rate calculation overflow"))?;
-        let existing_fee_scaled = (existing_fee as u128)
-            .checked_mul(new_tx_size as u128)
-            .ok_or_else(|| anyhow::anyhow!("Fee rate calculation overflow"))?;
 
-        // Apply mode-specific multiplier
-        let required_fee_scaled =
-            (existing_fee_scaled as f64 * rbf_config.min_fee_rate_multiplier) as u128;
-        if new_fee_scaled <= required_fee_scaled {
-            warn!(
-                "RBF replacement rejected: fee rate increase insufficient (required: {:.2}x, got: {:.2}x) for tx {}",
-                rbf_config.min_fee_rate_multiplier,
-                (new_fee_scaled as f64) / (existing_fee_scaled as f64),
-                hex::encode(existing_tx_hash)
-            );
-            return Ok(false);
+        let mut replaced = HashSet::new();
+        for conflict in conflicts {
+            replaced.extend(clusters.with_descendants(conflict));
         }
 
-        // Check absolute fee bump
-        let min_fee_bump = rbf_config.min_fee_bump_satoshis as i64;
-        if new_fee <= existing_fee + min_fee_bump {
-            warn!(
-                "RBF replacement rejected: absolute fee bump insufficient (required: {} sat, got: {} sat) for tx {}",
-                min_fee_bump,
-                new_fee - existing_fee,
-                hex::encode(existing_tx_hash)
-            );
-            return Ok(false);
+        let parents: HashSet<Hash> = tx
+            .inputs
+            .iter()
+            .map(|input| input.prevout.hash)
+            .filter(|hash| clusters.feefrac(hash).is_some())
+            .collect();
+        if parents.iter().any(|parent| replaced.contains(parent)) {
+            return Err("bad-txns-spends-conflicting-tx");
         }
-
-        // Conservative mode: Check minimum confirmations
-        // Note: Transactions in mempool have 0 confirmations. This check ensures that
-        // if a transaction has been confirmed (which shouldn't be in mempool), we require
-        // it to have minimum confirmations before allowing replacement.
-        // In practice, mempool transactions will always have 0 confirmations, so this
-        // check mainly serves as a safety mechanism.
-        if matches!(rbf_config.mode, crate::config::RbfMode::Conservative)
-            && rbf_config.min_confirmations > 0
-        {
-            if let Some(storage) = storage {
-                // Check if transaction is in blockchain and has enough confirmations
-                if let Ok(Some(metadata)) = storage.transactions().get_metadata(&existing_tx_hash) {
-                    // Transaction is in a block - check confirmations
-                    let block_hash = metadata.block_hash;
-                    if let Ok(Some(block_height)) = storage.blocks().get_height_by_hash(&block_hash)
-                    {
-                        if let Ok(Some(tip_height)) = storage.chain().get_height() {
-                            let confirmations = tip_height.saturating_sub(block_height) + 1;
-                            if confirmations < rbf_config.min_confirmations as u64 {
-                                warn!(
-                                    "RBF replacement rejected: conservative mode requires {} confirmations, tx {} has {}",
-                                    rbf_config.min_confirmations,
-                                    hex::encode(existing_tx_hash),
-                                    confirmations
-                                );
-                                return Ok(false);
-                            }
-                        }
-                    }
-                }
-                // If transaction is not in blockchain (mempool only), confirmations = 0
-                // For conservative mode, we might want to reject replacements of unconfirmed transactions
-                // if min_confirmations > 0, but that would prevent all mempool RBF replacements.
-                // So we allow it - the transaction is still in mempool and can be replaced.
-            }
+        // BIP125 rule 2: unconfirmed inputs only from the conflicts' parents
+        let conflict_parents: HashSet<Hash> = conflicts
+            .iter()
+            .filter_map(|conflict| clusters.parents(conflict))
+            .flatten()
+            .copied()
+            .collect();
+        if !parents.is_subset(&conflict_parents) {
+            return Err("replacement-adds-unconfirmed");
         }
 
-        // Check conflict (must spend at least one input from existing tx)
-        if !has_conflict_with_tx(new_tx, existing_tx) {
-            return Ok(false);
-        }
-
-        // Aggressive mode: Check for package replacement support
-        // Package replacement = replacing parent + child transactions together
-        if matches!(rbf_config.mode, crate::config::RbfMode::Aggressive)
-            && rbf_config.allow_package_replacements
-        {
-            // Check if new_tx has dependencies (child transactions) that should be replaced together
-            // For now, we allow the replacement if the new transaction has higher fees
-            // Full package replacement logic would require tracking transaction packages
-            // This is a simplified implementation
-            debug!(
-                "Aggressive mode: allowing package replacement for tx {}",
-                hex::encode(existing_tx_hash)
-            );
-        }
-
-        // For the remaining BIP125 checks (new dependencies), use the consensus replacement_checks
-        // but we've already applied our mode-specific fee requirements above
-        // Note: replacement_checks will re-check fee rate, but we've already validated with our multiplier
-        // So we call it to verify the other BIP125 rules (dependencies, etc.)
-        // However, since we've already done stricter checks, if replacement_checks passes, we're good
-        let bip125_result = replacement_checks(new_tx, existing_tx, utxo_set, &self.mempool)?;
-        if !bip125_result {
-            // BIP125 check failed (likely new dependencies issue)
-            return Ok(false);
-        }
-
-        // All checks passed
-        Ok(true)
+        let txid = calculate_tx_id(tx);
+        let feefrac = FeeFrac::new(
+            self.calculate_transaction_fee(tx, utxo_set)
+                .saturating_add_signed(self.fee_delta(&txid)),
+            serialize_transaction(tx).len() as u64,
+        );
+        let policy = self
+            .policy_config
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_default();
+        self.check_replacement_rules(
+            clusters,
+            conflicts,
+            &replaced,
+            feefrac,
+            vec![(txid, ClusterTx { feefrac, parents })],
+            &policy,
+            storage,
+        )?;
+        Ok(replaced)
     }
-
     /// Check ancestor/descendant limits for a transaction
     fn check_ancestor_descendant_limits(
         &self,
@@ -778,6 +1026,7 @@ impl MempoolManager {
 
         // Calculate transaction size
         let tx_size = serialize_transaction(tx).len() as u64;
+        let transactions = transactions.read().unwrap();
 
         // Find all ancestors (transactions this tx depends on)
         // Optimization: Pre-allocate with estimated capacity (most txs have < 10 ancestors)
@@ -792,10 +1041,10 @@ impl MempoolManager {
             }
             processed.insert(current_hash);
 
-            if let Some(current_tx) = self.transactions.get(&current_hash) {
+            if let Some(current_tx) = transactions.get(&current_hash) {
                 for input in &current_tx.inputs {
                     // Find parent transaction that created this output
-                    for parent_hash in self.transactions.keys() {
+                    for parent_hash in transactions.keys() {
                         if parent_hash == &input.prevout.hash {
                             if !ancestors.contains(parent_hash) {
                                 ancestors.insert(*parent_hash);
@@ -812,7 +1061,7 @@ impl MempoolManager {
         let ancestor_count = ancestors.len() as u32;
         let ancestor_size: u64 = ancestors
             .iter()
-            .filter_map(|h| self.transactions.get(h))
+            .filter_map(|h| transactions.get(h))
             .map(|t| serialize_transaction(t).len() as u64)
             .sum();
 
@@ -850,7 +1099,7 @@ impl MempoolManager {
             }
             processed.insert(current_hash);
 
-            if let Some(current_tx) = self.transactions.get(&current_hash) {
+            if let Some(current_tx) = transactions.get(&current_hash) {
                 // Find all outputs of current tx
                 let output_outpoints: Vec<_> = (0..current_tx.outputs.len())
                     .map(|idx| OutPoint {
@@ -860,7 +1109,7 @@ impl MempoolManager {
                     .collect();
 
                 // Find transactions that spend these outputs
-                for (child_hash, child_tx) in &self.transactions {
+                for (child_hash, child_tx) in transactions.iter() {
                     for input in &child_tx.inputs {
                         if output_outpoints.contains(&input.prevout) {
                             if !descendants.contains(child_hash) {
@@ -878,7 +1127,7 @@ impl MempoolManager {
         let descendant_count = descendants.len() as u32;
         let descendant_size: u64 = descendants
             .iter()
-            .filter_map(|h| self.transactions.get(h))
+            .filter_map(|h| transactions.get(h))
             .map(|t| serialize_transaction(t).len() as u64)
             .sum();
 
@@ -893,225 +1142,1299 @@ impl MempoolManager {
             return Ok(false);
         }
 
-        if descendant_size + tx_size > policy.max_descendant_size {
-            warn!(
-                "Transaction {} exceeds max descendant size: {} > {}",
-                hex::encode(tx_hash),
-                descendant_size + tx_size,
-                policy.max_descendant_size
-            );
-            return Ok(false);
+        if descendant_size + tx_size > policy.max_descendant_size {
+            warn!(
+                "Transaction {} exceeds max descendant size: {} > {}",
+                hex::encode(tx_hash),
+                descendant_size + tx_size,
+                policy.max_descendant_size
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// Parents of a transaction that are in the mempool
+    fn mempool_parents(&self, tx: &Transaction) -> HashSet<Hash> {
+        let transactions = self.transactions.read().unwrap();
+        tx.inputs
+            .iter()
+            .map(|input| input.prevout.hash)
+            .filter(|hash| transactions.contains_key(hash))
+            .collect()
+    }
+
+    /// Add transaction to mempool
+    ///
+    /// Its fee is computed from the confirmed coins in `utxo_set` and the
+    /// outputs of its mempool parents.
+    /// Uses interior mutability so it can be called even when MempoolManager is in an Arc
+    pub async fn add_transaction(&self, tx: Transaction, utxo_set: &UtxoSet) -> Result<bool> {
+        let _update = self.update_lock.lock().unwrap();
+        self.accept_transaction(tx, utxo_set)
+    }
+
+    /// [`Self::add_transaction`], with the update lock held
+    fn accept_transaction(&self, tx: Transaction, utxo_set: &UtxoSet) -> Result<bool> {
+        debug!("Adding transaction to mempool");
+
+        use bllvm_protocol::block::calculate_tx_id;
+        use bllvm_protocol::serialization::transaction::serialize_transaction;
+        let tx_hash = calculate_tx_id(&tx);
+
+        // A transaction spending the same outputs as mempool transactions
+        // must pass the replacement rules, and evicts them with their
+        // descendants
+        // Note: Storage is not available in MempoolManager context
+        // Conservative mode confirmation checks will be skipped if storage is None
+        let replacement = match self.check_replacement(&tx, utxo_set, None) {
+            Ok(replacement) => replacement,
+            Err(reason) => {
+                debug!(
+                    "Transaction {} conflicts with the mempool: {}",
+                    hex::encode(tx_hash),
+                    reason
+                );
+                return Ok(false);
+            }
+        };
+        if !replacement.conflicts.is_empty() {
+            debug!(
+                "RBF replacement allowed, replacing {} transactions",
+                replacement.replaced.len()
+            );
+
+            // Carry the replacement history over from the most replaced conflict
+            let (replacement_count, original_hash) = {
+                let tracking = self.rbf_tracking.read().unwrap();
+                replacement
+                    .conflicts
+                    .iter()
+                    .map(|hash| match tracking.get(hash) {
+                        Some(t) => (t.replacement_count + 1, t.original_tx_hash),
+                        None => (1, *hash),
+                    })
+                    .max_by_key(|(count, _)| *count)
+                    .unwrap_or((1, tx_hash))
+            };
+
+            for hash in &replacement.replaced {
+                self.remove_entry(hash);
+            }
+
+            self.rbf_tracking.write().unwrap().insert(
+                tx_hash,
+                RbfTracking {
+                    replacement_count,
+                    last_replacement_time: Self::current_timestamp(),
+                    original_tx_hash: original_hash,
+                },
+            );
+        }
+
+        // TRUC and ephemeral anchor rules
+        let sibling = match self.check_truc_policy(&tx) {
+            Ok(sibling) => sibling,
+            Err(violation) => {
+                warn!(
+                    "Transaction {} rejected: {}",
+                    hex::encode(tx_hash),
+                    violation
+                );
+                return Ok(false);
+            }
+        };
+
+        // Check ancestor/descendant limits before adding
+        if let Some(ref policy) = *self.policy_config.read().unwrap() {
+            if !self.check_ancestor_descendant_limits(&tx, &tx_hash, policy)? {
+                warn!(
+                    "Transaction {} rejected: exceeds ancestor/descendant limits",
+                    hex::encode(tx_hash)
+                );
+                return Ok(false);
+            }
+        }
+
+        // Check cluster limits before adding
+        let parents = self.mempool_parents(&tx);
+        let size = serialize_transaction(&tx).len() as u64;
+        let limits = {
+            let policy = self.policy_config.read().unwrap();
+            let policy = policy.clone().unwrap_or_default();
+            ClusterLimits {
+                max_count: policy.max_cluster_count,
+                max_size: policy.max_cluster_size,
+            }
+        };
+        if self
+            .clusters
+            .read()
+            .unwrap()
+            .exceeds_limits(&parents, size, limits)
+        {
+            warn!(
+                "Transaction {} rejected: exceeds cluster limits",
+                hex::encode(tx_hash)
+            );
+            return Ok(false);
+        }
+        let fee = self.calculate_transaction_fee(&tx, utxo_set);
+
+        if let Some(sibling) = sibling {
+            if let Err(violation) =
+                self.check_sibling_eviction(&sibling, FeeFrac::new(fee, size), utxo_set)
+            {
+                warn!(
+                    "Transaction {} rejected: {}",
+                    hex::encode(tx_hash),
+                    violation
+                );
+                return Ok(false);
+            }
+            debug!(
+                "TRUC child {} evicts sibling {}",
+                hex::encode(tx_hash),
+                hex::encode(sibling)
+            );
+            self.remove_entry(&sibling);
+        }
+
+        self.insert_transaction(tx_hash, tx, FeeFrac::new(fee, size), parents);
+        Ok(true)
+    }
+
+    /// Check a transaction submitted on its own against the TRUC and
+    /// ephemeral anchor rules
+    ///
+    /// Returns the sibling a TRUC child would evict, if any. A transaction
+    /// with an ephemeral anchor is only accepted together with the child
+    /// spending it, through [`Self::check_package`].
+    pub fn check_truc_policy(&self, tx: &Transaction) -> Result<Option<Hash>, PolicyViolation> {
+        use bllvm_protocol::block::calculate_tx_id;
+        use bllvm_protocol::serialization::transaction::serialize_transaction;
+
+        let policy = self
+            .policy_config
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_default();
+        let tx_hash = calculate_tx_id(tx);
+        check_ephemeral_outputs(tx, None, policy.ephemeral_anchors_enabled)?;
+        if !ephemeral_anchors(tx, tx_hash).is_empty() {
+            return Err(PolicyViolation::MissingEphemeralSpends);
+        }
+
+        let clusters = self.clusters.read().unwrap();
+        let transactions = self.transactions.read().unwrap();
+        let parents = Self::unconfirmed_parents(
+            &transactions,
+            &clusters,
+            tx,
+            &tx_hash,
+            &HashMap::new(),
+            &HashSet::new(),
+        );
+        check_ephemeral_spends(tx, &parents)?;
+        if !policy.truc_enabled {
+            return Ok(None);
+        }
+        let limits = TrucLimits {
+            max_vsize: policy.truc_max_vsize,
+            child_max_vsize: policy.truc_child_max_vsize,
+        };
+        check_truc(tx, serialize_transaction(tx).len() as u64, &parents, limits)
+    }
+
+    /// Check a TRUC child pays enough to evict its sibling: more than the
+    /// sibling at a higher fee rate, plus the incremental relay fee for itself
+    fn check_sibling_eviction(
+        &self,
+        sibling: &Hash,
+        feefrac: FeeFrac,
+        utxo_set: &UtxoSet,
+    ) -> Result<(), PolicyViolation> {
+        use bllvm_protocol::serialization::transaction::serialize_transaction;
+
+        let Some(sibling_tx) = self.get_transaction(sibling) else {
+            return Ok(());
+        };
+        let sibling_feefrac = FeeFrac::new(
+            self.calculate_transaction_fee(&sibling_tx, utxo_set),
+            serialize_transaction(&sibling_tx).len() as u64,
+        );
+        let incremental_relay_fee = self
+            .policy_config
+            .read()
+            .unwrap()
+            .as_ref()
+            .map_or(1000, |policy| policy.incremental_relay_fee);
+        let relay_fee = incremental_relay_fee * feefrac.size / 1000;
+        if feefrac.fee < sibling_feefrac.fee + relay_fee
+            || feefrac.cmp_rate(&sibling_feefrac) != Ordering::Greater
+        {
+            return Err(PolicyViolation::SiblingEvictionFee);
+        }
+        Ok(())
+    }
+
+    /// Unconfirmed parents of a transaction, from the mempool or `package`
+    ///
+    /// Children in `excluded`, such as transactions about to be replaced,
+    /// are not counted as the parents' children.
+    fn unconfirmed_parents<'a>(
+        transactions: &'a HashMap<Hash, Transaction>,
+        clusters: &ClusterMempool,
+        tx: &Transaction,
+        tx_hash: &Hash,
+        package: &HashMap<Hash, &'a Transaction>,
+        excluded: &HashSet<Hash>,
+    ) -> Vec<UnconfirmedParent<'a>> {
+        let is_unconfirmed =
+            |hash: &Hash| transactions.contains_key(hash) || package.contains_key(hash);
+        let mut seen = HashSet::new();
+        tx.inputs
+            .iter()
+            .map(|input| input.prevout.hash)
+            .filter(|hash| seen.insert(*hash))
+            .filter_map(|hash| {
+                let parent = transactions
+                    .get(&hash)
+                    .or_else(|| package.get(&hash).copied())?;
+                let package_children = package
+                    .iter()
+                    .filter(|(_, child)| child.inputs.iter().any(|i| i.prevout.hash == hash))
+                    .map(|(child_hash, _)| *child_hash);
+                let mut children: Vec<Hash> = clusters
+                    .children(&hash)
+                    .into_iter()
+                    .flatten()
+                    .copied()
+                    .chain(package_children)
+                    .filter(|child| child != tx_hash && !excluded.contains(child))
+                    .collect();
+                children.sort();
+                children.dedup();
+                Some(UnconfirmedParent {
+                    txid: hash,
+                    tx: parent,
+                    has_unconfirmed_parents: parent
+                        .inputs
+                        .iter()
+                        .any(|input| is_unconfirmed(&input.prevout.hash)),
+                    children,
+                })
+            })
+            .collect()
+    }
+
+    /// Store an accepted transaction and add it to its cluster
+    fn insert_transaction(
+        &self,
+        tx_hash: Hash,
+        tx: Transaction,
+        feefrac: FeeFrac,
+        parents: HashSet<Hash>,
+    ) {
+        // Add transaction to mempool (store full transaction)
+        self.transactions
+            .write()
+            .unwrap()
+            .insert(tx_hash, tx.clone());
+        self.mempool.write().unwrap().insert(tx_hash);
+
+        // Track spent outputs
+        self.spent_outputs
+            .write()
+            .unwrap()
+            .extend(tx.inputs.iter().map(|input| input.prevout.clone()));
+
+        // Add to its cluster
+        self.clusters
+            .write()
+            .unwrap()
+            .insert(tx_hash, feefrac, parents);
+
+        // Record timestamp
+        self.tx_timestamps
+            .write()
+            .unwrap()
+            .insert(tx_hash, Self::current_timestamp());
+
+        self.fee_estimator
+            .process_transaction(tx_hash, feefrac.fee, feefrac.size);
+
+        // Fee is recalculated once the UTXO set is available
+        *self.utxo_set_hash.write().unwrap() = None;
+    }
+
+    /// Evaluate a package (a child and its unconfirmed parents, parents first)
+    /// without changing the mempool
+    ///
+    /// Parents that pay the minimum relay fee rate on their own are accepted
+    /// individually; the rest are accepted only if, together with the child,
+    /// they pay it as a package, so a low-fee parent can be bumped by its
+    /// child. A package that conflicts with mempool transactions replaces
+    /// them only if it pays for them plus the incremental relay fee and
+    /// improves the fee rate diagram of the affected clusters.
+    pub fn check_package(&self, txs: &[Transaction], utxo_set: &UtxoSet) -> PackageAcceptance {
+        use bllvm_protocol::block::calculate_tx_id;
+        use bllvm_protocol::serialization::transaction::serialize_transaction;
+
+        let txids: Vec<Hash> = txs.iter().map(calculate_tx_id).collect();
+        let mut results: Vec<PackageTxResult> = txs
+            .iter()
+            .zip(&txids)
+            .map(|(tx, txid)| PackageTxResult {
+                txid: *txid,
+                vsize: serialize_transaction(tx).len() as u64,
+                fee: None,
+                allowed: false,
+                already_in_mempool: self.contains(txid),
+                reject_reason: None,
+            })
+            .collect();
+
+        if let Err(reason) = Self::check_package_shape(txs, &txids) {
+            return PackageAcceptance::rejected(results, reason);
+        }
+
+        let policy = self
+            .policy_config
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_default();
+        let new: Vec<usize> = (0..txs.len())
+            .filter(|&i| !results[i].already_in_mempool)
+            .collect();
+        for result in results.iter_mut().filter(|r| r.already_in_mempool) {
+            result.allowed = true;
+        }
+        if new.is_empty() {
+            return PackageAcceptance::accepted(results, None, Vec::new());
+        }
+        let package: HashMap<Hash, &Transaction> =
+            new.iter().map(|&i| (txids[i], &txs[i])).collect();
+
+        // Inputs: confirmed, in the mempool, or earlier in the package, each
+        // spent once
+        let mut package_spends = HashSet::new();
+        for &i in &new {
+            let mut input_total = 0u64;
+            for input in txs[i].inputs.iter() {
+                if !package_spends.insert(input.prevout.clone()) {
+                    results[i].reject_reason = Some("bad-txns-inputs-duplicate".to_string());
+                    return PackageAcceptance::rejected(results, "conflict-in-package");
+                }
+                let value = match utxo_set.get(&input.prevout) {
+                    Some(utxo) => Some(utxo.value as u64),
+                    None => self
+                        .transactions
+                        .read()
+                        .unwrap()
+                        .get(&input.prevout.hash)
+                        .or_else(|| package.get(&input.prevout.hash).copied())
+                        .and_then(|parent| parent.outputs.get(input.prevout.index as usize))
+                        .map(|output| output.value as u64),
+                };
+                match value {
+                    Some(value) => input_total += value,
+                    None => {
+                        results[i].reject_reason =
+                            Some("bad-txns-inputs-missingorspent".to_string());
+                        return PackageAcceptance::rejected(results, "package-missing-inputs");
+                    }
+                }
+            }
+            let output_total: u64 = txs[i].outputs.iter().map(|out| out.value as u64).sum();
+            if input_total < output_total {
+                results[i].reject_reason = Some("bad-txns-in-belowout".to_string());
+                return PackageAcceptance::rejected(results, "package-invalid");
+            }
+            results[i].fee = Some(input_total - output_total);
+        }
+
+        // Mempool transactions spending the same outputs, and their descendants
+        let conflicts: HashSet<Hash> = self
+            .transactions
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, tx)| {
+                tx.inputs
+                    .iter()
+                    .any(|input| package_spends.contains(&input.prevout))
+            })
+            .map(|(hash, _)| *hash)
+            .collect();
+        self.update_fees(utxo_set);
+        let clusters = self.clusters.read().unwrap();
+        let mut replaced = HashSet::new();
+        for conflict in &conflicts {
+            replaced.extend(clusters.with_descendants(conflict));
+        }
+        let parents_of = |i: usize| -> HashSet<Hash> {
+            txs[i]
+                .inputs
+                .iter()
+                .map(|input| input.prevout.hash)
+                .filter(|hash| self.contains(hash) || package.contains_key(hash))
+                .collect()
+        };
+        for &i in &new {
+            if parents_of(i).iter().any(|parent| replaced.contains(parent)) {
+                results[i].reject_reason = Some("bad-txns-spends-conflicting-tx".to_string());
+                return PackageAcceptance::rejected(results, "package-invalid");
+            }
+        }
+
+        // TRUC and ephemeral anchor rules. Anchors must be spent within the
+        // package; sibling eviction is only done for transactions submitted
+        // on their own.
+        let truc_limits = TrucLimits {
+            max_vsize: policy.truc_max_vsize,
+            child_max_vsize: policy.truc_child_max_vsize,
+        };
+        let check_policy = |i: usize| -> Result<(), PolicyViolation> {
+            check_ephemeral_outputs(&txs[i], results[i].fee, policy.ephemeral_anchors_enabled)?;
+            if !ephemeral_anchors(&txs[i], txids[i])
+                .iter()
+                .all(|anchor| package_spends.contains(anchor))
+            {
+                return Err(PolicyViolation::MissingEphemeralSpends);
+            }
+            let transactions = self.transactions.read().unwrap();
+            let parents = Self::unconfirmed_parents(
+                &transactions,
+                &clusters,
+                &txs[i],
+                &txids[i],
+                &package,
+                &replaced,
+            );
+            check_ephemeral_spends(&txs[i], &parents)?;
+            if policy.truc_enabled
+                && check_truc(&txs[i], results[i].vsize, &parents, truc_limits)?.is_some()
+            {
+                return Err(PolicyViolation::TrucTooManyDescendants);
+            }
+            Ok(())
+        };
+        let violation = new
+            .iter()
+            .find_map(|&i| check_policy(i).err().map(|violation| (i, violation)));
+        if let Some((i, violation)) = violation {
+            results[i].reject_reason = Some(violation.reject_reason().to_string());
+            return PackageAcceptance::rejected(results, violation.reject_reason());
+        }
+
+        let feefracs: Vec<FeeFrac> = results
+            .iter()
+            .map(|result| FeeFrac::new(result.fee.unwrap_or(0), result.vsize))
+            .collect();
+        let meets_min_rate =
+            |feefrac: FeeFrac| feefrac.fee >= policy.min_relay_fee_rate * feefrac.size;
+
+        // Parents paying their own way go in alone; the rest as a package.
+        // A replacement is always evaluated as a whole.
+        let mut individual = HashSet::new();
+        if conflicts.is_empty() {
+            for &i in &new {
+                let parents_ready = parents_of(i)
+                    .iter()
+                    .all(|parent| self.contains(parent) || individual.contains(parent));
+                if parents_ready && meets_min_rate(feefracs[i]) {
+                    individual.insert(txids[i]);
+                }
+            }
+        }
+        let deferred: Vec<usize> = new
+            .iter()
+            .copied()
+            .filter(|&i| !individual.contains(&txids[i]))
+            .collect();
+        let package_feefrac = deferred
+            .iter()
+            .fold(FeeFrac::default(), |total, &i| total + feefracs[i]);
+        let package_feerate = (!deferred.is_empty()).then(|| package_feefrac.rate_per_kvb());
+        let deferred_ok = meets_min_rate(package_feefrac);
+
+        if !conflicts.is_empty() {
+            if let Err(reason) = self.check_replacement_rules(
+                &clusters,
+                &conflicts,
+                &replaced,
+                package_feefrac,
+                new.iter()
+                    .map(|&i| {
+                        (
+                            txids[i],
+                            ClusterTx {
+                                feefrac: feefracs[i],
+                                parents: parents_of(i),
+                            },
+                        )
+                    })
+                    .collect(),
+                &policy,
+                None,
+            ) {
+                return PackageAcceptance::rejected(results, reason);
+            }
+            if !deferred_ok {
+                return PackageAcceptance::rejected(results, "min relay fee not met");
+            }
+        }
+
+        // Cluster limits, with the replaced transactions gone
+        let limits = ClusterLimits {
+            max_count: policy.max_cluster_count,
+            max_size: policy.max_cluster_size,
+        };
+        let mut simulated = clusters.clone();
+        drop(clusters);
+        for txid in &replaced {
+            simulated.remove(txid);
+        }
+        for &i in &new {
+            let admitted = individual.contains(&txids[i]) || deferred_ok;
+            if !admitted {
+                results[i].reject_reason = Some("min relay fee not met".to_string());
+                continue;
+            }
+            let parents = parents_of(i);
+            if simulated.exceeds_limits(&parents, results[i].vsize, limits) {
+                results[i].reject_reason = Some("too-large-cluster".to_string());
+                return PackageAcceptance::rejected(results, "too-large-cluster");
+            }
+            simulated.insert(txids[i], feefracs[i], parents);
+            results[i].allowed = true;
+        }
+
+        let mut replaced: Vec<Hash> = replaced.into_iter().collect();
+        replaced.sort();
+        PackageAcceptance::accepted(results, package_feerate, replaced)
+    }
+
+    /// Add a package to the mempool
+    ///
+    /// See [`Self::check_package`]. Replaced transactions are removed along
+    /// with their descendants; transactions already in the mempool are left
+    /// as they are.
+    /// Uses interior mutability so it can be called even when MempoolManager is in an Arc
+    pub async fn add_package(
+        &self,
+        txs: Vec<Transaction>,
+        utxo_set: &UtxoSet,
+    ) -> Result<PackageAcceptance> {
+        let _update = self.update_lock.lock().unwrap();
+        let acceptance = self.check_package(&txs, utxo_set);
+        if acceptance.reject_reason.is_some() {
+            debug!(
+                "Package rejected: {}",
+                acceptance.reject_reason.as_deref().unwrap_or_default()
+            );
+            return Ok(acceptance);
+        }
+
+        for hash in &acceptance.replaced {
+            debug!("Package replaces transaction {}", hex::encode(hash));
+            self.remove_entry(hash);
+        }
+        for (tx, result) in txs.into_iter().zip(&acceptance.tx_results) {
+            if !result.allowed || result.already_in_mempool {
+                continue;
+            }
+            let parents = self.mempool_parents(&tx);
+            let feefrac = FeeFrac::new(result.fee.unwrap_or(0), result.vsize);
+            self.insert_transaction(result.txid, tx, feefrac, parents);
+        }
+        Ok(acceptance)
+    }
+
+    /// Check a package is a child with its parents, sorted and within limits
+    fn check_package_shape(txs: &[Transaction], txids: &[Hash]) -> Result<(), &'static str> {
+        let package = TransactionPackage::new(txs.to_vec()).map_err(|e| match e {
+            PackageError::InvalidOrder => "package-not-sorted",
+            _ => "package-invalid",
+        })?;
+        PackageRelay::new()
+            .validate_package(&package)
+            .map_err(|reason| match reason {
+                PackageRejectReason::TooManyTransactions => "package-too-many-transactions",
+                PackageRejectReason::WeightExceedsLimit => "package-too-large",
+                PackageRejectReason::DuplicateTransactions => "package-contains-duplicates",
+                PackageRejectReason::InvalidOrder => "package-not-sorted",
+                _ => "package-invalid",
+            })?;
+
+        // Every transaction but the last is spent by the last
+        if let Some((child, parents)) = txs.split_last() {
+            let spent: HashSet<Hash> = child.inputs.iter().map(|i| i.prevout.hash).collect();
+            if !txids[..parents.len()]
+                .iter()
+                .all(|txid| spent.contains(txid))
+            {
+                return Err("package-not-child-with-parents");
+            }
+        }
+        Ok(())
+    }
+
+    /// The replacement rules shared by single transactions and packages
+    ///
+    /// `feefrac` is the fee and size of the replacing transaction or package
+    /// and `replacements` its transactions, for the diagram comparison. See
+    /// [`Self::check_replacement`] for the rules.
+    #[allow(clippy::too_many_arguments)]
+    fn check_replacement_rules(
+        &self,
+        clusters: &ClusterMempool,
+        conflicts: &HashSet<Hash>,
+        replaced: &HashSet<Hash>,
+        feefrac: FeeFrac,
+        replacements: Vec<(Hash, ClusterTx)>,
+        policy: &MempoolPolicyConfig,
+        storage: Option<&crate::storage::Storage>,
+    ) -> Result<(), &'static str> {
+        let rbf_config = self.rbf_config.read().unwrap().clone().unwrap_or_default();
+        if matches!(rbf_config.mode, crate::config::RbfMode::Disabled) {
+            return Err("txn-mempool-conflict");
+        }
+        if !rbf_config.full_rbf
+            && !conflicts.iter().all(|hash| {
+                self.get_transaction(hash)
+                    .is_some_and(|tx| signals_rbf(&tx))
+            })
+        {
+            return Err("txn-mempool-conflict");
+        }
+
+        // Replacement count and cooldown of each conflict
+        {
+            let tracking = self.rbf_tracking.read().unwrap();
+            let now = Self::current_timestamp();
+            for conflict in conflicts {
+                let Some(tracking) = tracking.get(conflict) else {
+                    continue;
+                };
+                if tracking.replacement_count >= rbf_config.max_replacements_per_tx {
+                    return Err("too many replacements");
+                }
+                if now.saturating_sub(tracking.last_replacement_time) < rbf_config.cooldown_seconds
+                {
+                    return Err("replacement cooldown not met");
+                }
+            }
+        }
+
+        // Conservative mode: Check minimum confirmations
+        // Note: Transactions in mempool have 0 confirmations. This check ensures that
+        // if a transaction has been confirmed (which shouldn't be in mempool), we require
+        // it to have minimum confirmations before allowing replacement.
+        if matches!(rbf_config.mode, crate::config::RbfMode::Conservative)
+            && rbf_config.min_confirmations > 0
+        {
+            if let Some(storage) = storage {
+                for conflict in conflicts {
+                    let confirmations = storage
+                        .transactions()
+                        .get_metadata(conflict)
+                        .ok()
+                        .flatten()
+                        .and_then(|metadata| {
+                            let block_height = storage
+                                .blocks()
+                                .get_height_by_hash(&metadata.block_hash)
+                                .ok()??;
+                            let tip_height = storage.chain().get_height().ok()??;
+                            Some(tip_height.saturating_sub(block_height) + 1)
+                        });
+                    if confirmations.is_some_and(|confirmations| {
+                        confirmations < rbf_config.min_confirmations as u64
+                    }) {
+                        return Err("replacement needs more confirmations");
+                    }
+                }
+            }
+        }
+
+        if replaced.len() > MAX_REPLACEMENT_CANDIDATES {
+            return Err("too many potential replacements");
+        }
+
+        // Higher fee rate than each conflict, by the configured multiplier
+        for conflict in conflicts {
+            let Some(conflict_feefrac) = clusters.feefrac(conflict) else {
+                continue;
+            };
+            let new_scaled = feefrac.fee as f64 * conflict_feefrac.size as f64;
+            let required_scaled = conflict_feefrac.fee as f64
+                * feefrac.size as f64
+                * rbf_config.min_fee_rate_multiplier;
+            if new_scaled <= required_scaled {
+                return Err("insufficient fee");
+            }
+        }
+
+        // Pays for everything it evicts, plus its own relay
+        let replaced_fee: u64 = replaced
+            .iter()
+            .filter_map(|hash| clusters.feefrac(hash))
+            .map(|feefrac| feefrac.fee)
+            .sum();
+        let relay_fee = policy.incremental_relay_fee * feefrac.size / 1000;
+        let min_bump = relay_fee.max(rbf_config.min_fee_bump_satoshis);
+        if feefrac.fee < replaced_fee.saturating_add(min_bump) {
+            return Err("insufficient fee");
+        }
+
+        if rbf_config.fee_rate_diagram_check {
+            let (old, new) = clusters.replacement_diagrams(conflicts, &replacements);
+            if !improves_diagram(&new, &old) {
+                return Err("replacement-failed: fee rate diagram not improved");
+            }
+        }
+        Ok(())
+    }
+
+    /// Parents of a transaction that are neither confirmed (in `utxo_set`)
+    /// nor in the mempool
+    pub fn missing_parents(&self, tx: &Transaction, utxo_set: &UtxoSet) -> Vec<Hash> {
+        let transactions = self.transactions.read().unwrap();
+        let mut missing: Vec<Hash> = tx
+            .inputs
+            .iter()
+            .filter(|input| {
+                !utxo_set.contains_key(&input.prevout)
+                    && !transactions.contains_key(&input.prevout.hash)
+            })
+            .map(|input| input.prevout.hash)
+            .collect();
+        missing.sort();
+        missing.dedup();
+        missing
+    }
+
+    /// Keep a transaction with missing parents in the orphan pool
+    ///
+    /// Returns false if the orphan is already known, too large, or evicted
+    /// straight away by the pool limits.
+    /// Uses interior mutability so it can be called even when MempoolManager is in an Arc
+    pub fn add_orphan(&self, tx: Transaction, peer: Option<SocketAddr>) -> bool {
+        use bllvm_protocol::block::calculate_tx_id;
+        use bllvm_protocol::serialization::transaction::serialize_transaction;
+
+        let policy = self
+            .policy_config
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_default();
+        let txid = calculate_tx_id(&tx);
+        let now = Self::current_timestamp();
+        let entry = OrphanEntry {
+            size: serialize_transaction(&tx).len() as u64,
+            tx,
+            peer,
+            expires_at: now + policy.orphan_expiry_secs,
+        };
+        let added = self.orphans.write().unwrap().add(txid, entry, &policy, now);
+        if added {
+            debug!("Added orphan transaction {}", hex::encode(txid));
+        }
+        added
+    }
+
+    /// Number of orphan transactions
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.read().unwrap().orphans.len()
+    }
+
+    /// Total size of orphan transactions in bytes
+    pub fn orphan_bytes(&self) -> u64 {
+        self.orphans.read().unwrap().bytes
+    }
+
+    /// Check if a transaction is in the orphan pool
+    pub fn has_orphan(&self, txid: &Hash) -> bool {
+        self.orphans.read().unwrap().orphans.contains_key(txid)
+    }
+
+    /// Outputs spent by orphan transactions
+    pub fn orphan_prevouts(&self) -> Vec<OutPoint> {
+        self.orphans
+            .read()
+            .unwrap()
+            .orphans
+            .values()
+            .flat_map(|orphan| orphan.tx.inputs.iter().map(|input| input.prevout.clone()))
+            .collect()
+    }
+
+    /// Drop the orphans announced by a disconnected peer
+    pub fn remove_orphans_for_peer(&self, peer: SocketAddr) -> usize {
+        self.orphans
+            .write()
+            .unwrap()
+            .remove_where(|orphan| orphan.peer == Some(peer))
+    }
+
+    /// Drop orphans included in a connected block or conflicting with it
+    pub fn remove_orphans_for_block(&self, block_txs: &[Transaction]) -> usize {
+        use bllvm_protocol::block::calculate_tx_id;
+
+        let txids: HashSet<Hash> = block_txs.iter().map(calculate_tx_id).collect();
+        let spent: HashSet<&OutPoint> = block_txs
+            .iter()
+            .flat_map(|tx| tx.inputs.iter().map(|input| &input.prevout))
+            .collect();
+        let mut orphans = self.orphans.write().unwrap();
+        let included: Vec<Hash> = orphans
+            .orphans
+            .keys()
+            .filter(|txid| txids.contains(*txid))
+            .copied()
+            .collect();
+        for txid in &included {
+            orphans.remove(txid);
+        }
+        included.len()
+            + orphans.remove_where(|orphan| {
+                orphan
+                    .tx
+                    .inputs
+                    .iter()
+                    .any(|input| spent.contains(&input.prevout))
+            })
+    }
+
+    /// Submit a transaction that may spend unknown outputs
+    ///
+    /// A transaction with missing parents is kept in the orphan pool;
+    /// otherwise its structure, inputs and scripts are checked against the
+    /// consensus rules for a block at `height` and it is added to the
+    /// mempool, and any orphans it completes are re-processed.
+    /// Uses interior mutability so it can be called even when MempoolManager is in an Arc
+    pub async fn submit_transaction(
+        &self,
+        tx: Transaction,
+        utxo_set: &UtxoSet,
+        height: u64,
+        peer: Option<SocketAddr>,
+    ) -> Result<TxSubmission> {
+        use bllvm_protocol::block::calculate_tx_id;
+
+        let _update = self.update_lock.lock().unwrap();
+        let missing_parents = self.missing_parents(&tx, utxo_set);
+        if !missing_parents.is_empty() {
+            return Ok(if self.add_orphan(tx, peer) {
+                TxSubmission::Orphan { missing_parents }
+            } else {
+                TxSubmission::Rejected
+            });
         }
 
-        Ok(true)
+        let txid = calculate_tx_id(&tx);
+        self.orphans.write().unwrap().remove(&txid);
+        if !self.check_consensus(&tx, utxo_set, height) {
+            debug!("Transaction {} fails consensus checks", hex::encode(txid));
+            return Ok(TxSubmission::Rejected);
+        }
+        if !self.accept_transaction(tx, utxo_set)? {
+            return Ok(TxSubmission::Rejected);
+        }
+        let reprocessed = self.accept_orphans(&[txid], utxo_set, height)?;
+        Ok(TxSubmission::Accepted { reprocessed })
     }
 
-    /// Update dependency graph when a transaction is added
-    fn update_dependency_graph(&self, tx: &Transaction, tx_hash: &Hash) {
-        let mut dependencies = self.tx_dependencies.write().unwrap();
-        let mut descendants = self.tx_descendants.write().unwrap();
+    /// Check a transaction against the consensus rules for a block at `height`
+    ///
+    /// Covers its structure, that each input is in `utxo_set` or created by
+    /// a mempool transaction, and its scripts. Conflicts with mempool
+    /// transactions are left to the replacement rules.
+    fn check_consensus(&self, tx: &Transaction, utxo_set: &UtxoSet, height: u64) -> bool {
+        use bllvm_protocol::mempool::MempoolResult;
+        use bllvm_protocol::{ConsensusProof, ValidationResult, UTXO};
+
+        let consensus = ConsensusProof::new();
+        if !matches!(
+            consensus.validate_transaction(tx),
+            Ok(ValidationResult::Valid)
+        ) {
+            return false;
+        }
+
+        // The coins it spends, with mempool outputs as if confirmed at `height`
+        let transactions = self.transactions.read().unwrap();
+        let mut coins = UtxoSet::new();
+        for input in tx.inputs.iter() {
+            let coin = utxo_set.get(&input.prevout).cloned().or_else(|| {
+                let parent = transactions.get(&input.prevout.hash)?;
+                let output = parent.outputs.get(input.prevout.index as usize)?;
+                Some(UTXO {
+                    value: output.value,
+                    script_pubkey: output.script_pubkey.clone(),
+                    height,
+                    is_coinbase: false,
+                })
+            });
+            if let Some(coin) = coin {
+                coins.insert(input.prevout.clone(), coin);
+            }
+        }
+        drop(transactions);
 
-        // Initialize empty sets for this transaction
-        dependencies.entry(*tx_hash).or_default();
-        descendants.entry(*tx_hash).or_default();
+        matches!(
+            consensus.accept_to_memory_pool(tx, &coins, &Mempool::new(), height),
+            Ok(MempoolResult::Accepted)
+        )
+    }
 
-        // Find parent transactions (ancestors) - transactions that created inputs
-        for input in &tx.inputs {
-            // Find transaction that created this output
-            for parent_hash in self.transactions.keys() {
-                if parent_hash == &input.prevout.hash {
-                    // This transaction depends on parent
-                    dependencies
-                        .entry(*tx_hash)
-                        .or_default()
-                        .insert(*parent_hash);
-
-                    // Parent has this as a descendant
-                    descendants
-                        .entry(*parent_hash)
-                        .or_default()
-                        .insert(*tx_hash);
-
-                    break;
+    /// Re-process the orphans spending `parents`, now that they are in the
+    /// mempool or confirmed
+    ///
+    /// Orphans still missing another parent stay in the pool; the others are
+    /// checked like [`Self::submit_transaction`] does. Each orphan accepted
+    /// is in turn treated as a parent. Returns the accepted orphans.
+    /// Uses interior mutability so it can be called even when MempoolManager is in an Arc
+    pub async fn process_orphans(
+        &self,
+        parents: &[Hash],
+        utxo_set: &UtxoSet,
+        height: u64,
+    ) -> Result<Vec<Hash>> {
+        let _update = self.update_lock.lock().unwrap();
+        self.accept_orphans(parents, utxo_set, height)
+    }
+
+    /// [`Self::process_orphans`], with the update lock held
+    fn accept_orphans(
+        &self,
+        parents: &[Hash],
+        utxo_set: &UtxoSet,
+        height: u64,
+    ) -> Result<Vec<Hash>> {
+        let mut queue: VecDeque<Hash> = parents.iter().copied().collect();
+        let mut accepted = Vec::new();
+        while let Some(parent) = queue.pop_front() {
+            let children = self.orphans.read().unwrap().children_of(&parent);
+            for child in children {
+                let Some(tx) = self
+                    .orphans
+                    .read()
+                    .unwrap()
+                    .orphans
+                    .get(&child)
+                    .map(|orphan| orphan.tx.clone())
+                else {
+                    continue;
+                };
+                if !self.missing_parents(&tx, utxo_set).is_empty() {
+                    continue;
+                }
+                self.orphans.write().unwrap().remove(&child);
+                if self.check_consensus(&tx, utxo_set, height)
+                    && self.accept_transaction(tx, utxo_set)?
+                {
+                    debug!("Accepted orphan transaction {}", hex::encode(child));
+                    accepted.push(child);
+                    queue.push_back(child);
+                } else {
+                    debug!("Orphan transaction {} rejected", hex::encode(child));
                 }
             }
         }
+        Ok(accepted)
     }
 
-    /// Add transaction to mempool
-    pub async fn add_transaction(&mut self, tx: Transaction) -> Result<bool> {
-        debug!("Adding transaction to mempool");
+    /// Update the orphan pool for a connected block: drop the orphans it
+    /// includes or conflicts with, and re-process those it completes
+    ///
+    /// `height` is the height of the next block.
+    pub async fn process_orphans_for_block(
+        &self,
+        block_txs: &[Transaction],
+        utxo_set: &UtxoSet,
+        height: u64,
+    ) -> Result<Vec<Hash>> {
+        use bllvm_protocol::block::calculate_tx_id;
+
+        let _update = self.update_lock.lock().unwrap();
+        self.remove_orphans_for_block(block_txs);
+        let parents: Vec<Hash> = block_txs.iter().map(calculate_tx_id).collect();
+        self.accept_orphans(&parents, utxo_set, height)
+    }
 
+    /// Update the mempool for a connected block
+    ///
+    /// Removes the transactions the block confirms, and the transactions
+    /// spending an output the block also spends along with their
+    /// descendants.
+    pub fn remove_for_block(&self, block_txs: &[Transaction]) -> Vec<(Hash, MempoolRemovalReason)> {
         use bllvm_protocol::block::calculate_tx_id;
-        let tx_hash = calculate_tx_id(&tx);
 
-        // Check for conflicts with existing mempool transactions
-        // If conflict exists, check if RBF replacement is allowed
-        let mut conflicting_tx_hash: Option<Hash> = None;
-        for input in &tx.inputs {
-            if let Some(existing_tx) = self
-                .transactions
-                .values()
-                .find(|t| t.inputs.iter().any(|i| i.prevout == input.prevout))
-            {
-                let existing_hash = calculate_tx_id(existing_tx);
-                conflicting_tx_hash = Some(existing_hash);
-                break;
+        let _update = self.update_lock.lock().unwrap();
+        let mut removed = Vec::new();
+        for txid in block_txs.iter().map(calculate_tx_id) {
+            if self.remove_entry(&txid) {
+                removed.push((txid, MempoolRemovalReason::Block));
             }
         }
 
-        // If there's a conflict, try RBF replacement
-        if let Some(existing_hash) = conflicting_tx_hash {
-            if let Some(existing_tx) = self.transactions.get(&existing_hash) {
-                // Check if RBF replacement is allowed
-                // Note: Storage is not available in MempoolManager context
-                // Conservative mode confirmation checks will be skipped if storage is None
-                if self.check_rbf_replacement(&tx, existing_tx, &self.utxo_set, None)? {
-                    debug!(
-                        "RBF replacement allowed, replacing transaction {}",
-                        hex::encode(existing_hash)
-                    );
-
-                    // Remove existing transaction
-                    self.remove_transaction(&existing_hash);
-
-                    // Update RBF tracking
-                    let original_hash = {
-                        let tracking = self.rbf_tracking.read().unwrap();
-                        tracking
-                            .get(&existing_hash)
-                            .map(|t| t.original_tx_hash)
-                            .unwrap_or(existing_hash)
-                    };
+        let spent: HashSet<&OutPoint> = block_txs
+            .iter()
+            .flat_map(|tx| tx.inputs.iter().map(|input| &input.prevout))
+            .collect();
+        let conflicts: Vec<Hash> = self
+            .transactions
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, tx)| tx.inputs.iter().any(|input| spent.contains(&input.prevout)))
+            .map(|(txid, _)| *txid)
+            .collect();
+        for txid in conflicts {
+            removed.extend(
+                self.remove_with_descendants(&txid)
+                    .into_iter()
+                    .map(|txid| (txid, MempoolRemovalReason::Conflict)),
+            );
+        }
+        removed
+    }
 
-                    let replacement_count = {
-                        let tracking = self.rbf_tracking.read().unwrap();
-                        tracking
-                            .get(&existing_hash)
-                            .map(|t| t.replacement_count + 1)
-                            .unwrap_or(1)
-                    };
+    /// Put the transactions of disconnected blocks back into the mempool
+    ///
+    /// `blocks` are the disconnected blocks, lowest first, and `utxo_set` is
+    /// the UTXO set at the new tip. Coinbases are skipped and every other
+    /// transaction is re-checked against the mempool policy. Mempool
+    /// transactions spending their outputs are taken out and re-added after
+    /// them, keeping their entry metadata; those that no longer fit are
+    /// reported as removed.
+    pub async fn add_for_reorg(
+        &self,
+        blocks: &[Block],
+        utxo_set: &UtxoSet,
+    ) -> Result<MempoolUpdate> {
+        use bllvm_protocol::block::calculate_tx_id;
 
-                    {
-                        let mut tracking = self.rbf_tracking.write().unwrap();
-                        tracking.insert(
-                            tx_hash,
-                            RbfTracking {
-                                replacement_count,
-                                last_replacement_time: Self::current_timestamp(),
-                                original_tx_hash: original_hash,
-                            },
-                        );
-                        tracking.remove(&existing_hash);
-                    }
-                } else {
-                    debug!("Transaction conflicts with existing mempool transaction and RBF replacement not allowed");
-                    return Ok(false);
-                }
-            }
-        } else {
-            // No conflict - check if inputs are already spent
-            for input in &tx.inputs {
-                if self.spent_outputs.contains(&input.prevout) {
-                    debug!("Transaction conflicts with existing mempool transaction");
-                    return Ok(false);
-                }
-            }
+        let _update = self.update_lock.lock().unwrap();
+        let disconnected: Vec<(Hash, Transaction)> = blocks
+            .iter()
+            .flat_map(|block| block.transactions.iter().skip(1))
+            .map(|tx| (calculate_tx_id(tx), tx.clone()))
+            .collect();
+        let disconnected_ids: HashSet<Hash> = disconnected.iter().map(|(txid, _)| *txid).collect();
+
+        // Children of the disconnected transactions have to follow them
+        let dependents: HashSet<Hash> = {
+            let clusters = self.clusters.read().unwrap();
+            let transactions = self.transactions.read().unwrap();
+            transactions
+                .iter()
+                .filter(|(_, tx)| {
+                    tx.inputs
+                        .iter()
+                        .any(|input| disconnected_ids.contains(&input.prevout.hash))
+                })
+                .flat_map(|(txid, _)| clusters.with_descendants(txid))
+                .collect()
+        };
+        let ordered: Vec<Hash> = self
+            .clusters
+            .read()
+            .unwrap()
+            .chunks_by_feerate()
+            .into_iter()
+            .flat_map(|chunk| chunk.txids.iter().copied())
+            .filter(|txid| dependents.contains(txid))
+            .collect();
+        let mut held = Vec::with_capacity(ordered.len());
+        for txid in ordered {
+            let Some(tx) = self.get_transaction(&txid) else {
+                continue;
+            };
+            let entry_time = self.entry_time(&txid);
+            let rbf = self.rbf_tracking.read().unwrap().get(&txid).cloned();
+            let unbroadcast = self.unbroadcast.read().unwrap().contains(&txid);
+            self.remove_entry(&txid);
+            held.push((txid, tx, entry_time, rbf, unbroadcast));
         }
 
-        // Check ancestor/descendant limits before adding
-        if let Some(ref policy) = *self.policy_config.read().unwrap() {
-            if !self.check_ancestor_descendant_limits(&tx, &tx_hash, policy)? {
-                warn!(
-                    "Transaction {} rejected: exceeds ancestor/descendant limits",
-                    hex::encode(tx_hash)
-                );
-                return Ok(false);
+        let mut update = MempoolUpdate::default();
+        for (txid, tx) in disconnected {
+            if !self.contains(&txid) && self.readmit_transaction(tx, utxo_set)? {
+                update.added.push(txid);
             }
         }
+        for (txid, tx, entry_time, rbf, unbroadcast) in held {
+            if !self.readmit_transaction(tx, utxo_set)? {
+                update.removed.push((txid, MempoolRemovalReason::Reorg));
+                continue;
+            }
+            if let Some(entry_time) = entry_time {
+                self.tx_timestamps.write().unwrap().insert(txid, entry_time);
+            }
+            if let Some(rbf) = rbf {
+                self.rbf_tracking.write().unwrap().insert(txid, rbf);
+            }
+            if unbroadcast {
+                self.unbroadcast.write().unwrap().insert(txid);
+            }
+        }
+        Ok(update)
+    }
 
-        // Add transaction to mempool (store full transaction)
-        self.transactions.insert(tx_hash, tx.clone());
-        self.mempool.insert(tx_hash);
-
-        // Track spent outputs
-        for input in &tx.inputs {
-            self.spent_outputs.insert(input.prevout.clone());
+    /// Remove the transactions no longer valid at the tip after a
+    /// reorganization, with their descendants
+    ///
+    /// A transaction is removed if it would not be final in the next block
+    /// (`tip_height + 1`, time locks checked against `median_time_past`),
+    /// spends a coinbase that would not be mature there, or spends an output
+    /// that is neither in `utxo_set` nor created by the mempool.
+    pub fn remove_for_reorg(
+        &self,
+        tip_height: u64,
+        median_time_past: u64,
+        utxo_set: &UtxoSet,
+    ) -> Vec<(Hash, MempoolRemovalReason)> {
+        let _update = self.update_lock.lock().unwrap();
+        let next_height = tip_height + 1;
+        let transactions = self.transactions.read().unwrap();
+        let invalid: Vec<Hash> = transactions
+            .iter()
+            .filter(|(_, tx)| {
+                !is_final_tx(tx, next_height, median_time_past)
+                    || tx
+                        .inputs
+                        .iter()
+                        .any(|input| match utxo_set.get(&input.prevout) {
+                            Some(utxo) => {
+                                utxo.is_coinbase
+                                    && next_height.saturating_sub(utxo.height) < COINBASE_MATURITY
+                            }
+                            None => !transactions.contains_key(&input.prevout.hash),
+                        })
+            })
+            .map(|(txid, _)| *txid)
+            .collect();
+        drop(transactions);
+
+        let mut removed = Vec::new();
+        for txid in invalid {
+            removed.extend(
+                self.remove_with_descendants(&txid)
+                    .into_iter()
+                    .map(|txid| (txid, MempoolRemovalReason::Reorg)),
+            );
         }
+        removed
+    }
 
-        // Update dependency graph
-        self.update_dependency_graph(&tx, &tx_hash);
+    /// Remove a transaction and its in-mempool descendants, returning those
+    /// removed
+    fn remove_with_descendants(&self, txid: &Hash) -> Vec<Hash> {
+        let family = self.clusters.read().unwrap().with_descendants(txid);
+        family
+            .into_iter()
+            .filter(|txid| self.remove_entry(txid))
+            .collect()
+    }
 
-        // Record timestamp
-        self.tx_timestamps
-            .write()
-            .unwrap()
-            .insert(tx_hash, Self::current_timestamp());
+    /// Re-check a transaction that was in the mempool or a block before and
+    /// add it back
+    ///
+    /// Its inputs must be in `utxo_set` or the mempool.
+    fn readmit_transaction(&self, tx: Transaction, utxo_set: &UtxoSet) -> Result<bool> {
+        use bllvm_protocol::{ConsensusProof, ValidationResult};
 
-        // Calculate and cache fee rate (will be updated when UTXO set is available)
-        // For now, set to 0 - will be recalculated in get_prioritized_transactions
-        let fee_rate = 0u64;
-        self.fee_cache.write().unwrap().insert(tx_hash, fee_rate);
-        self.fee_index
-            .write()
-            .unwrap()
-            .entry(Reverse(fee_rate))
-            .or_default()
-            .push(tx_hash);
+        let valid = matches!(
+            ConsensusProof::new().validate_transaction(&tx),
+            Ok(ValidationResult::Valid)
+        );
+        if !valid || !self.missing_parents(&tx, utxo_set).is_empty() {
+            return Ok(false);
+        }
+        self.accept_transaction(tx, utxo_set)
+    }
 
-        Ok(true)
+    /// Fee estimator fed by this mempool
+    pub fn fee_estimator(&self) -> Arc<FeeEstimator> {
+        Arc::clone(&self.fee_estimator)
     }
 
     /// Get mempool size
     pub fn size(&self) -> usize {
-        self.transactions.len()
+        self.transactions.read().unwrap().len()
+    }
+
+    /// Check if a transaction is in the mempool
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.transactions.read().unwrap().contains_key(hash)
+    }
+
+    /// Outputs spent by mempool transactions
+    pub fn prevouts(&self) -> Vec<OutPoint> {
+        self.spent_outputs.read().unwrap().iter().cloned().collect()
     }
 
     /// Get mempool transaction hashes
     pub fn transaction_hashes(&self) -> Vec<Hash> {
-        self.transactions.keys().cloned().collect()
+        self.transactions.read().unwrap().keys().cloned().collect()
     }
 
     /// Get transaction by hash
     pub fn get_transaction(&self, hash: &Hash) -> Option<Transaction> {
-        self.transactions.get(hash).cloned()
+        self.transactions.read().unwrap().get(hash).cloned()
     }
 
     /// Get all transactions
     pub fn get_transactions(&self) -> Vec<Transaction> {
-        self.transactions.values().cloned().collect()
+        self.transactions
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
     }
 
     /// Get prioritized transactions by fee rate
     ///
-    /// Returns transactions sorted by fee rate (satoshis per vbyte) in descending order.
-    /// Requires UTXO set to calculate fee rates.
-    ///
-    /// Optimization: Uses sorted index (BTreeMap) for O(log n) insertion, O(1) top-N retrieval
-    /// instead of O(n log n) sort on every call.
+    /// Returns transactions chunk by chunk, best chunk fee rate first, so a
+    /// parent always precedes its children. Requires UTXO set to calculate
+    /// fee rates.
     pub fn get_prioritized_transactions(
         &self,
         limit: usize,
         utxo_set: &UtxoSet,
     ) -> Vec<Transaction> {
-        // Recalculate fee rates and update index
-        // Note: In a production system, we'd track UTXO set changes and only recalculate when needed
-        self.update_fee_index(utxo_set);
-
-        // Use sorted index to get top N transactions (already sorted by fee rate descending)
-        let mut result = Vec::with_capacity(limit);
-        let fee_index = self.fee_index.read().unwrap();
-        for (Reverse(_fee_rate), tx_hashes) in fee_index.iter() {
-            for tx_hash in tx_hashes {
-                if let Some(tx) = self.transactions.get(tx_hash) {
-                    result.push(tx.clone());
-                    if result.len() >= limit {
-                        return result;
-                    }
-                }
-            }
-        }
-        result
+        self.update_fees(utxo_set);
+
+        let clusters = self.clusters.read().unwrap();
+        let transactions = self.transactions.read().unwrap();
+        clusters
+            .chunks_by_feerate()
+            .into_iter()
+            .flat_map(|chunk| chunk.txids.iter())
+            .filter_map(|tx_hash| transactions.get(tx_hash).cloned())
+            .take(limit)
+            .collect()
+    }
+
+    /// Mempool chunks with fees from the current UTXO set, best fee rate first
+    pub fn get_chunks(&self, utxo_set: &UtxoSet) -> Vec<Chunk> {
+        self.update_fees(utxo_set);
+        self.clusters
+            .read()
+            .unwrap()
+            .chunks_by_feerate()
+            .into_iter()
+            .cloned()
+            .collect()
     }
 
     /// Calculate a simple hash of the UTXO set for change detection
@@ -1138,13 +2461,13 @@ impl MempoolManager {
         hasher.finish()
     }
 
-    /// Update fee index with current UTXO set
+    /// Update cluster fees with current UTXO set
     ///
-    /// Recalculates fee rates for all transactions and rebuilds the sorted index.
+    /// Recalculates every transaction's fee, plus any prioritisetransaction
+    /// fee delta, and relinearizes the clusters whose fees changed.
     ///
     /// Optimization: Only recalculates when UTXO set changes (incremental updates)
-    /// Optimization: Batch UTXO lookups across all transactions for better cache locality
-    fn update_fee_index(&self, utxo_set: &UtxoSet) {
+    fn update_fees(&self, utxo_set: &UtxoSet) {
         // Calculate current UTXO set hash
         let current_hash = Self::calculate_utxo_set_hash(utxo_set);
 
@@ -1152,7 +2475,6 @@ impl MempoolManager {
         let mut last_hash = self.utxo_set_hash.write().unwrap();
         if Some(current_hash) == *last_hash {
             // UTXO set unchanged - skip recalculation
-            drop(last_hash);
             return;
         }
 
@@ -1160,73 +2482,40 @@ impl MempoolManager {
         *last_hash = Some(current_hash);
         drop(last_hash);
 
-        // Clear existing index (we'll rebuild it)
-        let mut fee_index = self.fee_index.write().unwrap();
-        fee_index.clear();
-        drop(fee_index);
-
-        let mut fee_cache = self.fee_cache.write().unwrap();
-        fee_cache.clear();
-
-        // Optimization: Pre-collect all prevouts from all transactions for batch UTXO lookup
-        let all_prevouts: Vec<(&Hash, &OutPoint)> = self
-            .transactions
+        let deltas = self.fee_deltas.read().unwrap();
+        let transactions = self.transactions.read().unwrap();
+        let fees: HashMap<Hash, u64> = transactions
             .iter()
-            .flat_map(|(tx_hash, tx)| tx.inputs.iter().map(move |input| (tx_hash, &input.prevout)))
+            .map(|(tx_hash, tx)| {
+                let fee = Self::transaction_fee(&transactions, tx, utxo_set);
+                let delta = deltas.get(tx_hash).copied().unwrap_or(0);
+                (*tx_hash, fee.saturating_add_signed(delta))
+            })
             .collect();
-
-        // Batch UTXO lookup for all transactions (single pass through HashMap)
-        let mut utxo_cache: HashMap<&OutPoint, u64> = HashMap::with_capacity(all_prevouts.len());
-        for (_, prevout) in &all_prevouts {
-            if let Some(utxo) = utxo_set.get(prevout) {
-                utxo_cache.insert(prevout, utxo.value as u64);
-            }
-        }
-
-        // Recalculate fee rates for all transactions using cached UTXOs
-        for (tx_hash, tx) in &self.transactions {
-            // Calculate fee using cached UTXOs
-            let mut input_total = 0u64;
-            for input in &tx.inputs {
-                if let Some(&value) = utxo_cache.get(&input.prevout) {
-                    input_total += value;
-                }
-            }
-
-            // Sum output values
-            let output_total: u64 = tx.outputs.iter().map(|out| out.value as u64).sum();
-
-            // Calculate fee
-            let fee = input_total.saturating_sub(output_total);
-
-            // Calculate transaction size
-            let size = self.estimate_transaction_size(tx);
-
-            // Calculate fee rate (satoshis per vbyte)
-            let fee_rate = if size > 0 {
-                fee * 1000 / size as u64
-            } else {
-                0
-            };
-
-            // Update cache
-            fee_cache.insert(*tx_hash, fee_rate);
-
-            // Add to sorted index
-            let mut fee_index = self.fee_index.write().unwrap();
-            fee_index
-                .entry(Reverse(fee_rate))
-                .or_default()
-                .push(*tx_hash);
-        }
+        drop(transactions);
+        drop(deltas);
+        self.clusters.write().unwrap().set_fees(&fees);
     }
 
     /// Calculate transaction fee
     ///
     /// Fee = sum of inputs - sum of outputs
     ///
+    /// Inputs spending an unconfirmed parent are valued from the parent's
+    /// outputs in the mempool.
+    ///
     /// Optimization: Uses batch UTXO lookup pattern for better cache locality
     pub fn calculate_transaction_fee(&self, tx: &Transaction, utxo_set: &UtxoSet) -> u64 {
+        Self::transaction_fee(&self.transactions.read().unwrap(), tx, utxo_set)
+    }
+
+    /// [`Self::calculate_transaction_fee`] against the given mempool
+    /// transactions
+    fn transaction_fee(
+        transactions: &HashMap<Hash, Transaction>,
+        tx: &Transaction,
+        utxo_set: &UtxoSet,
+    ) -> u64 {
         // Optimization: Batch UTXO lookups - collect all prevouts first, then lookup
         // This improves cache locality and reduces HashMap traversal overhead
         let prevouts: Vec<&OutPoint> = tx.inputs.iter().map(|input| &input.prevout).collect();
@@ -1236,6 +2525,11 @@ impl MempoolManager {
         for prevout in prevouts {
             if let Some(utxo) = utxo_set.get(prevout) {
                 input_total += utxo.value as u64;
+            } else if let Some(output) = transactions
+                .get(&prevout.hash)
+                .and_then(|parent| parent.outputs.get(prevout.index as usize))
+            {
+                input_total += output.value as u64;
             }
         }
 
@@ -1271,23 +2565,23 @@ impl MempoolManager {
     }
 
     /// Remove transaction from mempool
-    pub fn remove_transaction(&mut self, hash: &Hash) -> bool {
-        if let Some(tx) = self.transactions.remove(hash) {
-            self.mempool.remove(hash);
+    /// Uses interior mutability so it can be called even when MempoolManager is in an Arc
+    pub fn remove_transaction(&self, hash: &Hash) -> bool {
+        let _update = self.update_lock.lock().unwrap();
+        self.remove_entry(hash)
+    }
 
-            // Remove spent outputs tracking
-            for input in &tx.inputs {
-                self.spent_outputs.remove(&input.prevout);
-            }
+    /// [`Self::remove_transaction`], with the update lock held
+    fn remove_entry(&self, hash: &Hash) -> bool {
+        let removed = self.transactions.write().unwrap().remove(hash);
+        if let Some(tx) = removed {
+            self.mempool.write().unwrap().remove(hash);
 
-            // Remove from fee index
-            if let Some(fee_rate) = self.fee_cache.write().unwrap().remove(hash) {
-                let mut fee_index = self.fee_index.write().unwrap();
-                if let Some(tx_hashes) = fee_index.get_mut(&Reverse(fee_rate)) {
-                    tx_hashes.retain(|&h| h != *hash);
-                    if tx_hashes.is_empty() {
-                        fee_index.remove(&Reverse(fee_rate));
-                    }
+            // Remove spent outputs tracking
+            {
+                let mut spent_outputs = self.spent_outputs.write().unwrap();
+                for input in &tx.inputs {
+                    spent_outputs.remove(&input.prevout);
                 }
             }
 
@@ -1297,29 +2591,15 @@ impl MempoolManager {
             // Remove timestamp
             self.tx_timestamps.write().unwrap().remove(hash);
 
-            // Remove from dependency graph
-            {
-                let mut dependencies = self.tx_dependencies.write().unwrap();
-                let mut descendants = self.tx_descendants.write().unwrap();
-
-                // Remove this transaction from all its descendants' dependency lists
-                if let Some(children) = descendants.remove(hash) {
-                    for child_hash in children {
-                        if let Some(parents) = dependencies.get_mut(&child_hash) {
-                            parents.remove(hash);
-                        }
-                    }
-                }
+            // Left the mempool without confirming, unless the estimator
+            // already saw its block
+            self.fee_estimator.remove_transaction(hash);
 
-                // Remove this transaction from all its ancestors' descendant lists
-                if let Some(parents) = dependencies.remove(hash) {
-                    for parent_hash in parents {
-                        if let Some(children) = descendants.get_mut(&parent_hash) {
-                            children.remove(hash);
-                        }
-                    }
-                }
-            }
+            self.unbroadcast.write().unwrap().remove(hash);
+
+            // Remove from its cluster; children's fees change with it
+            self.clusters.write().unwrap().remove(hash);
+            *self.utxo_set_hash.write().unwrap() = None;
 
             true
         } else {
@@ -1328,40 +2608,192 @@ impl MempoolManager {
     }
 
     /// Clear mempool
-    pub fn clear(&mut self) {
-        self.transactions.clear();
-        self.mempool.clear();
-        self.spent_outputs.clear();
-        self.fee_index.write().unwrap().clear();
-        self.fee_cache.write().unwrap().clear();
+    /// Uses interior mutability so it can be called even when MempoolManager is in an Arc
+    pub fn clear(&self) {
+        let _update = self.update_lock.lock().unwrap();
+        self.transactions.write().unwrap().clear();
+        self.mempool.write().unwrap().clear();
+        self.spent_outputs.write().unwrap().clear();
+        self.clusters.write().unwrap().clear();
         self.rbf_tracking.write().unwrap().clear();
         self.tx_timestamps.write().unwrap().clear();
+        self.orphans.write().unwrap().clear();
+        self.unbroadcast.write().unwrap().clear();
+    }
+
+    /// Add to a transaction's fee delta (prioritisetransaction)
+    ///
+    /// Block templates and eviction see the delta as part of the fee. The
+    /// delta is kept even if the transaction is not in the mempool.
+    /// Uses interior mutability so it can be called even when MempoolManager is in an Arc
+    pub fn prioritise_transaction(&self, txid: Hash, delta: i64) {
+        {
+            let mut deltas = self.fee_deltas.write().unwrap();
+            let total = deltas.entry(txid).or_insert(0);
+            *total = total.saturating_add(delta);
+            if *total == 0 {
+                deltas.remove(&txid);
+            }
+        }
+        // Fees are recalculated with the new delta
+        *self.utxo_set_hash.write().unwrap() = None;
+    }
+
+    /// A transaction's prioritisetransaction fee delta
+    pub fn fee_delta(&self, txid: &Hash) -> i64 {
+        self.fee_deltas
+            .read()
+            .unwrap()
+            .get(txid)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// When a mempool transaction was added (Unix seconds)
+    pub fn entry_time(&self, txid: &Hash) -> Option<u64> {
+        self.tx_timestamps.read().unwrap().get(txid).copied()
+    }
+
+    /// Fee and size the mempool holds for a transaction
+    pub fn entry_feefrac(&self, txid: &Hash) -> Option<FeeFrac> {
+        self.clusters.read().unwrap().feefrac(txid)
+    }
+
+    /// Mark a mempool transaction as not yet relayed to any peer
+    ///
+    /// Returns false if the transaction is not in the mempool.
+    pub fn add_unbroadcast(&self, txid: Hash) -> bool {
+        if !self.contains(&txid) {
+            return false;
+        }
+        self.unbroadcast.write().unwrap().insert(txid)
+    }
+
+    /// Mark a transaction as relayed, once a peer has asked for it
+    pub fn remove_unbroadcast(&self, txid: &Hash) -> bool {
+        self.unbroadcast.write().unwrap().remove(txid)
+    }
+
+    /// Mempool transactions not yet relayed to any peer
+    pub fn unbroadcast_txids(&self) -> Vec<Hash> {
+        self.unbroadcast.read().unwrap().iter().copied().collect()
+    }
+
+    /// Where the mempool is saved (`mempool_persistence_path`)
+    pub fn persistence_path(&self) -> PathBuf {
+        let policy = self.policy_config.read().unwrap();
+        PathBuf::from(match policy.as_ref() {
+            Some(policy) => policy.mempool_persistence_path.clone(),
+            None => MempoolPolicyConfig::default().mempool_persistence_path,
+        })
     }
 
     /// Save mempool to disk for persistence
-    pub fn save_to_disk<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
+    ///
+    /// Writes every entry, parents first, with its entry time, fee delta and
+    /// RBF tracking, plus the fee deltas of transactions not in the mempool
+    /// and the unbroadcast set. The file is versioned and ends with a
+    /// double-SHA256 checksum, and is replaced atomically.
+    pub fn save_to_disk<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         use bllvm_protocol::serialization::transaction::serialize_transaction;
-        use std::fs::File;
         use std::io::Write;
 
-        let transactions = self.get_transactions();
-        let mut file = File::create(path)?;
-
-        // Write transaction count
-        file.write_all(&(transactions.len() as u32).to_le_bytes())?;
+        let saved = {
+            let timestamps = self.tx_timestamps.read().unwrap();
+            let rbf_tracking = self.rbf_tracking.read().unwrap();
+            let deltas = self.fee_deltas.read().unwrap();
+            let transactions = self.transactions.read().unwrap();
+            let entries = self
+                .clusters
+                .read()
+                .unwrap()
+                .chunks_by_feerate()
+                .into_iter()
+                .flat_map(|chunk| chunk.txids.iter())
+                .filter_map(|txid| {
+                    let tx = transactions.get(txid)?;
+                    Some(SavedEntry {
+                        tx: serialize_transaction(tx),
+                        entry_time: timestamps.get(txid).copied().unwrap_or(0),
+                        fee_delta: deltas.get(txid).copied().unwrap_or(0),
+                        rbf: rbf_tracking.get(txid).cloned(),
+                    })
+                })
+                .collect();
+            SavedMempool {
+                entries,
+                fee_deltas: deltas
+                    .iter()
+                    .filter(|(txid, _)| !transactions.contains_key(*txid))
+                    .map(|(txid, delta)| (*txid, *delta))
+                    .collect(),
+                unbroadcast: Some(self.unbroadcast_txids()),
+            }
+        };
 
-        // Write each transaction
-        for tx in transactions {
-            let serialized = serialize_transaction(&tx);
-            file.write_all(&(serialized.len() as u32).to_le_bytes())?;
-            file.write_all(&serialized)?;
+        let payload = bincode::serialize(&saved)?;
+        let checksum = Sha256::digest(Sha256::digest(&payload));
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
         }
-
+        let tmp_path = path.with_extension("new");
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(MEMPOOL_FILE_MAGIC)?;
+        file.write_all(&MEMPOOL_FILE_VERSION.to_le_bytes())?;
+        file.write_all(&payload)?;
+        file.write_all(&checksum)?;
         file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+
+        debug!(
+            "Saved {} mempool transactions to {}",
+            saved.entries.len(),
+            path.display()
+        );
         Ok(())
     }
 }
 
+/// Whether a transaction's lock time lets it into a block at `height` with
+/// median time-past `time` (BIP113)
+fn is_final_tx(tx: &Transaction, height: u64, time: u64) -> bool {
+    if tx.lock_time == 0 {
+        return true;
+    }
+    let limit = if tx.lock_time < LOCKTIME_THRESHOLD {
+        height
+    } else {
+        time
+    };
+    tx.lock_time < limit
+        || tx
+            .inputs
+            .iter()
+            .all(|input| input.sequence == SEQUENCE_FINAL)
+}
+
+/// Read and check a file written by [`MempoolManager::save_to_disk`]
+fn read_mempool_file(path: &Path) -> Result<SavedMempool> {
+    let data = std::fs::read(path)?;
+    let header_len = MEMPOOL_FILE_MAGIC.len() + 4;
+    if data.len() < header_len + 32 || &data[..4] != MEMPOOL_FILE_MAGIC {
+        bail!("{} is not a mempool file", path.display());
+    }
+    let version = u32::from_le_bytes(data[4..8].try_into()?);
+    if version != MEMPOOL_FILE_VERSION {
+        bail!("Unsupported mempool file version {}", version);
+    }
+    let (payload, checksum) = data[header_len..].split_at(data.len() - header_len - 32);
+    if Sha256::digest(Sha256::digest(payload)).as_slice() != checksum {
+        bail!(
+            "Mempool file {} is corrupt (checksum mismatch)",
+            path.display()
+        );
+    }
+    Ok(bincode::deserialize(payload)?)
+}
+
 impl Default for MempoolManager {
     fn default() -> Self {
         Self::new()
@@ -1395,34 +2827,127 @@ impl crate::node::miner::MempoolProvider for MempoolManager {
     fn remove_transaction(&mut self, hash: &[u8; 32]) -> bool {
         use bllvm_protocol::Hash;
         let hash_array: Hash = *hash;
-        self.remove_transaction(&hash_array)
+        MempoolManager::remove_transaction(self, &hash_array)
+    }
+
+    fn get_transaction_parents(&self, hash: &[u8; 32]) -> Vec<[u8; 32]> {
+        let mut parents: Vec<[u8; 32]> = self
+            .clusters
+            .read()
+            .unwrap()
+            .parents(hash)
+            .map(|parents| parents.iter().copied().collect())
+            .unwrap_or_default();
+        parents.sort();
+        parents
+    }
+
+    fn get_chunks(&self, utxo_set: &bllvm_protocol::UtxoSet) -> Option<Vec<Vec<[u8; 32]>>> {
+        Some(
+            self.get_chunks(utxo_set)
+                .into_iter()
+                .map(|chunk| chunk.txids)
+                .collect(),
+        )
     }
 }
 
 impl MempoolManager {
-    /// Load mempool from disk
-    pub fn load_from_disk<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<()> {
+    /// Outputs spent by the transactions of a mempool saved by
+    /// [`Self::save_to_disk`], for looking up the coins
+    /// [`Self::load_from_disk`] validates them against
+    pub fn saved_prevouts<P: AsRef<Path>>(path: P) -> Result<Vec<OutPoint>> {
         use bllvm_protocol::serialization::transaction::deserialize_transaction;
-        use std::fs::File;
-        use std::io::Read;
-
-        let mut file = File::open(path)?;
-        let mut count_bytes = [0u8; 4];
-        file.read_exact(&mut count_bytes)?;
-        let count = u32::from_le_bytes(count_bytes) as usize;
 
-        for _ in 0..count {
-            let mut len_bytes = [0u8; 4];
-            file.read_exact(&mut len_bytes)?;
-            let len = u32::from_le_bytes(len_bytes) as usize;
+        let saved = read_mempool_file(path.as_ref())?;
+        Ok(saved
+            .entries
+            .iter()
+            .filter_map(|entry| deserialize_transaction(&entry.tx).ok())
+            .flat_map(|tx| {
+                tx.inputs
+                    .iter()
+                    .map(|input| input.prevout.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect())
+    }
 
-            let mut tx_bytes = vec![0u8; len];
-            file.read_exact(&mut tx_bytes)?;
+    /// Load a mempool saved by [`Self::save_to_disk`]
+    ///
+    /// Each entry is re-validated against the current tip (`utxo_set`) and
+    /// the mempool policy before it is added back with its entry time, fee
+    /// delta and RBF tracking. Entries older than the mempool expiry are
+    /// dropped.
+    pub async fn load_from_disk<P: AsRef<Path>>(
+        &self,
+        path: P,
+        utxo_set: &UtxoSet,
+    ) -> Result<MempoolLoadStats> {
+        use bllvm_protocol::block::calculate_tx_id;
+        use bllvm_protocol::serialization::transaction::deserialize_transaction;
 
-            let tx = deserialize_transaction(&tx_bytes)?;
-            drop(self.add_transaction(tx));
+        let path = path.as_ref();
+        let saved = read_mempool_file(path)?;
+        let _update = self.update_lock.lock().unwrap();
+        let expiry_secs = self
+            .policy_config
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_default()
+            .mempool_expiry_hours
+            * 3600;
+        let now = Self::current_timestamp();
+        let mut stats = MempoolLoadStats::default();
+
+        for (txid, delta) in saved.fee_deltas {
+            self.prioritise_transaction(txid, delta);
+        }
+        for entry in saved.entries {
+            let Ok(tx) = deserialize_transaction(&entry.tx) else {
+                stats.failed += 1;
+                continue;
+            };
+            let txid = calculate_tx_id(&tx);
+            if entry.fee_delta != 0 {
+                self.prioritise_transaction(txid, entry.fee_delta);
+            }
+            if entry.entry_time.saturating_add(expiry_secs) < now {
+                stats.expired += 1;
+                continue;
+            }
+            if self.contains(&txid) {
+                stats.already_there += 1;
+                continue;
+            }
+            if !self.readmit_transaction(tx, utxo_set)? {
+                stats.failed += 1;
+                continue;
+            }
+            self.tx_timestamps
+                .write()
+                .unwrap()
+                .insert(txid, entry.entry_time);
+            if let Some(rbf) = entry.rbf {
+                self.rbf_tracking.write().unwrap().insert(txid, rbf);
+            }
+            stats.accepted += 1;
+        }
+        for txid in saved.unbroadcast.unwrap_or_default() {
+            if self.add_unbroadcast(txid) {
+                stats.unbroadcast += 1;
+            }
         }
 
-        Ok(())
+        info!(
+            "Loaded mempool from {}: {} accepted, {} failed, {} expired, {} already there",
+            path.display(),
+            stats.accepted,
+            stats.failed,
+            stats.expired,
+            stats.already_there
+        );
+        Ok(stats)
     }
 }
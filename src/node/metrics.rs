@@ -16,6 +16,9 @@ pub struct NodeMetrics {
     pub storage: StorageMetrics,
     /// RPC metrics
     pub rpc: RpcMetrics,
+    /// Mempool metrics
+    #[serde(default)]
+    pub mempool: MempoolMetrics,
     /// Performance metrics
     pub performance: PerformanceMetrics,
     /// System metrics
@@ -79,6 +82,8 @@ pub struct StorageMetrics {
     pub within_bounds: bool,
     /// Pruning statistics (if pruning enabled)
     pub pruning: Option<PruningMetrics>,
+    /// Progress of the last reindex (if one ran since startup)
+    pub reindex: Option<ReindexMetrics>,
 }
 
 /// Pruning metrics
@@ -94,6 +99,21 @@ pub struct PruningMetrics {
     pub last_prune_height: Option<u64>,
 }
 
+/// Reindex progress
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReindexMetrics {
+    /// "reindex" or "reindex-chainstate"
+    pub mode: String,
+    /// Blocks connected so far
+    pub blocks_connected: u64,
+    /// Height of the last connected block
+    pub height: Option<u64>,
+    /// Height the chain is being rebuilt to
+    pub target_height: Option<u64>,
+    /// Whether the reindex has finished
+    pub complete: bool,
+}
+
 /// RPC layer metrics
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct RpcMetrics {
@@ -111,6 +131,17 @@ pub struct RpcMetrics {
     pub active_connections: usize,
 }
 
+/// Mempool metrics
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct MempoolMetrics {
+    /// Transactions in the mempool
+    pub transaction_count: usize,
+    /// Orphan transactions waiting for their parents
+    pub orphan_count: usize,
+    /// Total size of orphan transactions (bytes)
+    pub orphan_bytes: u64,
+}
+
 /// Performance metrics
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct PerformanceMetrics {
@@ -143,6 +174,8 @@ pub struct MetricsCollector {
     storage: Arc<Mutex<StorageMetrics>>,
     /// RPC metrics
     rpc: Arc<Mutex<RpcMetrics>>,
+    /// Mempool metrics
+    mempool: Arc<Mutex<MempoolMetrics>>,
     /// Performance metrics
     performance: Arc<Mutex<PerformanceMetrics>>,
     /// System metrics
@@ -158,6 +191,7 @@ impl MetricsCollector {
             network: Arc::new(Mutex::new(NetworkMetrics::default())),
             storage: Arc::new(Mutex::new(StorageMetrics::default())),
             rpc: Arc::new(Mutex::new(RpcMetrics::default())),
+            mempool: Arc::new(Mutex::new(MempoolMetrics::default())),
             performance: Arc::new(Mutex::new(PerformanceMetrics::default())),
             system: Arc::new(Mutex::new(SystemMetrics::default())),
             start_time: SystemTime::now(),
@@ -183,6 +217,7 @@ impl MetricsCollector {
             network: self.network.lock().unwrap().clone(),
             storage: self.storage.lock().unwrap().clone(),
             rpc: self.rpc.lock().unwrap().clone(),
+            mempool: self.mempool.lock().unwrap().clone(),
             performance: self.performance.lock().unwrap().clone(),
             system: system.clone(),
             timestamp,
@@ -216,6 +251,15 @@ impl MetricsCollector {
         f(&mut metrics);
     }
 
+    /// Update mempool metrics
+    pub fn update_mempool<F>(&self, f: F)
+    where
+        F: FnOnce(&mut MempoolMetrics),
+    {
+        let mut metrics = self.mempool.lock().unwrap();
+        f(&mut metrics);
+    }
+
     /// Update performance metrics
     pub fn update_performance<F>(&self, f: F)
     where
@@ -240,6 +284,11 @@ impl MetricsCollector {
         Arc::clone(&self.rpc)
     }
 
+    /// Get mempool metrics reference
+    pub fn mempool(&self) -> Arc<Mutex<MempoolMetrics>> {
+        Arc::clone(&self.mempool)
+    }
+
     /// Get performance metrics reference
     pub fn performance(&self) -> Arc<Mutex<PerformanceMetrics>> {
         Arc::clone(&self.performance)
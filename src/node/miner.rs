@@ -4,8 +4,14 @@
 
 use crate::utils::current_timestamp;
 use anyhow::Result;
-use bllvm_protocol::{Block, BlockHeader, Transaction};
-use std::collections::HashMap;
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::serialization::transaction::serialize_transaction;
+use bllvm_protocol::{Block, BlockHeader, Hash, OutPoint, Transaction};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Instant;
 use tracing::{debug, info, warn};
 
 /// Mempool provider trait for dependency injection
@@ -29,6 +35,124 @@ pub trait MempoolProvider: Send + Sync {
 
     /// Remove transaction from mempool
     fn remove_transaction(&mut self, hash: &[u8; 32]) -> bool;
+
+    /// Get the in-mempool transactions a transaction spends outputs of
+    fn get_transaction_parents(&self, hash: &[u8; 32]) -> Vec<[u8; 32]> {
+        let Some(tx) = self.get_transaction(hash) else {
+            return Vec::new();
+        };
+        let mut parents: Vec<[u8; 32]> = tx
+            .inputs
+            .iter()
+            .map(|input| input.prevout.hash)
+            .filter(|parent| self.get_transaction(parent).is_some())
+            .collect();
+        parents.sort();
+        parents.dedup();
+        parents
+    }
+
+    /// Get the mempool's chunks, best fee rate first, if it linearizes its
+    /// clusters
+    ///
+    /// Each chunk lists its transactions parents first, and comes after the
+    /// chunks holding its ancestors.
+    fn get_chunks(&self, _utxo_set: &bllvm_protocol::UtxoSet) -> Option<Vec<Vec<[u8; 32]>>> {
+        None
+    }
+}
+
+/// Weight reserved for the block header and coinbase transaction
+const COINBASE_RESERVED_WEIGHT: u64 = 4000;
+
+/// Bytes reserved for the block header and coinbase transaction
+const COINBASE_RESERVED_SIZE: usize = 1000;
+
+/// Coinbase script used until one is set: P2PKH to the all-zero key hash
+const DEFAULT_COINBASE_SCRIPT: [u8; 25] = [
+    0x76, 0xa9, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x88, 0xac,
+];
+
+/// Nonces tried per template by default
+pub const DEFAULT_MAX_TRIES: u64 = 1_000_000;
+
+/// Packages allowed to fail to fit a nearly full block before assembly stops
+const MAX_CONSECUTIVE_FAILURES: usize = 1000;
+
+/// Transaction weight
+///
+/// Mempool transactions carry no witness data, so every serialized byte counts
+/// four weight units.
+pub fn transaction_weight(tx: &Transaction) -> u64 {
+    serialize_transaction(tx).len() as u64 * 4
+}
+
+/// Virtual size for a weight, rounded up
+fn weight_to_vsize(weight: u64) -> u64 {
+    weight.div_ceil(4)
+}
+
+/// A transaction placed in a block template
+#[derive(Debug, Clone)]
+pub struct TemplateEntry {
+    pub txid: Hash,
+    pub tx: Transaction,
+    /// Fee in satoshis
+    pub fee: u64,
+    pub weight: u64,
+    /// In-mempool parents, all placed earlier in the template
+    pub depends: Vec<Hash>,
+}
+
+/// A mempool transaction not yet placed in the block
+struct Candidate {
+    tx: Transaction,
+    fee: u64,
+    weight: u64,
+    size: usize,
+    parents: Vec<Hash>,
+    ancestors: HashSet<Hash>,
+    descendants: HashSet<Hash>,
+    /// Totals over the transaction and its unplaced ancestors
+    package_fee: u64,
+    package_weight: u64,
+    package_size: usize,
+}
+
+impl Candidate {
+    fn score(&self, txid: Hash) -> PackageScore {
+        PackageScore {
+            fee: self.package_fee,
+            weight: self.package_weight,
+            txid,
+        }
+    }
+}
+
+/// Heap entry ordered by ancestor package fee rate
+///
+/// Entries go stale when an ancestor is placed; they are skipped when popped
+/// if they no longer match the candidate's package.
+#[derive(Debug, PartialEq, Eq)]
+struct PackageScore {
+    fee: u64,
+    weight: u64,
+    txid: Hash,
+}
+
+impl Ord for PackageScore {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.fee as u128 * other.weight as u128)
+            .cmp(&(other.fee as u128 * self.weight as u128))
+            .then_with(|| other.txid.cmp(&self.txid))
+    }
+}
+
+impl PartialOrd for PackageScore {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 /// Transaction selector for block building
@@ -37,7 +161,7 @@ pub struct TransactionSelector {
     max_block_size: usize,
     /// Maximum block weight
     max_block_weight: u64,
-    /// Minimum fee rate (satoshis per byte)
+    /// Minimum package fee rate (satoshis per vbyte)
     min_fee_rate: u64,
 }
 
@@ -53,7 +177,7 @@ impl TransactionSelector {
         Self {
             max_block_size: 1_000_000,   // 1MB
             max_block_weight: 4_000_000, // 4M weight units
-            min_fee_rate: 1,             // 1 satoshi per byte
+            min_fee_rate: 1,             // 1 satoshi per vbyte
         }
     }
 
@@ -67,69 +191,313 @@ impl TransactionSelector {
     }
 
     /// Select transactions for block
-    /// Note: Requires UTXO set for fee calculation - caller must provide it
+    ///
+    /// See [`Self::assemble`]; returns just the transactions, in block order.
     pub fn select_transactions(
         &self,
         mempool: &dyn MempoolProvider,
         utxo_set: &bllvm_protocol::UtxoSet,
     ) -> Vec<Transaction> {
-        let mut selected = Vec::new();
-        let mut current_size = 0;
-        let mut current_weight = 0;
+        self.assemble(mempool, utxo_set)
+            .into_iter()
+            .map(|entry| entry.tx)
+            .collect()
+    }
 
-        // Get prioritized transactions (with UTXO set for fee calculation)
-        let transactions = mempool.get_prioritized_transactions(1000, utxo_set);
+    /// Assemble block transactions by ancestor package fee rate
+    ///
+    /// Repeatedly places the transaction whose package (itself plus its
+    /// unplaced in-mempool ancestors) pays the highest fee rate, parents first,
+    /// so a high-fee child pulls in its low-fee parents (CPFP). A package that
+    /// does not fit is skipped and filling continues with the next one.
+    /// Assembly stops at the first package below the minimum fee rate.
+    ///
+    /// If the mempool provides chunks (see [`MempoolProvider::get_chunks`]),
+    /// they are taken whole in that order instead, skipping those below the
+    /// minimum fee rate or that do not fit.
+    ///
+    /// Fees use input values from `utxo_set`, or from the parent's outputs for
+    /// inputs spending in-mempool transactions. Transactions with an input
+    /// found in neither, and their descendants, are left out.
+    pub fn assemble(
+        &self,
+        mempool: &dyn MempoolProvider,
+        utxo_set: &bllvm_protocol::UtxoSet,
+    ) -> Vec<TemplateEntry> {
+        let candidates = Self::build_candidates(mempool, utxo_set);
+        match mempool.get_chunks(utxo_set) {
+            Some(chunks) => self.assemble_chunks(chunks, candidates),
+            None => self.assemble_packages(candidates),
+        }
+    }
 
-        for tx in transactions {
-            let tx_size = self.calculate_transaction_size(&tx);
-            let tx_weight = self.calculate_transaction_weight(&tx);
+    /// Place whole chunks in order
+    fn assemble_chunks(
+        &self,
+        chunks: Vec<Vec<Hash>>,
+        mut candidates: HashMap<Hash, Candidate>,
+    ) -> Vec<TemplateEntry> {
+        let mut selected = Vec::new();
+        let mut placed = HashSet::new();
+        let mut block_weight = COINBASE_RESERVED_WEIGHT;
+        let mut block_size = COINBASE_RESERVED_SIZE;
 
-            // Check if adding this transaction would exceed limits
-            if current_size + tx_size > self.max_block_size
-                || current_weight + tx_weight > self.max_block_weight
-            {
-                break;
+        for chunk in chunks {
+            if !chunk.iter().all(|txid| candidates.contains_key(txid)) {
+                continue;
+            }
+            // A chunk whose ancestors were skipped cannot be placed either
+            let members: HashSet<&Hash> = chunk.iter().collect();
+            let ready = chunk.iter().all(|txid| {
+                candidates[txid]
+                    .parents
+                    .iter()
+                    .all(|parent| placed.contains(parent) || members.contains(parent))
+            });
+            if !ready {
+                continue;
             }
 
-            // Check minimum fee rate
-            if self.calculate_fee_rate(&tx) < self.min_fee_rate {
+            let (fee, weight, size) = chunk.iter().fold((0, 0, 0), |(fee, weight, size), txid| {
+                let candidate = &candidates[txid];
+                (
+                    fee + candidate.fee,
+                    weight + candidate.weight,
+                    size + candidate.size,
+                )
+            });
+            let min_fee = self.min_fee_rate as u128 * weight_to_vsize(weight) as u128;
+            if (fee as u128) < min_fee
+                || block_weight + weight > self.max_block_weight
+                || block_size + size > self.max_block_size
+            {
                 continue;
             }
 
-            selected.push(tx);
-            current_size += tx_size;
-            current_weight += tx_weight;
+            block_weight += weight;
+            block_size += size;
+            for txid in chunk {
+                let candidate = candidates
+                    .remove(&txid)
+                    .expect("chunk members are candidates");
+                placed.insert(txid);
+                selected.push(TemplateEntry {
+                    txid,
+                    tx: candidate.tx,
+                    fee: candidate.fee,
+                    weight: candidate.weight,
+                    depends: candidate.parents,
+                });
+            }
         }
 
         selected
     }
 
-    /// Calculate transaction size in bytes
-    fn calculate_transaction_size(&self, tx: &Transaction) -> usize {
-        // Simplified calculation - in real implementation would serialize
-        tx.inputs.len() * 148 + tx.outputs.len() * 34 + 10
-    }
+    /// Place the best ancestor package until none is left
+    fn assemble_packages(&self, mut candidates: HashMap<Hash, Candidate>) -> Vec<TemplateEntry> {
+        let mut heap: BinaryHeap<PackageScore> = candidates
+            .iter()
+            .map(|(txid, candidate)| candidate.score(*txid))
+            .collect();
+
+        let mut selected = Vec::new();
+        let mut failed = HashSet::new();
+        let mut block_weight = COINBASE_RESERVED_WEIGHT;
+        let mut block_size = COINBASE_RESERVED_SIZE;
+        let mut consecutive_failures = 0;
+
+        while let Some(score) = heap.pop() {
+            let candidate = match candidates.get(&score.txid) {
+                Some(candidate) if !failed.contains(&score.txid) => candidate,
+                _ => continue,
+            };
+            if candidate.score(score.txid) != score {
+                continue;
+            }
+
+            let min_fee = self.min_fee_rate as u128 * weight_to_vsize(score.weight) as u128;
+            if (score.fee as u128) < min_fee {
+                break;
+            }
 
-    /// Calculate transaction weight
-    fn calculate_transaction_weight(&self, tx: &Transaction) -> u64 {
-        // Simplified calculation - in real implementation would use proper weight calculation
-        self.calculate_transaction_size(tx) as u64 * 4
+            if block_weight + candidate.package_weight > self.max_block_weight
+                || block_size + candidate.package_size > self.max_block_size
+            {
+                failed.insert(score.txid);
+                consecutive_failures += 1;
+                if consecutive_failures > MAX_CONSECUTIVE_FAILURES
+                    && block_weight + COINBASE_RESERVED_WEIGHT > self.max_block_weight
+                {
+                    break;
+                }
+                continue;
+            }
+            consecutive_failures = 0;
+
+            // Every ancestor has fewer ancestors than its descendants
+            let mut package: Vec<Hash> = candidate
+                .ancestors
+                .iter()
+                .filter(|txid| candidates.contains_key(*txid))
+                .copied()
+                .collect();
+            package.push(score.txid);
+            package.sort_by_key(|txid| (candidates[txid].ancestors.len(), *txid));
+
+            let mut updated = HashSet::new();
+            for txid in package {
+                let placed = match candidates.remove(&txid) {
+                    Some(placed) => placed,
+                    None => continue,
+                };
+                block_weight += placed.weight;
+                block_size += placed.size;
+                for descendant in &placed.descendants {
+                    if let Some(entry) = candidates.get_mut(descendant) {
+                        entry.package_fee -= placed.fee;
+                        entry.package_weight -= placed.weight;
+                        entry.package_size -= placed.size;
+                        updated.insert(*descendant);
+                    }
+                }
+                selected.push(TemplateEntry {
+                    txid,
+                    tx: placed.tx,
+                    fee: placed.fee,
+                    weight: placed.weight,
+                    depends: placed.parents,
+                });
+            }
+            for txid in updated {
+                if let Some(candidate) = candidates.get(&txid) {
+                    heap.push(candidate.score(txid));
+                }
+            }
+        }
+
+        selected
     }
 
-    /// Calculate fee rate (satoshis per byte)
-    fn calculate_fee_rate(&self, tx: &Transaction) -> u64 {
-        // Simplified calculation - in real implementation would calculate actual fees
-        let size = self.calculate_transaction_size(tx);
-        if size == 0 {
-            return 0;
+    /// Collect the mempool transactions whose fees can be computed, with their
+    /// ancestor packages
+    fn build_candidates(
+        mempool: &dyn MempoolProvider,
+        utxo_set: &bllvm_protocol::UtxoSet,
+    ) -> HashMap<Hash, Candidate> {
+        let transactions: HashMap<Hash, Transaction> = mempool
+            .get_transactions()
+            .into_iter()
+            .map(|tx| (calculate_tx_id(&tx), tx))
+            .collect();
+
+        let mut fees = HashMap::new();
+        let mut parents = HashMap::new();
+        for (txid, tx) in &transactions {
+            let tx_parents: Vec<Hash> = mempool
+                .get_transaction_parents(txid)
+                .into_iter()
+                .filter(|parent| transactions.contains_key(parent))
+                .collect();
+
+            let mut input_total = 0u64;
+            let mut resolved = true;
+            for input in tx.inputs.iter() {
+                let value = match utxo_set.get(&input.prevout) {
+                    Some(utxo) => Some(utxo.value as u64),
+                    None if tx_parents.contains(&input.prevout.hash) => transactions
+                        [&input.prevout.hash]
+                        .outputs
+                        .get(input.prevout.index as usize)
+                        .map(|output| output.value as u64),
+                    None => None,
+                };
+                match value {
+                    Some(value) => input_total += value,
+                    None => {
+                        resolved = false;
+                        break;
+                    }
+                }
+            }
+            let output_total: u64 = tx.outputs.iter().map(|out| out.value as u64).sum();
+            if resolved && input_total >= output_total {
+                fees.insert(*txid, input_total - output_total);
+            } else {
+                debug!(
+                    "Leaving {} out of block template: unknown inputs",
+                    hex::encode(txid)
+                );
+            }
+            parents.insert(*txid, tx_parents);
         }
 
-        // Mock fee calculation
-        let total_output_value: u64 = tx.outputs.iter().map(|out| out.value as u64).sum();
-        let total_input_value = total_output_value + 1000; // Mock input value
-        let fee = total_input_value - total_output_value;
+        // A transaction is only usable if all of its parents are
+        loop {
+            let orphaned: Vec<Hash> = fees
+                .keys()
+                .filter(|txid| parents[*txid].iter().any(|p| !fees.contains_key(p)))
+                .copied()
+                .collect();
+            if orphaned.is_empty() {
+                break;
+            }
+            for txid in orphaned {
+                fees.remove(&txid);
+            }
+        }
 
-        fee / size as u64
+        let mut children: HashMap<Hash, Vec<Hash>> = HashMap::new();
+        for txid in fees.keys() {
+            for parent in &parents[txid] {
+                children.entry(*parent).or_default().push(*txid);
+            }
+        }
+
+        let mut candidates: HashMap<Hash, Candidate> = fees
+            .iter()
+            .map(|(txid, fee)| {
+                let tx = transactions[txid].clone();
+                let weight = transaction_weight(&tx);
+                let candidate = Candidate {
+                    size: (weight / 4) as usize,
+                    fee: *fee,
+                    weight,
+                    parents: parents[txid].clone(),
+                    ancestors: related(txid, &parents),
+                    descendants: related(txid, &children),
+                    package_fee: 0,
+                    package_weight: 0,
+                    package_size: 0,
+                    tx,
+                };
+                (*txid, candidate)
+            })
+            .collect();
+
+        let packages: Vec<(Hash, u64, u64, usize)> = candidates
+            .iter()
+            .map(|(txid, candidate)| {
+                let mut fee = candidate.fee;
+                let mut weight = candidate.weight;
+                let mut size = candidate.size;
+                for ancestor in &candidate.ancestors {
+                    let ancestor = &candidates[ancestor];
+                    fee += ancestor.fee;
+                    weight += ancestor.weight;
+                    size += ancestor.size;
+                }
+                (*txid, fee, weight, size)
+            })
+            .collect();
+        for (txid, fee, weight, size) in packages {
+            let candidate = candidates.get_mut(&txid).expect("candidate exists");
+            candidate.package_fee = fee;
+            candidate.package_weight = weight;
+            candidate.package_size = size;
+        }
+
+        candidates
     }
 
     /// Get maximum block size
@@ -148,12 +516,82 @@ impl TransactionSelector {
     }
 }
 
+/// All transactions reachable from `txid` along `edges`
+fn related(txid: &Hash, edges: &HashMap<Hash, Vec<Hash>>) -> HashSet<Hash> {
+    let mut seen = HashSet::new();
+    let mut stack: Vec<Hash> = edges.get(txid).cloned().unwrap_or_default();
+    while let Some(next) = stack.pop() {
+        if seen.insert(next) {
+            if let Some(more) = edges.get(&next) {
+                stack.extend(more.iter().copied());
+            }
+        }
+    }
+    seen
+}
+
+/// Coinbase script for a block at `height`
+///
+/// Starts with the height as a script number (BIP34), followed by an OP_0
+/// extra nonce that keeps the script at least two bytes long.
+fn coinbase_script_sig(height: u64) -> Vec<u8> {
+    let mut script = Vec::new();
+    match height {
+        0 => script.push(0x00),                     // OP_0
+        1..=16 => script.push(0x50 + height as u8), // OP_1 to OP_16
+        _ => {
+            let mut number = height.to_le_bytes().to_vec();
+            while number.last() == Some(&0) {
+                number.pop();
+            }
+            // Keep the number positive
+            if number.last().is_some_and(|byte| byte & 0x80 != 0) {
+                number.push(0);
+            }
+            script.push(number.len() as u8);
+            script.extend(number);
+        }
+    }
+    script.push(0x00);
+    script
+}
+
+/// Try every `step`th nonce from `first_nonce` below `end_nonce` until one
+/// meets the header's target or another thread has found one
+///
+/// Returns the winning nonce, if any, and the number of hashes tried.
+fn grind_nonces(
+    mut header: BlockHeader,
+    first_nonce: u64,
+    step: u64,
+    end_nonce: u64,
+    found: &AtomicBool,
+) -> (Option<u64>, u64) {
+    use bllvm_protocol::pow::check_proof_of_work;
+    use std::sync::atomic::Ordering::Relaxed;
+
+    let mut tried = 0u64;
+    let mut nonce = first_nonce;
+    while nonce < end_nonce && !found.load(Relaxed) {
+        header.nonce = nonce;
+        tried += 1;
+        if check_proof_of_work(&header).unwrap_or(false) {
+            found.store(true, Relaxed);
+            return (Some(nonce), tried);
+        }
+        nonce += step;
+    }
+    (None, tried)
+}
+
 /// Mining engine for block mining
 pub struct MiningEngine {
     /// Mining enabled flag
     mining_enabled: bool,
     /// Mining threads
     mining_threads: u32,
+    /// Nonces tried per template before giving up
+    max_tries: u64,
     /// Current block template
     block_template: Option<Block>,
     /// Mining statistics
@@ -180,6 +618,7 @@ impl MiningEngine {
         Self {
             mining_enabled: false,
             mining_threads: 1,
+            max_tries: DEFAULT_MAX_TRIES,
             block_template: None,
             stats: MiningStats {
                 blocks_mined: 0,
@@ -195,6 +634,7 @@ impl MiningEngine {
         Self {
             mining_enabled: false,
             mining_threads: threads,
+            max_tries: DEFAULT_MAX_TRIES,
             block_template: None,
             stats: MiningStats {
                 blocks_mined: 0,
@@ -237,119 +677,66 @@ impl MiningEngine {
         self.mining_threads = threads;
     }
 
+    /// Get the number of nonces tried per template
+    pub fn get_max_tries(&self) -> u64 {
+        self.max_tries
+    }
+
+    /// Set the number of nonces tried per template (at most the 32-bit nonce space)
+    pub fn set_max_tries(&mut self, max_tries: u64) {
+        self.max_tries = max_tries;
+    }
+
     /// Mine a block template using actual proof of work (async, multithreaded)
+    ///
+    /// The engine's threads share the first `max_tries` nonces, and the first
+    /// valid header found stops the others. Fails if none of them meets the
+    /// target; the caller can then retry with a fresh template.
     pub async fn mine_template(&mut self, template: Block) -> Result<Block> {
+        use bllvm_protocol::mining::MiningResult;
+
         debug!("Mining block template with {} threads", self.mining_threads);
 
         // Update template
         self.block_template = Some(template.clone());
 
-        // Use consensus layer to mine the block (actual PoW)
-        use bllvm_protocol::ConsensusProof;
-        let consensus = ConsensusProof::new();
+        let threads = u64::from(self.mining_threads.max(1));
+        let end_nonce = self.max_tries.min(u64::from(u32::MAX) + 1);
+        let found = Arc::new(AtomicBool::new(false));
+        let started = Instant::now();
 
-        // Calculate max attempts per thread based on difficulty
-        // For regtest: low difficulty, should find nonce quickly
-        // For testnet/mainnet: high difficulty, may need many attempts
-        let max_attempts_per_thread = 1_000_000u64; // Reasonable limit per thread
-
-        // Multi-threaded mining: spawn tasks for each thread
-        if self.mining_threads > 1 {
-            self.mine_template_multithreaded(template, max_attempts_per_thread, &consensus)
-                .await
-        } else {
-            // Single-threaded: use blocking task to avoid blocking async runtime
-            let template_clone = template.clone();
-            let (mined_block, result) = tokio::task::spawn_blocking(move || {
-                consensus.mine_block(template_clone, max_attempts_per_thread)
-            })
-            .await
-            .map_err(|e| anyhow::anyhow!("Mining task panicked: {}", e))?
-            .map_err(|e| anyhow::anyhow!("Mining failed: {}", e))?;
-
-            self.handle_mining_result(mined_block, result)
-        }
-    }
-
-    /// Multi-threaded mining implementation
-    async fn mine_template_multithreaded(
-        &mut self,
-        template: Block,
-        max_attempts_per_thread: u64,
-        _consensus: &bllvm_protocol::ConsensusProof,
-    ) -> Result<Block> {
-        use bllvm_protocol::mining::MiningResult;
-        use bllvm_protocol::pow::check_proof_of_work;
-        use tokio::sync::oneshot;
-
-        // Calculate nonce range per thread
-        let nonces_per_thread = max_attempts_per_thread;
-        let total_threads = self.mining_threads as u64;
-
-        // Spawn mining tasks for each thread
+        // Spawn a blocking task per thread for the CPU-bound grinding
         let mut handles = Vec::new();
-        for thread_id in 0..total_threads {
-            let template_clone = template.clone();
-            let start_nonce = thread_id * nonces_per_thread;
-            let end_nonce = start_nonce + nonces_per_thread;
-
-            let (tx, rx) = oneshot::channel();
-
-            // Spawn blocking task for CPU-bound mining work
-            let handle = tokio::task::spawn_blocking(move || {
-                // Try nonces in this thread's range
-                for nonce in start_nonce..end_nonce {
-                    let mut block = template_clone.clone();
-                    block.header.nonce = nonce;
-
-                    // Check proof of work using standalone function
-                    if let Ok(valid) = check_proof_of_work(&block.header) {
-                        if valid {
-                            let _ = tx.send(Ok((block, MiningResult::Success)));
-                            return;
-                        }
-                    }
-                }
-
-                // No valid nonce found in this range
-                let mut block = template_clone;
-                block.header.nonce = start_nonce; // Use first nonce as placeholder
-                let _ = tx.send(Ok((block, MiningResult::Failure)));
-            });
-
-            handles.push((handle, rx));
+        for first_nonce in 0..threads {
+            let header = template.header.clone();
+            let found = Arc::clone(&found);
+            handles.push(tokio::task::spawn_blocking(move || {
+                grind_nonces(header, first_nonce, threads, end_nonce, &found)
+            }));
         }
 
-        // Wait for first successful result or all failures
-        let mut results = Vec::new();
-        for (handle, rx) in handles {
-            // Wait for task completion
-            handle
+        let mut solution = None;
+        let mut hashes = 0u64;
+        for handle in handles {
+            let (nonce, tried) = handle
                 .await
                 .map_err(|e| anyhow::anyhow!("Mining task panicked: {}", e))?;
+            hashes += tried;
+            solution = solution.or(nonce);
+        }
 
-            // Get result
-            match rx.await {
-                Ok(Ok((block, result))) => {
-                    if matches!(result, MiningResult::Success) {
-                        // Found valid nonce! Return immediately
-                        return self.handle_mining_result(block, result);
-                    }
-                    results.push((block, result));
-                }
-                Ok(Err(e)) => return Err(e),
-                Err(_) => {
-                    // Channel closed, task may have found solution
-                    continue;
-                }
-            }
+        let elapsed = started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.update_hashrate(hashes as f64 / elapsed);
         }
 
-        // All threads failed
-        if let Some((block, _)) = results.first() {
-            self.handle_mining_result(block.clone(), MiningResult::Failure)
-        } else {
-            Err(anyhow::anyhow!("Mining failed: all threads exhausted"))
+        let mut block = template;
+        match solution {
+            Some(nonce) => {
+                block.header.nonce = nonce;
+                self.handle_mining_result(block, MiningResult::Success)
+            }
+            None => self.handle_mining_result(block, MiningResult::Failure),
         }
     }
 
@@ -375,7 +762,7 @@ impl MiningEngine {
                 Ok(mined_block)
             }
             MiningResult::Failure => {
-                // Could not find valid nonce in max_attempts
+                // No nonce meets the target
                 // This is normal for high difficulty (mainnet)
                 warn!("Could not find valid nonce (difficulty may be too high)");
                 Err(anyhow::anyhow!("Mining failed: could not find valid nonce"))
@@ -414,6 +801,8 @@ pub struct MiningCoordinator {
     mempool: std::sync::Arc<crate::node::mempool::MempoolManager>,
     /// Storage for UTXO set access
     storage: Option<std::sync::Arc<crate::storage::Storage>>,
+    /// Script the coinbase pays to
+    coinbase_script_pubkey: Vec<u8>,
     /// Stratum V2 client (optional)
     #[cfg(feature = "stratum-v2")]
     stratum_v2_client: Option<crate::network::stratum_v2::client::StratumV2Client>,
@@ -430,6 +819,7 @@ impl MiningCoordinator {
             transaction_selector: TransactionSelector::new(),
             mempool,
             storage,
+            coinbase_script_pubkey: DEFAULT_COINBASE_SCRIPT.to_vec(),
             #[cfg(feature = "stratum-v2")]
             stratum_v2_client: None,
         }
@@ -453,6 +843,7 @@ impl MiningCoordinator {
             ),
             mempool,
             storage,
+            coinbase_script_pubkey: DEFAULT_COINBASE_SCRIPT.to_vec(),
             #[cfg(feature = "stratum-v2")]
             stratum_v2_client: None,
         }
@@ -475,6 +866,16 @@ impl MiningCoordinator {
         self.stratum_v2_client.as_ref()
     }
 
+    /// Set the script the coinbase of new templates pays to
+    pub fn set_coinbase_script_pubkey(&mut self, script_pubkey: Vec<u8>) {
+        self.coinbase_script_pubkey = script_pubkey;
+    }
+
+    /// Get the script the coinbase of new templates pays to
+    pub fn coinbase_script_pubkey(&self) -> &[u8] {
+        &self.coinbase_script_pubkey
+    }
+
     /// Start the mining coordinator
     pub async fn start(&mut self) -> Result<()> {
         info!("Starting mining coordinator");
@@ -527,9 +928,123 @@ impl MiningCoordinator {
     }
 
     /// Generate block template
+    ///
+    /// Takes mempool transactions by ancestor package fee rate. Entries whose
+    /// inputs are no longer available, such as transactions confirmed by a block
+    /// the mempool has not caught up with, are left out.
     pub async fn generate_block_template(&mut self) -> Result<Block> {
         debug!("Generating block template");
 
+        // Get UTXO set from storage for fee calculation
+        let utxo_set = self.template_utxo_set()?;
+
+        // Assemble mempool transactions by ancestor package fee rate
+        let entries = self
+            .transaction_selector
+            .assemble(&*self.mempool as &dyn MempoolProvider, &utxo_set);
+
+        // Parents are placed before their children, so one pass finds every
+        // entry that can still be spent
+        let mut created = HashSet::new();
+        let mut transactions = Vec::with_capacity(entries.len());
+        let mut total_fees = 0u64;
+        for entry in entries {
+            let spendable = entry.tx.inputs.iter().all(|input| {
+                utxo_set.contains_key(&input.prevout) || created.contains(&input.prevout)
+            });
+            if !spendable {
+                debug!(
+                    "Leaving {} out of the template: inputs not available",
+                    hex::encode(entry.txid)
+                );
+                continue;
+            }
+            created.extend((0..entry.tx.outputs.len()).map(|index| OutPoint {
+                hash: entry.txid,
+                index: index as u64,
+            }));
+            total_fees += entry.fee;
+            transactions.push(entry.tx);
+        }
+
+        self.build_template(transactions, total_fees).await
+    }
+
+    /// Generate a block template holding exactly `transactions`, in order
+    ///
+    /// Each transaction may spend the UTXO set or outputs of the transactions
+    /// before it, and the coinbase claims their fees.
+    pub async fn generate_block_template_with_transactions(
+        &mut self,
+        transactions: Vec<Transaction>,
+    ) -> Result<Block> {
+        debug!(
+            "Generating block template with {} given transactions",
+            transactions.len()
+        );
+
+        let utxo_set = self.template_utxo_set()?;
+        let mut created: HashMap<OutPoint, u64> = HashMap::new();
+        let mut spent = HashSet::new();
+        let mut total_fees = 0u64;
+        for tx in &transactions {
+            let txid = calculate_tx_id(tx);
+            let mut input_total = 0u64;
+            for input in tx.inputs.iter() {
+                if !spent.insert(input.prevout.clone()) {
+                    return Err(anyhow::anyhow!(
+                        "Transaction {} spends an output already spent in the block",
+                        hex::encode(txid)
+                    ));
+                }
+                let value = created
+                    .get(&input.prevout)
+                    .copied()
+                    .or_else(|| utxo_set.get(&input.prevout).map(|utxo| utxo.value as u64))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Transaction {} spends a missing output", hex::encode(txid))
+                    })?;
+                input_total += value;
+            }
+            let output_total: u64 = tx.outputs.iter().map(|out| out.value as u64).sum();
+            total_fees += input_total.checked_sub(output_total).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Transaction {} spends more than its inputs",
+                    hex::encode(txid)
+                )
+            })?;
+            for (index, output) in tx.outputs.iter().enumerate() {
+                let outpoint = OutPoint {
+                    hash: txid,
+                    index: index as u64,
+                };
+                created.insert(outpoint, output.value as u64);
+            }
+        }
+
+        self.build_template(transactions, total_fees).await
+    }
+
+    /// UTXO set templates are built against
+    fn template_utxo_set(&self) -> Result<bllvm_protocol::UtxoSet> {
+        if let Some(ref storage) = self.storage {
+            storage
+                .utxo_cache()
+                .get_all_utxos()
+                .map_err(|e| anyhow::anyhow!("Failed to get UTXO set: {}", e))
+        } else {
+            // No storage - use empty UTXO set (will result in 0 fees)
+            Ok(bllvm_protocol::UtxoSet::new())
+        }
+    }
+
+    /// Put a coinbase claiming `total_fees` in front of `transactions` and
+    /// build a header on the chain tip
+    async fn build_template(
+        &self,
+        transactions: Vec<Transaction>,
+        total_fees: u64,
+    ) -> Result<Block> {
         // Get chain tip from storage for prev_block_hash and difficulty
         let (prev_block_hash, bits, height) = if let Some(ref storage) = self.storage {
             if let Some(tip_header) = storage
@@ -557,25 +1072,9 @@ impl MiningCoordinator {
             ([0u8; 32], 0x1d00ffff, 0)
         };
 
-        // Get UTXO set from storage for fee calculation
-        let utxo_set = if let Some(ref storage) = self.storage {
-            storage
-                .utxos()
-                .get_all_utxos()
-                .map_err(|e| anyhow::anyhow!("Failed to get UTXO set: {}", e))?
-        } else {
-            // No storage - use empty UTXO set (will result in 0 fees)
-            bllvm_protocol::UtxoSet::new()
-        };
-
-        // Select transactions from mempool (with UTXO set for accurate fee calculation)
-        let transactions = self
-            .transaction_selector
-            .select_transactions(&*self.mempool as &dyn MempoolProvider, &utxo_set);
-
         // Create coinbase transaction with subsidy + fees
         let coinbase_tx = self
-            .create_coinbase_transaction(height + 1, &transactions, &utxo_set)
+            .create_coinbase_transaction(height + 1, total_fees)
             .await?;
 
         // Build transaction list (coinbase first)
@@ -587,13 +1086,14 @@ impl MiningCoordinator {
         let merkle_root = calculate_merkle_root(&all_transactions)
             .map_err(|e| anyhow::anyhow!("Failed to calculate merkle root: {}", e))?;
 
-        // Get current timestamp
-        let timestamp = current_timestamp();
+        // The timestamp must be past the median time of the last 11 blocks,
+        // which blocks generated in quick succession can catch up with
+        let timestamp = current_timestamp().max(self.median_time_past()? + 1);
 
         // Build block template
         let template = Block {
             header: BlockHeader {
-                version: 1,
+                version: 0x2000_0000, // BIP9 version bits, none signalled
                 prev_block_hash,
                 merkle_root,
                 timestamp,
@@ -609,12 +1109,30 @@ impl MiningCoordinator {
         Ok(template)
     }
 
+    /// Median timestamp of the last 11 blocks on the active chain (BIP113)
+    fn median_time_past(&self) -> Result<u64> {
+        let Some(ref storage) = self.storage else {
+            return Ok(0);
+        };
+        let mut timestamps: Vec<u64> = storage
+            .blocks()
+            .get_recent_headers(11)
+            .map_err(|e| anyhow::anyhow!("Failed to get recent headers: {}", e))?
+            .iter()
+            .map(|header| header.timestamp)
+            .collect();
+        timestamps.sort_unstable();
+        Ok(timestamps.get(timestamps.len() / 2).copied().unwrap_or(0))
+    }
+
     /// Create coinbase transaction with subsidy + fees
+    ///
+    /// The input commits to the block height (BIP34) and the output pays the
+    /// configured coinbase script.
     async fn create_coinbase_transaction(
         &self,
         height: u64,
-        selected_transactions: &[Transaction],
-        utxo_set: &bllvm_protocol::UtxoSet,
+        total_fees: u64,
     ) -> Result<Transaction> {
         use bllvm_protocol::ConsensusProof;
 
@@ -622,13 +1140,7 @@ impl MiningCoordinator {
         let consensus = ConsensusProof::new();
         let subsidy = consensus.get_block_subsidy(height) as u64;
 
-        // 2. Calculate total fees from selected transactions
-        let total_fees: u64 = selected_transactions
-            .iter()
-            .map(|tx| self.mempool.calculate_transaction_fee(tx, utxo_set))
-            .sum();
-
-        // 3. Coinbase value = subsidy + fees
+        // 2. Coinbase value = subsidy + fees of the selected transactions
         let coinbase_value = subsidy.checked_add(total_fees).ok_or_else(|| {
             anyhow::anyhow!(
                 "Coinbase value overflow: subsidy {} + fees {}",
@@ -642,16 +1154,20 @@ impl MiningCoordinator {
             height, subsidy, total_fees, coinbase_value
         );
 
-        // 4. Create coinbase transaction
+        // 3. Create coinbase transaction
         Ok(Transaction {
             version: 1,
-            inputs: bllvm_protocol::tx_inputs![],
+            inputs: bllvm_protocol::tx_inputs![bllvm_protocol::TransactionInput {
+                prevout: OutPoint {
+                    hash: [0u8; 32],
+                    index: 0xffffffff,
+                },
+                script_sig: coinbase_script_sig(height),
+                sequence: 0xffffffff,
+            }],
             outputs: bllvm_protocol::tx_outputs![bllvm_protocol::TransactionOutput {
                 value: coinbase_value as i64,
-                script_pubkey: vec![
-                    0x76, 0xa9, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x88, 0xac,
-                ],
+                script_pubkey: self.coinbase_script_pubkey.clone(),
             }],
             lock_time: 0,
         })
@@ -859,25 +1375,38 @@ mod tests {
         mempool.add_transaction(tx2);
         mempool.add_transaction(tx3);
 
-        let empty_utxo_set = bllvm_protocol::UtxoSet::new();
-        let selected = selector.select_transactions(&mempool, &empty_utxo_set);
+        // All three spend the same 10,000 sat output
+        let mut utxo_set = bllvm_protocol::UtxoSet::new();
+        utxo_set.insert(
+            bllvm_protocol::OutPoint {
+                hash: [0u8; 32],
+                index: 0,
+            },
+            bllvm_protocol::UTXO {
+                value: 10_000,
+                script_pubkey: vec![0x51],
+                height: 1,
+                is_coinbase: false,
+            },
+        );
+        let selected = selector.select_transactions(&mempool, &utxo_set);
         assert!(!selected.is_empty());
         assert!(selected.len() <= 3);
+
+        // Without input values no fee can be computed
+        let empty_utxo_set = bllvm_protocol::UtxoSet::new();
+        assert!(selector
+            .select_transactions(&mempool, &empty_utxo_set)
+            .is_empty());
     }
 
     #[test]
-    fn test_transaction_selector_size_calculation() {
-        let selector = TransactionSelector::new();
+    fn test_transaction_weight_uses_serialized_size() {
         let tx = create_test_transaction(1, 1000);
-
-        let size = selector.calculate_transaction_size(&tx);
-        assert!(size > 0);
-
-        let weight = selector.calculate_transaction_weight(&tx);
-        assert!(weight > 0);
-
-        let fee_rate = selector.calculate_fee_rate(&tx);
-        assert!(fee_rate > 0);
+        let weight = transaction_weight(&tx);
+        assert_eq!(weight, serialize_transaction(&tx).len() as u64 * 4);
+        assert_eq!(weight_to_vsize(weight), weight / 4);
+        assert_eq!(weight_to_vsize(5), 2);
     }
 
     #[test]
@@ -1215,9 +1744,11 @@ mod tests {
     async fn test_mining_coordinator_mempool_operations() {
         use std::sync::Arc;
         // Create mempool and add transaction before wrapping in Arc
-        let mut mempool_manager = crate::node::mempool::MempoolManager::new();
+        let mempool_manager = crate::node::mempool::MempoolManager::new();
         let tx = create_test_transaction(1, 1000);
-        let _ = mempool_manager.add_transaction(tx).await;
+        let _ = mempool_manager
+            .add_transaction(tx, &bllvm_protocol::UtxoSet::new())
+            .await;
         let mempool = Arc::new(mempool_manager);
         let coordinator = MiningCoordinator::new(mempool, None);
 
@@ -1228,9 +1759,11 @@ mod tests {
     async fn test_mining_coordinator_block_template_generation() {
         use std::sync::Arc;
         // Create mempool and add transaction before wrapping in Arc
-        let mut mempool_manager = crate::node::mempool::MempoolManager::new();
+        let mempool_manager = crate::node::mempool::MempoolManager::new();
         let tx = create_test_transaction(1, 1000);
-        let _ = mempool_manager.add_transaction(tx).await;
+        let _ = mempool_manager
+            .add_transaction(tx, &bllvm_protocol::UtxoSet::new())
+            .await;
         let mempool = Arc::new(mempool_manager);
 
         let mut coordinator = MiningCoordinator::new(mempool, None);
@@ -1239,8 +1772,9 @@ mod tests {
         assert!(template.is_ok());
 
         let block = template.unwrap();
-        assert_eq!(block.header.version, 1);
-        assert!(!block.transactions.is_empty()); // Should have coinbase + mempool tx
+        assert_eq!(block.header.version, 0x2000_0000);
+        // The mempool tx spends an output missing from the (empty) UTXO set
+        assert_eq!(block.transactions.len(), 1);
     }
 
     #[tokio::test]
@@ -1250,21 +1784,35 @@ mod tests {
         let coordinator = MiningCoordinator::new(mempool, None);
 
         // Test coinbase creation with no transactions (subsidy only)
-        let empty_utxo_set = bllvm_protocol::UtxoSet::new();
-        let coinbase = coordinator
-            .create_coinbase_transaction(0, &[], &empty_utxo_set)
-            .await;
+        let coinbase = coordinator.create_coinbase_transaction(0, 0).await;
         assert!(coinbase.is_ok());
 
         let tx = coinbase.unwrap();
         assert_eq!(tx.version, 1);
-        assert!(tx.inputs.is_empty()); // Coinbase has no inputs
+        // Coinbase input: null prevout, BIP34 height and extra nonce
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(tx.inputs[0].prevout.hash, [0u8; 32]);
+        assert_eq!(tx.inputs[0].prevout.index, 0xffffffff);
+        assert_eq!(tx.inputs[0].script_sig, vec![0x00, 0x00]);
         assert_eq!(tx.outputs.len(), 1);
         // Should be 50 BTC (subsidy) at height 0, with no fees
         assert_eq!(tx.outputs[0].value, 5000000000); // 50 BTC
         assert_eq!(tx.lock_time, 0);
     }
 
+    #[test]
+    fn test_coinbase_script_sig_encodes_height() {
+        assert_eq!(coinbase_script_sig(1), vec![0x51, 0x00]);
+        assert_eq!(coinbase_script_sig(16), vec![0x60, 0x00]);
+        assert_eq!(coinbase_script_sig(17), vec![0x01, 0x11, 0x00]);
+        // The sign bit needs an extra byte
+        assert_eq!(coinbase_script_sig(128), vec![0x02, 0x80, 0x00, 0x00]);
+        assert_eq!(
+            coinbase_script_sig(500_000),
+            vec![0x03, 0x20, 0xa1, 0x07, 0x00]
+        );
+    }
+
     // Helper functions for tests
     fn create_test_transaction(version: i32, output_value: u64) -> Transaction {
         use bllvm_protocol::{OutPoint, TransactionInput};
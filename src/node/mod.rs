@@ -3,33 +3,70 @@
 //! This module provides sync coordination, mempool management,
 //! mining coordination, and overall node state management.
 
+pub mod block_download;
 pub mod block_processor;
+pub mod cluster;
+#[cfg(kani)]
+pub mod cluster_proofs;
 pub mod event_publisher;
+pub mod fee_estimation;
 pub mod health;
+pub mod import;
 pub mod mempool;
 #[cfg(kani)]
 pub mod mempool_proofs;
 pub mod metrics;
 pub mod miner;
 pub mod performance;
+pub mod reorg;
 pub mod sync;
+pub mod truc;
 
 use anyhow::Result;
 use std::net::SocketAddr;
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 
-use crate::config::NodeConfig;
+use crate::config::{MempoolPolicyConfig, NodeConfig};
 use crate::module::api::NodeApiImpl;
 use crate::module::ModuleManager;
-use crate::network::NetworkManager;
+use crate::network::{addrman, NetworkManager};
 use crate::node::event_publisher::EventPublisher;
+use crate::node::mempool::MempoolRemovalReason;
 use crate::node::metrics::MetricsCollector;
 use crate::node::performance::PerformanceProfiler;
+use crate::node::reorg::ReorgResult;
 use crate::rpc::RpcManager;
+use crate::storage::chainstate::ChainParams;
+use crate::storage::reindex::ReindexMode;
+use crate::storage::snapshot::SnapshotStatus;
 use crate::storage::Storage;
-use bllvm_protocol::{BitcoinProtocolEngine, ProtocolVersion};
+use bllvm_protocol::segwit::Witness;
+use bllvm_protocol::{
+    BitcoinProtocolEngine, Block, Hash, OutPoint, ProtocolVersion, Transaction, UtxoSet,
+};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// How often the background indexes are brought up to the chain tip
+const INDEX_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many connected blocks between saves of the fee estimates
+const FEE_ESTIMATES_SAVE_INTERVAL: u64 = 6;
+
+/// How often the address manager is saved to `peers.dat`
+const PEERS_SAVE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// A block submitted over RPC (submitblock and the generate methods)
+///
+/// The node processes it like a block from the network and sends back the
+/// outcome.
+pub struct BlockSubmission {
+    pub block: Block,
+    pub witnesses: Vec<Witness>,
+    pub reply: oneshot::Sender<Result<sync::BlockProcessOutcome>>,
+}
 
 /// Main node orchestrator
 pub struct Node {
@@ -43,6 +80,8 @@ pub struct Node {
     /// Payment state machine for unified payment coordination
     payment_state_machine: Option<Arc<crate::payment::state_machine::PaymentStateMachine>>,
     rpc: RpcManager,
+    /// Blocks submitted over RPC, waiting to be processed
+    block_submissions: mpsc::UnboundedReceiver<BlockSubmission>,
     #[allow(dead_code)]
     sync_coordinator: sync::SyncCoordinator,
     mempool_manager: Arc<mempool::MempoolManager>,
@@ -81,14 +120,7 @@ impl Node {
         rpc_addr: SocketAddr,
         protocol_version: Option<ProtocolVersion>,
     ) -> Result<Self> {
-        Self::with_storage_config(
-            data_dir,
-            network_addr,
-            rpc_addr,
-            protocol_version,
-            None,
-            None,
-        )
+        Self::with_storage_config(data_dir, network_addr, rpc_addr, protocol_version, None)
     }
 
     /// Create a new node with storage configuration
@@ -97,8 +129,7 @@ impl Node {
         network_addr: SocketAddr,
         rpc_addr: SocketAddr,
         protocol_version: Option<ProtocolVersion>,
-        pruning_config: Option<crate::config::PruningConfig>,
-        indexing_config: Option<crate::config::IndexingConfig>,
+        storage_config: Option<&crate::config::StorageConfig>,
     ) -> Result<Self> {
         info!("Initializing reference-node");
 
@@ -110,12 +141,14 @@ impl Node {
         // Create storage with configuration
         use crate::storage::database::default_backend;
         let backend = default_backend();
-        let storage = Storage::with_backend_pruning_and_indexing(
-            data_dir,
-            backend,
-            pruning_config,
-            indexing_config,
-        )?;
+        let storage = match storage_config {
+            Some(config) => Storage::with_config(data_dir, backend, config)?,
+            None => Storage::with_backend(data_dir, backend)?,
+        };
+        // Record the network so UTXO snapshots can be checked against it
+        storage
+            .chain()
+            .store_chain_params(&ChainParams::for_network(network_name(protocol_version)))?;
         let storage_arc = Arc::new(storage);
         let mempool_manager_arc = Arc::new(mempool::MempoolManager::new());
 
@@ -128,11 +161,13 @@ impl Node {
         let network_arc = Arc::new(network);
         let metrics_arc = Arc::new(MetricsCollector::new());
         let profiler_arc = Arc::new(PerformanceProfiler::new(1000));
+        let (block_submitter, block_submissions) = mpsc::unbounded_channel();
         let rpc = RpcManager::new(rpc_addr)
             .with_metrics(Arc::clone(&metrics_arc))
             .with_profiler(Arc::clone(&profiler_arc))
             .with_dependencies(Arc::clone(&storage_arc), Arc::clone(&mempool_manager_arc))
-            .with_network_manager(Arc::clone(&network_arc));
+            .with_network_manager(Arc::clone(&network_arc))
+            .with_block_submitter(block_submitter);
         let sync_coordinator = sync::SyncCoordinator::default();
         let mining_coordinator = miner::MiningCoordinator::new(
             Arc::clone(&mempool_manager_arc),
@@ -147,6 +182,7 @@ impl Node {
             network: Arc::try_unwrap(network_arc)
                 .unwrap_or_else(|_| NetworkManager::new(network_addr)),
             rpc,
+            block_submissions,
             data_dir: PathBuf::from(data_dir),
             sync_coordinator,
             mempool_manager: mempool_manager_arc,
@@ -200,6 +236,15 @@ impl Node {
         self.network = network;
         self.config = Some(config.clone());
 
+        // Apply storage cache sizes
+        if let Some(cache_config) = config.storage.as_ref().and_then(|s| s.cache.as_ref()) {
+            self.storage.configure_cache(cache_config);
+            info!(
+                "Storage cache configuration applied: utxo_cache_mb={}, utxo_flush_interval_secs={}",
+                cache_config.utxo_cache_mb, cache_config.utxo_flush_interval_secs
+            );
+        }
+
         // Apply RBF and mempool policy configurations to mempool manager
         // Uses interior mutability so we can set configs even when mempool is in an Arc
         if let Some(ref rbf_config) = config.rbf {
@@ -216,6 +261,13 @@ impl Node {
             );
         }
 
+        // Block download timeouts come from the request timeout configuration
+        if let Some(ref timeouts) = config.request_timeouts {
+            self.sync_coordinator = sync::SyncCoordinator::new().with_block_download_config(
+                block_download::BlockDownloadConfig::from_timeouts(timeouts),
+            );
+        }
+
         #[cfg(feature = "governance")]
         {
             self.governance_webhook = governance_webhook;
@@ -287,6 +339,77 @@ impl Node {
     async fn start_components(&mut self) -> Result<()> {
         info!("Starting node components");
 
+        // Rebuild chain state from the stored blocks if configured
+        let reindex = self
+            .config
+            .as_ref()
+            .and_then(|c| c.storage.as_ref())
+            .and_then(|s| ReindexMode::from_config(s.reindex));
+        if let Some(mode) = reindex {
+            let summary =
+                self.storage
+                    .reindex(mode, &self.protocol, Some(self.metrics.as_ref()))?;
+            if let Some(hash) = summary.invalid_block {
+                warn!(
+                    "{} stopped at invalid block {}",
+                    mode.name(),
+                    hex::encode(hash)
+                );
+            }
+            info!(
+                "{} complete: {} blocks connected, tip height {:?}",
+                mode.name(),
+                summary.blocks_connected,
+                summary.tip_height
+            );
+        }
+
+        // Repair any disagreement between the chain tip, block index and UTXO set
+        // left by a crash
+        let report = self.storage.check_consistency()?;
+        if report.is_clean() {
+            debug!(
+                "Storage consistency check passed (tip height {:?})",
+                report.tip_height
+            );
+        }
+
+        // Catch the background indexes up to the tip and keep them there
+        self.spawn_index_sync();
+
+        // Pick up the fee estimates from the last run
+        let fee_estimates = self.data_dir.join(fee_estimation::FEE_ESTIMATES_FILE);
+        match self.mempool_manager.fee_estimator().load(&fee_estimates) {
+            Ok(true) => info!("Loaded fee estimates from {}", fee_estimates.display()),
+            Ok(false) => {}
+            Err(e) => warn!("Failed to load fee estimates: {}", e),
+        }
+
+        // Save the mempool periodically so it survives restarts
+        if self.mempool_persistence().is_some() {
+            let path = self.mempool_manager.persistence_path();
+            if path.exists() {
+                // Re-validate the saved transactions against the current tip
+                let loaded = match mempool::MempoolManager::saved_prevouts(&path)
+                    .and_then(|prevouts| self.utxo_view(prevouts))
+                {
+                    Ok(utxo_set) => self.mempool_manager.load_from_disk(&path, &utxo_set).await,
+                    Err(e) => Err(e),
+                };
+                match loaded {
+                    Ok(stats) => {
+                        let mempool = &self.mempool_manager;
+                        self.metrics.update_mempool(|m| {
+                            m.transaction_count = mempool.size();
+                        });
+                        debug!("Mempool load stats: {:?}", stats);
+                    }
+                    Err(e) => warn!("Failed to load mempool from {}: {}", path.display(), e),
+                }
+            }
+            self.spawn_mempool_autosave();
+        }
+
         // Simplified component startup
         // In a real implementation, each component would be started in separate tasks
         // For now, we'll just initialize them
@@ -297,6 +420,21 @@ impl Node {
         info!("Mempool manager initialized");
         info!("Mining coordinator initialized");
 
+        // Pick up the addresses known from the last run
+        let peers_file = self.data_dir.join(addrman::PEERS_FILE);
+        match self
+            .network
+            .address_manager()
+            .write()
+            .await
+            .load(&peers_file)
+        {
+            Ok(true) => info!("Loaded peer addresses from {}", peers_file.display()),
+            Ok(false) => {}
+            Err(e) => warn!("Failed to load peer addresses: {}", e),
+        }
+        self.spawn_peers_autosave();
+
         // Start network manager
         if let Err(e) = self.network.start(self.network_addr).await {
             warn!("Failed to start network manager: {}", e);
@@ -599,6 +737,86 @@ impl Node {
         Ok(())
     }
 
+    /// Periodically bring the background indexes up to the chain tip
+    ///
+    /// In-sync indexes are updated with each connected block; this catches up
+    /// indexes that were enabled later, fell behind on an error or are
+    /// configured for background indexing.
+    fn spawn_index_sync(&self) {
+        let storage = Arc::clone(&self.storage);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(INDEX_SYNC_INTERVAL);
+            loop {
+                interval.tick().await;
+                let storage = Arc::clone(&storage);
+                match tokio::task::spawn_blocking(move || storage.sync_indexes()).await {
+                    Ok(Ok(blocks)) if blocks > 0 => debug!("Indexes synced {} blocks", blocks),
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => warn!("{}", e),
+                    Err(e) => {
+                        warn!("Index sync task stopped: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Mempool policy, if mempool persistence is enabled
+    fn mempool_persistence(&self) -> Option<&MempoolPolicyConfig> {
+        self.config
+            .as_ref()
+            .and_then(|c| c.mempool.as_ref())
+            .filter(|policy| policy.persist_mempool)
+    }
+
+    /// Periodically save the address manager to `peers.dat`
+    fn spawn_peers_autosave(&self) {
+        let address_manager = Arc::clone(self.network.address_manager());
+        let path = self.data_dir.join(addrman::PEERS_FILE);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PEERS_SAVE_INTERVAL);
+            // The first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = address_manager.read().await.save(&path) {
+                    warn!("Failed to save peer addresses: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Periodically save the mempool (`mempool_save_interval_secs`)
+    fn spawn_mempool_autosave(&self) {
+        let Some(interval_secs) = self
+            .mempool_persistence()
+            .map(|policy| policy.mempool_save_interval_secs)
+            .filter(|secs| *secs > 0)
+        else {
+            return;
+        };
+        let mempool = Arc::clone(&self.mempool_manager);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+            // The first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let mempool = Arc::clone(&mempool);
+                let path = mempool.persistence_path();
+                match tokio::task::spawn_blocking(move || mempool.save_to_disk(&path)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("Failed to save mempool: {}", e),
+                    Err(e) => {
+                        warn!("Mempool autosave task stopped: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
     /// Initialize peer connections automatically
     ///
     /// Determines network type from protocol version and uses config if available.
@@ -664,9 +882,10 @@ impl Node {
         // Set up graceful shutdown signal handling
         let shutdown_rx = crate::utils::create_shutdown_receiver();
 
-        // Get initial state for block processing
-        let mut current_height = self.storage.chain().get_height()?.unwrap_or(0);
-        let mut utxo_set = bllvm_protocol::UtxoSet::new();
+        // Resume downloading blocks of headers indexed before the restart
+        if let Err(e) = self.sync_coordinator.update_block_download(&self.storage) {
+            warn!("Failed to update block download: {}", e);
+        }
 
         // Main node loop - coordinates between all components and handles shutdown signals
         loop {
@@ -675,225 +894,49 @@ impl Node {
                 info!("Shutdown signal received, stopping node gracefully...");
                 break;
             }
-            // Process any received blocks (non-blocking)
-            while let Some(block_data) = self.network.try_recv_block() {
-                info!("Processing block from network");
-                let blocks_arc = self.storage.blocks();
-                match self.sync_coordinator.process_block(
-                    &blocks_arc,
-                    &self.protocol,
-                    Some(&self.storage),
-                    &block_data,
-                    current_height,
-                    &mut utxo_set,
-                    Some(Arc::clone(&self.metrics)),
-                    Some(Arc::clone(&self.profiler)),
-                ) {
-                    Ok(true) => {
-                        info!("Block accepted at height {}", current_height);
-
-                        // Parse block for governance webhook (need block object, not just block_data)
-                        // We'll get it from storage after it's stored
-                        let blocks_arc = self.storage.blocks();
-                        let block_hash =
-                            if let Ok(Some(hash)) = blocks_arc.get_hash_by_height(current_height) {
-                                hash
-                            } else {
-                                warn!("Failed to get block hash for height {}", current_height);
-                                [0u8; 32]
-                            };
-
-                        // Update chain tip (for chainwork, etc.)
-                        if let Ok(Some(block)) = blocks_arc.get_block(&block_hash) {
-                            if let Err(e) = self.storage.chain().update_tip(
-                                &block_hash,
-                                &block.header,
-                                current_height,
-                            ) {
-                                warn!("Failed to update chain tip: {}", e);
-                            }
-
-                            // Update UTXO stats cache (for fast gettxoutsetinfo RPC)
-                            let transaction_count =
-                                self.storage.transaction_count().unwrap_or(0) as u64;
-                            if let Err(e) = self.storage.chain().update_utxo_stats_cache(
-                                &block_hash,
-                                current_height,
-                                &utxo_set,
-                                transaction_count,
-                            ) {
-                                warn!("Failed to update UTXO stats cache: {}", e);
-                            }
-
-                            // Update network hashrate cache (for fast getmininginfo RPC)
-                            if let Err(e) = self
-                                .storage
-                                .chain()
-                                .calculate_and_cache_network_hashrate(current_height, &blocks_arc)
-                            {
-                                warn!("Failed to update network hashrate cache: {}", e);
-                            }
-
-                            // Notify governance app about new block (for fee forwarding tracking)
-                            #[cfg(feature = "governance")]
-                            if let Some(ref webhook) = self.governance_webhook {
-                                if let Err(e) = webhook.notify_block(&block, current_height).await {
-                                    warn!("Failed to notify governance app about block at height {}: {}", current_height, e);
-                                }
-                            }
-                        }
-
-                        // Persist UTXO set to storage after block validation
-                        // This is critical for commitment generation and incremental pruning
-                        if let Err(e) = self.storage.utxos().store_utxo_set(&utxo_set) {
-                            warn!(
-                                "Failed to persist UTXO set after block {}: {}",
-                                current_height, e
-                            );
-                        }
-
-                        // Generate UTXO commitment from current state (if enabled)
-                        // Use current_height (the block that was just validated) before incrementing
-                        #[cfg(feature = "utxo-commitments")]
-                        {
-                            if let Some(pruning_manager) = self.storage.pruning() {
-                                if let (Some(commitment_store), Some(_utxostore)) = (
-                                    pruning_manager.commitment_store(),
-                                    pruning_manager.utxostore(),
-                                ) {
-                                    // Get block hash from storage (block was just stored at current_height)
-                                    let blocks_arc = self.storage.blocks();
-                                    if let Ok(Some(block_hash)) =
-                                        blocks_arc.get_hash_by_height(current_height)
-                                    {
-                                        // Generate commitment from current UTXO set state
-                                        if let Err(e) = pruning_manager
-                                            .generate_commitment_from_current_state(
-                                                &block_hash,
-                                                current_height,
-                                                &utxo_set,
-                                                &commitment_store,
-                                            )
-                                        {
-                                            warn!(
-                                                "Failed to generate commitment for block {}: {}",
-                                                current_height, e
-                                            );
-                                        } else {
-                                            debug!(
-                                                "Generated UTXO commitment for block {}",
-                                                current_height
-                                            );
-                                        }
-                                    } else {
-                                        warn!("Could not find block hash for height {} to generate commitment", current_height);
-                                    }
-                                }
-                            }
-                        }
-
-                        // Increment height after processing
-                        current_height += 1;
-
-                        // Check for incremental pruning during IBD
-                        // Consider IBD if we're still syncing (height < tip or no recent blocks)
-                        let is_ibd = current_height < 1000; // Simple heuristic: consider IBD if < 1000 blocks
-                        if let Some(pruning_manager) = self.storage.pruning() {
-                            if let Ok(Some(prune_stats)) =
-                                pruning_manager.incremental_prune_during_ibd(current_height, is_ibd)
-                            {
-                                info!("Incremental pruning during IBD: {} blocks pruned, {} bytes freed", 
-                                      prune_stats.blocks_pruned, prune_stats.storage_freed);
-                                // Flush storage to persist pruning changes
-                                if let Err(e) = self.storage.flush() {
-                                    warn!(
-                                        "Failed to flush storage after incremental pruning: {}",
-                                        e
-                                    );
-                                }
-                            }
-                        }
-
-                        // Check for automatic pruning after block acceptance
-                        if let Some(pruning_manager) = self.storage.pruning() {
-                            let stats = pruning_manager.get_stats();
-                            let should_prune = pruning_manager
-                                .should_auto_prune(current_height, stats.last_prune_height);
-
-                            if should_prune {
-                                info!("Automatic pruning triggered at height {}", current_height);
-
-                                // Calculate prune height based on configuration
-                                let prune_height = match &pruning_manager.config.mode {
-                                    crate::config::PruningMode::Disabled => None,
-                                    crate::config::PruningMode::Normal {
-                                        keep_from_height, ..
-                                    } => {
-                                        // Prune to keep_from_height, but ensure we keep min_blocks
-                                        let min_keep = pruning_manager.config.min_blocks_to_keep;
-                                        let effective_keep = (*keep_from_height)
-                                            .max(current_height.saturating_sub(min_keep));
-                                        Some(effective_keep)
-                                    }
-                                    #[cfg(feature = "utxo-commitments")]
-                                    crate::config::PruningMode::Aggressive {
-                                        keep_from_height,
-                                        min_blocks,
-                                        ..
-                                    } => {
-                                        // Prune to keep_from_height, respecting min_blocks
-                                        let effective_keep = (*keep_from_height)
-                                            .max(current_height.saturating_sub(*min_blocks));
-                                        Some(effective_keep)
-                                    }
-                                    #[cfg(not(feature = "utxo-commitments"))]
-                                    crate::config::PruningMode::Aggressive { .. } => {
-                                        // Aggressive pruning requires utxo-commitments feature
-                                        // Fall back to no pruning if feature is disabled
-                                        None
-                                    }
-                                    crate::config::PruningMode::Custom {
-                                        keep_bodies_from_height,
-                                        ..
-                                    } => {
-                                        // Prune to keep_bodies_from_height, respecting min_blocks
-                                        let min_keep = pruning_manager.config.min_blocks_to_keep;
-                                        let effective_keep = (*keep_bodies_from_height)
-                                            .max(current_height.saturating_sub(min_keep));
-                                        Some(effective_keep)
-                                    }
-                                };
-
-                                if let Some(prune_to_height) = prune_height {
-                                    if prune_to_height < current_height {
-                                        match pruning_manager.prune_to_height(
-                                            prune_to_height,
-                                            current_height,
-                                            false,
-                                        ) {
-                                            Ok(prune_stats) => {
-                                                info!("Automatic pruning completed: {} blocks pruned, {} blocks kept", 
-                                                      prune_stats.blocks_pruned, prune_stats.blocks_kept);
-                                                // Flush storage to persist pruning changes
-                                                use crate::utils::log_error;
-                                                log_error(|| self.storage.flush(), "Failed to flush storage after automatic pruning");
-                                            }
-                                            Err(e) => {
-                                                warn!("Automatic pruning failed: {}", e);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
+            // Add headers received from peers to the block index, and queue
+            // the blocks of new headers for download
+            while let Some((peer_addr, headers)) = self.network.try_recv_headers() {
+                match self
+                    .sync_coordinator
+                    .process_headers(&self.storage, &headers)
+                {
+                    Ok(outcome) if outcome.accepted > 0 => {
+                        if let Err(e) = self.sync_coordinator.update_block_download(&self.storage) {
+                            warn!("Failed to update block download: {}", e);
                         }
                     }
-                    Ok(false) => {
-                        warn!("Block rejected at height {}", current_height);
-                    }
-                    Err(e) => {
+                    Ok(_) => {}
+                    Err(e) => warn!("Invalid headers from {}: {}", peer_addr, e),
+                }
+            }
+
+            // Take any received blocks (non-blocking). Blocks being downloaded
+            // are held back until they can be connected in chain order.
+            let mut ready_blocks = Vec::new();
+            while let Some(block_data) = self.network.try_recv_block() {
+                ready_blocks.extend(self.sync_coordinator.receive_block(block_data));
+            }
+            if !ready_blocks.is_empty() {
+                for block_data in ready_blocks {
+                    info!("Processing block from network");
+                    if let Err(e) = self.process_block(&block_data).await {
                         warn!("Error processing block: {}", e);
                     }
                 }
+                // Move the download window past the connected blocks
+                if let Err(e) = self.sync_coordinator.update_block_download(&self.storage) {
+                    warn!("Failed to update block download: {}", e);
+                }
+            }
+
+            // Blocks submitted over RPC take the same path
+            while let Ok(submission) = self.block_submissions.try_recv() {
+                let outcome = self
+                    .process_parsed_block(&submission.block, &submission.witnesses)
+                    .await;
+                // The RPC caller may have gone away
+                let _ = submission.reply.send(outcome);
             }
 
             // Process other network messages (non-blocking, processes one message if available)
@@ -929,6 +972,9 @@ impl Node {
                     }
                 }
             }
+
+            // Request blocks in the download window and drop stalling peers
+            self.request_blocks().await;
         }
 
         // Graceful shutdown - stop all components
@@ -937,6 +983,432 @@ impl Node {
         Ok(())
     }
 
+    /// Process a block received from the network
+    ///
+    /// The block is connected, or stored on a side branch and the chain
+    /// reorganized onto it if it has more work. The mempool, fee estimates
+    /// and notifications then follow the new tip.
+    pub async fn process_block(&mut self, block_data: &[u8]) -> Result<sync::BlockProcessOutcome> {
+        let (block, witnesses) = block_processor::parse_block_from_wire(block_data)?;
+        self.process_parsed_block(&block, &witnesses).await
+    }
+
+    /// Process a block that has already been parsed, such as one submitted
+    /// over RPC
+    pub async fn process_parsed_block(
+        &mut self,
+        block: &Block,
+        witnesses: &[Witness],
+    ) -> Result<sync::BlockProcessOutcome> {
+        // The next height to connect and the UTXO cache blocks are validated
+        // against. The tip can also move outside the main loop (loadtxoutset).
+        let mut current_height = self
+            .storage
+            .chain()
+            .get_height()?
+            .map(|height| height + 1)
+            .unwrap_or(0);
+        let mut utxo_cache = self.storage.utxo_cache();
+        let blocks_arc = self.storage.blocks();
+        let outcome = self.sync_coordinator.process_parsed_block(
+            &blocks_arc,
+            &self.protocol,
+            Some(&self.storage),
+            block,
+            witnesses,
+            current_height,
+            &mut utxo_cache,
+            Some(Arc::clone(&self.metrics)),
+            Some(Arc::clone(&self.profiler)),
+        )?;
+        match &outcome {
+            sync::BlockProcessOutcome::Connected { .. } => {
+                info!("Block accepted at height {}", current_height);
+
+                // Parse block for governance webhook (need block object, not just block_data)
+                // We'll get it from storage after it's stored
+                let blocks_arc = self.storage.blocks();
+                let block_hash =
+                    if let Ok(Some(hash)) = blocks_arc.get_hash_by_height(current_height) {
+                        hash
+                    } else {
+                        warn!("Failed to get block hash for height {}", current_height);
+                        [0u8; 32]
+                    };
+
+                // Chain tip and chainwork were committed with the block and the
+                // UTXO changes applied to the cache. UTXO set statistics come
+                // from the coinstats index, or a full scan in gettxoutsetinfo.
+                if let Ok(Some(block)) = blocks_arc.get_block(&block_hash) {
+                    // Update network hashrate cache (for fast getmininginfo RPC)
+                    if let Err(e) = self
+                        .storage
+                        .chain()
+                        .calculate_and_cache_network_hashrate(current_height, &blocks_arc)
+                    {
+                        warn!("Failed to update network hashrate cache: {}", e);
+                    }
+
+                    // Record the confirmations for fee estimation, before the
+                    // confirmed transactions leave the mempool
+                    use bllvm_protocol::block::calculate_tx_id;
+                    let txids: Vec<_> = block.transactions.iter().map(calculate_tx_id).collect();
+                    self.mempool_manager
+                        .fee_estimator()
+                        .process_block(current_height, &txids);
+                    if current_height % FEE_ESTIMATES_SAVE_INTERVAL == 0 {
+                        self.save_fee_estimates();
+                    }
+
+                    // Remove the mempool transactions the block confirms or
+                    // conflicts with, and re-process the orphans it unblocks
+                    self.update_mempool_for_block(&block, current_height).await;
+
+                    let mempool = &self.mempool_manager;
+                    self.metrics.update_mempool(|m| {
+                        m.transaction_count = mempool.size();
+                        m.orphan_count = mempool.orphan_count();
+                        m.orphan_bytes = mempool.orphan_bytes();
+                    });
+
+                    // Notify governance app about new block (for fee forwarding tracking)
+                    #[cfg(feature = "governance")]
+                    if let Some(ref webhook) = self.governance_webhook {
+                        if let Err(e) = webhook.notify_block(&block, current_height).await {
+                            warn!(
+                                "Failed to notify governance app about block at height {}: {}",
+                                current_height, e
+                            );
+                        }
+                    }
+                }
+
+                // Generate UTXO commitment from current state (if enabled)
+                // Use current_height (the block that was just validated) before incrementing
+                #[cfg(feature = "utxo-commitments")]
+                {
+                    if let Some(pruning_manager) = self.storage.pruning() {
+                        if let (Some(commitment_store), Some(_utxostore)) = (
+                            pruning_manager.commitment_store(),
+                            pruning_manager.utxostore(),
+                        ) {
+                            // Get block hash from storage (block was just stored at current_height)
+                            let blocks_arc = self.storage.blocks();
+                            if let Ok(Some(block_hash)) =
+                                blocks_arc.get_hash_by_height(current_height)
+                            {
+                                // Generate commitment from current UTXO set state
+                                if let Err(e) = utxo_cache.get_all_utxos().and_then(|utxo_set| {
+                                    pruning_manager.generate_commitment_from_current_state(
+                                        &block_hash,
+                                        current_height,
+                                        &utxo_set,
+                                        &commitment_store,
+                                    )
+                                }) {
+                                    warn!(
+                                        "Failed to generate commitment for block {}: {}",
+                                        current_height, e
+                                    );
+                                } else {
+                                    debug!(
+                                        "Generated UTXO commitment for block {}",
+                                        current_height
+                                    );
+                                }
+                            } else {
+                                warn!("Could not find block hash for height {} to generate commitment", current_height);
+                            }
+                        }
+                    }
+                }
+
+                // Increment height after processing
+                current_height += 1;
+
+                // Check for incremental pruning during IBD
+                // Consider IBD if we're still syncing (height < tip or no recent blocks)
+                let is_ibd = current_height < 1000; // Simple heuristic: consider IBD if < 1000 blocks
+                if let Some(pruning_manager) = self.storage.pruning() {
+                    if let Ok(Some(prune_stats)) =
+                        pruning_manager.incremental_prune_during_ibd(current_height, is_ibd)
+                    {
+                        info!(
+                            "Incremental pruning during IBD: {} blocks pruned, {} bytes freed",
+                            prune_stats.blocks_pruned, prune_stats.storage_freed
+                        );
+                        // Flush storage to persist pruning changes
+                        if let Err(e) = self.storage.flush() {
+                            warn!("Failed to flush storage after incremental pruning: {}", e);
+                        }
+                    }
+                }
+
+                // Check for automatic pruning after block acceptance
+                if let Some(pruning_manager) = self.storage.pruning() {
+                    let stats = pruning_manager.get_stats();
+                    let should_prune =
+                        pruning_manager.should_auto_prune(current_height, stats.last_prune_height);
+
+                    if should_prune {
+                        info!("Automatic pruning triggered at height {}", current_height);
+
+                        // Calculate prune height based on configuration
+                        let prune_height = match &pruning_manager.config.mode {
+                            crate::config::PruningMode::Disabled => None,
+                            crate::config::PruningMode::Normal {
+                                keep_from_height, ..
+                            } => {
+                                // Prune to keep_from_height, but ensure we keep min_blocks
+                                let min_keep = pruning_manager.config.min_blocks_to_keep;
+                                let effective_keep = (*keep_from_height)
+                                    .max(current_height.saturating_sub(min_keep));
+                                Some(effective_keep)
+                            }
+                            #[cfg(feature = "utxo-commitments")]
+                            crate::config::PruningMode::Aggressive {
+                                keep_from_height,
+                                min_blocks,
+                                ..
+                            } => {
+                                // Prune to keep_from_height, respecting min_blocks
+                                let effective_keep = (*keep_from_height)
+                                    .max(current_height.saturating_sub(*min_blocks));
+                                Some(effective_keep)
+                            }
+                            #[cfg(not(feature = "utxo-commitments"))]
+                            crate::config::PruningMode::Aggressive { .. } => {
+                                // Aggressive pruning requires utxo-commitments feature
+                                // Fall back to no pruning if feature is disabled
+                                None
+                            }
+                            crate::config::PruningMode::Custom {
+                                keep_bodies_from_height,
+                                ..
+                            } => {
+                                // Prune to keep_bodies_from_height, respecting min_blocks
+                                let min_keep = pruning_manager.config.min_blocks_to_keep;
+                                let effective_keep = (*keep_bodies_from_height)
+                                    .max(current_height.saturating_sub(min_keep));
+                                Some(effective_keep)
+                            }
+                        };
+
+                        if let Some(prune_to_height) = prune_height {
+                            if prune_to_height < current_height {
+                                match pruning_manager.prune_to_height(
+                                    prune_to_height,
+                                    current_height,
+                                    false,
+                                ) {
+                                    Ok(prune_stats) => {
+                                        info!("Automatic pruning completed: {} blocks pruned, {} blocks kept", 
+                                              prune_stats.blocks_pruned, prune_stats.blocks_kept);
+                                        // Flush storage to persist pruning changes
+                                        use crate::utils::log_error;
+                                        log_error(
+                                            || self.storage.flush(),
+                                            "Failed to flush storage after automatic pruning",
+                                        );
+                                    }
+                                    Err(e) => {
+                                        warn!("Automatic pruning failed: {}", e);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            sync::BlockProcessOutcome::Reorganized(reorg) => {
+                info!(
+                    "Chain reorganized: {} blocks disconnected, {} connected, new height {}",
+                    reorg.disconnected.len(),
+                    reorg.connected.len(),
+                    reorg.new_height
+                );
+
+                if let Err(e) = self.update_mempool_for_reorg(reorg).await {
+                    warn!("Failed to update mempool after reorganization: {}", e);
+                }
+
+                if let Some(ref event_publisher) = self.event_publisher {
+                    for (hash, height) in &reorg.disconnected {
+                        event_publisher
+                            .publish_block_disconnected(hash, *height)
+                            .await;
+                    }
+                    event_publisher
+                        .publish_chain_reorg(&reorg.old_tip, &reorg.new_tip)
+                        .await;
+                }
+            }
+            sync::BlockProcessOutcome::SideBranch { hash, height } => {
+                debug!(
+                    "Block {} stored on side branch at height {}",
+                    hex::encode(hash),
+                    height
+                );
+            }
+            sync::BlockProcessOutcome::BackgroundValidated { height, status, .. } => match status {
+                SnapshotStatus::Unvalidated => {
+                    debug!("Validated snapshot history up to height {}", height)
+                }
+                SnapshotStatus::Validated => {
+                    info!(
+                        "Snapshot validated: history up to height {} matches",
+                        height
+                    )
+                }
+                SnapshotStatus::Invalid => {
+                    error!(
+                        "Snapshot is invalid: history up to height {} does not match it; continuing from the chain state validated from genesis",
+                        height
+                    );
+                    // Its transactions were accepted against the snapshot's UTXO set
+                    self.mempool_manager.clear();
+                }
+            },
+            sync::BlockProcessOutcome::AlreadyKnown => {
+                debug!("Ignoring already connected block");
+            }
+            sync::BlockProcessOutcome::Rejected => {
+                warn!("Block rejected at height {}", current_height);
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Update the mempool for a connected block
+    ///
+    /// Removes the transactions the block confirms or conflicts with, and
+    /// adds the orphans whose parents it confirms. `height` is the block's.
+    async fn update_mempool_for_block(&self, block: &Block, height: u64) {
+        let removed = self.mempool_manager.remove_for_block(&block.transactions);
+        if !removed.is_empty() {
+            debug!(
+                "Removed {} mempool transactions confirmed or conflicted by block",
+                removed.len()
+            );
+        }
+        self.publish_mempool_removals(&removed).await;
+
+        let accepted = match self.utxo_view(self.mempool_manager.orphan_prevouts()) {
+            Ok(utxo_set) => self
+                .mempool_manager
+                .process_orphans_for_block(&block.transactions, &utxo_set, height + 1)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to process orphans for block: {}", e);
+                    Vec::new()
+                }),
+            Err(e) => {
+                warn!("Failed to look up orphan inputs: {}", e);
+                Vec::new()
+            }
+        };
+        if !accepted.is_empty() {
+            debug!("Block let {} orphans into the mempool", accepted.len());
+        }
+        if let Some(ref event_publisher) = self.event_publisher {
+            for txid in &accepted {
+                if let Some(tx) = self.mempool_manager.get_transaction(txid) {
+                    event_publisher
+                        .publish_new_transaction(&tx, txid, true)
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Update the mempool after a reorganization
+    ///
+    /// Transactions the new blocks confirm or conflict with are removed, the
+    /// disconnected blocks' transactions are put back, and whatever is no
+    /// longer valid at the new tip is removed.
+    async fn update_mempool_for_reorg(&self, reorg: &ReorgResult) -> Result<()> {
+        let blocks = self.storage.blocks();
+        let load_block = |hash: &Hash| -> Result<Block> {
+            blocks
+                .get_block(hash)?
+                .ok_or_else(|| anyhow::anyhow!("Block {} not found", hex::encode(hash)))
+        };
+
+        for (hash, height) in &reorg.connected {
+            self.update_mempool_for_block(&load_block(hash)?, *height).await;
+        }
+
+        let disconnected = reorg
+            .disconnected
+            .iter()
+            .rev()
+            .map(|(hash, _)| load_block(hash))
+            .collect::<Result<Vec<_>>>()?;
+        let utxo_set = self.mempool_utxo_view(
+            disconnected
+                .iter()
+                .flat_map(|block| block.transactions.iter()),
+        )?;
+        let update = self
+            .mempool_manager
+            .add_for_reorg(&disconnected, &utxo_set)
+            .await?;
+        debug!(
+            "Returned {} transactions from disconnected blocks to the mempool",
+            update.added.len()
+        );
+        if let Some(ref event_publisher) = self.event_publisher {
+            for txid in &update.added {
+                if let Some(tx) = self.mempool_manager.get_transaction(txid) {
+                    event_publisher
+                        .publish_new_transaction(&tx, txid, true)
+                        .await;
+                }
+            }
+        }
+        self.publish_mempool_removals(&update.removed).await;
+
+        let mut timestamps: Vec<u64> = blocks
+            .get_ancestor_headers(&reorg.new_tip, 11)?
+            .iter()
+            .map(|header| header.timestamp)
+            .collect();
+        timestamps.sort_unstable();
+        let median_time_past = timestamps.get(timestamps.len() / 2).copied().unwrap_or(0);
+        let removed =
+            self.mempool_manager
+                .remove_for_reorg(reorg.new_height, median_time_past, &utxo_set);
+        self.publish_mempool_removals(&removed).await;
+        Ok(())
+    }
+
+    /// The unspent outputs spent by mempool transactions and by `txs`, from
+    /// the UTXO cache
+    fn mempool_utxo_view<'a>(&self, txs: impl Iterator<Item = &'a Transaction>) -> Result<UtxoSet> {
+        let prevouts = self
+            .mempool_manager
+            .prevouts()
+            .into_iter()
+            .chain(txs.flat_map(|tx| tx.inputs.iter().map(|input| input.prevout.clone())));
+        self.utxo_view(prevouts)
+    }
+
+    /// The unspent outputs among `prevouts`, from the UTXO cache
+    fn utxo_view(&self, prevouts: impl IntoIterator<Item = OutPoint>) -> Result<UtxoSet> {
+        let prevouts: Vec<OutPoint> = prevouts.into_iter().collect();
+        self.storage.utxo_cache().fetch_coins(&prevouts)
+    }
+
+    /// Publish a ZMQ `sequence` removal for each transaction that left the
+    /// mempool
+    async fn publish_mempool_removals(&self, removed: &[(Hash, MempoolRemovalReason)]) {
+        if let Some(ref event_publisher) = self.event_publisher {
+            for (txid, reason) in removed {
+                event_publisher.publish_mempool_removal(txid, *reason).await;
+            }
+        }
+    }
+
     /// Run node processing once (for testing)
     pub async fn run_once(&mut self) -> Result<()> {
         info!("Running node processing once");
@@ -947,6 +1419,27 @@ impl Node {
         Ok(())
     }
 
+    /// Request the blocks in the download window from connected peers
+    ///
+    /// Peers that let requests time out or stall the window are disconnected;
+    /// their blocks are requested from the others.
+    async fn request_blocks(&mut self) {
+        if self.sync_coordinator.block_downloader().is_empty() {
+            return;
+        }
+        let now = std::time::Instant::now();
+        for peer in self.sync_coordinator.check_block_download(now) {
+            self.network.disconnect_peer(peer).await;
+        }
+        let peers = self.network.peer_addresses();
+        for (peer, hashes) in self.sync_coordinator.schedule_block_requests(&peers, now) {
+            debug!("Requesting {} blocks from {}", hashes.len(), peer);
+            if let Err(e) = self.network.request_blocks(peer, &hashes).await {
+                warn!("Failed to request blocks from {}: {}", peer, e);
+            }
+        }
+    }
+
     /// Check node health with graceful error handling
     async fn check_health(&self) -> Result<()> {
         // Check peer count (non-blocking, always succeeds)
@@ -1073,6 +1566,28 @@ impl Node {
         // Stop all components
         self.rpc.stop()?;
 
+        self.save_fee_estimates();
+        let peers_file = self.data_dir.join(addrman::PEERS_FILE);
+        if let Err(e) = self
+            .network
+            .address_manager()
+            .read()
+            .await
+            .save(&peers_file)
+        {
+            warn!(
+                "Failed to save peer addresses to {}: {}",
+                peers_file.display(),
+                e
+            );
+        }
+        if self.mempool_persistence().is_some() {
+            let path = self.mempool_manager.persistence_path();
+            if let Err(e) = self.mempool_manager.save_to_disk(&path) {
+                warn!("Failed to save mempool to {}: {}", path.display(), e);
+            }
+        }
+
         // Flush storage
         self.storage.flush()?;
 
@@ -1080,6 +1595,14 @@ impl Node {
         Ok(())
     }
 
+    /// Save the fee estimates to the data directory
+    fn save_fee_estimates(&self) {
+        let path = self.data_dir.join(fee_estimation::FEE_ESTIMATES_FILE);
+        if let Err(e) = self.mempool_manager.fee_estimator().save(&path) {
+            warn!("Failed to save fee estimates to {}: {}", path.display(), e);
+        }
+    }
+
     /// Get module manager (mutable)
     pub fn module_manager_mut(&mut self) -> Option<&mut ModuleManager> {
         self.module_manager.as_mut()
@@ -1120,6 +1643,11 @@ impl Node {
         &self.rpc
     }
 
+    /// Get mempool manager
+    pub fn mempool(&self) -> &mempool::MempoolManager {
+        &self.mempool_manager
+    }
+
     /// Get health report
     pub fn health_check(&self) -> health::HealthReport {
         use crate::node::health::HealthChecker;
@@ -1139,3 +1667,12 @@ impl Node {
         )
     }
 }
+
+/// Chain parameters network name for a protocol version
+fn network_name(protocol_version: ProtocolVersion) -> &'static str {
+    match protocol_version {
+        ProtocolVersion::BitcoinV1 => "mainnet",
+        ProtocolVersion::Testnet3 => "testnet",
+        ProtocolVersion::Regtest => "regtest",
+    }
+}
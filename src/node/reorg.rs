@@ -0,0 +1,553 @@
+//! Chain reorganization
+//!
+//! Records undo data for connected blocks and switches the active chain to a
+//! competing branch with more cumulative work: blocks are disconnected back to
+//! the fork point using their undo data, then the new branch is connected.
+
+use crate::node::block_processor::{commit_connected_block, validate_block_with_context};
+use crate::storage::blockstore::{BlockStore, BlockUndo, SpentOutput};
+use crate::storage::chainwork::ChainWork;
+use crate::storage::database::WriteBatch;
+use crate::storage::utxocache::UtxoView;
+use crate::storage::Storage;
+use anyhow::Result;
+use bllvm_protocol::segwit::Witness;
+use bllvm_protocol::{BitcoinProtocolEngine, Block, Hash, UtxoSet, ValidationResult};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/// Maximum number of blocks walked back when searching for a fork point
+///
+/// Bounds the work done for branches that do not connect to the active chain.
+pub const MAX_REORG_DEPTH: usize = 10_000;
+
+/// Result of a completed chain reorganization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReorgResult {
+    /// Tip of the chain before the reorganization
+    pub old_tip: Hash,
+    /// Height of the old tip
+    pub old_height: u64,
+    /// Tip of the chain after the reorganization
+    pub new_tip: Hash,
+    /// Height of the new tip
+    pub new_height: u64,
+    /// Height of the last block shared by both branches
+    pub fork_height: u64,
+    /// Blocks removed from the active chain (tip first)
+    pub disconnected: Vec<(Hash, u64)>,
+    /// Blocks added to the active chain (lowest height first)
+    pub connected: Vec<(Hash, u64)>,
+}
+
+/// Outcome of an attempted reorganization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReorgOutcome {
+    /// The active chain was kept: it has at least as much work, or some block
+    /// bodies of the branch are not available yet
+    Unchanged,
+    /// The active chain switched to the branch
+    Reorganized(ReorgResult),
+    /// A block of the branch failed validation and the original chain was
+    /// restored
+    Rejected {
+        /// The block that failed
+        hash: Hash,
+        /// Its height on the branch
+        height: u64,
+    },
+}
+
+/// Build undo data for a block before it is connected
+///
+/// Must be called with the UTXO set as it was *before* the block is applied.
+/// Outputs that are created and spent within the same block are not recorded,
+/// since disconnecting the block removes them anyway.
+pub fn build_block_undo(block: &Block, utxo_set: &UtxoSet) -> BlockUndo {
+    let mut undo = BlockUndo::default();
+
+    // Coinbase (first transaction) has no real inputs
+    for tx in block.transactions.iter().skip(1) {
+        for input in tx.inputs.iter() {
+            if let Some(utxo) = utxo_set.get(&input.prevout) {
+                undo.spent_outputs.push(SpentOutput {
+                    outpoint: input.prevout.clone(),
+                    utxo: utxo.clone(),
+                });
+            }
+        }
+    }
+
+    undo
+}
+
+/// Revert the effect of a block on the UTXO set
+///
+/// Removes every output the block created and restores the outputs it spent.
+/// Returns `false` if the UTXO set did not contain all of the block's outputs,
+/// which indicates the set was not at this block when it was disconnected.
+pub fn disconnect_block(block: &Block, undo: &BlockUndo, utxo_set: &mut UtxoSet) -> bool {
+    // Reverting an in-memory set cannot fail; the height is not used
+    utxo_set.revert_block(block, 0, undo).unwrap_or(false)
+}
+
+/// Reorganization engine
+///
+/// Operates on the block store's height index (the active chain) and the
+/// caller's UTXO view. Chain state and transaction index updates are applied
+/// when `Storage` is available, in which case the view should be its UTXO cache
+/// ([`Storage::utxo_cache`]) so the UTXO changes are persisted.
+pub struct ReorgEngine<'a> {
+    blockstore: &'a BlockStore,
+    protocol: &'a BitcoinProtocolEngine,
+    storage: Option<&'a Arc<Storage>>,
+}
+
+impl<'a> ReorgEngine<'a> {
+    /// Create a new reorganization engine
+    pub fn new(
+        blockstore: &'a BlockStore,
+        protocol: &'a BitcoinProtocolEngine,
+        storage: Option<&'a Arc<Storage>>,
+    ) -> Self {
+        Self {
+            blockstore,
+            protocol,
+            storage,
+        }
+    }
+
+    /// Find where a branch joins the active chain
+    ///
+    /// Returns the fork height and the branch's block hashes ordered from the
+    /// block after the fork point up to `branch_tip`.
+    pub fn find_fork_point(&self, branch_tip: &Hash) -> Result<(u64, Vec<Hash>)> {
+        let mut branch = Vec::new();
+        let mut current = *branch_tip;
+
+        loop {
+            if self.blockstore.is_in_main_chain(&current)? {
+                let fork_height = self
+                    .blockstore
+                    .get_height_by_hash(&current)?
+                    .ok_or_else(|| anyhow::anyhow!("Main chain block has no height"))?;
+                branch.reverse();
+                return Ok((fork_height, branch));
+            }
+
+            if branch.len() >= MAX_REORG_DEPTH {
+                return Err(anyhow::anyhow!(
+                    "Branch exceeds maximum reorganization depth of {}",
+                    MAX_REORG_DEPTH
+                ));
+            }
+
+            let header = self.blockstore.get_header(&current)?.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Branch does not connect to the active chain (missing {})",
+                    hex::encode(current)
+                )
+            })?;
+            branch.push(current);
+            current = header.prev_block_hash;
+        }
+    }
+
+    /// Sum the proof-of-work of a list of blocks
+    pub fn branch_work(&self, hashes: &[Hash]) -> Result<ChainWork> {
+        let mut work = ChainWork::ZERO;
+        for hash in hashes {
+            let header = self
+                .blockstore
+                .get_header(hash)?
+                .ok_or_else(|| anyhow::anyhow!("Missing header {}", hex::encode(hash)))?;
+            work += ChainWork::from_bits(header.bits);
+        }
+        Ok(work)
+    }
+
+    /// Sum the proof-of-work of the active chain above `fork_height`
+    pub fn main_chain_work_since(&self, fork_height: u64, tip_height: u64) -> Result<ChainWork> {
+        let mut hashes = Vec::new();
+        for height in (fork_height + 1)..=tip_height {
+            if let Some(hash) = self.blockstore.get_hash_by_height(height)? {
+                hashes.push(hash);
+            }
+        }
+        self.branch_work(&hashes)
+    }
+
+    /// Validate and connect a block on top of the active chain
+    ///
+    /// Commits the block, its undo data and (with `Storage`) the chain tip in one
+    /// batch and applies the block to `utxos`. Returns `false` if validation
+    /// fails, in which case neither storage nor the UTXO view are modified.
+    pub fn connect_block(
+        &self,
+        block: &Block,
+        witnesses: &[Witness],
+        height: u64,
+        utxos: &mut dyn UtxoView,
+    ) -> Result<bool> {
+        // Validate against just the coins this block touches
+        let mut view = utxos.fetch_view(block)?;
+        let undo = build_block_undo(block, &view);
+
+        let result = validate_block_with_context(
+            self.blockstore,
+            self.protocol,
+            block,
+            witnesses,
+            &mut view,
+            height,
+        )?;
+        if !matches!(result, ValidationResult::Valid) {
+            return Ok(false);
+        }
+
+        commit_connected_block(
+            self.blockstore,
+            self.storage,
+            block,
+            witnesses,
+            height,
+            &undo,
+            utxos,
+        )?;
+
+        Ok(true)
+    }
+
+    /// Disconnect the block at the tip of the active chain
+    ///
+    /// The block body is kept so it can be reconnected later. Returns the
+    /// disconnected block.
+    pub fn disconnect_tip(&self, height: u64, utxos: &mut dyn UtxoView) -> Result<Block> {
+        let hash = self
+            .blockstore
+            .get_hash_by_height(height)?
+            .ok_or_else(|| anyhow::anyhow!("No block at height {}", height))?;
+        let block = self.blockstore.get_block(&hash)?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Cannot disconnect block {}: body not available (pruned?)",
+                hex::encode(hash)
+            )
+        })?;
+        let undo = self.blockstore.get_undo(&hash)?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Cannot disconnect block {}: undo data not available",
+                hex::encode(hash)
+            )
+        })?;
+
+        if !utxos.revert_block(&block, height, &undo)? {
+            warn!(
+                "Unclean disconnect of block {} at height {}",
+                hex::encode(hash),
+                height
+            );
+        }
+
+        let committed = if let Some(storage) = self.storage {
+            storage.disconnect_block(&block, height)
+        } else {
+            let mut batch = WriteBatch::new();
+            self.blockstore.stage_remove_undo(&mut batch, &hash);
+            self.blockstore
+                .stage_remove_height(&mut batch, height)
+                .and_then(|_| self.blockstore.apply_batch(&batch))
+        };
+        if let Err(e) = committed {
+            // Keep the UTXO view at the block that is still the tip
+            utxos.apply_block(&block, &hash, height)?;
+            return Err(e);
+        }
+
+        debug!(
+            "Disconnected block {} at height {}",
+            hex::encode(hash),
+            height
+        );
+        Ok(block)
+    }
+
+    /// Reorganize onto `branch_tip` if its branch has more work than the active chain
+    ///
+    /// If a block of the new branch fails validation, it is marked failed in
+    /// the block index (with storage), the original chain is restored and
+    /// [`ReorgOutcome::Rejected`] is returned. Errors are left for storage
+    /// failures.
+    pub fn reorganize(
+        &self,
+        branch_tip: &Hash,
+        tip_height: u64,
+        utxos: &mut dyn UtxoView,
+    ) -> Result<ReorgOutcome> {
+        let (fork_height, branch) = self.find_fork_point(branch_tip)?;
+        if branch.is_empty() {
+            return Ok(ReorgOutcome::Unchanged);
+        }
+
+        let branch_work = self.branch_work(&branch)?;
+        let main_work = self.main_chain_work_since(fork_height, tip_height)?;
+        if branch_work <= main_work {
+            debug!(
+                "Branch to {} has {} work, active chain has {}; not reorganizing",
+                hex::encode(branch_tip),
+                branch_work,
+                main_work
+            );
+            return Ok(ReorgOutcome::Unchanged);
+        }
+
+        for hash in &branch {
+            if !self.blockstore.has_block_body(hash)? {
+                debug!(
+                    "Branch block {} not downloaded yet; deferring reorganization",
+                    hex::encode(hash)
+                );
+                return Ok(ReorgOutcome::Unchanged);
+            }
+        }
+
+        let old_tip = self
+            .blockstore
+            .get_hash_by_height(tip_height)?
+            .ok_or_else(|| anyhow::anyhow!("No block at tip height {}", tip_height))?;
+
+        info!(
+            "Reorganizing: fork at height {}, disconnecting {} blocks, connecting {} blocks",
+            fork_height,
+            tip_height - fork_height,
+            branch.len()
+        );
+
+        // Disconnect the active chain back to the fork point
+        let mut disconnected = Vec::new();
+        let mut disconnected_blocks = Vec::new();
+        for height in ((fork_height + 1)..=tip_height).rev() {
+            let hash = self
+                .blockstore
+                .get_hash_by_height(height)?
+                .ok_or_else(|| anyhow::anyhow!("No block at height {}", height))?;
+            let block = self.disconnect_tip(height, utxos)?;
+            disconnected.push((hash, height));
+            disconnected_blocks.push(block);
+        }
+
+        // Connect the new branch
+        let mut connected = Vec::new();
+        for (i, hash) in branch.iter().enumerate() {
+            let height = fork_height + 1 + i as u64;
+            let block = self
+                .blockstore
+                .get_block(hash)?
+                .ok_or_else(|| anyhow::anyhow!("Missing block {}", hex::encode(hash)))?;
+            let witnesses = self.stored_witnesses(hash, &block)?;
+
+            let accepted = match self.connect_block(&block, &witnesses, height, utxos) {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // An error says nothing about the block's validity, so it
+                    // is not marked failed
+                    warn!(
+                        "Error connecting block {}: {}; restoring old chain",
+                        hex::encode(hash),
+                        e
+                    );
+                    self.restore_chain(fork_height, &connected, disconnected_blocks, utxos)?;
+                    return Err(e);
+                }
+            };
+
+            if !accepted {
+                warn!(
+                    "Block {} at height {} failed validation during reorganization; restoring old chain",
+                    hex::encode(hash),
+                    height
+                );
+                if let Some(storage) = self.storage {
+                    // A branch block that was only stored is indexed first,
+                    // so it stays failed
+                    let index = storage.block_index();
+                    index.accept_header(hash, &block.header)?;
+                    index.mark_failed(hash)?;
+                }
+                self.restore_chain(fork_height, &connected, disconnected_blocks, utxos)?;
+                return Ok(ReorgOutcome::Rejected {
+                    hash: *hash,
+                    height,
+                });
+            }
+            connected.push((*hash, height));
+        }
+
+        let new_height = fork_height + branch.len() as u64;
+
+        info!(
+            "Reorganization complete: new tip {} at height {}",
+            hex::encode(branch_tip),
+            new_height
+        );
+
+        Ok(ReorgOutcome::Reorganized(ReorgResult {
+            old_tip,
+            old_height: tip_height,
+            new_tip: *branch_tip,
+            new_height,
+            fork_height,
+            disconnected,
+            connected,
+        }))
+    }
+
+    /// Switch to the best chain in the block index if it has more work than the
+    /// active chain
+    ///
+    /// The candidate is the most-work block that is not failed and whose branch
+    /// back to the active chain has all block data (see
+    /// [`BlockIndex::best_chain_candidate`]). A candidate whose branch turns out
+    /// to hold an invalid block is now failed, so the next best one is tried.
+    /// Requires `Storage`.
+    ///
+    /// [`BlockIndex::best_chain_candidate`]: crate::storage::blockindex::BlockIndex::best_chain_candidate
+    pub fn activate_best_chain(
+        &self,
+        tip_height: u64,
+        utxos: &mut dyn UtxoView,
+    ) -> Result<Option<ReorgResult>> {
+        let storage = self
+            .storage
+            .ok_or_else(|| anyhow::anyhow!("Best chain selection requires storage"))?;
+        let index = storage.block_index();
+        loop {
+            let candidate = match index.best_chain_candidate(self.blockstore)? {
+                Some(candidate) => candidate,
+                None => return Ok(None),
+            };
+            let tip_hash = self.blockstore.get_hash_by_height(tip_height)?;
+            if tip_hash == Some(candidate.hash) {
+                return Ok(None);
+            }
+            let tip_work = match tip_hash {
+                Some(hash) => index.get(&hash)?.map(|entry| entry.chainwork),
+                None => None,
+            }
+            .unwrap_or_default();
+            if candidate.chainwork <= tip_work {
+                return Ok(None);
+            }
+            match self.reorganize(&candidate.hash, tip_height, utxos)? {
+                ReorgOutcome::Reorganized(result) => return Ok(Some(result)),
+                ReorgOutcome::Unchanged => return Ok(None),
+                ReorgOutcome::Rejected { .. } => continue,
+            }
+        }
+    }
+
+    /// Mark a block invalid and move the active chain off it
+    ///
+    /// The block and its descendants are marked failed in the block index. If
+    /// the block is on the active chain, blocks are disconnected down to its
+    /// parent, then the best remaining chain is activated. Returns the new tip
+    /// height. Requires `Storage`.
+    pub fn invalidate_block(
+        &self,
+        hash: &Hash,
+        tip_height: u64,
+        utxos: &mut dyn UtxoView,
+    ) -> Result<u64> {
+        let storage = self
+            .storage
+            .ok_or_else(|| anyhow::anyhow!("Invalidating blocks requires storage"))?;
+        let main_chain_height = if self.blockstore.is_in_main_chain(hash)? {
+            let height = self
+                .blockstore
+                .get_height_by_hash(hash)?
+                .ok_or_else(|| anyhow::anyhow!("Main chain block has no height"))?;
+            if height == 0 {
+                return Err(anyhow::anyhow!("Cannot invalidate the genesis block"));
+            }
+            Some(height)
+        } else {
+            None
+        };
+        storage.block_index().mark_failed(hash)?;
+
+        let mut tip_height = tip_height;
+        if let Some(height) = main_chain_height {
+            while tip_height >= height {
+                self.disconnect_tip(tip_height, utxos)?;
+                tip_height -= 1;
+            }
+            info!(
+                "Invalidated block {} at height {}; tip is now at height {}",
+                hex::encode(hash),
+                height,
+                tip_height
+            );
+        }
+
+        Ok(match self.activate_best_chain(tip_height, utxos)? {
+            Some(result) => result.new_height,
+            None => tip_height,
+        })
+    }
+
+    /// Clear the failed status of a block, its descendants and its ancestors,
+    /// then activate the best chain
+    ///
+    /// Returns the new tip height. Requires `Storage`.
+    pub fn reconsider_block(
+        &self,
+        hash: &Hash,
+        tip_height: u64,
+        utxos: &mut dyn UtxoView,
+    ) -> Result<u64> {
+        let storage = self
+            .storage
+            .ok_or_else(|| anyhow::anyhow!("Reconsidering blocks requires storage"))?;
+        storage.block_index().reset_failure(hash)?;
+        Ok(match self.activate_best_chain(tip_height, utxos)? {
+            Some(result) => result.new_height,
+            None => tip_height,
+        })
+    }
+
+    /// Undo a partially applied reorganization and reconnect the original chain
+    fn restore_chain(
+        &self,
+        fork_height: u64,
+        connected: &[(Hash, u64)],
+        mut disconnected_blocks: Vec<Block>,
+        utxos: &mut dyn UtxoView,
+    ) -> Result<()> {
+        for (_, height) in connected.iter().rev() {
+            self.disconnect_tip(*height, utxos)?;
+        }
+
+        // Disconnected blocks were collected tip first
+        disconnected_blocks.reverse();
+        for (i, block) in disconnected_blocks.iter().enumerate() {
+            let height = fork_height + 1 + i as u64;
+            let hash = self.blockstore.get_block_hash(block);
+            let witnesses = self.stored_witnesses(&hash, block)?;
+            if !self.connect_block(block, &witnesses, height, utxos)? {
+                return Err(anyhow::anyhow!(
+                    "Failed to reconnect previously valid block {} at height {}",
+                    hex::encode(hash),
+                    height
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stored witnesses for a block, or empty witnesses if none were stored
+    fn stored_witnesses(&self, hash: &Hash, block: &Block) -> Result<Vec<Witness>> {
+        Ok(self
+            .blockstore
+            .get_witness(hash)?
+            .unwrap_or_else(|| block.transactions.iter().map(|_| Vec::new()).collect()))
+    }
+}
@@ -1,22 +1,62 @@
 //! Block sync coordinator
 //!
-//! Handles blockchain synchronization, header download, block validation,
-//! and chain reorganization.
+//! Handles blockchain synchronization, header download, block download,
+//! block validation, and chain reorganization.
 
+use crate::node::block_download::{BlockDownloadConfig, BlockDownloader};
 use crate::node::block_processor::{
-    parse_block_from_wire, prepare_block_validation_context, store_block_with_context_and_index,
-    validate_block_with_context,
+    block_hash_from_wire, commit_connected_block, parse_block_from_wire,
+    prepare_block_validation_context, validate_block_with_context, validate_block_with_headers,
 };
 use crate::node::metrics::MetricsCollector;
 use crate::node::performance::{OperationType, PerformanceProfiler, PerformanceTimer};
+use crate::node::reorg::{build_block_undo, ReorgEngine, ReorgOutcome, ReorgResult};
+use crate::storage::blockindex::BlockStatus;
 use crate::storage::blockstore::BlockStore;
+use crate::storage::database::WriteBatch;
+use crate::storage::snapshot::SnapshotStatus;
+use crate::storage::utxocache::UtxoView;
 use crate::storage::Storage;
 use anyhow::Result;
-use bllvm_protocol::{BitcoinProtocolEngine, Block, BlockHeader, UtxoSet, ValidationResult};
+use bllvm_protocol::segwit::Witness;
+use bllvm_protocol::{BitcoinProtocolEngine, Block, BlockHeader, Hash, ValidationResult};
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Instant;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+/// Outcome of processing a block received from the network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockProcessOutcome {
+    /// Block extended the active chain
+    Connected { hash: Hash, height: u64 },
+    /// Block was stored on a side branch without more work than the active chain
+    SideBranch { hash: Hash, height: u64 },
+    /// Block made its branch heavier than the active chain and the node switched to it
+    Reorganized(ReorgResult),
+    /// Block below a loaded snapshot's base was validated in the background
+    BackgroundValidated {
+        hash: Hash,
+        height: u64,
+        status: SnapshotStatus,
+    },
+    /// Block is already part of the active chain
+    AlreadyKnown,
+    /// Block failed validation or does not connect to any known block
+    Rejected,
+}
+
+/// Outcome of adding a batch of headers to the block index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadersOutcome {
+    /// Headers newly added to the index
+    pub accepted: usize,
+    /// Headers that were already indexed
+    pub already_known: usize,
+    /// Hash and height of the most-work valid header after processing
+    pub best_header: Option<(Hash, u64)>,
+}
 
 /// Block provider for dependency injection
 pub struct BlockProvider {
@@ -36,6 +76,10 @@ pub struct SyncStateMachine {
     best_header: Option<BlockHeader>,
     /// Current chain tip
     chain_tip: Option<BlockHeader>,
+    /// Height of the active chain tip
+    chain_height: u64,
+    /// Height of the best known header
+    best_header_height: u64,
     /// Sync progress (0.0 to 1.0)
     progress: f64,
     /// Error message if in error state
@@ -55,6 +99,8 @@ impl SyncStateMachine {
             state: SyncState::Initial,
             best_header: None,
             chain_tip: None,
+            chain_height: 0,
+            best_header_height: 0,
             progress: 0.0,
             error_message: None,
         }
@@ -84,6 +130,16 @@ impl SyncStateMachine {
         self.chain_tip = Some(header);
     }
 
+    /// Update the heights of the chain tip and the best header
+    ///
+    /// While syncing, progress is the fraction of the best header chain that
+    /// has been connected.
+    pub fn update_heights(&mut self, chain_height: u64, best_header_height: u64) {
+        self.chain_height = chain_height;
+        self.best_header_height = best_header_height;
+        self.update_progress();
+    }
+
     /// Get current state
     pub fn state(&self) -> &SyncState {
         &self.state
@@ -113,8 +169,10 @@ impl SyncStateMachine {
     fn update_progress(&mut self) {
         self.progress = match self.state {
             SyncState::Initial => 0.0,
-            SyncState::Headers => 0.3,
-            SyncState::Blocks => 0.6,
+            SyncState::Headers | SyncState::Blocks if self.best_header_height > 0 => {
+                (self.chain_height as f64 / self.best_header_height as f64).min(1.0)
+            }
+            SyncState::Headers | SyncState::Blocks => 0.0,
             SyncState::Synced => 1.0,
             SyncState::Error(_) => 0.0,
         };
@@ -135,6 +193,7 @@ pub enum SyncState {
 pub struct SyncCoordinator {
     state_machine: SyncStateMachine,
     block_provider: BlockProvider,
+    downloader: BlockDownloader,
 }
 
 impl Default for SyncCoordinator {
@@ -155,9 +214,16 @@ impl SyncCoordinator {
         Self {
             state_machine: SyncStateMachine::new(),
             block_provider: BlockProvider::new(),
+            downloader: BlockDownloader::default(),
         }
     }
 
+    /// Use `config` for block download limits and timeouts
+    pub fn with_block_download_config(mut self, config: BlockDownloadConfig) -> Self {
+        self.downloader = BlockDownloader::new(config);
+        self
+    }
+
     /// Start sync process
     pub fn start_sync(&mut self) -> Result<()> {
         info!("Starting blockchain sync");
@@ -180,13 +246,196 @@ impl SyncCoordinator {
         self.state_machine.is_synced()
     }
 
+    /// Get the sync state machine
+    pub fn state_machine(&self) -> &SyncStateMachine {
+        &self.state_machine
+    }
+
+    /// Get the block downloader
+    pub fn block_downloader(&self) -> &BlockDownloader {
+        &self.downloader
+    }
+
+    /// Move the block download along the best header chain
+    ///
+    /// Follows the active chain tip, queues the blocks between it and the
+    /// most-work header, and updates the sync progress. The state is
+    /// [`SyncState::Blocks`] while blocks are missing and [`SyncState::Synced`]
+    /// once the tip has caught up.
+    pub fn update_block_download(&mut self, storage: &Arc<Storage>) -> Result<()> {
+        let chain_height = storage.chain().get_height()?;
+        let next_height = chain_height.map(|height| height + 1).unwrap_or(0);
+        self.downloader.advance_to(next_height);
+
+        let index = storage.block_index();
+        let Some(best) = index.best_header()? else {
+            return Ok(());
+        };
+        let best_height = best.height;
+        self.state_machine
+            .update_heights(chain_height.unwrap_or(0), best_height);
+        self.state_machine.update_best_header(best.header.clone());
+
+        if best_height < next_height {
+            if !self.downloader.is_empty() {
+                self.downloader.reset(next_height);
+            }
+            if matches!(self.state_machine.state(), SyncState::Blocks) {
+                info!("Block download complete at height {}", best_height);
+                self.state_machine.transition_to(SyncState::Synced);
+            }
+            return Ok(());
+        }
+        if !matches!(self.state_machine.state(), SyncState::Blocks) {
+            info!("Downloading blocks {} to {}", next_height, best_height);
+            self.state_machine.transition_to(SyncState::Blocks);
+        }
+
+        // Walk back from the best header until reaching a block already
+        // queued, or the next block to connect
+        let mut hashes = Vec::new();
+        let mut entry = best;
+        while self.downloader.hash_at(entry.height) != Some(&entry.hash) {
+            hashes.push(entry.hash);
+            if entry.height == next_height {
+                break;
+            }
+            entry = index.get(entry.parent())?.ok_or_else(|| {
+                anyhow::anyhow!("Header {} has no indexed parent", hex::encode(entry.hash))
+            })?;
+        }
+        let start_height = best_height + 1 - hashes.len() as u64;
+        if hashes.is_empty() && self.downloader.end_height() == start_height {
+            return Ok(());
+        }
+        hashes.reverse();
+        self.downloader.replace_from(start_height, hashes);
+        Ok(())
+    }
+
+    /// Assign blocks in the download window to `peers`
+    ///
+    /// Returns the block hashes to request from each peer.
+    pub fn schedule_block_requests(
+        &mut self,
+        peers: &[SocketAddr],
+        now: Instant,
+    ) -> Vec<(SocketAddr, Vec<Hash>)> {
+        self.downloader.schedule(peers, now)
+    }
+
+    /// Find the peer holding up the block download
+    ///
+    /// Timed-out requests go to other peers. A peer is only returned when it
+    /// holds the lowest missing block while the download window is blocked;
+    /// it should be disconnected.
+    pub fn check_block_download(&mut self, now: Instant) -> Vec<SocketAddr> {
+        self.downloader.check_timeouts(now)
+    }
+
+    /// Take a block received from the network
+    ///
+    /// Blocks being downloaded are held back until every block before them
+    /// has arrived; other blocks are returned straight away. Returns the
+    /// blocks to process, in order.
+    pub fn receive_block(&mut self, block_data: Vec<u8>) -> Vec<Vec<u8>> {
+        // The block is only parsed once, by process_block
+        let Some(hash) = block_hash_from_wire(&block_data) else {
+            // Left for process_block to report
+            return vec![block_data];
+        };
+        if !self.downloader.is_downloading(&hash) {
+            return vec![block_data];
+        }
+        self.downloader.block_received(&hash, block_data);
+        let mut ready = Vec::new();
+        while let Some((height, data)) = self.downloader.pop_ready() {
+            debug!("Downloaded block at height {} is ready", height);
+            ready.push(data);
+        }
+        ready
+    }
+
+    /// Add headers received from a peer to the block index (headers-first sync)
+    ///
+    /// The headers must form a chain whose first parent is already indexed,
+    /// and each must carry valid proof-of-work for its own target. Contextual
+    /// checks (difficulty adjustment, timestamps) are applied when the block
+    /// itself is connected. Processing stops at the first bad header, which is
+    /// returned as an error; headers before it stay indexed.
+    pub fn process_headers(
+        &mut self,
+        storage: &Arc<Storage>,
+        headers: &[BlockHeader],
+    ) -> Result<HeadersOutcome> {
+        use bllvm_protocol::pow::check_proof_of_work;
+
+        let blockstore = storage.blocks();
+        let index = storage.block_index();
+        let mut accepted = 0;
+        let mut already_known = 0;
+        let mut prev_hash: Option<Hash> = None;
+
+        for header in headers {
+            let hash = blockstore.get_header_hash(header);
+            if let Some(prev_hash) = prev_hash {
+                if header.prev_block_hash != prev_hash {
+                    return Err(anyhow::anyhow!(
+                        "Header {} does not follow the previous header in the batch",
+                        hex::encode(hash)
+                    ));
+                }
+            }
+            prev_hash = Some(hash);
+
+            if index.contains(&hash)? {
+                already_known += 1;
+                continue;
+            }
+            if !check_proof_of_work(header).unwrap_or(false) {
+                return Err(anyhow::anyhow!(
+                    "Header {} has invalid proof of work",
+                    hex::encode(hash)
+                ));
+            }
+            let entry = index.accept_header(&hash, header)?;
+            if entry.is_failed() {
+                return Err(anyhow::anyhow!(
+                    "Header {} builds on an invalid block",
+                    hex::encode(hash)
+                ));
+            }
+            accepted += 1;
+        }
+
+        let best_header = index.best_header()?;
+        if let Some(ref best) = best_header {
+            self.state_machine.update_best_header(best.header.clone());
+        }
+        debug!(
+            "Indexed {} new headers ({} already known)",
+            accepted, already_known
+        );
+        Ok(HeadersOutcome {
+            accepted,
+            already_known,
+            best_header: best_header.map(|entry| (entry.hash, entry.height)),
+        })
+    }
+
     /// Process an incoming block from the network
     ///
     /// This function:
     /// 1. Parses the block from wire format (extracting witness data)
-    /// 2. Validates the block with proper witnesses and headers
-    /// 3. Stores the block with witnesses and updates headers
-    /// 4. Indexes transactions if storage is provided
+    /// 2. Routes blocks that do not build on the active tip to side-branch handling,
+    ///    which reorganizes onto the branch if it has more work
+    /// 3. Validates the block with proper witnesses and headers
+    /// 4. Atomically stores the block with witnesses, undo data and updated headers,
+    ///    plus the transaction index and chain tip if storage is provided, and applies
+    ///    it to `utxos`
+    ///
+    /// `current_height` is the height the block would have if it extends the active tip.
+    /// With storage, `utxos` should be its UTXO cache ([`Storage::utxo_cache`]).
     pub fn process_block(
         &mut self,
         blockstore: &BlockStore,
@@ -194,25 +443,84 @@ impl SyncCoordinator {
         storage: Option<&Arc<Storage>>,
         block_data: &[u8],
         current_height: u64,
-        utxo_set: &mut UtxoSet,
+        utxos: &mut dyn UtxoView,
+        metrics: Option<Arc<MetricsCollector>>,
+        profiler: Option<Arc<PerformanceProfiler>>,
+    ) -> Result<BlockProcessOutcome> {
+        // Parse block from wire format (extracts witness data)
+        let (block, witnesses) = parse_block_from_wire(block_data)?;
+        self.process_parsed_block(
+            blockstore,
+            protocol,
+            storage,
+            &block,
+            &witnesses,
+            current_height,
+            utxos,
+            metrics,
+            profiler,
+        )
+    }
+
+    /// Process a block that has already been parsed
+    ///
+    /// Steps 2 to 4 of [`Self::process_block`], for blocks that do not arrive
+    /// in wire format, such as those mined by the generate RPC methods.
+    pub fn process_parsed_block(
+        &mut self,
+        blockstore: &BlockStore,
+        protocol: &BitcoinProtocolEngine,
+        storage: Option<&Arc<Storage>>,
+        block: &Block,
+        witnesses: &[Witness],
+        current_height: u64,
+        utxos: &mut dyn UtxoView,
         metrics: Option<Arc<MetricsCollector>>,
         profiler: Option<Arc<PerformanceProfiler>>,
-    ) -> Result<bool> {
+    ) -> Result<BlockProcessOutcome> {
         let _timer = profiler
             .as_ref()
             .map(|p| PerformanceTimer::start(Arc::clone(p), OperationType::BlockProcessing));
         let start_time = Instant::now();
+        let block_hash = blockstore.get_block_hash(block);
+
+        // History below a loaded snapshot's base is validated separately
+        if let Some(storage) = storage {
+            if let Some(height) = storage.background_block_height(block)? {
+                return self.process_background_block(
+                    blockstore, protocol, storage, block, witnesses, height,
+                );
+            }
+        }
 
-        // Parse block from wire format (extracts witness data)
-        let (block, witnesses) = parse_block_from_wire(block_data)?;
+        if blockstore.is_in_main_chain(&block_hash)? {
+            debug!("Block {} already in active chain", hex::encode(block_hash));
+            return Ok(BlockProcessOutcome::AlreadyKnown);
+        }
+
+        // Blocks that do not build on the active tip belong to a competing branch
+        if current_height > 0 {
+            let tip_hash = blockstore.get_hash_by_height(current_height - 1)?;
+            if tip_hash != Some(block.header.prev_block_hash) {
+                return self.process_side_branch_block(
+                    blockstore,
+                    protocol,
+                    storage,
+                    block,
+                    witnesses,
+                    current_height - 1,
+                    utxos,
+                );
+            }
+        }
 
         // Prepare validation context (get witnesses and headers)
         let (stored_witnesses, recent_headers) =
-            prepare_block_validation_context(blockstore, &block, current_height)?;
+            prepare_block_validation_context(blockstore, block, current_height)?;
 
         // Use witnesses from wire format (they may not be stored yet)
         let witnesses_to_use = if !witnesses.is_empty() {
-            &witnesses
+            witnesses
         } else {
             &stored_witnesses
         };
@@ -225,26 +533,34 @@ impl SyncCoordinator {
             );
         }
 
+        // Validate against just the coins this block touches, recording the spent
+        // outputs before they are removed
+        let mut view = utxos.fetch_view(block)?;
+        let undo = build_block_undo(block, &view);
+
         // Validate block with witness data and headers using protocol validation
         let validation_result = validate_block_with_context(
             blockstore,
             protocol,
-            &block,
+            block,
             witnesses_to_use,
-            utxo_set,
+            &mut view,
             current_height,
         )?;
 
         let processing_time = start_time.elapsed();
 
         if matches!(validation_result, ValidationResult::Valid) {
-            // Store block with witnesses, update headers, and index transactions
-            store_block_with_context_and_index(
+            // Commit block, witnesses, headers, undo data, UTXO changes, transaction
+            // index and chain tip in one atomic batch
+            commit_connected_block(
                 blockstore,
                 storage,
-                &block,
+                block,
                 witnesses_to_use,
                 current_height,
+                &undo,
+                utxos,
             )?;
 
             // Update metrics
@@ -269,11 +585,233 @@ impl SyncCoordinator {
                 "Block validated and stored at height {} (took {:?})",
                 current_height, processing_time
             );
-            Ok(true)
+            Ok(BlockProcessOutcome::Connected {
+                hash: block_hash,
+                height: current_height,
+            })
         } else {
             error!("Block validation failed at height {}", current_height);
-            Ok(false)
+            Ok(BlockProcessOutcome::Rejected)
+        }
+    }
+
+    /// Validate a block below a loaded snapshot's base against the background UTXO set
+    ///
+    /// The active chain state starts at the snapshot base, so these blocks do
+    /// not touch it. Once the base block is reached the background UTXO set is
+    /// checked against the snapshot.
+    fn process_background_block(
+        &mut self,
+        blockstore: &BlockStore,
+        protocol: &BitcoinProtocolEngine,
+        storage: &Arc<Storage>,
+        block: &Block,
+        witnesses: &[Witness],
+        height: u64,
+    ) -> Result<BlockProcessOutcome> {
+        let block_hash = blockstore.get_block_hash(block);
+        let (stored_witnesses, _) = prepare_block_validation_context(blockstore, block, height)?;
+        let witnesses_to_use = if !witnesses.is_empty() {
+            witnesses
+        } else {
+            &stored_witnesses
+        };
+        let recent_headers = blockstore.get_ancestor_headers(&block.header.prev_block_hash, 11)?;
+
+        let mut utxos = storage.background_utxo_cache();
+        let mut view = utxos.fetch_view(block)?;
+        let undo = build_block_undo(block, &view);
+        let validation_result = validate_block_with_headers(
+            protocol,
+            block,
+            witnesses_to_use,
+            &mut view,
+            height,
+            Some(recent_headers.as_slice()).filter(|headers| !headers.is_empty()),
+        )?;
+        if !matches!(validation_result, ValidationResult::Valid) {
+            error!(
+                "Block {} failed background validation at height {}",
+                hex::encode(block_hash),
+                height
+            );
+            return Ok(BlockProcessOutcome::Rejected);
+        }
+
+        utxos.apply_block(block, &block_hash, height)?;
+        let status = match storage.connect_background_block(block, witnesses_to_use, height) {
+            Ok(status) => status,
+            Err(e) => {
+                utxos.revert_block(block, height, &undo)?;
+                return Err(e);
+            }
+        };
+        debug!(
+            "Block {} validated in the background at height {}",
+            hex::encode(block_hash),
+            height
+        );
+        if status == SnapshotStatus::Invalid {
+            // The chain state validated from genesis replaced the snapshot's at
+            // its base; connect the blocks above the base again on top of it
+            let engine = ReorgEngine::new(blockstore, protocol, Some(storage));
+            if let Some(result) = engine.activate_best_chain(height, &mut storage.utxo_cache())? {
+                info!(
+                    "Reconnected {} blocks above the invalid snapshot's base; tip is now at height {}",
+                    result.connected.len(),
+                    result.new_height
+                );
+            }
+        }
+        Ok(BlockProcessOutcome::BackgroundValidated {
+            hash: block_hash,
+            height,
+            status,
+        })
+    }
+
+    /// Handle a block whose parent is not the active tip
+    ///
+    /// The block is stored without a height index entry and added to the block
+    /// index. If the best chain in the index now has more work than the active
+    /// chain, the node reorganizes onto it.
+    fn process_side_branch_block(
+        &mut self,
+        blockstore: &BlockStore,
+        protocol: &BitcoinProtocolEngine,
+        storage: Option<&Arc<Storage>>,
+        block: &Block,
+        witnesses: &[Witness],
+        tip_height: u64,
+        utxos: &mut dyn UtxoView,
+    ) -> Result<BlockProcessOutcome> {
+        let block_hash = blockstore.get_block_hash(block);
+        let parent_hash = block.header.prev_block_hash;
+
+        if blockstore.get_header(&parent_hash)?.is_none() {
+            debug!(
+                "Block {} has unknown parent {}; ignoring",
+                hex::encode(block_hash),
+                hex::encode(parent_hash)
+            );
+            return Ok(BlockProcessOutcome::Rejected);
+        }
+
+        // Nothing is stored for a block that could not be valid on any chain
+        if !Self::check_branch_header(blockstore, &block.header)? {
+            warn!(
+                "Side-branch block {} has invalid proof of work or timestamp; ignoring",
+                hex::encode(block_hash)
+            );
+            return Ok(BlockProcessOutcome::Rejected);
+        }
+
+        if let Some(storage) = storage {
+            let failed_parent = storage
+                .block_index()
+                .get(&parent_hash)?
+                .is_some_and(|parent| parent.is_failed());
+            if failed_parent {
+                // Indexed as a failed child so it shows up as an invalid tip
+                storage
+                    .block_index()
+                    .accept_header(&block_hash, &block.header)?;
+                warn!(
+                    "Block {} builds on invalid block {}",
+                    hex::encode(block_hash),
+                    hex::encode(parent_hash)
+                );
+                return Ok(BlockProcessOutcome::Rejected);
+            }
+        }
+
+        // Store the block body so it is available if the branch becomes active
+        blockstore.store_block(block)?;
+        if !witnesses.is_empty() {
+            blockstore.store_witness(&block_hash, witnesses)?;
+        }
+
+        let engine = ReorgEngine::new(blockstore, protocol, storage);
+        let (fork_height, branch) = engine.find_fork_point(&block_hash)?;
+        let height = fork_height + branch.len() as u64;
+
+        let reorganized = if let Some(storage) = storage {
+            let parent_chainwork = storage
+                .chain()
+                .get_chainwork(&parent_hash)?
+                .unwrap_or_default();
+            let mut batch = WriteBatch::new();
+            storage.block_index().stage_block(
+                &mut batch,
+                &block_hash,
+                &block.header,
+                height,
+                parent_chainwork,
+                BlockStatus::HAVE_DATA,
+            )?;
+            blockstore.apply_batch(&batch)?;
+            match engine.activate_best_chain(tip_height, utxos)? {
+                Some(result) => ReorgOutcome::Reorganized(result),
+                // Trying the block's branch may have found it invalid
+                None if storage
+                    .block_index()
+                    .get(&block_hash)?
+                    .is_some_and(|entry| entry.is_failed()) =>
+                {
+                    ReorgOutcome::Rejected {
+                        hash: block_hash,
+                        height,
+                    }
+                }
+                None => ReorgOutcome::Unchanged,
+            }
+        } else {
+            engine.reorganize(&block_hash, tip_height, utxos)?
+        };
+
+        match reorganized {
+            ReorgOutcome::Reorganized(result) => Ok(BlockProcessOutcome::Reorganized(result)),
+            ReorgOutcome::Rejected { hash, height } => {
+                warn!(
+                    "Side-branch block {} at height {} is invalid or builds on an invalid block",
+                    hex::encode(hash),
+                    height
+                );
+                Ok(BlockProcessOutcome::Rejected)
+            }
+            ReorgOutcome::Unchanged => {
+                info!(
+                    "Stored side-branch block {} at height {} (fork at {})",
+                    hex::encode(block_hash),
+                    height,
+                    fork_height
+                );
+                Ok(BlockProcessOutcome::SideBranch {
+                    hash: block_hash,
+                    height,
+                })
+            }
+        }
+    }
+
+    /// Header checks for a block stored before it is connected
+    ///
+    /// The header must carry valid proof of work for its own target and a
+    /// timestamp after the median time of its last 11 ancestors (BIP113).
+    fn check_branch_header(blockstore: &BlockStore, header: &BlockHeader) -> Result<bool> {
+        use bllvm_protocol::pow::check_proof_of_work;
+
+        if !check_proof_of_work(header).unwrap_or(false) {
+            return Ok(false);
         }
+        let mut timestamps: Vec<u64> = blockstore
+            .get_ancestor_headers(&header.prev_block_hash, 11)?
+            .iter()
+            .map(|header| header.timestamp)
+            .collect();
+        timestamps.sort_unstable();
+        let median_time_past = timestamps.get(timestamps.len() / 2).copied().unwrap_or(0);
+        Ok(header.timestamp > median_time_past)
     }
 }
 
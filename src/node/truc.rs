@@ -0,0 +1,191 @@
+//! TRUC and ephemeral anchor policy
+//!
+//! Version 3 transactions opt into TRUC (topologically restricted until
+//! confirmation, BIP431) rules: while unconfirmed, a TRUC transaction has at
+//! most one unconfirmed parent and one unconfirmed child, TRUC and non-TRUC
+//! transactions never spend each other's unconfirmed outputs, and a child is
+//! kept small so it cannot pin its parent. A second child of a TRUC parent
+//! may evict the first (sibling eviction) by paying more for it.
+//!
+//! An ephemeral anchor is a zero-value output on a zero-fee transaction. It
+//! exists only to be spent by a fee-paying child in the same package, so the
+//! parent never sits in the mempool with the output unspent.
+
+use bllvm_protocol::{Hash, OutPoint, Transaction};
+use std::fmt;
+
+/// Why a transaction breaks the TRUC or ephemeral anchor rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyViolation {
+    /// TRUC transaction above the TRUC size limit
+    TrucTooBig,
+    /// TRUC transaction with an unconfirmed parent above the child size limit
+    TrucChildTooBig,
+    /// TRUC transaction with more than one unconfirmed ancestor
+    TrucTooManyAncestors,
+    /// TRUC parent already has a child that cannot be evicted
+    TrucTooManyDescendants,
+    /// TRUC transaction spending an unconfirmed non-TRUC transaction
+    TrucSpendsNonTruc,
+    /// Non-TRUC transaction spending an unconfirmed TRUC transaction
+    NonTrucSpendsTruc,
+    /// TRUC child paying too little to evict its sibling
+    SiblingEvictionFee,
+    /// Zero-value output that cannot be an ephemeral anchor
+    Dust,
+    /// Ephemeral anchor on a transaction that pays a fee
+    DustWithFee,
+    /// Ephemeral anchor left unspent by the package, or by a child
+    MissingEphemeralSpends,
+}
+
+impl PolicyViolation {
+    /// Reject reason reported to peers and over RPC
+    pub fn reject_reason(&self) -> &'static str {
+        match self {
+            PolicyViolation::TrucTooBig => "TRUC-violation: version=3 tx is too big",
+            PolicyViolation::TrucChildTooBig => "TRUC-violation: version=3 child tx is too big",
+            PolicyViolation::TrucTooManyAncestors => {
+                "TRUC-violation: tx would have too many ancestors"
+            }
+            PolicyViolation::TrucTooManyDescendants => {
+                "TRUC-violation: tx would exceed descendant count limit"
+            }
+            PolicyViolation::TrucSpendsNonTruc => {
+                "TRUC-violation: version=3 tx cannot spend from non-version=3 tx"
+            }
+            PolicyViolation::NonTrucSpendsTruc => {
+                "TRUC-violation: non-version=3 tx cannot spend from version=3 tx"
+            }
+            PolicyViolation::SiblingEvictionFee => {
+                "TRUC-violation: insufficient fee to evict sibling"
+            }
+            PolicyViolation::Dust => "dust",
+            PolicyViolation::DustWithFee => "dust, tx with dust output must be 0-fee",
+            PolicyViolation::MissingEphemeralSpends => "missing-ephemeral-spends",
+        }
+    }
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.reject_reason())
+    }
+}
+
+/// TRUC size limits in vbytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrucLimits {
+    pub max_vsize: u64,
+    pub child_max_vsize: u64,
+}
+
+/// An unconfirmed (mempool or package) parent of a transaction
+#[derive(Debug, Clone)]
+pub struct UnconfirmedParent<'a> {
+    pub txid: Hash,
+    pub tx: &'a Transaction,
+    /// Whether the parent has unconfirmed parents of its own
+    pub has_unconfirmed_parents: bool,
+    /// The parent's other unconfirmed children
+    pub children: Vec<Hash>,
+}
+
+/// Whether a transaction opts into the TRUC rules
+pub fn is_truc(tx: &Transaction) -> bool {
+    tx.version == 3
+}
+
+/// Zero-value outputs of a transaction
+pub fn ephemeral_anchors(tx: &Transaction, txid: Hash) -> Vec<OutPoint> {
+    tx.outputs
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| output.value == 0)
+        .map(|(index, _)| OutPoint {
+            hash: txid,
+            index: index as u64,
+        })
+        .collect()
+}
+
+/// Check the TRUC rules for a transaction of `vsize` vbytes
+///
+/// Returns the sibling the transaction would have to evict, if its parent
+/// already has a child.
+pub fn check_truc(
+    tx: &Transaction,
+    vsize: u64,
+    parents: &[UnconfirmedParent],
+    limits: TrucLimits,
+) -> Result<Option<Hash>, PolicyViolation> {
+    let truc = is_truc(tx);
+    for parent in parents {
+        match (truc, is_truc(parent.tx)) {
+            (true, false) => return Err(PolicyViolation::TrucSpendsNonTruc),
+            (false, true) => return Err(PolicyViolation::NonTrucSpendsTruc),
+            _ => {}
+        }
+    }
+    if !truc {
+        return Ok(None);
+    }
+    if vsize > limits.max_vsize {
+        return Err(PolicyViolation::TrucTooBig);
+    }
+
+    let parent = match parents {
+        [] => return Ok(None),
+        [parent] if !parent.has_unconfirmed_parents => parent,
+        _ => return Err(PolicyViolation::TrucTooManyAncestors),
+    };
+    if vsize > limits.child_max_vsize {
+        return Err(PolicyViolation::TrucChildTooBig);
+    }
+    match parent.children.as_slice() {
+        [] => Ok(None),
+        [sibling] => Ok(Some(*sibling)),
+        _ => Err(PolicyViolation::TrucTooManyDescendants),
+    }
+}
+
+/// Check a transaction's own zero-value outputs
+///
+/// At most one is allowed, and only as an ephemeral anchor: on a zero-fee
+/// transaction, with ephemeral anchors enabled. `fee` is `None` when the
+/// inputs are not resolved yet.
+pub fn check_ephemeral_outputs(
+    tx: &Transaction,
+    fee: Option<u64>,
+    anchors_enabled: bool,
+) -> Result<(), PolicyViolation> {
+    let anchors = tx.outputs.iter().filter(|output| output.value == 0).count();
+    if anchors == 0 {
+        return Ok(());
+    }
+    if !anchors_enabled || anchors > 1 {
+        return Err(PolicyViolation::Dust);
+    }
+    if fee.is_some_and(|fee| fee != 0) {
+        return Err(PolicyViolation::DustWithFee);
+    }
+    Ok(())
+}
+
+/// Check a transaction spends every ephemeral anchor of its unconfirmed
+/// parents
+pub fn check_ephemeral_spends(
+    tx: &Transaction,
+    parents: &[UnconfirmedParent],
+) -> Result<(), PolicyViolation> {
+    let spends_all = parents.iter().all(|parent| {
+        ephemeral_anchors(parent.tx, parent.txid)
+            .iter()
+            .all(|anchor| tx.inputs.iter().any(|input| input.prevout == *anchor))
+    });
+    if spends_all {
+        Ok(())
+    } else {
+        Err(PolicyViolation::MissingEphemeralSpends)
+    }
+}
@@ -0,0 +1,281 @@
+//! Address and output descriptor decoding
+//!
+//! Turns base58check (P2PKH, P2SH) and bech32/bech32m (segwit) addresses, and
+//! single-key output descriptors, into the scripts they pay to.
+
+use bech32::{FromBase32, Variant};
+use bllvm_protocol::types::ByteString;
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Characters a descriptor may contain, grouped for the checksum (BIP380)
+const DESCRIPTOR_INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+
+const DESCRIPTOR_CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Address prefixes of a network
+struct AddressPrefixes {
+    /// Bech32 human-readable part
+    hrp: &'static str,
+    /// Base58 version byte of P2PKH addresses
+    pubkey_hash: u8,
+    /// Base58 version byte of P2SH addresses
+    script_hash: u8,
+}
+
+fn address_prefixes(network: &str) -> AddressPrefixes {
+    match network {
+        "testnet" => AddressPrefixes {
+            hrp: "tb",
+            pubkey_hash: 0x6f,
+            script_hash: 0xc4,
+        },
+        "regtest" => AddressPrefixes {
+            hrp: "bcrt",
+            pubkey_hash: 0x6f,
+            script_hash: 0xc4,
+        },
+        _ => AddressPrefixes {
+            hrp: "bc",
+            pubkey_hash: 0x00,
+            script_hash: 0x05,
+        },
+    }
+}
+
+/// Decode an address of `network` ("mainnet", "testnet" or "regtest") into
+/// the script it pays to
+pub fn address_to_script_pubkey(address: &str, network: &str) -> Result<ByteString, String> {
+    let prefixes = address_prefixes(network);
+    // Bech32 strings are all one case; the separator is the last '1'
+    let is_bech32 = address
+        .to_lowercase()
+        .rsplit_once('1')
+        .is_some_and(|(hrp, _)| hrp == prefixes.hrp);
+    if is_bech32 {
+        decode_segwit_address(address, prefixes.hrp)
+    } else {
+        decode_base58_address(address, &prefixes)
+    }
+}
+
+/// Decode a segwit address (BIP173, BIP350)
+fn decode_segwit_address(address: &str, hrp: &str) -> Result<ByteString, String> {
+    let (decoded_hrp, data, variant) =
+        bech32::decode(address).map_err(|e| format!("Invalid bech32 encoding: {e}"))?;
+    if decoded_hrp != hrp {
+        return Err(format!(
+            "Address is not for this network (prefix {decoded_hrp})"
+        ));
+    }
+    let (version, program) = data
+        .split_first()
+        .ok_or_else(|| "Missing witness version".to_string())?;
+    let version = version.to_u8();
+    let program =
+        Vec::<u8>::from_base32(program).map_err(|e| format!("Invalid witness program: {e}"))?;
+
+    if version > 16 {
+        return Err(format!("Invalid witness version {version}"));
+    }
+    if !(2..=40).contains(&program.len()) {
+        return Err(format!("Invalid witness program length {}", program.len()));
+    }
+    match (version, variant) {
+        (0, Variant::Bech32) if program.len() == 20 || program.len() == 32 => {}
+        (0, Variant::Bech32) => {
+            return Err(format!(
+                "Invalid witness v0 program length {}",
+                program.len()
+            ))
+        }
+        (0, Variant::Bech32m) => return Err("Witness v0 must use bech32".to_string()),
+        (_, Variant::Bech32) => {
+            return Err(format!("Witness v{version} must use bech32m"));
+        }
+        (_, Variant::Bech32m) => {}
+    }
+
+    // OP_0 or OP_1..OP_16, then a push of the program
+    let mut script = vec![if version == 0 { 0x00 } else { 0x50 + version }];
+    script.push(program.len() as u8);
+    script.extend_from_slice(&program);
+    Ok(script)
+}
+
+/// Decode a base58check P2PKH or P2SH address
+fn decode_base58_address(address: &str, prefixes: &AddressPrefixes) -> Result<ByteString, String> {
+    let data = base58_decode(address)?;
+    if data.len() != 25 {
+        return Err(format!("Invalid address length {}", data.len()));
+    }
+    let (payload, checksum) = data.split_at(21);
+    if Sha256::digest(Sha256::digest(payload))[..4] != *checksum {
+        return Err("Invalid address checksum".to_string());
+    }
+
+    let (version, hash) = (payload[0], &payload[1..]);
+    let mut script = Vec::with_capacity(25);
+    if version == prefixes.pubkey_hash {
+        // OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG
+        script.extend_from_slice(&[0x76, 0xa9, 0x14]);
+        script.extend_from_slice(hash);
+        script.extend_from_slice(&[0x88, 0xac]);
+    } else if version == prefixes.script_hash {
+        // OP_HASH160 <hash> OP_EQUAL
+        script.extend_from_slice(&[0xa9, 0x14]);
+        script.extend_from_slice(hash);
+        script.push(0x87);
+    } else {
+        return Err(format!(
+            "Address is not for this network (version byte {version:#04x})"
+        ));
+    }
+    Ok(script)
+}
+
+fn base58_decode(input: &str) -> Result<Vec<u8>, String> {
+    // Big-endian base-256 digits of the number
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in input.bytes() {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or_else(|| format!("Invalid base58 character '{}'", c as char))?
+            as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    // Each leading '1' is a leading zero byte
+    let zeros = input.bytes().take_while(|&c| c == b'1').count();
+    let mut decoded = vec![0u8; zeros];
+    decoded.extend(bytes);
+    Ok(decoded)
+}
+
+/// Decode a single-key output descriptor into the script it describes
+///
+/// Supports `addr(ADDRESS)`, `raw(HEX)`, and `pk`, `pkh` and `wpkh` of a hex
+/// public key, with an optional `#checksum` (BIP380), which is verified.
+pub fn descriptor_to_script_pubkey(descriptor: &str, network: &str) -> Result<ByteString, String> {
+    let descriptor = match descriptor.split_once('#') {
+        Some((body, checksum)) => {
+            let expected = descriptor_checksum(body)?;
+            if checksum != expected {
+                return Err(format!("Invalid descriptor checksum, expected {expected}"));
+            }
+            body
+        }
+        None => descriptor,
+    };
+
+    let (function, argument) = descriptor
+        .strip_suffix(')')
+        .and_then(|d| d.split_once('('))
+        .ok_or_else(|| format!("Invalid descriptor: {descriptor}"))?;
+    match function {
+        "addr" => address_to_script_pubkey(argument, network),
+        "raw" => hex::decode(argument).map_err(|e| format!("Invalid raw script: {e}")),
+        "pk" => {
+            let key = parse_public_key(argument)?;
+            // <pubkey> OP_CHECKSIG
+            let mut script = vec![key.len() as u8];
+            script.extend_from_slice(&key);
+            script.push(0xac);
+            Ok(script)
+        }
+        "pkh" => {
+            let key_hash = hash160(&parse_public_key(argument)?);
+            let mut script = vec![0x76, 0xa9, 0x14];
+            script.extend_from_slice(&key_hash);
+            script.extend_from_slice(&[0x88, 0xac]);
+            Ok(script)
+        }
+        "wpkh" => {
+            let key = parse_public_key(argument)?;
+            if key.len() != 33 {
+                return Err("wpkh() requires a compressed public key".to_string());
+            }
+            let mut script = vec![0x00, 0x14];
+            script.extend_from_slice(&hash160(&key));
+            Ok(script)
+        }
+        _ => Err(format!("Unsupported descriptor: {function}()")),
+    }
+}
+
+fn parse_public_key(hex_key: &str) -> Result<Vec<u8>, String> {
+    let key = hex::decode(hex_key).map_err(|_| format!("Invalid public key: {hex_key}"))?;
+    match (key.len(), key.first().copied()) {
+        (33, Some(0x02 | 0x03)) | (65, Some(0x04)) => Ok(key),
+        _ => Err(format!("Invalid public key: {hex_key}")),
+    }
+}
+
+fn hash160(data: &[u8]) -> [u8; 20] {
+    let mut hash = [0u8; 20];
+    hash.copy_from_slice(&Ripemd160::digest(Sha256::digest(data)));
+    hash
+}
+
+fn descriptor_polymod(c: u64, value: u64) -> u64 {
+    let top = c >> 35;
+    let mut c = ((c & 0x7_ffff_ffff) << 5) ^ value;
+    for (bit, generator) in [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        if (top >> bit) & 1 == 1 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// Descriptor checksum (BIP380)
+pub fn descriptor_checksum(descriptor: &str) -> Result<String, String> {
+    let mut c = 1u64;
+    let mut class = 0u64;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let position = DESCRIPTOR_INPUT_CHARSET
+            .find(ch)
+            .ok_or_else(|| format!("Invalid character '{ch}' in descriptor"))?
+            as u64;
+        c = descriptor_polymod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = descriptor_polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = descriptor_polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = descriptor_polymod(c, 0);
+    }
+    c ^= 1;
+
+    Ok((0..8)
+        .map(|i| DESCRIPTOR_CHECKSUM_CHARSET[((c >> (5 * (7 - i))) & 31) as usize] as char)
+        .collect())
+}
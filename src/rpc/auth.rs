@@ -334,7 +334,12 @@ impl RpcAuthManager {
             // Default per-method limits (more restrictive for expensive methods)
             match method_name {
                 "getblock" | "getblockheader" | "getrawtransaction" => (20, 2), // Expensive queries
-                "sendrawtransaction" | "submitblock" => (10, 1),                // Write operations
+                "sendrawtransaction"
+                | "submitpackage"
+                | "submitblock"
+                | "generatetoaddress"
+                | "generatetodescriptor"
+                | "generateblock" => (10, 1), // Write operations
                 _ => (100, 10), // Default for other methods
             }
         });
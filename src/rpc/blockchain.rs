@@ -2,11 +2,16 @@
 //!
 //! Implements blockchain-related JSON-RPC methods for querying blockchain state.
 
+use crate::node::reorg::ReorgEngine;
 use crate::rpc::errors::RpcError;
+use crate::storage::blockindex::BlockStatus;
+use crate::storage::chainwork::ChainWork;
+use crate::storage::hashing::{UtxoHashFormat, UtxoSetHasher};
 use crate::storage::Storage;
 use anyhow::Result;
-use bllvm_protocol::BlockHeader;
+use bllvm_protocol::{BitcoinProtocolEngine, BlockHeader};
 use serde_json::{json, Number, Value};
+use std::path::Path;
 use std::sync::Arc;
 use tracing::{debug, warn};
 
@@ -109,37 +114,6 @@ impl BlockchainRpc {
         INITIAL_SUBSIDY >> halvings
     }
 
-    /// Calculate hash_serialized_2 for UTXO set (double SHA256)
-    ///
-    /// Serializes UTXO set deterministically and computes double SHA256 hash.
-    /// Matches Bitcoin Core's gettxoutsetinfo hash_serialized_2 calculation.
-    fn calculate_utxo_set_hash(utxo_set: &bllvm_protocol::UtxoSet) -> [u8; 32] {
-        use crate::storage::hashing::double_sha256;
-
-        // Sort UTXOs for deterministic hashing (by outpoint: hash first, then index)
-        let mut entries: Vec<_> = utxo_set.iter().collect();
-        entries.sort_by(|(a, _), (b, _)| match a.hash.cmp(&b.hash) {
-            std::cmp::Ordering::Equal => a.index.cmp(&b.index),
-            other => other,
-        });
-
-        // Serialize each UTXO entry
-        let mut serialized = Vec::new();
-        for (outpoint, utxo) in entries {
-            // Serialize outpoint (32-byte hash + 8-byte index, little-endian)
-            serialized.extend_from_slice(&outpoint.hash);
-            serialized.extend_from_slice(&outpoint.index.to_le_bytes());
-
-            // Serialize UTXO (8-byte value + script_pubkey + 8-byte height, little-endian)
-            serialized.extend_from_slice(&utxo.value.to_le_bytes());
-            serialized.extend_from_slice(&utxo.script_pubkey);
-            serialized.extend_from_slice(&utxo.height.to_le_bytes());
-        }
-
-        // Double SHA256 hash
-        double_sha256(&serialized)
-    }
-
     /// Calculate confirmations for a block
     fn calculate_confirmations(block_height: u64, tip_height: u64) -> i64 {
         if block_height > tip_height {
@@ -148,14 +122,25 @@ impl BlockchainRpc {
         (tip_height - block_height + 1) as i64
     }
 
+    /// Fail with "Block not found" unless the block is in the block index
+    fn require_indexed(storage: &Storage, hash: &[u8; 32]) -> Result<()> {
+        if storage.block_index().contains(hash)? {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Block not found"))
+        }
+    }
+
+    /// Protocol engine for the network this datadir belongs to
+    fn protocol_engine(storage: &Storage) -> Result<BitcoinProtocolEngine> {
+        let version = storage.chain().get_chain_params()?.protocol_version();
+        BitcoinProtocolEngine::new(version)
+            .map_err(|e| anyhow::anyhow!("Failed to create protocol engine: {}", e))
+    }
+
     /// Format chainwork as hex string (32 bytes, big-endian)
-    /// Supports both u64 (legacy) and u128 (optimized cached chainwork)
-    fn format_chainwork(work: u128) -> String {
-        let mut bytes = [0u8; 32];
-        // Store work in last 16 bytes (big-endian)
-        let work_bytes = work.to_be_bytes();
-        bytes[16..32].copy_from_slice(&work_bytes);
-        hex::encode(bytes)
+    fn format_chainwork(work: ChainWork) -> String {
+        work.to_string()
     }
 
     /// Get blockchain information
@@ -188,7 +173,12 @@ impl BlockchainRpc {
 
             let best_hash = storage.chain().get_tip_hash()?.unwrap_or([0u8; 32]);
             let height = storage.chain().get_height()?.unwrap_or(0);
-            let block_count = storage.blocks().block_count().unwrap_or(0);
+            // Headers sync can run ahead of block download
+            let headers = storage
+                .block_index()
+                .best_header()?
+                .map(|entry| entry.height.max(height))
+                .unwrap_or(height);
 
             let best_hash_hex = {
                 let should_refresh = CACHED_TIP_HASH_HEX.with(|c| {
@@ -235,14 +225,14 @@ impl BlockchainRpc {
                 .get_chainwork(&best_hash)?
                 .unwrap_or_else(|| {
                     // Fallback: calculate total work if cache miss
-                    storage.chain().calculate_total_work().unwrap_or(0) as u128
+                    storage.chain().calculate_total_work().unwrap_or_default()
                 });
             let chainwork_hex = Self::format_chainwork(chainwork);
 
             Ok(json!({
                 "chain": "main",
                 "blocks": height,
-                "headers": block_count,
+                "headers": headers,
                 "bestblockhash": best_hash_hex,
                 "difficulty": difficulty,
                 "mediantime": mediantime,
@@ -758,18 +748,48 @@ impl BlockchainRpc {
 
     /// Get UTXO set information
     ///
-    /// Params: []
-    pub async fn get_txoutset_info(&self) -> Result<Value> {
+    /// Params: ["hash_type", hash_or_height] (optional: "hash_serialized_2"
+    /// (default), "hash_serialized_3" or "none", and a block hash or height)
+    ///
+    /// With hash_type "none" the totals come from the coinstats index when it
+    /// is enabled; statistics for a block other than the tip require it.
+    /// "hash_serialized_3" is the AssumeUTXO snapshot content hash, and is
+    /// always computed from a scan of the UTXO set.
+    pub async fn get_txoutset_info(&self, params: &Value) -> Result<Value> {
         debug!("RPC: gettxoutsetinfo");
 
+        let hash_type = params
+            .get(0)
+            .and_then(|p| p.as_str())
+            .unwrap_or("hash_serialized_2");
+        if !matches!(
+            hash_type,
+            "hash_serialized_2" | "hash_serialized_3" | "none"
+        ) {
+            return Err(RpcError::invalid_params(format!(
+                "Unknown hash_type {hash_type}; expected hash_serialized_2, hash_serialized_3 or none"
+            ))
+            .into());
+        }
+        let hash_or_height = params.get(1).filter(|p| !p.is_null());
+
         if let Some(ref storage) = self.storage {
+            if hash_or_height.is_some() || hash_type == "none" {
+                if let Some(stats) = self.indexed_coin_stats(storage, hash_or_height)? {
+                    return Ok(stats);
+                }
+            }
             let (height, best_hash) = {
                 let h = storage.chain().get_height()?.unwrap_or(0);
                 let hash = storage.chain().get_tip_hash()?.unwrap_or([0u8; 32]);
                 (h, hash)
             };
 
-            if let Ok(Some(stats)) = storage.chain().get_latest_utxo_stats() {
+            let cached_stats = match hash_type {
+                "hash_serialized_3" => None,
+                _ => storage.chain().get_latest_utxo_stats().ok().flatten(),
+            };
+            if let Some(stats) = cached_stats {
                 // Use cached stats - much faster than loading entire UTXO set!
                 Ok(json!({
                     "height": stats.height,
@@ -782,25 +802,36 @@ impl BlockchainRpc {
                     "total_amount": stats.total_amount as f64 / 100_000_000.0
                 }))
             } else {
-                // Fallback: Calculate from UTXO set (expensive, but works if cache is missing)
-                // This will be slow with large UTXO sets, but ensures correctness
-                let utxos = storage.utxos().get_all_utxos()?;
-                let txouts = utxos.len();
-                let total_amount: u64 = utxos.values().map(|utxo| utxo.value as u64).sum();
-
-                // Calculate hash_serialized_2 (double SHA256 of serialized UTXO set)
-                let hash_serialized_2 = Self::calculate_utxo_set_hash(&utxos);
-
-                Ok(json!({
+                // Fallback: scan the UTXO set (expensive, but works if cache is missing)
+                let format = match hash_type {
+                    "hash_serialized_3" => UtxoHashFormat::Serialized3,
+                    _ => UtxoHashFormat::Serialized2,
+                };
+                let mut hasher = UtxoSetHasher::new(format);
+                let mut txouts = 0u64;
+                let mut total_amount = 0u64;
+                storage.utxo_cache().for_each_utxo(|outpoint, utxo| {
+                    txouts += 1;
+                    total_amount += utxo.value as u64;
+                    hasher.add(outpoint, utxo);
+                    Ok(())
+                })?;
+
+                let mut info = json!({
                     "height": height,
                     "bestblock": hex::encode(best_hash),
                     "transactions": storage.transaction_count().unwrap_or(0),
                     "txouts": txouts,
                     "bogosize": txouts * 180, // Approximate
-                    "hash_serialized_2": hex::encode(hash_serialized_2),
                     "disk_size": storage.disk_size().unwrap_or(0),
                     "total_amount": total_amount as f64 / 100_000_000.0
-                }))
+                });
+                let hash = json!(hex::encode(hasher.finish()));
+                match format {
+                    UtxoHashFormat::Serialized3 => info["hash_serialized_3"] = hash,
+                    UtxoHashFormat::Serialized2 => info["hash_serialized_2"] = hash,
+                }
+                Ok(info)
             }
         } else {
             Ok(json!({
@@ -816,6 +847,124 @@ impl BlockchainRpc {
         }
     }
 
+    /// UTXO set totals from the coinstats index
+    ///
+    /// For the tip, returns `None` when the index is disabled or has not
+    /// reached it, so the caller can scan the UTXO set instead.
+    fn indexed_coin_stats(
+        &self,
+        storage: &Storage,
+        hash_or_height: Option<&Value>,
+    ) -> Result<Option<Value>> {
+        let index = match storage.coin_stats_index() {
+            Some(index) => index,
+            None if hash_or_height.is_none() => return Ok(None),
+            None => {
+                return Err(RpcError::invalid_params(
+                    "Querying specific block heights requires the coinstats index \
+                     (enable_coin_stats_index)",
+                )
+                .into())
+            }
+        };
+        let block_hash = match hash_or_height {
+            None => match storage.chain().get_tip_hash()? {
+                Some(hash) => hash,
+                None => return Ok(None),
+            },
+            Some(Value::Number(height)) => {
+                let height = height
+                    .as_u64()
+                    .ok_or_else(|| RpcError::invalid_params("Invalid block height"))?;
+                storage
+                    .blocks()
+                    .get_hash_by_height(height)?
+                    .ok_or_else(|| RpcError::invalid_params("Block height out of range"))?
+            }
+            Some(Value::String(hash)) => decode_hash32(hash)?,
+            Some(_) => {
+                return Err(
+                    RpcError::invalid_params("hash_or_height must be a hash or height").into(),
+                )
+            }
+        };
+
+        match index.get(&block_hash)? {
+            Some(stats) => Ok(Some(json!({
+                "height": stats.height,
+                "bestblock": hex::encode(block_hash),
+                "txouts": stats.txouts,
+                "bogosize": stats.bogosize,
+                "total_amount": stats.total_amount as f64 / 100_000_000.0
+            }))),
+            None if hash_or_height.is_none() => Ok(None),
+            None => Err(RpcError::internal_error(
+                "Unable to find UTXO set statistics for this block; \
+                 the coinstats index may still be syncing",
+            )
+            .into()),
+        }
+    }
+
+    /// Write the UTXO set at the chain tip to an AssumeUTXO snapshot file
+    ///
+    /// Params: ["path"]
+    pub async fn dump_txoutset(&self, params: &Value) -> Result<Value> {
+        debug!("RPC: dumptxoutset");
+
+        let path = params
+            .get(0)
+            .and_then(|p| p.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Path parameter required"))?;
+
+        if let Some(ref storage) = self.storage {
+            let metadata = storage.dump_snapshot(Path::new(path))?;
+            Ok(json!({
+                "coins_written": metadata.coins_count,
+                "base_hash": hex::encode(metadata.base_hash),
+                "base_height": metadata.base_height,
+                "path": path,
+                "txoutset_hash": hex::encode(metadata.utxo_hash),
+            }))
+        } else {
+            Err(anyhow::anyhow!(
+                "Storage not available. This operation requires storage to be initialized."
+            ))
+        }
+    }
+
+    /// Load an AssumeUTXO snapshot into a datadir without a chain
+    ///
+    /// The snapshot must match one of the network's known snapshots. Syncing
+    /// continues from its base block while earlier blocks are validated in the
+    /// background.
+    ///
+    /// Params: ["path"]
+    pub async fn load_txoutset(&self, params: &Value) -> Result<Value> {
+        debug!("RPC: loadtxoutset");
+
+        let path = params
+            .get(0)
+            .and_then(|p| p.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Path parameter required"))?;
+
+        if let Some(ref storage) = self.storage {
+            let chain_params = storage.chain().get_chain_params()?;
+            let metadata =
+                storage.load_snapshot(Path::new(path), chain_params.assumeutxo_snapshots())?;
+            Ok(json!({
+                "coins_loaded": metadata.coins_count,
+                "tip_hash": hex::encode(metadata.base_hash),
+                "base_height": metadata.base_height,
+                "path": path,
+            }))
+        } else {
+            Err(anyhow::anyhow!(
+                "Storage not available. This operation requires storage to be initialized."
+            ))
+        }
+    }
+
     /// Verify blockchain database
     ///
     /// Params: [checklevel (optional, default: 3), numblocks (optional, default: 288)]
@@ -848,7 +997,7 @@ impl BlockchainRpc {
 
             let mut errors = Vec::new();
             let utxo_set = storage
-                .utxos()
+                .utxo_cache()
                 .get_all_utxos()
                 .map_err(|e| anyhow::anyhow!("Failed to get UTXO set: {}", e))?;
 
@@ -973,21 +1122,46 @@ impl BlockchainRpc {
                 }));
             }
 
-            // Add other tracked tips (forks, etc.)
-            if let Ok(chain_tips) = storage.chain().get_chain_tips() {
-                for (hash, height, branchlen, status) in chain_tips {
-                    // Skip if already added as active tip (use cached tip_hash)
-                    if hash == tip_hash {
-                        continue;
-                    }
+            // Add every other leaf of the block index (forks, headers-only branches)
+            let index = storage.block_index();
+            let blocks = storage.blocks();
+            for tip in index.chain_tips()? {
+                if tip.hash == tip_hash {
+                    continue;
+                }
 
-                    tips.push(json!({
-                        "height": height,
-                        "hash": hex::encode(hash),
-                        "branchlen": branchlen,
-                        "status": status
-                    }));
+                // Walk back to the active chain, noting what the branch lacks
+                let mut status = "valid-fork";
+                let mut fork_height = None;
+                let mut current = Some(tip.clone());
+                while let Some(entry) = current {
+                    if blocks.is_in_main_chain(&entry.hash)? {
+                        fork_height = Some(entry.height);
+                        break;
+                    }
+                    if !entry.has_data() {
+                        status = "headers-only";
+                    } else if status == "valid-fork"
+                        && !entry.status.contains(BlockStatus::FULLY_VALID)
+                    {
+                        status = "valid-headers";
+                    }
+                    current = index.get(entry.parent())?;
+                }
+                if tip.is_failed() {
+                    status = "invalid";
                 }
+                let branchlen = match fork_height {
+                    Some(fork_height) => tip.height - fork_height,
+                    None => tip.height + 1,
+                };
+
+                tips.push(json!({
+                    "height": tip.height,
+                    "hash": hex::encode(tip.hash),
+                    "branchlen": branchlen,
+                    "status": status
+                }));
             }
 
             Ok(json!(tips))
@@ -1364,16 +1538,16 @@ impl BlockchainRpc {
             decode_hash32(blockhash).map_err(|e| anyhow::anyhow!("Invalid block hash: {}", e))?;
 
         if let Some(ref storage) = self.storage {
-            // Mark block as invalid
-            storage.chain().mark_invalid(&hash)?;
+            Self::require_indexed(storage, &hash)?;
+            let protocol = Self::protocol_engine(storage)?;
+            let blocks = storage.blocks();
+            let tip_height = storage.chain().get_height()?.unwrap_or(0);
+            let mut utxos = storage.utxo_cache();
 
-            // Check if this is the current tip - if so, we'd need to trigger a reorg
-            // For now, we just mark it as invalid and let the node handle it on next block
-            if let Ok(Some(tip_hash)) = storage.chain().get_tip_hash() {
-                if hash == tip_hash {
-                    warn!("Invalidated current chain tip - reorg may be needed");
-                }
-            }
+            // Marks the block and its descendants failed, disconnects it if it
+            // is on the active chain and activates the best remaining chain
+            ReorgEngine::new(&blocks, &protocol, Some(storage))
+                .invalidate_block(&hash, tip_height, &mut utxos)?;
 
             Ok(Value::Null)
         } else {
@@ -1399,11 +1573,16 @@ impl BlockchainRpc {
             decode_hash32(blockhash).map_err(|e| anyhow::anyhow!("Invalid block hash: {}", e))?;
 
         if let Some(ref storage) = self.storage {
-            // Remove from invalid blocks set
-            storage.chain().unmark_invalid(&hash)?;
+            Self::require_indexed(storage, &hash)?;
+            let protocol = Self::protocol_engine(storage)?;
+            let blocks = storage.blocks();
+            let tip_height = storage.chain().get_height()?.unwrap_or(0);
+            let mut utxos = storage.utxo_cache();
 
-            // If this block is now valid, it may be reconsidered for chain inclusion
-            // The node will handle this on next block processing
+            // Clears the failure flags and switches to the block's chain if it
+            // now has the most work
+            ReorgEngine::new(&blocks, &protocol, Some(storage))
+                .reconsider_block(&hash, tip_height, &mut utxos)?;
 
             Ok(Value::Null)
         } else {
@@ -1562,6 +1741,18 @@ impl BlockchainRpc {
             decode_hash32(blockhash).map_err(|e| anyhow::anyhow!("Invalid block hash: {}", e))?;
 
         if let Some(ref storage) = self.storage {
+            if let Some(index) = storage.filter_index() {
+                let filter = index.get(&hash)?.ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Filter not found. Block filters may still be in the process of being indexed."
+                    )
+                })?;
+                return Ok(json!({
+                    "filter": hex::encode(&filter.filter_data),
+                    "header": hex::encode(filter.header().header_hash()),
+                }));
+            }
+
             // Get block from storage
             if let Ok(Some(block)) = storage.blocks().get_block(&hash) {
                 // Get filter service from network manager (if available)
@@ -1571,7 +1762,7 @@ impl BlockchainRpc {
                 // Get previous outpoint scripts from UTXO set
                 // For each input, find the UTXO and get its script_pubkey
                 let mut previous_scripts = Vec::new();
-                if let Ok(utxo_set) = storage.utxos().get_all_utxos() {
+                if let Ok(utxo_set) = storage.utxo_cache().get_all_utxos() {
                     for tx in &block.transactions {
                         for input in &tx.inputs {
                             if let Some(utxo) = utxo_set.get(&input.prevout) {
@@ -1603,8 +1794,8 @@ impl BlockchainRpc {
 
     /// Get index information
     ///
-    /// Params: [] (no parameters)
-    pub async fn get_index_info(&self, _params: &Value) -> Result<Value> {
+    /// Params: ["index_name"] (optional, only report this index)
+    pub async fn get_index_info(&self, params: &Value) -> Result<Value> {
         debug!("RPC: getindexinfo");
 
         let storage = self.storage.as_ref().ok_or_else(|| {
@@ -1612,31 +1803,30 @@ impl BlockchainRpc {
                 "Storage not available. This operation requires storage to be initialized.",
             )
         })?;
+        let index_name = params.get(0).and_then(|p| p.as_str());
 
-        // Get index statistics
-        let index_stats = storage
-            .transactions()
-            .get_index_stats()
-            .map_err(|e| RpcError::internal_error(format!("Failed to get index stats: {e}")))?;
-
-        let best_block_height = storage.chain().get_height()?.unwrap_or(0);
-
-        // Return available indexes with statistics
-        Ok(json!({
-            "txindex": {
-                "synced": true,
-                "best_block_height": best_block_height,
-                "total_transactions": index_stats.total_transactions,
-                "address_index_enabled": index_stats.address_index_enabled,
-                "value_index_enabled": index_stats.value_index_enabled,
-                "indexed_addresses": index_stats.indexed_addresses,
-                "indexed_value_buckets": index_stats.indexed_value_buckets,
-            },
-            "basic block filter index": {
-                "synced": true,
-                "best_block_height": best_block_height
+        let mut result = serde_json::Map::new();
+        for info in storage.index_info()? {
+            if index_name.is_some_and(|name| name != info.name) {
+                continue;
             }
-        }))
+            let mut entry = json!({
+                "synced": info.synced,
+                "best_block_height": info.best_block_height,
+            });
+            if info.name == "txindex" {
+                let stats = storage.transactions().get_index_stats().map_err(|e| {
+                    RpcError::internal_error(format!("Failed to get index stats: {e}"))
+                })?;
+                entry["total_transactions"] = json!(stats.total_transactions);
+                entry["address_index_enabled"] = json!(stats.address_index_enabled);
+                entry["value_index_enabled"] = json!(stats.value_index_enabled);
+                entry["indexed_addresses"] = json!(stats.indexed_addresses);
+                entry["indexed_value_buckets"] = json!(stats.indexed_value_buckets);
+            }
+            result.insert(info.name.to_string(), entry);
+        }
+        Ok(Value::Object(result))
     }
 
     /// Get transaction IDs for an address
@@ -1902,7 +2092,13 @@ impl BlockchainRpc {
                             hash: txid,
                             index: idx as u64,
                         };
-                        if storage.utxos().get_utxo(&outpoint).ok().flatten().is_some() {
+                        if storage
+                            .utxo_cache()
+                            .get_utxo(&outpoint)
+                            .ok()
+                            .flatten()
+                            .is_some()
+                        {
                             // UTXO is unspent
                             balance += output.value;
                             utxo_count += 1;
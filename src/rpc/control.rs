@@ -223,10 +223,13 @@ impl ControlRpc {
             "getblockcount",
             "getdifficulty",
             "gettxoutsetinfo",
+            "dumptxoutset",
+            "loadtxoutset",
             "verifychain",
             "getrawtransaction",
             "sendrawtransaction",
             "testmempoolaccept",
+            "submitpackage",
             "decoderawtransaction",
             "gettxout",
             "gettxoutproof",
@@ -247,6 +250,9 @@ impl ControlRpc {
             "getmininginfo",
             "getblocktemplate",
             "submitblock",
+            "generatetoaddress",
+            "generatetodescriptor",
+            "generateblock",
             "estimatesmartfee",
             "stop",
             "uptime",
@@ -297,10 +303,13 @@ impl ControlRpc {
                 "getblockcount",
                 "getdifficulty",
                 "gettxoutsetinfo",
+                "dumptxoutset",
+                "loadtxoutset",
                 "verifychain",
                 "getrawtransaction",
                 "sendrawtransaction",
                 "testmempoolaccept",
+                "submitpackage",
                 "decoderawtransaction",
                 "gettxout",
                 "gettxoutproof",
@@ -321,6 +330,9 @@ impl ControlRpc {
                 "getmininginfo",
                 "getblocktemplate",
                 "submitblock",
+                "generatetoaddress",
+                "generatetodescriptor",
+                "generateblock",
                 "estimatesmartfee",
                 "stop",
                 "uptime",
@@ -2,6 +2,7 @@
 //!
 //! Bitcoin Core-compatible JSON-RPC error codes and error handling
 
+use crate::node::truc::PolicyViolation;
 use serde_json::{json, Value};
 use std::fmt;
 
@@ -25,6 +26,8 @@ pub enum RpcErrorCode {
     TxAlreadyInChain,
     /// Transaction rejected (-25)
     TxRejected,
+    /// Transaction rejected by mempool policy (-26)
+    TxPolicyRejected,
     /// Transaction missing inputs (-1)
     TxMissingInputs,
     /// Transaction already in mempool (-27)
@@ -49,6 +52,7 @@ impl RpcErrorCode {
             RpcErrorCode::ServerError(code) => *code,
             RpcErrorCode::TxAlreadyInChain => -1,
             RpcErrorCode::TxRejected => -25,
+            RpcErrorCode::TxPolicyRejected => -26,
             RpcErrorCode::TxMissingInputs => -1,
             RpcErrorCode::TxAlreadyInMempool => -27,
             RpcErrorCode::BlockNotFound => -5,
@@ -68,6 +72,7 @@ impl RpcErrorCode {
             RpcErrorCode::ServerError(_) => "Server error",
             RpcErrorCode::TxAlreadyInChain => "Transaction already in block chain",
             RpcErrorCode::TxRejected => "Transaction rejected",
+            RpcErrorCode::TxPolicyRejected => "Transaction rejected by mempool policy",
             RpcErrorCode::TxMissingInputs => "Missing inputs",
             RpcErrorCode::TxAlreadyInMempool => "Transaction already in mempool",
             RpcErrorCode::BlockNotFound => "Block not found",
@@ -190,6 +195,24 @@ impl RpcError {
         Self::with_data(RpcErrorCode::TxRejected, reason, data)
     }
 
+    /// Transaction rejected by a mempool policy rule (TRUC, ephemeral
+    /// anchors)
+    pub fn tx_policy_rejected(violation: PolicyViolation, txid: Option<&str>) -> Self {
+        let mut data = json!({
+            "reject_reason": violation.reject_reason(),
+        });
+
+        if let Some(txid) = txid {
+            data["txid"] = json!(txid);
+        }
+
+        Self::with_data(
+            RpcErrorCode::TxPolicyRejected,
+            violation.reject_reason(),
+            data,
+        )
+    }
+
     /// Transaction rejected due to insufficient fee
     pub fn tx_rejected_insufficient_fee(
         txid: Option<&str>,
@@ -493,6 +516,14 @@ mod tests {
         assert!(err.message.contains("abc123"));
     }
 
+    #[test]
+    fn test_policy_rejection() {
+        let err = RpcError::tx_policy_rejected(PolicyViolation::TrucChildTooBig, Some("abc123"));
+        assert_eq!(err.code.code(), -26);
+        assert_eq!(err.message, "TRUC-violation: version=3 child tx is too big");
+        assert_eq!(err.data.unwrap()["txid"], "abc123");
+    }
+
     #[test]
     fn test_error_to_json() {
         let err = RpcError::method_not_found("test");
@@ -64,7 +64,9 @@ impl MempoolRpc {
                 "usage": bytes,
                 "maxmempool": 300000000,
                 "mempoolminfee": 0.00001000,
-                "minrelaytxfee": 0.00001000
+                "minrelaytxfee": 0.00001000,
+                "orphans": mempool.orphan_count(),
+                "orphanbytes": mempool.orphan_bytes()
             }))
         } else {
             // Graceful degradation: return empty mempool info when mempool unavailable
@@ -79,6 +81,8 @@ impl MempoolRpc {
                 "maxmempool": 300000000,
                 "mempoolminfee": 0.00001000,
                 "minrelaytxfee": 0.00001000,
+                "orphans": 0,
+                "orphanbytes": 0,
                 "note": "Mempool not available - returning empty mempool"
             }))
         }
@@ -104,7 +108,15 @@ impl MempoolRpc {
                 let utxo_set = if let (Some(_mempool), Some(storage)) =
                     (self.mempool.as_ref(), self.storage.as_ref())
                 {
-                    Some(storage.utxos().get_all_utxos().unwrap_or_default())
+                    let prevouts = transactions
+                        .iter()
+                        .flat_map(|tx| tx.inputs.iter().map(|input| &input.prevout));
+                    Some(
+                        storage
+                            .utxo_cache()
+                            .fetch_coins(prevouts)
+                            .unwrap_or_default(),
+                    )
                 } else {
                     None
                 };
@@ -188,14 +200,14 @@ impl MempoolRpc {
 
     /// Save mempool to disk (for node restart persistence)
     ///
+    /// Writes to the configured `mempool_persistence_path`.
+    ///
     /// Params: []
     pub async fn savemempool(&self, _params: &Value) -> RpcResult<Value> {
         debug!("RPC: savemempool");
 
         if let Some(mempool) = &self.mempool {
-            use crate::utils::env_or_default;
-            let data_dir = env_or_default("DATA_DIR", "data");
-            let mempool_path = std::path::Path::new(&data_dir).join("mempool.dat");
+            let mempool_path = mempool.persistence_path();
 
             // Arc implements Deref, so we can call methods directly
             if let Err(e) = mempool.save_to_disk(&mempool_path) {
@@ -204,7 +216,7 @@ impl MempoolRpc {
                 )));
             }
 
-            Ok(Value::Null)
+            Ok(json!({ "filename": mempool_path.display().to_string() }))
         } else {
             Err(crate::rpc::errors::RpcError::internal_error(
                 "Mempool not initialized".to_string(),
@@ -256,7 +268,10 @@ impl MempoolRpc {
                         result.insert(ancestor_txid, json!({
                             "size": size,
                             "fee": if let Some(ref storage) = self.storage {
-                                let utxo_set = storage.utxos().get_all_utxos().unwrap_or_default();
+                                let utxo_set = storage
+                                    .utxo_cache()
+                                    .fetch_coins(ancestor_tx.inputs.iter().map(|input| &input.prevout))
+                                    .unwrap_or_default();
                                 let fee_satoshis = mempool.calculate_transaction_fee(&ancestor_tx, &utxo_set);
                                 fee_satoshis as f64 / 100_000_000.0
                             } else {
@@ -365,7 +380,10 @@ impl MempoolRpc {
                         result.insert(descendant_txid, json!({
                             "size": size,
                             "fee": if let Some(ref storage) = self.storage {
-                                let utxo_set = storage.utxos().get_all_utxos().unwrap_or_default();
+                                let utxo_set = storage
+                                    .utxo_cache()
+                                    .fetch_coins(descendant_tx.inputs.iter().map(|input| &input.prevout))
+                                    .unwrap_or_default();
                                 let fee_satoshis = mempool.calculate_transaction_fee(&descendant_tx, &utxo_set);
                                 fee_satoshis as f64 / 100_000_000.0
                             } else {
@@ -454,18 +472,23 @@ impl MempoolRpc {
                     .sum();
 
                 let fee = if let Some(ref storage) = self.storage {
-                    let utxo_set = storage.utxos().get_all_utxos().unwrap_or_default();
+                    let utxo_set = storage
+                        .utxo_cache()
+                        .fetch_coins(tx.inputs.iter().map(|input| &input.prevout))
+                        .unwrap_or_default();
                     let fee_satoshis = mempool.calculate_transaction_fee(&tx, &utxo_set);
                     fee_satoshis as f64 / 100_000_000.0
                 } else {
                     0.0
                 };
 
+                let modified_fee = fee + mempool.fee_delta(&hash) as f64 / 100_000_000.0;
+
                 Ok(json!({
                     "size": size,
                     "fee": fee,
-                    "modifiedfee": fee,
-                    "time": current_timestamp(),
+                    "modifiedfee": modified_fee,
+                    "time": mempool.entry_time(&hash).unwrap_or_else(current_timestamp),
                     "height": -1,
                     "descendantcount": descendant_count + 1,
                     "descendantsize": descendant_size + size,
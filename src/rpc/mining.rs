@@ -3,23 +3,46 @@
 //! Implements mining-related JSON-RPC methods for block template generation and mining.
 //! Uses formally verified consensus-proof mining functions.
 
+use crate::node::fee_estimation::{EstimateMode, MAX_CONFIRMATION_TARGET};
 use crate::node::mempool::MempoolManager;
+use crate::node::miner::{
+    transaction_weight, MiningCoordinator, TemplateEntry, TransactionSelector, DEFAULT_MAX_TRIES,
+};
+use crate::node::sync::{BlockProcessOutcome, SyncCoordinator};
+use crate::node::BlockSubmission;
+use crate::rpc::address::{address_to_script_pubkey, descriptor_to_script_pubkey};
 use crate::rpc::errors::{RpcError, RpcResult};
+use crate::rpc::validation::{
+    validate_numeric_param, validate_optional_numeric_param, validate_string_param,
+    MAX_ADDRESS_STRING_LENGTH,
+};
 use crate::storage::Storage;
 use crate::utils::current_timestamp;
-use bllvm_protocol::mining::BlockTemplate;
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::segwit::Witness;
 use bllvm_protocol::serialization::deserialize_block_with_witnesses;
 use bllvm_protocol::serialization::serialize_transaction;
+use bllvm_protocol::serialization::transaction::deserialize_transaction;
 use bllvm_protocol::{
-    types::{BlockHeader, ByteString, Natural, Transaction, UtxoSet},
-    ConsensusProof, ValidationResult,
+    types::{BlockHeader, ByteString, Hash, Natural, Transaction, UtxoSet},
+    BitcoinProtocolEngine, Block, ConsensusProof,
 };
 use hex;
 use serde_json::{json, Value};
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex};
 use tracing::{debug, warn};
 
+/// Lowest fee rate estimatesmartfee reports (sat/vB)
+const MIN_RELAY_FEE_RATE: f64 = 1.0;
+
+/// Convert a fee rate from sat/vB to BTC/kvB
+fn sat_per_vb_to_btc_per_kvb(fee_rate: f64) -> f64 {
+    fee_rate * 1000.0 / 100_000_000.0
+}
+
 /// Mining RPC methods with dependencies
 pub struct MiningRpc {
     /// Consensus proof instance for mining operations
@@ -28,6 +51,10 @@ pub struct MiningRpc {
     storage: Option<Arc<Storage>>,
     /// Mempool accessor for transaction retrieval
     mempool: Option<Arc<MempoolManager>>,
+    /// Held while generating, so blocks are mined on the tip the last one left
+    generate_lock: Mutex<()>,
+    /// Where blocks go to be processed by the node, if one is attached
+    block_submitter: Option<mpsc::UnboundedSender<BlockSubmission>>,
 }
 
 impl MiningRpc {
@@ -37,6 +64,8 @@ impl MiningRpc {
             consensus: ConsensusProof::new(),
             storage: None,
             mempool: None,
+            generate_lock: Mutex::new(()),
+            block_submitter: None,
         }
     }
 
@@ -46,9 +75,20 @@ impl MiningRpc {
             consensus: ConsensusProof::new(),
             storage: Some(storage),
             mempool: Some(mempool),
+            generate_lock: Mutex::new(()),
+            block_submitter: None,
         }
     }
 
+    /// Send submitted and generated blocks to the node for processing
+    pub fn with_block_submitter(
+        mut self,
+        block_submitter: mpsc::UnboundedSender<BlockSubmission>,
+    ) -> Self {
+        self.block_submitter = Some(block_submitter);
+        self
+    }
+
     /// Get mining information
     pub async fn get_mining_info(&self) -> RpcResult<Value> {
         #[cfg(debug_assertions)]
@@ -193,12 +233,13 @@ impl MiningRpc {
             .ok_or_else(|| RpcError::internal_error("No chain tip"))?;
         let prev_headers = self.get_headers_for_difficulty()?;
 
-        // 2. Get mempool transactions
-        let mempool_txs: Vec<Transaction> = self.get_mempool_transactions()?;
-
-        // 3. Get UTXO set
+        // 2. Get UTXO set
         let utxo_set = self.get_utxo_set()?;
 
+        // 3. Assemble mempool transactions by ancestor package fee rate
+        let entries = self.assemble_transactions(&utxo_set);
+        let mempool_txs: Vec<Transaction> = entries.iter().map(|entry| entry.tx.clone()).collect();
+
         // 4. Extract coinbase parameters from request or use defaults
         let coinbase_script = self.extract_coinbase_script(params).unwrap_or_default();
         let coinbase_address = self.extract_coinbase_address(params).unwrap_or_default();
@@ -224,15 +265,19 @@ impl MiningRpc {
         };
 
         // 6. Convert to JSON-RPC format (BIP 22/23)
-        self.template_to_json_rpc(&template, &prev_header, height)
+        self.template_to_json_rpc(&template, &prev_header, height, &entries)
     }
 
     /// Convert BlockTemplate to JSON-RPC format
+    ///
+    /// `entries` supplies the fee, weight and in-mempool parents of each
+    /// template transaction.
     fn template_to_json_rpc(
         &self,
         template: &bllvm_protocol::mining::BlockTemplate,
         prev_header: &BlockHeader,
         height: Natural,
+        entries: &[TemplateEntry],
     ) -> RpcResult<Value> {
         // Convert previous block hash to hex (big-endian)
         let prev_hash_hex = hex::encode(prev_header.prev_block_hash);
@@ -243,15 +288,23 @@ impl MiningRpc {
         // Convert bits to hex (8 characters)
         let bits_hex = format!("{:08x}", template.header.bits);
 
-        // Convert transactions to JSON array
-        let transactions_json: Vec<Value> = template
-            .transactions
-            .iter()
-            .map(|tx| self.transaction_to_json(tx))
-            .collect();
+        // Convert transactions to JSON array; depends are 1-based positions
+        // in this array
+        let entries: HashMap<Hash, &TemplateEntry> =
+            entries.iter().map(|entry| (entry.txid, entry)).collect();
+        let mut positions: HashMap<Hash, usize> = HashMap::new();
+        let mut total_fees = 0u64;
+        let mut transactions_json = Vec::with_capacity(template.transactions.len());
+        for tx in template.transactions.iter() {
+            let txid = calculate_tx_id(tx);
+            let entry = entries.get(&txid).copied();
+            total_fees += entry.map_or(0, |entry| entry.fee);
+            transactions_json.push(self.transaction_to_json(tx, entry, &positions));
+            positions.insert(txid, transactions_json.len());
+        }
 
         // Calculate coinbase value (subsidy + fees)
-        let coinbase_value = self.calculate_coinbase_value(template, height);
+        let coinbase_value = self.consensus.get_block_subsidy(template.height) as u64 + total_fees;
 
         // Get active rules (BIP 9 feature flags)
         let rules = self.get_active_rules(height);
@@ -356,16 +409,11 @@ impl MiningRpc {
         }
     }
 
-    fn get_mempool_transactions(&self) -> RpcResult<Vec<Transaction>> {
+    fn assemble_transactions(&self, utxo_set: &UtxoSet) -> Vec<TemplateEntry> {
         if let Some(ref mempool) = self.mempool {
-            // Get UTXO set for fee calculation
-            let utxo_set = self.get_utxo_set()?;
-
-            // Get prioritized transactions (limit to reasonable number for block template)
-            let limit = 1000;
-            Ok(mempool.get_prioritized_transactions(limit, &utxo_set))
+            TransactionSelector::new().assemble(mempool.as_ref(), utxo_set)
         } else {
-            Ok(vec![])
+            vec![]
         }
     }
 
@@ -459,7 +507,7 @@ impl MiningRpc {
         if let Some(ref storage) = self.storage {
             // Get UTXO set from storage
             storage
-                .utxos()
+                .utxo_cache()
                 .get_all_utxos()
                 .map_err(|e| RpcError::internal_error(format!("Failed to get UTXO set: {e}")))
         } else {
@@ -496,17 +544,36 @@ impl MiningRpc {
         Some(vec![])
     }
 
-    fn transaction_to_json(&self, tx: &Transaction) -> Value {
+    fn transaction_to_json(
+        &self,
+        tx: &Transaction,
+        entry: Option<&TemplateEntry>,
+        positions: &HashMap<Hash, usize>,
+    ) -> Value {
         // Convert transaction to JSON-RPC format
         let tx_bytes = serialize_transaction(tx);
         let tx_hash = self.calculate_tx_hash(&tx_bytes);
-        let fee = self.calculate_transaction_fee(tx);
         let sigops = self.count_sigops(tx);
-        let weight = self.calculate_weight(tx);
+        let (fee, weight, depends) = match entry {
+            Some(entry) => {
+                let depends: Vec<usize> = entry
+                    .depends
+                    .iter()
+                    .filter_map(|parent| positions.get(parent).copied())
+                    .collect();
+                (entry.fee, entry.weight, depends)
+            }
+            None => (
+                self.calculate_transaction_fee(tx),
+                transaction_weight(tx),
+                vec![],
+            ),
+        };
 
         json!({
             "data": hex::encode(&tx_bytes),
             "txid": hex::encode(tx_hash),
+            "depends": depends,
             "fee": fee,
             "sigops": sigops,
             "weight": weight,
@@ -573,27 +640,6 @@ impl MiningRpc {
         }
     }
 
-    fn calculate_weight(&self, tx: &Transaction) -> u64 {
-        // Transaction weight = (base_size * 3) + total_size (for SegWit)
-        // For now, return base size * 4 (non-SegWit transaction)
-        let base_size = serialize_transaction(tx).len() as u64;
-        base_size * 4
-    }
-
-    fn calculate_coinbase_value(&self, template: &BlockTemplate, _height: Natural) -> u64 {
-        // Use consensus-proof's get_block_subsidy (formally verified)
-        let subsidy = self.consensus.get_block_subsidy(template.height) as u64;
-
-        // Calculate total fees from transactions
-        let fees: u64 = template
-            .transactions
-            .iter()
-            .map(|tx| self.calculate_transaction_fee(tx))
-            .sum();
-
-        subsidy + fees
-    }
-
     fn get_active_rules(&self, height: Natural) -> Vec<String> {
         // Determine active BIP 9 rules based on height
         let mut rules = vec!["csv".to_string()]; // CSV always active after height
@@ -637,109 +683,336 @@ impl MiningRpc {
             .map_err(|e| RpcError::invalid_params(format!("Invalid hex data: {e}")))?;
 
         // Deserialize block
-        let (block, _witnesses) = deserialize_block_with_witnesses(&block_bytes)
+        let (block, witnesses) = deserialize_block_with_witnesses(&block_bytes)
             .map_err(|e| RpcError::invalid_params(format!("Failed to deserialize block: {e}")))?;
 
-        // Get current chain state
-        let height = self
-            .get_current_height()?
+        match self.process_block(block, witnesses).await? {
+            BlockProcessOutcome::Connected { hash, .. } => {
+                debug!("Submitted block {} connected", hex::encode(hash));
+                Ok(Value::Null)
+            }
+            BlockProcessOutcome::Reorganized(_)
+            | BlockProcessOutcome::BackgroundValidated { .. } => Ok(Value::Null),
+            // Valid as far as it could be checked, but not on the active chain
+            BlockProcessOutcome::SideBranch { .. } => Ok(json!("inconclusive")),
+            BlockProcessOutcome::AlreadyKnown => Ok(json!("duplicate")),
+            BlockProcessOutcome::Rejected => Err(RpcError::invalid_params(
+                "Invalid block: failed validation or does not connect to a known block",
+            )),
+        }
+    }
+
+    /// Process a block the way the node processes blocks from the network
+    ///
+    /// The path shared by submitblock and the generate methods. With a node
+    /// attached ([`Self::with_block_submitter`]) the block goes through
+    /// [`Node::process_parsed_block`](crate::node::Node::process_parsed_block),
+    /// which also updates the mempool, fee estimates and notifications.
+    /// Otherwise it is processed by the same [`SyncCoordinator`] path and its
+    /// transactions leave the mempool.
+    async fn process_block(
+        &self,
+        block: Block,
+        witnesses: Vec<Witness>,
+    ) -> RpcResult<BlockProcessOutcome> {
+        if let Some(ref block_submitter) = self.block_submitter {
+            let (reply, outcome) = oneshot::channel();
+            block_submitter
+                .send(BlockSubmission {
+                    block,
+                    witnesses,
+                    reply,
+                })
+                .map_err(|_| RpcError::internal_error("Node is not processing blocks"))?;
+            return outcome
+                .await
+                .map_err(|_| RpcError::internal_error("Node stopped before processing the block"))?
+                .map_err(|e| RpcError::internal_error(format!("Failed to process block: {e}")));
+        }
+
+        let storage = self
+            .storage
+            .as_ref()
             .ok_or_else(|| RpcError::internal_error("Chain not initialized"))?;
-        let utxo_set = self.get_utxo_set()?;
+        let current_height = self.get_current_height()?.map_or(0, |height| height + 1);
+        let protocol_version = storage
+            .chain()
+            .get_chain_params()
+            .map_err(|e| RpcError::internal_error(format!("Failed to get chain params: {e}")))?
+            .protocol_version();
+        let protocol = BitcoinProtocolEngine::new(protocol_version).map_err(|e| {
+            RpcError::internal_error(format!("Failed to create protocol engine: {e}"))
+        })?;
+        let mut utxos = storage.utxo_cache();
+        let outcome = SyncCoordinator::new()
+            .process_parsed_block(
+                &storage.blocks(),
+                &protocol,
+                Some(storage),
+                &block,
+                &witnesses,
+                current_height,
+                &mut utxos,
+                None,
+                None,
+            )
+            .map_err(|e| RpcError::internal_error(format!("Failed to process block: {e}")))?;
+        if matches!(
+            outcome,
+            BlockProcessOutcome::Connected { .. } | BlockProcessOutcome::Reorganized(_)
+        ) {
+            if let Some(ref mempool) = self.mempool {
+                mempool.remove_for_block(&block.transactions);
+            }
+        }
+        Ok(outcome)
+    }
 
-        // Validate block using consensus layer
-        // Note: This validates consensus rules, but doesn't check if block extends chain
-        // In production, would also check:
-        // 1. Block extends current tip
-        // 2. Block is not already in chain
-        // 3. Block is not orphaned
-        match self.consensus.validate_block(&block, utxo_set, height) {
-            Ok((ValidationResult::Valid, _)) => {
-                // Block is valid - in production would submit to block processor
-                // For now, just return success
-                debug!("Block submitted successfully");
-                Ok(Value::Null)
+    /// Mine blocks paying the coinbase to an address (regtest only)
+    ///
+    /// Params: [nblocks, "address", maxtries (optional, default: 1000000)]
+    ///
+    /// `maxtries` is the number of nonces tried per block. Returns the hashes
+    /// of the blocks mined.
+    pub async fn generate_to_address(&self, params: &Value) -> RpcResult<Value> {
+        debug!("RPC: generatetoaddress");
+
+        let nblocks = validate_numeric_param::<u64>(params, 0, "nblocks", None, None)?;
+        let address = validate_string_param(params, 1, "address", Some(MAX_ADDRESS_STRING_LENGTH))?;
+        let max_tries = validate_optional_numeric_param(
+            params,
+            2,
+            "maxtries",
+            DEFAULT_MAX_TRIES,
+            Some(1),
+            None,
+        )?;
+
+        let network = self.regtest_network()?;
+        let script_pubkey = address_to_script_pubkey(&address, &network)
+            .map_err(|reason| RpcError::invalid_address_format(&address, Some(&reason), None))?;
+        let hashes = self
+            .generate_blocks(script_pubkey, nblocks, max_tries, None)
+            .await?;
+        Ok(json!(hashes))
+    }
+
+    /// Mine blocks paying the coinbase to an output descriptor (regtest only)
+    ///
+    /// Params: [num_blocks, "descriptor", maxtries (optional, default: 1000000)]
+    pub async fn generate_to_descriptor(&self, params: &Value) -> RpcResult<Value> {
+        debug!("RPC: generatetodescriptor");
+
+        let nblocks = validate_numeric_param::<u64>(params, 0, "num_blocks", None, None)?;
+        let descriptor = validate_string_param(params, 1, "descriptor", None)?;
+        let max_tries = validate_optional_numeric_param(
+            params,
+            2,
+            "maxtries",
+            DEFAULT_MAX_TRIES,
+            Some(1),
+            None,
+        )?;
+
+        let network = self.regtest_network()?;
+        let script_pubkey = descriptor_to_script_pubkey(&descriptor, &network)
+            .map_err(|reason| RpcError::invalid_params(format!("Invalid descriptor: {reason}")))?;
+        let hashes = self
+            .generate_blocks(script_pubkey, nblocks, max_tries, None)
+            .await?;
+        Ok(json!(hashes))
+    }
+
+    /// Mine one block holding exactly the given transactions, in order (regtest only)
+    ///
+    /// Params: ["output", ["rawtx" or "txid", ...]]
+    ///
+    /// `output` is an address or output descriptor for the coinbase; a txid
+    /// names a mempool transaction. Returns the hash of the block in a list.
+    pub async fn generate_block(&self, params: &Value) -> RpcResult<Value> {
+        debug!("RPC: generateblock");
+
+        let output = validate_string_param(params, 0, "output", None)?;
+        let network = self.regtest_network()?;
+        let script_pubkey = if output.contains('(') {
+            descriptor_to_script_pubkey(&output, &network).map_err(|reason| {
+                RpcError::invalid_params(format!("Invalid descriptor: {reason}"))
+            })?
+        } else {
+            address_to_script_pubkey(&output, &network)
+                .map_err(|reason| RpcError::invalid_address_format(&output, Some(&reason), None))?
+        };
+
+        let entries = params
+            .get(1)
+            .and_then(|p| p.as_array())
+            .ok_or_else(|| RpcError::invalid_params("Missing transactions parameter"))?;
+        let mut transactions = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let entry = entry
+                .as_str()
+                .ok_or_else(|| RpcError::invalid_params("Transactions must be hex strings"))?;
+            transactions.push(self.transaction_for_block(entry)?);
+        }
+
+        let hashes = self
+            .generate_blocks(script_pubkey, 1, DEFAULT_MAX_TRIES, Some(transactions))
+            .await?;
+        Ok(json!(hashes))
+    }
+
+    /// A generateblock entry: a mempool txid or a raw transaction
+    fn transaction_for_block(&self, entry: &str) -> RpcResult<Transaction> {
+        let bytes = hex::decode(entry)
+            .map_err(|e| RpcError::invalid_params(format!("Invalid transaction hex: {e}")))?;
+        if bytes.len() == 32 {
+            let mut txid = [0u8; 32];
+            txid.copy_from_slice(&bytes);
+            return self
+                .mempool
+                .as_ref()
+                .and_then(|mempool| mempool.get_transaction(&txid))
+                .ok_or_else(|| {
+                    RpcError::invalid_params(format!("Transaction {entry} not in mempool"))
+                });
+        }
+        deserialize_transaction(&bytes)
+            .map_err(|e| RpcError::invalid_params(format!("Failed to parse transaction: {e}")))
+    }
+
+    /// Network name of the chain, if it is regtest
+    fn regtest_network(&self) -> RpcResult<String> {
+        let storage = self
+            .storage
+            .as_ref()
+            .ok_or_else(|| RpcError::internal_error("Chain not initialized"))?;
+        let network = storage
+            .chain()
+            .get_chain_params()
+            .map_err(|e| RpcError::internal_error(format!("Failed to get chain params: {e}")))?
+            .network;
+        if network != "regtest" {
+            return Err(RpcError::invalid_request(
+                "Block generation is only available on regtest",
+            ));
+        }
+        Ok(network)
+    }
+
+    /// Mine `nblocks` blocks on the active tip and connect each one
+    ///
+    /// Templates come from the mining coordinator, filled from the mempool
+    /// unless `transactions` are given, and are ground on the mining engine's
+    /// threads. Stops early if a block's nonces run out.
+    async fn generate_blocks(
+        &self,
+        script_pubkey: ByteString,
+        nblocks: u64,
+        max_tries: u64,
+        transactions: Option<Vec<Transaction>>,
+    ) -> RpcResult<Vec<String>> {
+        let storage = self
+            .storage
+            .as_ref()
+            .ok_or_else(|| RpcError::internal_error("Chain not initialized"))?;
+        let mempool = self
+            .mempool
+            .clone()
+            .unwrap_or_else(|| Arc::new(MempoolManager::new()));
+        let _guard = self.generate_lock.lock().await;
+
+        let mut coordinator = MiningCoordinator::new(mempool, Some(Arc::clone(storage)));
+        coordinator.set_coinbase_script_pubkey(script_pubkey);
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get() as u32)
+            .unwrap_or(1);
+        coordinator.mining_engine_mut().set_threads(threads);
+        coordinator.mining_engine_mut().set_max_tries(max_tries);
+
+        let mut hashes = Vec::new();
+        for _ in 0..nblocks {
+            let template = match transactions {
+                Some(ref transactions) => {
+                    coordinator
+                        .generate_block_template_with_transactions(transactions.clone())
+                        .await
+                }
+                None => coordinator.generate_block_template().await,
             }
-            Ok((ValidationResult::Invalid(reason), _)) => {
-                Err(RpcError::invalid_params(format!("Invalid block: {reason}")))
+            .map_err(|e| RpcError::internal_error(format!("Failed to build template: {e}")))?;
+
+            let block = match coordinator
+                .mining_engine_mut()
+                .mine_template(template)
+                .await
+            {
+                Ok(block) => block,
+                Err(e) => {
+                    warn!("Stopping generation after {} blocks: {}", hashes.len(), e);
+                    break;
+                }
+            };
+
+            // Generated blocks carry no witness data
+            let witnesses: Vec<Witness> = block.transactions.iter().map(|_| Vec::new()).collect();
+            let hash = storage.blocks().get_block_hash(&block);
+            match self.process_block(block, witnesses).await? {
+                BlockProcessOutcome::Connected { .. } => hashes.push(hex::encode(hash)),
+                _ => {
+                    return Err(RpcError::internal_error(format!(
+                        "Generated block {} was not connected",
+                        hex::encode(hash)
+                    )))
+                }
             }
-            Err(e) => Err(RpcError::internal_error(format!("Validation error: {e}"))),
         }
+        Ok(hashes)
     }
 
     /// Estimate smart fee rate
     ///
     /// Params: [conf_target (optional, default: 6), estimate_mode (optional, default: "conservative")]
+    ///
+    /// Without enough confirmation history the minimum relay fee rate is
+    /// returned, with an `errors` entry saying so.
     pub async fn estimate_smart_fee(&self, params: &Value) -> RpcResult<Value> {
         debug!("RPC: estimatesmartfee");
 
         let conf_target = params.get(0).and_then(|p| p.as_u64()).unwrap_or(6);
+        if conf_target == 0 || conf_target > MAX_CONFIRMATION_TARGET {
+            return Err(RpcError::invalid_params(format!(
+                "Invalid conf_target, must be between 1 and {MAX_CONFIRMATION_TARGET}"
+            )));
+        }
 
         let estimate_mode = params
             .get(1)
             .and_then(|p| p.as_str())
             .unwrap_or("conservative");
+        let mode = EstimateMode::from_rpc(estimate_mode).ok_or_else(|| {
+            RpcError::invalid_params(format!(
+                "Invalid estimate_mode: {estimate_mode}. Must be 'unset', 'economical', or 'conservative'"
+            ))
+        })?;
 
-        // Validate estimate_mode
-        match estimate_mode {
-            "unset" | "economical" | "conservative" => {}
-            _ => {
-                return Err(RpcError::invalid_params(format!(
-                    "Invalid estimate_mode: {estimate_mode}. Must be 'unset', 'economical', or 'conservative'"
-                )))
-            }
-        }
-
-        // Get mempool transactions and UTXO set for fee calculation
-        let mempool_txs = if let Some(ref mempool) = self.mempool {
-            let utxo_set = self.get_utxo_set()?;
-            mempool.get_prioritized_transactions(100, &utxo_set) // Get top 100 by fee rate
-        } else {
-            vec![]
-        };
-
-        // Calculate fee rate based on mempool state
-        // Simple algorithm: use median fee rate of top transactions
-        let fee_rate = if !mempool_txs.is_empty() {
-            let _utxo_set = self.get_utxo_set()?;
-            let mut fee_rates = Vec::new();
-
-            // MempoolManager.get_prioritized_transactions() already calculates fees correctly
-            // We can extract fee rates from the prioritized list, but for now calculate directly
-            for tx in &mempool_txs {
-                let fee = self.calculate_transaction_fee(tx); // Now uses UTXO set
-                let size = self.calculate_weight(tx) as usize;
-
-                if size > 0 {
-                    // Fee rate in BTC per vbyte
-                    let rate = (fee as f64) / (size as f64) / 100_000_000.0; // Convert satoshis to BTC
-                    fee_rates.push(rate);
-                }
-            }
-
-            // Use median fee rate, or minimum if no transactions
-            if !fee_rates.is_empty() {
-                fee_rates.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
-                let median_idx = fee_rates.len() / 2;
-                fee_rates[median_idx]
-            } else {
-                0.00001 // Default: 1 sat/vB
-            }
-        } else {
-            // No mempool transactions - return minimum fee
-            0.00001 // 1 sat/vB
-        };
-
-        // Adjust based on estimate_mode
-        let adjusted_rate = match estimate_mode {
-            "economical" => fee_rate * 0.8,   // 20% lower for economical
-            "conservative" => fee_rate * 1.2, // 20% higher for conservative
-            _ => fee_rate,
-        };
+        let estimate = self.mempool.as_ref().and_then(|mempool| {
+            mempool
+                .fee_estimator()
+                .estimate_smart_fee(conf_target, mode)
+        });
 
-        Ok(json!({
-            "feerate": adjusted_rate,
-            "blocks": conf_target
-        }))
+        // Fee rates are reported in BTC/kvB
+        Ok(match estimate {
+            Some(estimate) => json!({
+                "feerate": sat_per_vb_to_btc_per_kvb(estimate.fee_rate.max(MIN_RELAY_FEE_RATE)),
+                "blocks": estimate.blocks
+            }),
+            None => json!({
+                "feerate": sat_per_vb_to_btc_per_kvb(MIN_RELAY_FEE_RATE),
+                "errors": ["Insufficient data or no feerate found"],
+                "blocks": conf_target
+            }),
+        })
     }
 
     /// Prioritize a transaction in the mempool
@@ -769,20 +1042,13 @@ impl MiningRpc {
         hash.copy_from_slice(&hash_bytes);
 
         if let Some(ref mempool) = self.mempool {
-            // Check if transaction exists in mempool
-            if mempool.get_transaction(&hash).is_some() {
-                // Note: In production, would update transaction priority/fee delta
-                // For now, just return success
-                debug!(
-                    "Transaction {} prioritized with fee delta: {}",
-                    txid, fee_delta
-                );
-                Ok(json!(true))
-            } else {
-                Err(RpcError::invalid_params(format!(
-                    "Transaction {txid} not found in mempool"
-                )))
-            }
+            // The delta is kept for transactions not (yet) in the mempool too
+            mempool.prioritise_transaction(hash, fee_delta);
+            debug!(
+                "Transaction {} prioritized with fee delta: {}",
+                txid, fee_delta
+            );
+            Ok(json!(true))
         } else {
             Err(RpcError::internal_error(
                 "Mempool not initialized".to_string(),
@@ -3,6 +3,7 @@
 //! This module provides JSON-RPC server, blockchain query methods,
 //! network info methods, transaction submission, and mining methods.
 
+pub mod address;
 pub mod auth;
 pub mod blockchain;
 pub mod control;
@@ -28,6 +29,7 @@ use crate::config::RpcAuthConfig;
 use crate::node::mempool::MempoolManager;
 use crate::node::metrics::MetricsCollector;
 use crate::node::performance::PerformanceProfiler;
+use crate::node::BlockSubmission;
 use crate::storage::Storage;
 use anyhow::Result;
 use std::net::SocketAddr;
@@ -55,6 +57,8 @@ pub struct RpcManager {
     storage: Option<Arc<Storage>>,
     mempool: Option<Arc<MempoolManager>>,
     network_manager: Option<Arc<crate::network::NetworkManager>>,
+    /// Where submitted and generated blocks go to be processed by the node
+    block_submitter: Option<mpsc::UnboundedSender<BlockSubmission>>,
     shutdown_tx: Option<mpsc::UnboundedSender<()>>,
     #[cfg(feature = "quinn")]
     quinn_shutdown_tx: Option<mpsc::UnboundedSender<()>>,
@@ -93,6 +97,7 @@ impl RpcManager {
             profiler: None,
             mempool: None,
             network_manager: None,
+            block_submitter: None,
             shutdown_tx: None,
             #[cfg(feature = "quinn")]
             quinn_shutdown_tx: None,
@@ -147,8 +152,7 @@ impl RpcManager {
         mempool: Arc<MempoolManager>,
     ) -> Self {
         // Update all RPC handlers with dependencies
-        self.mining_rpc =
-            mining::MiningRpc::with_dependencies(Arc::clone(&storage), Arc::clone(&mempool));
+        self.mining_rpc = self.mining_rpc(&storage, &mempool);
         self.blockchain_rpc = blockchain::BlockchainRpc::with_dependencies(Arc::clone(&storage));
         // Note: mempool_rpc is created later in with_dependencies_auth_and_metrics if needed
         // This early creation was unused - removed to avoid warning
@@ -166,6 +170,32 @@ impl RpcManager {
         self
     }
 
+    /// Send submitted and generated blocks to the node for processing
+    ///
+    /// Without a submitter the mining RPC connects blocks itself.
+    pub fn with_block_submitter(
+        mut self,
+        block_submitter: mpsc::UnboundedSender<BlockSubmission>,
+    ) -> Self {
+        self.mining_rpc = std::mem::replace(&mut self.mining_rpc, mining::MiningRpc::new())
+            .with_block_submitter(block_submitter.clone());
+        self.block_submitter = Some(block_submitter);
+        self
+    }
+
+    /// Mining RPC handler with the manager's dependencies
+    fn mining_rpc(
+        &self,
+        storage: &Arc<Storage>,
+        mempool: &Arc<MempoolManager>,
+    ) -> mining::MiningRpc {
+        let mining = mining::MiningRpc::with_dependencies(Arc::clone(storage), Arc::clone(mempool));
+        match self.block_submitter {
+            Some(ref block_submitter) => mining.with_block_submitter(block_submitter.clone()),
+            None => mining,
+        }
+    }
+
     /// Set metrics collector
     pub fn with_metrics(mut self, metrics: Arc<MetricsCollector>) -> Self {
         self.metrics = Some(metrics);
@@ -223,6 +253,7 @@ impl RpcManager {
             storage: None,
             mempool: None,
             network_manager: None,
+            block_submitter: None,
             shutdown_tx: None,
             quinn_shutdown_tx: None,
             auth_manager: None,
@@ -278,10 +309,7 @@ impl RpcManager {
                 None,
                 None,
             ));
-            let mining = Arc::new(mining::MiningRpc::with_dependencies(
-                Arc::clone(storage),
-                Arc::clone(mempool),
-            ));
+            let mining = Arc::new(self.mining_rpc(storage, mempool));
             let network = if let Some(ref network_manager) = self.network_manager {
                 Arc::new(network::NetworkRpc::with_dependencies(Arc::clone(
                     network_manager,
@@ -400,10 +428,7 @@ impl RpcManager {
                     None,
                     None,
                 ));
-                let mining = Arc::new(mining::MiningRpc::with_dependencies(
-                    Arc::clone(storage),
-                    Arc::clone(mempool),
-                ));
+                let mining = Arc::new(self.mining_rpc(storage, mempool));
                 let network = if let Some(ref network_manager) = self.network_manager {
                     Arc::new(network::NetworkRpc::with_dependencies(Arc::clone(
                         network_manager,
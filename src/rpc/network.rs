@@ -2,6 +2,7 @@
 //!
 //! Implements network-related JSON-RPC methods for querying and managing network state.
 
+use crate::network::netaddr::Network;
 use crate::network::NetworkManager;
 use crate::rpc::errors::{RpcError, RpcResult};
 use crate::utils::current_timestamp;
@@ -77,9 +78,32 @@ impl NetworkRpc {
                 })
             });
 
-            // Clone and update only the dynamic field
+            // Clone and update only the dynamic fields
             let mut result = base_info.clone();
             result["connections"] = json!(peer_count);
+
+            let reachable = network.reachable_networks();
+            let networks: Vec<Value> = Network::ALL
+                .into_iter()
+                .map(|net| {
+                    let proxy = network.proxies().proxy_for(net);
+                    json!({
+                        "name": net.to_string(),
+                        "limited": !reachable.contains(&net),
+                        "reachable": reachable.contains(&net),
+                        "proxy": proxy.map(|p| p.addr().to_string()).unwrap_or_default(),
+                        "proxy_randomize_credentials": proxy.is_some_and(|p| p.randomizes_credentials()),
+                    })
+                })
+                .collect();
+            result["networks"] = json!(networks);
+            if let Some(onion) = network.onion_service() {
+                result["localaddresses"] = json!([{
+                    "address": onion.addr.to_string(),
+                    "port": onion.port,
+                    "score": 4,
+                }]);
+            }
             Ok(result)
         } else {
             Ok(json!({
@@ -143,6 +167,8 @@ impl NetworkRpc {
                         "bytessent": peer.bytes_sent(),
                         "bytesrecv": peer.bytes_recv(),
                         "conntime": peer.conntime(),
+                        "transport_protocol_type": peer.transport_protocol().as_str(),
+                        "session_id": peer.session_id().map(hex::encode).unwrap_or_default(),
                         "timeoffset": 0,
                         "pingtime": 0.0,
                         "minping": 0.0,
@@ -538,49 +564,86 @@ impl NetworkRpc {
 
     /// Get node addresses
     ///
-    /// Params: ["count"] (optional, default: 1)
+    /// Params: ["count", "network"] (optional, default: 1 address from any
+    /// network; count 0 returns all known addresses)
     pub async fn getnodeaddresses(&self, params: &Value) -> RpcResult<Value> {
         debug!("RPC: getnodeaddresses");
 
-        let count = params.get(0).and_then(|p| p.as_u64()).unwrap_or(1).min(100) as usize; // Limit to 100
+        let count = params.get(0).and_then(|p| p.as_u64()).unwrap_or(1) as usize;
+        let network = match params.get(1).and_then(|p| p.as_str()) {
+            Some(name) => Some(Network::from_name(name).ok_or_else(|| {
+                RpcError::invalid_params(format!("Network not recognized: {}", name))
+            })?),
+            None => None,
+        };
+
+        if let Some(ref network_manager) = self.network_manager {
+            let count = if count == 0 { usize::MAX } else { count };
+            let addresses = network_manager
+                .address_manager()
+                .read()
+                .await
+                .get_addresses(count, 100, network);
+
+            let addresses: Vec<Value> = addresses
+                .into_iter()
+                .map(|addr| {
+                    json!({
+                        "time": addr.time,
+                        "services": format!("{:016x}", addr.services),
+                        "address": addr.addr.to_string(),
+                        "port": addr.port,
+                        "network": addr.addr.network().name(),
+                    })
+                })
+                .collect();
+            Ok(json!(addresses))
+        } else {
+            Ok(json!([]))
+        }
+    }
+
+    /// Get address manager statistics
+    ///
+    /// Params: []
+    pub async fn getaddrmaninfo(&self, _params: &Value) -> RpcResult<Value> {
+        debug!("RPC: getaddrmaninfo");
 
         if let Some(ref network) = self.network_manager {
-            // Get peer addresses
-            let peer_addrs = network.get_peer_addresses().await;
-
-            // Convert to node address format
-            let mut addresses = Vec::new();
-            for addr in peer_addrs.into_iter().take(count) {
-                match addr {
-                    crate::network::transport::TransportAddr::Tcp(sock) => {
-                        addresses.push(json!({
-                            "time": current_timestamp(),
-                            "services": "0000000000000001",
-                            "address": sock.ip().to_string(),
-                            "port": sock.port(),
-                            "network": if sock.is_ipv4() { "ipv4" } else { "ipv6" }
-                        }));
-                    }
-                    #[cfg(feature = "quinn")]
-                    crate::network::transport::TransportAddr::Quinn(sock) => {
-                        addresses.push(json!({
-                            "time": current_timestamp(),
-                            "services": "0000000000000001",
-                            "address": sock.ip().to_string(),
-                            "port": sock.port(),
-                            "network": if sock.is_ipv4() { "ipv4" } else { "ipv6" }
-                        }));
-                    }
-                    #[cfg(feature = "iroh")]
-                    crate::network::transport::TransportAddr::Iroh(_) => {
-                        // Skip Iroh peers for this method (no SocketAddr)
-                    }
-                }
-            }
+            let info = network.address_manager().read().await.info();
+            let counts = |counts: crate::network::addrman::NetworkCounts| {
+                json!({
+                    "new": counts.new,
+                    "tried": counts.tried,
+                    "total": counts.new + counts.tried,
+                })
+            };
 
-            Ok(json!(addresses))
+            let mut result = serde_json::Map::new();
+            for (name, network_counts) in &info.networks {
+                result.insert(name.to_string(), counts(*network_counts));
+            }
+            result.insert("all_networks".to_string(), counts(info.total()));
+            result.insert(
+                "buckets".to_string(),
+                json!({
+                    "new": {
+                        "count": crate::network::addrman::NEW_BUCKET_COUNT,
+                        "used": info.new_buckets_used,
+                    },
+                    "tried": {
+                        "count": crate::network::addrman::TRIED_BUCKET_COUNT,
+                        "used": info.tried_buckets_used,
+                    },
+                    "size": crate::network::addrman::BUCKET_SIZE,
+                }),
+            );
+            result.insert("tried_collisions".to_string(), json!(info.tried_collisions));
+            Ok(Value::Object(result))
         } else {
-            Ok(json!([]))
+            Ok(json!({
+                "error": "Network manager not available"
+            }))
         }
     }
 
@@ -3,23 +3,33 @@
 //! Implements raw transaction-related JSON-RPC methods:
 //! - sendrawtransaction
 //! - testmempoolaccept
+//! - submitpackage
 //! - decoderawtransaction
 //! - getrawtransaction (enhanced)
 //! - gettxout
 //! - gettxoutproof
 //! - verifytxoutproof
 
-use crate::node::mempool::MempoolManager;
+use crate::node::mempool::{MempoolManager, PackageAcceptance};
 use crate::node::metrics::MetricsCollector;
 use crate::node::performance::{OperationType, PerformanceProfiler, PerformanceTimer};
 use crate::rpc::errors::{RpcError, RpcErrorCode, RpcResult};
 use crate::storage::Storage;
+use bllvm_protocol::{Transaction, UtxoSet};
 use hex;
 use serde_json::{json, Value};
 use std::sync::Arc;
 use std::time::Instant;
 use tracing::{debug, warn};
 
+/// Most raw transactions testmempoolaccept and submitpackage take
+const MAX_PACKAGE_TXS: usize = 25;
+
+/// Satoshis to BTC, as reported in fee fields
+fn sats_to_btc(sats: u64) -> f64 {
+    sats as f64 / 100_000_000.0
+}
+
 /// Raw Transaction RPC methods
 pub struct RawTxRpc {
     storage: Option<Arc<Storage>>,
@@ -153,9 +163,12 @@ impl RawTxRpc {
                     }
 
                     // Transaction structure is valid, now check inputs against UTXO set
-                    let utxo_set = storage.utxos().get_all_utxos().map_err(|e| {
-                        RpcError::internal_error(format!("Failed to get UTXO set: {e}"))
-                    })?;
+                    let utxo_set = storage
+                        .utxo_cache()
+                        .fetch_coins(tx.inputs.iter().map(|input| &input.prevout))
+                        .map_err(|e| {
+                            RpcError::internal_error(format!("Failed to get UTXO set: {e}"))
+                        })?;
 
                     // Check if all inputs exist in UTXO set
                     for input in &tx.inputs {
@@ -182,6 +195,11 @@ impl RawTxRpc {
                         }
                     }
 
+                    // TRUC and ephemeral anchor policy
+                    if let Err(violation) = mempool.check_truc_policy(&tx) {
+                        return Err(RpcError::tx_policy_rejected(violation, Some(&txid_hex)));
+                    }
+
                     // Add to mempool
                     // Note: add_transaction requires &mut self, but we have Arc<MempoolManager>
                     // In production, this would need to use interior mutability (Mutex/RwLock)
@@ -222,10 +240,41 @@ impl RawTxRpc {
 
     /// Test if a raw transaction would be accepted to the mempool
     ///
-    /// Params: ["hexstring", maxfeerate (optional)]
+    /// Params: ["hexstring", maxfeerate (optional)], or [["hexstring", ...]]
+    /// to test several transactions as a package (a child and its parents,
+    /// parents first)
     pub async fn testmempoolaccept(&self, params: &Value) -> RpcResult<Value> {
         debug!("RPC: testmempoolaccept");
 
+        if params.get(0).is_some_and(|p| p.is_array()) {
+            let acceptance = self.evaluate_package(params)?;
+            let package_error = acceptance.reject_reason.clone();
+            let replaced: Vec<String> = acceptance.replaced.iter().map(hex::encode).collect();
+            let results: Vec<Value> = acceptance
+                .tx_results
+                .iter()
+                .map(|result| {
+                    let mut entry = json!({
+                        "txid": hex::encode(result.txid),
+                        "allowed": result.allowed,
+                        "vsize": result.vsize,
+                        "reject-reason": result.reject_reason,
+                    });
+                    if let Some(fee) = result.fee {
+                        entry["fees"] = json!({ "base": sats_to_btc(fee) });
+                    }
+                    if let Some(ref error) = package_error {
+                        entry["package-error"] = json!(error);
+                    }
+                    if result.allowed && !replaced.is_empty() {
+                        entry["replaced-transactions"] = json!(replaced);
+                    }
+                    entry
+                })
+                .collect();
+            return Ok(json!(results));
+        }
+
         let hex_string = params
             .get(0)
             .and_then(|p| p.as_str())
@@ -267,19 +316,45 @@ impl RawTxRpc {
         let consensus = ConsensusProof::new();
         let validation_result = consensus.validate_transaction(&tx);
 
-        let allowed = matches!(
+        let valid = matches!(
             validation_result,
             Ok(bllvm_protocol::ValidationResult::Valid)
         );
-        let reject_reason = if !allowed {
+        let utxo_set = self.storage.as_ref().map(|storage| {
+            storage
+                .utxo_cache()
+                .fetch_coins(tx.inputs.iter().map(|input| &input.prevout))
+                .unwrap_or_default()
+        });
+        let mut replaced = Vec::new();
+        let reject_reason = if !valid {
             match validation_result {
                 Ok(bllvm_protocol::ValidationResult::Invalid(reason)) => Some(reason),
                 Err(e) => Some(format!("Validation error: {e}")),
                 _ => None,
             }
+        } else if let Some(ref mempool) = self.mempool {
+            // TRUC and ephemeral anchor policy, then the replacement rules for
+            // any mempool transactions it conflicts with
+            let empty = bllvm_protocol::UtxoSet::new();
+            match mempool.check_truc_policy(&tx) {
+                Err(violation) => Some(violation.reject_reason().to_string()),
+                Ok(_) => match mempool.check_replacement(
+                    &tx,
+                    utxo_set.as_ref().unwrap_or(&empty),
+                    self.storage.as_deref(),
+                ) {
+                    Ok(replacement) => {
+                        replaced = replacement.replaced;
+                        None
+                    }
+                    Err(reason) => Some(reason.to_string()),
+                },
+            }
         } else {
             None
         };
+        let allowed = reject_reason.is_none();
 
         // Calculate transaction size
         use bllvm_protocol::serialization::transaction::serialize_transaction;
@@ -287,19 +362,15 @@ impl RawTxRpc {
         let vsize = size; // Simplified - in real implementation would use weight/4
 
         // Calculate fee using mempool manager if available
-        let fee = if let Some(ref mempool) = self.mempool {
-            if let Some(ref storage) = self.storage {
-                let utxo_set = storage.utxos().get_all_utxos().unwrap_or_default();
-                let fee_satoshis = mempool.calculate_transaction_fee(&tx, &utxo_set);
+        let fee = match (&self.mempool, &utxo_set) {
+            (Some(mempool), Some(utxo_set)) => {
+                let fee_satoshis = mempool.calculate_transaction_fee(&tx, utxo_set);
                 fee_satoshis as f64 / 100_000_000.0 // Convert to BTC
-            } else {
-                0.00001000 // Default if no storage
             }
-        } else {
-            0.00001000 // Default if no mempool
+            _ => 0.00001000, // Default if no mempool or storage
         };
 
-        Ok(json!([{
+        let mut result = json!({
             "txid": txid_hex,
             "allowed": allowed,
             "vsize": vsize,
@@ -307,7 +378,130 @@ impl RawTxRpc {
                 "base": fee
             },
             "reject-reason": reject_reason
-        }]))
+        });
+        if !replaced.is_empty() {
+            let replaced: Vec<String> = replaced.iter().map(hex::encode).collect();
+            result["replaced-transactions"] = json!(replaced);
+        }
+        Ok(json!([result]))
+    }
+
+    /// Submit a package of raw transactions to the mempool
+    ///
+    /// Params: [["hexstring", ...]] - a child and its unconfirmed parents,
+    /// parents first
+    pub async fn submitpackage(&self, params: &Value) -> RpcResult<Value> {
+        debug!("RPC: submitpackage");
+
+        let (mempool, txs, utxo_set) = self.decode_package(params)?;
+        let acceptance = mempool
+            .add_package(txs, &utxo_set)
+            .await
+            .map_err(|e| RpcError::internal_error(format!("Failed to add package: {e}")))?;
+
+        let mut tx_results = serde_json::Map::new();
+        for result in &acceptance.tx_results {
+            let txid = hex::encode(result.txid);
+            let mut entry = json!({ "txid": txid, "vsize": result.vsize });
+            if let Some(fee) = result.fee {
+                entry["fees"] = json!({ "base": sats_to_btc(fee) });
+            }
+            if !result.allowed {
+                let error = result
+                    .reject_reason
+                    .as_deref()
+                    .or(acceptance.reject_reason.as_deref())
+                    .unwrap_or("package-not-validated");
+                entry["error"] = json!(error);
+            }
+            tx_results.insert(txid, entry);
+        }
+
+        let package_msg = match acceptance.reject_reason {
+            None if acceptance.all_allowed() => "success".to_string(),
+            None => "transaction failed".to_string(),
+            Some(ref reason) => reason.clone(),
+        };
+        let replaced: Vec<String> = acceptance.replaced.iter().map(hex::encode).collect();
+        Ok(json!({
+            "package_msg": package_msg,
+            "tx-results": tx_results,
+            "replaced-transactions": replaced,
+        }))
+    }
+
+    /// Decode the package in `params[0]`, check each transaction against
+    /// the consensus rules, and evaluate it against the mempool
+    fn evaluate_package(&self, params: &Value) -> RpcResult<PackageAcceptance> {
+        let (mempool, txs, utxo_set) = self.decode_package(params)?;
+        Ok(mempool.check_package(&txs, &utxo_set))
+    }
+
+    /// Decode the package in `params[0]` and check each transaction against
+    /// the consensus rules
+    ///
+    /// Returns the mempool, the package and the UTXO set to evaluate it against.
+    fn decode_package(
+        &self,
+        params: &Value,
+    ) -> RpcResult<(&Arc<MempoolManager>, Vec<Transaction>, UtxoSet)> {
+        use bllvm_protocol::block::calculate_tx_id;
+        use bllvm_protocol::serialization::transaction::deserialize_transaction;
+        use bllvm_protocol::ConsensusProof;
+
+        let rawtxs = params
+            .get(0)
+            .and_then(|p| p.as_array())
+            .ok_or_else(|| RpcError::missing_parameter("rawtxs", Some("array")))?;
+        if rawtxs.is_empty() || rawtxs.len() > MAX_PACKAGE_TXS {
+            return Err(RpcError::invalid_params(format!(
+                "Array must contain between 1 and {MAX_PACKAGE_TXS} transactions"
+            )));
+        }
+
+        let (Some(storage), Some(mempool)) = (self.storage.as_ref(), self.mempool.as_ref()) else {
+            return Err(RpcError::invalid_params(
+                "RPC not initialized with dependencies",
+            ));
+        };
+
+        let consensus = ConsensusProof::new();
+        let mut txs = Vec::with_capacity(rawtxs.len());
+        for (i, raw) in rawtxs.iter().enumerate() {
+            let tx = raw
+                .as_str()
+                .and_then(|hex_string| hex::decode(hex_string).ok())
+                .and_then(|bytes| deserialize_transaction(&bytes).ok())
+                .ok_or_else(|| {
+                    RpcError::invalid_params(format!(
+                        "Transaction {i} is not a valid hex transaction"
+                    ))
+                })?;
+            match consensus.validate_transaction(&tx) {
+                Ok(bllvm_protocol::ValidationResult::Valid) => {}
+                Ok(bllvm_protocol::ValidationResult::Invalid(reason)) => {
+                    let txid_hex = hex::encode(calculate_tx_id(&tx));
+                    return Err(RpcError::tx_rejected_with_context(
+                        format!("Transaction validation failed: {reason}"),
+                        Some(&txid_hex),
+                        Some("validation_failed"),
+                        None,
+                    ));
+                }
+                Err(e) => {
+                    return Err(RpcError::internal_error(format!(
+                        "Transaction validation error: {e}"
+                    )));
+                }
+            }
+            txs.push(tx);
+        }
+
+        let utxo_set = storage
+            .utxo_cache()
+            .get_all_utxos()
+            .map_err(|e| RpcError::internal_error(format!("Failed to get UTXO set: {e}")))?;
+        Ok((mempool, txs, utxo_set))
     }
 
     /// Decode a raw transaction
@@ -528,7 +722,7 @@ impl RawTxRpc {
                 tokio::task::spawn_blocking({
                     let storage = storage.clone();
                     let outpoint = outpoint.clone();
-                    move || storage.utxos().get_utxo(&outpoint)
+                    move || storage.utxo_cache().get_utxo(&outpoint)
                 })
                 .await
             })
@@ -934,7 +1128,7 @@ impl RawTxRpc {
                 use bllvm_protocol::serialization::transaction::serialize_transaction;
                 let size = serialize_transaction(&tx).len();
                 let fee = if let Some(ref storage) = self.storage {
-                    let utxo_set = storage.utxos().get_all_utxos().unwrap_or_default();
+                    let utxo_set = storage.utxo_cache().get_all_utxos().unwrap_or_default();
                     mempool.calculate_transaction_fee(&tx, &utxo_set) as f64 / 100_000_000.0
                 } else {
                     0.0
@@ -1,17 +1,23 @@
 //! Fee estimation endpoints
 //!
-//! GET /api/v1/fees/estimate
+//! GET /api/v1/fees/estimate?blocks=N&mode=economical|conservative
+//!
+//! Backed by the node's fee estimator, as estimatesmartfee is.
 
 use crate::rpc::mining::MiningRpc;
 use anyhow::Result;
 use serde_json::{json, Value};
 
 /// Get fee estimate
-pub async fn get_fee_estimate(mining: &MiningRpc, blocks: Option<u64>) -> Result<Value> {
-    let params = if let Some(blocks) = blocks {
-        json!([blocks])
-    } else {
-        json!([6]) // Default to 6 blocks
+pub async fn get_fee_estimate(
+    mining: &MiningRpc,
+    blocks: Option<u64>,
+    mode: Option<&str>,
+) -> Result<Value> {
+    let blocks = blocks.unwrap_or(6); // Default to 6 blocks
+    let params = match mode {
+        Some(mode) => json!([blocks, mode]),
+        None => json!([blocks]),
     };
     let estimate = mining.estimate_smart_fee(&params).await?;
     Ok(estimate)
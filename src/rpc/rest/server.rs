@@ -163,7 +163,7 @@ impl RestApiServer {
         } else if path.starts_with("/api/v1/network") {
             Self::handle_network_request(server, method, path, request_id).await
         } else if path.starts_with("/api/v1/fees") {
-            Self::handle_fee_request(server, method, path, request_id).await
+            Self::handle_fee_request(server, method, path, uri.query(), request_id).await
         } else if path.starts_with("/api/v1/payments") {
             // CTV payment endpoints (requires bip70-http feature)
             #[cfg(feature = "bip70-http")]
@@ -640,6 +640,44 @@ impl RestApiServer {
                             request_id,
                         ),
                     }
+                } else if path_parts.len() == 4 && path_parts[3] == "package" {
+                    // POST /api/v1/transactions/package (submit package)
+                    let body = match req.collect().await {
+                        Ok(b) => b.to_bytes(),
+                        Err(e) => {
+                            return Self::error_response(
+                                StatusCode::BAD_REQUEST,
+                                "BAD_REQUEST",
+                                &format!("Failed to read request body: {}", e),
+                                None,
+                                request_id,
+                            );
+                        }
+                    };
+
+                    let rawtxs = match serde_json::from_slice::<Value>(&body) {
+                        Ok(rawtxs) if rawtxs.is_array() => rawtxs,
+                        _ => {
+                            return Self::error_response(
+                                StatusCode::BAD_REQUEST,
+                                "BAD_REQUEST",
+                                "Package must be a JSON array of transaction hex strings",
+                                None,
+                                request_id,
+                            );
+                        }
+                    };
+
+                    match transactions::submit_package(&server.rawtx, rawtxs).await {
+                        Ok(data) => Self::success_response(data, request_id),
+                        Err(e) => Self::error_response(
+                            StatusCode::BAD_REQUEST,
+                            "PACKAGE_REJECTED",
+                            &format!("Package rejected: {}", e),
+                            None,
+                            request_id,
+                        ),
+                    }
                 } else {
                     Self::error_response(
                         StatusCode::BAD_REQUEST,
@@ -911,6 +949,7 @@ impl RestApiServer {
         server: Arc<Self>,
         method: Method,
         path: &str,
+        query: Option<&str>,
         request_id: String,
     ) -> Response<Full<Bytes>> {
         if method != Method::GET {
@@ -923,7 +962,7 @@ impl RestApiServer {
             );
         }
 
-        // Parse path: /api/v1/fees/estimate?blocks=N (optional query param)
+        // Parse path: /api/v1/fees/estimate?blocks=N&mode=M (optional query params)
         let path_parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
 
         if path_parts.len() < 4
@@ -942,8 +981,27 @@ impl RestApiServer {
 
         match path_parts.get(3) {
             Some(&"estimate") => {
-                // TODO: Parse query parameters for blocks (for now use default)
-                match fees::get_fee_estimate(&server.mining, None).await {
+                let param = |name: &str| {
+                    query?
+                        .split('&')
+                        .filter_map(|pair| pair.split_once('='))
+                        .find(|(key, _)| *key == name)
+                        .map(|(_, value)| value)
+                };
+                let blocks = match param("blocks").map(str::parse::<u64>) {
+                    Some(Ok(blocks)) => Some(blocks),
+                    Some(Err(_)) => {
+                        return Self::error_response(
+                            StatusCode::BAD_REQUEST,
+                            "BAD_REQUEST",
+                            "Invalid blocks parameter",
+                            None,
+                            request_id,
+                        )
+                    }
+                    None => None,
+                };
+                match fees::get_fee_estimate(&server.mining, blocks, param("mode")).await {
                     Ok(data) => Self::success_response(data, request_id),
                     Err(e) => Self::error_response(
                         StatusCode::INTERNAL_SERVER_ERROR,
@@ -3,6 +3,7 @@
 //! GET  /api/v1/transactions/{txid}
 //! GET  /api/v1/transactions/{txid}/confirmations
 //! POST /api/v1/transactions (submit transaction)
+//! POST /api/v1/transactions/package (submit package)
 
 use crate::rpc::rawtx::RawTxRpc;
 use anyhow::Result;
@@ -40,3 +41,10 @@ pub async fn submit_transaction(rawtx: &RawTxRpc, hex: &str) -> Result<Value> {
     let result = rawtx.sendrawtransaction(&params).await?;
     Ok(result)
 }
+
+/// Submit a package of raw transactions (a child and its parents, parents first)
+pub async fn submit_package(rawtx: &RawTxRpc, rawtxs: Value) -> Result<Value> {
+    let params = json!([rawtxs]);
+    let result = rawtx.submitpackage(&params).await?;
+    Ok(result)
+}
@@ -568,6 +568,28 @@ impl RpcServer {
             metrics.rpc.avg_response_time_ms
         ));
 
+        // Mempool metrics
+        output.push_str("# HELP bllvm_mempool_transactions Transactions in the mempool\n");
+        output.push_str("# TYPE bllvm_mempool_transactions gauge\n");
+        output.push_str(&format!(
+            "bllvm_mempool_transactions {}\n",
+            metrics.mempool.transaction_count
+        ));
+
+        output.push_str("# HELP bllvm_mempool_orphans Orphan transactions waiting for parents\n");
+        output.push_str("# TYPE bllvm_mempool_orphans gauge\n");
+        output.push_str(&format!(
+            "bllvm_mempool_orphans {}\n",
+            metrics.mempool.orphan_count
+        ));
+
+        output.push_str("# HELP bllvm_mempool_orphan_bytes Total size of orphan transactions\n");
+        output.push_str("# TYPE bllvm_mempool_orphan_bytes gauge\n");
+        output.push_str(&format!(
+            "bllvm_mempool_orphan_bytes {}\n",
+            metrics.mempool.orphan_bytes
+        ));
+
         // Performance metrics
         output.push_str("# HELP bllvm_performance_avg_block_processing_time_ms Average block processing time in milliseconds\n");
         output.push_str("# TYPE bllvm_performance_avg_block_processing_time_ms gauge\n");
@@ -949,7 +971,17 @@ impl RpcServer {
                 .map_err(|e| errors::RpcError::internal_error(e.to_string())),
             "gettxoutsetinfo" => self
                 .blockchain
-                .get_txoutset_info()
+                .get_txoutset_info(&params)
+                .await
+                .map_err(|e| errors::RpcError::internal_error(e.to_string())),
+            "dumptxoutset" => self
+                .blockchain
+                .dump_txoutset(&params)
+                .await
+                .map_err(|e| errors::RpcError::internal_error(e.to_string())),
+            "loadtxoutset" => self
+                .blockchain
+                .load_txoutset(&params)
                 .await
                 .map_err(|e| errors::RpcError::internal_error(e.to_string())),
             "verifychain" => {
@@ -1015,6 +1047,7 @@ impl RpcServer {
             "getrawtransaction" => self.rawtx.getrawtransaction(&params).await,
             "sendrawtransaction" => self.rawtx.sendrawtransaction(&params).await,
             "testmempoolaccept" => self.rawtx.testmempoolaccept(&params).await,
+            "submitpackage" => self.rawtx.submitpackage(&params).await,
             "decoderawtransaction" => self.rawtx.decoderawtransaction(&params).await,
             "gettxout" => self.rawtx.gettxout(&params).await,
             "gettxoutproof" => self.rawtx.gettxoutproof(&params).await,
@@ -1041,12 +1074,16 @@ impl RpcServer {
             "listbanned" => self.network.list_banned(&params).await,
             "getaddednodeinfo" => self.network.getaddednodeinfo(&params).await,
             "getnodeaddresses" => self.network.getnodeaddresses(&params).await,
+            "getaddrmaninfo" => self.network.getaddrmaninfo(&params).await,
             "setnetworkactive" => self.network.setnetworkactive(&params).await,
 
             // Mining methods
             "getmininginfo" => self.mining.get_mining_info().await,
             "getblocktemplate" => self.mining.get_block_template(&params).await,
             "submitblock" => self.mining.submit_block(&params).await,
+            "generatetoaddress" => self.mining.generate_to_address(&params).await,
+            "generatetodescriptor" => self.mining.generate_to_descriptor(&params).await,
+            "generateblock" => self.mining.generate_block(&params).await,
             "estimatesmartfee" => self.mining.estimate_smart_fee(&params).await,
             "prioritisetransaction" => self.mining.prioritise_transaction(&params).await,
             "getblockfilter" => self
@@ -0,0 +1,543 @@
+//! Flat-file block storage
+//!
+//! Block bodies and undo data are appended to numbered files in a `blocks`
+//! directory, in the style of Bitcoin Core's `blkNNNNN.dat` and
+//! `revNNNNN.dat`. The database only keeps each record's position, so it
+//! stays small, and pruning deletes whole files instead of removing millions
+//! of database entries.
+//!
+//! Every record is [`RECORD_MAGIC`], the payload length as a little-endian
+//! `u32`, then the bincode-encoded payload. Appends are not synced one by one:
+//! a file is synced when the next block file is started, and
+//! [`BlockFileStore::sync`] must be called before a batch holding staged
+//! positions commits, so a committed position always points at durable data.
+//! A crash before the batch commits only leaves unreferenced bytes.
+//!
+//! A block file is filled until it reaches the configured maximum size, then
+//! the next one is started. Undo data goes to the `rev` file with the same
+//! number as the block's `blk` file, so both can be deleted together. The
+//! records in each file are listed in the database in append order, so
+//! deleting a file does not mean scanning every stored position.
+
+use crate::storage::database::{Database, Tree, WriteBatch};
+use anyhow::{anyhow, Context, Result};
+use bllvm_protocol::Hash;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const BLOCK_POSITIONS_TREE: &str = "block_positions";
+const UNDO_POSITIONS_TREE: &str = "undo_positions";
+const BLOCK_FILES_TREE: &str = "block_files";
+const BLOCK_FILE_RECORDS_TREE: &str = "block_file_records";
+const LAST_FILE_KEY: &[u8] = b"last_file";
+
+/// Magic bytes at the start of every record
+pub const RECORD_MAGIC: [u8; 4] = *b"blvm";
+
+/// Default maximum size of a block file (128 MiB, as in Bitcoin Core)
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Bytes before each record's payload: magic and length
+const RECORD_HEADER_SIZE: u64 = 8;
+
+/// Location of a record in a block or undo file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilePosition {
+    /// File number (`blk{file:05}.dat` / `rev{file:05}.dat`)
+    pub file: u32,
+    /// Offset of the record header
+    pub offset: u64,
+    /// Payload length
+    pub len: u32,
+}
+
+/// Summary of one block file and its undo file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockFileInfo {
+    /// Blocks appended to the file
+    pub blocks: u32,
+    /// Size of the `blk` file in bytes
+    pub size: u64,
+    /// Undo records appended to the `rev` file
+    pub undos: u32,
+    /// Size of the `rev` file in bytes
+    pub undo_size: u64,
+}
+
+/// Files removed by [`BlockFileStore::remove_files`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrunedFiles {
+    /// File numbers that were deleted
+    pub files: Vec<u32>,
+    /// Block bodies whose positions were dropped
+    pub blocks: u64,
+    /// Bytes freed on disk (block and undo files)
+    pub bytes: u64,
+}
+
+#[derive(Clone, Copy)]
+enum FileKind {
+    Blocks,
+    Undo,
+}
+
+impl FileKind {
+    fn prefix(self) -> &'static str {
+        match self {
+            FileKind::Blocks => "blk",
+            FileKind::Undo => "rev",
+        }
+    }
+}
+
+/// Key of the `index`th record appended to a file in the per-file record list
+fn record_key(kind: FileKind, file: u32, index: u32) -> [u8; 9] {
+    let mut key = [0u8; 9];
+    key[0] = match kind {
+        FileKind::Blocks => b'b',
+        FileKind::Undo => b'u',
+    };
+    key[1..5].copy_from_slice(&file.to_be_bytes());
+    key[5..].copy_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// Write state, kept in memory so several appends staged into one batch see
+/// each other's updates
+struct FileState {
+    /// Number of the block file being appended to
+    last_file: u32,
+    infos: BTreeMap<u32, BlockFileInfo>,
+    /// Open files with appends not yet synced to disk
+    unsynced: BTreeMap<PathBuf, File>,
+}
+
+impl FileState {
+    /// Sync every file appended to since the last sync
+    fn sync(&mut self) -> Result<()> {
+        for (path, handle) in &self.unsynced {
+            handle
+                .sync_data()
+                .with_context(|| format!("Failed to sync {}", path.display()))?;
+        }
+        self.unsynced.clear();
+        Ok(())
+    }
+}
+
+/// Append-only block and undo files with positions indexed in the database
+pub struct BlockFileStore {
+    db: Arc<dyn Database>,
+    dir: PathBuf,
+    max_file_size: u64,
+    block_positions: Arc<dyn Tree>,
+    undo_positions: Arc<dyn Tree>,
+    /// Hash of each record by file and append order
+    records: Arc<dyn Tree>,
+    /// Serializes all appends
+    state: Mutex<FileState>,
+}
+
+impl BlockFileStore {
+    /// Open (or create) the block files in `dir`
+    pub fn new<P: AsRef<Path>>(db: Arc<dyn Database>, dir: P, max_file_size: u64) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create block directory {}", dir.display()))?;
+        let block_positions = Arc::from(db.open_tree(BLOCK_POSITIONS_TREE)?);
+        let undo_positions = Arc::from(db.open_tree(UNDO_POSITIONS_TREE)?);
+        let records = Arc::from(db.open_tree(BLOCK_FILE_RECORDS_TREE)?);
+        let files = db.open_tree(BLOCK_FILES_TREE)?;
+        let mut last_file = 0;
+        let mut infos = BTreeMap::new();
+        for result in files.iter() {
+            let (key, data) = result?;
+            if key == LAST_FILE_KEY {
+                last_file = u32::from_be_bytes(
+                    data.as_slice()
+                        .try_into()
+                        .map_err(|_| anyhow!("Invalid last block file record"))?,
+                );
+            } else if let Ok(file) = <[u8; 4]>::try_from(key.as_slice()) {
+                infos.insert(u32::from_be_bytes(file), bincode::deserialize(&data)?);
+            }
+        }
+
+        Ok(Self {
+            db,
+            dir,
+            max_file_size: max_file_size.max(1),
+            block_positions,
+            undo_positions,
+            records,
+            state: Mutex::new(FileState {
+                last_file,
+                infos,
+                unsynced: BTreeMap::new(),
+            }),
+        })
+    }
+
+    /// Directory holding the block files
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of block file `file`
+    pub fn block_file_path(&self, file: u32) -> PathBuf {
+        self.path(FileKind::Blocks, file)
+    }
+
+    /// Path of undo file `file`
+    pub fn undo_file_path(&self, file: u32) -> PathBuf {
+        self.path(FileKind::Undo, file)
+    }
+
+    fn path(&self, kind: FileKind, file: u32) -> PathBuf {
+        self.dir.join(format!("{}{:05}.dat", kind.prefix(), file))
+    }
+
+    /// Number of the block file currently being appended to
+    pub fn last_file(&self) -> u32 {
+        self.state.lock().unwrap().last_file
+    }
+
+    /// Append a block body and stage its position
+    ///
+    /// Starts a new file first if the record would push the current one past
+    /// the maximum size (an empty file always takes the record); the files
+    /// written so far are synced before that. Call [`Self::sync`] before
+    /// committing the batch.
+    pub fn stage_block<T: Serialize>(
+        &self,
+        batch: &mut WriteBatch,
+        hash: &Hash,
+        block: &T,
+    ) -> Result<FilePosition> {
+        let payload = bincode::serialize(block)?;
+        let mut state = self.state.lock().unwrap();
+
+        let current_size = file_len(&self.path(FileKind::Blocks, state.last_file))?;
+        if current_size > 0
+            && current_size + RECORD_HEADER_SIZE + payload.len() as u64 > self.max_file_size
+        {
+            state.sync()?;
+            state.last_file += 1;
+            batch.insert(
+                BLOCK_FILES_TREE,
+                LAST_FILE_KEY,
+                &state.last_file.to_be_bytes(),
+            );
+        }
+
+        let file = state.last_file;
+        let position = self.append(&mut state, FileKind::Blocks, file, &payload)?;
+        let info = state.infos.entry(file).or_default();
+        batch.insert(
+            BLOCK_FILE_RECORDS_TREE,
+            &record_key(FileKind::Blocks, file, info.blocks),
+            hash,
+        );
+        info.blocks += 1;
+        info.size = position.offset + RECORD_HEADER_SIZE + position.len as u64;
+        batch.insert(
+            BLOCK_FILES_TREE,
+            &position.file.to_be_bytes(),
+            &bincode::serialize(info)?,
+        );
+        batch.insert(BLOCK_POSITIONS_TREE, hash, &bincode::serialize(&position)?);
+        Ok(position)
+    }
+
+    /// Append undo data for a block and stage its position
+    ///
+    /// Goes to the undo file matching the block's body, or to the current file
+    /// if the body is not in a block file. Call [`Self::sync`] before
+    /// committing the batch.
+    pub fn stage_undo<T: Serialize>(
+        &self,
+        batch: &mut WriteBatch,
+        hash: &Hash,
+        undo: &T,
+    ) -> Result<FilePosition> {
+        let payload = bincode::serialize(undo)?;
+        let mut state = self.state.lock().unwrap();
+        let file = match self.block_position(hash)? {
+            Some(position) => position.file,
+            None => state.last_file,
+        };
+
+        let position = self.append(&mut state, FileKind::Undo, file, &payload)?;
+        let info = state.infos.entry(file).or_default();
+        batch.insert(
+            BLOCK_FILE_RECORDS_TREE,
+            &record_key(FileKind::Undo, file, info.undos),
+            hash,
+        );
+        info.undos += 1;
+        info.undo_size = position.offset + RECORD_HEADER_SIZE + position.len as u64;
+        batch.insert(
+            BLOCK_FILES_TREE,
+            &file.to_be_bytes(),
+            &bincode::serialize(info)?,
+        );
+        batch.insert(UNDO_POSITIONS_TREE, hash, &bincode::serialize(&position)?);
+        Ok(position)
+    }
+
+    /// Sync all appended records to disk
+    ///
+    /// Must be called before committing a batch with positions staged by
+    /// [`Self::stage_block`] or [`Self::stage_undo`].
+    pub fn sync(&self) -> Result<()> {
+        self.state.lock().unwrap().sync()
+    }
+
+    /// Write one record at the end of a file, leaving it unsynced
+    fn append(
+        &self,
+        state: &mut FileState,
+        kind: FileKind,
+        file: u32,
+        payload: &[u8],
+    ) -> Result<FilePosition> {
+        let len = u32::try_from(payload.len())
+            .map_err(|_| anyhow!("Record of {} bytes is too large", payload.len()))?;
+        let handle = match state.unsynced.entry(self.path(kind, file)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let handle = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(entry.key())
+                    .with_context(|| format!("Failed to open {}", entry.key().display()))?;
+                entry.insert(handle)
+            }
+        };
+        // Appends start at the real end of the file, even if earlier records
+        // were written but never committed
+        let offset = handle.metadata()?.len();
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE as usize + payload.len());
+        record.extend_from_slice(&RECORD_MAGIC);
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(payload);
+        handle.write_all(&record)?;
+
+        Ok(FilePosition { file, offset, len })
+    }
+
+    /// Read a block body
+    pub fn read_block<T: DeserializeOwned>(&self, hash: &Hash) -> Result<Option<T>> {
+        match self.block_position(hash)? {
+            Some(position) => Ok(Some(self.read(FileKind::Blocks, &position)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Read undo data for a block
+    pub fn read_undo<T: DeserializeOwned>(&self, hash: &Hash) -> Result<Option<T>> {
+        match self.undo_position(hash)? {
+            Some(position) => Ok(Some(self.read(FileKind::Undo, &position)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn read<T: DeserializeOwned>(&self, kind: FileKind, position: &FilePosition) -> Result<T> {
+        let path = self.path(kind, position.file);
+        let mut handle =
+            File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        handle.seek(SeekFrom::Start(position.offset))?;
+
+        let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+        handle.read_exact(&mut header)?;
+        if header[..4] != RECORD_MAGIC {
+            return Err(anyhow!(
+                "Bad record magic at {}:{}",
+                path.display(),
+                position.offset
+            ));
+        }
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if len != position.len {
+            return Err(anyhow!(
+                "Record at {}:{} has length {}, index says {}",
+                path.display(),
+                position.offset,
+                len,
+                position.len
+            ));
+        }
+
+        let mut payload = vec![0u8; len as usize];
+        handle.read_exact(&mut payload)?;
+        Ok(bincode::deserialize(&payload)?)
+    }
+
+    /// Position of a block body
+    pub fn block_position(&self, hash: &Hash) -> Result<Option<FilePosition>> {
+        match self.block_positions.get(hash.as_slice())? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Position of a block's undo data
+    pub fn undo_position(&self, hash: &Hash) -> Result<Option<FilePosition>> {
+        match self.undo_positions.get(hash.as_slice())? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Check whether a block body is stored in a block file
+    pub fn has_block(&self, hash: &Hash) -> Result<bool> {
+        self.block_positions.contains_key(hash.as_slice())
+    }
+
+    /// Number of block bodies stored in block files
+    pub fn block_count(&self) -> Result<usize> {
+        self.block_positions.len()
+    }
+
+    /// Every stored block body position, in no particular order
+    pub fn block_positions(&self) -> Result<Vec<(Hash, FilePosition)>> {
+        let mut positions = Vec::new();
+        for result in self.block_positions.iter() {
+            let (key, data) = result?;
+            if let Ok(hash) = Hash::try_from(key.as_slice()) {
+                positions.push((hash, bincode::deserialize(&data)?));
+            }
+        }
+        Ok(positions)
+    }
+
+    /// Block bodies stored in a block file, in append order
+    ///
+    /// Reads only the file's own records, not every stored position.
+    pub fn file_blocks(&self, file: u32) -> Result<Vec<Hash>> {
+        let count = self.file_info(file).map_or(0, |info| info.blocks);
+        let mut hashes = Vec::new();
+        for (_, hash) in self.file_records(FileKind::Blocks, file, count)? {
+            if self.block_position(&hash)?.map(|position| position.file) == Some(file) {
+                hashes.push(hash);
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// Stage removal of a block body's position
+    ///
+    /// The bytes stay in the file until the whole file is removed.
+    pub fn stage_remove_block(&self, batch: &mut WriteBatch, hash: &Hash) {
+        batch.remove(BLOCK_POSITIONS_TREE, hash);
+    }
+
+    /// Stage removal of a block's undo position
+    pub fn stage_remove_undo(&self, batch: &mut WriteBatch, hash: &Hash) {
+        batch.remove(UNDO_POSITIONS_TREE, hash);
+    }
+
+    /// Summary of a block file
+    pub fn file_info(&self, file: u32) -> Option<BlockFileInfo> {
+        self.state.lock().unwrap().infos.get(&file).cloned()
+    }
+
+    /// Summaries of every block file, by file number
+    pub fn file_infos(&self) -> Vec<(u32, BlockFileInfo)> {
+        let state = self.state.lock().unwrap();
+        state
+            .infos
+            .iter()
+            .map(|(file, info)| (*file, info.clone()))
+            .collect()
+    }
+
+    /// Delete block files and their undo files
+    ///
+    /// Positions pointing into the files are removed in one batch before the
+    /// files are deleted, so no committed position ever points at a missing
+    /// file. The file currently being appended to is never removed.
+    pub fn remove_files(&self, files: &BTreeSet<u32>) -> Result<PrunedFiles> {
+        let mut state = self.state.lock().unwrap();
+        let last_file = state.last_file;
+        let files: BTreeSet<u32> = files
+            .iter()
+            .copied()
+            .filter(|file| *file != last_file)
+            .collect();
+        let mut pruned = PrunedFiles::default();
+        if files.is_empty() {
+            return Ok(pruned);
+        }
+
+        // Positions of re-stored records moved to another file and are kept
+        let mut batch = WriteBatch::new();
+        for file in &files {
+            let info = state.infos.get(file).cloned().unwrap_or_default();
+            for (key, hash) in self.file_records(FileKind::Blocks, *file, info.blocks)? {
+                if self.block_position(&hash)?.map(|position| position.file) == Some(*file) {
+                    batch.remove(BLOCK_POSITIONS_TREE, &hash);
+                    pruned.blocks += 1;
+                }
+                batch.remove(BLOCK_FILE_RECORDS_TREE, &key);
+            }
+            for (key, hash) in self.file_records(FileKind::Undo, *file, info.undos)? {
+                if self.undo_position(&hash)?.map(|position| position.file) == Some(*file) {
+                    batch.remove(UNDO_POSITIONS_TREE, &hash);
+                }
+                batch.remove(BLOCK_FILE_RECORDS_TREE, &key);
+            }
+            batch.remove(BLOCK_FILES_TREE, &file.to_be_bytes());
+        }
+        self.db.apply_batch(&batch)?;
+
+        for file in files {
+            state.infos.remove(&file);
+            for kind in [FileKind::Blocks, FileKind::Undo] {
+                let path = self.path(kind, file);
+                state.unsynced.remove(&path);
+                pruned.bytes += file_len(&path)?;
+                match fs::remove_file(&path) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => {
+                        return Err(e)
+                            .with_context(|| format!("Failed to remove {}", path.display()))
+                    }
+                }
+            }
+            pruned.files.push(file);
+        }
+        Ok(pruned)
+    }
+
+    /// Keys and hashes of the first `count` records listed for a file
+    fn file_records(&self, kind: FileKind, file: u32, count: u32) -> Result<Vec<([u8; 9], Hash)>> {
+        let mut records = Vec::new();
+        for index in 0..count {
+            let key = record_key(kind, file, index);
+            if let Some(data) = self.records.get(&key)? {
+                let hash = Hash::try_from(data.as_slice())
+                    .map_err(|_| anyhow!("Invalid block file record"))?;
+                records.push((key, hash));
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Length of a file, or 0 if it does not exist
+fn file_len(path: &Path) -> Result<u64> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,544 @@
+//! Block index
+//!
+//! Every known block header, keyed by hash, with its height, cumulative work
+//! and validation status. Headers enter the index as soon as they are received
+//! (headers-first sync), before their blocks are downloaded; the status flags
+//! then record whether the block's data is available, whether it has been
+//! fully validated, and whether it or one of its ancestors failed validation.
+//!
+//! The height index in [`BlockStore`] only describes the active chain. Chain
+//! tips, the best header and the best chain to activate are derived from this
+//! index instead, through an in-memory view of its shape that is loaded when
+//! the index is opened and updated as entries are written.
+
+use crate::storage::blockstore::BlockStore;
+use crate::storage::chainstate::ChainState;
+use crate::storage::chainwork::ChainWork;
+use crate::storage::database::{Database, Tree, WriteBatch};
+use anyhow::{anyhow, Result};
+use bitflags::bitflags;
+use bllvm_protocol::{BlockHeader, Hash};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, RwLock};
+
+const BLOCK_INDEX_TREE: &str = "block_index";
+
+bitflags! {
+    /// Validation status of an indexed block
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct BlockStatus: u32 {
+        /// Header connects to an indexed parent and has valid proof-of-work
+        const HEADER_VALID = 1 << 0;
+        /// Block body is stored
+        const HAVE_DATA = 1 << 1;
+        /// Block passed full validation when connected
+        const FULLY_VALID = 1 << 2;
+        /// Block itself failed validation (or was invalidated)
+        const FAILED_VALID = 1 << 3;
+        /// Block descends from a failed block
+        const FAILED_CHILD = 1 << 4;
+        /// Either failure flag
+        const FAILED = Self::FAILED_VALID.bits() | Self::FAILED_CHILD.bits();
+    }
+}
+
+impl Serialize for BlockStatus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.bits().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockStatus {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Ok(Self::from_bits_retain(u32::deserialize(deserializer)?))
+    }
+}
+
+/// An indexed block header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockIndexEntry {
+    pub hash: Hash,
+    pub header: BlockHeader,
+    pub height: u64,
+    /// Cumulative work up to and including this block
+    pub chainwork: ChainWork,
+    pub status: BlockStatus,
+}
+
+impl BlockIndexEntry {
+    /// Hash of the parent block
+    pub fn parent(&self) -> &Hash {
+        &self.header.prev_block_hash
+    }
+
+    /// Whether this block or one of its ancestors failed validation
+    pub fn is_failed(&self) -> bool {
+        self.status.intersects(BlockStatus::FAILED)
+    }
+
+    pub fn has_data(&self) -> bool {
+        self.status.contains(BlockStatus::HAVE_DATA)
+    }
+}
+
+/// Ordering of entries by most work: more chainwork, then the lower block,
+/// then the lower hash
+type WorkKey = (ChainWork, Reverse<u64>, Reverse<Hash>);
+
+fn work_key(entry: &BlockIndexEntry) -> WorkKey {
+    (entry.chainwork, Reverse(entry.height), Reverse(entry.hash))
+}
+
+/// In-memory view of the index's shape
+#[derive(Default)]
+struct IndexState {
+    /// Children of each block with an indexed child
+    children: HashMap<Hash, HashSet<Hash>>,
+    /// Entries that no indexed block builds on
+    tips: HashSet<Hash>,
+    /// Entries not known to be invalid
+    valid: BTreeSet<WorkKey>,
+    /// Entries not known to be invalid that have data
+    with_data: BTreeSet<WorkKey>,
+    /// Key of each entry, to update the sets when it is rewritten
+    keys: HashMap<Hash, WorkKey>,
+}
+
+impl IndexState {
+    fn track(&mut self, entry: &BlockIndexEntry) {
+        match self.keys.remove(&entry.hash) {
+            Some(key) => {
+                self.valid.remove(&key);
+                self.with_data.remove(&key);
+            }
+            None => {
+                self.children
+                    .entry(*entry.parent())
+                    .or_default()
+                    .insert(entry.hash);
+                self.tips.remove(entry.parent());
+                if !self.children.contains_key(&entry.hash) {
+                    self.tips.insert(entry.hash);
+                }
+            }
+        }
+        let key = work_key(entry);
+        if !entry.is_failed() {
+            self.valid.insert(key);
+            if entry.has_data() {
+                self.with_data.insert(key);
+            }
+        }
+        self.keys.insert(entry.hash, key);
+    }
+}
+
+/// Block index storage manager
+pub struct BlockIndex {
+    db: Arc<dyn Database>,
+    entries: Arc<dyn Tree>,
+    state: RwLock<IndexState>,
+}
+
+impl BlockIndex {
+    /// Create a new block index, loading the view of its shape
+    pub fn new(db: Arc<dyn Database>) -> Result<Self> {
+        let entries = Arc::from(db.open_tree(BLOCK_INDEX_TREE)?);
+        let index = Self {
+            db,
+            entries,
+            state: RwLock::new(IndexState::default()),
+        };
+        {
+            let mut state = index.state.write().unwrap();
+            for result in index.entries.iter() {
+                let (_, data) = result?;
+                state.track(&bincode::deserialize(&data)?);
+            }
+        }
+        Ok(index)
+    }
+
+    /// Get the entry for a block
+    pub fn get(&self, hash: &Hash) -> Result<Option<BlockIndexEntry>> {
+        match self.entries.get(hash.as_slice())? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Check whether a block is indexed
+    pub fn contains(&self, hash: &Hash) -> Result<bool> {
+        self.entries.contains_key(hash.as_slice())
+    }
+
+    /// Check whether the index has no entries
+    pub fn is_empty(&self) -> Result<bool> {
+        self.entries.is_empty()
+    }
+
+    /// Number of indexed blocks
+    pub fn len(&self) -> Result<usize> {
+        self.entries.len()
+    }
+
+    /// Remove every entry
+    pub fn clear(&self) -> Result<()> {
+        self.entries.clear()?;
+        *self.state.write().unwrap() = IndexState::default();
+        Ok(())
+    }
+
+    /// All indexed blocks, in no particular order
+    pub fn entries(&self) -> Result<Vec<BlockIndexEntry>> {
+        let mut entries = Vec::new();
+        for result in self.entries.iter() {
+            let (_, data) = result?;
+            entries.push(bincode::deserialize(&data)?);
+        }
+        Ok(entries)
+    }
+
+    /// Write an entry
+    pub fn store_entry(&self, entry: &BlockIndexEntry) -> Result<()> {
+        self.entries
+            .insert(&entry.hash, &bincode::serialize(entry)?)?;
+        self.state.write().unwrap().track(entry);
+        Ok(())
+    }
+
+    /// Stage an entry into a batch
+    ///
+    /// The in-memory view takes the entry at once, so the batch must be
+    /// applied.
+    pub fn stage_entry(&self, batch: &mut WriteBatch, entry: &BlockIndexEntry) -> Result<()> {
+        batch.insert(BLOCK_INDEX_TREE, &entry.hash, &bincode::serialize(entry)?);
+        self.state.write().unwrap().track(entry);
+        Ok(())
+    }
+
+    /// Add a header received during headers sync
+    ///
+    /// The parent must already be indexed, unless the header is a genesis
+    /// block (all-zero parent). Proof-of-work is checked by the caller. A
+    /// header that is already indexed is returned unchanged; one that builds on
+    /// a failed block is indexed as failed.
+    pub fn accept_header(&self, hash: &Hash, header: &BlockHeader) -> Result<BlockIndexEntry> {
+        if let Some(entry) = self.get(hash)? {
+            return Ok(entry);
+        }
+        let parent = if header.prev_block_hash == [0u8; 32] {
+            None
+        } else {
+            Some(self.get(&header.prev_block_hash)?.ok_or_else(|| {
+                anyhow!(
+                    "Header {} has unknown parent {}",
+                    hex::encode(hash),
+                    hex::encode(header.prev_block_hash)
+                )
+            })?)
+        };
+        let entry = Self::child_entry(hash, header, parent.as_ref(), 0, ChainWork::ZERO);
+        self.store_entry(&entry)?;
+        Ok(entry)
+    }
+
+    /// Stage a block's entry with `status` added, creating the entry if needed
+    ///
+    /// A new entry builds on its parent's entry. If the parent is not indexed
+    /// (the genesis block, or the first block above a loaded snapshot's
+    /// headers), the block is placed at `height` on top of `parent_chainwork`.
+    pub fn stage_block(
+        &self,
+        batch: &mut WriteBatch,
+        hash: &Hash,
+        header: &BlockHeader,
+        height: u64,
+        parent_chainwork: ChainWork,
+        status: BlockStatus,
+    ) -> Result<BlockIndexEntry> {
+        let mut entry = match self.get(hash)? {
+            Some(entry) => entry,
+            None => {
+                let parent = self.get(&header.prev_block_hash)?;
+                Self::child_entry(hash, header, parent.as_ref(), height, parent_chainwork)
+            }
+        };
+        entry.status |= status | BlockStatus::HEADER_VALID;
+        self.stage_entry(batch, &entry)?;
+        Ok(entry)
+    }
+
+    /// Add status flags to an indexed block
+    pub fn add_status(&self, hash: &Hash, status: BlockStatus) -> Result<()> {
+        let mut entry = self
+            .get(hash)?
+            .ok_or_else(|| anyhow!("Block {} is not indexed", hex::encode(hash)))?;
+        entry.status |= status;
+        self.store_entry(&entry)
+    }
+
+    fn child_entry(
+        hash: &Hash,
+        header: &BlockHeader,
+        parent: Option<&BlockIndexEntry>,
+        height: u64,
+        parent_chainwork: ChainWork,
+    ) -> BlockIndexEntry {
+        let work = ChainWork::from_bits(header.bits);
+        let (height, chainwork, status) = match parent {
+            Some(parent) => {
+                let status = if parent.is_failed() {
+                    BlockStatus::HEADER_VALID | BlockStatus::FAILED_CHILD
+                } else {
+                    BlockStatus::HEADER_VALID
+                };
+                (parent.height + 1, parent.chainwork + work, status)
+            }
+            None => (height, parent_chainwork + work, BlockStatus::HEADER_VALID),
+        };
+        BlockIndexEntry {
+            hash: *hash,
+            header: header.clone(),
+            height,
+            chainwork,
+            status,
+        }
+    }
+
+    /// Ancestor of `hash` at `height` (the block itself if at that height)
+    pub fn get_ancestor(&self, hash: &Hash, height: u64) -> Result<Option<BlockIndexEntry>> {
+        let mut current = match self.get(hash)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        while current.height > height {
+            current = match self.get(current.parent())? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(current).filter(|entry| entry.height == height))
+    }
+
+    /// Most-work header that is not known to be invalid
+    ///
+    /// Ties go to the lower block, then to the lower hash, so the result does
+    /// not depend on storage order.
+    pub fn best_header(&self) -> Result<Option<BlockIndexEntry>> {
+        let best = self
+            .state
+            .read()
+            .unwrap()
+            .valid
+            .last()
+            .map(|(_, _, Reverse(hash))| *hash);
+        match best {
+            Some(hash) => self.get(&hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Blocks that no indexed block builds on, most cumulative work first
+    pub fn chain_tips(&self) -> Result<Vec<BlockIndexEntry>> {
+        let hashes: Vec<Hash> = self.state.read().unwrap().tips.iter().copied().collect();
+        let mut tips = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            if let Some(entry) = self.get(&hash)? {
+                tips.push(entry);
+            }
+        }
+        tips.sort_by(|a, b| b.chainwork.cmp(&a.chainwork).then(b.height.cmp(&a.height)));
+        Ok(tips)
+    }
+
+    /// Most-work block that could become the active tip
+    ///
+    /// A candidate must not be failed, and it and every block between it and
+    /// the active chain must have data, so the branch can be connected. The
+    /// active tip itself is always a candidate; callers should only switch to
+    /// a candidate with strictly more work.
+    pub fn best_chain_candidate(&self, blocks: &BlockStore) -> Result<Option<BlockIndexEntry>> {
+        // Blocks found not to connect, so later candidates can stop early
+        let mut unreachable: HashSet<Hash> = HashSet::new();
+        let mut cursor: Option<WorkKey> = None;
+        loop {
+            // Next candidate with less work, without holding the lock while
+            // walking its branch
+            let next = {
+                let state = self.state.read().unwrap();
+                match cursor {
+                    None => state.with_data.last().copied(),
+                    Some(cursor) => state.with_data.range(..cursor).next_back().copied(),
+                }
+            };
+            let Some(key) = next else {
+                break;
+            };
+            cursor = Some(key);
+            let (_, _, Reverse(hash)) = key;
+            let Some(entry) = self.get(&hash)? else {
+                continue;
+            };
+            let mut path = Vec::new();
+            let mut current = entry.clone();
+            let reachable = loop {
+                if blocks.is_in_main_chain(&current.hash)? {
+                    break true;
+                }
+                if unreachable.contains(&current.hash) || current.is_failed() || !current.has_data()
+                {
+                    break false;
+                }
+                path.push(current.hash);
+                current = match self.get(current.parent())? {
+                    Some(parent) => parent,
+                    None => break false,
+                };
+            };
+            if reachable {
+                return Ok(Some(entry));
+            }
+            unreachable.extend(path);
+        }
+        Ok(None)
+    }
+
+    /// Mark a block as failed and all of its descendants as failed children
+    ///
+    /// Returns the number of entries changed.
+    pub fn mark_failed(&self, hash: &Hash) -> Result<usize> {
+        let mut entry = self
+            .get(hash)?
+            .ok_or_else(|| anyhow!("Block {} is not indexed", hex::encode(hash)))?;
+        let mut batch = WriteBatch::new();
+        let mut changed = 0;
+        if !entry.status.contains(BlockStatus::FAILED_VALID) {
+            entry.status |= BlockStatus::FAILED_VALID;
+            self.stage_entry(&mut batch, &entry)?;
+            changed += 1;
+        }
+        for mut descendant in self.descendants(hash)? {
+            if !descendant.status.contains(BlockStatus::FAILED_CHILD) {
+                descendant.status |= BlockStatus::FAILED_CHILD;
+                self.stage_entry(&mut batch, &descendant)?;
+                changed += 1;
+            }
+        }
+        self.db.apply_batch(&batch)?;
+        Ok(changed)
+    }
+
+    /// Clear failure flags from a block, its descendants and its ancestors
+    ///
+    /// Used by `reconsiderblock`. Returns the number of entries changed.
+    pub fn reset_failure(&self, hash: &Hash) -> Result<usize> {
+        let entry = self
+            .get(hash)?
+            .ok_or_else(|| anyhow!("Block {} is not indexed", hex::encode(hash)))?;
+        let mut affected = self.descendants(hash)?;
+        let mut current = Some(entry);
+        while let Some(entry) = current {
+            current = self.get(entry.parent())?;
+            affected.push(entry);
+        }
+
+        let mut batch = WriteBatch::new();
+        let mut changed = 0;
+        for mut entry in affected {
+            if entry.is_failed() {
+                entry.status.remove(BlockStatus::FAILED);
+                self.stage_entry(&mut batch, &entry)?;
+                changed += 1;
+            }
+        }
+        self.db.apply_batch(&batch)?;
+        Ok(changed)
+    }
+
+    /// Every indexed block that builds on `hash`, directly or indirectly
+    pub fn descendants(&self, hash: &Hash) -> Result<Vec<BlockIndexEntry>> {
+        let mut hashes = Vec::new();
+        {
+            let state = self.state.read().unwrap();
+            let mut queue = VecDeque::from([*hash]);
+            while let Some(parent) = queue.pop_front() {
+                if let Some(children) = state.children.get(&parent) {
+                    queue.extend(children.iter().copied());
+                    hashes.extend(children.iter().copied());
+                }
+            }
+        }
+        let mut descendants = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            if let Some(entry) = self.get(&hash)? {
+                descendants.push(entry);
+            }
+        }
+        Ok(descendants)
+    }
+
+    /// Build the index from the headers of a database written before it existed
+    ///
+    /// Heights and chainwork follow parent links; a header whose parent is
+    /// not stored takes its height from the height index and builds on the
+    /// chainwork recorded for its parent, if any. Blocks on the active chain
+    /// are marked fully valid, and blocks recorded as invalid are marked failed
+    /// (their descendants as failed children). Returns the number of entries written.
+    pub fn rebuild(&self, blocks: &BlockStore, chain: &ChainState) -> Result<usize> {
+        let headers: HashMap<Hash, BlockHeader> = blocks.headers()?.into_iter().collect();
+        let mut built: HashMap<Hash, BlockIndexEntry> = HashMap::new();
+
+        for hash in headers.keys() {
+            let mut pending = Vec::new();
+            let mut current = *hash;
+            let mut parent = loop {
+                if let Some(entry) = built.get(&current) {
+                    break Some(entry.clone());
+                }
+                match headers.get(&current) {
+                    Some(header) => {
+                        pending.push(current);
+                        current = header.prev_block_hash;
+                    }
+                    None => break None,
+                }
+            };
+            for hash in pending.into_iter().rev() {
+                let header = &headers[&hash];
+                let mut entry = match &parent {
+                    Some(parent) => {
+                        Self::child_entry(&hash, header, Some(parent), 0, ChainWork::ZERO)
+                    }
+                    None => {
+                        let height = blocks.get_height_by_hash(&hash)?.unwrap_or(0);
+                        let parent_chainwork = chain
+                            .get_chainwork(&header.prev_block_hash)?
+                            .unwrap_or_default();
+                        Self::child_entry(&hash, header, None, height, parent_chainwork)
+                    }
+                };
+                if blocks.has_block_body(&hash)? {
+                    entry.status |= BlockStatus::HAVE_DATA;
+                }
+                if blocks.is_in_main_chain(&hash)? {
+                    entry.status |= BlockStatus::FULLY_VALID;
+                }
+                if chain.is_invalid(&hash)? {
+                    entry.status |= BlockStatus::FAILED_VALID;
+                }
+                built.insert(hash, entry.clone());
+                parent = Some(entry);
+            }
+        }
+
+        let mut batch = WriteBatch::new();
+        for entry in built.values() {
+            self.stage_entry(&mut batch, entry)?;
+        }
+        self.db.apply_batch(&batch)?;
+        Ok(built.len())
+    }
+}
@@ -1,14 +1,30 @@
 //! Block storage implementation
 //!
 //! Stores blocks by hash and maintains block index by height.
+//!
+//! Block bodies and undo data are written either to database trees or, with
+//! [`BlockStore::with_block_files`], to flat files (see [`BlockFileStore`]).
+//! Reads check both, so a database can switch to block files without
+//! rewriting the bodies it already holds.
 
-use crate::storage::database::{Database, Tree};
+use crate::storage::blockfiles::{BlockFileStore, PrunedFiles};
+use crate::storage::database::{Database, Tree, WriteBatch};
 use anyhow::Result;
 use bllvm_protocol::segwit::Witness;
-use bllvm_protocol::{Block, BlockHeader, Hash};
+use bllvm_protocol::{Block, BlockHeader, Hash, OutPoint, UTXO};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
 use std::sync::Arc;
 
+const BLOCKS_TREE: &str = "blocks";
+const HEADERS_TREE: &str = "headers";
+const HEIGHT_INDEX_TREE: &str = "height_index";
+const HASH_TO_HEIGHT_TREE: &str = "hash_to_height";
+const WITNESSES_TREE: &str = "witnesses";
+const RECENT_HEADERS_TREE: &str = "recent_headers";
+const BLOCK_METADATA_TREE: &str = "block_metadata";
+const BLOCK_UNDO_TREE: &str = "block_undo";
+
 /// Block metadata stored separately from block data for fast RPC lookups
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockMetadata {
@@ -16,9 +32,24 @@ pub struct BlockMetadata {
     // Could add more metadata here: size, weight, etc.
 }
 
+/// An output spent by a connected block, recorded so it can be restored on disconnect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpentOutput {
+    pub outpoint: OutPoint,
+    pub utxo: UTXO,
+}
+
+/// Undo data for a connected block
+///
+/// Holds every output the block spent, in the order they were spent, so that
+/// disconnecting the block can put them back into the UTXO set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlockUndo {
+    pub spent_outputs: Vec<SpentOutput>,
+}
+
 /// Block storage manager
 pub struct BlockStore {
-    #[allow(dead_code)]
     db: Arc<dyn Database>,
     blocks: Arc<dyn Tree>,
     headers: Arc<dyn Tree>,
@@ -27,18 +58,21 @@ pub struct BlockStore {
     witnesses: Arc<dyn Tree>,
     recent_headers: Arc<dyn Tree>, // For median time-past: stores last 11+ headers by height
     block_metadata: Arc<dyn Tree>, // hash → BlockMetadata (for fast TX count lookup)
+    block_undo: Arc<dyn Tree>,     // hash → BlockUndo (spent outputs for disconnect)
+    block_files: Option<Arc<BlockFileStore>>, // Flat files for new bodies and undo data
 }
 
 impl BlockStore {
     /// Create a new block store
     pub fn new(db: Arc<dyn Database>) -> Result<Self> {
-        let blocks = Arc::from(db.open_tree("blocks")?);
-        let headers = Arc::from(db.open_tree("headers")?);
-        let height_index = Arc::from(db.open_tree("height_index")?);
-        let hash_to_height = Arc::from(db.open_tree("hash_to_height")?);
-        let witnesses = Arc::from(db.open_tree("witnesses")?);
-        let recent_headers = Arc::from(db.open_tree("recent_headers")?);
-        let block_metadata = Arc::from(db.open_tree("block_metadata")?);
+        let blocks = Arc::from(db.open_tree(BLOCKS_TREE)?);
+        let headers = Arc::from(db.open_tree(HEADERS_TREE)?);
+        let height_index = Arc::from(db.open_tree(HEIGHT_INDEX_TREE)?);
+        let hash_to_height = Arc::from(db.open_tree(HASH_TO_HEIGHT_TREE)?);
+        let witnesses = Arc::from(db.open_tree(WITNESSES_TREE)?);
+        let recent_headers = Arc::from(db.open_tree(RECENT_HEADERS_TREE)?);
+        let block_metadata = Arc::from(db.open_tree(BLOCK_METADATA_TREE)?);
+        let block_undo = Arc::from(db.open_tree(BLOCK_UNDO_TREE)?);
 
         Ok(Self {
             db,
@@ -49,30 +83,101 @@ impl BlockStore {
             witnesses,
             recent_headers,
             block_metadata,
+            block_undo,
+            block_files: None,
         })
     }
 
+    /// Create a block store that writes bodies and undo data to block files
+    pub fn with_block_files(
+        db: Arc<dyn Database>,
+        block_files: Arc<BlockFileStore>,
+    ) -> Result<Self> {
+        let mut store = Self::new(db)?;
+        store.block_files = Some(block_files);
+        Ok(store)
+    }
+
+    /// The block files, if bodies are written to flat files
+    pub fn block_files(&self) -> Option<&Arc<BlockFileStore>> {
+        self.block_files.as_ref()
+    }
+
+    /// Commit a batch of staged writes atomically
+    ///
+    /// Block file appends are synced first, so the batch never commits a
+    /// position whose record is not on disk.
+    pub fn apply_batch(&self, batch: &WriteBatch) -> Result<()> {
+        if let Some(files) = &self.block_files {
+            files.sync()?;
+        }
+        self.db.apply_batch(batch)
+    }
+
     /// Store a block
     pub fn store_block(&self, block: &Block) -> Result<()> {
-        let block_hash = self.block_hash(block);
-        let block_data = bincode::serialize(block)?;
+        let mut batch = WriteBatch::new();
+        self.stage_block(&mut batch, block)?;
+        self.apply_batch(&batch)
+    }
 
-        self.blocks.insert(block_hash.as_slice(), &block_data)?;
-        let header_data = bincode::serialize(&block.header)?;
-        self.headers.insert(block_hash.as_slice(), &header_data)?;
+    /// Stage a block body, header and metadata into a batch
+    ///
+    /// Height index and median time-past entries are staged separately via
+    /// [`Self::stage_height`] and [`Self::stage_recent_header`].
+    pub fn stage_block(&self, batch: &mut WriteBatch, block: &Block) -> Result<()> {
+        let block_hash = self.block_hash(block);
+        match &self.block_files {
+            // Appending is not idempotent; a reconnected block keeps its body
+            Some(_) if self.has_block_body(&block_hash)? => {}
+            Some(files) => {
+                files.stage_block(batch, &block_hash, block)?;
+            }
+            None => batch.insert(BLOCKS_TREE, &block_hash, &bincode::serialize(block)?),
+        }
+        batch.insert(
+            HEADERS_TREE,
+            &block_hash,
+            &bincode::serialize(&block.header)?,
+        );
 
         // Store block metadata separately for fast RPC lookups (TX count, etc.)
         let metadata = BlockMetadata {
             n_tx: block.transactions.len() as u32,
         };
-        let metadata_data = bincode::serialize(&metadata)?;
-        self.block_metadata
-            .insert(block_hash.as_slice(), &metadata_data)?;
+        batch.insert(
+            BLOCK_METADATA_TREE,
+            &block_hash,
+            &bincode::serialize(&metadata)?,
+        );
+        Ok(())
+    }
 
-        // Store header for median time-past calculation
-        // We'll need height passed separately, so this will be called after store_height
-        // For now, just store the header - height will be set via store_recent_header
+    /// Stage a header without its block body into a batch
+    pub fn stage_header(&self, batch: &mut WriteBatch, header: &BlockHeader) -> Result<()> {
+        let hash = self.get_header_hash(header);
+        batch.insert(HEADERS_TREE, &hash, &bincode::serialize(header)?);
+        Ok(())
+    }
 
+    /// Stage everything needed to make a block the tip at `height`
+    ///
+    /// Covers the body, header, metadata, witnesses, median time-past window
+    /// and both directions of the height index.
+    pub fn stage_connected_block(
+        &self,
+        batch: &mut WriteBatch,
+        block: &Block,
+        witnesses: &[Witness],
+        height: u64,
+    ) -> Result<()> {
+        let block_hash = self.block_hash(block);
+        self.stage_block(batch, block)?;
+        if !witnesses.is_empty() {
+            self.stage_witness(batch, &block_hash, witnesses)?;
+        }
+        self.stage_recent_header(batch, height, &block.header)?;
+        self.stage_height(batch, height, &block_hash);
         Ok(())
     }
 
@@ -107,6 +212,17 @@ impl BlockStore {
         Ok(())
     }
 
+    /// Stage witness data for a block into a batch
+    pub fn stage_witness(
+        &self,
+        batch: &mut WriteBatch,
+        block_hash: &Hash,
+        witness: &[Witness],
+    ) -> Result<()> {
+        batch.insert(WITNESSES_TREE, block_hash, &bincode::serialize(witness)?);
+        Ok(())
+    }
+
     /// Get witness data for a block
     pub fn get_witness(&self, block_hash: &Hash) -> Result<Option<Vec<Witness>>> {
         if let Some(data) = self.witnesses.get(block_hash.as_slice())? {
@@ -120,16 +236,29 @@ impl BlockStore {
     /// Store recent headers for median time-past calculation
     /// Maintains a sliding window of the last 11+ headers by height
     pub fn store_recent_header(&self, height: u64, header: &BlockHeader) -> Result<()> {
-        let height_bytes = height.to_be_bytes();
-        let header_data = bincode::serialize(header)?;
-        self.recent_headers.insert(&height_bytes, &header_data)?;
+        let mut batch = WriteBatch::new();
+        self.stage_recent_header(&mut batch, height, header)?;
+        self.apply_batch(&batch)
+    }
+
+    /// Stage a recent header (and trimming of the window) into a batch
+    pub fn stage_recent_header(
+        &self,
+        batch: &mut WriteBatch,
+        height: u64,
+        header: &BlockHeader,
+    ) -> Result<()> {
+        batch.insert(
+            RECENT_HEADERS_TREE,
+            &height.to_be_bytes(),
+            &bincode::serialize(header)?,
+        );
 
         // Clean up old headers (keep only last 11 for median time-past)
         // Remove headers older than height - 11
         if height > 11 {
             let remove_height = height - 12;
-            let remove_bytes = remove_height.to_be_bytes();
-            self.recent_headers.remove(&remove_bytes)?;
+            batch.remove(RECENT_HEADERS_TREE, &remove_height.to_be_bytes());
         }
 
         Ok(())
@@ -172,8 +301,33 @@ impl BlockStore {
         Ok(headers)
     }
 
+    /// Get up to `count` headers ending at `hash`, ordered from oldest to newest
+    ///
+    /// Walks back through parent hashes, so unlike [`Self::get_recent_headers`]
+    /// it works for any stored header rather than only the active tip.
+    pub fn get_ancestor_headers(&self, hash: &Hash, count: usize) -> Result<Vec<BlockHeader>> {
+        let mut headers = Vec::new();
+        let mut hash = *hash;
+        while headers.len() < count {
+            match self.get_header(&hash)? {
+                Some(header) => {
+                    hash = header.prev_block_hash;
+                    headers.push(header);
+                }
+                None => break,
+            }
+        }
+        headers.reverse();
+        Ok(headers)
+    }
+
     /// Get a block by hash
     pub fn get_block(&self, hash: &Hash) -> Result<Option<Block>> {
+        if let Some(files) = &self.block_files {
+            if let Some(block) = files.read_block(hash)? {
+                return Ok(Some(block));
+            }
+        }
         if let Some(data) = self.blocks.get(hash.as_slice())? {
             let block: Block = bincode::deserialize(&data)?;
             Ok(Some(block))
@@ -192,17 +346,220 @@ impl BlockStore {
         }
     }
 
+    /// Get every stored header with its hash, in no particular order
+    pub fn headers(&self) -> Result<Vec<(Hash, BlockHeader)>> {
+        let mut headers = Vec::new();
+        for result in self.headers.iter() {
+            let (key, data) = result?;
+            if let Ok(hash) = Hash::try_from(key.as_slice()) {
+                headers.push((hash, bincode::deserialize(&data)?));
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Hashes of all blocks whose bodies are stored, in no particular order
+    pub fn stored_block_hashes(&self) -> Result<Vec<Hash>> {
+        let mut hashes = HashSet::new();
+        for result in self.blocks.iter() {
+            let (key, _) = result?;
+            if let Ok(hash) = Hash::try_from(key.as_slice()) {
+                hashes.insert(hash);
+            }
+        }
+        if let Some(files) = &self.block_files {
+            hashes.extend(files.block_positions()?.into_iter().map(|(hash, _)| hash));
+        }
+        Ok(hashes.into_iter().collect())
+    }
+
+    /// Re-key stored blocks written under an older block hash encoding
+    ///
+    /// Every body, header, witness and metadata entry is moved to the block's
+    /// consensus hash ([`Self::get_header_hash`]). Undo data under an old hash
+    /// is dropped, and the height index is left alone: a full reindex rebuilds
+    /// both. A body in a block file is written again and the old copy stays
+    /// on disk until its file is pruned. Returns the number of blocks moved.
+    pub fn rehash_stored_blocks(&self) -> Result<u64> {
+        let mut rehashed = 0;
+        for old_hash in self.stored_block_hashes()? {
+            let block = match self.get_block(&old_hash)? {
+                Some(block) => block,
+                None => continue,
+            };
+            let hash = self.block_hash(&block);
+            if hash == old_hash {
+                continue;
+            }
+            let mut batch = WriteBatch::new();
+            if let Some(witnesses) = self.get_witness(&old_hash)? {
+                self.stage_witness(&mut batch, &hash, &witnesses)?;
+                self.stage_remove_witness(&mut batch, &old_hash);
+            }
+            self.stage_remove_block_body(&mut batch, &old_hash);
+            self.stage_remove_undo(&mut batch, &old_hash);
+            batch.remove(HEADERS_TREE, &old_hash);
+            batch.remove(BLOCK_METADATA_TREE, &old_hash);
+            self.stage_block(&mut batch, &block)?;
+            self.apply_batch(&batch)?;
+            rehashed += 1;
+        }
+
+        // Headers received without their blocks
+        let mut batch = WriteBatch::new();
+        for (old_hash, header) in self.headers()? {
+            let hash = self.get_header_hash(&header);
+            if hash != old_hash {
+                batch.remove(HEADERS_TREE, &old_hash);
+                batch.insert(HEADERS_TREE, &hash, &bincode::serialize(&header)?);
+            }
+        }
+        self.apply_batch(&batch)?;
+        Ok(rehashed)
+    }
+
+    /// Remove the active chain's height index and median time-past window
+    ///
+    /// Block bodies, headers, witnesses and undo data are kept.
+    pub fn clear_chain_index(&self) -> Result<()> {
+        self.height_index.clear()?;
+        self.hash_to_height.clear()?;
+        self.recent_headers.clear()
+    }
+
     /// Store block height index
     /// Maintains both height→hash and hash→height indices for O(1) lookups
     pub fn store_height(&self, height: u64, hash: &Hash) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        self.stage_height(&mut batch, height, hash);
+        self.apply_batch(&batch)
+    }
+
+    /// Stage both height index directions into a batch
+    pub fn stage_height(&self, batch: &mut WriteBatch, height: u64, hash: &Hash) {
         let height_bytes = height.to_be_bytes();
         // Store height → hash mapping
-        self.height_index.insert(&height_bytes, hash.as_slice())?;
+        batch.insert(HEIGHT_INDEX_TREE, &height_bytes, hash);
         // Store hash → height reverse mapping for O(1) lookup
-        self.hash_to_height.insert(hash.as_slice(), &height_bytes)?;
+        batch.insert(HASH_TO_HEIGHT_TREE, hash, &height_bytes);
+    }
+
+    /// Stage removal of a hash's reverse (hash → height) index entry into a batch
+    pub fn stage_remove_hash_index(&self, batch: &mut WriteBatch, hash: &Hash) {
+        batch.remove(HASH_TO_HEIGHT_TREE, hash);
+    }
+
+    /// Remove the height index entry for a height (used when disconnecting the tip)
+    ///
+    /// The block body and header are kept so the block can be reconnected later.
+    pub fn remove_height(&self, height: u64) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        self.stage_remove_height(&mut batch, height)?;
+        self.apply_batch(&batch)
+    }
+
+    /// Stage removal of the height index entry for a height into a batch
+    pub fn stage_remove_height(&self, batch: &mut WriteBatch, height: u64) -> Result<()> {
+        if let Some(hash) = self.get_hash_by_height(height)? {
+            batch.remove(HASH_TO_HEIGHT_TREE, &hash);
+        }
+        let height_bytes = height.to_be_bytes();
+        batch.remove(HEIGHT_INDEX_TREE, &height_bytes);
+        batch.remove(RECENT_HEADERS_TREE, &height_bytes);
+
+        // Refill the median time-past window that store_recent_header trimmed
+        if height >= 12 {
+            let refill_height = height - 12;
+            if let Some(hash) = self.get_hash_by_height(refill_height)? {
+                if let Some(header) = self.get_header(&hash)? {
+                    batch.insert(
+                        RECENT_HEADERS_TREE,
+                        &refill_height.to_be_bytes(),
+                        &bincode::serialize(&header)?,
+                    );
+                }
+            }
+        }
         Ok(())
     }
 
+    /// Get the highest height present in the height index
+    pub fn get_indexed_tip_height(&self) -> Result<Option<u64>> {
+        let mut tip = None;
+        for item in self.height_index.iter() {
+            let (height_bytes, _) = item?;
+            if height_bytes.len() == 8 {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&height_bytes);
+                let height = u64::from_be_bytes(bytes);
+                tip = Some(tip.map_or(height, |t: u64| t.max(height)));
+            }
+        }
+        Ok(tip)
+    }
+
+    /// Check whether a block is part of the active chain
+    pub fn is_in_main_chain(&self, hash: &Hash) -> Result<bool> {
+        if let Some(height) = self.get_height_by_hash(hash)? {
+            return Ok(self.get_hash_by_height(height)?.as_ref() == Some(hash));
+        }
+        Ok(false)
+    }
+
+    /// Store undo data for a connected block
+    pub fn store_undo(&self, block_hash: &Hash, undo: &BlockUndo) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        self.stage_undo(&mut batch, block_hash, undo)?;
+        self.apply_batch(&batch)
+    }
+
+    /// Stage undo data for a connected block into a batch
+    pub fn stage_undo(
+        &self,
+        batch: &mut WriteBatch,
+        block_hash: &Hash,
+        undo: &BlockUndo,
+    ) -> Result<()> {
+        match &self.block_files {
+            Some(files) if files.undo_position(block_hash)?.is_some() => {}
+            Some(files) => {
+                files.stage_undo(batch, block_hash, undo)?;
+            }
+            None => batch.insert(BLOCK_UNDO_TREE, block_hash, &bincode::serialize(undo)?),
+        }
+        Ok(())
+    }
+
+    /// Get undo data for a block
+    pub fn get_undo(&self, block_hash: &Hash) -> Result<Option<BlockUndo>> {
+        if let Some(files) = &self.block_files {
+            if let Some(undo) = files.read_undo(block_hash)? {
+                return Ok(Some(undo));
+            }
+        }
+        if let Some(data) = self.block_undo.get(block_hash.as_slice())? {
+            let undo: BlockUndo = bincode::deserialize(&data)?;
+            Ok(Some(undo))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Remove undo data for a block
+    pub fn remove_undo(&self, block_hash: &Hash) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        self.stage_remove_undo(&mut batch, block_hash);
+        self.apply_batch(&batch)
+    }
+
+    /// Stage removal of undo data for a block into a batch
+    pub fn stage_remove_undo(&self, batch: &mut WriteBatch, block_hash: &Hash) {
+        batch.remove(BLOCK_UNDO_TREE, block_hash);
+        if let Some(files) = &self.block_files {
+            files.stage_remove_undo(batch, block_hash);
+        }
+    }
+
     /// Get block hash by height
     pub fn get_hash_by_height(&self, height: u64) -> Result<Option<Hash>> {
         let height_bytes = height.to_be_bytes();
@@ -254,12 +611,16 @@ impl BlockStore {
 
     /// Check if a block exists
     pub fn has_block(&self, hash: &Hash) -> Result<bool> {
-        self.blocks.contains_key(hash.as_slice())
+        self.has_block_body(hash)
     }
 
     /// Get total number of blocks stored
     pub fn block_count(&self) -> Result<usize> {
-        self.blocks.len()
+        let in_files = match &self.block_files {
+            Some(files) => files.block_count()?,
+            None => 0,
+        };
+        Ok(self.blocks.len()? + in_files)
     }
 
     /// Calculate block hash using proper Bitcoin double SHA256
@@ -268,26 +629,25 @@ impl BlockStore {
         self.block_hash(block)
     }
 
-    fn block_hash(&self, block: &Block) -> Hash {
+    /// Get the hash of a block header
+    pub fn get_header_hash(&self, header: &BlockHeader) -> Hash {
         use crate::storage::hashing::double_sha256;
+        use bllvm_protocol::serialization::serialize_block_header;
 
-        // Serialize block header for hashing
-        let mut header_data = Vec::new();
-        header_data.extend_from_slice(&block.header.version.to_le_bytes());
-        header_data.extend_from_slice(&block.header.prev_block_hash);
-        header_data.extend_from_slice(&block.header.merkle_root);
-        header_data.extend_from_slice(&block.header.timestamp.to_le_bytes());
-        header_data.extend_from_slice(&block.header.bits.to_le_bytes());
-        header_data.extend_from_slice(&block.header.nonce.to_le_bytes());
+        // Hash the 80-byte consensus encoding, so hashes match the
+        // prev_block_hash links of blocks from the network and Bitcoin Core
+        double_sha256(&serialize_block_header(header))
+    }
 
-        // Calculate Bitcoin double SHA256 hash
-        double_sha256(&header_data)
+    fn block_hash(&self, block: &Block) -> Hash {
+        self.get_header_hash(&block.header)
     }
 
     /// Remove block body (keep header for PoW verification)
     pub fn remove_block_body(&self, hash: &Hash) -> Result<()> {
-        self.blocks.remove(hash.as_slice())?;
-        Ok(())
+        let mut batch = WriteBatch::new();
+        self.stage_remove_block_body(&mut batch, hash);
+        self.apply_batch(&batch)
     }
 
     /// Remove witness data for a block
@@ -296,6 +656,22 @@ impl BlockStore {
         Ok(())
     }
 
+    /// Stage removal of a block body into a batch (header is kept)
+    ///
+    /// A body in a block file stays on disk until [`Self::prune_block_files`]
+    /// deletes the whole file.
+    pub fn stage_remove_block_body(&self, batch: &mut WriteBatch, hash: &Hash) {
+        batch.remove(BLOCKS_TREE, hash);
+        if let Some(files) = &self.block_files {
+            files.stage_remove_block(batch, hash);
+        }
+    }
+
+    /// Stage removal of witness data for a block into a batch
+    pub fn stage_remove_witness(&self, batch: &mut WriteBatch, hash: &Hash) {
+        batch.remove(WITNESSES_TREE, hash);
+    }
+
     /// Remove block by height (removes body, keeps header)
     pub fn remove_block_by_height(&self, height: u64) -> Result<()> {
         if let Some(hash) = self.get_hash_by_height(height)? {
@@ -317,6 +693,57 @@ impl BlockStore {
 
     /// Check if a block body exists (not just header)
     pub fn has_block_body(&self, hash: &Hash) -> Result<bool> {
+        if let Some(files) = &self.block_files {
+            if files.has_block(hash)? {
+                return Ok(true);
+            }
+        }
+        self.has_database_body(hash)
+    }
+
+    /// Check if a block body is stored in the database rather than a block file
+    ///
+    /// Such bodies are pruned one at a time; bodies in block files are pruned
+    /// with their file.
+    pub fn has_database_body(&self, hash: &Hash) -> Result<bool> {
         self.blocks.contains_key(hash.as_slice())
     }
+
+    /// Delete block files that hold no active-chain block at or above
+    /// `prune_height`
+    ///
+    /// Blocks are appended in arrival order, so every file numbered below the
+    /// lowest file holding such a block is deleted with its undo file; stale
+    /// blocks in those files go with them. Blocks below `prune_height` in later
+    /// files are kept, as is the file being written to. Does nothing without
+    /// block files.
+    pub fn prune_block_files(&self, prune_height: u64) -> Result<PrunedFiles> {
+        let files = match &self.block_files {
+            Some(files) => files,
+            None => return Ok(PrunedFiles::default()),
+        };
+
+        let mut first_kept = files.last_file();
+        'files: for (file, _) in files.file_infos() {
+            if file >= first_kept {
+                break;
+            }
+            for hash in files.file_blocks(file)? {
+                if let Some(height) = self.get_height_by_hash(&hash)? {
+                    if height >= prune_height && self.is_in_main_chain(&hash)? {
+                        first_kept = file;
+                        break 'files;
+                    }
+                }
+            }
+        }
+
+        let prunable: BTreeSet<u32> = files
+            .file_infos()
+            .into_iter()
+            .map(|(file, _)| file)
+            .filter(|file| *file < first_kept)
+            .collect();
+        files.remove_files(&prunable)
+    }
 }
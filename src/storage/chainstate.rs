@@ -2,12 +2,96 @@
 //!
 //! Stores chain metadata including tip, height, and chain parameters.
 
-use crate::storage::database::{Database, Tree};
+use crate::storage::blockstore::BlockStore;
+use crate::storage::chainwork::ChainWork;
+use crate::storage::database::{Database, Tree, WriteBatch};
+use crate::storage::hashing::calculate_utxo_set_hash;
+use crate::storage::snapshot::SnapshotBase;
 use anyhow::Result;
-use bllvm_protocol::{BlockHeader, Hash};
+use bllvm_protocol::{BlockHeader, Hash, ProtocolVersion};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
+const CHAIN_INFO_TREE: &str = "chain_info";
+const WORK_CACHE_TREE: &str = "work_cache";
+const CHAINWORK_CACHE_TREE: &str = "chainwork_cache";
+const CHAIN_INFO_KEY: &[u8] = b"current";
+const CHAIN_PARAMS_KEY: &[u8] = b"params";
+const SNAPSHOT_BASE_KEY: &[u8] = b"snapshot_base";
+const CHAINWORK_VERSION_KEY: &[u8] = b"chainwork_version";
+const BLOCK_HASH_VERSION_KEY: &[u8] = b"block_hash_version";
+
+/// Chainwork storage format: 1 = 256-bit values (earlier databases used
+/// 64-bit work, 128-bit chainwork and a 64-bit `ChainInfo::total_work`)
+const CHAINWORK_VERSION: u32 = 1;
+
+/// Block hash encoding: 1 = double SHA256 of the 80-byte consensus header
+/// (earlier databases hashed the header fields at their in-memory widths, so
+/// their blocks are keyed by hashes nothing else uses)
+const BLOCK_HASH_VERSION: u32 = 1;
+
+/// A UTXO snapshot accepted by `loadtxoutset`
+///
+/// `utxo_hash` is the snapshot's content hash as computed by
+/// [`calculate_snapshot_hash`](crate::storage::hashing::calculate_snapshot_hash).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssumeUtxoData {
+    pub height: u64,
+    pub block_hash: Hash,
+    pub utxo_hash: Hash,
+}
+
+/// Parse a hash written the way Bitcoin Core displays it (byte-reversed hex)
+const fn display_hash(hex: &str) -> Hash {
+    const fn nibble(c: u8) -> u8 {
+        match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            _ => panic!("invalid hex digit"),
+        }
+    }
+    let hex = hex.as_bytes();
+    assert!(hex.len() == 64);
+    let mut hash = [0u8; 32];
+    let mut i = 0;
+    while i < 32 {
+        hash[31 - i] = (nibble(hex[2 * i]) << 4) | nibble(hex[2 * i + 1]);
+        i += 1;
+    }
+    hash
+}
+
+// Known snapshots per network. Mainnet and testnet entries are the ones
+// Bitcoin Core publishes; the content hash is its hash_serialized_3.
+const MAINNET_ASSUMEUTXO: &[AssumeUtxoData] = &[
+    AssumeUtxoData {
+        height: 840_000,
+        block_hash: display_hash(
+            "0000000000000000000320283a032748cef8227873ff4872689bf23f1cda83a5",
+        ),
+        utxo_hash: display_hash("a2a5521b1b5ab65f67818e5e8eccabb7171a517f9e2382208f77687310768f96"),
+    },
+    AssumeUtxoData {
+        height: 880_000,
+        block_hash: display_hash(
+            "000000000000000000010b17283c3c400507969a9c2afd1dcf2082ec5cca2880",
+        ),
+        utxo_hash: display_hash("dbd190983eaf433ef7c15f78a278ae42c00ef52e0fd2a54953782175fbadcea9"),
+    },
+];
+const TESTNET_ASSUMEUTXO: &[AssumeUtxoData] = &[AssumeUtxoData {
+    height: 2_500_000,
+    block_hash: display_hash("0000000000000093bcb68c03a9a168ae252572d348a2eaeba2cdf9231d73206f"),
+    utxo_hash: display_hash("f841584909f68e47897952345234e37fcd9128cd818f41ee6c3ca68db8071be7"),
+}];
+// The four coinbase-only blocks the AssumeUTXO tests build from genesis
+const REGTEST_ASSUMEUTXO: &[AssumeUtxoData] = &[AssumeUtxoData {
+    height: 3,
+    block_hash: display_hash("8abfbe40c20c5ec750696de05e4d785cdd4aeb8a4f3f6ba589ff3599806ef990"),
+    utxo_hash: display_hash("d2fd23f8a9146d5e6d6db902cbf2ae3e51d87f257b65c173fbd397aef9a3e756"),
+}];
+
 /// UTXO set statistics (cached for fast RPC lookups)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UTXOStats {
@@ -24,10 +108,22 @@ pub struct ChainInfo {
     pub tip_hash: Hash,
     pub tip_header: BlockHeader,
     pub height: u64,
-    pub total_work: u64,
+    /// Cumulative chainwork of the tip
+    pub total_work: ChainWork,
     pub chain_params: ChainParams,
 }
 
+/// Chain info record as written before chainwork became 256-bit
+#[derive(Deserialize)]
+struct LegacyChainInfo {
+    tip_hash: Hash,
+    tip_header: BlockHeader,
+    height: u64,
+    #[allow(dead_code)]
+    total_work: u64,
+    chain_params: ChainParams,
+}
+
 /// Chain parameters
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChainParams {
@@ -48,9 +144,39 @@ impl Default for ChainParams {
     }
 }
 
+impl ChainParams {
+    /// Parameters for a network ("mainnet", "testnet" or "regtest")
+    pub fn for_network(network: &str) -> Self {
+        let subsidy_halving_interval = if network == "regtest" { 150 } else { 210000 };
+        Self {
+            network: network.to_string(),
+            subsidy_halving_interval,
+            ..Default::default()
+        }
+    }
+
+    /// Protocol version to validate this network's blocks with
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        match self.network.as_str() {
+            "testnet" => ProtocolVersion::Testnet3,
+            "regtest" => ProtocolVersion::Regtest,
+            _ => ProtocolVersion::BitcoinV1,
+        }
+    }
+
+    /// Known AssumeUTXO snapshots for this network
+    pub fn assumeutxo_snapshots(&self) -> &'static [AssumeUtxoData] {
+        match self.network.as_str() {
+            "mainnet" => MAINNET_ASSUMEUTXO,
+            "testnet" => TESTNET_ASSUMEUTXO,
+            "regtest" => REGTEST_ASSUMEUTXO,
+            _ => &[],
+        }
+    }
+}
+
 /// Chain state storage manager
 pub struct ChainState {
-    #[allow(dead_code)]
     db: Arc<dyn Database>,
     chain_info: Arc<dyn Tree>,
     work_cache: Arc<dyn Tree>, // work per block (individual block work)
@@ -64,15 +190,15 @@ pub struct ChainState {
 impl ChainState {
     /// Create a new chain state store
     pub fn new(db: Arc<dyn Database>) -> Result<Self> {
-        let chain_info = Arc::from(db.open_tree("chain_info")?);
-        let work_cache = Arc::from(db.open_tree("work_cache")?);
-        let chainwork_cache = Arc::from(db.open_tree("chainwork_cache")?);
+        let chain_info = Arc::from(db.open_tree(CHAIN_INFO_TREE)?);
+        let work_cache = Arc::from(db.open_tree(WORK_CACHE_TREE)?);
+        let chainwork_cache = Arc::from(db.open_tree(CHAINWORK_CACHE_TREE)?);
         let utxo_stats_cache = Arc::from(db.open_tree("utxo_stats_cache")?);
         let network_hashrate_cache = Arc::from(db.open_tree("network_hashrate_cache")?);
         let invalid_blocks = Arc::from(db.open_tree("invalid_blocks")?);
         let chain_tips = Arc::from(db.open_tree("chain_tips")?);
 
-        Ok(Self {
+        let chain_state = Self {
             db,
             chain_info,
             work_cache,
@@ -81,7 +207,12 @@ impl ChainState {
             network_hashrate_cache,
             invalid_blocks,
             chain_tips,
-        })
+        };
+        // A new database is written in the current format from the start
+        if !chain_state.chain_info.contains_key(CHAIN_INFO_KEY)? {
+            chain_state.store_chainwork_version()?;
+        }
+        Ok(chain_state)
     }
 
     /// Initialize chain state with genesis block
@@ -90,8 +221,8 @@ impl ChainState {
             tip_hash: self.calculate_hash(genesis_header),
             tip_header: genesis_header.clone(),
             height: 0,
-            total_work: 0,
-            chain_params: ChainParams::default(),
+            total_work: ChainWork::ZERO,
+            chain_params: self.get_chain_params()?,
         };
 
         self.store_chain_info(&chain_info)?;
@@ -101,13 +232,13 @@ impl ChainState {
     /// Store chain information
     pub fn store_chain_info(&self, info: &ChainInfo) -> Result<()> {
         let data = bincode::serialize(info)?;
-        self.chain_info.insert(b"current", &data)?;
+        self.chain_info.insert(CHAIN_INFO_KEY, &data)?;
         Ok(())
     }
 
     /// Load current chain information
     pub fn load_chain_info(&self) -> Result<Option<ChainInfo>> {
-        if let Some(data) = self.chain_info.get(b"current")? {
+        if let Some(data) = self.chain_info.get(CHAIN_INFO_KEY)? {
             let info: ChainInfo = bincode::deserialize(&data)?;
             Ok(Some(info))
         } else {
@@ -115,6 +246,42 @@ impl ChainState {
         }
     }
 
+    /// Record the parameters of the network this datadir belongs to
+    ///
+    /// Used when the chain info record is first created, and by snapshot
+    /// dump/load to tell which network a snapshot is for.
+    pub fn store_chain_params(&self, params: &ChainParams) -> Result<()> {
+        self.chain_info
+            .insert(CHAIN_PARAMS_KEY, &bincode::serialize(params)?)?;
+        Ok(())
+    }
+
+    /// Get the recorded chain parameters (mainnet if none were recorded)
+    pub fn get_chain_params(&self) -> Result<ChainParams> {
+        match self.chain_info.get(CHAIN_PARAMS_KEY)? {
+            Some(data) => Ok(bincode::deserialize(&data)?),
+            None => Ok(ChainParams::default()),
+        }
+    }
+
+    /// Get the snapshot the active chain state was loaded from, if any
+    pub fn get_snapshot_base(&self) -> Result<Option<SnapshotBase>> {
+        match self.chain_info.get(SNAPSHOT_BASE_KEY)? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stage the snapshot base record into a batch
+    pub fn stage_snapshot_base(&self, batch: &mut WriteBatch, base: &SnapshotBase) -> Result<()> {
+        batch.insert(
+            CHAIN_INFO_TREE,
+            SNAPSHOT_BASE_KEY,
+            &bincode::serialize(base)?,
+        );
+        Ok(())
+    }
+
     /// Calculate difficulty from block bits (compact target format)
     /// Difficulty = MAX_TARGET / target
     /// For display purposes, normalized to genesis difficulty = 1.0
@@ -145,44 +312,9 @@ impl ChainState {
     }
 
     /// Calculate work from block bits (compact target format)
-    /// Work = 2^256 / (target + 1) for Bitcoin
-    /// Simplified: work = u128::MAX / (target + 1)
-    fn calculate_work_from_bits(bits: u64) -> u64 {
-        // Expand target from compact format
-        let exponent = (bits >> 24) as u8;
-        let mantissa = bits & 0x00ffffff;
-
-        if mantissa == 0 {
-            return 0;
-        }
-
-        // Calculate target: target = mantissa * 2^(8*(exponent-3))
-        // For simplicity, use a simplified calculation
-        // Prevent overflow by capping the shift amount
-        let target = if exponent <= 3 {
-            let shift = 8 * (3 - exponent);
-            if shift >= 64 {
-                0 // Shift would overflow, return 0
-            } else {
-                mantissa >> shift
-            }
-        } else {
-            let shift = 8 * (exponent - 3);
-            if shift >= 64 {
-                u64::MAX // Shift would overflow, return max value
-            } else {
-                mantissa << shift
-            }
-        };
-
-        // Work = MAX_TARGET / (target + 1)
-        // Use u64::MAX as approximation for MAX_TARGET
-        if target == 0 || target == u64::MAX {
-            return 1; // Minimum work
-        }
-
-        // Prevent division by zero
-        u64::MAX / (target + 1).max(1)
+    /// Work = 2^256 / (target + 1); see [`ChainWork::from_bits`]
+    pub fn calculate_work_from_bits(bits: u64) -> ChainWork {
+        ChainWork::from_bits(bits)
     }
 
     /// Update chain tip and calculate incremental chainwork
@@ -197,26 +329,70 @@ impl ChainState {
             let prev_chainwork = if height > 0 {
                 // Get previous block hash
                 if let Ok(Some(prev_hash)) = self.get_prev_block_hash(tip_header) {
-                    self.get_chainwork(&prev_hash)?.unwrap_or(0)
+                    self.get_chainwork(&prev_hash)?.unwrap_or_default()
                 } else {
-                    0
+                    ChainWork::ZERO
                 }
             } else {
                 // Genesis block: chainwork = work
-                0
+                ChainWork::ZERO
             };
 
-            let new_chainwork = prev_chainwork + block_work as u128;
+            let new_chainwork = prev_chainwork + block_work;
             self.store_chainwork(tip_hash, new_chainwork)?;
 
             info.tip_hash = *tip_hash;
             info.tip_header = tip_header.clone();
             info.height = height;
+            info.total_work = new_chainwork;
             self.store_chain_info(&info)?;
         }
         Ok(())
     }
 
+    /// Stage a tip update and its work/chainwork entries into a batch
+    ///
+    /// Unlike [`Self::update_tip`], this creates the chain info record if the
+    /// chain has not been initialized yet, so the first connected block sets it.
+    pub fn stage_tip(
+        &self,
+        batch: &mut WriteBatch,
+        tip_hash: &Hash,
+        tip_header: &BlockHeader,
+        height: u64,
+    ) -> Result<()> {
+        let block_work = Self::calculate_work_from_bits(tip_header.bits);
+        batch.insert(WORK_CACHE_TREE, tip_hash, &block_work.to_be_bytes());
+
+        let prev_chainwork = if height > 0 {
+            self.get_chainwork(&tip_header.prev_block_hash)?
+                .unwrap_or_default()
+        } else {
+            ChainWork::ZERO
+        };
+        let new_chainwork = prev_chainwork + block_work;
+        batch.insert(CHAINWORK_CACHE_TREE, tip_hash, &new_chainwork.to_be_bytes());
+
+        let info = match self.load_chain_info()? {
+            Some(mut info) => {
+                info.tip_hash = *tip_hash;
+                info.tip_header = tip_header.clone();
+                info.height = height;
+                info.total_work = new_chainwork;
+                info
+            }
+            None => ChainInfo {
+                tip_hash: *tip_hash,
+                tip_header: tip_header.clone(),
+                height,
+                total_work: new_chainwork,
+                chain_params: self.get_chain_params()?,
+            },
+        };
+        batch.insert(CHAIN_INFO_TREE, CHAIN_INFO_KEY, &bincode::serialize(&info)?);
+        Ok(())
+    }
+
     /// Get previous block hash from header
     fn get_prev_block_hash(&self, header: &BlockHeader) -> Result<Option<Hash>> {
         Ok(Some(header.prev_block_hash))
@@ -250,7 +426,7 @@ impl ChainState {
     }
 
     /// Store work for a block
-    pub fn store_work(&self, hash: &Hash, work: u64) -> Result<()> {
+    pub fn store_work(&self, hash: &Hash, work: ChainWork) -> Result<()> {
         let key = hash.as_slice();
         let value = work.to_be_bytes();
         self.work_cache.insert(key, &value)?;
@@ -258,21 +434,16 @@ impl ChainState {
     }
 
     /// Get work for a block
-    pub fn get_work(&self, hash: &Hash) -> Result<Option<u64>> {
-        let key = hash.as_slice();
-        if let Some(data) = self.work_cache.get(key)? {
-            let work = u64::from_be_bytes([
-                data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-            ]);
-            Ok(Some(work))
-        } else {
-            Ok(None)
+    pub fn get_work(&self, hash: &Hash) -> Result<Option<ChainWork>> {
+        match self.work_cache.get(hash.as_slice())? {
+            Some(data) => Ok(Some(Self::decode_work(&data)?)),
+            None => Ok(None),
         }
     }
 
     /// Store cumulative chainwork for a block
     /// Chainwork is the sum of work from genesis to this block
-    pub fn store_chainwork(&self, hash: &Hash, chainwork: u128) -> Result<()> {
+    pub fn store_chainwork(&self, hash: &Hash, chainwork: ChainWork) -> Result<()> {
         let key = hash.as_slice();
         let value = chainwork.to_be_bytes();
         self.chainwork_cache.insert(key, &value)?;
@@ -281,47 +452,149 @@ impl ChainState {
 
     /// Get cumulative chainwork for a block
     /// Returns the sum of work from genesis to this block (O(1) lookup)
-    pub fn get_chainwork(&self, hash: &Hash) -> Result<Option<u128>> {
-        let key = hash.as_slice();
-        if let Some(data) = self.chainwork_cache.get(key)? {
-            // Ensure we have at least 16 bytes for u128
-            if data.len() >= 16 {
-                let mut bytes = [0u8; 16];
-                bytes.copy_from_slice(&data[..16]);
-                Ok(Some(u128::from_be_bytes(bytes)))
-            } else {
-                // Handle shorter data (shouldn't happen, but be defensive)
-                let mut bytes = [0u8; 16];
-                for (i, &byte) in data.iter().enumerate() {
-                    if i < 16 {
-                        bytes[15 - i] = byte; // Pad from right
+    pub fn get_chainwork(&self, hash: &Hash) -> Result<Option<ChainWork>> {
+        match self.chainwork_cache.get(hash.as_slice())? {
+            Some(data) => Ok(Some(Self::decode_work(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn decode_work(data: &[u8]) -> Result<ChainWork> {
+        ChainWork::from_be_slice(data)
+            .ok_or_else(|| anyhow::anyhow!("Invalid work value of {} bytes", data.len()))
+    }
+
+    /// Total chain work (cumulative chainwork of the tip)
+    pub fn calculate_total_work(&self) -> Result<ChainWork> {
+        Ok(self
+            .load_chain_info()?
+            .map(|info| info.total_work)
+            .unwrap_or_default())
+    }
+
+    /// Convert a database written with 64/128-bit chainwork to 256-bit values
+    ///
+    /// Rewrites the chain info record and recomputes work and chainwork for
+    /// every block that has an entry (and their stored ancestors) from the
+    /// stored headers, all in one batch. Runs once; returns whether anything
+    /// was migrated.
+    pub fn migrate_chainwork(&self, blocks: &BlockStore) -> Result<bool> {
+        if self.chain_info.contains_key(CHAINWORK_VERSION_KEY)? {
+            return Ok(false);
+        }
+        let legacy: LegacyChainInfo = match self.chain_info.get(CHAIN_INFO_KEY)? {
+            Some(data) => bincode::deserialize(&data)?,
+            None => {
+                self.store_chainwork_version()?;
+                return Ok(false);
+            }
+        };
+
+        let mut hashes = HashSet::new();
+        let mut stale = Vec::new();
+        for tree in [&self.work_cache, &self.chainwork_cache] {
+            for result in tree.iter() {
+                let (key, _) = result?;
+                if let Ok(hash) = Hash::try_from(key.as_slice()) {
+                    hashes.insert(hash);
+                }
+            }
+        }
+        for result in self.chain_tips.iter() {
+            let (key, _) = result?;
+            if let Ok(hash) = Hash::try_from(key.as_slice()) {
+                hashes.insert(hash);
+            }
+        }
+        hashes.insert(legacy.tip_hash);
+
+        let mut batch = WriteBatch::new();
+        let mut chainworks: HashMap<Hash, ChainWork> = HashMap::new();
+        for hash in &hashes {
+            // Walk back to a block whose chainwork is known (or to the first
+            // block without a stored header), then add work forwards
+            let mut pending = Vec::new();
+            let mut current = *hash;
+            let mut chainwork = loop {
+                if let Some(chainwork) = chainworks.get(&current) {
+                    break *chainwork;
+                }
+                match blocks.get_header(&current)? {
+                    Some(header) => {
+                        let prev = header.prev_block_hash;
+                        pending.push((current, ChainWork::from_bits(header.bits)));
+                        current = prev;
                     }
+                    None => break ChainWork::ZERO,
                 }
-                Ok(Some(u128::from_be_bytes(bytes)))
+            };
+            if pending.is_empty() && !chainworks.contains_key(hash) {
+                stale.push(*hash);
+            }
+            for (block_hash, work) in pending.into_iter().rev() {
+                chainwork += work;
+                batch.insert(WORK_CACHE_TREE, &block_hash, &work.to_be_bytes());
+                batch.insert(CHAINWORK_CACHE_TREE, &block_hash, &chainwork.to_be_bytes());
+                chainworks.insert(block_hash, chainwork);
             }
-        } else {
-            Ok(None)
         }
-    }
-
-    /// Calculate total chain work
-    pub fn calculate_total_work(&self) -> Result<u64> {
-        let mut total = 0u64;
+        // Entries for blocks whose header is gone cannot be recomputed
+        for hash in &stale {
+            batch.remove(WORK_CACHE_TREE, hash);
+            batch.remove(CHAINWORK_CACHE_TREE, hash);
+        }
 
-        for result in self.work_cache.iter() {
-            let (_, data) = result?;
-            let work = u64::from_be_bytes([
-                data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-            ]);
-            total += work;
+        let info = ChainInfo {
+            tip_hash: legacy.tip_hash,
+            tip_header: legacy.tip_header,
+            height: legacy.height,
+            total_work: chainworks
+                .get(&legacy.tip_hash)
+                .copied()
+                .unwrap_or_default(),
+            chain_params: legacy.chain_params,
+        };
+        batch.insert(CHAIN_INFO_TREE, CHAIN_INFO_KEY, &bincode::serialize(&info)?);
+        batch.insert(
+            CHAIN_INFO_TREE,
+            CHAINWORK_VERSION_KEY,
+            &CHAINWORK_VERSION.to_be_bytes(),
+        );
+        self.db.apply_batch(&batch)?;
+
+        tracing::info!(
+            "Migrated chainwork to 256-bit values for {} blocks (tip chainwork {})",
+            chainworks.len(),
+            info.total_work
+        );
+        Ok(true)
+    }
+
+    fn store_chainwork_version(&self) -> Result<()> {
+        self.chain_info
+            .insert(CHAINWORK_VERSION_KEY, &CHAINWORK_VERSION.to_be_bytes())
+    }
+
+    /// Whether stored blocks are keyed by the current block hash encoding
+    ///
+    /// A database without a chain counts as current; one with a chain but no
+    /// recorded encoding predates it and needs a full reindex.
+    pub fn has_current_block_hashes(&self) -> Result<bool> {
+        match self.chain_info.get(BLOCK_HASH_VERSION_KEY)? {
+            Some(data) => Ok(data.as_slice() == BLOCK_HASH_VERSION.to_be_bytes()),
+            None => Ok(!self.is_initialized()?),
         }
+    }
 
-        Ok(total)
+    /// Record that stored blocks are keyed by the current block hash encoding
+    pub fn store_block_hash_version(&self) -> Result<()> {
+        self.chain_info
+            .insert(BLOCK_HASH_VERSION_KEY, &BLOCK_HASH_VERSION.to_be_bytes())
     }
 
     /// Check if chain is initialized
     pub fn is_initialized(&self) -> Result<bool> {
-        self.chain_info.contains_key(b"current")
+        self.chain_info.contains_key(CHAIN_INFO_KEY)
     }
 
     /// Store UTXO set statistics for a block
@@ -402,31 +675,10 @@ impl ChainState {
         utxo_set: &bllvm_protocol::UtxoSet,
         transaction_count: u64,
     ) -> Result<()> {
-        use crate::storage::hashing::double_sha256;
-
         // Calculate UTXO set statistics
         let txouts = utxo_set.len() as u64;
         let total_amount: u128 = utxo_set.values().map(|utxo| utxo.value as u128).sum();
-
-        // Calculate hash_serialized_2 (double SHA256 of serialized UTXO set)
-        // Sort UTXOs for deterministic hashing
-        let mut entries: Vec<_> = utxo_set.iter().collect();
-        entries.sort_by(|(a, _), (b, _)| match a.hash.cmp(&b.hash) {
-            std::cmp::Ordering::Equal => a.index.cmp(&b.index),
-            other => other,
-        });
-
-        // Serialize each UTXO entry
-        let mut serialized = Vec::new();
-        for (outpoint, utxo) in entries {
-            serialized.extend_from_slice(&outpoint.hash);
-            serialized.extend_from_slice(&outpoint.index.to_le_bytes());
-            serialized.extend_from_slice(&utxo.value.to_le_bytes());
-            serialized.extend_from_slice(&utxo.script_pubkey);
-            serialized.extend_from_slice(&utxo.height.to_le_bytes());
-        }
-
-        let hash_serialized_2 = double_sha256(&serialized);
+        let hash_serialized_2 = calculate_utxo_set_hash(utxo_set);
 
         // Store in cache
         let stats = UTXOStats {
@@ -510,6 +762,8 @@ impl ChainState {
         self.network_hashrate_cache.clear()?;
         self.invalid_blocks.clear()?;
         self.chain_tips.clear()?;
+        self.store_chainwork_version()?;
+        self.store_block_hash_version()?;
         Ok(())
     }
 
@@ -555,74 +809,12 @@ impl ChainState {
         Ok(invalid)
     }
 
-    /// Add a chain tip (for fork tracking)
-    pub fn add_chain_tip(
-        &self,
-        hash: &Hash,
-        height: u64,
-        branchlen: u64,
-        status: &str,
-    ) -> Result<()> {
-        #[derive(Serialize, Deserialize)]
-        struct TipInfo {
-            height: u64,
-            branchlen: u64,
-            status: String,
-        }
-
-        let tip_info = TipInfo {
-            height,
-            branchlen,
-            status: status.to_string(),
-        };
-        let data = bincode::serialize(&tip_info)?;
-        self.chain_tips.insert(hash.as_slice(), &data)?;
-        Ok(())
-    }
-
-    /// Remove a chain tip
-    pub fn remove_chain_tip(&self, hash: &Hash) -> Result<()> {
-        self.chain_tips.remove(hash.as_slice())?;
-        Ok(())
-    }
-
-    /// Get all chain tips
-    pub fn get_chain_tips(&self) -> Result<Vec<(Hash, u64, u64, String)>> {
-        #[derive(Deserialize)]
-        struct TipInfo {
-            height: u64,
-            branchlen: u64,
-            status: String,
-        }
-
-        let mut tips = Vec::new();
-        for result in self.chain_tips.iter() {
-            let (key, data) = result?;
-            if key.len() == 32 {
-                if let Ok(tip_info) = bincode::deserialize::<TipInfo>(&data) {
-                    let mut hash = [0u8; 32];
-                    hash.copy_from_slice(&key);
-                    tips.push((hash, tip_info.height, tip_info.branchlen, tip_info.status));
-                }
-            }
-        }
-        Ok(tips)
-    }
-
     /// Calculate block hash using proper Bitcoin double SHA256
     fn calculate_hash(&self, header: &BlockHeader) -> Hash {
         use crate::storage::hashing::double_sha256;
+        use bllvm_protocol::serialization::serialize_block_header;
 
-        // Serialize block header for hashing
-        let mut header_data = Vec::new();
-        header_data.extend_from_slice(&header.version.to_le_bytes());
-        header_data.extend_from_slice(&header.prev_block_hash);
-        header_data.extend_from_slice(&header.merkle_root);
-        header_data.extend_from_slice(&header.timestamp.to_le_bytes());
-        header_data.extend_from_slice(&header.bits.to_le_bytes());
-        header_data.extend_from_slice(&header.nonce.to_le_bytes());
-
-        // Calculate Bitcoin double SHA256 hash
-        double_sha256(&header_data)
+        // Same encoding as BlockStore::get_header_hash
+        double_sha256(&serialize_block_header(header))
     }
 }
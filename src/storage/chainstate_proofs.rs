@@ -11,6 +11,7 @@
 #[cfg(kani)]
 mod kani_proofs {
     use crate::storage::chainstate::{ChainInfo, ChainParams, ChainState};
+    use crate::storage::chainwork::ChainWork;
     use crate::storage::kani_helpers::kani_mocks::MockDatabase;
     use bllvm_protocol::{BlockHeader, Hash};
     use kani::*;
@@ -131,7 +132,7 @@ mod kani_proofs {
         let initial_chainwork = chain_state
             .get_chainwork(&genesis_hash)
             .unwrap()
-            .unwrap_or(0);
+            .unwrap_or_default();
 
         // Update to height 1
         let hash1: Hash = kani::any();
@@ -139,7 +140,10 @@ mod kani_proofs {
         chain_state.update_tip(&hash1, &header1, 1).unwrap();
 
         // Get chainwork at height 1
-        let chainwork1 = chain_state.get_chainwork(&hash1).unwrap().unwrap_or(0);
+        let chainwork1 = chain_state
+            .get_chainwork(&hash1)
+            .unwrap()
+            .unwrap_or_default();
 
         // Chainwork should be non-decreasing
         assert!(
@@ -153,7 +157,10 @@ mod kani_proofs {
         chain_state.update_tip(&hash2, &header2, 2).unwrap();
 
         // Get chainwork at height 2
-        let chainwork2 = chain_state.get_chainwork(&hash2).unwrap().unwrap_or(0);
+        let chainwork2 = chain_state
+            .get_chainwork(&hash2)
+            .unwrap()
+            .unwrap_or_default();
 
         // Chainwork should be non-decreasing
         assert!(
@@ -205,7 +212,7 @@ mod kani_proofs {
         let tip_header = create_bounded_header([0u8; 32], 0x1d00ffff);
         let height = kani::any::<u64>();
         kani::assume(height <= proof_limits::MAX_HEIGHT_FOR_PROOF);
-        let total_work = kani::any::<u64>();
+        let total_work = ChainWork::from_u64(kani::any::<u64>());
 
         let chain_info = ChainInfo {
             tip_hash,
@@ -245,10 +252,13 @@ mod kani_proofs {
         let genesis_chainwork = chain_state
             .get_chainwork(&genesis_hash)
             .unwrap()
-            .unwrap_or(0);
+            .unwrap_or_default();
 
         // Get work for genesis block
-        let genesis_work = chain_state.get_work(&genesis_hash).unwrap().unwrap_or(0);
+        let genesis_work = chain_state
+            .get_work(&genesis_hash)
+            .unwrap()
+            .unwrap_or_default();
 
         // Update to height 1
         let hash1: Hash = kani::any();
@@ -256,10 +266,13 @@ mod kani_proofs {
         chain_state.update_tip(&hash1, &header1, 1).unwrap();
 
         // Get work for block 1
-        let work1 = chain_state.get_work(&hash1).unwrap().unwrap_or(0);
+        let work1 = chain_state.get_work(&hash1).unwrap().unwrap_or_default();
 
         // Get chainwork at height 1
-        let chainwork1 = chain_state.get_chainwork(&hash1).unwrap().unwrap_or(0);
+        let chainwork1 = chain_state
+            .get_chainwork(&hash1)
+            .unwrap()
+            .unwrap_or_default();
 
         // Verify: chainwork[1] = chainwork[0] + work[1]
         // Note: chainwork[0] might be 0 or genesis_work depending on implementation
@@ -268,7 +281,7 @@ mod kani_proofs {
         // Work should be positive for valid blocks (bits > 0)
         if header1.bits > 0 {
             assert!(
-                work1 > 0 || genesis_work > 0,
+                !work1.is_zero() || !genesis_work.is_zero(),
                 "At least one block should have work"
             );
         }
@@ -0,0 +1,243 @@
+//! 256-bit proof-of-work amounts
+//!
+//! The work of a block is the expected number of hashes needed to find it,
+//! `2^256 / (target + 1)`, where the target is expanded from the header's
+//! compact `bits`. Cumulative chainwork on mainnet is far beyond `u128`, so
+//! work is kept as an unsigned 256-bit integer and stored as 32 big-endian
+//! bytes.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign};
+
+/// Unsigned 256-bit amount of proof-of-work
+///
+/// Limbs are little-endian: `limbs[0]` holds the least significant 64 bits.
+/// Addition saturates at `2^256 - 1`, which no real chain can reach.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChainWork {
+    limbs: [u64; 4],
+}
+
+impl ChainWork {
+    pub const ZERO: ChainWork = ChainWork { limbs: [0; 4] };
+    pub const MAX: ChainWork = ChainWork {
+        limbs: [u64::MAX; 4],
+    };
+
+    /// Work amount from a `u64`
+    pub const fn from_u64(value: u64) -> Self {
+        Self {
+            limbs: [value, 0, 0, 0],
+        }
+    }
+
+    /// Work amount from a `u128`
+    pub const fn from_u128(value: u128) -> Self {
+        Self {
+            limbs: [value as u64, (value >> 64) as u64, 0, 0],
+        }
+    }
+
+    /// Work needed for a block with compact target `bits`
+    ///
+    /// Computed as `!target / (target + 1) + 1`, which equals
+    /// `2^256 / (target + 1)` without needing 257 bits. Negative, zero and
+    /// overflowing targets are invalid and have no work.
+    pub fn from_bits(bits: u64) -> Self {
+        let target = match expand_compact(bits) {
+            Some(target) if !target.is_zero() => target,
+            _ => return Self::ZERO,
+        };
+        let (quotient, _) = target
+            .not()
+            .div_rem(&target.saturating_add(Self::from_u64(1)));
+        quotient.saturating_add(Self::from_u64(1))
+    }
+
+    /// Decode 32 big-endian bytes
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = 32 - 8 * (i + 1);
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&bytes[start..start + 8]);
+            *limb = u64::from_be_bytes(word);
+        }
+        Self { limbs }
+    }
+
+    /// Decode a big-endian value of up to 32 bytes
+    ///
+    /// Accepts the 8-byte work and 16-byte chainwork values written by older
+    /// versions. Returns `None` if `data` is longer than 32 bytes.
+    pub fn from_be_slice(data: &[u8]) -> Option<Self> {
+        if data.len() > 32 {
+            return None;
+        }
+        let mut bytes = [0u8; 32];
+        bytes[32 - data.len()..].copy_from_slice(data);
+        Some(Self::from_be_bytes(bytes))
+    }
+
+    /// Encode as 32 big-endian bytes
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.limbs.iter().enumerate() {
+            let start = 32 - 8 * (i + 1);
+            bytes[start..start + 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Value as `u128`, or `None` if it does not fit
+    pub fn to_u128(&self) -> Option<u128> {
+        if self.limbs[2] != 0 || self.limbs[3] != 0 {
+            return None;
+        }
+        Some(((self.limbs[1] as u128) << 64) | self.limbs[0] as u128)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs == [0; 4]
+    }
+
+    /// Add, returning `None` on overflow
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let mut limbs = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (sum, c1) = self.limbs[i].overflowing_add(other.limbs[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+        if carry {
+            None
+        } else {
+            Some(Self { limbs })
+        }
+    }
+
+    /// Add, clamping at [`Self::MAX`]
+    pub fn saturating_add(self, other: Self) -> Self {
+        self.checked_add(other).unwrap_or(Self::MAX)
+    }
+
+    /// Subtract, returning `None` if `other` is larger
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        if self < other {
+            return None;
+        }
+        let mut limbs = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (diff, b1) = self.limbs[i].overflowing_sub(other.limbs[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = b1 || b2;
+        }
+        Some(Self { limbs })
+    }
+
+    fn not(self) -> Self {
+        Self {
+            limbs: self.limbs.map(|limb| !limb),
+        }
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        (self.limbs[index / 64] >> (index % 64)) & 1 == 1
+    }
+
+    fn shl1(self) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = self.limbs[i] << 1;
+            if i > 0 {
+                *limb |= self.limbs[i - 1] >> 63;
+            }
+        }
+        Self { limbs }
+    }
+
+    fn shl(self, bits: u32) -> Self {
+        (0..bits).fold(self, |value, _| value.shl1())
+    }
+
+    /// Binary long division; `divisor` must be non-zero
+    fn div_rem(&self, divisor: &Self) -> (Self, Self) {
+        let mut quotient = Self::ZERO;
+        let mut remainder = Self::ZERO;
+        for index in (0..256).rev() {
+            remainder = remainder.shl1();
+            if self.bit(index) {
+                remainder.limbs[0] |= 1;
+            }
+            if remainder >= *divisor {
+                remainder = remainder
+                    .checked_sub(*divisor)
+                    .expect("remainder is at least the divisor");
+                quotient.limbs[index / 64] |= 1 << (index % 64);
+            }
+        }
+        (quotient, remainder)
+    }
+}
+
+/// Expand a compact target, or `None` if it is negative or overflows 256 bits
+fn expand_compact(bits: u64) -> Option<ChainWork> {
+    let size = ((bits >> 24) & 0xff) as u32;
+    let word = bits & 0x007f_ffff;
+    if word != 0 && bits & 0x0080_0000 != 0 {
+        return None;
+    }
+    if word != 0 && (size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32)) {
+        return None;
+    }
+    if size <= 3 {
+        Some(ChainWork::from_u64(word >> (8 * (3 - size))))
+    } else {
+        Some(ChainWork::from_u64(word).shl(8 * (size - 3)))
+    }
+}
+
+impl Ord for ChainWork {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs.iter().rev().cmp(other.limbs.iter().rev())
+    }
+}
+
+impl PartialOrd for ChainWork {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for ChainWork {
+    type Output = ChainWork;
+
+    fn add(self, other: Self) -> Self {
+        self.saturating_add(other)
+    }
+}
+
+impl AddAssign for ChainWork {
+    fn add_assign(&mut self, other: Self) {
+        *self = self.saturating_add(other);
+    }
+}
+
+impl std::iter::Sum for ChainWork {
+    fn sum<I: Iterator<Item = ChainWork>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, |total, work| total + work)
+    }
+}
+
+/// Formats as 64 lowercase hex digits, as in `getblockchaininfo.chainwork`
+impl fmt::Display for ChainWork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.to_be_bytes()))
+    }
+}
@@ -0,0 +1,123 @@
+//! UTXO set statistics index
+//!
+//! Keeps running totals of the UTXO set (output count, amount, bogosize) at
+//! every block of the active chain, keyed by block hash, so gettxoutsetinfo can
+//! answer for the tip or any earlier block without scanning the UTXO set. Each
+//! block's totals are its parent's plus the outputs it creates, minus the
+//! outputs it spends (from the block's undo data).
+
+use crate::storage::blockstore::BlockUndo;
+use crate::storage::database::{Database, Tree, WriteBatch};
+use crate::storage::indexer::BackgroundIndex;
+use anyhow::{anyhow, Result};
+use bllvm_protocol::{Block, Hash};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const COIN_STATS_TREE: &str = "coin_stats";
+
+/// Fixed part of a coin's bogosize (outpoint, height, amount, script length)
+const COIN_BOGOSIZE_BASE: u64 = 50;
+
+/// UTXO set totals after a block
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoinStats {
+    pub height: u64,
+    pub txouts: u64,
+    /// Total in satoshis
+    pub total_amount: u128,
+    /// Rough serialized size of the set, as computed by Bitcoin Core
+    pub bogosize: u64,
+}
+
+/// Bogosize of a single coin
+fn coin_bogosize(script_pubkey: &[u8]) -> u64 {
+    COIN_BOGOSIZE_BASE + script_pubkey.len() as u64
+}
+
+/// UTXO set statistics index
+pub struct CoinStatsIndex {
+    stats: Arc<dyn Tree>,
+}
+
+impl CoinStatsIndex {
+    /// Open the UTXO set statistics index
+    pub fn new(db: Arc<dyn Database>) -> Result<Self> {
+        Ok(Self {
+            stats: Arc::from(db.open_tree(COIN_STATS_TREE)?),
+        })
+    }
+
+    /// Get the UTXO set totals after a block
+    pub fn get(&self, block_hash: &Hash) -> Result<Option<CoinStats>> {
+        match self.stats.get(block_hash)? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl BackgroundIndex for CoinStatsIndex {
+    fn name(&self) -> &'static str {
+        "coinstatsindex"
+    }
+
+    fn requires_undo(&self) -> bool {
+        true
+    }
+
+    fn stage_connect(
+        &self,
+        batch: &mut WriteBatch,
+        block: &Block,
+        block_hash: &Hash,
+        height: u64,
+        undo: &BlockUndo,
+    ) -> Result<()> {
+        let mut stats = if height == 0 {
+            CoinStats::default()
+        } else {
+            self.get(&block.header.prev_block_hash)?.ok_or_else(|| {
+                anyhow!(
+                    "No UTXO statistics for parent {} of block {}",
+                    hex::encode(block.header.prev_block_hash),
+                    hex::encode(block_hash)
+                )
+            })?
+        };
+        stats.height = height;
+
+        for tx in block.transactions.iter() {
+            for output in tx.outputs.iter() {
+                stats.txouts += 1;
+                stats.total_amount += output.value as u128;
+                stats.bogosize += coin_bogosize(&output.script_pubkey);
+            }
+        }
+        for spent in undo.spent_outputs.iter() {
+            stats.txouts = stats.txouts.saturating_sub(1);
+            stats.total_amount = stats.total_amount.saturating_sub(spent.utxo.value as u128);
+            stats.bogosize = stats
+                .bogosize
+                .saturating_sub(coin_bogosize(&spent.utxo.script_pubkey));
+        }
+
+        batch.insert(COIN_STATS_TREE, block_hash, &bincode::serialize(&stats)?);
+        Ok(())
+    }
+
+    fn stage_disconnect(
+        &self,
+        batch: &mut WriteBatch,
+        _block: &Block,
+        block_hash: &Hash,
+        _height: u64,
+    ) -> Result<()> {
+        batch.remove(COIN_STATS_TREE, block_hash);
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.stats.clear()
+    }
+}
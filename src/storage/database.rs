@@ -16,6 +16,112 @@ pub trait Database: Send + Sync {
 
     /// Flush all pending writes
     fn flush(&self) -> Result<()>;
+
+    /// Apply a batch of writes across any number of trees atomically
+    ///
+    /// Either every operation in the batch becomes visible or none does, so a
+    /// crash mid-commit cannot leave the trees disagreeing with each other.
+    fn apply_batch(&self, batch: &WriteBatch) -> Result<()>;
+}
+
+/// A single write staged in a [`WriteBatch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp {
+    Insert {
+        tree: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Remove {
+        tree: String,
+        key: Vec<u8>,
+    },
+}
+
+impl BatchOp {
+    /// Name of the tree this operation writes to
+    pub fn tree(&self) -> &str {
+        match self {
+            BatchOp::Insert { tree, .. } | BatchOp::Remove { tree, .. } => tree,
+        }
+    }
+}
+
+/// Cross-tree write batch
+///
+/// Operations are applied in the order they were staged, so a later insert or
+/// remove of the same key wins. Commit with [`Database::apply_batch`].
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    /// Create an empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stage an insert into a named tree
+    pub fn insert(&mut self, tree: &str, key: &[u8], value: &[u8]) {
+        self.ops.push(BatchOp::Insert {
+            tree: tree.to_string(),
+            key: key.to_vec(),
+            value: value.to_vec(),
+        });
+    }
+
+    /// Stage a removal from a named tree
+    pub fn remove(&mut self, tree: &str, key: &[u8]) {
+        self.ops.push(BatchOp::Remove {
+            tree: tree.to_string(),
+            key: key.to_vec(),
+        });
+    }
+
+    /// Append all operations from another batch
+    pub fn extend(&mut self, other: WriteBatch) {
+        self.ops.extend(other.ops);
+    }
+
+    /// Staged operations, in commit order
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    /// Sorted, de-duplicated names of every tree the batch touches
+    pub fn tree_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.ops.iter().map(|op| op.tree()).collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Tree names from [`WriteBatch::tree_names`] together with, for each
+    /// staged operation in order, the position of its tree in that list
+    pub(crate) fn tree_positions(&self) -> Result<(Vec<&str>, Vec<usize>)> {
+        let names = self.tree_names();
+        let positions = self
+            .ops
+            .iter()
+            .map(|op| {
+                names
+                    .binary_search(&op.tree())
+                    .map_err(|_| anyhow::anyhow!("Batch operation on untracked tree {}", op.tree()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((names, positions))
+    }
+
+    /// Number of staged operations
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Check if nothing has been staged
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
 }
 
 /// Tree/Table abstraction trait
@@ -131,9 +237,10 @@ pub fn fallback_backend(primary: DatabaseBackend) -> Option<DatabaseBackend> {
 // Sled implementation
 #[cfg(feature = "sled")]
 mod sled_impl {
-    use super::{Database, Tree};
+    use super::{BatchOp, Database, Tree, WriteBatch};
     use anyhow::Result;
-    use sled::Db;
+    use sled::transaction::{ConflictableTransactionError, TransactionError};
+    use sled::{Db, Transactional};
     use std::path::Path;
     use std::sync::Arc;
 
@@ -160,6 +267,39 @@ mod sled_impl {
             self.db.flush()?;
             Ok(())
         }
+
+        fn apply_batch(&self, batch: &WriteBatch) -> Result<()> {
+            if batch.is_empty() {
+                return Ok(());
+            }
+
+            // Multi-tree transaction: sled commits all trees together or not at all
+            let (names, positions) = batch.tree_positions()?;
+            let trees = names
+                .iter()
+                .map(|name| self.db.open_tree(name))
+                .collect::<sled::Result<Vec<_>>>()?;
+
+            trees
+                .as_slice()
+                .transaction(|tx_trees| {
+                    for (op, &idx) in batch.ops().iter().zip(&positions) {
+                        match op {
+                            BatchOp::Insert { key, value, .. } => {
+                                tx_trees[idx].insert(key.as_slice(), value.as_slice())?;
+                            }
+                            BatchOp::Remove { key, .. } => {
+                                tx_trees[idx].remove(key.as_slice())?;
+                            }
+                        }
+                    }
+                    Ok::<(), ConflictableTransactionError<()>>(())
+                })
+                .map_err(|e: TransactionError<()>| {
+                    anyhow::anyhow!("Sled batch transaction failed: {:?}", e)
+                })?;
+            Ok(())
+        }
     }
 
     struct SledTree {
@@ -206,9 +346,10 @@ mod sled_impl {
 // Redb implementation
 #[cfg(feature = "redb")]
 mod redb_impl {
-    use super::{Database, Tree};
+    use super::{BatchOp, Database, Tree, WriteBatch};
     use anyhow::Result;
     use redb::{Database as RedbDb, ReadableTable, TableDefinition};
+    use std::ops::Bound;
     use std::path::Path;
     use std::sync::Arc;
 
@@ -223,6 +364,12 @@ mod redb_impl {
     static RECENT_HEADERS_TABLE: TableDefinition<&[u8], &[u8]> =
         TableDefinition::new("recent_headers");
     static UTXOS_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("utxos");
+    static UTXO_META_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("utxo_meta");
+    static SNAPSHOT_UTXOS_TABLE: TableDefinition<&[u8], &[u8]> =
+        TableDefinition::new("snapshot_utxos");
+    static SNAPSHOT_UTXO_META_TABLE: TableDefinition<&[u8], &[u8]> =
+        TableDefinition::new("snapshot_utxo_meta");
+    static BLOCK_INDEX_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("block_index");
     static SPENT_OUTPUTS_TABLE: TableDefinition<&[u8], &[u8]> =
         TableDefinition::new("spent_outputs");
     static CHAIN_INFO_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("chain_info");
@@ -242,6 +389,19 @@ mod redb_impl {
     static CHAIN_TIPS_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("chain_tips");
     static BLOCK_METADATA_TABLE: TableDefinition<&[u8], &[u8]> =
         TableDefinition::new("block_metadata");
+    static BLOCK_UNDO_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("block_undo");
+    static BLOCK_POSITIONS_TABLE: TableDefinition<&[u8], &[u8]> =
+        TableDefinition::new("block_positions");
+    static UNDO_POSITIONS_TABLE: TableDefinition<&[u8], &[u8]> =
+        TableDefinition::new("undo_positions");
+    static BLOCK_FILES_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("block_files");
+    static BLOCK_FILE_RECORDS_TABLE: TableDefinition<&[u8], &[u8]> =
+        TableDefinition::new("block_file_records");
+    static BLOCK_IMPORT_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("block_import");
+    static INDEX_STATE_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("index_state");
+    static BLOCK_FILTERS_TABLE: TableDefinition<&[u8], &[u8]> =
+        TableDefinition::new("block_filters");
+    static COIN_STATS_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("coin_stats");
     static CHAINWORK_CACHE_TABLE: TableDefinition<&[u8], &[u8]> =
         TableDefinition::new("chainwork_cache");
     static UTXO_STATS_CACHE_TABLE: TableDefinition<&[u8], &[u8]> =
@@ -285,6 +445,10 @@ mod redb_impl {
                             let _ = write_txn.open_table(WITNESSES_TABLE)?;
                             let _ = write_txn.open_table(RECENT_HEADERS_TABLE)?;
                             let _ = write_txn.open_table(UTXOS_TABLE)?;
+                            let _ = write_txn.open_table(UTXO_META_TABLE)?;
+                            let _ = write_txn.open_table(SNAPSHOT_UTXOS_TABLE)?;
+                            let _ = write_txn.open_table(SNAPSHOT_UTXO_META_TABLE)?;
+                            let _ = write_txn.open_table(BLOCK_INDEX_TABLE)?;
                             let _ = write_txn.open_table(SPENT_OUTPUTS_TABLE)?;
                             let _ = write_txn.open_table(CHAIN_INFO_TABLE)?;
                             let _ = write_txn.open_table(WORK_CACHE_TABLE)?;
@@ -302,6 +466,15 @@ mod redb_impl {
                             let _ = write_txn.open_table(INVALID_BLOCKS_TABLE)?;
                             let _ = write_txn.open_table(CHAIN_TIPS_TABLE)?;
                             let _ = write_txn.open_table(BLOCK_METADATA_TABLE)?;
+                            let _ = write_txn.open_table(BLOCK_UNDO_TABLE)?;
+                            let _ = write_txn.open_table(BLOCK_POSITIONS_TABLE)?;
+                            let _ = write_txn.open_table(UNDO_POSITIONS_TABLE)?;
+                            let _ = write_txn.open_table(BLOCK_FILES_TABLE)?;
+                            let _ = write_txn.open_table(BLOCK_FILE_RECORDS_TABLE)?;
+                            let _ = write_txn.open_table(BLOCK_IMPORT_TABLE)?;
+                            let _ = write_txn.open_table(INDEX_STATE_TABLE)?;
+                            let _ = write_txn.open_table(BLOCK_FILTERS_TABLE)?;
+                            let _ = write_txn.open_table(COIN_STATS_TABLE)?;
                             let _ = write_txn.open_table(CHAINWORK_CACHE_TABLE)?;
                             let _ = write_txn.open_table(UTXO_STATS_CACHE_TABLE)?;
                             let _ = write_txn.open_table(NETWORK_HASHRATE_CACHE_TABLE)?;
@@ -336,6 +509,10 @@ mod redb_impl {
                 let _ = write_txn.open_table(WITNESSES_TABLE)?;
                 let _ = write_txn.open_table(RECENT_HEADERS_TABLE)?;
                 let _ = write_txn.open_table(UTXOS_TABLE)?;
+                let _ = write_txn.open_table(UTXO_META_TABLE)?;
+                let _ = write_txn.open_table(SNAPSHOT_UTXOS_TABLE)?;
+                let _ = write_txn.open_table(SNAPSHOT_UTXO_META_TABLE)?;
+                let _ = write_txn.open_table(BLOCK_INDEX_TABLE)?;
                 let _ = write_txn.open_table(SPENT_OUTPUTS_TABLE)?;
                 let _ = write_txn.open_table(CHAIN_INFO_TABLE)?;
                 let _ = write_txn.open_table(WORK_CACHE_TABLE)?;
@@ -349,6 +526,15 @@ mod redb_impl {
                 let _ = write_txn.open_table(INVALID_BLOCKS_TABLE)?;
                 let _ = write_txn.open_table(CHAIN_TIPS_TABLE)?;
                 let _ = write_txn.open_table(BLOCK_METADATA_TABLE)?;
+                let _ = write_txn.open_table(BLOCK_UNDO_TABLE)?;
+                let _ = write_txn.open_table(BLOCK_POSITIONS_TABLE)?;
+                let _ = write_txn.open_table(UNDO_POSITIONS_TABLE)?;
+                let _ = write_txn.open_table(BLOCK_FILES_TABLE)?;
+                let _ = write_txn.open_table(BLOCK_FILE_RECORDS_TABLE)?;
+                let _ = write_txn.open_table(BLOCK_IMPORT_TABLE)?;
+                let _ = write_txn.open_table(INDEX_STATE_TABLE)?;
+                let _ = write_txn.open_table(BLOCK_FILTERS_TABLE)?;
+                let _ = write_txn.open_table(COIN_STATS_TABLE)?;
                 let _ = write_txn.open_table(CHAINWORK_CACHE_TABLE)?;
                 let _ = write_txn.open_table(UTXO_STATS_CACHE_TABLE)?;
                 let _ = write_txn.open_table(NETWORK_HASHRATE_CACHE_TABLE)?;
@@ -376,6 +562,10 @@ mod redb_impl {
                 "witnesses" => Some(&WITNESSES_TABLE),
                 "recent_headers" => Some(&RECENT_HEADERS_TABLE),
                 "utxos" => Some(&UTXOS_TABLE),
+                "utxo_meta" => Some(&UTXO_META_TABLE),
+                "snapshot_utxos" => Some(&SNAPSHOT_UTXOS_TABLE),
+                "snapshot_utxo_meta" => Some(&SNAPSHOT_UTXO_META_TABLE),
+                "block_index" => Some(&BLOCK_INDEX_TABLE),
                 "spent_outputs" => Some(&SPENT_OUTPUTS_TABLE),
                 "chain_info" => Some(&CHAIN_INFO_TABLE),
                 "work_cache" => Some(&WORK_CACHE_TABLE),
@@ -389,6 +579,15 @@ mod redb_impl {
                 "invalid_blocks" => Some(&INVALID_BLOCKS_TABLE),
                 "chain_tips" => Some(&CHAIN_TIPS_TABLE),
                 "block_metadata" => Some(&BLOCK_METADATA_TABLE),
+                "block_undo" => Some(&BLOCK_UNDO_TABLE),
+                "block_positions" => Some(&BLOCK_POSITIONS_TABLE),
+                "undo_positions" => Some(&UNDO_POSITIONS_TABLE),
+                "block_files" => Some(&BLOCK_FILES_TABLE),
+                "block_file_records" => Some(&BLOCK_FILE_RECORDS_TABLE),
+                "block_import" => Some(&BLOCK_IMPORT_TABLE),
+                "index_state" => Some(&INDEX_STATE_TABLE),
+                "block_filters" => Some(&BLOCK_FILTERS_TABLE),
+                "coin_stats" => Some(&COIN_STATS_TABLE),
                 "chainwork_cache" => Some(&CHAINWORK_CACHE_TABLE),
                 "utxo_stats_cache" => Some(&UTXO_STATS_CACHE_TABLE),
                 "network_hashrate_cache" => Some(&NETWORK_HASHRATE_CACHE_TABLE),
@@ -426,6 +625,41 @@ mod redb_impl {
             write_txn.commit()?;
             Ok(())
         }
+
+        fn apply_batch(&self, batch: &WriteBatch) -> Result<()> {
+            if batch.is_empty() {
+                return Ok(());
+            }
+
+            // One write transaction for the whole batch; dropping it on error aborts
+            let (names, positions) = batch.tree_positions()?;
+            let write_txn = self.db.begin_write()?;
+            {
+                // Each table is opened once and must be dropped before commit
+                let mut tables = Vec::with_capacity(names.len());
+                for name in &names {
+                    let table_def = self.get_table_def(name).ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Unknown table name: {}. Redb requires pre-defined tables.",
+                            name
+                        )
+                    })?;
+                    tables.push(write_txn.open_table(*table_def)?);
+                }
+                for (op, &idx) in batch.ops().iter().zip(&positions) {
+                    match op {
+                        BatchOp::Insert { key, value, .. } => {
+                            tables[idx].insert(key.as_slice(), value.as_slice())?;
+                        }
+                        BatchOp::Remove { key, .. } => {
+                            tables[idx].remove(key.as_slice())?;
+                        }
+                    }
+                }
+            }
+            write_txn.commit()?;
+            Ok(())
+        }
     }
 
     struct RedbTree {
@@ -470,11 +704,11 @@ mod redb_impl {
         }
 
         fn clear(&self) -> Result<()> {
-            // Redb doesn't have a direct clear() method
-            // Range from iter() doesn't implement Iterator as expected
-            // For now, we'll skip the clear implementation
-            // TODO: Implement proper Range iteration for clear()
-            // This can be implemented later when Range API is better understood
+            // Redb has no clear; drop the table and create it again
+            let write_txn = self.db.begin_write()?;
+            write_txn.delete_table(*self.table_def)?;
+            write_txn.open_table(*self.table_def)?;
+            write_txn.commit()?;
             Ok(())
         }
 
@@ -485,54 +719,72 @@ mod redb_impl {
         }
 
         fn iter(&self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_> {
-            // Redb iteration requires a read transaction
-            // We need to collect all items into a vector since the transaction must outlive the iterator
-            let read_txn = match self.db.begin_read() {
-                Ok(txn) => txn,
-                Err(e) => {
-                    return Box::new(std::iter::once(Err(anyhow::anyhow!(
-                        "Failed to begin read transaction: {}",
-                        e
-                    ))));
-                }
-            };
+            Box::new(RedbIter {
+                db: Arc::clone(&self.db),
+                table_def: self.table_def,
+                chunk: Vec::new().into_iter(),
+                last_key: None,
+                done: false,
+            })
+        }
+    }
 
-            let table = match read_txn.open_table(*self.table_def) {
-                Ok(tbl) => tbl,
-                Err(e) => {
-                    return Box::new(std::iter::once(Err(anyhow::anyhow!(
-                        "Failed to open table: {}",
-                        e
-                    ))));
-                }
-            };
+    /// Entries read per read transaction while iterating a table
+    const ITER_CHUNK_ENTRIES: usize = 4096;
 
-            // Collect all items into a vector
-            // Redb Range implements IntoIterator, but we need to collect into a vector
-            // because the read transaction must outlive the iterator
-            let mut items = Vec::new();
-            // Redb's range() returns a Result<Range, Error>
-            // Each iteration over the Range yields a Result<(Key, Value), Error>
-            // Use turbofish syntax to specify the type parameter for the range bounds
-            match table.range::<&[u8]>(..) {
-                Ok(range_iter) => {
-                    for item_result in range_iter {
-                        match item_result {
-                            Ok((key, value)) => {
-                                items.push(Ok((key.value().to_vec(), value.value().to_vec())));
-                            }
-                            Err(e) => {
-                                items.push(Err(anyhow::anyhow!("Redb iteration error: {}", e)));
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    items.push(Err(anyhow::anyhow!("Failed to create range: {}", e)));
-                }
+    /// Iterator over a table that reads it a chunk at a time
+    ///
+    /// A read transaction cannot be held by the iterator it serves, so each
+    /// chunk is read in its own transaction, resuming after the last key
+    /// returned. Only one chunk is held in memory at a time; entries written
+    /// while iterating may or may not be seen.
+    struct RedbIter {
+        db: Arc<RedbDb>,
+        table_def: &'static TableDefinition<'static, &'static [u8], &'static [u8]>,
+        chunk: std::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+        last_key: Option<Vec<u8>>,
+        done: bool,
+    }
+
+    impl RedbIter {
+        fn read_chunk(&mut self) -> Result<()> {
+            let read_txn = self.db.begin_read()?;
+            let table = read_txn.open_table(*self.table_def)?;
+            let last_key = self.last_key.take();
+            let start = match last_key {
+                Some(ref key) => Bound::Excluded(key.as_slice()),
+                None => Bound::Unbounded,
+            };
+            let mut entries = Vec::with_capacity(ITER_CHUNK_ENTRIES);
+            for item in table
+                .range::<&[u8]>((start, Bound::Unbounded))?
+                .take(ITER_CHUNK_ENTRIES)
+            {
+                let (key, value) = item?;
+                entries.push((key.value().to_vec(), value.value().to_vec()));
             }
+            self.done = entries.len() < ITER_CHUNK_ENTRIES;
+            self.last_key = entries.last().map(|(key, _)| key.clone());
+            self.chunk = entries.into_iter();
+            Ok(())
+        }
+    }
+
+    impl Iterator for RedbIter {
+        type Item = Result<(Vec<u8>, Vec<u8>)>;
 
-            Box::new(items.into_iter())
+        fn next(&mut self) -> Option<Self::Item> {
+            if let Some(entry) = self.chunk.next() {
+                return Some(Ok(entry));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.read_chunk() {
+                self.done = true;
+                return Some(Err(anyhow::anyhow!("Redb iteration error: {}", e)));
+            }
+            self.chunk.next().map(Ok)
         }
     }
 }
@@ -0,0 +1,125 @@
+//! BIP158 basic block filter index
+//!
+//! Stores the compact block filter and BIP157 filter header of every block on
+//! the active chain, keyed by block hash. Built from the block and its undo
+//! data, which supplies the scripts of the outputs the block spends.
+
+use crate::storage::blockstore::BlockUndo;
+use crate::storage::database::{Database, Tree, WriteBatch};
+use crate::storage::indexer::BackgroundIndex;
+use anyhow::{anyhow, Result};
+use bllvm_protocol::bip157::FilterHeader;
+use bllvm_protocol::bip158::build_block_filter;
+use bllvm_protocol::{Block, Hash};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const BLOCK_FILTERS_TREE: &str = "block_filters";
+
+/// A block's basic filter and its position in the filter header chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedFilter {
+    pub filter_data: Vec<u8>,
+    pub num_elements: u32,
+    /// Hash of the filter
+    pub filter_hash: Hash,
+    /// Filter header of the parent block (all zero for genesis)
+    pub prev_header_hash: Hash,
+}
+
+impl IndexedFilter {
+    /// BIP157 filter header of the block
+    pub fn header(&self) -> FilterHeader {
+        FilterHeader {
+            filter_hash: self.filter_hash,
+            prev_header_hash: self.prev_header_hash,
+        }
+    }
+}
+
+/// Basic block filter index
+pub struct BlockFilterIndex {
+    filters: Arc<dyn Tree>,
+}
+
+impl BlockFilterIndex {
+    /// Open the block filter index
+    pub fn new(db: Arc<dyn Database>) -> Result<Self> {
+        Ok(Self {
+            filters: Arc::from(db.open_tree(BLOCK_FILTERS_TREE)?),
+        })
+    }
+
+    /// Get the indexed filter for a block
+    pub fn get(&self, block_hash: &Hash) -> Result<Option<IndexedFilter>> {
+        match self.filters.get(block_hash)? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl BackgroundIndex for BlockFilterIndex {
+    fn name(&self) -> &'static str {
+        "basic block filter index"
+    }
+
+    fn requires_undo(&self) -> bool {
+        true
+    }
+
+    fn stage_connect(
+        &self,
+        batch: &mut WriteBatch,
+        block: &Block,
+        block_hash: &Hash,
+        height: u64,
+        undo: &BlockUndo,
+    ) -> Result<()> {
+        let prev_header = if height == 0 {
+            None
+        } else {
+            let parent = self.get(&block.header.prev_block_hash)?.ok_or_else(|| {
+                anyhow!(
+                    "No filter for parent {} of block {}",
+                    hex::encode(block.header.prev_block_hash),
+                    hex::encode(block_hash)
+                )
+            })?;
+            Some(parent.header())
+        };
+
+        let spent_scripts: Vec<Vec<u8>> = undo
+            .spent_outputs
+            .iter()
+            .map(|spent| spent.utxo.script_pubkey.clone())
+            .collect();
+        let filter = build_block_filter(&block.transactions, &spent_scripts)
+            .map_err(|e| anyhow!("Failed to build filter: {}", e))?;
+        let header = FilterHeader::new(&filter, prev_header.as_ref());
+
+        let entry = IndexedFilter {
+            filter_data: filter.filter_data,
+            num_elements: filter.num_elements,
+            filter_hash: header.filter_hash,
+            prev_header_hash: header.prev_header_hash,
+        };
+        batch.insert(BLOCK_FILTERS_TREE, block_hash, &bincode::serialize(&entry)?);
+        Ok(())
+    }
+
+    fn stage_disconnect(
+        &self,
+        batch: &mut WriteBatch,
+        _block: &Block,
+        block_hash: &Hash,
+        _height: u64,
+    ) -> Result<()> {
+        batch.remove(BLOCK_FILTERS_TREE, block_hash);
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.filters.clear()
+    }
+}
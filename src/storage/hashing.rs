@@ -3,7 +3,7 @@
 //! Implements proper Bitcoin double SHA256 hashing for all storage operations.
 //! This replaces the incorrect DefaultHasher usage throughout the storage layer.
 
-#[cfg(not(feature = "production"))]
+use bllvm_protocol::{OutPoint, UtxoSet, UTXO};
 use sha2::{Digest, Sha256};
 
 /// Calculate Bitcoin double SHA256 hash
@@ -76,6 +76,129 @@ pub fn hash160(data: &[u8]) -> [u8; 20] {
     ripemd160(&sha256_hash)
 }
 
+/// Serialization of the coins hashed by [`UtxoSetHasher`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UtxoHashFormat {
+    /// `hash_serialized_2` of `gettxoutsetinfo`: each coin's outpoint (hash,
+    /// 8-byte index), value, script and 8-byte height, little-endian
+    Serialized2,
+    /// Bitcoin Core's `hash_serialized_3`, the content hash of AssumeUTXO
+    /// snapshots: each coin's outpoint (hash, 4-byte index), `height * 2 +
+    /// coinbase` as 4 bytes, value and length-prefixed script. Coins Bitcoin
+    /// Core never adds to its UTXO set are skipped (unspendable scripts and
+    /// the genesis coinbase), so hashes match the ones it publishes.
+    Serialized3,
+}
+
+impl UtxoHashFormat {
+    /// Whether a coin is part of the hashed set
+    pub fn includes(&self, utxo: &UTXO) -> bool {
+        match self {
+            UtxoHashFormat::Serialized2 => true,
+            UtxoHashFormat::Serialized3 => {
+                let script = &utxo.script_pubkey;
+                utxo.height > 0
+                    && script.first() != Some(&OP_RETURN)
+                    && script.len() <= MAX_SCRIPT_SIZE
+            }
+        }
+    }
+}
+
+/// OP_RETURN, which makes a script unspendable
+const OP_RETURN: u8 = 0x6a;
+
+/// Maximum script length; longer scripts are unspendable
+const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// Double SHA256 of a UTXO set, built up one coin at a time
+///
+/// Coins must be added in outpoint order (hash, then index), so a set can be
+/// hashed while it is read from storage.
+pub struct UtxoSetHasher {
+    format: UtxoHashFormat,
+    hasher: Sha256,
+}
+
+impl UtxoSetHasher {
+    pub fn new(format: UtxoHashFormat) -> Self {
+        Self {
+            format,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Add the next coin
+    ///
+    /// Coins the format leaves out are ignored.
+    pub fn add(&mut self, outpoint: &OutPoint, utxo: &UTXO) {
+        if !self.format.includes(utxo) {
+            return;
+        }
+        match self.format {
+            UtxoHashFormat::Serialized2 => {
+                self.hasher.update(outpoint.hash);
+                self.hasher.update(outpoint.index.to_le_bytes());
+                self.hasher.update(utxo.value.to_le_bytes());
+                self.hasher.update(&utxo.script_pubkey);
+                self.hasher.update(utxo.height.to_le_bytes());
+            }
+            UtxoHashFormat::Serialized3 => {
+                let script = &utxo.script_pubkey;
+                self.hasher.update(outpoint.hash);
+                self.hasher.update((outpoint.index as u32).to_le_bytes());
+                let code = ((utxo.height as u32) << 1) | utxo.is_coinbase as u32;
+                self.hasher.update(code.to_le_bytes());
+                self.hasher.update(utxo.value.to_le_bytes());
+                self.hasher.update(compact_size(script.len() as u64));
+                self.hasher.update(script);
+            }
+        }
+    }
+
+    pub fn finish(self) -> [u8; 32] {
+        let mut result = [0u8; 32];
+        result.copy_from_slice(&Sha256::digest(self.hasher.finalize()));
+        result
+    }
+}
+
+/// Bitcoin's variable-length integer encoding
+fn compact_size(value: u64) -> Vec<u8> {
+    match value {
+        0..=0xfc => vec![value as u8],
+        0xfd..=0xffff => [&[0xfd][..], &(value as u16).to_le_bytes()].concat(),
+        0x10000..=0xffff_ffff => [&[0xfe][..], &(value as u32).to_le_bytes()].concat(),
+        _ => [&[0xff][..], &value.to_le_bytes()].concat(),
+    }
+}
+
+/// Hash a UTXO set held in memory, sorting it by outpoint first
+fn hash_utxo_set(utxo_set: &UtxoSet, format: UtxoHashFormat) -> [u8; 32] {
+    let mut entries: Vec<_> = utxo_set.iter().collect();
+    entries.sort_by(|(a, _), (b, _)| (a.hash, a.index).cmp(&(b.hash, b.index)));
+    let mut hasher = UtxoSetHasher::new(format);
+    for (outpoint, utxo) in entries {
+        hasher.add(outpoint, utxo);
+    }
+    hasher.finish()
+}
+
+/// Calculate hash_serialized_2 for a UTXO set (double SHA256)
+///
+/// The `gettxoutsetinfo` hash; see [`UtxoHashFormat::Serialized2`].
+pub fn calculate_utxo_set_hash(utxo_set: &UtxoSet) -> [u8; 32] {
+    hash_utxo_set(utxo_set, UtxoHashFormat::Serialized2)
+}
+
+/// Calculate hash_serialized_3 for a UTXO set (double SHA256)
+///
+/// The content hash of AssumeUTXO snapshots; see
+/// [`UtxoHashFormat::Serialized3`].
+pub fn calculate_snapshot_hash(utxo_set: &UtxoSet) -> [u8; 32] {
+    hash_utxo_set(utxo_set, UtxoHashFormat::Serialized3)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -134,6 +257,68 @@ mod tests {
         assert_ne!(result, ripemd_hash);
     }
 
+    fn coin(height: u64, script_pubkey: Vec<u8>) -> UTXO {
+        UTXO {
+            value: 50_0000_0000,
+            script_pubkey,
+            height,
+            is_coinbase: true,
+        }
+    }
+
+    #[test]
+    fn test_utxo_set_hash_formats() {
+        let mut utxo_set = UtxoSet::new();
+        let outpoint = OutPoint {
+            hash: [1u8; 32],
+            index: 0,
+        };
+        utxo_set.insert(outpoint, coin(1, vec![0x51]));
+        assert_eq!(
+            hex::encode(calculate_utxo_set_hash(&utxo_set)),
+            "d73153f491028d5e8be38234fec856228e53735f6e28ba54055a658de1319c5b"
+        );
+        assert_eq!(
+            hex::encode(calculate_snapshot_hash(&utxo_set)),
+            "4e3aab553cba164b0f09a71aeb3e6f4631fb09eb0aa3ee1c9e44b1ad6e807b67"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_hash_skips_coins_core_leaves_out() {
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.insert(
+            OutPoint {
+                hash: [1u8; 32],
+                index: 0,
+            },
+            coin(1, vec![0x51]),
+        );
+        let snapshot_hash = calculate_snapshot_hash(&utxo_set);
+        let utxo_set_hash = calculate_utxo_set_hash(&utxo_set);
+
+        // Genesis coinbase, OP_RETURN output and oversized script
+        for (index, utxo) in [
+            coin(0, vec![0x51]),
+            coin(1, vec![0x6a, 0x01, 0x00]),
+            coin(1, vec![0x51; 10_001]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert!(!UtxoHashFormat::Serialized3.includes(&utxo));
+            utxo_set.insert(
+                OutPoint {
+                    hash: [2u8; 32],
+                    index: index as u64,
+                },
+                utxo,
+            );
+        }
+        assert_eq!(calculate_snapshot_hash(&utxo_set), snapshot_hash);
+        assert_ne!(calculate_utxo_set_hash(&utxo_set), utxo_set_hash);
+    }
+
     #[test]
     fn test_hash_deterministic() {
         let data = b"deterministic test";
@@ -0,0 +1,365 @@
+//! Background block indexes
+//!
+//! Optional indexes derived from the active chain (transactions, addresses,
+//! BIP158 filters, UTXO set statistics) implement [`BackgroundIndex`] and are
+//! run by an [`IndexManager`]. Each index records the block it is synced to,
+//! its best-block locator, in the `index_state` tree in the same batch as its
+//! entries, so the two never disagree after a crash.
+//!
+//! An index that is in sync is updated in the same batch as every block
+//! connect and disconnect. One that falls behind (enabled on an existing
+//! chain, a failed update, or `background_indexing` set) is caught up from the
+//! stored blocks by [`Storage::sync_indexes`], which first rolls back any
+//! blocks it indexed that have since left the active chain.
+
+use super::Storage;
+use crate::storage::blockstore::BlockUndo;
+use crate::storage::database::{Database, Tree, WriteBatch};
+use anyhow::{anyhow, Result};
+use bllvm_protocol::{Block, Hash};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::{debug, info, warn};
+
+const INDEX_STATE_TREE: &str = "index_state";
+
+/// Blocks indexed between progress reports while catching up
+const PROGRESS_INTERVAL: u64 = 1000;
+
+/// An index built from the blocks of the active chain
+pub trait BackgroundIndex: Send + Sync {
+    /// Name of the index, as reported by getindexinfo
+    fn name(&self) -> &'static str;
+
+    /// Whether [`Self::stage_connect`] needs the block's undo data
+    fn requires_undo(&self) -> bool {
+        false
+    }
+
+    /// Stage the entries for a block connected at `height`
+    ///
+    /// The index is synced to the block's parent. `undo` is empty unless
+    /// [`Self::requires_undo`] is set.
+    fn stage_connect(
+        &self,
+        batch: &mut WriteBatch,
+        block: &Block,
+        block_hash: &Hash,
+        height: u64,
+        undo: &BlockUndo,
+    ) -> Result<()>;
+
+    /// Stage removal of the entries for the block the index is synced to
+    fn stage_disconnect(
+        &self,
+        batch: &mut WriteBatch,
+        block: &Block,
+        block_hash: &Hash,
+        height: u64,
+    ) -> Result<()>;
+
+    /// Remove all entries
+    fn reset(&self) -> Result<()>;
+}
+
+/// The block an index is synced to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexLocator {
+    pub block_hash: Hash,
+    pub height: u64,
+}
+
+/// Sync status of an index, as reported by getindexinfo
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexInfo {
+    pub name: &'static str,
+    /// Whether the index is at the active chain tip
+    pub synced: bool,
+    /// Height of the last indexed block
+    pub best_block_height: Option<u64>,
+}
+
+/// The enabled background indexes and their locators
+pub struct IndexManager {
+    indexes: Vec<Arc<dyn BackgroundIndex>>,
+    state: Arc<dyn Tree>,
+    /// Update in-sync indexes with each block connect and disconnect
+    inline: bool,
+    /// Held while an index update is staged and committed
+    lock: Mutex<()>,
+}
+
+impl IndexManager {
+    /// Create a manager for `indexes`
+    ///
+    /// With `inline` unset, indexes are only updated by
+    /// [`Storage::sync_indexes`].
+    pub fn new(
+        db: Arc<dyn Database>,
+        indexes: Vec<Arc<dyn BackgroundIndex>>,
+        inline: bool,
+    ) -> Result<Self> {
+        Ok(Self {
+            indexes,
+            state: Arc::from(db.open_tree(INDEX_STATE_TREE)?),
+            inline,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// The enabled indexes
+    pub fn indexes(&self) -> &[Arc<dyn BackgroundIndex>] {
+        &self.indexes
+    }
+
+    /// The block an index is synced to, or `None` if it is empty
+    pub fn locator(&self, name: &str) -> Result<Option<IndexLocator>> {
+        match self.state.get(name.as_bytes())? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn stage_locator(
+        &self,
+        batch: &mut WriteBatch,
+        name: &str,
+        locator: Option<IndexLocator>,
+    ) -> Result<()> {
+        match locator {
+            Some(locator) => {
+                batch.insert(
+                    INDEX_STATE_TREE,
+                    name.as_bytes(),
+                    &bincode::serialize(&locator)?,
+                );
+            }
+            None => batch.remove(INDEX_STATE_TREE, name.as_bytes()),
+        }
+        Ok(())
+    }
+
+    /// Serialize index updates; hold the guard until the batch is committed
+    pub(crate) fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stage a newly connected block into every index synced to its parent
+    ///
+    /// An index that fails to stage the block is left out of the batch and
+    /// caught up later.
+    pub fn stage_connect(
+        &self,
+        batch: &mut WriteBatch,
+        block: &Block,
+        block_hash: &Hash,
+        height: u64,
+        undo: &BlockUndo,
+    ) -> Result<()> {
+        if !self.inline {
+            return Ok(());
+        }
+        let parent = (height > 0).then_some(block.header.prev_block_hash);
+        for index in &self.indexes {
+            if self.locator(index.name())?.map(|l| l.block_hash) != parent {
+                continue;
+            }
+            let mut staged = WriteBatch::new();
+            match index.stage_connect(&mut staged, block, block_hash, height, undo) {
+                Ok(()) => {
+                    batch.extend(staged);
+                    self.stage_locator(
+                        batch,
+                        index.name(),
+                        Some(IndexLocator {
+                            block_hash: *block_hash,
+                            height,
+                        }),
+                    )?;
+                }
+                Err(e) => warn!(
+                    "{} fell behind at block {}: {}",
+                    index.name(),
+                    hex::encode(block_hash),
+                    e
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Stage a disconnected block out of every index synced to it
+    pub fn stage_disconnect(
+        &self,
+        batch: &mut WriteBatch,
+        block: &Block,
+        block_hash: &Hash,
+        height: u64,
+    ) -> Result<()> {
+        if !self.inline {
+            return Ok(());
+        }
+        for index in &self.indexes {
+            if self.locator(index.name())?.map(|l| l.block_hash) != Some(*block_hash) {
+                continue;
+            }
+            let mut staged = WriteBatch::new();
+            match index.stage_disconnect(&mut staged, block, block_hash, height) {
+                Ok(()) => {
+                    batch.extend(staged);
+                    let parent = (height > 0).then(|| IndexLocator {
+                        block_hash: block.header.prev_block_hash,
+                        height: height - 1,
+                    });
+                    self.stage_locator(batch, index.name(), parent)?;
+                }
+                Err(e) => warn!(
+                    "{} could not disconnect block {}: {}",
+                    index.name(),
+                    hex::encode(block_hash),
+                    e
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Empty every index
+    pub fn reset(&self) -> Result<()> {
+        let _guard = self.lock();
+        for index in &self.indexes {
+            index.reset()?;
+            self.state.remove(index.name().as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl Storage {
+    /// Bring every index up to the active chain tip
+    ///
+    /// Rolls back blocks an index holds that are no longer on the active chain,
+    /// then indexes the stored blocks above its locator. An index waits when
+    /// the next block's body (or undo data, if it needs it) is not stored, as
+    /// below a loaded snapshot or on a pruned node. Returns the number of blocks
+    /// indexed or rolled back; if an index fails the others are still synced
+    /// and the failure is returned.
+    pub fn sync_indexes(&self) -> Result<u64> {
+        let mut steps = 0;
+        let mut failed = Vec::new();
+        for index in self.indexes.indexes() {
+            match self.sync_index(index.as_ref()) {
+                Ok(n) => steps += n,
+                Err(e) => failed.push(format!("{}: {}", index.name(), e)),
+            }
+        }
+        if failed.is_empty() {
+            Ok(steps)
+        } else {
+            Err(anyhow!("Failed to sync indexes: {}", failed.join("; ")))
+        }
+    }
+
+    /// Sync status of every index
+    pub fn index_info(&self) -> Result<Vec<IndexInfo>> {
+        let tip_hash = self.chainstate.get_tip_hash()?;
+        let mut infos = Vec::new();
+        for index in self.indexes.indexes() {
+            let locator = self.indexes.locator(index.name())?;
+            infos.push(IndexInfo {
+                name: index.name(),
+                synced: locator.map(|l| l.block_hash) == tip_hash,
+                best_block_height: locator.map(|l| l.height),
+            });
+        }
+        Ok(infos)
+    }
+
+    fn sync_index(&self, index: &dyn BackgroundIndex) -> Result<u64> {
+        let mut steps = 0;
+        while self.sync_index_step(index)? {
+            steps += 1;
+            if steps % PROGRESS_INTERVAL == 0 {
+                info!("{}: {} blocks synced", index.name(), steps);
+            }
+        }
+        Ok(steps)
+    }
+
+    /// Roll back or index one block; `false` once the index cannot advance
+    fn sync_index_step(&self, index: &dyn BackgroundIndex) -> Result<bool> {
+        let _guard = self.indexes.lock();
+        let tip_height = self.chainstate.get_height()?;
+        let locator = self.indexes.locator(index.name())?;
+
+        if let Some(locator) = locator {
+            let on_chain = tip_height.is_some_and(|tip| locator.height <= tip)
+                && self.blockstore.get_hash_by_height(locator.height)? == Some(locator.block_hash);
+            if !on_chain {
+                let block = self
+                    .blockstore
+                    .get_block(&locator.block_hash)?
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "cannot roll back block {}: body not stored",
+                            hex::encode(locator.block_hash)
+                        )
+                    })?;
+                let mut batch = WriteBatch::new();
+                index.stage_disconnect(&mut batch, &block, &locator.block_hash, locator.height)?;
+                let parent = (locator.height > 0).then(|| IndexLocator {
+                    block_hash: block.header.prev_block_hash,
+                    height: locator.height - 1,
+                });
+                self.indexes
+                    .stage_locator(&mut batch, index.name(), parent)?;
+                self.db.apply_batch(&batch)?;
+                return Ok(true);
+            }
+        }
+
+        let height = locator.map_or(0, |l| l.height + 1);
+        if tip_height.map_or(true, |tip| height > tip) {
+            return Ok(false);
+        }
+        let hash = match self.blockstore.get_hash_by_height(height)? {
+            Some(hash) => hash,
+            None => return Ok(false),
+        };
+        let block = match self.blockstore.get_block(&hash)? {
+            Some(block) => block,
+            None => {
+                debug!("{} waiting for block at height {}", index.name(), height);
+                return Ok(false);
+            }
+        };
+        let undo = if index.requires_undo() {
+            match self.blockstore.get_undo(&hash)? {
+                Some(undo) => undo,
+                None => {
+                    debug!(
+                        "{} waiting for undo data at height {}",
+                        index.name(),
+                        height
+                    );
+                    return Ok(false);
+                }
+            }
+        } else {
+            BlockUndo::default()
+        };
+
+        let mut batch = WriteBatch::new();
+        index.stage_connect(&mut batch, &block, &hash, height, &undo)?;
+        self.indexes.stage_locator(
+            &mut batch,
+            index.name(),
+            Some(IndexLocator {
+                block_hash: hash,
+                height,
+            }),
+        )?;
+        self.db.apply_batch(&batch)?;
+        Ok(true)
+    }
+}
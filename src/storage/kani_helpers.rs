@@ -5,7 +5,7 @@
 
 #[cfg(kani)]
 pub mod kani_mocks {
-    use super::super::database::{Database, Tree};
+    use super::super::database::{BatchOp, Database, Tree, WriteBatch};
     use anyhow::Result;
     use std::collections::HashMap;
     use std::sync::{Arc, Mutex};
@@ -39,6 +39,27 @@ pub mod kani_mocks {
             // No-op for mock
             Ok(())
         }
+
+        fn apply_batch(&self, batch: &WriteBatch) -> Result<()> {
+            // Single lock over all trees keeps the batch atomic for the mock
+            let mut trees = self.trees.lock().unwrap();
+            for op in batch.ops() {
+                let tree = trees
+                    .entry(op.tree().to_string())
+                    .or_insert_with(|| Arc::new(MockTree::new()))
+                    .clone();
+                let mut data = tree.data.lock().unwrap();
+                match op {
+                    BatchOp::Insert { key, value, .. } => {
+                        data.insert(key.clone(), value.clone());
+                    }
+                    BatchOp::Remove { key, .. } => {
+                        data.remove(key);
+                    }
+                }
+            }
+            Ok(())
+        }
     }
 
     /// Mock tree implementation using HashMap
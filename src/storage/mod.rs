@@ -3,38 +3,104 @@
 //! This module provides persistent storage for blocks, UTXO set, and chain state.
 //! Supports multiple database backends via feature flags (sled, redb).
 
+pub mod blockfiles;
+pub mod blockindex;
 pub mod blockstore;
 pub mod chainstate;
 #[cfg(kani)]
 pub mod chainstate_proofs;
+pub mod chainwork;
+pub mod coinstatsindex;
 #[cfg(feature = "utxo-commitments")]
 pub mod commitment_store;
 #[cfg(kani)]
 pub mod cryptographic_proofs;
 pub mod database;
+pub mod filterindex;
 pub mod hashing;
+pub mod indexer;
 #[cfg(kani)]
 pub mod kani_helpers;
 pub mod pruning;
+pub mod reindex;
+pub mod snapshot;
 pub mod txindex;
+pub mod utxocache;
 pub mod utxostore;
 #[cfg(kani)]
 pub mod utxostore_proofs;
 
-use crate::config::PruningConfig;
+use crate::config::{
+    BlockStorageBackendConfig, BlockStorageConfig, PruningConfig, ReindexConfig,
+    StorageCacheConfig, StorageConfig,
+};
 use anyhow::Result;
-use database::{create_database, default_backend, fallback_backend, Database, DatabaseBackend};
-use std::path::Path;
+use bllvm_protocol::segwit::Witness;
+use bllvm_protocol::{Block, BlockHeader, Hash};
+use blockindex::BlockStatus;
+use database::{
+    create_database, default_backend, fallback_backend, Database, DatabaseBackend, WriteBatch,
+};
+use hashing::{UtxoHashFormat, UtxoSetHasher};
+use snapshot::{SnapshotBase, SnapshotMetadata, SnapshotStatus};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tracing::{info, warn};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Maximum number of blocks walked back when repairing the height index from the chain tip
+const MAX_REPAIR_DEPTH: u64 = 10_000;
+
+/// Number of headers before the tip stored in a snapshot for median time-past
+const SNAPSHOT_RECENT_HEADERS: usize = 11;
+
+/// Coins written per batch when loading a snapshot
+const SNAPSHOT_LOAD_BATCH_COINS: usize = 100_000;
+
+/// Outcome of the startup consistency check
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    /// Chain tip height after any repair
+    pub tip_height: Option<u64>,
+    /// Height index entries above the chain tip that were rolled back
+    pub heights_rolled_back: u64,
+    /// Height index entries restored by walking back from the chain tip
+    pub heights_restored: u64,
+    /// Whether the chain tip record had to be rebuilt from the height index
+    pub tip_rebuilt: bool,
+    /// Blocks connected since the last UTXO cache flush that were replayed
+    pub utxo_blocks_replayed: u64,
+    /// Blocks below a loaded snapshot's base that were replayed into the
+    /// background UTXO set
+    pub background_blocks_replayed: u64,
+}
+
+impl ConsistencyReport {
+    /// True if no repair was needed
+    pub fn is_clean(&self) -> bool {
+        self.heights_rolled_back == 0
+            && self.heights_restored == 0
+            && !self.tip_rebuilt
+            && self.utxo_blocks_replayed == 0
+            && self.background_blocks_replayed == 0
+    }
+}
 
 /// Storage manager that coordinates all storage operations
 pub struct Storage {
     db: Arc<dyn Database>,
     blockstore: Arc<blockstore::BlockStore>,
     utxostore: Arc<utxostore::UtxoStore>,
+    utxo_cache: Arc<utxocache::UtxoCache>,
+    background_utxo_cache: Arc<utxocache::UtxoCache>,
     chainstate: chainstate::ChainState,
+    block_index: Arc<blockindex::BlockIndex>,
     txindex: Arc<txindex::TxIndex>,
+    filter_index: Option<Arc<filterindex::BlockFilterIndex>>,
+    coin_stats_index: Option<Arc<coinstatsindex::CoinStatsIndex>>,
+    indexes: Arc<indexer::IndexManager>,
     pruning_manager: Option<Arc<pruning::PruningManager>>,
 }
 
@@ -92,60 +158,157 @@ impl Storage {
         backend: DatabaseBackend,
         pruning_config: Option<PruningConfig>,
         indexing_config: Option<crate::config::IndexingConfig>,
+    ) -> Result<Self> {
+        Self::open(
+            data_dir.as_ref(),
+            backend,
+            pruning_config,
+            indexing_config,
+            &BlockStorageConfig::default(),
+            false,
+        )
+    }
+
+    /// Create a new storage instance from a storage configuration
+    ///
+    /// Applies the pruning, indexing, block storage and cache settings.
+    pub fn with_config<P: AsRef<Path>>(
+        data_dir: P,
+        backend: DatabaseBackend,
+        config: &StorageConfig,
+    ) -> Result<Self> {
+        let storage = Self::open(
+            data_dir.as_ref(),
+            backend,
+            config.pruning.clone(),
+            config.indexing.clone(),
+            &config.block_storage,
+            config.reindex == ReindexConfig::Reindex,
+        )?;
+        if let Some(ref cache_config) = config.cache {
+            storage.configure_cache(cache_config);
+        }
+        Ok(storage)
+    }
+
+    fn open(
+        data_dir: &Path,
+        backend: DatabaseBackend,
+        pruning_config: Option<PruningConfig>,
+        indexing_config: Option<crate::config::IndexingConfig>,
+        block_storage: &BlockStorageConfig,
+        full_reindex: bool,
     ) -> Result<Self> {
         let db = Arc::from(create_database(data_dir, backend)?);
 
-        let blockstore = Arc::new(blockstore::BlockStore::new(Arc::clone(&db))?);
+        let blockstore = match block_storage.backend {
+            BlockStorageBackendConfig::Database => blockstore::BlockStore::new(Arc::clone(&db))?,
+            BlockStorageBackendConfig::FlatFiles => {
+                let files = blockfiles::BlockFileStore::new(
+                    Arc::clone(&db),
+                    data_dir.join("blocks"),
+                    block_storage.max_block_file_mb.saturating_mul(1024 * 1024),
+                )?;
+                blockstore::BlockStore::with_block_files(Arc::clone(&db), Arc::new(files))?
+            }
+        };
+        let blockstore = Arc::new(blockstore);
         let utxostore = Arc::new(utxostore::UtxoStore::new(Arc::clone(&db))?);
         let chainstate = chainstate::ChainState::new(Arc::clone(&db))?;
+        if chainstate.has_current_block_hashes()? {
+            chainstate.store_block_hash_version()?;
+        } else if !full_reindex {
+            // Blocks are keyed by hashes that match neither the network nor
+            // their children's links; only a full reindex can move them
+            return Err(anyhow::anyhow!(
+                "{} stores blocks under an older block hash encoding; start once with `reindex = \"reindex\"` in the storage configuration to rehash them",
+                data_dir.display()
+            ));
+        }
+        chainstate.migrate_chainwork(&blockstore)?;
+        let block_index = Arc::new(blockindex::BlockIndex::new(Arc::clone(&db))?);
+        if block_index.is_empty()? && chainstate.is_initialized()? {
+            // Databases written before the block index existed
+            let indexed = block_index.rebuild(&blockstore, &chainstate)?;
+            info!("Built block index from {} stored headers", indexed);
+        }
 
-        // Configure transaction indexing based on config
-        let txindex = if let Some(indexing) = indexing_config {
-            Arc::new(txindex::TxIndex::with_indexing(
-                Arc::clone(&db),
-                indexing.enable_address_index,
-                indexing.enable_value_index,
-            )?)
+        // Configure the background indexes based on config
+        let indexing = indexing_config.unwrap_or_default();
+        let txindex = Arc::new(txindex::TxIndex::with_indexing(
+            Arc::clone(&db),
+            indexing.enable_address_index,
+            indexing.enable_value_index,
+        )?);
+        let mut indexes: Vec<Arc<dyn indexer::BackgroundIndex>> = vec![txindex.clone()];
+        if txindex.has_derived_indexes() {
+            indexes.push(Arc::new(txindex::AddressIndex::new(Arc::clone(&txindex))));
+        }
+        let filter_index = if indexing.enable_block_filter_index {
+            let index = Arc::new(filterindex::BlockFilterIndex::new(Arc::clone(&db))?);
+            indexes.push(index.clone());
+            Some(index)
         } else {
-            Arc::new(txindex::TxIndex::new(Arc::clone(&db))?)
+            None
         };
+        let coin_stats_index = if indexing.enable_coin_stats_index {
+            let index = Arc::new(coinstatsindex::CoinStatsIndex::new(Arc::clone(&db))?);
+            indexes.push(index.clone());
+            Some(index)
+        } else {
+            None
+        };
+        let indexes = Arc::new(indexer::IndexManager::new(
+            Arc::clone(&db),
+            indexes,
+            !indexing.background_indexing,
+        )?);
+
+        let utxo_cache = Arc::new(utxocache::UtxoCache::new(Arc::clone(&utxostore))?);
+        let background_utxo_cache = Arc::new(utxocache::UtxoCache::new(Arc::new(
+            utxostore::UtxoStore::background(Arc::clone(&db))?,
+        ))?);
 
         let pruning_manager = pruning_config.map(|config| {
             #[cfg(feature = "utxo-commitments")]
-            {
+            let manager = {
                 // Check if aggressive mode requires UTXO commitments
                 let needs_commitments = matches!(config.mode, crate::config::PruningMode::Aggressive { keep_commitments: true, .. })
                     || matches!(config.mode, crate::config::PruningMode::Custom { keep_commitments: true, .. });
                 if needs_commitments {
-                    let commitment_store = match commitment_store::CommitmentStore::new(Arc::clone(&db)) {
-                        Ok(store) => Arc::new(store),
+                    match commitment_store::CommitmentStore::new(Arc::clone(&db)) {
+                        Ok(store) => pruning::PruningManager::with_utxo_commitments(
+                            config,
+                            Arc::clone(&blockstore),
+                            Arc::new(store),
+                            Arc::clone(&utxostore),
+                        ),
                         Err(e) => {
                             warn!("Failed to create commitment store: {}. Pruning will continue without commitments.", e);
-                            return Arc::new(pruning::PruningManager::new(config, Arc::clone(&blockstore)));
+                            pruning::PruningManager::new(config, Arc::clone(&blockstore))
                         }
-                    };
-                    Arc::new(pruning::PruningManager::with_utxo_commitments(
-                        config,
-                        Arc::clone(&blockstore),
-                        commitment_store,
-                        Arc::clone(&utxostore),
-                    ))
+                    }
                 } else {
-                    Arc::new(pruning::PruningManager::new(config, Arc::clone(&blockstore)))
+                    pruning::PruningManager::new(config, Arc::clone(&blockstore))
                 }
-            }
+            };
             #[cfg(not(feature = "utxo-commitments"))]
-            {
-                Arc::new(pruning::PruningManager::new(config, Arc::clone(&blockstore)))
-            }
+            let manager = pruning::PruningManager::new(config, Arc::clone(&blockstore));
+            Arc::new(manager.with_utxo_cache(Arc::clone(&utxo_cache)))
         });
 
         Ok(Self {
             db,
             blockstore,
             utxostore,
+            utxo_cache,
+            background_utxo_cache,
             chainstate,
+            block_index,
             txindex,
+            filter_index,
+            coin_stats_index,
+            indexes,
             pruning_manager,
         })
     }
@@ -156,6 +319,9 @@ impl Storage {
     }
 
     /// Get the UTXO store
+    ///
+    /// Holds the UTXO set as of the last cache flush; read the current set
+    /// through [`Self::utxo_cache`].
     pub fn utxos(&self) -> &utxostore::UtxoStore {
         &self.utxostore
     }
@@ -165,16 +331,67 @@ impl Storage {
         Arc::clone(&self.utxostore)
     }
 
+    /// Get the UTXO cache
+    ///
+    /// Block validation reads and writes the UTXO set through the cache; the
+    /// UTXO store only holds the state as of the last flush.
+    pub fn utxo_cache(&self) -> Arc<utxocache::UtxoCache> {
+        Arc::clone(&self.utxo_cache)
+    }
+
+    /// Get the UTXO cache used to validate history below a loaded snapshot
+    ///
+    /// Built up from genesis by [`Self::connect_background_block`]; empty unless
+    /// the chain state was loaded with [`Self::load_snapshot`].
+    pub fn background_utxo_cache(&self) -> Arc<utxocache::UtxoCache> {
+        Arc::clone(&self.background_utxo_cache)
+    }
+
+    /// Apply cache size and flush interval settings
+    pub fn configure_cache(&self, config: &StorageCacheConfig) {
+        let max_memory = config.utxo_cache_mb.saturating_mul(1024 * 1024);
+        let flush_interval = Duration::from_secs(config.utxo_flush_interval_secs);
+        self.utxo_cache.set_limits(max_memory, flush_interval);
+        self.background_utxo_cache
+            .set_limits(max_memory, flush_interval);
+    }
+
     /// Get the chain state
     pub fn chain(&self) -> &chainstate::ChainState {
         &self.chainstate
     }
 
+    /// Get the block index
+    ///
+    /// Every known header with its height, chainwork and validation status;
+    /// chain tips and the best chain are selected from it.
+    pub fn block_index(&self) -> Arc<blockindex::BlockIndex> {
+        Arc::clone(&self.block_index)
+    }
+
     /// Get the transaction index (as Arc for sharing)
     pub fn transactions(&self) -> Arc<txindex::TxIndex> {
         Arc::clone(&self.txindex)
     }
 
+    /// Get the background index manager
+    ///
+    /// Holds every enabled index and the block it is synced to; see
+    /// [`Self::sync_indexes`].
+    pub fn indexes(&self) -> Arc<indexer::IndexManager> {
+        Arc::clone(&self.indexes)
+    }
+
+    /// Get the BIP158 block filter index (if enabled)
+    pub fn filter_index(&self) -> Option<Arc<filterindex::BlockFilterIndex>> {
+        self.filter_index.as_ref().map(Arc::clone)
+    }
+
+    /// Get the UTXO set statistics index (if enabled)
+    pub fn coin_stats_index(&self) -> Option<Arc<coinstatsindex::CoinStatsIndex>> {
+        self.coin_stats_index.as_ref().map(Arc::clone)
+    }
+
     /// Open a custom tree for application-specific data
     ///
     /// This allows modules to store their own key-value data in the database.
@@ -183,11 +400,765 @@ impl Storage {
         Ok(Arc::from(self.db.open_tree(name)?))
     }
 
-    /// Flush all pending writes to disk
+    /// Flush all pending writes to disk, including the UTXO cache
     pub fn flush(&self) -> Result<()> {
+        self.utxo_cache.flush()?;
         self.db.flush()
     }
 
+    /// Commit a batch of staged writes atomically
+    ///
+    /// Goes through the block store so staged block file records are synced
+    /// first.
+    pub fn apply_batch(&self, batch: &WriteBatch) -> Result<()> {
+        self.blockstore.apply_batch(batch)
+    }
+
+    /// Atomically commit a block that was validated on top of the active tip
+    ///
+    /// The block body, witnesses, height index, undo data, chain tip and the
+    /// entries of every index synced to the parent go into one batch. The
+    /// block's UTXO changes must already be applied to the UTXO cache; if a
+    /// cache flush is due it is committed in the same batch.
+    pub fn connect_block(
+        &self,
+        block: &Block,
+        witnesses: &[Witness],
+        height: u64,
+        undo: &blockstore::BlockUndo,
+    ) -> Result<()> {
+        let block_hash = self.blockstore.get_block_hash(block);
+
+        let mut batch = WriteBatch::new();
+        self.blockstore
+            .stage_connected_block(&mut batch, block, witnesses, height)?;
+        self.blockstore.stage_undo(&mut batch, &block_hash, undo)?;
+        let _indexes = self.indexes.lock();
+        self.indexes
+            .stage_connect(&mut batch, block, &block_hash, height, undo)?;
+        let parent_chainwork = self
+            .chainstate
+            .get_chainwork(&block.header.prev_block_hash)?
+            .unwrap_or_default();
+        self.block_index.stage_block(
+            &mut batch,
+            &block_hash,
+            &block.header,
+            height,
+            parent_chainwork,
+            BlockStatus::HAVE_DATA | BlockStatus::FULLY_VALID,
+        )?;
+        self.chainstate
+            .stage_tip(&mut batch, &block_hash, &block.header, height)?;
+        if self.utxo_cache.should_flush() {
+            self.utxo_cache
+                .flush_with(&mut batch, |batch| self.blockstore.apply_batch(batch))?;
+        } else {
+            self.blockstore.apply_batch(&batch)?;
+        }
+        Ok(())
+    }
+
+    /// Atomically disconnect the active tip at `height`
+    ///
+    /// Reverses everything [`Self::connect_block`] wrote except the block body,
+    /// which is kept so the block can be reconnected. The parent becomes the tip.
+    /// The block must already be reverted in the UTXO cache, which is flushed in
+    /// the same batch: the undo data is deleted here, so the stored UTXO set may
+    /// never be left at a block that is no longer on the active chain.
+    pub fn disconnect_block(&self, block: &Block, height: u64) -> Result<()> {
+        if height == 0 {
+            return Err(anyhow::anyhow!("Cannot disconnect the genesis block"));
+        }
+        let block_hash = self.blockstore.get_block_hash(block);
+        let parent_hash = block.header.prev_block_hash;
+        let parent_header = self.blockstore.get_header(&parent_hash)?.ok_or_else(|| {
+            anyhow::anyhow!("Missing header for parent {}", hex::encode(parent_hash))
+        })?;
+
+        let mut batch = WriteBatch::new();
+        self.blockstore.stage_remove_height(&mut batch, height)?;
+        self.blockstore.stage_remove_undo(&mut batch, &block_hash);
+        let _indexes = self.indexes.lock();
+        self.indexes
+            .stage_disconnect(&mut batch, block, &block_hash, height)?;
+        self.chainstate
+            .stage_tip(&mut batch, &parent_hash, &parent_header, height - 1)?;
+        self.utxo_cache
+            .flush_with(&mut batch, |batch| self.db.apply_batch(batch))
+    }
+
+    /// Check that the chain tip, the height index and the UTXO set agree, repairing them if not
+    ///
+    /// Block connects and disconnects are atomic, but databases written by older
+    /// versions (or damaged on disk) can have a height index that runs past the
+    /// recorded tip, a tip whose ancestors are missing from the index, or no tip
+    /// record at all. The stored UTXO set lags the tip by the blocks connected
+    /// since the last cache flush; those are replayed from their bodies. Should
+    /// be called once at startup, before blocks are processed.
+    pub fn check_consistency(&self) -> Result<ConsistencyReport> {
+        // The active UTXO set is only consistent once a switch away from an
+        // invalid snapshot has finished
+        self.resume_background_activation()?;
+        let mut report = self.check_chain_index()?;
+        report.utxo_blocks_replayed = self.replay_utxo_blocks(report.tip_height)?;
+        report.background_blocks_replayed = self.replay_background_blocks()?;
+        Ok(report)
+    }
+
+    /// Repair the chain tip record and the height index
+    fn check_chain_index(&self) -> Result<ConsistencyReport> {
+        let mut report = ConsistencyReport::default();
+        let mut batch = WriteBatch::new();
+        let index_tip = self.blockstore.get_indexed_tip_height()?;
+
+        let info = match self.chainstate.load_chain_info()? {
+            Some(info) => info,
+            None => {
+                // No tip record: adopt the highest indexed block
+                if let Some(height) = index_tip {
+                    let hash = self.blockstore.get_hash_by_height(height)?;
+                    let header = match hash {
+                        Some(hash) => self.blockstore.get_header(&hash)?.map(|h| (hash, h)),
+                        None => None,
+                    };
+                    if let Some((hash, header)) = header {
+                        self.chainstate
+                            .stage_tip(&mut batch, &hash, &header, height)?;
+                        self.db.apply_batch(&batch)?;
+                        report.tip_rebuilt = true;
+                        report.tip_height = Some(height);
+                        warn!(
+                            "Chain tip record missing; rebuilt from height index at height {}",
+                            height
+                        );
+                    }
+                }
+                return Ok(report);
+            }
+        };
+        report.tip_height = Some(info.height);
+
+        // Roll back index entries above the recorded tip (interrupted connect)
+        if let Some(index_height) = index_tip {
+            for height in ((info.height + 1)..=index_height).rev() {
+                if let Some(hash) = self.blockstore.get_hash_by_height(height)? {
+                    if let Some(block) = self.blockstore.get_block(&hash)? {
+                        for tx in block.transactions.iter() {
+                            let tx_hash = bllvm_protocol::block::calculate_tx_id(tx);
+                            self.txindex
+                                .stage_remove_transaction(&mut batch, &tx_hash)?;
+                        }
+                    }
+                }
+                self.blockstore.stage_remove_height(&mut batch, height)?;
+                report.heights_rolled_back += 1;
+            }
+        }
+
+        // Restore index entries missing below the tip (interrupted disconnect)
+        let mut hash = info.tip_hash;
+        let mut height = info.height;
+        for _ in 0..MAX_REPAIR_DEPTH {
+            let indexed = self.blockstore.get_hash_by_height(height)?;
+            if indexed == Some(hash) {
+                break;
+            }
+            let header = match self.blockstore.get_header(&hash)? {
+                Some(header) => header,
+                None => {
+                    warn!(
+                        "Cannot repair height index: header {} at height {} is missing",
+                        hex::encode(hash),
+                        height
+                    );
+                    break;
+                }
+            };
+            if let Some(stale) = indexed {
+                self.blockstore.stage_remove_hash_index(&mut batch, &stale);
+            }
+            self.blockstore.stage_height(&mut batch, height, &hash);
+            if height + 11 >= info.height {
+                self.blockstore
+                    .stage_recent_header(&mut batch, height, &header)?;
+            }
+            report.heights_restored += 1;
+            if height == 0 {
+                break;
+            }
+            hash = header.prev_block_hash;
+            height -= 1;
+        }
+
+        if !report.is_clean() {
+            self.db.apply_batch(&batch)?;
+            warn!(
+                "Repaired storage at startup: {} height entries rolled back, {} restored (tip height {})",
+                report.heights_rolled_back, report.heights_restored, info.height
+            );
+        }
+        Ok(report)
+    }
+
+    /// Connect the blocks between the last UTXO cache flush and the chain tip
+    ///
+    /// Returns the number of blocks replayed.
+    fn replay_utxo_blocks(&self, tip_height: Option<u64>) -> Result<u64> {
+        let tip_height = match tip_height {
+            Some(height) => height,
+            None => return Ok(0),
+        };
+
+        let start = match self.utxo_cache.best_block() {
+            Some((hash, height)) => {
+                if height > tip_height || self.blockstore.get_hash_by_height(height)? != Some(hash)
+                {
+                    return Err(anyhow::anyhow!(
+                        "UTXO set is at block {} (height {}), which is not on the active chain; reindex-chainstate is required",
+                        hex::encode(hash),
+                        height
+                    ));
+                }
+                height + 1
+            }
+            None if self.utxostore.utxo_count()? > 0 => {
+                // Written by a version without the cache, which stored the UTXO
+                // set together with every block
+                let hash = self
+                    .blockstore
+                    .get_hash_by_height(tip_height)?
+                    .ok_or_else(|| anyhow::anyhow!("No block at tip height {}", tip_height))?;
+                let mut batch = WriteBatch::new();
+                self.utxostore
+                    .stage_best_block(&mut batch, &hash, tip_height)?;
+                self.db.apply_batch(&batch)?;
+                self.utxo_cache.reset()?;
+                info!(
+                    "Recorded existing UTXO set as being at height {}",
+                    tip_height
+                );
+                return Ok(0);
+            }
+            None => 0,
+        };
+
+        let mut replayed = 0;
+        for height in start..=tip_height {
+            let hash = self
+                .blockstore
+                .get_hash_by_height(height)?
+                .ok_or_else(|| anyhow::anyhow!("No block at height {}", height))?;
+            let block = self.blockstore.get_block(&hash)?.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Cannot replay block {} at height {} into the UTXO set: body not available (pruned?); reindex-chainstate is required",
+                    hex::encode(hash),
+                    height
+                )
+            })?;
+            self.utxo_cache.apply_block(&block, &hash, height)?;
+            if self.utxo_cache.should_flush() {
+                self.utxo_cache.flush()?;
+            }
+            replayed += 1;
+        }
+
+        if replayed > 0 {
+            self.utxo_cache.flush()?;
+            warn!(
+                "Replayed {} blocks into the UTXO set (heights {} to {})",
+                replayed, start, tip_height
+            );
+        }
+        Ok(replayed)
+    }
+
+    /// Connect blocks already committed below a loaded snapshot's base that the
+    /// background UTXO set has not been flushed past
+    ///
+    /// Stops at the first block whose body is missing; validation resumes from
+    /// there when the block is received again. Returns the number of blocks
+    /// replayed.
+    fn replay_background_blocks(&self) -> Result<u64> {
+        let base = match self.chainstate.get_snapshot_base()? {
+            Some(base) => base,
+            None => return Ok(0),
+        };
+        let cache = &self.background_utxo_cache;
+        if base.status != SnapshotStatus::Unvalidated {
+            // Left over from a completion interrupted before it was cleaned up
+            if cache.best_block().is_some() {
+                cache.store().clear()?;
+                cache.reset()?;
+            }
+            return Ok(0);
+        }
+
+        let start = cache
+            .best_block()
+            .map(|(_, height)| height + 1)
+            .unwrap_or(0);
+        let mut replayed = 0;
+        for height in start..base.base_height {
+            let hash = match self.blockstore.get_hash_by_height(height)? {
+                Some(hash) => hash,
+                None => break,
+            };
+            let block = match self.blockstore.get_block(&hash)? {
+                Some(block) => block,
+                None => break,
+            };
+            cache.apply_block(&block, &hash, height)?;
+            if cache.should_flush() {
+                cache.flush()?;
+            }
+            replayed += 1;
+        }
+
+        if replayed > 0 {
+            cache.flush()?;
+            warn!(
+                "Replayed {} blocks into the background UTXO set (heights {} to {})",
+                replayed,
+                start,
+                start + replayed - 1
+            );
+        }
+        Ok(replayed)
+    }
+
+    /// Get the snapshot the active chain state was loaded from, if any
+    pub fn snapshot_base(&self) -> Result<Option<SnapshotBase>> {
+        self.chainstate.get_snapshot_base()
+    }
+
+    /// Write the UTXO set at the chain tip to an AssumeUTXO snapshot file
+    ///
+    /// The cache is flushed first so the stored set is at the tip. The file is
+    /// written next to `path` and renamed into place once complete; an existing
+    /// file is never overwritten.
+    pub fn dump_snapshot(&self, path: &Path) -> Result<SnapshotMetadata> {
+        if path.exists() {
+            return Err(anyhow::anyhow!(
+                "{} already exists; refusing to overwrite it",
+                path.display()
+            ));
+        }
+        let info = self
+            .chainstate
+            .load_chain_info()?
+            .ok_or_else(|| anyhow::anyhow!("No chain tip to take a snapshot at"))?;
+
+        self.flush()?;
+        if self.utxo_cache.best_block() != Some((info.tip_hash, info.height)) {
+            return Err(anyhow::anyhow!(
+                "UTXO set is not at the chain tip {} (height {})",
+                hex::encode(info.tip_hash),
+                info.height
+            ));
+        }
+        let mut recent_headers = self
+            .blockstore
+            .get_ancestor_headers(&info.tip_hash, SNAPSHOT_RECENT_HEADERS)?;
+        if recent_headers.is_empty() {
+            recent_headers.push(info.tip_header.clone());
+        }
+
+        // Coin count and content hash are filled in as the coins are written
+        let mut metadata = SnapshotMetadata {
+            network: self.chainstate.get_chain_params()?.network,
+            base_hash: info.tip_hash,
+            base_height: info.height,
+            recent_headers,
+            coins_count: 0,
+            utxo_hash: [0u8; 32],
+        };
+
+        let mut incomplete = path.as_os_str().to_owned();
+        incomplete.push(".incomplete");
+        let incomplete = PathBuf::from(incomplete);
+        let result = self
+            .write_snapshot_file(&incomplete, &mut metadata)
+            .and_then(|()| std::fs::rename(&incomplete, path).map_err(anyhow::Error::from));
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&incomplete);
+            return Err(e);
+        }
+
+        info!(
+            "Wrote UTXO snapshot of {} coins at height {} to {}",
+            metadata.coins_count,
+            metadata.base_height,
+            path.display()
+        );
+        Ok(metadata)
+    }
+
+    /// Stream the stored UTXO set into a snapshot file at `path`
+    ///
+    /// The header goes out first with a placeholder coin count and content
+    /// hash; they are filled into `metadata` while the coins are written and the
+    /// header is rewritten in place at the end.
+    fn write_snapshot_file(&self, path: &Path, metadata: &mut SnapshotMetadata) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        snapshot::write_snapshot_header(&mut writer, metadata)?;
+        let mut hasher = UtxoSetHasher::new(UtxoHashFormat::Serialized3);
+        for result in self.utxostore.iter_utxos() {
+            let (outpoint, utxo) = result?;
+            // Coins left out of the content hash are not part of the snapshot
+            if !UtxoHashFormat::Serialized3.includes(&utxo) {
+                continue;
+            }
+            snapshot::write_snapshot_coin(&mut writer, &outpoint, &utxo)?;
+            hasher.add(&outpoint, &utxo);
+            metadata.coins_count += 1;
+        }
+        metadata.utxo_hash = hasher.finish();
+
+        // The store only changes when the UTXO cache flushes, which moves its best block
+        if self.utxostore.get_best_block()? != Some((metadata.base_hash, metadata.base_height)) {
+            return Err(anyhow::anyhow!(
+                "UTXO set changed while the snapshot was being written; try again"
+            ));
+        }
+
+        let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        snapshot::write_snapshot_header(&mut file, metadata)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Load an AssumeUTXO snapshot into a datadir that has no chain yet
+    ///
+    /// The snapshot must be for this datadir's network and match an entry in
+    /// `known` (normally [`chainstate::ChainParams::assumeutxo_snapshots`]). Its
+    /// base block becomes the chain tip, so syncing continues from there, and
+    /// history up to the base is validated in the background against
+    /// [`Self::background_utxo_cache`].
+    pub fn load_snapshot(
+        &self,
+        path: &Path,
+        known: &[chainstate::AssumeUtxoData],
+    ) -> Result<SnapshotMetadata> {
+        if self.chainstate.load_chain_info()?.is_some() {
+            return Err(anyhow::anyhow!(
+                "A snapshot can only be loaded into a datadir without a chain"
+            ));
+        }
+
+        let file = File::open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+        let mut reader = BufReader::new(file);
+        let metadata = snapshot::read_snapshot_header(&mut reader)?;
+
+        let network = self.chainstate.get_chain_params()?.network;
+        if metadata.network != network {
+            return Err(anyhow::anyhow!(
+                "Snapshot is for {}, but this node is on {}",
+                metadata.network,
+                network
+            ));
+        }
+        snapshot::verify_known_snapshot(&metadata, known)?;
+        self.check_snapshot_headers(&metadata)?;
+
+        // Coins go in first, in several batches. Until the final batch records
+        // the tip, the datadir still has no chain and the load can be retried.
+        self.utxostore.clear()?;
+        if let Err(e) = self.load_snapshot_coins(reader, &metadata) {
+            // Leave no coins behind for a chain that was never recorded
+            let _ = self.utxostore.clear();
+            return Err(e);
+        }
+
+        let mut batch = WriteBatch::new();
+        let first_height = metadata.base_height + 1 - metadata.recent_headers.len() as u64;
+        for (offset, header) in metadata.recent_headers.iter().enumerate() {
+            self.blockstore.stage_header(&mut batch, header)?;
+            self.blockstore.stage_recent_header(
+                &mut batch,
+                first_height + offset as u64,
+                header,
+            )?;
+        }
+        let base_header = &metadata.recent_headers[metadata.recent_headers.len() - 1];
+        self.blockstore
+            .stage_height(&mut batch, metadata.base_height, &metadata.base_hash);
+        // The base's ancestors are indexed as background validation reaches them
+        self.block_index.stage_block(
+            &mut batch,
+            &metadata.base_hash,
+            base_header,
+            metadata.base_height,
+            Default::default(),
+            BlockStatus::empty(),
+        )?;
+        self.chainstate.stage_tip(
+            &mut batch,
+            &metadata.base_hash,
+            base_header,
+            metadata.base_height,
+        )?;
+        self.utxostore
+            .stage_best_block(&mut batch, &metadata.base_hash, metadata.base_height)?;
+        self.chainstate.stage_snapshot_base(
+            &mut batch,
+            &SnapshotBase {
+                base_hash: metadata.base_hash,
+                base_height: metadata.base_height,
+                utxo_hash: metadata.utxo_hash,
+                status: SnapshotStatus::Unvalidated,
+            },
+        )?;
+        self.db.apply_batch(&batch)?;
+
+        self.utxo_cache.reset()?;
+        self.background_utxo_cache.store().clear()?;
+        self.background_utxo_cache.reset()?;
+
+        info!(
+            "Loaded UTXO snapshot of {} coins at height {} (block {}); validating history in the background",
+            metadata.coins_count,
+            metadata.base_height,
+            hex::encode(metadata.base_hash)
+        );
+        Ok(metadata)
+    }
+
+    /// Store the coins following a snapshot's header, checking them against
+    /// its content hash as they are read
+    fn load_snapshot_coins<R: Read>(&self, reader: R, metadata: &SnapshotMetadata) -> Result<()> {
+        let mut coins = snapshot::SnapshotCoinReader::new(reader, metadata);
+        let mut batch = WriteBatch::new();
+        while let Some((outpoint, utxo)) = coins.next_coin()? {
+            self.utxostore
+                .stage_add_utxo(&mut batch, &outpoint, &utxo)?;
+            if batch.len() >= SNAPSHOT_LOAD_BATCH_COINS {
+                self.db.apply_batch(&batch)?;
+                batch = WriteBatch::new();
+            }
+        }
+        coins.finish()?;
+        self.db.apply_batch(&batch)
+    }
+
+    /// Check that a snapshot's headers form a chain ending at its base block
+    fn check_snapshot_headers(&self, metadata: &SnapshotMetadata) -> Result<()> {
+        let headers: &[BlockHeader] = &metadata.recent_headers;
+        if headers.is_empty() || headers.len() as u64 > metadata.base_height + 1 {
+            return Err(anyhow::anyhow!(
+                "Snapshot has {} headers for base height {}",
+                headers.len(),
+                metadata.base_height
+            ));
+        }
+        for pair in headers.windows(2) {
+            if pair[1].prev_block_hash != self.blockstore.get_header_hash(&pair[0]) {
+                return Err(anyhow::anyhow!("Snapshot headers do not form a chain"));
+            }
+        }
+        let base_hash = self.blockstore.get_header_hash(&headers[headers.len() - 1]);
+        if base_hash != metadata.base_hash {
+            return Err(anyhow::anyhow!(
+                "Snapshot headers end at {}, not the base block {}",
+                hex::encode(base_hash),
+                hex::encode(metadata.base_hash)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Height `block` has in the background validation of a loaded snapshot
+    ///
+    /// `Some` if a snapshot is still being validated and `block` extends the
+    /// validated history: the genesis block first, then each block's child, up
+    /// to and including the snapshot's base block.
+    pub fn background_block_height(&self, block: &Block) -> Result<Option<u64>> {
+        let base = match self.chainstate.get_snapshot_base()? {
+            Some(base) if base.status == SnapshotStatus::Unvalidated => base,
+            _ => return Ok(None),
+        };
+        let height = match self.background_utxo_cache.best_block() {
+            Some((hash, height)) if block.header.prev_block_hash == hash => height + 1,
+            None if block.header.prev_block_hash == [0u8; 32] => 0,
+            _ => return Ok(None),
+        };
+        if height > base.base_height
+            || (height == base.base_height
+                && self.blockstore.get_block_hash(block) != base.base_hash)
+        {
+            return Ok(None);
+        }
+        Ok(Some(height))
+    }
+
+    /// Commit a block validated against the background UTXO set
+    ///
+    /// The block's UTXO changes must already be applied to
+    /// [`Self::background_utxo_cache`]. Its body and height index entry are
+    /// stored, and when it is the snapshot's base block the background UTXO set
+    /// is compared with the snapshot: the outcome is recorded in the snapshot
+    /// base record in the same batch, and the background set is dropped.
+    pub fn connect_background_block(
+        &self,
+        block: &Block,
+        witnesses: &[Witness],
+        height: u64,
+    ) -> Result<SnapshotStatus> {
+        let mut base = self
+            .chainstate
+            .get_snapshot_base()?
+            .ok_or_else(|| anyhow::anyhow!("No snapshot is being validated"))?;
+        let block_hash = self.blockstore.get_block_hash(block);
+        let cache = &self.background_utxo_cache;
+
+        let mut batch = WriteBatch::new();
+        self.blockstore.stage_block(&mut batch, block)?;
+        if !witnesses.is_empty() {
+            self.blockstore
+                .stage_witness(&mut batch, &block_hash, witnesses)?;
+        }
+        self.blockstore
+            .stage_height(&mut batch, height, &block_hash);
+        self.block_index.stage_block(
+            &mut batch,
+            &block_hash,
+            &block.header,
+            height,
+            Default::default(),
+            BlockStatus::HAVE_DATA | BlockStatus::FULLY_VALID,
+        )?;
+
+        if height < base.base_height {
+            if cache.should_flush() {
+                cache.flush_with(&mut batch, |batch| self.blockstore.apply_batch(batch))?;
+            } else {
+                self.blockstore.apply_batch(&batch)?;
+            }
+            return Ok(SnapshotStatus::Unvalidated);
+        }
+
+        let mut hasher = UtxoSetHasher::new(UtxoHashFormat::Serialized3);
+        cache.for_each_utxo(|outpoint, utxo| {
+            hasher.add(outpoint, utxo);
+            Ok(())
+        })?;
+        let utxo_hash = hasher.finish();
+        base.status = if utxo_hash == base.utxo_hash {
+            SnapshotStatus::Validated
+        } else {
+            SnapshotStatus::Invalid
+        };
+        self.chainstate.stage_snapshot_base(&mut batch, &base)?;
+        cache.flush_with(&mut batch, |batch| self.blockstore.apply_batch(batch))?;
+
+        if base.status == SnapshotStatus::Validated {
+            cache.store().clear()?;
+            cache.reset()?;
+            info!(
+                "Background validation reached snapshot base {} at height {}; UTXO set matches",
+                hex::encode(base.base_hash),
+                height
+            );
+        } else {
+            error!(
+                "Background validation reached snapshot base {} at height {}, but the UTXO set hashes to {} instead of {}; the snapshot is invalid",
+                hex::encode(base.base_hash),
+                height,
+                hex::encode(utxo_hash),
+                hex::encode(base.utxo_hash)
+            );
+            self.activate_background_chainstate(&base)?;
+        }
+        Ok(base.status)
+    }
+
+    /// Replace the active chain state with the background one once the
+    /// snapshot it was loaded from has been found invalid
+    ///
+    /// The background UTXO set, validated from genesis up to the snapshot's
+    /// base block, replaces the snapshot's, and the chain tip moves back to the
+    /// base. Blocks connected above the base were checked against the invalid
+    /// set, so they leave the active chain and lose their validated status;
+    /// their bodies are kept so the node can connect them again. The
+    /// background set is dropped last, so an interrupted switch is finished at
+    /// startup (see [`Self::check_consistency`]).
+    fn activate_background_chainstate(&self, base: &SnapshotBase) -> Result<()> {
+        let background = self.background_utxo_cache.store();
+        self.utxostore.clear()?;
+        let mut batch = WriteBatch::new();
+        for result in background.iter_utxos() {
+            let (outpoint, utxo) = result?;
+            self.utxostore
+                .stage_add_utxo(&mut batch, &outpoint, &utxo)?;
+            if batch.len() >= SNAPSHOT_LOAD_BATCH_COINS {
+                self.db.apply_batch(&batch)?;
+                batch = WriteBatch::new();
+            }
+        }
+
+        // Top down, so each height's median time-past refill is undone by the
+        // removal of the height below it
+        let tip_height = self
+            .chainstate
+            .load_chain_info()?
+            .map(|info| info.height)
+            .unwrap_or(base.base_height);
+        for height in (base.base_height + 1..=tip_height).rev() {
+            let Some(hash) = self.blockstore.get_hash_by_height(height)? else {
+                continue;
+            };
+            self.blockstore.stage_remove_height(&mut batch, height)?;
+            self.blockstore.stage_remove_undo(&mut batch, &hash);
+            if let Some(mut entry) = self.block_index.get(&hash)? {
+                entry.status.remove(BlockStatus::FULLY_VALID);
+                self.block_index.stage_entry(&mut batch, &entry)?;
+            }
+        }
+        let base_header = self
+            .blockstore
+            .get_header(&base.base_hash)?
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Missing header for snapshot base {}",
+                    hex::encode(base.base_hash)
+                )
+            })?;
+        self.chainstate
+            .stage_tip(&mut batch, &base.base_hash, &base_header, base.base_height)?;
+        self.utxostore
+            .stage_best_block(&mut batch, &base.base_hash, base.base_height)?;
+        self.db.apply_batch(&batch)?;
+        self.utxo_cache.reset()?;
+        // Indexes hold blocks above the base; rebuild them from the new chain
+        self.indexes.reset()?;
+
+        background.clear()?;
+        self.background_utxo_cache.reset()?;
+        warn!(
+            "Switched to the chain state validated from genesis at height {}; dropped {} blocks connected on the invalid snapshot",
+            base.base_height,
+            tip_height.saturating_sub(base.base_height)
+        );
+        Ok(())
+    }
+
+    /// Finish replacing the active chain state after an invalid snapshot if
+    /// that was interrupted
+    fn resume_background_activation(&self) -> Result<()> {
+        match self.chainstate.get_snapshot_base()? {
+            Some(base)
+                if base.status == SnapshotStatus::Invalid
+                    && self.background_utxo_cache.best_block()
+                        == Some((base.base_hash, base.base_height)) =>
+            {
+                self.activate_background_chainstate(&base)
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Get approximate disk size used by storage (in bytes)
     ///
     /// Returns an estimate based on tree sizes. If any operation fails,
@@ -271,12 +1242,7 @@ impl Storage {
 
     /// Index a block's transactions (optimized batch indexing)
     /// This should be called after a block is stored to index all its transactions
-    pub fn index_block(
-        &self,
-        block: &bllvm_protocol::Block,
-        block_hash: &bllvm_protocol::Hash,
-        block_height: u64,
-    ) -> Result<()> {
+    pub fn index_block(&self, block: &Block, block_hash: &Hash, block_height: u64) -> Result<()> {
         self.txindex.index_block(block, block_hash, block_height)
     }
 
@@ -12,6 +12,8 @@ use crate::network::filter_service::BlockFilterService;
 use crate::storage::blockstore::BlockStore;
 #[cfg(feature = "utxo-commitments")]
 use crate::storage::commitment_store::CommitmentStore;
+use crate::storage::database::WriteBatch;
+use crate::storage::utxocache::UtxoCache;
 #[cfg(feature = "utxo-commitments")]
 use crate::storage::utxostore::UtxoStore;
 use anyhow::{anyhow, Result};
@@ -47,6 +49,7 @@ pub struct PruningManager {
     utxostore: Option<Arc<UtxoStore>>,
     #[cfg(feature = "bip158")]
     filter_service: Option<Arc<BlockFilterService>>,
+    utxo_cache: Option<Arc<UtxoCache>>,
     stats: std::sync::Mutex<PruningStats>,
 }
 
@@ -62,6 +65,7 @@ impl PruningManager {
             utxostore: None,
             #[cfg(feature = "bip158")]
             filter_service: None,
+            utxo_cache: None,
             stats: std::sync::Mutex::new(PruningStats::default()),
         }
     }
@@ -81,6 +85,7 @@ impl PruningManager {
             utxostore: Some(utxostore),
             #[cfg(feature = "bip158")]
             filter_service: None,
+            utxo_cache: None,
             stats: std::sync::Mutex::new(PruningStats::default()),
         }
     }
@@ -102,10 +107,20 @@ impl PruningManager {
             utxostore,
             #[cfg(feature = "bip158")]
             filter_service,
+            utxo_cache: None,
             stats: std::sync::Mutex::new(PruningStats::default()),
         }
     }
 
+    /// Flush this UTXO cache before pruning
+    ///
+    /// Blocks connected since the last flush are replayed from their bodies at
+    /// startup, so the cache must be flushed before any of them can be pruned.
+    pub fn with_utxo_cache(mut self, utxo_cache: Arc<UtxoCache>) -> Self {
+        self.utxo_cache = Some(utxo_cache);
+        self
+    }
+
     /// Get pruning statistics
     pub fn get_stats(&self) -> PruningStats {
         self.stats.lock().unwrap().clone()
@@ -284,6 +299,10 @@ impl PruningManager {
             ));
         }
 
+        if let Some(ref utxo_cache) = self.utxo_cache {
+            utxo_cache.flush()?;
+        }
+
         info!(
             "Starting pruning: prune_to_height={}, current_height={}, mode={:?}",
             prune_to_height, current_height, self.config.mode
@@ -372,6 +391,23 @@ impl PruningManager {
         Ok(stats)
     }
 
+    /// Delete whole block files below the prune height
+    ///
+    /// Bodies in the database are removed block by block by the pruning
+    /// modes; bodies in flat block files are only freed with their file.
+    fn prune_block_files(&self, prune_height: u64, stats: &mut PruningStats) -> Result<()> {
+        let pruned = self.blockstore.prune_block_files(prune_height)?;
+        if !pruned.files.is_empty() {
+            debug!(
+                "Deleted block files {:?} ({} blocks, {} bytes)",
+                pruned.files, pruned.blocks, pruned.bytes
+            );
+        }
+        stats.blocks_pruned += pruned.blocks;
+        stats.storage_freed += pruned.bytes;
+        Ok(())
+    }
+
     /// Normal pruning: Keep recent blocks, remove older blocks
     fn prune_normal(
         &self,
@@ -394,17 +430,22 @@ impl PruningManager {
             prune_to_height, keep_from_height, min_recent_blocks, effective_keep_height, actual_prune_height
         );
 
-        // Prune blocks up to actual_prune_height
+        // Prune blocks up to actual_prune_height, committing all removals at once
+        let mut batch = WriteBatch::new();
         for height in 0..actual_prune_height {
             if let Some(hash) = self.blockstore.get_hash_by_height(height)? {
                 // Remove block body (keep header for PoW verification)
-                if let Some(_block) = self.blockstore.get_block(&hash)? {
-                    self.blockstore.remove_block_body(&hash)?;
+                if self.blockstore.has_database_body(&hash)? {
+                    self.blockstore.stage_remove_block_body(&mut batch, &hash);
+                    // Undo data is useless once the body is gone
+                    self.blockstore.stage_remove_undo(&mut batch, &hash);
                     stats.blocks_pruned += 1;
                     stats.storage_freed += 1024; // Approximate block size
                 }
             }
         }
+        self.blockstore.apply_batch(&batch)?;
+        self.prune_block_files(actual_prune_height, &mut stats)?;
 
         // Count kept blocks
         stats.blocks_kept = current_height.saturating_sub(actual_prune_height);
@@ -455,19 +496,21 @@ impl PruningManager {
             }
         }
 
-        // Prune blocks up to actual_prune_height
+        // Prune blocks up to actual_prune_height, committing all removals at once
+        let mut batch = WriteBatch::new();
         for height in 0..actual_prune_height {
             if let Some(hash) = self.blockstore.get_hash_by_height(height)? {
                 // Remove block body (keep header)
-                if let Some(_block) = self.blockstore.get_block(&hash)? {
-                    self.blockstore.remove_block_body(&hash)?;
+                if self.blockstore.has_database_body(&hash)? {
+                    self.blockstore.stage_remove_block_body(&mut batch, &hash);
+                    self.blockstore.stage_remove_undo(&mut batch, &hash);
                     stats.blocks_pruned += 1;
                     stats.storage_freed += 1024;
                 }
 
                 // Remove witnesses if not keeping filtered blocks
                 if !keep_filtered_blocks {
-                    self.blockstore.remove_witness(&hash)?;
+                    self.blockstore.stage_remove_witness(&mut batch, &hash);
                 }
 
                 // Handle BIP158 filters if configured
@@ -485,6 +528,9 @@ impl PruningManager {
             }
         }
 
+        self.blockstore.apply_batch(&batch)?;
+        self.prune_block_files(actual_prune_height, &mut stats)?;
+
         stats.blocks_kept = current_height.saturating_sub(actual_prune_height);
         stats.headers_kept = current_height;
 
@@ -518,21 +564,21 @@ impl PruningManager {
             prune_to_height, keep_bodies_from_height, actual_prune_height
         );
 
-        // Prune blocks up to actual_prune_height
+        // Prune blocks up to actual_prune_height, committing all removals at once
+        let mut batch = WriteBatch::new();
         for height in 0..actual_prune_height {
             if let Some(hash) = self.blockstore.get_hash_by_height(height)? {
                 // Remove block body if not keeping from this height
-                if height < keep_bodies_from_height {
-                    if let Some(_block) = self.blockstore.get_block(&hash)? {
-                        self.blockstore.remove_block_body(&hash)?;
-                        stats.blocks_pruned += 1;
-                        stats.storage_freed += 1024;
-                    }
+                if height < keep_bodies_from_height && self.blockstore.has_database_body(&hash)? {
+                    self.blockstore.stage_remove_block_body(&mut batch, &hash);
+                    self.blockstore.stage_remove_undo(&mut batch, &hash);
+                    stats.blocks_pruned += 1;
+                    stats.storage_freed += 1024;
                 }
 
                 // Remove witnesses if not keeping
                 if !keep_witnesses {
-                    self.blockstore.remove_witness(&hash)?;
+                    self.blockstore.stage_remove_witness(&mut batch, &hash);
                 }
 
                 // Handle commitments if enabled
@@ -586,6 +632,9 @@ impl PruningManager {
             }
         }
 
+        self.blockstore.apply_batch(&batch)?;
+        self.prune_block_files(actual_prune_height, &mut stats)?;
+
         stats.blocks_kept = current_height.saturating_sub(actual_prune_height);
         stats.headers_kept = if keep_headers { current_height } else { 0 };
 
@@ -0,0 +1,316 @@
+//! Rebuilding chain state from stored blocks
+//!
+//! `reindex` rebuilds the block index, height index, chain state, UTXO set and
+//! background indexes from the block bodies on disk, connecting the most-work
+//! chain among them. `reindex-chainstate` keeps the block and height indexes
+//! and replays the active chain into a fresh UTXO set and chain state. Either
+//! way every block is validated again as it is connected, and a block that
+//! fails is marked failed in the block index, ending the rebuilt chain below
+//! it. A full reindex also moves blocks stored by databases from before the
+//! consensus block hash encoding to their consensus hashes.
+
+use super::blockindex::BlockStatus;
+use super::chainwork::ChainWork;
+use super::Storage;
+use crate::config::ReindexConfig;
+use crate::node::metrics::{MetricsCollector, ReindexMetrics};
+use crate::node::reorg::ReorgEngine;
+use crate::storage::database::WriteBatch;
+use anyhow::{anyhow, Result};
+use bllvm_protocol::{BitcoinProtocolEngine, BlockHeader, Hash};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// Blocks connected between progress reports
+const PROGRESS_INTERVAL: u64 = 1000;
+
+/// What a reindex rebuilds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReindexMode {
+    /// Block index, chain state, UTXO set and background indexes, from the
+    /// stored block bodies
+    Full,
+    /// UTXO set and chain state, by replaying the active chain
+    Chainstate,
+}
+
+impl ReindexMode {
+    /// Mode selected by the startup configuration, if any
+    pub fn from_config(config: ReindexConfig) -> Option<Self> {
+        match config {
+            ReindexConfig::Off => None,
+            ReindexConfig::Reindex => Some(Self::Full),
+            ReindexConfig::ReindexChainstate => Some(Self::Chainstate),
+        }
+    }
+
+    /// Name of the mode as used in logs and metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Full => "reindex",
+            Self::Chainstate => "reindex-chainstate",
+        }
+    }
+}
+
+/// Outcome of a reindex
+#[derive(Debug, Clone, Default)]
+pub struct ReindexSummary {
+    /// Stored blocks added to the block index (full reindex only)
+    pub blocks_indexed: u64,
+    pub blocks_connected: u64,
+    /// Active tip height after the reindex
+    pub tip_height: Option<u64>,
+    /// Block that failed validation, if any
+    pub invalid_block: Option<Hash>,
+}
+
+impl Storage {
+    /// Rebuild chain state from the stored blocks
+    ///
+    /// Blocks are validated with `protocol` as they are connected. Progress is
+    /// published as [`ReindexMetrics`] in the storage metrics of `metrics`.
+    /// Reindexing a pruned node fails: a chainstate reindex checks that every
+    /// block body is available before anything is cleared, and a full reindex
+    /// can only connect the stored blocks reachable from genesis.
+    pub fn reindex(
+        self: &Arc<Self>,
+        mode: ReindexMode,
+        protocol: &BitcoinProtocolEngine,
+        metrics: Option<&MetricsCollector>,
+    ) -> Result<ReindexSummary> {
+        info!("Starting {}", mode.name());
+        let mut summary = ReindexSummary::default();
+
+        let path = match mode {
+            ReindexMode::Chainstate => {
+                if !self.chainstate.has_current_block_hashes()? {
+                    return Err(anyhow!(
+                        "Stored blocks use an older block hash encoding; a full reindex is required"
+                    ));
+                }
+                let path = self.active_chain_hashes()?;
+                self.reset_chain_state()?;
+                path
+            }
+            ReindexMode::Full => {
+                // Databases from before the consensus block hash encoding
+                let rehashed = self.blockstore.rehash_stored_blocks()?;
+                if rehashed > 0 {
+                    info!("Rehashed {} stored blocks", rehashed);
+                }
+                let headers = self.stored_block_headers()?;
+                self.reset_chain_state()?;
+                self.block_index.clear()?;
+                self.blockstore.clear_chain_index()?;
+                self.indexes.reset()?;
+                let (indexed, path) = self.index_stored_blocks(&headers)?;
+                summary.blocks_indexed = indexed;
+                info!("Indexed {} stored blocks", indexed);
+                path
+            }
+        };
+
+        let target_height = path.len().checked_sub(1).map(|height| height as u64);
+        let report = |connected: u64, height: Option<u64>, complete: bool| {
+            if let Some(metrics) = metrics {
+                metrics.update_storage(|m| {
+                    m.reindex = Some(ReindexMetrics {
+                        mode: mode.name().to_string(),
+                        blocks_connected: connected,
+                        height,
+                        target_height,
+                        complete,
+                    });
+                });
+            }
+        };
+        report(0, None, false);
+
+        let blocks = self.blocks();
+        let engine = ReorgEngine::new(&blocks, protocol, Some(self));
+        let mut utxos = self.utxo_cache();
+        for (height, hash) in path.iter().enumerate() {
+            let height = height as u64;
+            let block = self.blockstore.get_block(hash)?.ok_or_else(|| {
+                anyhow!(
+                    "Cannot reindex block {} at height {}: body not available (pruned?)",
+                    hex::encode(hash),
+                    height
+                )
+            })?;
+            let witnesses = self
+                .blockstore
+                .get_witness(hash)?
+                .unwrap_or_else(|| block.transactions.iter().map(|_| Vec::new()).collect());
+
+            if !engine.connect_block(&block, &witnesses, height, &mut utxos)? {
+                error!(
+                    "Block {} at height {} failed validation during {}",
+                    hex::encode(hash),
+                    height,
+                    mode.name()
+                );
+                self.block_index.mark_failed(hash)?;
+                summary.invalid_block = Some(*hash);
+                break;
+            }
+            summary.blocks_connected += 1;
+            summary.tip_height = Some(height);
+            if summary.blocks_connected % PROGRESS_INTERVAL == 0 {
+                info!(
+                    "{}: height {} of {}",
+                    mode.name(),
+                    height,
+                    target_height.unwrap_or(0)
+                );
+                report(summary.blocks_connected, summary.tip_height, false);
+            }
+        }
+
+        if summary.invalid_block.is_some() {
+            match mode {
+                // The height index still lists the rest of the old chain
+                ReindexMode::Chainstate => self.truncate_height_index(summary.tip_height)?,
+                // Another stored branch may now be the best valid chain
+                ReindexMode::Full => {
+                    if let Some(tip_height) = summary.tip_height {
+                        if let Some(result) = engine.activate_best_chain(tip_height, &mut utxos)? {
+                            summary.tip_height = Some(result.new_height);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.flush()?;
+        report(summary.blocks_connected, summary.tip_height, true);
+        info!(
+            "Finished {}: {} blocks connected, tip height {:?}",
+            mode.name(),
+            summary.blocks_connected,
+            summary.tip_height
+        );
+        Ok(summary)
+    }
+
+    /// Hashes of the active chain from genesis, checking every body is stored
+    fn active_chain_hashes(&self) -> Result<Vec<Hash>> {
+        let tip_height = match self.chainstate.get_height()? {
+            Some(height) => Some(height),
+            None => self.blockstore.get_indexed_tip_height()?,
+        };
+        let mut path = Vec::new();
+        let mut prev_hash = [0u8; 32];
+        for height in 0..=tip_height.unwrap_or(0) {
+            let hash = match self.blockstore.get_hash_by_height(height)? {
+                Some(hash) => hash,
+                None => break,
+            };
+            match self.blockstore.get_header(&hash)? {
+                Some(header) if header.prev_block_hash == prev_hash => {}
+                _ => {
+                    warn!(
+                        "Height index breaks at height {}; replaying the blocks below it",
+                        height
+                    );
+                    break;
+                }
+            }
+            if !self.blockstore.has_block_body(&hash)? {
+                return Err(anyhow!(
+                    "Cannot reindex-chainstate: block {} at height {} is not stored (pruned?)",
+                    hex::encode(hash),
+                    height
+                ));
+            }
+            path.push(hash);
+            prev_hash = hash;
+        }
+        Ok(path)
+    }
+
+    /// Headers of every block whose body is stored
+    fn stored_block_headers(&self) -> Result<HashMap<Hash, BlockHeader>> {
+        let mut headers = HashMap::new();
+        for hash in self.blockstore.stored_block_hashes()? {
+            if let Some(header) = self.blockstore.get_header(&hash)? {
+                headers.insert(hash, header);
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Clear the UTXO sets and chain state, keeping the network parameters
+    fn reset_chain_state(&self) -> Result<()> {
+        let params = self.chainstate.get_chain_params()?;
+        self.chainstate.reset()?;
+        self.chainstate.store_chain_params(&params)?;
+        self.utxostore.clear()?;
+        self.utxo_cache.reset()?;
+        self.background_utxo_cache.store().clear()?;
+        self.background_utxo_cache.reset()
+    }
+
+    /// Add stored blocks to the empty block index, parents first
+    ///
+    /// Blocks that do not descend from a genesis block (all-zero parent) are
+    /// left out. Returns the number of blocks indexed and the most-work chain
+    /// among them, from genesis.
+    fn index_stored_blocks(
+        &self,
+        headers: &HashMap<Hash, BlockHeader>,
+    ) -> Result<(u64, Vec<Hash>)> {
+        let mut children: HashMap<Hash, Vec<Hash>> = HashMap::new();
+        for (hash, header) in headers {
+            children
+                .entry(header.prev_block_hash)
+                .or_default()
+                .push(*hash);
+        }
+
+        let mut indexed = 0;
+        let mut best: Option<(ChainWork, Hash)> = None;
+        let mut queue: Vec<Hash> = children.get(&[0u8; 32]).cloned().unwrap_or_default();
+        while let Some(hash) = queue.pop() {
+            let mut entry = self.block_index.accept_header(&hash, &headers[&hash])?;
+            entry.status |= BlockStatus::HAVE_DATA;
+            self.block_index.store_entry(&entry)?;
+            indexed += 1;
+
+            if best.map_or(true, |(work, _)| entry.chainwork > work) {
+                best = Some((entry.chainwork, hash));
+            }
+            if let Some(hashes) = children.get(&hash) {
+                queue.extend(hashes);
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut current = best.map(|(_, hash)| hash);
+        while let Some(hash) = current {
+            path.push(hash);
+            current = headers
+                .get(&hash)
+                .map(|header| header.prev_block_hash)
+                .filter(|prev| *prev != [0u8; 32]);
+        }
+        path.reverse();
+        Ok((indexed, path))
+    }
+
+    /// Remove height index entries above the active tip
+    fn truncate_height_index(&self, tip_height: Option<u64>) -> Result<()> {
+        let indexed_tip = match self.blockstore.get_indexed_tip_height()? {
+            Some(height) => height,
+            None => return Ok(()),
+        };
+        let first = tip_height.map_or(0, |height| height + 1);
+        let mut batch = WriteBatch::new();
+        for height in (first..=indexed_tip).rev() {
+            self.blockstore.stage_remove_height(&mut batch, height)?;
+        }
+        self.db.apply_batch(&batch)
+    }
+}
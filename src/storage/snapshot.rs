@@ -0,0 +1,262 @@
+//! AssumeUTXO snapshot files
+//!
+//! A snapshot is the UTXO set at a block, written out so that a new node can
+//! start from that block instead of connecting every block since genesis. The
+//! file holds a magic and format version, the bincode-encoded
+//! [`SnapshotMetadata`], then one `(OutPoint, UTXO)` pair per coin in outpoint
+//! order, so a snapshot can be written and read one coin at a time. The
+//! metadata carries the content hash of the coins (Bitcoin Core's
+//! hash_serialized_3, [`UtxoHashFormat::Serialized3`]), which is checked
+//! against the coins read and against the network's table of known snapshots.
+//! Coins the hash leaves out (unspendable outputs and the genesis coinbase) are
+//! not part of a snapshot.
+
+use crate::storage::chainstate::AssumeUtxoData;
+use crate::storage::hashing::{UtxoHashFormat, UtxoSetHasher};
+use anyhow::{anyhow, Result};
+use bllvm_protocol::{BlockHeader, Hash, OutPoint, UtxoSet, UTXO};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// Magic bytes at the start of every snapshot file
+pub const SNAPSHOT_MAGIC: [u8; 5] = *b"utxo\xff";
+
+/// Current snapshot file format version
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+/// Upper bound on coins preallocated while reading, so a corrupt count cannot
+/// exhaust memory before the coins themselves run out
+const MAX_PREALLOCATED_COINS: u64 = 1_000_000;
+
+/// Header of a snapshot file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotMetadata {
+    /// Network the snapshot was taken on
+    pub network: String,
+    /// Block the UTXO set corresponds to
+    pub base_hash: Hash,
+    pub base_height: u64,
+    /// Headers ending at the base block, oldest first, so the blocks after the
+    /// base can be checked against median time-past
+    pub recent_headers: Vec<BlockHeader>,
+    pub coins_count: u64,
+    /// Content hash of the coins
+    pub utxo_hash: Hash,
+}
+
+/// Background validation state of a chain state loaded from a snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnapshotStatus {
+    /// History up to the base block is still being validated
+    Unvalidated,
+    /// Validating history reproduced the snapshot's UTXO set
+    Validated,
+    /// Validating history produced a different UTXO set
+    Invalid,
+}
+
+/// Record of the snapshot the active chain state was loaded from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotBase {
+    pub base_hash: Hash,
+    pub base_height: u64,
+    pub utxo_hash: Hash,
+    pub status: SnapshotStatus,
+}
+
+/// Write a snapshot of `utxo_set`
+///
+/// `metadata.coins_count` and `metadata.utxo_hash` must describe `utxo_set`.
+pub fn write_snapshot<W: Write>(
+    mut writer: W,
+    metadata: &SnapshotMetadata,
+    utxo_set: &UtxoSet,
+) -> Result<()> {
+    write_snapshot_header(&mut writer, metadata)?;
+    let mut entries: Vec<_> = utxo_set.iter().collect();
+    entries.sort_by(|(a, _), (b, _)| (a.hash, a.index).cmp(&(b.hash, b.index)));
+    for (outpoint, utxo) in entries {
+        write_snapshot_coin(&mut writer, outpoint, utxo)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the magic, format version and metadata that start a snapshot file
+///
+/// The encoded header has the same length for any coin count and content
+/// hash, so a writer that only knows them after the coins can write a
+/// placeholder first and overwrite it at the end.
+pub fn write_snapshot_header<W: Write>(mut writer: W, metadata: &SnapshotMetadata) -> Result<()> {
+    writer.write_all(&SNAPSHOT_MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    bincode::serialize_into(&mut writer, metadata)?;
+    Ok(())
+}
+
+/// Write one coin; coins must be written in outpoint order
+pub fn write_snapshot_coin<W: Write>(
+    mut writer: W,
+    outpoint: &OutPoint,
+    utxo: &UTXO,
+) -> Result<()> {
+    bincode::serialize_into(&mut writer, &(outpoint, utxo))?;
+    Ok(())
+}
+
+/// Read a snapshot, checking its format and that the coins match its content hash
+pub fn read_snapshot<R: Read>(mut reader: R) -> Result<(SnapshotMetadata, UtxoSet)> {
+    let metadata = read_snapshot_header(&mut reader)?;
+    let mut utxo_set =
+        HashMap::with_capacity(metadata.coins_count.min(MAX_PREALLOCATED_COINS) as usize);
+    let mut coins = SnapshotCoinReader::new(reader, &metadata);
+    while let Some((outpoint, utxo)) = coins.next_coin()? {
+        utxo_set.insert(outpoint, utxo);
+    }
+    coins.finish()?;
+    Ok((metadata, utxo_set))
+}
+
+/// Read the header of a snapshot file, checking its magic and format version
+pub fn read_snapshot_header<R: Read>(mut reader: R) -> Result<SnapshotMetadata> {
+    let mut magic = [0u8; 5];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|e| anyhow!("Failed to read snapshot header: {}", e))?;
+    if magic != SNAPSHOT_MAGIC {
+        return Err(anyhow!("Not a UTXO snapshot file (bad magic)"));
+    }
+    let mut version = [0u8; 2];
+    reader.read_exact(&mut version)?;
+    let version = u16::from_le_bytes(version);
+    if version != SNAPSHOT_VERSION {
+        return Err(anyhow!(
+            "Unsupported snapshot version {} (expected {})",
+            version,
+            SNAPSHOT_VERSION
+        ));
+    }
+    bincode::deserialize_from(&mut reader)
+        .map_err(|e| anyhow!("Failed to read snapshot metadata: {}", e))
+}
+
+/// Reads the coins that follow a snapshot header one at a time
+///
+/// Each coin is checked as it is read: coins must be in strictly increasing
+/// outpoint order and covered by the content hash. Once every coin has been
+/// read, [`Self::finish`] checks the end of the file and the content hash.
+pub struct SnapshotCoinReader<R> {
+    reader: R,
+    coins_count: u64,
+    coins_read: u64,
+    last: Option<(Hash, u64)>,
+    hasher: UtxoSetHasher,
+    utxo_hash: Hash,
+}
+
+impl<R: Read> SnapshotCoinReader<R> {
+    pub fn new(reader: R, metadata: &SnapshotMetadata) -> Self {
+        Self {
+            reader,
+            coins_count: metadata.coins_count,
+            coins_read: 0,
+            last: None,
+            hasher: UtxoSetHasher::new(UtxoHashFormat::Serialized3),
+            utxo_hash: metadata.utxo_hash,
+        }
+    }
+
+    /// Read the next coin, or `None` once all of them have been read
+    pub fn next_coin(&mut self) -> Result<Option<(OutPoint, UTXO)>> {
+        if self.coins_read == self.coins_count {
+            return Ok(None);
+        }
+        let index = self.coins_read;
+        let (outpoint, utxo): (OutPoint, UTXO) = bincode::deserialize_from(&mut self.reader)
+            .map_err(|e| {
+                anyhow!(
+                    "Snapshot truncated: failed to read coin {} of {}: {}",
+                    index,
+                    self.coins_count,
+                    e
+                )
+            })?;
+        if !UtxoHashFormat::Serialized3.includes(&utxo) {
+            return Err(anyhow!(
+                "Snapshot coin {} is not covered by the content hash",
+                index
+            ));
+        }
+        let key = (outpoint.hash, outpoint.index);
+        if self.last.is_some_and(|last| last >= key) {
+            return Err(anyhow!(
+                "Snapshot coin {} is out of order or repeats the previous one",
+                index
+            ));
+        }
+        self.last = Some(key);
+        self.hasher.add(&outpoint, &utxo);
+        self.coins_read += 1;
+        Ok(Some((outpoint, utxo)))
+    }
+
+    /// Check that the file ends after the last coin and the coins match the
+    /// content hash
+    pub fn finish(mut self) -> Result<()> {
+        if self.coins_read != self.coins_count {
+            return Err(anyhow!(
+                "Only {} of {} snapshot coins were read",
+                self.coins_read,
+                self.coins_count
+            ));
+        }
+        let mut trailing = [0u8; 1];
+        if self.reader.read(&mut trailing)? != 0 {
+            return Err(anyhow!(
+                "Snapshot has data after its {} coins",
+                self.coins_count
+            ));
+        }
+        let utxo_hash = self.hasher.finish();
+        if utxo_hash != self.utxo_hash {
+            return Err(anyhow!(
+                "Snapshot content hash mismatch: header says {}, coins hash to {}",
+                hex::encode(self.utxo_hash),
+                hex::encode(utxo_hash)
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Check a snapshot against a network's table of known snapshots
+pub fn verify_known_snapshot(metadata: &SnapshotMetadata, known: &[AssumeUtxoData]) -> Result<()> {
+    let expected = known
+        .iter()
+        .find(|data| data.height == metadata.base_height)
+        .ok_or_else(|| {
+            anyhow!(
+                "No known {} snapshot at height {}",
+                metadata.network,
+                metadata.base_height
+            )
+        })?;
+    if expected.block_hash != metadata.base_hash {
+        return Err(anyhow!(
+            "Snapshot base block {} does not match known block {} at height {}",
+            hex::encode(metadata.base_hash),
+            hex::encode(expected.block_hash),
+            metadata.base_height
+        ));
+    }
+    if expected.utxo_hash != metadata.utxo_hash {
+        return Err(anyhow!(
+            "Snapshot at height {} has content hash {}, expected {}",
+            metadata.base_height,
+            hex::encode(metadata.utxo_hash),
+            hex::encode(expected.utxo_hash)
+        ));
+    }
+    Ok(())
+}
@@ -2,23 +2,34 @@
 //!
 //! Provides fast lookup of transactions by hash and maintains transaction metadata.
 
-use crate::storage::database::{Database, Tree};
+use crate::storage::blockstore::BlockUndo;
+use crate::storage::database::{Database, Tree, WriteBatch};
 use crate::storage::hashing::sha256;
+use crate::storage::indexer::BackgroundIndex;
 use anyhow::Result;
-use bllvm_protocol::{Hash, Transaction};
+use bllvm_protocol::{Block, Hash, Transaction};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
+const TX_BY_HASH_TREE: &str = "tx_by_hash";
+const TX_BY_BLOCK_TREE: &str = "tx_by_block";
+const TX_METADATA_TREE: &str = "tx_metadata";
+
+const ADDRESS_TX_INDEX_TREE: &str = "address_tx_index";
+const ADDRESS_OUTPUT_INDEX_TREE: &str = "address_output_index";
+const ADDRESS_INPUT_INDEX_TREE: &str = "address_input_index";
+const VALUE_INDEX_TREE: &str = "value_index";
+
 /// Address output entry (internal helper)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct AddressOutputEntry {
     tx_hash: Hash,
     output_index: u32,
 }
 
 /// Value entry (internal helper)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct ValueEntry {
     tx_hash: Hash,
     output_index: u32,
@@ -48,7 +59,6 @@ pub struct TxMetadata {
 
 /// Transaction index storage manager
 pub struct TxIndex {
-    #[allow(dead_code)]
     db: Arc<dyn Database>,
     tx_by_hash: Arc<dyn Tree>,
     tx_by_block: Arc<dyn Tree>,
@@ -79,17 +89,17 @@ impl TxIndex {
         enable_address_index: bool,
         enable_value_index: bool,
     ) -> Result<Self> {
-        let tx_by_hash = Arc::from(db.open_tree("tx_by_hash")?);
-        let tx_by_block = Arc::from(db.open_tree("tx_by_block")?);
-        let tx_metadata = Arc::from(db.open_tree("tx_metadata")?);
+        let tx_by_hash = Arc::from(db.open_tree(TX_BY_HASH_TREE)?);
+        let tx_by_block = Arc::from(db.open_tree(TX_BY_BLOCK_TREE)?);
+        let tx_metadata = Arc::from(db.open_tree(TX_METADATA_TREE)?);
 
         // Address indexing trees (always create, but only use if enabled)
-        let address_tx_index = Arc::from(db.open_tree("address_tx_index")?);
-        let address_output_index = Arc::from(db.open_tree("address_output_index")?);
-        let address_input_index = Arc::from(db.open_tree("address_input_index")?);
+        let address_tx_index = Arc::from(db.open_tree(ADDRESS_TX_INDEX_TREE)?);
+        let address_output_index = Arc::from(db.open_tree(ADDRESS_OUTPUT_INDEX_TREE)?);
+        let address_input_index = Arc::from(db.open_tree(ADDRESS_INPUT_INDEX_TREE)?);
 
         // Value indexing tree (always create, but only use if enabled)
-        let value_index = Arc::from(db.open_tree("value_index")?);
+        let value_index = Arc::from(db.open_tree(VALUE_INDEX_TREE)?);
 
         Ok(Self {
             db,
@@ -125,6 +135,59 @@ impl TxIndex {
         Ok(())
     }
 
+    /// Stage the primary index entries (by hash, by block, metadata) for a block
+    ///
+    /// Address and value indexes are derived data and are not staged; apply them
+    /// with [`Self::index_block_derived`] once the batch has been committed.
+    pub fn stage_block(
+        &self,
+        batch: &mut WriteBatch,
+        block: &bllvm_protocol::Block,
+        block_hash: &Hash,
+        block_height: u64,
+    ) -> Result<()> {
+        for (tx_index, tx) in block.transactions.iter().enumerate() {
+            self.stage_transaction(batch, tx, block_hash, block_height, tx_index as u32)?;
+        }
+        Ok(())
+    }
+
+    /// Update the optional address and value indexes for a block
+    pub fn index_block_derived(&self, block: &bllvm_protocol::Block) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        self.stage_block_derived(&mut batch, block)?;
+        self.db.apply_batch(&batch)
+    }
+
+    /// Stage the optional address and value index entries for a block
+    pub fn stage_block_derived(
+        &self,
+        batch: &mut WriteBatch,
+        block: &bllvm_protocol::Block,
+    ) -> Result<()> {
+        if !self.has_derived_indexes() {
+            return Ok(());
+        }
+        self.stage_derived(batch, &block_txs(block), true)
+    }
+
+    /// Stage removal of a block's address and value index entries
+    pub fn stage_remove_block_derived(
+        &self,
+        batch: &mut WriteBatch,
+        block: &bllvm_protocol::Block,
+    ) -> Result<()> {
+        if !self.has_derived_indexes() {
+            return Ok(());
+        }
+        self.stage_derived(batch, &block_txs(block), false)
+    }
+
+    /// Whether the address or value index is enabled
+    pub fn has_derived_indexes(&self) -> bool {
+        self.enable_address_index || self.enable_value_index
+    }
+
     /// Index a transaction
     ///
     /// Performance optimization: Batches all database writes for a single transaction
@@ -136,6 +199,23 @@ impl TxIndex {
         block_height: u64,
         tx_index: u32,
     ) -> Result<()> {
+        // Primary entries are committed together so lookups never see a partial record
+        let mut batch = WriteBatch::new();
+        let tx_hash = self.stage_transaction(&mut batch, tx, block_hash, block_height, tx_index)?;
+        self.db.apply_batch(&batch)?;
+
+        self.index_derived(tx, &tx_hash)
+    }
+
+    /// Stage the primary index entries for a transaction, returning its hash
+    pub fn stage_transaction(
+        &self,
+        batch: &mut WriteBatch,
+        tx: &Transaction,
+        block_hash: &Hash,
+        block_height: u64,
+        tx_index: u32,
+    ) -> Result<Hash> {
         // Use the standard transaction ID calculation from bllvm-protocol
         let tx_hash = bllvm_protocol::block::calculate_tx_id(tx);
 
@@ -154,210 +234,98 @@ impl TxIndex {
 
         let block_key = self.block_tx_key(block_hash, tx_index);
 
-        // Batch all database writes together (reduces I/O overhead)
-        // All writes are sequential but grouped to minimize context switching
-        self.tx_by_hash.insert(tx_hash.as_slice(), &tx_data)?;
-        self.tx_metadata
-            .insert(tx_hash.as_slice(), &metadata_data)?;
-        self.tx_by_block.insert(&block_key, tx_hash.as_slice())?;
+        batch.insert(TX_BY_HASH_TREE, &tx_hash, &tx_data);
+        batch.insert(TX_METADATA_TREE, &tx_hash, &metadata_data);
+        batch.insert(TX_BY_BLOCK_TREE, &block_key, &tx_hash);
 
-        // Advanced indexing: Address index (if enabled)
-        // These already batch internally, but we group them here for consistency
-        if self.enable_address_index {
-            self.index_addresses(tx, &tx_hash)?;
-        }
+        Ok(tx_hash)
+    }
 
-        // Advanced indexing: Value index (if enabled)
-        // These already batch internally, but we group them here for consistency
-        if self.enable_value_index {
-            self.index_values(tx, &tx_hash)?;
+    /// Update the optional address and value indexes for a transaction
+    fn index_derived(&self, tx: &Transaction, tx_hash: &Hash) -> Result<()> {
+        if !self.enable_address_index && !self.enable_value_index {
+            return Ok(());
         }
-
-        Ok(())
+        let mut batch = WriteBatch::new();
+        self.stage_derived(&mut batch, &[(*tx_hash, tx)], true)?;
+        self.db.apply_batch(&batch)
     }
 
-    /// Index addresses (script_pubkeys) from transaction outputs
+    /// Stage address and value index updates for a block's transactions
     ///
-    /// Performance optimizations:
-    /// - Batches updates per address (one DB read/write per unique address instead of per output)
-    /// - Uses HashSet for O(1) duplicate checking instead of O(n) linear search
-    /// - Only writes to DB if updates were made
-    ///
-    /// This reduces DB I/O from O(outputs) to O(unique_addresses) per transaction.
-    fn index_addresses(&self, tx: &Transaction, tx_hash: &Hash) -> Result<()> {
-        use std::collections::HashMap;
-
-        // Batch updates by address_hash to minimize DB operations
-        let mut address_tx_updates: HashMap<[u8; 32], HashSet<Hash>> = HashMap::new();
-        let mut address_output_updates: HashMap<[u8; 32], HashSet<(Hash, u32)>> = HashMap::new();
-
-        // Collect all updates for this transaction
-        for (output_index, output) in tx.outputs.iter().enumerate() {
-            let script_pubkey = &output.script_pubkey;
-            let address_hash = sha256(script_pubkey);
-
-            // Track tx_hash for this address
-            address_tx_updates
-                .entry(address_hash)
-                .or_default()
-                .insert(*tx_hash);
-
-            // Track (tx_hash, output_index) for this address
-            address_output_updates
-                .entry(address_hash)
-                .or_default()
-                .insert((*tx_hash, output_index as u32));
-        }
-
-        // Apply batched updates (one DB read/write per unique address)
-        for (address_hash, new_tx_hashes) in address_tx_updates {
-            // Read existing transactions for this address
-            let mut existing_txs = self.get_address_transactions(&address_hash)?;
-            let existing_set: HashSet<Hash> = existing_txs.iter().copied().collect();
-
-            // Add new transactions (using HashSet for O(1) deduplication)
-            let mut updated = false;
-            for tx_hash in new_tx_hashes {
-                if !existing_set.contains(&tx_hash) {
-                    existing_txs.push(tx_hash);
-                    updated = true;
-                }
-            }
-
-            // Write back only if updated
-            if updated {
-                let tx_list_data = bincode::serialize(&existing_txs)?;
-                self.address_tx_index.insert(&address_hash, &tx_list_data)?;
-            }
-        }
-
-        for (address_hash, new_outputs) in address_output_updates {
-            // Read existing outputs for this address
-            let existing_outputs = self.get_address_outputs(&address_hash)?;
-            let existing_set: HashSet<(Hash, u32)> = existing_outputs
-                .iter()
-                .map(|o| (o.tx_hash, o.output_index))
-                .collect();
-
-            // Add new outputs (using HashSet for O(1) deduplication)
-            let mut updated_outputs = existing_outputs;
-            let mut updated = false;
-            for (tx_hash, output_index) in new_outputs {
-                if !existing_set.contains(&(tx_hash, output_index)) {
-                    updated_outputs.push(AddressOutputEntry {
-                        tx_hash,
-                        output_index,
-                    });
-                    updated = true;
+    /// Adds the block's outputs when `connect` is set and removes them
+    /// otherwise. Updates are grouped per address and per value bucket, so each
+    /// key is read and written once per block.
+    fn stage_derived(
+        &self,
+        batch: &mut WriteBatch,
+        txs: &[(Hash, &Transaction)],
+        connect: bool,
+    ) -> Result<()> {
+        let mut address_txs: HashMap<[u8; 32], HashSet<Hash>> = HashMap::new();
+        let mut address_outputs: HashMap<[u8; 32], Vec<AddressOutputEntry>> = HashMap::new();
+        let mut value_entries: HashMap<u64, Vec<ValueEntry>> = HashMap::new();
+        for (tx_hash, tx) in txs {
+            for (output_index, output) in tx.outputs.iter().enumerate() {
+                let output_index = output_index as u32;
+                if self.enable_address_index {
+                    let address_hash = sha256(&output.script_pubkey);
+                    address_txs
+                        .entry(address_hash)
+                        .or_default()
+                        .insert(*tx_hash);
+                    address_outputs
+                        .entry(address_hash)
+                        .or_default()
+                        .push(AddressOutputEntry {
+                            tx_hash: *tx_hash,
+                            output_index,
+                        });
                 }
-            }
-
-            // Write back only if updated
-            if updated {
-                #[derive(Serialize, Deserialize)]
-                struct AddressOutputSer {
-                    tx_hash: Hash,
-                    output_index: u32,
+                if self.enable_value_index {
+                    let value = output.value as u64;
+                    value_entries
+                        .entry(value_to_bucket(value))
+                        .or_default()
+                        .push(ValueEntry {
+                            tx_hash: *tx_hash,
+                            output_index,
+                            value,
+                        });
                 }
-                let output_list: Vec<AddressOutputSer> = updated_outputs
-                    .iter()
-                    .map(|o| AddressOutputSer {
-                        tx_hash: o.tx_hash,
-                        output_index: o.output_index,
-                    })
-                    .collect();
-                let output_list_data = bincode::serialize(&output_list)?;
-                self.address_output_index
-                    .insert(&address_hash, &output_list_data)?;
             }
         }
 
-        // Note: Input indexing requires UTXO lookup to get script_pubkey from prevout
-        // This is more complex and can be added later if needed
-
-        Ok(())
-    }
-
-    /// Index values from transaction outputs
-    ///
-    /// Performance optimizations:
-    /// - Batches updates per bucket (one DB read/write per unique bucket instead of per output)
-    /// - Uses HashSet for O(1) duplicate checking instead of O(n) linear search
-    /// - Only writes to DB if updates were made
-    ///
-    /// This reduces DB I/O from O(outputs) to O(unique_buckets) per transaction.
-    fn index_values(&self, tx: &Transaction, tx_hash: &Hash) -> Result<()> {
-        use std::collections::HashMap;
-
-        // Use logarithmic bucketing for value ranges
-        // Buckets: 0-1000, 1000-10000, 10000-100000, 100000-1000000, 1000000-10000000, etc.
-        fn value_to_bucket(value: u64) -> u64 {
-            if value == 0 {
-                return 0;
+        for (address_hash, tx_hashes) in address_txs {
+            let mut existing = self.get_address_transactions(&address_hash)?;
+            if connect {
+                let known: HashSet<Hash> = existing.iter().copied().collect();
+                existing.extend(tx_hashes.into_iter().filter(|hash| !known.contains(hash)));
+            } else {
+                existing.retain(|hash| !tx_hashes.contains(hash));
             }
-            // Logarithmic bucketing: bucket = floor(log10(value)) * 1000
-            let log10 = (value as f64).log10().floor() as u64;
-            (log10 + 1) * 1000
+            stage_list(batch, ADDRESS_TX_INDEX_TREE, &address_hash, &existing)?;
         }
 
-        // Batch updates by bucket to minimize DB operations
-        let mut bucket_updates: HashMap<u64, Vec<(Hash, u32, u64)>> = HashMap::new();
-
-        // Collect all updates for this transaction
-        for (output_index, output) in tx.outputs.iter().enumerate() {
-            let value = output.value as u64;
-            let bucket = value_to_bucket(value);
-
-            bucket_updates
-                .entry(bucket)
-                .or_default()
-                .push((*tx_hash, output_index as u32, value));
+        for (address_hash, outputs) in address_outputs {
+            let existing = self.get_address_outputs(&address_hash)?;
+            let updated = merge_entries(existing, outputs, connect, |entry| {
+                (entry.tx_hash, entry.output_index)
+            });
+            stage_list(batch, ADDRESS_OUTPUT_INDEX_TREE, &address_hash, &updated)?;
         }
 
-        // Apply batched updates (one DB read/write per unique bucket)
-        for (bucket, new_entries) in bucket_updates {
-            // Read existing entries for this bucket
-            let mut existing_entries = self.get_value_entries(&bucket)?;
-            let existing_set: HashSet<(Hash, u32)> = existing_entries
-                .iter()
-                .map(|e| (e.tx_hash, e.output_index))
-                .collect();
-
-            // Add new entries (using HashSet for O(1) deduplication)
-            let mut updated = false;
-            for (tx_hash, output_index, value) in new_entries {
-                if !existing_set.contains(&(tx_hash, output_index)) {
-                    existing_entries.push(ValueEntry {
-                        tx_hash,
-                        output_index,
-                        value,
-                    });
-                    updated = true;
-                }
-            }
-
-            // Write back only if updated
-            if updated {
-                #[derive(Serialize, Deserialize)]
-                struct ValueEntrySer {
-                    tx_hash: Hash,
-                    output_index: u32,
-                    value: u64,
-                }
-                let entry_list: Vec<ValueEntrySer> = existing_entries
-                    .iter()
-                    .map(|e| ValueEntrySer {
-                        tx_hash: e.tx_hash,
-                        output_index: e.output_index,
-                        value: e.value,
-                    })
-                    .collect();
-                let entries_data = bincode::serialize(&entry_list)?;
-                let bucket_key = bucket.to_be_bytes();
-                self.value_index.insert(&bucket_key, &entries_data)?;
-            }
+        for (bucket, entries) in value_entries {
+            let existing = self.get_value_entries(&bucket)?;
+            let updated = merge_entries(existing, entries, connect, |entry| {
+                (entry.tx_hash, entry.output_index)
+            });
+            stage_list(batch, VALUE_INDEX_TREE, &bucket.to_be_bytes(), &updated)?;
         }
 
+        // Note: Input indexing requires UTXO lookup to get script_pubkey from prevout
+        // This is more complex and can be added later if needed
+
         Ok(())
     }
 
@@ -373,21 +341,8 @@ impl TxIndex {
 
     /// Get all outputs for an address (internal helper)
     fn get_address_outputs(&self, address_hash: &[u8; 32]) -> Result<Vec<AddressOutputEntry>> {
-        #[derive(Serialize, Deserialize)]
-        struct AddressOutput {
-            tx_hash: Hash,
-            output_index: u32,
-        }
-
         if let Some(data) = self.address_output_index.get(address_hash)? {
-            let outputs: Vec<AddressOutput> = bincode::deserialize(&data)?;
-            Ok(outputs
-                .into_iter()
-                .map(|o| AddressOutputEntry {
-                    tx_hash: o.tx_hash,
-                    output_index: o.output_index,
-                })
-                .collect())
+            Ok(bincode::deserialize(&data)?)
         } else {
             Ok(Vec::new())
         }
@@ -397,21 +352,7 @@ impl TxIndex {
     fn get_value_entries(&self, bucket: &u64) -> Result<Vec<ValueEntry>> {
         let bucket_key = bucket.to_be_bytes();
         if let Some(data) = self.value_index.get(&bucket_key)? {
-            #[derive(Serialize, Deserialize)]
-            struct ValueEntrySer {
-                tx_hash: Hash,
-                output_index: u32,
-                value: u64,
-            }
-            let entries: Vec<ValueEntrySer> = bincode::deserialize(&data)?;
-            Ok(entries
-                .into_iter()
-                .map(|e| ValueEntry {
-                    tx_hash: e.tx_hash,
-                    output_index: e.output_index,
-                    value: e.value,
-                })
-                .collect())
+            Ok(bincode::deserialize(&data)?)
         } else {
             Ok(Vec::new())
         }
@@ -573,14 +514,6 @@ impl TxIndex {
         }
 
         // Determine which buckets to query
-        fn value_to_bucket(value: u64) -> u64 {
-            if value == 0 {
-                return 0;
-            }
-            let log10 = (value as f64).log10().floor() as u64;
-            (log10 + 1) * 1000
-        }
-
         let min_bucket = value_to_bucket(min_value);
         let max_bucket = value_to_bucket(max_value);
 
@@ -607,6 +540,17 @@ impl TxIndex {
         Ok(transactions)
     }
 
+    /// Stage removal of a transaction's primary index entries into a batch
+    pub fn stage_remove_transaction(&self, batch: &mut WriteBatch, tx_hash: &Hash) -> Result<()> {
+        if let Some(metadata) = self.get_metadata(tx_hash)? {
+            let block_key = self.block_tx_key(&metadata.block_hash, metadata.tx_index);
+            batch.remove(TX_BY_BLOCK_TREE, &block_key);
+        }
+        batch.remove(TX_BY_HASH_TREE, tx_hash);
+        batch.remove(TX_METADATA_TREE, tx_hash);
+        Ok(())
+    }
+
     /// Remove transaction from index
     pub fn remove_transaction(&self, tx_hash: &Hash) -> Result<()> {
         if let Some(metadata) = self.get_metadata(tx_hash)? {
@@ -622,10 +566,19 @@ impl TxIndex {
 
     /// Clear all transactions
     pub fn clear(&self) -> Result<()> {
+        self.clear_primary()?;
+        self.clear_derived()
+    }
+
+    /// Clear the primary entries (by hash, by block, metadata)
+    fn clear_primary(&self) -> Result<()> {
         self.tx_by_hash.clear()?;
         self.tx_by_block.clear()?;
-        self.tx_metadata.clear()?;
+        self.tx_metadata.clear()
+    }
 
+    /// Clear the address and value indexes
+    fn clear_derived(&self) -> Result<()> {
         if self.enable_address_index {
             self.address_tx_index.clear()?;
             self.address_output_index.clear()?;
@@ -703,3 +656,138 @@ impl TxIndex {
         key
     }
 }
+
+impl BackgroundIndex for TxIndex {
+    fn name(&self) -> &'static str {
+        "txindex"
+    }
+
+    fn stage_connect(
+        &self,
+        batch: &mut WriteBatch,
+        block: &Block,
+        block_hash: &Hash,
+        height: u64,
+        _undo: &BlockUndo,
+    ) -> Result<()> {
+        self.stage_block(batch, block, block_hash, height)
+    }
+
+    fn stage_disconnect(
+        &self,
+        batch: &mut WriteBatch,
+        block: &Block,
+        _block_hash: &Hash,
+        _height: u64,
+    ) -> Result<()> {
+        for tx in block.transactions.iter() {
+            let tx_hash = bllvm_protocol::block::calculate_tx_id(tx);
+            self.stage_remove_transaction(batch, &tx_hash)?;
+        }
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.clear_primary()
+    }
+}
+
+/// The address and value indexes of a [`TxIndex`], maintained as their own
+/// background index
+///
+/// Only registered when either index is enabled in the indexing configuration.
+pub struct AddressIndex {
+    txindex: Arc<TxIndex>,
+}
+
+impl AddressIndex {
+    /// Index the derived entries of `txindex`
+    pub fn new(txindex: Arc<TxIndex>) -> Self {
+        Self { txindex }
+    }
+}
+
+impl BackgroundIndex for AddressIndex {
+    fn name(&self) -> &'static str {
+        "addressindex"
+    }
+
+    fn stage_connect(
+        &self,
+        batch: &mut WriteBatch,
+        block: &Block,
+        _block_hash: &Hash,
+        _height: u64,
+        _undo: &BlockUndo,
+    ) -> Result<()> {
+        self.txindex.stage_block_derived(batch, block)
+    }
+
+    fn stage_disconnect(
+        &self,
+        batch: &mut WriteBatch,
+        block: &Block,
+        _block_hash: &Hash,
+        _height: u64,
+    ) -> Result<()> {
+        self.txindex.stage_remove_block_derived(batch, block)
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.txindex.clear_derived()
+    }
+}
+
+/// Logarithmic value bucket: 0, then 1000 per decimal digit of the value
+fn value_to_bucket(value: u64) -> u64 {
+    if value == 0 {
+        return 0;
+    }
+    let log10 = (value as f64).log10().floor() as u64;
+    (log10 + 1) * 1000
+}
+
+/// Transactions of a block with their hashes
+fn block_txs(block: &Block) -> Vec<(Hash, &Transaction)> {
+    block
+        .transactions
+        .iter()
+        .map(|tx| (bllvm_protocol::block::calculate_tx_id(tx), tx))
+        .collect()
+}
+
+/// Add `entries` to `existing` (skipping duplicates), or remove them from it
+fn merge_entries<T, K: Eq + std::hash::Hash>(
+    mut existing: Vec<T>,
+    entries: Vec<T>,
+    connect: bool,
+    key: impl Fn(&T) -> K,
+) -> Vec<T> {
+    if connect {
+        let mut known: HashSet<K> = existing.iter().map(&key).collect();
+        for entry in entries {
+            if known.insert(key(&entry)) {
+                existing.push(entry);
+            }
+        }
+    } else {
+        let removed: HashSet<K> = entries.iter().map(&key).collect();
+        existing.retain(|entry| !removed.contains(&key(entry)));
+    }
+    existing
+}
+
+/// Stage a list-valued index entry, removing the key once the list is empty
+fn stage_list<T: Serialize>(
+    batch: &mut WriteBatch,
+    tree: &str,
+    key: &[u8],
+    list: &[T],
+) -> Result<()> {
+    if list.is_empty() {
+        batch.remove(tree, key);
+    } else {
+        batch.insert(tree, key, &bincode::serialize(list)?);
+    }
+    Ok(())
+}
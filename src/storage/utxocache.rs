@@ -0,0 +1,584 @@
+//! UTXO cache
+//!
+//! Keeps recently used and modified coins in memory in front of the UTXO store,
+//! so blocks are validated and applied without loading the whole UTXO set.
+//! Modified entries are written back in batches ("flushes") when the cache grows
+//! past its memory budget or the flush interval elapses. Each flush also records
+//! the block the stored UTXO set corresponds to, so a node that stops between
+//! flushes can replay the missing blocks at startup.
+
+use crate::storage::blockstore::BlockUndo;
+use crate::storage::database::WriteBatch;
+use crate::storage::utxostore::UtxoStore;
+use anyhow::Result;
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{Block, Hash, OutPoint, Transaction, UtxoSet, UTXO};
+use std::collections::{BTreeMap, HashMap};
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Default memory budget (50 MB, matching `StorageCacheConfig::utxo_cache_mb`)
+pub const DEFAULT_UTXO_CACHE_BYTES: usize = 50 * 1024 * 1024;
+
+/// Default maximum time between flushes
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Approximate per-entry overhead of the cache map (key, entry and bucket)
+const ENTRY_OVERHEAD: usize = size_of::<OutPoint>() + size_of::<CacheEntry>() + 16;
+
+/// UTXO set that blocks are validated against and applied to
+///
+/// Implemented by an in-memory [`UtxoSet`] and by the shared [`UtxoCache`], so
+/// block processing works the same with or without persistent storage.
+pub trait UtxoView {
+    /// Look up an unspent output
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<UTXO>>;
+
+    /// Spend the block's inputs and add its outputs
+    fn apply_block(&mut self, block: &Block, block_hash: &Hash, height: u64) -> Result<()>;
+
+    /// Revert a block using its undo data
+    ///
+    /// Returns `false` if the view did not contain all of the block's outputs,
+    /// which indicates it was not at this block when it was disconnected.
+    fn revert_block(&mut self, block: &Block, height: u64, undo: &BlockUndo) -> Result<bool>;
+
+    /// Collect the coins a block needs for validation
+    ///
+    /// Contains the outputs spent by the block's inputs and any existing outputs
+    /// with the same outpoints as the block's new ones (for duplicate checks).
+    fn fetch_view(&self, block: &Block) -> Result<UtxoSet> {
+        collect_view(block, |outpoint| self.get_utxo(outpoint))
+    }
+}
+
+impl UtxoView for UtxoSet {
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<UTXO>> {
+        Ok(self.get(outpoint).cloned())
+    }
+
+    fn apply_block(&mut self, block: &Block, _block_hash: &Hash, height: u64) -> Result<()> {
+        for (tx_index, tx) in block.transactions.iter().enumerate() {
+            if tx_index > 0 {
+                for input in tx.inputs.iter() {
+                    self.remove(&input.prevout);
+                }
+            }
+            for (outpoint, utxo) in block_outputs(tx, tx_index, height) {
+                self.insert(outpoint, utxo);
+            }
+        }
+        Ok(())
+    }
+
+    fn revert_block(&mut self, block: &Block, _height: u64, undo: &BlockUndo) -> Result<bool> {
+        let mut clean = true;
+        for tx in block.transactions.iter().rev() {
+            for outpoint in output_outpoints(tx) {
+                if self.remove(&outpoint).is_none() {
+                    clean = false;
+                }
+            }
+        }
+        for spent in undo.spent_outputs.iter().rev() {
+            if self
+                .insert(spent.outpoint.clone(), spent.utxo.clone())
+                .is_some()
+            {
+                clean = false;
+            }
+        }
+        Ok(clean)
+    }
+}
+
+/// Cached coin and its state relative to the UTXO store
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    /// The coin, or `None` if it has been spent
+    coin: Option<UTXO>,
+    /// Differs from the stored coin and must be written on the next flush
+    dirty: bool,
+    /// Not present in the store, so spending it needs no write at all
+    fresh: bool,
+}
+
+impl CacheEntry {
+    fn memory_usage(&self) -> usize {
+        ENTRY_OVERHEAD
+            + self
+                .coin
+                .as_ref()
+                .map(|coin| coin.script_pubkey.capacity())
+                .unwrap_or(0)
+    }
+}
+
+struct CacheState {
+    entries: HashMap<OutPoint, CacheEntry>,
+    memory_usage: usize,
+    max_memory: usize,
+    flush_interval: Duration,
+    last_flush: Instant,
+    /// Block the cached view corresponds to
+    best_block: Option<(Hash, u64)>,
+}
+
+impl CacheState {
+    fn insert(&mut self, outpoint: OutPoint, entry: CacheEntry) {
+        self.memory_usage += entry.memory_usage();
+        if let Some(old) = self.entries.insert(outpoint, entry) {
+            self.memory_usage -= old.memory_usage();
+        }
+    }
+
+    fn remove(&mut self, outpoint: &OutPoint) {
+        if let Some(old) = self.entries.remove(outpoint) {
+            self.memory_usage -= old.memory_usage();
+        }
+    }
+
+    fn get(&mut self, store: &UtxoStore, outpoint: &OutPoint) -> Result<Option<UTXO>> {
+        if let Some(entry) = self.entries.get(outpoint) {
+            return Ok(entry.coin.clone());
+        }
+        let coin = store.get_utxo(outpoint)?;
+        if let Some(ref coin) = coin {
+            self.insert(
+                outpoint.clone(),
+                CacheEntry {
+                    coin: Some(coin.clone()),
+                    dirty: false,
+                    fresh: false,
+                },
+            );
+        }
+        Ok(coin)
+    }
+
+    fn add(&mut self, outpoint: OutPoint, utxo: UTXO) {
+        // A coin can only be fresh if the store cannot hold an older version of it
+        let fresh = match self.entries.get(&outpoint) {
+            Some(entry) => entry.coin.is_none() && !entry.dirty,
+            None => true,
+        };
+        self.insert(
+            outpoint,
+            CacheEntry {
+                coin: Some(utxo),
+                dirty: true,
+                fresh,
+            },
+        );
+    }
+
+    fn spend(&mut self, store: &UtxoStore, outpoint: &OutPoint) -> Result<Option<UTXO>> {
+        let coin = self.get(store, outpoint)?;
+        if coin.is_none() {
+            return Ok(None);
+        }
+        let fresh = self
+            .entries
+            .get(outpoint)
+            .map(|entry| entry.fresh)
+            .unwrap_or(false);
+        if fresh {
+            // Never written, so there is nothing to delete from the store
+            self.remove(outpoint);
+        } else {
+            self.insert(
+                outpoint.clone(),
+                CacheEntry {
+                    coin: None,
+                    dirty: true,
+                    fresh: false,
+                },
+            );
+        }
+        Ok(coin)
+    }
+}
+
+/// Write-back cache in front of the UTXO store
+pub struct UtxoCache {
+    store: Arc<UtxoStore>,
+    state: Mutex<CacheState>,
+}
+
+impl UtxoCache {
+    /// Create a cache with the default memory budget and flush interval
+    pub fn new(store: Arc<UtxoStore>) -> Result<Self> {
+        Self::with_limits(store, DEFAULT_UTXO_CACHE_BYTES, DEFAULT_FLUSH_INTERVAL)
+    }
+
+    /// Create a cache with a memory budget (bytes) and maximum flush interval
+    pub fn with_limits(
+        store: Arc<UtxoStore>,
+        max_memory: usize,
+        flush_interval: Duration,
+    ) -> Result<Self> {
+        let best_block = store.get_best_block()?;
+        Ok(Self {
+            store,
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                memory_usage: 0,
+                max_memory,
+                flush_interval,
+                last_flush: Instant::now(),
+                best_block,
+            }),
+        })
+    }
+
+    /// Change the memory budget (bytes) and maximum flush interval
+    pub fn set_limits(&self, max_memory: usize, flush_interval: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.max_memory = max_memory;
+        state.flush_interval = flush_interval;
+    }
+
+    /// The underlying UTXO store
+    pub fn store(&self) -> &UtxoStore {
+        &self.store
+    }
+
+    /// Block the cached UTXO set corresponds to
+    pub fn best_block(&self) -> Option<(Hash, u64)> {
+        self.state.lock().unwrap().best_block
+    }
+
+    /// Get a UTXO by outpoint, reading through to the store on a miss
+    pub fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<UTXO>> {
+        self.state.lock().unwrap().get(&self.store, outpoint)
+    }
+
+    /// Check if a UTXO exists
+    pub fn has_utxo(&self, outpoint: &OutPoint) -> Result<bool> {
+        Ok(self.get_utxo(outpoint)?.is_some())
+    }
+
+    /// Add a UTXO to the set
+    pub fn add_utxo(&self, outpoint: &OutPoint, utxo: &UTXO) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .add(outpoint.clone(), utxo.clone());
+        Ok(())
+    }
+
+    /// Remove a UTXO from the set, returning it if it was unspent
+    pub fn remove_utxo(&self, outpoint: &OutPoint) -> Result<Option<UTXO>> {
+        self.state.lock().unwrap().spend(&self.store, outpoint)
+    }
+
+    /// Load the entire UTXO set, including changes not flushed yet
+    ///
+    /// Reads every stored coin; use only where a full scan is unavoidable. The
+    /// cache is not locked during the scan (see [`UtxoCache::for_each_utxo`]).
+    pub fn get_all_utxos(&self) -> Result<UtxoSet> {
+        let mut utxo_set = UtxoSet::new();
+        self.for_each_utxo(|outpoint, utxo| {
+            utxo_set.insert(outpoint.clone(), utxo.clone());
+            Ok(())
+        })?;
+        Ok(utxo_set)
+    }
+
+    /// Call `f` with every coin in outpoint order, including changes not
+    /// flushed yet
+    ///
+    /// Unflushed changes are copied out first and the cache is unlocked while
+    /// the store is read, so the set is streamed rather than loaded whole and
+    /// blocks can still be applied meanwhile. A flush during the scan can make
+    /// the coins visited mix the states before and after it.
+    pub fn for_each_utxo<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&OutPoint, &UTXO) -> Result<()>,
+    {
+        let dirty: BTreeMap<(Hash, u64), Option<UTXO>> = {
+            let state = self.state.lock().unwrap();
+            state
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.dirty)
+                .map(|(outpoint, entry)| ((outpoint.hash, outpoint.index), entry.coin.clone()))
+                .collect()
+        };
+
+        // Merge the changes into the stored coins; both are in outpoint order.
+        // A spent coin (`None`) hides the stored one.
+        let mut dirty = dirty.into_iter().peekable();
+        let mut visit = |(hash, index): (Hash, u64), coin: Option<UTXO>| match coin {
+            Some(coin) => f(&OutPoint { hash, index }, &coin),
+            None => Ok(()),
+        };
+        for result in self.store.iter_utxos() {
+            let (outpoint, utxo) = result?;
+            let key = (outpoint.hash, outpoint.index);
+            while let Some((dirty_key, coin)) = dirty.next_if(|(dirty_key, _)| *dirty_key < key) {
+                visit(dirty_key, coin)?;
+            }
+            match dirty.next_if(|(dirty_key, _)| *dirty_key == key) {
+                Some((_, coin)) => visit(key, coin)?,
+                None => visit(key, Some(utxo))?,
+            }
+        }
+        for (dirty_key, coin) in dirty {
+            visit(dirty_key, coin)?;
+        }
+        Ok(())
+    }
+
+    /// Get total number of UTXOs, including changes not flushed yet
+    pub fn utxo_count(&self) -> Result<usize> {
+        let state = self.state.lock().unwrap();
+        let mut count = self.store.utxo_count()? as i64;
+        for (outpoint, entry) in state.entries.iter().filter(|(_, e)| e.dirty) {
+            let stored = !entry.fresh && self.store.has_utxo(outpoint)?;
+            match (stored, entry.coin.is_some()) {
+                (false, true) => count += 1,
+                (true, false) => count -= 1,
+                _ => {}
+            }
+        }
+        Ok(count.max(0) as usize)
+    }
+
+    /// Spend a block's inputs and add its outputs
+    ///
+    /// Outputs created and spent within the block never reach the store.
+    pub fn apply_block(&self, block: &Block, block_hash: &Hash, height: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for (tx_index, tx) in block.transactions.iter().enumerate() {
+            if tx_index > 0 {
+                for input in tx.inputs.iter() {
+                    state.spend(&self.store, &input.prevout)?;
+                }
+            }
+            for (outpoint, utxo) in block_outputs(tx, tx_index, height) {
+                state.add(outpoint, utxo);
+            }
+        }
+        state.best_block = Some((*block_hash, height));
+        Ok(())
+    }
+
+    /// Revert a block using its undo data
+    ///
+    /// Returns `false` if the cache did not contain all of the block's outputs.
+    pub fn revert_block(&self, block: &Block, height: u64, undo: &BlockUndo) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let mut clean = true;
+        for tx in block.transactions.iter().rev() {
+            for outpoint in output_outpoints(tx) {
+                if state.spend(&self.store, &outpoint)?.is_none() {
+                    clean = false;
+                }
+            }
+        }
+        for spent in undo.spent_outputs.iter().rev() {
+            if state.get(&self.store, &spent.outpoint)?.is_some() {
+                clean = false;
+            }
+            state.add(spent.outpoint.clone(), spent.utxo.clone());
+        }
+        state.best_block = height
+            .checked_sub(1)
+            .map(|parent_height| (block.header.prev_block_hash, parent_height));
+        Ok(clean)
+    }
+
+    /// Collect the coins a block needs for validation
+    pub fn fetch_view(&self, block: &Block) -> Result<UtxoSet> {
+        let mut state = self.state.lock().unwrap();
+        collect_view(block, |outpoint| state.get(&self.store, outpoint))
+    }
+
+    /// Collect the unspent outputs among `prevouts`
+    ///
+    /// Builds the view a transaction check needs without loading the whole
+    /// UTXO set; missing or spent outpoints are left out.
+    pub fn fetch_coins<'a>(
+        &self,
+        prevouts: impl IntoIterator<Item = &'a OutPoint>,
+    ) -> Result<UtxoSet> {
+        let mut state = self.state.lock().unwrap();
+        let mut view = UtxoSet::new();
+        for prevout in prevouts {
+            if view.contains_key(prevout) {
+                continue;
+            }
+            if let Some(utxo) = state.get(&self.store, prevout)? {
+                view.insert(prevout.clone(), utxo);
+            }
+        }
+        Ok(view)
+    }
+
+    /// Approximate memory used by cached entries (bytes)
+    pub fn memory_usage(&self) -> usize {
+        self.state.lock().unwrap().memory_usage
+    }
+
+    /// Number of cached entries
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// True if nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of entries that differ from the store
+    pub fn dirty_count(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .filter(|entry| entry.dirty)
+            .count()
+    }
+
+    /// True if the cache is over its memory budget or the flush interval elapsed
+    pub fn should_flush(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.memory_usage > state.max_memory || state.last_flush.elapsed() >= state.flush_interval
+    }
+
+    /// Write all changes to the store
+    pub fn flush(&self) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        self.flush_with(&mut batch, |batch| self.store.apply_batch(batch))
+    }
+
+    /// Stage all changes into `batch` and commit it with `commit`
+    ///
+    /// Lets callers make the flush part of a larger atomic write. The cache is
+    /// locked until `commit` returns and its entries are only marked clean if
+    /// the commit succeeds. If the cache is still over budget afterwards, all
+    /// entries are evicted.
+    pub fn flush_with<F>(&self, batch: &mut WriteBatch, commit: F) -> Result<()>
+    where
+        F: FnOnce(&WriteBatch) -> Result<()>,
+    {
+        let mut state = self.state.lock().unwrap();
+
+        let mut written = 0usize;
+        for (outpoint, entry) in state.entries.iter().filter(|(_, e)| e.dirty) {
+            match entry.coin {
+                Some(ref coin) => self.store.stage_add_utxo(batch, outpoint, coin)?,
+                // Fresh coins are dropped when spent, so this one is in the store
+                None => self.store.stage_remove_utxo(batch, outpoint),
+            }
+            written += 1;
+        }
+        if let Some((hash, height)) = state.best_block {
+            self.store.stage_best_block(batch, &hash, height)?;
+        }
+
+        commit(&*batch)?;
+
+        state.entries.retain(|_, entry| entry.coin.is_some());
+        for entry in state.entries.values_mut() {
+            entry.dirty = false;
+            entry.fresh = false;
+        }
+        state.memory_usage = state.entries.values().map(CacheEntry::memory_usage).sum();
+        if state.memory_usage > state.max_memory {
+            state.entries.clear();
+            state.memory_usage = 0;
+        }
+        state.last_flush = Instant::now();
+
+        debug!(
+            "Flushed {} UTXO cache entries at {:?}",
+            written,
+            state.best_block.map(|(_, height)| height)
+        );
+        Ok(())
+    }
+
+    /// Drop all changes that have not been flushed
+    ///
+    /// Used after a failed commit, so the cache matches the store again.
+    pub fn reset(&self) -> Result<()> {
+        let best_block = self.store.get_best_block()?;
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.memory_usage = 0;
+        state.best_block = best_block;
+        Ok(())
+    }
+}
+
+impl UtxoView for Arc<UtxoCache> {
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<UTXO>> {
+        UtxoCache::get_utxo(self, outpoint)
+    }
+
+    fn apply_block(&mut self, block: &Block, block_hash: &Hash, height: u64) -> Result<()> {
+        UtxoCache::apply_block(self, block, block_hash, height)
+    }
+
+    fn revert_block(&mut self, block: &Block, height: u64, undo: &BlockUndo) -> Result<bool> {
+        UtxoCache::revert_block(self, block, height, undo)
+    }
+}
+
+/// Collect a block's input coins and any coins its outputs would overwrite
+fn collect_view<F>(block: &Block, mut get: F) -> Result<UtxoSet>
+where
+    F: FnMut(&OutPoint) -> Result<Option<UTXO>>,
+{
+    let mut view = UtxoSet::new();
+    for (tx_index, tx) in block.transactions.iter().enumerate() {
+        if tx_index > 0 {
+            for input in tx.inputs.iter() {
+                if let Some(utxo) = get(&input.prevout)? {
+                    view.insert(input.prevout.clone(), utxo);
+                }
+            }
+        }
+        for outpoint in output_outpoints(tx) {
+            if let Some(utxo) = get(&outpoint)? {
+                view.insert(outpoint, utxo);
+            }
+        }
+    }
+    Ok(view)
+}
+
+/// Outpoints of a transaction's outputs
+fn output_outpoints(tx: &Transaction) -> impl Iterator<Item = OutPoint> {
+    let txid = calculate_tx_id(tx);
+    (0..tx.outputs.len()).map(move |index| OutPoint {
+        hash: txid,
+        index: index as u64,
+    })
+}
+
+/// Coins created by a transaction at `tx_index` in a block at `height`
+fn block_outputs(
+    tx: &Transaction,
+    tx_index: usize,
+    height: u64,
+) -> impl Iterator<Item = (OutPoint, UTXO)> + '_ {
+    output_outpoints(tx)
+        .zip(tx.outputs.iter())
+        .map(move |(outpoint, output)| {
+            (
+                outpoint,
+                UTXO {
+                    value: output.value,
+                    script_pubkey: output.script_pubkey.clone(),
+                    height,
+                    is_coinbase: tx_index == 0,
+                },
+            )
+        })
+}
@@ -2,24 +2,31 @@
 //!
 //! Stores and manages the UTXO set for efficient transaction validation.
 
-use crate::storage::database::{Database, Tree};
+use crate::storage::database::{Database, Tree, WriteBatch};
 use anyhow::Result;
-use bllvm_protocol::{OutPoint, UtxoSet, UTXO};
+use bllvm_protocol::{Hash, OutPoint, UtxoSet, UTXO};
 use std::collections::HashMap;
 use std::sync::Arc;
 
 #[cfg(feature = "production")]
 use std::sync::{OnceLock, RwLock};
 
+const UTXOS_TREE: &str = "utxos";
+const UTXO_META_TREE: &str = "utxo_meta";
+const SNAPSHOT_UTXOS_TREE: &str = "snapshot_utxos";
+const SNAPSHOT_UTXO_META_TREE: &str = "snapshot_utxo_meta";
+const BEST_BLOCK_KEY: &[u8] = b"best_block";
+
 /// UTXO serialization cache (production feature only)
 ///
 /// Caches serialized UTXO bytes to avoid re-serializing the same UTXO.
-/// Cache key is the OutPoint hash.
+/// Cache key is the OutPoint (txid and output index).
 #[cfg(feature = "production")]
-static SERIALIZATION_CACHE: OnceLock<RwLock<lru::LruCache<[u8; 32], Vec<u8>>>> = OnceLock::new();
+static SERIALIZATION_CACHE: OnceLock<RwLock<lru::LruCache<([u8; 32], u64), Vec<u8>>>> =
+    OnceLock::new();
 
 #[cfg(feature = "production")]
-fn get_serialization_cache() -> &'static RwLock<lru::LruCache<[u8; 32], Vec<u8>>> {
+fn get_serialization_cache() -> &'static RwLock<lru::LruCache<([u8; 32], u64), Vec<u8>>> {
     SERIALIZATION_CACHE.get_or_init(|| {
         use lru::LruCache;
         use std::num::NonZeroUsize;
@@ -31,29 +38,51 @@ fn get_serialization_cache() -> &'static RwLock<lru::LruCache<[u8; 32], Vec<u8>>
 
 /// Calculate cache key from OutPoint
 #[cfg(feature = "production")]
-fn outpoint_cache_key(outpoint: &OutPoint) -> [u8; 32] {
-    // Use OutPoint hash directly as cache key (already 32 bytes)
-    outpoint.hash
+fn outpoint_cache_key(outpoint: &OutPoint) -> ([u8; 32], u64) {
+    // Outputs of the same transaction share a txid, so the index is part of the key
+    (outpoint.hash, outpoint.index)
 }
 
 /// UTXO set storage manager
 pub struct UtxoStore {
-    #[allow(dead_code)]
     db: Arc<dyn Database>,
     utxos: Arc<dyn Tree>,
     spent_outputs: Arc<dyn Tree>,
+    meta: Arc<dyn Tree>,
+    utxos_tree: &'static str,
+    meta_tree: &'static str,
 }
 
 impl UtxoStore {
     /// Create a new UTXO store
     pub fn new(db: Arc<dyn Database>) -> Result<Self> {
-        let utxos = Arc::from(db.open_tree("utxos")?);
+        Self::with_trees(db, UTXOS_TREE, UTXO_META_TREE)
+    }
+
+    /// Create the UTXO store used to validate history below a loaded snapshot
+    ///
+    /// Kept in its own trees so it can be built up from genesis alongside the
+    /// active UTXO set, which starts at the snapshot base.
+    pub fn background(db: Arc<dyn Database>) -> Result<Self> {
+        Self::with_trees(db, SNAPSHOT_UTXOS_TREE, SNAPSHOT_UTXO_META_TREE)
+    }
+
+    fn with_trees(
+        db: Arc<dyn Database>,
+        utxos_tree: &'static str,
+        meta_tree: &'static str,
+    ) -> Result<Self> {
+        let utxos = Arc::from(db.open_tree(utxos_tree)?);
         let spent_outputs = Arc::from(db.open_tree("spent_outputs")?);
+        let meta = Arc::from(db.open_tree(meta_tree)?);
 
         Ok(Self {
             db,
             utxos,
             spent_outputs,
+            meta,
+            utxos_tree,
+            meta_tree,
         })
     }
 
@@ -160,16 +189,20 @@ impl UtxoStore {
 
     /// Load the entire UTXO set
     pub fn load_utxo_set(&self) -> Result<UtxoSet> {
-        let mut utxo_set = HashMap::new();
+        self.iter_utxos().collect::<Result<HashMap<_, _>>>()
+    }
 
-        for result in self.utxos.iter() {
+    /// Iterate over every UTXO in outpoint order (hash, then index)
+    ///
+    /// Coins are read from the store as the iterator advances, so the set is
+    /// never held in memory as a whole.
+    pub fn iter_utxos(&self) -> impl Iterator<Item = Result<(OutPoint, UTXO)>> + '_ {
+        self.utxos.iter().map(move |result| {
             let (key, value) = result?;
             let outpoint = self.outpoint_from_key(&key)?;
             let utxo: UTXO = bincode::deserialize(&value)?;
-            utxo_set.insert(outpoint, utxo);
-        }
-
-        Ok(utxo_set)
+            Ok((outpoint, utxo))
+        })
     }
 
     /// Add a UTXO to the set
@@ -177,7 +210,16 @@ impl UtxoStore {
     /// Performance optimization: Caches serialized UTXO bytes
     pub fn add_utxo(&self, outpoint: &OutPoint, utxo: &UTXO) -> Result<()> {
         let key = self.outpoint_key(outpoint);
+        let value = self.serialize_utxo(outpoint, utxo)?;
+        self.utxos.insert(&key, &value)?;
+        Ok(())
+    }
 
+    /// Serialize a UTXO for storage
+    ///
+    /// Performance optimization: Caches serialized UTXO bytes
+    #[cfg_attr(not(feature = "production"), allow(unused_variables))]
+    fn serialize_utxo(&self, outpoint: &OutPoint, utxo: &UTXO) -> Result<Vec<u8>> {
         #[cfg(feature = "production")]
         let value = {
             // Check cache first
@@ -208,8 +250,7 @@ impl UtxoStore {
         #[cfg(not(feature = "production"))]
         let value = bincode::serialize(utxo)?;
 
-        self.utxos.insert(&key, &value)?;
-        Ok(())
+        Ok(value)
     }
 
     /// Remove a UTXO from the set
@@ -219,6 +260,52 @@ impl UtxoStore {
         Ok(())
     }
 
+    /// Stage adding a UTXO into a batch
+    pub fn stage_add_utxo(
+        &self,
+        batch: &mut WriteBatch,
+        outpoint: &OutPoint,
+        utxo: &UTXO,
+    ) -> Result<()> {
+        let value = self.serialize_utxo(outpoint, utxo)?;
+        batch.insert(self.utxos_tree, &self.outpoint_key(outpoint), &value);
+        Ok(())
+    }
+
+    /// Stage removing a UTXO into a batch
+    pub fn stage_remove_utxo(&self, batch: &mut WriteBatch, outpoint: &OutPoint) {
+        batch.remove(self.utxos_tree, &self.outpoint_key(outpoint));
+    }
+
+    /// Commit a batch of staged writes atomically
+    pub fn apply_batch(&self, batch: &WriteBatch) -> Result<()> {
+        self.db.apply_batch(batch)
+    }
+
+    /// Get the block the stored UTXO set corresponds to
+    ///
+    /// Written together with every UTXO cache flush. `None` for a database that
+    /// has never been flushed through the cache.
+    pub fn get_best_block(&self) -> Result<Option<(Hash, u64)>> {
+        match self.meta.get(BEST_BLOCK_KEY)? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stage the block the stored UTXO set corresponds to into a batch
+    pub fn stage_best_block(&self, batch: &mut WriteBatch, hash: &Hash, height: u64) -> Result<()> {
+        let value = bincode::serialize(&(*hash, height))?;
+        batch.insert(self.meta_tree, BEST_BLOCK_KEY, &value);
+        Ok(())
+    }
+
+    /// Remove every UTXO and the best-block marker
+    pub fn clear(&self) -> Result<()> {
+        self.utxos.clear()?;
+        self.meta.clear()
+    }
+
     /// Get a UTXO by outpoint
     pub fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<UTXO>> {
         let key = self.outpoint_key(outpoint);
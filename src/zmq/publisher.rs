@@ -200,6 +200,35 @@ impl ZmqPublisher {
         Ok(())
     }
 
+    /// Publish a sequence notification for a transaction leaving the mempool
+    ///
+    /// Message format: [topic: "sequence", data: 0x02, 32 bytes hash, reason (ASCII, e.g. "block")]
+    ///
+    /// # Arguments
+    ///
+    /// * `tx_hash` - Transaction hash
+    /// * `reason` - Why the transaction was removed
+    pub async fn publish_mempool_removal(&self, tx_hash: &Hash, reason: &str) -> Result<()> {
+        if let Some(ref socket) = self.sequence_socket {
+            let mut seq = self.sequence.write().await;
+            *seq = seq.wrapping_add(1);
+            let sequence_num = *seq;
+
+            let mut data = Vec::with_capacity(33 + reason.len());
+            data.push(0x02);
+            data.extend_from_slice(tx_hash.as_slice());
+            data.extend_from_slice(reason.as_bytes());
+
+            socket.send("sequence", zmq::SNDMORE)?;
+            socket.send(&data, 0)?;
+            debug!(
+                "Published sequence notification: seq={}, tx={:?}, removed ({})",
+                sequence_num, tx_hash, reason
+            );
+        }
+        Ok(())
+    }
+
     /// Publish all block notifications (hash and raw)
     ///
     /// Convenience method that publishes both hashblock and rawblock if enabled.
@@ -311,6 +340,10 @@ impl ZmqPublisher {
         Ok(())
     }
 
+    pub async fn publish_mempool_removal(&self, _tx_hash: &Hash, _reason: &str) -> Result<()> {
+        Ok(())
+    }
+
     pub async fn publish_block(&self, _block: &Block, _block_hash: &Hash) -> Result<()> {
         Ok(())
     }
@@ -249,6 +249,47 @@ mod tests {
         assert_eq!(&data_bytes[1..], tx_hash.as_slice());
     }
 
+    #[tokio::test]
+    async fn test_zmq_mempool_removal_notification() {
+        let config = ZmqConfig {
+            hashblock: None,
+            hashtx: None,
+            rawblock: None,
+            rawtx: None,
+            sequence: Some("tcp://127.0.0.1:28340".to_string()),
+        };
+
+        let publisher = ZmqPublisher::new(&config).unwrap();
+        let tx_hash: Hash = [4u8; 32];
+
+        let ctx = Context::new();
+        let subscriber = ctx.socket(SUB).unwrap();
+        subscriber.connect("tcp://127.0.0.1:28340").unwrap();
+        subscriber.set_subscribe(b"sequence").unwrap();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        publisher
+            .publish_mempool_removal(&tx_hash, "conflict")
+            .await
+            .unwrap();
+
+        let result = timeout(Duration::from_secs(1), async {
+            let topic = subscriber.recv_msg(0).unwrap();
+            let data = subscriber.recv_msg(0).unwrap();
+            (topic, data)
+        })
+        .await;
+
+        assert!(result.is_ok());
+        let (topic, data) = result.unwrap();
+        assert_eq!(topic.as_str(), Some("sequence"));
+        let data_bytes: &[u8] = data.as_ref();
+        assert_eq!(data_bytes[0], 0x02); // Mempool removal
+        assert_eq!(&data_bytes[1..33], tx_hash.as_slice());
+        assert_eq!(&data_bytes[33..], b"conflict");
+    }
+
     #[tokio::test]
     async fn test_zmq_config_is_enabled() {
         let config = ZmqConfig {
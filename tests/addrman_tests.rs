@@ -0,0 +1,297 @@
+//! Tests for the bucketed address manager
+
+use bllvm_node::network::addrman::{
+    filter_addresses, is_banned, network_addr_to_socket, socket_to_network_addr, AddrMan,
+};
+use bllvm_node::network::netaddr::{NetAddr, Network, ServiceAddr};
+use bllvm_node::network::protocol::NetworkAddressV2;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tempfile::TempDir;
+
+fn service(addr: &str) -> ServiceAddr {
+    addr.parse().unwrap()
+}
+
+fn addr(ip: &str, port: u16) -> NetworkAddressV2 {
+    socket_to_network_addr(SocketAddr::new(ip.parse().unwrap(), port), 1).into()
+}
+
+fn source(ip: &str) -> NetAddr {
+    ip.parse().unwrap()
+}
+
+#[test]
+fn test_add_address() {
+    let mut addrman = AddrMan::new();
+    assert!(addrman.is_empty());
+
+    assert!(addrman.add_address(addr("1.2.3.4", 8333), source("5.6.7.8")));
+    // Known addresses are refreshed, not added again
+    assert!(!addrman.add_address(addr("1.2.3.4", 8333), source("9.9.9.9")));
+    assert_eq!(addrman.len(), 1);
+    assert_eq!((addrman.new_count(), addrman.tried_count()), (1, 0));
+
+    // Unroutable addresses are never stored
+    assert!(!addrman.add_address(addr("127.0.0.1", 8333), source("5.6.7.8")));
+    assert!(!addrman.add_address(addr("192.168.1.1", 8333), source("5.6.7.8")));
+    assert!(!addrman.add_address(addr("1.2.3.5", 0), source("5.6.7.8")));
+    assert_eq!(addrman.len(), 1);
+}
+
+#[test]
+fn test_single_source_fills_few_buckets() {
+    let mut addrman = AddrMan::new();
+    let addresses: Vec<_> = (0..=255u8)
+        .flat_map(|a| (0..8u8).map(move |b| addr(&format!("{}.{}.1.1", a.max(1), b), 8333)))
+        .collect();
+    addrman.add_addresses(addresses, source("5.6.7.8"));
+
+    // One source group reaches at most 64 new buckets
+    assert!(addrman.info().new_buckets_used <= 64);
+    assert!(addrman.len() <= 64 * 64);
+}
+
+#[test]
+fn test_good_moves_to_tried_and_select() {
+    let ip_networks = [Network::Ipv4, Network::Ipv6];
+    let mut addrman = AddrMan::new();
+    assert!(addrman.select(false, &ip_networks).is_none());
+
+    let peer = service("1.2.3.4:8333");
+    addrman.add_address(addr("1.2.3.4", 8333), source("5.6.7.8"));
+    addrman.attempt(&peer);
+    assert_eq!(addrman.get(&peer).unwrap().attempts, 1);
+
+    assert!(addrman.good(&peer));
+    let info = addrman.get(&peer).unwrap();
+    assert!(info.in_tried);
+    assert_eq!(info.attempts, 0);
+    assert_eq!((addrman.new_count(), addrman.tried_count()), (0, 1));
+
+    // Feelers only test new addresses
+    assert!(addrman.select(true, &ip_networks).is_none());
+    assert_eq!(addrman.select(false, &ip_networks), Some(peer));
+
+    // Unknown addresses can't become good
+    assert!(!addrman.good(&service("2.3.4.5:8333")));
+}
+
+#[test]
+fn test_get_addresses_limits() {
+    let mut addrman = AddrMan::new();
+    for i in 1..=100u8 {
+        addrman.add_address(
+            addr(&format!("{}.1.1.1", i), 8333),
+            source(&format!("{}.9.9.9", i)),
+        );
+    }
+    let count = addrman.len();
+    assert_eq!(
+        addrman.get_addresses(2500, 23, None).len(),
+        count * 23 / 100
+    );
+    assert_eq!(addrman.get_addresses(5, 100, None).len(), 5);
+}
+
+#[test]
+fn test_get_addresses_by_network() {
+    let mut addrman = AddrMan::new();
+    let onion = NetworkAddressV2 {
+        time: 0,
+        services: 1,
+        addr: NetAddr::TorV3([7; 32]),
+        port: 8333,
+    };
+    addrman.add_address(onion.clone(), source("5.6.7.8"));
+    addrman.add_address(addr("1.2.3.4", 8333), source("5.6.7.8"));
+
+    let onions = addrman.get_addresses(10, 100, Some(Network::Onion));
+    assert_eq!(onions.len(), 1);
+    assert_eq!(onions[0].service(), onion.service());
+    // Time is filled in when the address is added
+    assert!(onions[0].time > 0);
+    assert!(addrman
+        .get_addresses(10, 100, Some(Network::I2p))
+        .is_empty());
+    assert_eq!(addrman.get_addresses(10, 100, None).len(), 2);
+}
+
+#[test]
+fn test_info_counts() {
+    let mut addrman = AddrMan::new();
+    addrman.add_address(addr("1.2.3.4", 8333), source("5.6.7.8"));
+    addrman.good(&service("1.2.3.4:8333"));
+    addrman.add_address(addr("2001:db8::1", 8333), source("5.6.7.8"));
+
+    let info = addrman.info();
+    let counts: HashMap<_, _> = info.networks.iter().cloned().collect();
+    assert_eq!(counts.len(), Network::ALL.len());
+    assert_eq!(
+        (counts[&Network::Ipv4].new, counts[&Network::Ipv4].tried),
+        (0, 1)
+    );
+    assert_eq!(
+        (counts[&Network::Ipv6].new, counts[&Network::Ipv6].tried),
+        (1, 0)
+    );
+    assert_eq!(counts[&Network::Onion].new, 0);
+    assert_eq!((info.total().new, info.total().tried), (1, 1));
+    assert_eq!((info.new_buckets_used, info.tried_buckets_used), (1, 1));
+    assert_eq!(info.tried_collisions, 0);
+}
+
+#[test]
+fn test_save_and_load() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("peers.dat");
+
+    let mut addrman = AddrMan::new();
+    let tried = service("3.1.1.1:8333");
+    addrman.add_address(addr("3.1.1.1", 8333), source("5.6.7.8"));
+    addrman.good(&tried);
+    let i2p = NetworkAddressV2 {
+        time: 0,
+        services: 1,
+        addr: NetAddr::I2p([9; 32]),
+        port: 8333,
+    };
+    assert!(addrman.add_address(i2p.clone(), source("5.6.7.8")));
+    for i in 4..=20u8 {
+        addrman.add_address(addr(&format!("{}.1.1.1", i), 8333), source("5.6.7.8"));
+    }
+    addrman.save(&path).unwrap();
+
+    let mut loaded = AddrMan::new();
+    assert!(loaded.load(&path).unwrap());
+    assert_eq!(loaded.len(), addrman.len());
+    assert_eq!(loaded.new_count(), addrman.new_count());
+    assert_eq!(loaded.tried_count(), 1);
+    assert!(loaded.get(&tried).unwrap().in_tried);
+    assert!(loaded.get(&i2p.service()).is_some());
+    // The saved key reproduces the same bucket layout
+    assert_eq!(
+        loaded.info().new_buckets_used,
+        addrman.info().new_buckets_used
+    );
+
+    // A missing file leaves the tables alone
+    assert!(!loaded.load(temp_dir.path().join("missing.dat")).unwrap());
+    assert_eq!(loaded.len(), addrman.len());
+}
+
+#[test]
+fn test_load_rejects_corrupt_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("peers.dat");
+
+    let mut addrman = AddrMan::new();
+    addrman.add_address(addr("1.2.3.4", 8333), source("5.6.7.8"));
+    addrman.save(&path).unwrap();
+
+    let mut data = std::fs::read(&path).unwrap();
+    let middle = data.len() / 2;
+    data[middle] ^= 0xff;
+    std::fs::write(&path, &data).unwrap();
+    assert!(AddrMan::new().load(&path).is_err());
+
+    std::fs::write(&path, b"not a peers file").unwrap();
+    assert!(AddrMan::new().load(&path).is_err());
+}
+
+#[test]
+fn test_remove_address() {
+    let mut addrman = AddrMan::new();
+    let peer = service("1.2.3.4:8333");
+    addrman.add_address(addr("1.2.3.4", 8333), source("5.6.7.8"));
+    addrman.good(&peer);
+    addrman.remove_address(&peer);
+    assert!(addrman.is_empty());
+    assert_eq!(addrman.tried_count(), 0);
+}
+
+#[test]
+fn test_address_conversion() {
+    let v4 = socket_to_network_addr("1.2.3.4:8333".parse().unwrap(), 1);
+    assert_eq!(v4.ip[10..12], [0xff, 0xff]);
+    assert_eq!(
+        network_addr_to_socket(&v4),
+        "1.2.3.4:8333".parse::<SocketAddr>().unwrap()
+    );
+    // IPv4-mapped legacy addresses become IPv4 addresses
+    let v4 = NetworkAddressV2::from(v4);
+    assert_eq!(v4.addr, NetAddr::Ipv4([1, 2, 3, 4]));
+    assert_eq!(v4.to_v1().unwrap().ip[10..12], [0xff, 0xff]);
+
+    let v6 = addr("2001:db8::1", 18333);
+    assert_eq!(v6.addr.network(), Network::Ipv6);
+    assert_eq!(
+        v6.service().to_socket(),
+        Some("[2001:db8::1]:18333".parse::<SocketAddr>().unwrap())
+    );
+    assert_eq!(
+        service("[2001:db8::1]:18333").to_string(),
+        "[2001:db8::1]:18333"
+    );
+
+    // Overlay addresses have no legacy form
+    let onion = NetworkAddressV2 {
+        time: 0,
+        services: 1,
+        addr: NetAddr::TorV3([1; 32]),
+        port: 8333,
+    };
+    assert!(onion.to_v1().is_none());
+    assert!(onion.service().to_socket().is_none());
+}
+
+#[test]
+fn test_is_local() {
+    assert!(source("127.0.0.1").is_local());
+    assert!(source("10.0.0.1").is_local());
+    assert!(source("169.254.1.1").is_local());
+    assert!(source("::1").is_local());
+    assert!(source("fe80::1").is_local());
+    assert!(source("fd00::1").is_local());
+    assert!(!source("8.8.8.8").is_local());
+    assert!(!source("2001:db8::1").is_local());
+    assert!(!NetAddr::TorV3([0; 32]).is_local());
+    // CJDNS addresses must be in fc00::/8
+    assert!(NetAddr::Cjdns([0xfc; 16]).is_routable());
+    assert!(!NetAddr::Cjdns([0xfd; 16]).is_routable());
+}
+
+#[test]
+fn test_filter_addresses() {
+    let banned = addr("1.1.1.1", 8333);
+    let connected = addr("2.2.2.2", 8333);
+    let expired_ban = addr("3.3.3.3", 8333);
+    let fresh = addr("4.4.4.4", 8333);
+    let onion = NetworkAddressV2 {
+        time: 0,
+        services: 1,
+        addr: NetAddr::TorV3([1; 32]),
+        port: 8333,
+    };
+
+    let mut ban_list = HashMap::new();
+    ban_list.insert(banned.service().to_socket().unwrap(), u64::MAX);
+    ban_list.insert(expired_ban.service().to_socket().unwrap(), 1);
+    assert!(is_banned(&banned.service(), &ban_list));
+    assert!(!is_banned(&expired_ban.service(), &ban_list));
+    assert!(!is_banned(&onion.service(), &ban_list));
+
+    let filtered = filter_addresses(
+        vec![
+            banned,
+            connected.clone(),
+            expired_ban.clone(),
+            fresh.clone(),
+            onion.clone(),
+            addr("127.0.0.1", 8333),
+        ],
+        &ban_list,
+        &[connected.service().to_socket().unwrap()],
+    );
+    assert_eq!(filtered, vec![expired_ban, fresh, onion]);
+}
@@ -0,0 +1,179 @@
+//! Tests for BIP155 addrv2 messages and network addresses
+
+use bllvm_node::network::netaddr::{NetAddr, Network, ServiceAddr};
+use bllvm_node::network::protocol::{
+    AddrV2Message, NetworkAddressV2, ProtocolMessage, ProtocolParser, MAX_ADDR_TO_SEND,
+};
+
+fn entry(addr: NetAddr, port: u16) -> NetworkAddressV2 {
+    NetworkAddressV2 {
+        time: 1_700_000_000,
+        services: 0x409,
+        addr,
+        port,
+    }
+}
+
+fn every_network() -> Vec<NetworkAddressV2> {
+    vec![
+        entry(NetAddr::Ipv4([1, 2, 3, 4]), 8333),
+        entry(
+            "2001:db8::1".parse::<std::net::IpAddr>().unwrap().into(),
+            8333,
+        ),
+        entry(NetAddr::TorV3([0xab; 32]), 8333),
+        entry(NetAddr::I2p([0xcd; 32]), 0),
+        entry(NetAddr::Cjdns([0xfc; 16]), 8333),
+    ]
+}
+
+fn round_trip(message: &ProtocolMessage) -> ProtocolMessage {
+    let bytes = ProtocolParser::serialize_message(message).unwrap();
+    ProtocolParser::parse_message(&bytes).unwrap()
+}
+
+/// Payload with one entry on network `id` with `len` address bytes
+fn raw_entry(id: u8, len: usize) -> Vec<u8> {
+    let mut payload = vec![1]; // count
+    payload.extend_from_slice(&0u32.to_le_bytes());
+    payload.push(1); // services
+    payload.push(id);
+    payload.push(len as u8);
+    payload.extend(std::iter::repeat_n(0x42, len));
+    payload.extend_from_slice(&8333u16.to_be_bytes());
+    payload
+}
+
+#[test]
+fn test_addrv2_round_trip_every_network() {
+    let addresses = every_network();
+    let networks: Vec<Network> = addresses.iter().map(|a| a.addr.network()).collect();
+    assert_eq!(networks, Network::ALL);
+
+    for address in &addresses {
+        let message = ProtocolMessage::AddrV2(AddrV2Message {
+            addresses: vec![address.clone()],
+        });
+        match round_trip(&message) {
+            ProtocolMessage::AddrV2(msg) => assert_eq!(msg.addresses, vec![address.clone()]),
+            other => panic!("Expected addrv2, got {:?}", other),
+        }
+    }
+
+    let message = AddrV2Message { addresses };
+    assert_eq!(AddrV2Message::decode(&message.encode()).unwrap(), message);
+}
+
+#[test]
+fn test_addrv2_wire_encoding() {
+    let message = AddrV2Message {
+        addresses: vec![entry(NetAddr::Ipv4([1, 2, 3, 4]), 8333)],
+    };
+    let mut expected = vec![1];
+    expected.extend_from_slice(&1_700_000_000u32.to_le_bytes());
+    expected.extend_from_slice(&[0xfd, 0x09, 0x04]); // CompactSize services
+    expected.extend_from_slice(&[1, 4, 1, 2, 3, 4]);
+    expected.extend_from_slice(&8333u16.to_be_bytes());
+    assert_eq!(message.encode(), expected);
+}
+
+#[test]
+fn test_sendaddrv2_round_trip() {
+    let bytes = ProtocolParser::serialize_message(&ProtocolMessage::SendAddrV2).unwrap();
+    // Header only
+    assert_eq!(bytes.len(), 24);
+    assert!(matches!(
+        ProtocolParser::parse_message(&bytes).unwrap(),
+        ProtocolMessage::SendAddrV2
+    ));
+}
+
+#[test]
+fn test_addrv2_skips_unknown_networks() {
+    // Tor v2 and unknown network IDs are ignored, not rejected
+    assert!(AddrV2Message::decode(&raw_entry(3, 10))
+        .unwrap()
+        .addresses
+        .is_empty());
+    assert!(AddrV2Message::decode(&raw_entry(42, 20))
+        .unwrap()
+        .addresses
+        .is_empty());
+    // So is IPv4-mapped IPv6
+    let mut mapped = raw_entry(2, 16);
+    mapped[8..24].copy_from_slice(
+        &std::net::Ipv4Addr::new(1, 2, 3, 4)
+            .to_ipv6_mapped()
+            .octets(),
+    );
+    assert!(AddrV2Message::decode(&mapped).unwrap().addresses.is_empty());
+}
+
+#[test]
+fn test_addrv2_rejects_malformed() {
+    // Known network, wrong length
+    assert!(AddrV2Message::decode(&raw_entry(1, 5)).is_err());
+    assert!(AddrV2Message::decode(&raw_entry(4, 31)).is_err());
+    // Truncated
+    let payload = raw_entry(1, 4);
+    assert!(AddrV2Message::decode(&payload[..payload.len() - 1]).is_err());
+    // Trailing data
+    let mut trailing = raw_entry(1, 4);
+    trailing.push(0);
+    assert!(AddrV2Message::decode(&trailing).is_err());
+    // Non-canonical CompactSize count
+    let mut non_canonical = vec![0xfd, 1, 0];
+    non_canonical.extend_from_slice(&raw_entry(1, 4)[1..]);
+    assert!(AddrV2Message::decode(&non_canonical).is_err());
+
+    // Too many addresses
+    let message = AddrV2Message {
+        addresses: vec![entry(NetAddr::Ipv4([1, 2, 3, 4]), 8333); MAX_ADDR_TO_SEND + 1],
+    };
+    assert!(AddrV2Message::decode(&message.encode()).is_err());
+
+    // Address longer than 512 bytes, even on an unknown network
+    let mut oversized = vec![1];
+    oversized.extend_from_slice(&0u32.to_le_bytes());
+    oversized.extend_from_slice(&[1, 42, 0xfd]);
+    oversized.extend_from_slice(&513u16.to_le_bytes());
+    oversized.extend(std::iter::repeat_n(0, 513 + 2));
+    assert!(AddrV2Message::decode(&oversized).is_err());
+}
+
+#[test]
+fn test_address_strings_round_trip() {
+    for address in every_network() {
+        let service = address.service();
+        let parsed: ServiceAddr = service.to_string().parse().unwrap();
+        // CJDNS addresses print as IPv6
+        if address.addr.network() == Network::Cjdns {
+            assert_eq!(parsed.addr.network(), Network::Ipv6);
+            assert_eq!(parsed.addr.as_bytes(), service.addr.as_bytes());
+        } else {
+            assert_eq!(parsed, service);
+        }
+    }
+
+    let onion = NetAddr::TorV3([0xab; 32]).to_string();
+    assert!(onion.ends_with(".onion"));
+    assert_eq!(onion.len(), 56 + ".onion".len());
+    let i2p = NetAddr::I2p([0xcd; 32]).to_string();
+    assert!(i2p.ends_with(".b32.i2p"));
+    assert_eq!(i2p.len(), 52 + ".b32.i2p".len());
+    assert!("notbase32!.onion".parse::<NetAddr>().is_err());
+}
+
+#[test]
+fn test_legacy_conversion() {
+    for address in every_network() {
+        let legacy = address.to_v1();
+        match address.addr.network() {
+            Network::Ipv4 | Network::Ipv6 => {
+                let back = NetworkAddressV2::from(legacy.unwrap());
+                assert_eq!(back.service(), address.service());
+            }
+            _ => assert!(legacy.is_none()),
+        }
+    }
+}
@@ -0,0 +1,418 @@
+//! Tests for AssumeUTXO snapshots: dump, load, and background validation of
+//! the history below the snapshot base
+
+use bllvm_node::node::block_processor::commit_connected_block;
+use bllvm_node::node::reorg::build_block_undo;
+use bllvm_node::storage::blockindex::BlockStatus;
+use bllvm_node::storage::chainstate::{AssumeUtxoData, ChainParams};
+use bllvm_node::storage::hashing::calculate_snapshot_hash;
+use bllvm_node::storage::snapshot::{
+    read_snapshot, write_snapshot, write_snapshot_coin, write_snapshot_header, SnapshotMetadata,
+    SnapshotStatus,
+};
+use bllvm_node::storage::Storage;
+use bllvm_node::{Block, OutPoint, UTXO};
+use bllvm_protocol::block::calculate_tx_id;
+use std::fs::File;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+mod common;
+use common::{
+    connect_chain, create_regtest_storage, create_test_storage, make_block, plain_coinbase,
+};
+
+/// Known snapshots of the regtest network
+fn regtest_snapshots() -> &'static [AssumeUtxoData] {
+    ChainParams::for_network("regtest").assumeutxo_snapshots()
+}
+
+/// Apply `block` to the background UTXO set and commit it, as block sync does
+/// after validating it
+fn connect_background(storage: &Arc<Storage>, block: &Block) -> SnapshotStatus {
+    let height = storage
+        .background_block_height(block)
+        .unwrap()
+        .expect("block should extend the background chain");
+    let hash = storage.blocks().get_block_hash(block);
+    storage
+        .background_utxo_cache()
+        .apply_block(block, &hash, height)
+        .unwrap();
+    storage
+        .connect_background_block(block, &[], height)
+        .unwrap()
+}
+
+fn known(metadata: &SnapshotMetadata) -> Vec<AssumeUtxoData> {
+    vec![AssumeUtxoData {
+        height: metadata.base_height,
+        block_hash: metadata.base_hash,
+        utxo_hash: metadata.utxo_hash,
+    }]
+}
+
+/// Dump a four-block regtest chain and return the blocks, snapshot path and
+/// metadata
+fn dump_chain(dir: &TempDir) -> (Vec<Block>, std::path::PathBuf, SnapshotMetadata) {
+    let (_source_dir, source) = create_regtest_storage();
+    let blocks = connect_chain(&source, 4);
+    let path = dir.path().join("utxo.dat");
+    let metadata = source.dump_snapshot(&path).unwrap();
+    (blocks, path, metadata)
+}
+
+#[test]
+fn test_dump_snapshot_at_tip() {
+    let (_temp_dir, storage) = create_test_storage();
+    let blocks = connect_chain(&storage, 4);
+    let tip_hash = storage.blocks().get_block_hash(&blocks[3]);
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("utxo.dat");
+
+    let metadata = storage.dump_snapshot(&path).unwrap();
+    assert_eq!(metadata.network, "mainnet");
+    assert_eq!(metadata.base_hash, tip_hash);
+    assert_eq!(metadata.base_height, 3);
+    // The genesis coinbase is left out, as in Bitcoin Core
+    assert_eq!(metadata.coins_count, 3);
+    assert_eq!(metadata.recent_headers.len(), 4);
+    // Same hash as gettxoutsetinfo's hash_serialized_3
+    assert_eq!(
+        metadata.utxo_hash,
+        calculate_snapshot_hash(&storage.utxo_cache().get_all_utxos().unwrap())
+    );
+
+    let (read, utxo_set) = read_snapshot(File::open(&path).unwrap()).unwrap();
+    assert_eq!(read.base_hash, tip_hash);
+    assert_eq!(utxo_set.len(), 3);
+    assert!(!dir.path().join("utxo.dat.incomplete").exists());
+
+    // Never overwrites an existing file
+    assert!(storage.dump_snapshot(&path).is_err());
+}
+
+#[test]
+fn test_load_snapshot_into_fresh_datadir() {
+    let dir = TempDir::new().unwrap();
+    let (blocks, path, metadata) = dump_chain(&dir);
+    let base_hash = metadata.base_hash;
+    // The chain is the one in the regtest table
+    assert_eq!(regtest_snapshots(), &known(&metadata)[..]);
+
+    let (_temp_dir, storage) = create_regtest_storage();
+    let loaded = storage.load_snapshot(&path, regtest_snapshots()).unwrap();
+    assert_eq!(loaded.base_hash, base_hash);
+
+    assert_eq!(storage.chain().get_tip_hash().unwrap(), Some(base_hash));
+    assert_eq!(storage.chain().get_height().unwrap(), Some(3));
+    assert_eq!(
+        storage.blocks().get_hash_by_height(3).unwrap(),
+        Some(base_hash)
+    );
+    assert_eq!(storage.utxos().utxo_count().unwrap(), 3);
+    assert_eq!(
+        storage.utxos().get_best_block().unwrap(),
+        Some((base_hash, 3))
+    );
+    assert_eq!(storage.blocks().get_recent_headers(11).unwrap().len(), 4);
+    for (height, block) in blocks.iter().enumerate() {
+        let outpoint = OutPoint {
+            hash: calculate_tx_id(&block.transactions[0]),
+            index: 0,
+        };
+        assert_eq!(
+            storage.utxo_cache().has_utxo(&outpoint).unwrap(),
+            height > 0
+        );
+    }
+
+    let base = storage.snapshot_base().unwrap().unwrap();
+    assert_eq!(base.base_hash, base_hash);
+    assert_eq!(base.status, SnapshotStatus::Unvalidated);
+    assert!(storage.check_consistency().unwrap().is_clean());
+
+    // Syncing continues from the base block
+    let next = make_block(base_hash, 4, vec![plain_coinbase(4)]);
+    let mut cache = storage.utxo_cache();
+    let undo = build_block_undo(&next, &cache.fetch_view(&next).unwrap());
+    commit_connected_block(
+        &storage.blocks(),
+        Some(&storage),
+        &next,
+        &[],
+        4,
+        &undo,
+        &mut cache,
+    )
+    .unwrap();
+    assert_eq!(storage.chain().get_height().unwrap(), Some(4));
+    assert_eq!(storage.utxo_cache().utxo_count().unwrap(), 4);
+}
+
+#[test]
+fn test_load_snapshot_requires_known_hash() {
+    let dir = TempDir::new().unwrap();
+    let (_blocks, path, metadata) = dump_chain(&dir);
+    let (_temp_dir, storage) = create_regtest_storage();
+
+    // Not in the table at all
+    assert!(storage.load_snapshot(&path, &[]).is_err());
+    assert!(storage
+        .load_snapshot(&path, ChainParams::default().assumeutxo_snapshots())
+        .is_err());
+
+    // Known height, different content
+    let mut wrong = known(&metadata);
+    wrong[0].utxo_hash = [0xab; 32];
+    assert!(storage.load_snapshot(&path, &wrong).is_err());
+
+    // Known height, different block
+    let mut wrong = known(&metadata);
+    wrong[0].block_hash = [0xab; 32];
+    assert!(storage.load_snapshot(&path, &wrong).is_err());
+
+    assert!(storage.chain().get_tip_hash().unwrap().is_none());
+    assert_eq!(storage.utxos().utxo_count().unwrap(), 0);
+}
+
+#[test]
+fn test_load_snapshot_rejects_wrong_network_and_existing_chain() {
+    let dir = TempDir::new().unwrap();
+    let (_blocks, path, metadata) = dump_chain(&dir);
+
+    let (_temp_dir, mainnet) = create_test_storage();
+    assert!(mainnet.load_snapshot(&path, &known(&metadata)).is_err());
+
+    let (_temp_dir, synced) = create_regtest_storage();
+    connect_chain(&synced, 1);
+    assert!(synced.load_snapshot(&path, &known(&metadata)).is_err());
+}
+
+#[test]
+fn test_read_snapshot_checks_content_hash() {
+    let dir = TempDir::new().unwrap();
+    let (_blocks, path, mut metadata) = dump_chain(&dir);
+    let (_, mut utxo_set) = read_snapshot(File::open(&path).unwrap()).unwrap();
+
+    // Flipping a coin's coinbase flag changes the content hash
+    let coin = utxo_set.values_mut().next().unwrap();
+    coin.is_coinbase = !coin.is_coinbase;
+    let tampered = dir.path().join("tampered.dat");
+    write_snapshot(File::create(&tampered).unwrap(), &metadata, &utxo_set).unwrap();
+    assert!(read_snapshot(File::open(&tampered).unwrap()).is_err());
+
+    // Coins out of outpoint order, even though they hash correctly
+    let (_, ordered) = read_snapshot(File::open(&path).unwrap()).unwrap();
+    let mut entries: Vec<_> = ordered.iter().collect();
+    entries.sort_by(|(a, _), (b, _)| (b.hash, b.index).cmp(&(a.hash, a.index)));
+    let unordered = dir.path().join("unordered.dat");
+    let mut file = File::create(&unordered).unwrap();
+    write_snapshot_header(&mut file, &metadata).unwrap();
+    for (outpoint, utxo) in entries {
+        write_snapshot_coin(&mut file, outpoint, utxo).unwrap();
+    }
+    drop(file);
+    assert!(read_snapshot(File::open(&unordered).unwrap()).is_err());
+
+    // Truncated coin list
+    metadata.coins_count += 1;
+    metadata.utxo_hash = calculate_snapshot_hash(&utxo_set);
+    let truncated = dir.path().join("truncated.dat");
+    write_snapshot(File::create(&truncated).unwrap(), &metadata, &utxo_set).unwrap();
+    assert!(read_snapshot(File::open(&truncated).unwrap()).is_err());
+
+    assert!(read_snapshot(&b"not a snapshot"[..]).is_err());
+}
+
+#[test]
+fn test_failed_load_leaves_no_coins_behind() {
+    let dir = TempDir::new().unwrap();
+    let (_blocks, path, metadata) = dump_chain(&dir);
+    let (_, mut utxo_set) = read_snapshot(File::open(&path).unwrap()).unwrap();
+    let coin = utxo_set.values_mut().next().unwrap();
+    coin.value += 1;
+    let tampered = dir.path().join("tampered.dat");
+    write_snapshot(File::create(&tampered).unwrap(), &metadata, &utxo_set).unwrap();
+
+    // The hash mismatch is only found after every coin has been stored
+    let (_temp_dir, storage) = create_regtest_storage();
+    assert!(storage.load_snapshot(&tampered, &known(&metadata)).is_err());
+    assert_eq!(storage.utxos().utxo_count().unwrap(), 0);
+    assert_eq!(storage.chain().get_height().unwrap(), None);
+
+    storage.load_snapshot(&path, &known(&metadata)).unwrap();
+    assert_eq!(storage.utxos().utxo_count().unwrap(), 3);
+}
+
+#[test]
+fn test_background_validation_reaches_snapshot_base() {
+    let dir = TempDir::new().unwrap();
+    let (blocks, path, _) = dump_chain(&dir);
+    let (_temp_dir, storage) = create_regtest_storage();
+    storage.load_snapshot(&path, regtest_snapshots()).unwrap();
+
+    // Only the next block of history is accepted, starting from genesis
+    assert_eq!(storage.background_block_height(&blocks[1]).unwrap(), None);
+    assert_eq!(
+        storage.background_block_height(&blocks[0]).unwrap(),
+        Some(0)
+    );
+
+    for block in &blocks[..3] {
+        assert_eq!(
+            connect_background(&storage, block),
+            SnapshotStatus::Unvalidated
+        );
+    }
+    assert_eq!(storage.background_utxo_cache().utxo_count().unwrap(), 3);
+    assert!(storage
+        .blocks()
+        .get_block(&storage.blocks().get_block_hash(&blocks[1]))
+        .unwrap()
+        .is_some());
+
+    assert_eq!(
+        connect_background(&storage, &blocks[3]),
+        SnapshotStatus::Validated
+    );
+    assert_eq!(
+        storage.snapshot_base().unwrap().unwrap().status,
+        SnapshotStatus::Validated
+    );
+    for (height, block) in blocks.iter().enumerate() {
+        assert_eq!(
+            storage.blocks().get_hash_by_height(height as u64).unwrap(),
+            Some(storage.blocks().get_block_hash(block))
+        );
+    }
+
+    // The background set is dropped and history is no longer routed to it
+    assert!(storage.background_utxo_cache().best_block().is_none());
+    assert_eq!(storage.background_utxo_cache().utxo_count().unwrap(), 0);
+    assert_eq!(storage.background_block_height(&blocks[0]).unwrap(), None);
+}
+
+#[test]
+fn test_background_validation_detects_bad_snapshot() {
+    let dir = TempDir::new().unwrap();
+    let (blocks, path, _) = dump_chain(&dir);
+
+    // Same base block, plus a coin the history never created
+    let (mut metadata, mut utxo_set) = read_snapshot(File::open(&path).unwrap()).unwrap();
+    utxo_set.insert(
+        OutPoint {
+            hash: [0xee; 32],
+            index: 0,
+        },
+        UTXO {
+            value: 21_000_000_0000_0000,
+            script_pubkey: vec![0x51],
+            height: 2,
+            is_coinbase: false,
+        },
+    );
+    metadata.coins_count = utxo_set.len() as u64;
+    metadata.utxo_hash = calculate_snapshot_hash(&utxo_set);
+    let bad = dir.path().join("bad.dat");
+    write_snapshot(File::create(&bad).unwrap(), &metadata, &utxo_set).unwrap();
+
+    let (_temp_dir, storage) = create_regtest_storage();
+    storage.load_snapshot(&bad, &known(&metadata)).unwrap();
+    let fake = OutPoint {
+        hash: [0xee; 32],
+        index: 0,
+    };
+    assert!(storage.utxo_cache().has_utxo(&fake).unwrap());
+
+    // A block connected on top of the snapshot before it is found invalid
+    let next = make_block(metadata.base_hash, 4, vec![plain_coinbase(4)]);
+    let next_hash = storage.blocks().get_block_hash(&next);
+    let mut cache = storage.utxo_cache();
+    let undo = build_block_undo(&next, &cache.fetch_view(&next).unwrap());
+    commit_connected_block(
+        &storage.blocks(),
+        Some(&storage),
+        &next,
+        &[],
+        4,
+        &undo,
+        &mut cache,
+    )
+    .unwrap();
+
+    for block in &blocks[..3] {
+        connect_background(&storage, block);
+    }
+    assert_eq!(
+        connect_background(&storage, &blocks[3]),
+        SnapshotStatus::Invalid
+    );
+    assert_eq!(
+        storage.snapshot_base().unwrap().unwrap().status,
+        SnapshotStatus::Invalid
+    );
+
+    // The chain state validated from genesis replaces the snapshot's: the tip
+    // is back at the base and the block above it must be connected again
+    assert_eq!(storage.chain().get_height().unwrap(), Some(3));
+    assert!(storage.blocks().get_hash_by_height(4).unwrap().is_none());
+    let entry = storage.block_index().get(&next_hash).unwrap().unwrap();
+    assert!(entry.has_data());
+    assert!(!entry.status.contains(BlockStatus::FULLY_VALID));
+    assert_eq!(
+        storage.utxos().get_best_block().unwrap(),
+        Some((metadata.base_hash, 3))
+    );
+    assert!(!storage.utxo_cache().has_utxo(&fake).unwrap());
+    // Every coinbase up to the base, including the genesis one
+    assert_eq!(storage.utxo_cache().utxo_count().unwrap(), 4);
+    assert!(storage.background_utxo_cache().best_block().is_none());
+    assert!(storage.check_consistency().unwrap().is_clean());
+}
+
+#[test]
+fn test_unflushed_background_blocks_are_replayed_at_startup() {
+    let dir = TempDir::new().unwrap();
+    let (blocks, path, _) = dump_chain(&dir);
+    let temp_dir = TempDir::new().unwrap();
+    {
+        let storage = Arc::new(Storage::new(temp_dir.path()).unwrap());
+        storage
+            .chain()
+            .store_chain_params(&ChainParams::for_network("regtest"))
+            .unwrap();
+        storage.load_snapshot(&path, regtest_snapshots()).unwrap();
+        connect_background(&storage, &blocks[0]);
+        connect_background(&storage, &blocks[1]);
+        // Dropped without flushing the background UTXO set
+    }
+
+    let storage = Arc::new(Storage::new(temp_dir.path()).unwrap());
+    assert!(storage.background_utxo_cache().best_block().is_none());
+    let report = storage.check_consistency().unwrap();
+    assert_eq!(report.background_blocks_replayed, 2);
+    assert_eq!(
+        storage.background_utxo_cache().best_block(),
+        Some((storage.blocks().get_block_hash(&blocks[1]), 1))
+    );
+
+    // Validation picks up where it left off
+    assert_eq!(
+        storage.background_block_height(&blocks[2]).unwrap(),
+        Some(2)
+    );
+    connect_background(&storage, &blocks[2]);
+    assert_eq!(
+        connect_background(&storage, &blocks[3]),
+        SnapshotStatus::Validated
+    );
+    assert!(storage.check_consistency().unwrap().is_clean());
+}
+
+#[test]
+fn test_dump_requires_chain() {
+    let (_temp_dir, storage) = create_test_storage();
+    let dir = TempDir::new().unwrap();
+    assert!(storage.dump_snapshot(&dir.path().join("utxo.dat")).is_err());
+}
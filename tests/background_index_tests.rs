@@ -0,0 +1,255 @@
+//! Tests for the background index framework (txindex, BIP158 filters, UTXO stats)
+
+use bllvm_node::config::{IndexingConfig, StorageConfig};
+use bllvm_node::node::reorg::{ReorgEngine, ReorgOutcome};
+use bllvm_node::rpc::blockchain::BlockchainRpc;
+use bllvm_node::storage::database::default_backend;
+use bllvm_node::storage::Storage;
+use bllvm_node::Block;
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{BitcoinProtocolEngine, ProtocolVersion};
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+mod common;
+use common::{connect_regtest_chain, mine_regtest_block};
+
+const SUBSIDY: u128 = 50_0000_0000;
+
+fn open_storage(dir: &Path, background_indexing: bool, all_indexes: bool) -> Arc<Storage> {
+    let config = StorageConfig {
+        indexing: Some(IndexingConfig {
+            background_indexing,
+            enable_block_filter_index: all_indexes,
+            enable_coin_stats_index: all_indexes,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    Arc::new(Storage::with_config(dir, default_backend(), &config).unwrap())
+}
+
+/// Connect `count` blocks from genesis as the active chain
+fn connect_chain(storage: &Arc<Storage>, count: u64) -> Vec<Block> {
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    connect_regtest_chain(storage, &protocol, count)
+}
+
+/// Reorganize onto a three-block branch forking after genesis
+fn reorg_to_side_branch(storage: &Arc<Storage>, main: &[Block]) -> Vec<Block> {
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let blocks = storage.blocks();
+    let engine = ReorgEngine::new(&blocks, &protocol, Some(storage));
+    let mut prev = blocks.get_block_hash(&main[0]);
+    let mut side = Vec::new();
+    for height in 1..=3 {
+        let block = mine_regtest_block(prev, height, 2);
+        prev = blocks.get_block_hash(&block);
+        blocks.store_block(&block).unwrap();
+        side.push(block);
+    }
+    let mut utxos = storage.utxo_cache();
+    assert!(matches!(
+        engine
+            .reorganize(&prev, main.len() as u64 - 1, &mut utxos)
+            .unwrap(),
+        ReorgOutcome::Reorganized(_)
+    ));
+    side
+}
+
+fn index_height(storage: &Storage, name: &str) -> Option<u64> {
+    let info = storage.index_info().unwrap();
+    let info = info.iter().find(|info| info.name == name).unwrap();
+    info.best_block_height
+}
+
+#[test]
+fn test_indexes_follow_connected_blocks() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = open_storage(temp_dir.path(), false, true);
+    let chain = connect_chain(&storage, 4);
+    let blocks = storage.blocks();
+
+    let info = storage.index_info().unwrap();
+    let names: Vec<_> = info.iter().map(|info| info.name).collect();
+    assert_eq!(
+        names,
+        vec!["txindex", "basic block filter index", "coinstatsindex"]
+    );
+    assert!(info
+        .iter()
+        .all(|info| info.synced && info.best_block_height == Some(3)));
+    // Nothing left to catch up
+    assert_eq!(storage.sync_indexes().unwrap(), 0);
+
+    let tip = blocks.get_block_hash(&chain[3]);
+    let stats = storage
+        .coin_stats_index()
+        .unwrap()
+        .get(&tip)
+        .unwrap()
+        .unwrap();
+    assert_eq!(stats.height, 3);
+    assert_eq!(stats.txouts, 4);
+    assert_eq!(stats.total_amount, 4 * SUBSIDY);
+    assert_eq!(stats.bogosize, 4 * 51);
+
+    // Filter headers chain back to genesis
+    let filters = storage.filter_index().unwrap();
+    let mut prev_header = [0u8; 32];
+    for block in &chain {
+        let filter = filters.get(&blocks.get_block_hash(block)).unwrap().unwrap();
+        assert_eq!(filter.prev_header_hash, prev_header);
+        prev_header = filter.header().header_hash();
+    }
+}
+
+#[test]
+fn test_index_enabled_later_catches_up() {
+    let temp_dir = TempDir::new().unwrap();
+    let chain = {
+        let storage = open_storage(temp_dir.path(), false, false);
+        let chain = connect_chain(&storage, 4);
+        storage.flush().unwrap();
+        chain
+    };
+
+    let storage = open_storage(temp_dir.path(), false, true);
+    assert_eq!(index_height(&storage, "txindex"), Some(3));
+    assert_eq!(index_height(&storage, "coinstatsindex"), None);
+    assert_eq!(index_height(&storage, "basic block filter index"), None);
+
+    // Only the two new indexes have blocks to index
+    assert_eq!(storage.sync_indexes().unwrap(), 8);
+    assert!(storage.index_info().unwrap().iter().all(|info| info.synced));
+    let tip = storage.blocks().get_block_hash(&chain[3]);
+    let stats = storage
+        .coin_stats_index()
+        .unwrap()
+        .get(&tip)
+        .unwrap()
+        .unwrap();
+    assert_eq!(stats.txouts, 4);
+    assert!(storage.filter_index().unwrap().get(&tip).unwrap().is_some());
+}
+
+#[test]
+fn test_background_indexes_roll_back_reorged_blocks() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = open_storage(temp_dir.path(), true, true);
+    let main = connect_chain(&storage, 3);
+
+    // Not updated with each block
+    assert_eq!(index_height(&storage, "txindex"), None);
+    assert_eq!(storage.sync_indexes().unwrap(), 9);
+    assert!(storage.index_info().unwrap().iter().all(|info| info.synced));
+
+    let side = reorg_to_side_branch(&storage, &main);
+    assert!(storage
+        .index_info()
+        .unwrap()
+        .iter()
+        .all(|info| !info.synced));
+
+    // Two blocks rolled back and three connected, per index
+    assert_eq!(storage.sync_indexes().unwrap(), 15);
+    assert!(storage
+        .index_info()
+        .unwrap()
+        .iter()
+        .all(|info| info.synced && info.best_block_height == Some(3)));
+
+    let txindex = storage.transactions();
+    for block in &main[1..] {
+        assert!(!txindex
+            .has_transaction(&calculate_tx_id(&block.transactions[0]))
+            .unwrap());
+        let hash = storage.blocks().get_block_hash(block);
+        assert!(storage
+            .coin_stats_index()
+            .unwrap()
+            .get(&hash)
+            .unwrap()
+            .is_none());
+        assert!(storage
+            .filter_index()
+            .unwrap()
+            .get(&hash)
+            .unwrap()
+            .is_none());
+    }
+    for block in &side {
+        assert!(txindex
+            .has_transaction(&calculate_tx_id(&block.transactions[0]))
+            .unwrap());
+    }
+    let tip = storage.blocks().get_block_hash(&side[2]);
+    let stats = storage
+        .coin_stats_index()
+        .unwrap()
+        .get(&tip)
+        .unwrap()
+        .unwrap();
+    assert_eq!(stats.txouts, 4);
+}
+
+#[test]
+fn test_inline_indexes_follow_reorg() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = open_storage(temp_dir.path(), false, true);
+    let main = connect_chain(&storage, 3);
+    let side = reorg_to_side_branch(&storage, &main);
+
+    assert!(storage
+        .index_info()
+        .unwrap()
+        .iter()
+        .all(|info| info.synced && info.best_block_height == Some(3)));
+    assert_eq!(storage.sync_indexes().unwrap(), 0);
+    assert!(!storage
+        .transactions()
+        .has_transaction(&calculate_tx_id(&main[2].transactions[0]))
+        .unwrap());
+    assert!(storage
+        .transactions()
+        .has_transaction(&calculate_tx_id(&side[2].transactions[0]))
+        .unwrap());
+}
+
+#[tokio::test]
+async fn test_getindexinfo_reports_synced_heights() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = open_storage(temp_dir.path(), true, true);
+    let chain = connect_chain(&storage, 3);
+    let rpc = BlockchainRpc::with_dependencies(Arc::clone(&storage));
+
+    let info = rpc.get_index_info(&json!([])).await.unwrap();
+    assert_eq!(info["coinstatsindex"]["synced"], json!(false));
+    assert_eq!(info["coinstatsindex"]["best_block_height"], json!(null));
+
+    storage.sync_indexes().unwrap();
+    let info = rpc.get_index_info(&json!([])).await.unwrap();
+    for name in ["txindex", "basic block filter index", "coinstatsindex"] {
+        assert_eq!(info[name]["synced"], json!(true));
+        assert_eq!(info[name]["best_block_height"], json!(2));
+    }
+    assert_eq!(info["txindex"]["total_transactions"], json!(3));
+
+    let info = rpc
+        .get_index_info(&json!(["coinstatsindex"]))
+        .await
+        .unwrap();
+    assert_eq!(info.as_object().unwrap().len(), 1);
+
+    // Historical UTXO set totals come from the coinstats index
+    let stats = rpc.get_txoutset_info(&json!(["none", 1])).await.unwrap();
+    assert_eq!(stats["height"], json!(1));
+    assert_eq!(stats["txouts"], json!(2));
+    assert_eq!(
+        stats["bestblock"],
+        json!(hex::encode(storage.blocks().get_block_hash(&chain[1])))
+    );
+}
@@ -0,0 +1,118 @@
+//! Tests for ancestor fee rate (CPFP) block assembly
+
+use bllvm_node::node::mempool::MempoolManager;
+use bllvm_node::node::miner::{transaction_weight, TransactionSelector};
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{
+    Hash, OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoSet, UTXO,
+};
+
+/// Weight the selector reserves for the header and coinbase
+const RESERVED_WEIGHT: u64 = 4000;
+
+fn spend(prev_hash: Hash, value: u64, script_len: usize) -> Transaction {
+    Transaction {
+        version: 1,
+        inputs: bllvm_protocol::tx_inputs![TransactionInput {
+            prevout: OutPoint {
+                hash: prev_hash,
+                index: 0,
+            },
+            script_sig: vec![],
+            sequence: 0xffffffff,
+        }],
+        outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+            value: value as i64,
+            script_pubkey: vec![0x51; script_len],
+        }],
+        lock_time: 0,
+    }
+}
+
+fn utxo_set(coins: &[(Hash, u64)]) -> UtxoSet {
+    coins
+        .iter()
+        .map(|(hash, value)| {
+            (
+                OutPoint {
+                    hash: *hash,
+                    index: 0,
+                },
+                UTXO {
+                    value: *value as i64,
+                    script_pubkey: vec![0x51],
+                    height: 1,
+                    is_coinbase: false,
+                },
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_child_pays_for_parent() {
+    let utxos = utxo_set(&[([1; 32], 100_000), ([2; 32], 100_000)]);
+    let parent = spend([1; 32], 99_900, 1);
+    let child = spend(calculate_tx_id(&parent), 89_900, 1);
+    let standalone = spend([2; 32], 95_000, 1);
+    let (parent_id, child_id, standalone_id) = (
+        calculate_tx_id(&parent),
+        calculate_tx_id(&child),
+        calculate_tx_id(&standalone),
+    );
+
+    let mempool = MempoolManager::new();
+    assert!(mempool.add_transaction(standalone, &utxos).await.unwrap());
+    assert!(mempool.add_transaction(parent, &utxos).await.unwrap());
+    assert!(mempool.add_transaction(child, &utxos).await.unwrap());
+
+    let entries = TransactionSelector::new().assemble(&mempool, &utxos);
+    let order: Vec<Hash> = entries.iter().map(|entry| entry.txid).collect();
+    // The parent alone pays the least, but with its child it outbids the
+    // standalone transaction
+    assert_eq!(order, vec![parent_id, child_id, standalone_id]);
+
+    assert_eq!(entries[0].fee, 100);
+    assert_eq!(entries[1].fee, 10_000);
+    assert_eq!(entries[2].fee, 5_000);
+    assert!(entries[0].depends.is_empty());
+    assert_eq!(entries[1].depends, vec![parent_id]);
+    assert_eq!(entries[1].weight, transaction_weight(&entries[1].tx));
+}
+
+#[tokio::test]
+async fn test_assembly_continues_past_transaction_that_does_not_fit() {
+    let utxos = utxo_set(&[([1; 32], 2_000_000), ([2; 32], 100_000)]);
+    let large = spend([1; 32], 1_000_000, 4_000);
+    let small = spend([2; 32], 99_000, 1);
+    let small_id = calculate_tx_id(&small);
+    let limit = RESERVED_WEIGHT + transaction_weight(&small);
+
+    let mempool = MempoolManager::new();
+    assert!(mempool.add_transaction(large, &utxos).await.unwrap());
+    assert!(mempool.add_transaction(small, &utxos).await.unwrap());
+
+    let selector = TransactionSelector::with_params(1_000_000, limit, 1);
+    let entries = selector.assemble(&mempool, &utxos);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].txid, small_id);
+}
+
+#[tokio::test]
+async fn test_assembly_skips_low_fee_and_unknown_inputs() {
+    let utxos = utxo_set(&[([1; 32], 100_000), ([2; 32], 100_000)]);
+    let paying = spend([1; 32], 99_000, 1);
+    let free = spend([2; 32], 100_000, 1);
+    let unknown = spend([3; 32], 50_000, 1);
+    let unknown_child = spend(calculate_tx_id(&unknown), 40_000, 1);
+    let paying_id = calculate_tx_id(&paying);
+
+    let mempool = MempoolManager::new();
+    for tx in [paying, free, unknown, unknown_child] {
+        assert!(mempool.add_transaction(tx, &utxos).await.unwrap());
+    }
+
+    let entries = TransactionSelector::new().assemble(&mempool, &utxos);
+    let order: Vec<Hash> = entries.iter().map(|entry| entry.txid).collect();
+    assert_eq!(order, vec![paying_id]);
+}
@@ -0,0 +1,248 @@
+//! Tests for the parallel block download window
+
+use bllvm_node::config::RequestTimeoutConfig;
+use bllvm_node::node::block_download::{BlockDownloadConfig, BlockDownloader};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+fn config(window_size: u64, max_blocks_in_flight_per_peer: usize) -> BlockDownloadConfig {
+    BlockDownloadConfig {
+        window_size,
+        max_blocks_in_flight_per_peer,
+        max_buffered_bytes: usize::MAX,
+        request_timeout: Duration::from_secs(60),
+        stall_timeout: Duration::from_secs(2),
+    }
+}
+
+fn hash(height: u64) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash[..8].copy_from_slice(&height.to_le_bytes());
+    hash
+}
+
+fn peer(n: u8) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, n], 8333))
+}
+
+/// Downloader for the blocks at heights `start..end`
+fn downloader(config: BlockDownloadConfig, start: u64, end: u64) -> BlockDownloader {
+    let mut downloader = BlockDownloader::new(config);
+    downloader.reset(start);
+    downloader.replace_from(start, (start..end).map(hash).collect());
+    downloader
+}
+
+fn requested(requests: &[(SocketAddr, Vec<[u8; 32]>)], peer: SocketAddr) -> Vec<[u8; 32]> {
+    requests
+        .iter()
+        .find(|(p, _)| *p == peer)
+        .map(|(_, hashes)| hashes.clone())
+        .unwrap_or_default()
+}
+
+#[test]
+fn test_config_from_request_timeouts() {
+    let timeouts = RequestTimeoutConfig {
+        async_request_timeout_seconds: 120,
+        block_stall_timeout_seconds: 5,
+        ..Default::default()
+    };
+    let config = BlockDownloadConfig::from_timeouts(&timeouts);
+    assert_eq!(config.request_timeout, Duration::from_secs(120));
+    assert_eq!(config.stall_timeout, Duration::from_secs(5));
+}
+
+#[test]
+fn test_window_is_spread_across_peers() {
+    let mut downloader = downloader(config(10, 4), 100, 200);
+    assert_eq!(downloader.window_end(), 110);
+    let now = Instant::now();
+
+    // Blocks alternate between the peers, lowest height first, up to the per-peer limit
+    let requests = downloader.schedule(&[peer(1), peer(2)], now);
+    assert_eq!(
+        requested(&requests, peer(1)),
+        vec![hash(100), hash(102), hash(104), hash(106)]
+    );
+    assert_eq!(
+        requested(&requests, peer(2)),
+        vec![hash(101), hash(103), hash(105), hash(107)]
+    );
+    assert_eq!(downloader.in_flight_count(), 8);
+
+    // A new peer gets what is left of the window, and nothing past it
+    let requests = downloader.schedule(&[peer(1), peer(2), peer(3)], now);
+    assert_eq!(requests, vec![(peer(3), vec![hash(108), hash(109)])]);
+    assert!(downloader
+        .schedule(&[peer(1), peer(2), peer(3)], now)
+        .is_empty());
+}
+
+#[test]
+fn test_blocks_are_handed_out_in_order() {
+    let mut downloader = downloader(config(10, 16), 0, 5);
+    downloader.schedule(&[peer(1)], Instant::now());
+
+    assert!(downloader.block_received(&hash(2), vec![2]));
+    assert!(downloader.block_received(&hash(1), vec![1]));
+    assert_eq!(downloader.pop_ready(), None);
+    assert_eq!(downloader.buffered_count(), 2);
+
+    assert!(downloader.block_received(&hash(0), vec![0]));
+    assert_eq!(downloader.pop_ready(), Some((0, vec![0])));
+    assert_eq!(downloader.pop_ready(), Some((1, vec![1])));
+    assert_eq!(downloader.pop_ready(), Some((2, vec![2])));
+    assert_eq!(downloader.pop_ready(), None);
+    assert_eq!(downloader.next_height(), 3);
+    assert_eq!(downloader.peer_in_flight(peer(1)), 2);
+
+    // Blocks that aren't being downloaded are left to the caller
+    assert!(!downloader.is_downloading(&hash(0)));
+    assert!(!downloader.block_received(&hash(42), vec![42]));
+}
+
+#[test]
+fn test_window_moves_as_blocks_connect() {
+    let mut downloader = downloader(config(4, 16), 0, 10);
+    let now = Instant::now();
+    assert_eq!(downloader.schedule(&[peer(1)], now)[0].1.len(), 4);
+
+    downloader.block_received(&hash(0), vec![0]);
+    downloader.pop_ready();
+    assert_eq!(downloader.window_end(), 5);
+    assert_eq!(
+        downloader.schedule(&[peer(1)], now),
+        vec![(peer(1), vec![hash(4)])]
+    );
+}
+
+#[test]
+fn test_timed_out_requests_are_reassigned() {
+    let mut downloader = downloader(config(10, 2), 0, 10);
+    let now = Instant::now();
+    downloader.schedule(&[peer(1), peer(2)], now);
+    assert!(downloader.check_timeouts(now).is_empty());
+
+    // Peer 2 delivers; peer 1 doesn't, but it is not stalling the window
+    downloader.block_received(&hash(1), vec![1]);
+    downloader.block_received(&hash(3), vec![3]);
+    let later = now + Duration::from_secs(60);
+    assert!(downloader.check_timeouts(later).is_empty());
+    assert_eq!(downloader.peer_in_flight(peer(1)), 0);
+
+    // Its blocks go to other peers; it only gets new ones
+    let requests = downloader.schedule(&[peer(1), peer(2), peer(3)], later);
+    assert_eq!(requested(&requests, peer(1)), vec![hash(4), hash(5)]);
+    assert_eq!(requested(&requests, peer(2)), vec![hash(0), hash(6)]);
+    assert_eq!(requested(&requests, peer(3)), vec![hash(2), hash(7)]);
+
+    // The slow peer's block is still taken if it turns up
+    assert!(downloader.block_received(&hash(0), vec![0]));
+    assert_eq!(downloader.pop_ready(), Some((0, vec![0])));
+    assert_eq!(downloader.pop_ready(), Some((1, vec![1])));
+}
+
+#[test]
+fn test_stalling_peer_is_dropped() {
+    let mut downloader = downloader(config(4, 2), 0, 10);
+    let now = Instant::now();
+    downloader.schedule(&[peer(1), peer(2)], now);
+
+    // Peer 2 delivers its blocks; peer 1 holds the start of the window
+    downloader.block_received(&hash(1), vec![1]);
+    downloader.block_received(&hash(3), vec![3]);
+    assert!(downloader.schedule(&[peer(1), peer(2)], now).is_empty());
+    assert!(downloader.check_timeouts(now).is_empty());
+    let soon = now + Duration::from_secs(1);
+    assert!(downloader.check_timeouts(soon).is_empty());
+
+    let later = now + Duration::from_secs(2);
+    assert_eq!(downloader.check_timeouts(later), vec![peer(1)]);
+    assert_eq!(
+        downloader.schedule(&[peer(2)], later),
+        vec![(peer(2), vec![hash(0), hash(2)])]
+    );
+}
+
+#[test]
+fn test_no_stall_without_blocks_past_the_window() {
+    let mut downloader = downloader(config(4, 2), 0, 4);
+    let now = Instant::now();
+    downloader.schedule(&[peer(1), peer(2)], now);
+    downloader.block_received(&hash(1), vec![1]);
+    downloader.block_received(&hash(3), vec![3]);
+
+    // Nothing waits on the window, so peer 1 only has the request timeout
+    downloader.check_timeouts(now);
+    assert!(downloader
+        .check_timeouts(now + Duration::from_secs(10))
+        .is_empty());
+}
+
+#[test]
+fn test_departed_peers_requests_are_reassigned() {
+    let mut downloader = downloader(config(10, 3), 0, 10);
+    let now = Instant::now();
+    downloader.schedule(&[peer(1), peer(2)], now);
+    assert_eq!(downloader.in_flight_count(), 6);
+
+    let requests = downloader.schedule(&[peer(2), peer(3)], now);
+    assert_eq!(downloader.peer_in_flight(peer(1)), 0);
+    assert_eq!(
+        requested(&requests, peer(3)),
+        vec![hash(0), hash(2), hash(4)]
+    );
+}
+
+#[test]
+fn test_follow_active_chain_and_branch_switch() {
+    let mut downloader = downloader(config(10, 16), 0, 10);
+    let now = Instant::now();
+    downloader.schedule(&[peer(1)], now);
+    downloader.block_received(&hash(5), vec![5]);
+
+    // Blocks connected some other way are dropped
+    downloader.advance_to(3);
+    assert_eq!(downloader.next_height(), 3);
+    assert_eq!(downloader.hash_at(3), Some(&hash(3)));
+    assert_eq!(downloader.peer_in_flight(peer(1)), 6);
+
+    // A different branch above height 5 replaces the blocks there
+    let branch: Vec<[u8; 32]> = (5..8).map(|height| hash(height + 1000)).collect();
+    downloader.replace_from(5, branch.clone());
+    assert_eq!(downloader.end_height(), 8);
+    assert_eq!(downloader.hash_at(5), Some(&branch[0]));
+    assert_eq!(downloader.buffered_count(), 0);
+    assert_eq!(downloader.peer_in_flight(peer(1)), 2);
+
+    // Going back, e.g. after a rejected block, starts over
+    downloader.advance_to(2);
+    assert!(downloader.is_empty());
+    assert_eq!(downloader.in_flight_count(), 0);
+    assert_eq!(downloader.next_height(), 2);
+}
+
+#[test]
+fn test_buffered_blocks_are_capped_in_bytes() {
+    let mut config = config(10, 2);
+    config.max_buffered_bytes = 2;
+    let mut downloader = downloader(config, 0, 10);
+    let now = Instant::now();
+    downloader.schedule(&[peer(1), peer(2)], now);
+
+    // Peer 2 fills the buffer while peer 1 holds the blocks before it
+    downloader.block_received(&hash(1), vec![1]);
+    downloader.block_received(&hash(3), vec![3]);
+    assert_eq!(downloader.buffered_bytes(), 2);
+    assert!(downloader.schedule(&[peer(1), peer(2)], now).is_empty());
+
+    // Draining the buffer lets the download move on
+    downloader.block_received(&hash(0), vec![0]);
+    assert_eq!(downloader.pop_ready(), Some((0, vec![0])));
+    assert_eq!(downloader.pop_ready(), Some((1, vec![1])));
+    assert_eq!(downloader.buffered_bytes(), 1);
+    let requests = downloader.schedule(&[peer(1), peer(2)], now);
+    assert_eq!(requested(&requests, peer(1)), vec![hash(5)]);
+    assert_eq!(requested(&requests, peer(2)), vec![hash(4), hash(6)]);
+}
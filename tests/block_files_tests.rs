@@ -0,0 +1,263 @@
+//! Tests for flat-file block storage: appending, file rotation, reading back,
+//! whole-file pruning and reading bodies left in the database
+
+use bllvm_node::config::{
+    BlockStorageBackendConfig, BlockStorageConfig, PruningConfig, PruningMode, StorageConfig,
+};
+use bllvm_node::storage::blockfiles::BlockFileStore;
+use bllvm_node::storage::blockstore::{BlockStore, BlockUndo, SpentOutput};
+use bllvm_node::storage::database::{create_database, default_backend, Database};
+use bllvm_node::storage::pruning::PruningManager;
+use bllvm_node::storage::Storage;
+use bllvm_node::{Hash, OutPoint, UTXO};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+mod common;
+use common::make_block;
+
+fn make_undo(value: i64) -> BlockUndo {
+    BlockUndo {
+        spent_outputs: vec![SpentOutput {
+            outpoint: OutPoint {
+                hash: [value as u8; 32],
+                index: 0,
+            },
+            utxo: UTXO {
+                value,
+                script_pubkey: vec![0x51],
+                height: 1,
+                is_coinbase: false,
+            },
+        }],
+    }
+}
+
+fn open_db(temp_dir: &TempDir) -> Arc<dyn Database> {
+    Arc::from(create_database(temp_dir.path(), default_backend()).unwrap())
+}
+
+/// Block store writing to block files of at most `max_file_size` bytes
+fn file_blockstore(db: &Arc<dyn Database>, temp_dir: &TempDir, max_file_size: u64) -> BlockStore {
+    let files = BlockFileStore::new(
+        Arc::clone(db),
+        temp_dir.path().join("blocks"),
+        max_file_size,
+    )
+    .unwrap();
+    BlockStore::with_block_files(Arc::clone(db), Arc::new(files)).unwrap()
+}
+
+/// Store `count` blocks with undo data as the active chain
+fn store_chain(blocks: &BlockStore, count: u64) -> Vec<Hash> {
+    let mut prev = [0u8; 32];
+    let mut hashes = Vec::new();
+    for height in 0..count {
+        let block = make_block(prev, height, Vec::new());
+        prev = blocks.get_block_hash(&block);
+        blocks
+            .store_block_with_witness(&block, &[], height)
+            .unwrap();
+        blocks.store_height(height, &prev).unwrap();
+        blocks.store_undo(&prev, &make_undo(height as i64)).unwrap();
+        hashes.push(prev);
+    }
+    hashes
+}
+
+#[test]
+fn test_blocks_and_undo_roundtrip_through_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = open_db(&temp_dir);
+    let blocks = file_blockstore(&db, &temp_dir, 1024 * 1024);
+    let hashes = store_chain(&blocks, 3);
+
+    for (height, hash) in hashes.iter().enumerate() {
+        let block = blocks.get_block(hash).unwrap().unwrap();
+        assert_eq!(blocks.get_block_hash(&block), *hash);
+        let undo = blocks.get_undo(hash).unwrap().unwrap();
+        assert_eq!(undo.spent_outputs[0].utxo.value, height as i64);
+        assert!(blocks.has_block_body(hash).unwrap());
+        // Only positions are in the database
+        assert!(!blocks.has_database_body(hash).unwrap());
+    }
+    assert_eq!(blocks.block_count().unwrap(), 3);
+
+    let files = blocks.block_files().unwrap();
+    assert!(files.block_file_path(0).exists());
+    assert!(files.undo_file_path(0).exists());
+    let (file, info) = files.file_infos().remove(0);
+    assert_eq!(file, 0);
+    assert_eq!(info.blocks, 3);
+    assert_eq!(
+        info.size,
+        std::fs::metadata(files.block_file_path(0)).unwrap().len()
+    );
+
+    // Removing a body drops its position; the bytes stay in the file
+    blocks.remove_block_body(&hashes[1]).unwrap();
+    assert!(!blocks.has_block_body(&hashes[1]).unwrap());
+    assert!(blocks.get_block(&hashes[1]).unwrap().is_none());
+    assert!(blocks.get_block(&hashes[2]).unwrap().is_some());
+}
+
+#[test]
+fn test_files_rotate_at_max_size() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = open_db(&temp_dir);
+    // Room for one block record per file
+    let blocks = file_blockstore(&db, &temp_dir, 64);
+    let hashes = store_chain(&blocks, 4);
+
+    let files = blocks.block_files().unwrap();
+    for (i, hash) in hashes.iter().enumerate() {
+        let position = files.block_position(hash).unwrap().unwrap();
+        assert_eq!(position.file, i as u32);
+        assert_eq!(position.offset, 0);
+        // Undo data follows its block into the matching rev file
+        assert_eq!(files.undo_position(hash).unwrap().unwrap().file, i as u32);
+    }
+    assert_eq!(files.last_file(), 3);
+
+    // The current file survives a restart
+    drop(blocks);
+    let blocks = file_blockstore(&db, &temp_dir, 64);
+    assert_eq!(blocks.block_files().unwrap().last_file(), 3);
+    for hash in &hashes {
+        assert!(blocks.get_block(hash).unwrap().is_some());
+    }
+}
+
+#[test]
+fn test_prune_deletes_whole_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = open_db(&temp_dir);
+    let blocks = Arc::new(file_blockstore(&db, &temp_dir, 64));
+    let hashes = store_chain(&blocks, 6);
+
+    let config = PruningConfig {
+        mode: PruningMode::Normal {
+            keep_from_height: 3,
+            min_recent_blocks: 0,
+        },
+        min_blocks_to_keep: 1,
+        ..Default::default()
+    };
+    let manager = PruningManager::new(config, Arc::clone(&blocks));
+    let stats = manager.prune_to_height(3, 5, false).unwrap();
+    assert_eq!(stats.blocks_pruned, 3);
+    assert!(stats.storage_freed > 0);
+
+    let files = blocks.block_files().unwrap();
+    for (height, hash) in hashes.iter().enumerate() {
+        let pruned = height < 3;
+        assert_eq!(files.block_file_path(height as u32).exists(), !pruned);
+        assert_eq!(files.undo_file_path(height as u32).exists(), !pruned);
+        assert_eq!(blocks.has_block_body(hash).unwrap(), !pruned);
+        assert_eq!(blocks.get_undo(hash).unwrap().is_some(), !pruned);
+        assert!(blocks.get_header(hash).unwrap().is_some());
+    }
+    assert_eq!(
+        files
+            .file_infos()
+            .into_iter()
+            .map(|(file, _)| file)
+            .collect::<Vec<_>>(),
+        vec![3, 4, 5]
+    );
+}
+
+#[test]
+fn test_prune_keeps_files_with_needed_blocks() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = open_db(&temp_dir);
+    // Every block lands in the same file
+    let blocks = file_blockstore(&db, &temp_dir, 1024 * 1024);
+    let hashes = store_chain(&blocks, 4);
+
+    let pruned = blocks.prune_block_files(2).unwrap();
+    assert!(pruned.files.is_empty());
+    for hash in &hashes {
+        assert!(blocks.has_block_body(hash).unwrap());
+    }
+}
+
+#[test]
+fn test_removing_a_file_keeps_records_stored_again_elsewhere() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = open_db(&temp_dir);
+    let blocks = file_blockstore(&db, &temp_dir, 64);
+    let hashes = store_chain(&blocks, 3);
+
+    // Block 0 is stored again after its body was dropped, in a new file
+    let block = blocks.get_block(&hashes[0]).unwrap().unwrap();
+    blocks.remove_block_body(&hashes[0]).unwrap();
+    blocks.store_block(&block).unwrap();
+    let files = blocks.block_files().unwrap();
+    assert_eq!(files.block_position(&hashes[0]).unwrap().unwrap().file, 3);
+
+    let pruned = files.remove_files(&BTreeSet::from([0, 1])).unwrap();
+    assert_eq!(pruned.files, vec![0, 1]);
+    assert_eq!(pruned.blocks, 1);
+    assert!(blocks.get_block(&hashes[0]).unwrap().is_some());
+    assert!(!blocks.has_block_body(&hashes[1]).unwrap());
+    assert!(blocks.get_undo(&hashes[0]).unwrap().is_none());
+    assert!(blocks.get_undo(&hashes[2]).unwrap().is_some());
+}
+
+#[test]
+fn test_database_bodies_readable_after_switching_to_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let db = open_db(&temp_dir);
+    let old = {
+        let blocks = BlockStore::new(Arc::clone(&db)).unwrap();
+        store_chain(&blocks, 2)
+    };
+
+    let blocks = file_blockstore(&db, &temp_dir, 1024 * 1024);
+    for hash in &old {
+        assert!(blocks.get_block(hash).unwrap().is_some());
+        assert!(blocks.get_undo(hash).unwrap().is_some());
+        assert!(blocks.has_database_body(hash).unwrap());
+    }
+
+    let block = make_block(old[1], 2, Vec::new());
+    blocks.store_block(&block).unwrap();
+    let hash = blocks.get_block_hash(&block);
+    assert!(blocks.has_block_body(&hash).unwrap());
+    assert!(!blocks.has_database_body(&hash).unwrap());
+    assert_eq!(blocks.block_count().unwrap(), 3);
+}
+
+#[test]
+fn test_storage_config_selects_backend() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = StorageConfig {
+        block_storage: BlockStorageConfig {
+            backend: BlockStorageBackendConfig::FlatFiles,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let storage = Storage::with_config(temp_dir.path(), default_backend(), &config).unwrap();
+    let block = make_block([0u8; 32], 0, Vec::new());
+    storage.blocks().store_block(&block).unwrap();
+    let hash = storage.blocks().get_block_hash(&block);
+    assert!(storage.blocks().block_files().is_some());
+    assert!(!storage.blocks().has_database_body(&hash).unwrap());
+    assert!(temp_dir.path().join("blocks").join("blk00000.dat").exists());
+    drop(storage);
+
+    // The database backend stays the default
+    let temp_dir = TempDir::new().unwrap();
+    let storage = Storage::with_config(
+        temp_dir.path(),
+        default_backend(),
+        &StorageConfig::default(),
+    )
+    .unwrap();
+    storage.blocks().store_block(&block).unwrap();
+    assert!(storage.blocks().block_files().is_none());
+    assert!(storage.blocks().has_database_body(&hash).unwrap());
+}
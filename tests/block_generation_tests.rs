@@ -0,0 +1,252 @@
+//! Tests for regtest block generation and address decoding
+
+use bllvm_node::node::block_processor::commit_connected_block;
+use bllvm_node::node::mempool::MempoolManager;
+use bllvm_node::node::reorg::build_block_undo;
+use bllvm_node::node::sync::BlockProcessOutcome;
+use bllvm_node::node::BlockSubmission;
+use bllvm_node::rpc::address::{
+    address_to_script_pubkey, descriptor_checksum, descriptor_to_script_pubkey,
+};
+use bllvm_node::rpc::mining::MiningRpc;
+use bllvm_node::storage::chainstate::ChainParams;
+use bllvm_node::storage::Storage;
+use bllvm_node::{Block, BlockHeader, OutPoint, Transaction, TransactionInput, TransactionOutput};
+use bllvm_protocol::serialization::{serialize_block_header, serialize_transaction};
+use serde_json::json;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+const P2WPKH_SCRIPT: &str = "0014751e76e8199196d454941c45d1b3a323f1433bd6";
+const REGTEST_P2WPKH: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+/// Regtest storage with a genesis block
+fn regtest_storage() -> (TempDir, Arc<Storage>) {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = Arc::new(Storage::new(temp_dir.path()).unwrap());
+    storage
+        .chain()
+        .store_chain_params(&ChainParams::for_network("regtest"))
+        .unwrap();
+
+    let genesis = Block {
+        header: BlockHeader {
+            version: 1,
+            prev_block_hash: [0u8; 32],
+            merkle_root: [0u8; 32],
+            timestamp: 1_296_688_602,
+            bits: 0x207fffff,
+            nonce: 2,
+        },
+        transactions: vec![Transaction {
+            version: 1,
+            inputs: bllvm_protocol::tx_inputs![TransactionInput {
+                prevout: OutPoint {
+                    hash: [0u8; 32],
+                    index: 0xffffffff,
+                },
+                script_sig: vec![0x00, 0x00],
+                sequence: 0xffffffff,
+            }],
+            outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+                value: 50_0000_0000,
+                script_pubkey: vec![0x51],
+            }],
+            lock_time: 0,
+        }]
+        .into_boxed_slice(),
+    };
+    let mut cache = storage.utxo_cache();
+    let undo = build_block_undo(&genesis, &cache.fetch_view(&genesis).unwrap());
+    commit_connected_block(
+        &storage.blocks(),
+        Some(&storage),
+        &genesis,
+        &[],
+        0,
+        &undo,
+        &mut cache,
+    )
+    .unwrap();
+    (temp_dir, storage)
+}
+
+fn block_by_hash(storage: &Storage, hash_hex: &str) -> Block {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&hex::decode(hash_hex).unwrap());
+    storage.blocks().get_block(&hash).unwrap().unwrap()
+}
+
+#[test]
+fn test_address_decoding() {
+    let script = |address: &str| hex::encode(address_to_script_pubkey(address, "regtest").unwrap());
+
+    assert_eq!(script(REGTEST_P2WPKH), P2WPKH_SCRIPT);
+    // Bech32 strings may be upper case
+    assert_eq!(script(&REGTEST_P2WPKH.to_uppercase()), P2WPKH_SCRIPT);
+    // Taproot uses bech32m
+    assert_eq!(
+        script("bcrt1pqqqsyqcyq5rqwzqfpg9scrgwpugpzysnzs23v9ccrydpk8qarc0sj9hjuh"),
+        "5120000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+    );
+    assert!(address_to_script_pubkey(
+        "bcrt1pqqqsyqcyq5rqwzqfpg9scrgwpugpzysnzs23v9ccrydpk8qarc0s8e87e4",
+        "regtest"
+    )
+    .is_err());
+
+    // Base58check P2PKH and P2SH
+    assert_eq!(
+        script("mrS8eVKXguwufwvsVe9GtgGb7fif9UQeAu"),
+        "76a91477bff20c60e522dfaa3350c39b030a5d004e839a88ac"
+    );
+    assert_eq!(
+        hex::encode(
+            address_to_script_pubkey("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", "mainnet").unwrap()
+        ),
+        "a914b472a266d0bd89c13706a4132ccfb16f7c3b9fcb87"
+    );
+
+    // Other networks' addresses and corrupted checksums are rejected
+    assert!(address_to_script_pubkey("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", "regtest").is_err());
+    assert!(
+        address_to_script_pubkey("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", "regtest").is_err()
+    );
+    assert!(address_to_script_pubkey("mrS8eVKXguwufwvsVe9GtgGb7fif9UQeAv", "regtest").is_err());
+}
+
+#[test]
+fn test_descriptor_decoding() {
+    assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+    assert_eq!(
+        descriptor_to_script_pubkey("raw(deadbeef)#89f8spxm", "regtest").unwrap(),
+        vec![0xde, 0xad, 0xbe, 0xef]
+    );
+    assert!(descriptor_to_script_pubkey("raw(deadbeef)#89f8spxx", "regtest").is_err());
+
+    let key = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    assert_eq!(
+        hex::encode(descriptor_to_script_pubkey(&format!("wpkh({key})"), "regtest").unwrap()),
+        P2WPKH_SCRIPT
+    );
+    assert_eq!(
+        hex::encode(
+            descriptor_to_script_pubkey(&format!("addr({REGTEST_P2WPKH})"), "regtest").unwrap()
+        ),
+        P2WPKH_SCRIPT
+    );
+    assert!(descriptor_to_script_pubkey(&format!("tr({key})"), "regtest").is_err());
+}
+
+#[tokio::test]
+async fn test_generate_to_address_extends_chain() {
+    let (_temp_dir, storage) = regtest_storage();
+    let mining =
+        MiningRpc::with_dependencies(Arc::clone(&storage), Arc::new(MempoolManager::new()));
+
+    let hashes = mining
+        .generate_to_address(&json!([3, REGTEST_P2WPKH]))
+        .await
+        .unwrap();
+    let hashes: Vec<String> = serde_json::from_value(hashes).unwrap();
+    assert_eq!(hashes.len(), 3);
+    assert_eq!(storage.chain().get_height().unwrap(), Some(3));
+    assert_eq!(
+        hex::encode(storage.chain().get_tip_hash().unwrap().unwrap()),
+        hashes[2]
+    );
+
+    for (height, hash) in (1u64..).zip(&hashes) {
+        let block = block_by_hash(&storage, hash);
+        let coinbase = &block.transactions[0];
+        assert_eq!(
+            hex::encode(&coinbase.outputs[0].script_pubkey),
+            P2WPKH_SCRIPT
+        );
+        // BIP34 height push
+        assert_eq!(coinbase.inputs[0].script_sig[0], 0x50 + height as u8);
+    }
+}
+
+#[tokio::test]
+async fn test_generate_block_and_descriptor() {
+    let (_temp_dir, storage) = regtest_storage();
+    let mining =
+        MiningRpc::with_dependencies(Arc::clone(&storage), Arc::new(MempoolManager::new()));
+
+    let hashes = mining
+        .generate_to_descriptor(&json!([1, "raw(51)"]))
+        .await
+        .unwrap();
+    assert_eq!(hashes.as_array().unwrap().len(), 1);
+
+    let hashes = mining
+        .generate_block(&json!([REGTEST_P2WPKH, []]))
+        .await
+        .unwrap();
+    let hash = hashes[0].as_str().unwrap();
+    let block = block_by_hash(&storage, hash);
+    assert_eq!(block.transactions.len(), 1);
+    assert_eq!(storage.chain().get_height().unwrap(), Some(2));
+
+    // Mined blocks are already connected
+    let mut block_bytes = serialize_block_header(&block.header).to_vec();
+    block_bytes.push(block.transactions.len() as u8);
+    for tx in block.transactions.iter() {
+        block_bytes.extend_from_slice(&serialize_transaction(tx));
+    }
+    let block_hex = hex::encode(block_bytes);
+    assert_eq!(
+        mining.submit_block(&json!([block_hex])).await.unwrap(),
+        json!("duplicate")
+    );
+}
+
+#[tokio::test]
+async fn test_submitted_blocks_go_to_the_node() {
+    let (_temp_dir, storage) = regtest_storage();
+    let (block_submitter, mut submissions) = tokio::sync::mpsc::unbounded_channel();
+    let mining =
+        MiningRpc::with_dependencies(Arc::clone(&storage), Arc::new(MempoolManager::new()))
+            .with_block_submitter(block_submitter);
+
+    // Stand in for the node, which decides what happens to the block
+    let node_storage = Arc::clone(&storage);
+    let node = tokio::spawn(async move {
+        let submission: BlockSubmission = submissions.recv().await.unwrap();
+        let hash = node_storage.blocks().get_block_hash(&submission.block);
+        submission
+            .reply
+            .send(Ok(BlockProcessOutcome::SideBranch { hash, height: 0 }))
+            .unwrap();
+        hash
+    });
+
+    let genesis_hash = storage.blocks().get_hash_by_height(0).unwrap().unwrap();
+    let genesis = storage.blocks().get_block(&genesis_hash).unwrap().unwrap();
+    let mut block_bytes = serialize_block_header(&genesis.header).to_vec();
+    block_bytes.push(genesis.transactions.len() as u8);
+    for tx in genesis.transactions.iter() {
+        block_bytes.extend_from_slice(&serialize_transaction(tx));
+    }
+    assert_eq!(
+        mining
+            .submit_block(&json!([hex::encode(block_bytes)]))
+            .await
+            .unwrap(),
+        json!("inconclusive")
+    );
+    assert_eq!(node.await.unwrap(), genesis_hash);
+}
+
+#[tokio::test]
+async fn test_generate_requires_regtest() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = Arc::new(Storage::new(temp_dir.path()).unwrap());
+    let mining = MiningRpc::with_dependencies(storage, Arc::new(MempoolManager::new()));
+
+    assert!(mining
+        .generate_to_address(&json!([1, REGTEST_P2WPKH]))
+        .await
+        .is_err());
+}
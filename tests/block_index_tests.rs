@@ -0,0 +1,341 @@
+//! Tests for the block index: headers-first acceptance, chain tips, failure
+//! flags, best chain selection and invalidate/reconsider on regtest chains
+
+use bllvm_node::node::reorg::ReorgEngine;
+use bllvm_node::node::sync::SyncCoordinator;
+use bllvm_node::storage::blockindex::BlockStatus;
+use bllvm_node::storage::blockstore::BlockStore;
+use bllvm_node::storage::chainwork::ChainWork;
+use bllvm_node::storage::database::{create_database, default_backend};
+use bllvm_node::storage::Storage;
+use bllvm_node::{Hash, UtxoSet};
+use bllvm_protocol::pow::check_proof_of_work;
+use bllvm_protocol::{BitcoinProtocolEngine, ProtocolVersion};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+mod common;
+use common::{
+    create_test_storage, extend_main_chain, make_header, mine_regtest_block, REGTEST_BITS,
+};
+
+/// Connect `count` regtest blocks from genesis, returning their hashes
+fn main_chain_hashes(
+    engine: &ReorgEngine,
+    blockstore: &BlockStore,
+    count: u64,
+    utxo_set: &mut UtxoSet,
+) -> Vec<Hash> {
+    extend_main_chain(engine, blockstore, [0u8; 32], 0, count, 0, utxo_set)
+        .iter()
+        .map(|block| blockstore.get_block_hash(block))
+        .collect()
+}
+
+#[test]
+fn test_accept_header_requires_parent() {
+    let (_temp_dir, storage) = create_test_storage();
+    let index = storage.block_index();
+    let blocks = storage.blocks();
+
+    let orphan = make_header([5u8; 32], 1, REGTEST_BITS);
+    assert!(index
+        .accept_header(&blocks.get_header_hash(&orphan), &orphan)
+        .is_err());
+    assert!(index.is_empty().unwrap());
+
+    let genesis = make_header([0u8; 32], 0, REGTEST_BITS);
+    let genesis_hash = blocks.get_header_hash(&genesis);
+    let entry = index.accept_header(&genesis_hash, &genesis).unwrap();
+    assert_eq!(entry.height, 0);
+    assert_eq!(entry.chainwork, ChainWork::from_u64(2));
+    assert_eq!(entry.status, BlockStatus::HEADER_VALID);
+
+    let child = make_header(genesis_hash, 1, REGTEST_BITS);
+    let entry = index
+        .accept_header(&blocks.get_header_hash(&child), &child)
+        .unwrap();
+    assert_eq!(entry.height, 1);
+    assert_eq!(entry.chainwork, ChainWork::from_u64(4));
+    assert!(!entry.has_data());
+    assert_eq!(index.len().unwrap(), 2);
+}
+
+#[test]
+fn test_chain_tips_ordered_by_cumulative_work() {
+    let (_temp_dir, storage) = create_test_storage();
+    let index = storage.block_index();
+    let blocks = storage.blocks();
+
+    let genesis = make_header([0u8; 32], 0, REGTEST_BITS);
+    let root = blocks.get_header_hash(&genesis);
+    index.accept_header(&root, &genesis).unwrap();
+
+    // A long branch of easy blocks
+    let mut prev = root;
+    for nonce in 1..=5 {
+        let header = make_header(prev, nonce, REGTEST_BITS);
+        prev = blocks.get_header_hash(&header);
+        index.accept_header(&prev, &header).unwrap();
+    }
+    let long_tip = prev;
+
+    // A single much harder block
+    let header = make_header(root, 100, 0x1d00ffff);
+    let heavy_tip = blocks.get_header_hash(&header);
+    index.accept_header(&heavy_tip, &header).unwrap();
+
+    let tips = index.chain_tips().unwrap();
+    assert_eq!(tips.len(), 2);
+    assert_eq!(tips[0].hash, heavy_tip);
+    assert_eq!(tips[0].height, 1);
+    assert_eq!(tips[1].hash, long_tip);
+    assert_eq!(tips[1].height, 5);
+    assert_eq!(index.best_header().unwrap().unwrap().hash, heavy_tip);
+}
+
+#[test]
+fn test_mark_failed_propagates_and_reset_clears() {
+    let (_temp_dir, storage) = create_test_storage();
+    let index = storage.block_index();
+    let blocks = storage.blocks();
+
+    // genesis -> a -> b, and a -> c
+    let genesis = make_header([0u8; 32], 0, REGTEST_BITS);
+    let genesis_hash = blocks.get_header_hash(&genesis);
+    index.accept_header(&genesis_hash, &genesis).unwrap();
+    let a = make_header(genesis_hash, 1, REGTEST_BITS);
+    let a_hash = blocks.get_header_hash(&a);
+    index.accept_header(&a_hash, &a).unwrap();
+    let b = make_header(a_hash, 2, REGTEST_BITS);
+    let b_hash = blocks.get_header_hash(&b);
+    index.accept_header(&b_hash, &b).unwrap();
+    let c = make_header(a_hash, 3, REGTEST_BITS);
+    let c_hash = blocks.get_header_hash(&c);
+    index.accept_header(&c_hash, &c).unwrap();
+
+    assert_eq!(index.mark_failed(&a_hash).unwrap(), 3);
+    let status = |hash: &Hash| index.get(hash).unwrap().unwrap().status;
+    assert!(!status(&genesis_hash).intersects(BlockStatus::FAILED));
+    assert!(status(&a_hash).contains(BlockStatus::FAILED_VALID));
+    assert!(status(&b_hash).contains(BlockStatus::FAILED_CHILD));
+    assert!(status(&c_hash).contains(BlockStatus::FAILED_CHILD));
+    assert_eq!(index.best_header().unwrap().unwrap().hash, genesis_hash);
+
+    // New headers on a failed branch are failed children
+    let d = make_header(b_hash, 4, REGTEST_BITS);
+    let d_hash = blocks.get_header_hash(&d);
+    assert!(index.accept_header(&d_hash, &d).unwrap().is_failed());
+
+    assert_eq!(index.reset_failure(&a_hash).unwrap(), 4);
+    for hash in [a_hash, b_hash, c_hash, d_hash] {
+        assert!(!status(&hash).intersects(BlockStatus::FAILED));
+    }
+    assert_eq!(index.best_header().unwrap().unwrap().hash, d_hash);
+}
+
+#[test]
+fn test_best_chain_candidate_requires_data() {
+    let (_temp_dir, storage) = create_test_storage();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let blocks = storage.blocks();
+    let index = storage.block_index();
+    let engine = ReorgEngine::new(&blocks, &protocol, Some(&storage));
+    let mut utxo_set = UtxoSet::new();
+
+    let main = main_chain_hashes(&engine, &blocks, 2, &mut utxo_set);
+
+    // Longer branch from genesis known only by its headers
+    let mut prev = main[0];
+    for height in 1..=3 {
+        let header = mine_regtest_block(prev, height, 1).header;
+        prev = blocks.get_header_hash(&header);
+        index.accept_header(&prev, &header).unwrap();
+    }
+    let headers_tip = prev;
+
+    assert_eq!(index.best_header().unwrap().unwrap().hash, headers_tip);
+    let candidate = index.best_chain_candidate(&blocks).unwrap().unwrap();
+    assert_eq!(candidate.hash, main[1]);
+    assert!(engine
+        .activate_best_chain(1, &mut utxo_set)
+        .unwrap()
+        .is_none());
+
+    let tips = index.chain_tips().unwrap();
+    assert_eq!(tips[0].hash, headers_tip);
+    assert!(!tips[0].has_data());
+    assert_eq!(tips[1].hash, main[1]);
+    assert!(tips[1]
+        .status
+        .contains(BlockStatus::HAVE_DATA | BlockStatus::FULLY_VALID));
+}
+
+#[test]
+fn test_invalidate_and_reconsider_block() {
+    let (_temp_dir, storage) = create_test_storage();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let blocks = storage.blocks();
+    let index = storage.block_index();
+    let engine = ReorgEngine::new(&blocks, &protocol, Some(&storage));
+    let mut utxo_set = UtxoSet::new();
+
+    let main = main_chain_hashes(&engine, &blocks, 3, &mut utxo_set);
+
+    // One-block side branch from genesis with its data downloaded
+    let side = mine_regtest_block(main[0], 1, 1);
+    let side_hash = blocks.get_block_hash(&side);
+    blocks.store_block(&side).unwrap();
+    index.accept_header(&side_hash, &side.header).unwrap();
+    index
+        .add_status(&side_hash, BlockStatus::HAVE_DATA)
+        .unwrap();
+
+    // Invalidating main[1] falls back to the side branch
+    assert_eq!(
+        engine.invalidate_block(&main[1], 2, &mut utxo_set).unwrap(),
+        1
+    );
+    assert_eq!(blocks.get_hash_by_height(1).unwrap(), Some(side_hash));
+    assert!(blocks.get_hash_by_height(2).unwrap().is_none());
+    assert!(index.get(&main[2]).unwrap().unwrap().is_failed());
+    assert_eq!(storage.chain().get_tip_hash().unwrap(), Some(side_hash));
+
+    // Reconsidering it restores the heavier original chain
+    assert_eq!(
+        engine.reconsider_block(&main[1], 1, &mut utxo_set).unwrap(),
+        2
+    );
+    for (height, hash) in main.iter().enumerate() {
+        assert_eq!(
+            blocks.get_hash_by_height(height as u64).unwrap(),
+            Some(*hash)
+        );
+        assert!(!index.get(hash).unwrap().unwrap().is_failed());
+    }
+
+    assert!(engine.invalidate_block(&main[0], 2, &mut utxo_set).is_err());
+}
+
+#[test]
+fn test_process_headers() {
+    let (_temp_dir, storage) = create_test_storage();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let blocks = storage.blocks();
+    let engine = ReorgEngine::new(&blocks, &protocol, Some(&storage));
+    let mut utxo_set = UtxoSet::new();
+    let main = main_chain_hashes(&engine, &blocks, 1, &mut utxo_set);
+
+    let mut headers = Vec::new();
+    let mut prev = main[0];
+    for height in 1..=3 {
+        let header = mine_regtest_block(prev, height, 0).header;
+        prev = blocks.get_header_hash(&header);
+        headers.push(header);
+    }
+
+    let mut sync = SyncCoordinator::new();
+    let outcome = sync.process_headers(&storage, &headers).unwrap();
+    assert_eq!(outcome.accepted, 3);
+    assert_eq!(outcome.already_known, 0);
+    assert_eq!(outcome.best_header, Some((prev, 3)));
+
+    let outcome = sync.process_headers(&storage, &headers).unwrap();
+    assert_eq!(outcome.accepted, 0);
+    assert_eq!(outcome.already_known, 3);
+
+    // Batch that skips a header
+    let gap = vec![headers[0].clone(), headers[2].clone()];
+    assert!(sync.process_headers(&storage, &gap).is_err());
+
+    // Header that misses its target
+    let mut bad = mine_regtest_block(prev, 4, 0).header;
+    while check_proof_of_work(&bad).unwrap() {
+        bad.nonce += 1;
+    }
+    assert!(sync.process_headers(&storage, &[bad.clone()]).is_err());
+    assert!(!storage
+        .block_index()
+        .contains(&blocks.get_header_hash(&bad))
+        .unwrap());
+}
+
+#[test]
+fn test_best_header_and_tips_survive_reopen() {
+    let temp_dir = TempDir::new().unwrap();
+    let (genesis_hash, a_hash, b_hash) = {
+        let storage = Storage::new(temp_dir.path()).unwrap();
+        let index = storage.block_index();
+        let blocks = storage.blocks();
+
+        // genesis -> a, and genesis -> b with a failed
+        let genesis = make_header([0u8; 32], 0, REGTEST_BITS);
+        let genesis_hash = blocks.get_header_hash(&genesis);
+        index.accept_header(&genesis_hash, &genesis).unwrap();
+        let a = make_header(genesis_hash, 1, 0x1d00ffff);
+        let a_hash = blocks.get_header_hash(&a);
+        index.accept_header(&a_hash, &a).unwrap();
+        let b = make_header(genesis_hash, 2, REGTEST_BITS);
+        let b_hash = blocks.get_header_hash(&b);
+        index.accept_header(&b_hash, &b).unwrap();
+        assert_eq!(index.best_header().unwrap().unwrap().hash, a_hash);
+        index.mark_failed(&a_hash).unwrap();
+        assert_eq!(index.best_header().unwrap().unwrap().hash, b_hash);
+        (genesis_hash, a_hash, b_hash)
+    };
+
+    let storage = Storage::new(temp_dir.path()).unwrap();
+    let index = storage.block_index();
+    assert_eq!(index.best_header().unwrap().unwrap().hash, b_hash);
+    let tips: Vec<Hash> = index
+        .chain_tips()
+        .unwrap()
+        .iter()
+        .map(|tip| tip.hash)
+        .collect();
+    assert_eq!(tips, vec![a_hash, b_hash]);
+    let descendants = index.descendants(&genesis_hash).unwrap();
+    assert_eq!(descendants.len(), 2);
+}
+
+#[test]
+fn test_index_rebuilt_for_legacy_database() {
+    let temp_dir = TempDir::new().unwrap();
+    let main = {
+        let storage = Arc::new(Storage::new(temp_dir.path()).unwrap());
+        let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+        let blocks = storage.blocks();
+        let engine = ReorgEngine::new(&blocks, &protocol, Some(&storage));
+        let mut utxo_set = UtxoSet::new();
+        let main = main_chain_hashes(&engine, &blocks, 3, &mut utxo_set);
+        storage.chain().mark_invalid(&main[2]).unwrap();
+        main
+    };
+
+    // Drop the index as a database from before it existed would lack it
+    {
+        let db = create_database(temp_dir.path(), default_backend()).unwrap();
+        db.open_tree("block_index").unwrap().clear().unwrap();
+    }
+
+    let storage = Storage::new(temp_dir.path()).unwrap();
+    let index = storage.block_index();
+    assert_eq!(index.len().unwrap(), 3);
+    for (height, hash) in main.iter().enumerate() {
+        let entry = index.get(hash).unwrap().unwrap();
+        assert_eq!(entry.height, height as u64);
+        assert_eq!(
+            Some(entry.chainwork),
+            storage.chain().get_chainwork(hash).unwrap()
+        );
+        assert!(entry
+            .status
+            .contains(BlockStatus::HAVE_DATA | BlockStatus::FULLY_VALID));
+    }
+    assert!(index
+        .get(&main[2])
+        .unwrap()
+        .unwrap()
+        .status
+        .contains(BlockStatus::FAILED_VALID));
+}
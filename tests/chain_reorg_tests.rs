@@ -0,0 +1,415 @@
+//! Tests for block undo data and chain reorganization on regtest forks
+
+use bllvm_node::node::reorg::{build_block_undo, disconnect_block, ReorgEngine, ReorgOutcome};
+use bllvm_node::storage::blockindex::BlockStatus;
+use bllvm_node::storage::blockstore::{BlockUndo, SpentOutput};
+use bllvm_node::storage::utxocache::UtxoView;
+use bllvm_node::{Block, BlockHeader, Hash, OutPoint, UtxoSet, UTXO};
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{BitcoinProtocolEngine, ProtocolVersion};
+
+mod common;
+use common::{
+    coinbase, create_test_storage, extend_main_chain, mine_regtest_block, mine_regtest_block_with,
+    spend, REGTEST_BITS,
+};
+
+#[test]
+fn test_block_undo_roundtrip() {
+    let (_temp_dir, storage) = create_test_storage();
+    let hash = [7u8; 32];
+    let undo = BlockUndo {
+        spent_outputs: vec![SpentOutput {
+            outpoint: OutPoint {
+                hash: [1u8; 32],
+                index: 3,
+            },
+            utxo: UTXO {
+                value: 1000,
+                script_pubkey: vec![0x51],
+                height: 5,
+                is_coinbase: false,
+            },
+        }],
+    };
+
+    storage.blocks().store_undo(&hash, &undo).unwrap();
+    let loaded = storage.blocks().get_undo(&hash).unwrap().unwrap();
+    assert_eq!(loaded.spent_outputs.len(), 1);
+    assert_eq!(
+        loaded.spent_outputs[0].outpoint,
+        undo.spent_outputs[0].outpoint
+    );
+    assert_eq!(loaded.spent_outputs[0].utxo.value, 1000);
+    assert_eq!(loaded.spent_outputs[0].utxo.height, 5);
+
+    storage.blocks().remove_undo(&hash).unwrap();
+    assert!(storage.blocks().get_undo(&hash).unwrap().is_none());
+}
+
+#[test]
+fn test_disconnect_block_restores_utxo_set() {
+    let funding = OutPoint {
+        hash: [9u8; 32],
+        index: 0,
+    };
+    let mut utxo_set = UtxoSet::new();
+    utxo_set.insert(
+        funding.clone(),
+        UTXO {
+            value: 10_000,
+            script_pubkey: vec![0x51],
+            height: 1,
+            is_coinbase: false,
+        },
+    );
+
+    let cb = coinbase(2, 0);
+    let tx = spend(funding.clone(), 9_000);
+    let block = Block {
+        header: BlockHeader {
+            version: 4,
+            prev_block_hash: [0u8; 32],
+            merkle_root: [0u8; 32],
+            timestamp: 0,
+            bits: REGTEST_BITS,
+            nonce: 0,
+        },
+        transactions: vec![cb.clone(), tx.clone()].into_boxed_slice(),
+    };
+
+    let undo = build_block_undo(&block, &utxo_set);
+    assert_eq!(undo.spent_outputs.len(), 1);
+    assert_eq!(undo.spent_outputs[0].outpoint, funding);
+
+    // Apply the block by hand
+    utxo_set.remove(&funding);
+    for created in [&cb, &tx] {
+        utxo_set.insert(
+            OutPoint {
+                hash: calculate_tx_id(created),
+                index: 0,
+            },
+            UTXO {
+                value: created.outputs[0].value,
+                script_pubkey: created.outputs[0].script_pubkey.clone(),
+                height: 2,
+                is_coinbase: std::ptr::eq(created, &cb),
+            },
+        );
+    }
+
+    assert!(disconnect_block(&block, &undo, &mut utxo_set));
+    assert_eq!(utxo_set.len(), 1);
+    assert_eq!(utxo_set.get(&funding).unwrap().value, 10_000);
+}
+
+#[test]
+fn test_build_block_undo_skips_in_block_spends() {
+    let cb = coinbase(1, 0);
+    let child = spend(
+        OutPoint {
+            hash: calculate_tx_id(&cb),
+            index: 0,
+        },
+        1_000,
+    );
+    let block = Block {
+        header: BlockHeader {
+            version: 4,
+            prev_block_hash: [0u8; 32],
+            merkle_root: [0u8; 32],
+            timestamp: 0,
+            bits: REGTEST_BITS,
+            nonce: 0,
+        },
+        transactions: vec![cb, child].into_boxed_slice(),
+    };
+
+    let undo = build_block_undo(&block, &UtxoSet::new());
+    assert!(undo.spent_outputs.is_empty());
+}
+
+#[test]
+fn test_remove_height_leaves_block_body() {
+    let (_temp_dir, storage) = create_test_storage();
+    let blocks = storage.blocks();
+    let block = mine_regtest_block([0u8; 32], 0, 0);
+    let hash = blocks.get_block_hash(&block);
+
+    blocks.store_block(&block).unwrap();
+    blocks.store_height(0, &hash).unwrap();
+    assert!(blocks.is_in_main_chain(&hash).unwrap());
+
+    blocks.remove_height(0).unwrap();
+    assert!(!blocks.is_in_main_chain(&hash).unwrap());
+    assert!(blocks.get_hash_by_height(0).unwrap().is_none());
+    assert!(blocks.has_block_body(&hash).unwrap());
+}
+
+#[test]
+fn test_regtest_fork_point_and_work() {
+    let (_temp_dir, storage) = create_test_storage();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let blocks = storage.blocks();
+    let engine = ReorgEngine::new(&blocks, &protocol, Some(&storage));
+    let mut utxo_set = UtxoSet::new();
+
+    let main = extend_main_chain(&engine, &blocks, [0u8; 32], 0, 3, 0, &mut utxo_set);
+    let fork_base = blocks.get_block_hash(&main[0]);
+
+    // Competing branch of two blocks forking after height 0
+    let side1 = mine_regtest_block(fork_base, 1, 1);
+    let side1_hash = blocks.get_block_hash(&side1);
+    let side2 = mine_regtest_block(side1_hash, 2, 1);
+    let side2_hash = blocks.get_block_hash(&side2);
+    blocks.store_block(&side1).unwrap();
+    blocks.store_block(&side2).unwrap();
+
+    let (fork_height, branch) = engine.find_fork_point(&side2_hash).unwrap();
+    assert_eq!(fork_height, 0);
+    assert_eq!(branch, vec![side1_hash, side2_hash]);
+
+    // Equal length branches at equal difficulty have equal work
+    assert_eq!(
+        engine.branch_work(&branch).unwrap(),
+        engine.main_chain_work_since(0, 2).unwrap()
+    );
+
+    // Equal work does not trigger a reorganization
+    assert_eq!(
+        engine.reorganize(&side2_hash, 2, &mut utxo_set).unwrap(),
+        ReorgOutcome::Unchanged
+    );
+    assert_eq!(
+        blocks.get_hash_by_height(2).unwrap(),
+        Some(blocks.get_block_hash(&main[2]))
+    );
+}
+
+#[test]
+fn test_regtest_reorg_to_heavier_branch() {
+    let (_temp_dir, storage) = create_test_storage();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let blocks = storage.blocks();
+    let engine = ReorgEngine::new(&blocks, &protocol, Some(&storage));
+    let mut utxo_set = UtxoSet::new();
+
+    let main = extend_main_chain(&engine, &blocks, [0u8; 32], 0, 3, 0, &mut utxo_set);
+    let old_tip = blocks.get_block_hash(&main[2]);
+    let fork_base = blocks.get_block_hash(&main[0]);
+
+    // Snapshot of the UTXO set at the fork point
+    let mut fork_utxos = utxo_set.clone();
+    for block in main[1..].iter().rev() {
+        let hash = blocks.get_block_hash(block);
+        let undo = blocks.get_undo(&hash).unwrap().unwrap();
+        disconnect_block(block, &undo, &mut fork_utxos);
+    }
+
+    // Three-block branch from height 1 outweighs the two main-chain blocks above the fork
+    let mut prev = fork_base;
+    let mut side = Vec::new();
+    for height in 1..=3 {
+        let block = mine_regtest_block(prev, height, 2);
+        prev = blocks.get_block_hash(&block);
+        blocks.store_block(&block).unwrap();
+        side.push(block);
+    }
+    let new_tip = prev;
+
+    let ReorgOutcome::Reorganized(result) = engine.reorganize(&new_tip, 2, &mut utxo_set).unwrap()
+    else {
+        panic!("heavier branch should become active");
+    };
+
+    assert_eq!(result.old_tip, old_tip);
+    assert_eq!(result.new_tip, new_tip);
+    assert_eq!(result.fork_height, 0);
+    assert_eq!(result.new_height, 3);
+    assert_eq!(result.disconnected.len(), 2);
+    assert_eq!(result.disconnected[0], (old_tip, 2));
+    assert_eq!(result.connected.len(), 3);
+
+    for (i, block) in side.iter().enumerate() {
+        let hash = blocks.get_block_hash(block);
+        assert_eq!(blocks.get_hash_by_height(i as u64 + 1).unwrap(), Some(hash));
+        assert!(blocks.get_undo(&hash).unwrap().is_some());
+    }
+
+    // Old branch coinbases are gone, new branch coinbases are present
+    for block in &main[1..] {
+        let outpoint = OutPoint {
+            hash: calculate_tx_id(&block.transactions[0]),
+            index: 0,
+        };
+        assert!(!utxo_set.contains_key(&outpoint));
+    }
+    for block in &side {
+        let outpoint = OutPoint {
+            hash: calculate_tx_id(&block.transactions[0]),
+            index: 0,
+        };
+        assert!(utxo_set.contains_key(&outpoint));
+        fork_utxos.remove(&outpoint);
+    }
+    assert_eq!(fork_utxos.len(), 1); // Only the shared block-0 coinbase remains
+
+    // The old tip stays in the block index as a fully validated stale fork
+    let tips = storage.block_index().chain_tips().unwrap();
+    assert_eq!(tips[0].hash, new_tip);
+    assert!(tips.iter().any(|tip| tip.hash == old_tip
+        && tip.height == 2
+        && !tip.is_failed()
+        && tip
+            .status
+            .contains(BlockStatus::HAVE_DATA | BlockStatus::FULLY_VALID)));
+}
+
+#[test]
+fn test_reorg_onto_invalid_branch_is_rejected() {
+    let (_temp_dir, storage) = create_test_storage();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let blocks = storage.blocks();
+    let engine = ReorgEngine::new(&blocks, &protocol, Some(&storage));
+    let mut utxo_set = UtxoSet::new();
+
+    let main = extend_main_chain(&engine, &blocks, [0u8; 32], 0, 3, 0, &mut utxo_set);
+    let main_utxos = utxo_set.clone();
+
+    // The second block of a heavier branch spends a coin that does not exist
+    let side1 = mine_regtest_block(blocks.get_block_hash(&main[0]), 1, 2);
+    let missing = OutPoint {
+        hash: [7u8; 32],
+        index: 0,
+    };
+    let bad = mine_regtest_block_with(
+        blocks.get_block_hash(&side1),
+        2,
+        2,
+        vec![spend(missing, 1_0000_0000)],
+    );
+    let side3 = mine_regtest_block(blocks.get_block_hash(&bad), 3, 2);
+    for block in [&side1, &bad, &side3] {
+        blocks.store_block(block).unwrap();
+    }
+    let bad_hash = blocks.get_block_hash(&bad);
+
+    assert_eq!(
+        engine
+            .reorganize(&blocks.get_block_hash(&side3), 2, &mut utxo_set)
+            .unwrap(),
+        ReorgOutcome::Rejected {
+            hash: bad_hash,
+            height: 2
+        }
+    );
+
+    // The original chain and its coins are back
+    for (height, block) in main.iter().enumerate() {
+        assert_eq!(
+            blocks.get_hash_by_height(height as u64).unwrap(),
+            Some(blocks.get_block_hash(block))
+        );
+    }
+    assert_eq!(utxo_set.len(), main_utxos.len());
+    assert!(main_utxos
+        .keys()
+        .all(|outpoint| utxo_set.contains_key(outpoint)));
+
+    let entry = storage.block_index().get(&bad_hash).unwrap().unwrap();
+    assert!(entry.status.contains(BlockStatus::FAILED_VALID));
+}
+
+/// UTXO view that fails to apply one block
+struct FailingView {
+    coins: UtxoSet,
+    fail_on: Hash,
+}
+
+impl UtxoView for FailingView {
+    fn get_utxo(&self, outpoint: &OutPoint) -> anyhow::Result<Option<UTXO>> {
+        self.coins.get_utxo(outpoint)
+    }
+
+    fn apply_block(&mut self, block: &Block, block_hash: &Hash, height: u64) -> anyhow::Result<()> {
+        if *block_hash == self.fail_on {
+            return Err(anyhow::anyhow!("injected failure"));
+        }
+        self.coins.apply_block(block, block_hash, height)
+    }
+
+    fn revert_block(
+        &mut self,
+        block: &Block,
+        height: u64,
+        undo: &BlockUndo,
+    ) -> anyhow::Result<bool> {
+        self.coins.revert_block(block, height, undo)
+    }
+}
+
+#[test]
+fn test_reorg_error_restores_chain_without_failing_block() {
+    let (_temp_dir, storage) = create_test_storage();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let blocks = storage.blocks();
+    let engine = ReorgEngine::new(&blocks, &protocol, Some(&storage));
+    let mut utxo_set = UtxoSet::new();
+
+    let main = extend_main_chain(&engine, &blocks, [0u8; 32], 0, 3, 0, &mut utxo_set);
+    let main_utxos = utxo_set.clone();
+
+    let side1 = mine_regtest_block(blocks.get_block_hash(&main[0]), 1, 2);
+    let side2 = mine_regtest_block(blocks.get_block_hash(&side1), 2, 2);
+    let side3 = mine_regtest_block(blocks.get_block_hash(&side2), 3, 2);
+    for block in [&side1, &side2, &side3] {
+        blocks.store_block(block).unwrap();
+    }
+    let side2_hash = blocks.get_block_hash(&side2);
+
+    // Applying the valid second branch block fails for a reason unrelated to it
+    let mut view = FailingView {
+        coins: utxo_set,
+        fail_on: side2_hash,
+    };
+    assert!(engine
+        .reorganize(&blocks.get_block_hash(&side3), 2, &mut view)
+        .is_err());
+
+    // The original chain and its coins are back
+    for (height, block) in main.iter().enumerate() {
+        assert_eq!(
+            blocks.get_hash_by_height(height as u64).unwrap(),
+            Some(blocks.get_block_hash(block))
+        );
+    }
+    assert_eq!(view.coins.len(), main_utxos.len());
+    assert!(main_utxos
+        .keys()
+        .all(|outpoint| view.coins.contains_key(outpoint)));
+
+    // The block is not marked invalid, so the branch can be retried
+    let failed = storage
+        .block_index()
+        .get(&side2_hash)
+        .unwrap()
+        .is_some_and(|entry| entry.is_failed());
+    assert!(!failed);
+}
+
+#[test]
+fn test_disconnect_tip_without_undo_fails() {
+    let (_temp_dir, storage) = create_test_storage();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let blocks = storage.blocks();
+    let engine = ReorgEngine::new(&blocks, &protocol, Some(&storage));
+
+    let block = mine_regtest_block([0u8; 32], 0, 0);
+    let hash = blocks.get_block_hash(&block);
+    blocks.store_block(&block).unwrap();
+    blocks.store_height(0, &hash).unwrap();
+
+    let mut utxo_set = UtxoSet::new();
+    assert!(engine.disconnect_tip(0, &mut utxo_set).is_err());
+}
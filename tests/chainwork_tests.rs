@@ -0,0 +1,189 @@
+//! Tests for 256-bit chainwork: work from compact targets, storage, and
+//! migrating databases written with 64/128-bit work
+
+use bllvm_node::storage::chainstate::ChainParams;
+use bllvm_node::storage::chainwork::ChainWork;
+use bllvm_node::storage::database::{create_database, default_backend};
+use bllvm_node::storage::Storage;
+use bllvm_node::{Block, BlockHeader, Hash};
+use serde::Serialize;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+mod common;
+use common::make_header;
+
+/// Store a header chain (indexed by height) and return its hashes
+fn store_headers(storage: &Storage, count: u64, bits: u64) -> Vec<(Hash, BlockHeader)> {
+    let mut prev = [0u8; 32];
+    let mut chain = Vec::new();
+    for height in 0..count {
+        let header = make_header(prev, height, bits);
+        let block = Block {
+            header: header.clone(),
+            transactions: Vec::new().into_boxed_slice(),
+        };
+        storage.blocks().store_block(&block).unwrap();
+        prev = storage.blocks().get_block_hash(&block);
+        storage.blocks().store_height(height, &prev).unwrap();
+        chain.push((prev, header));
+    }
+    chain
+}
+
+/// Chain info record as written before chainwork became 256-bit
+#[derive(Serialize)]
+struct LegacyChainInfo {
+    tip_hash: Hash,
+    tip_header: BlockHeader,
+    height: u64,
+    total_work: u64,
+    chain_params: ChainParams,
+}
+
+#[test]
+fn test_work_from_known_targets() {
+    // Mainnet genesis difficulty: chainwork 0x100010001 per block
+    assert_eq!(
+        ChainWork::from_bits(0x1d00ffff),
+        ChainWork::from_u64(0x1_0001_0001)
+    );
+    // Regtest minimum difficulty
+    assert_eq!(ChainWork::from_bits(0x207fffff), ChainWork::from_u64(2));
+    // A 2024-era mainnet target: 2^256 / (0x034219 * 2^160 + 1)
+    assert_eq!(
+        ChainWork::from_bits(0x17034219).to_string(),
+        format!("{}{}", "0".repeat(44), "4e9235f043634662e0cb")
+    );
+}
+
+#[test]
+fn test_invalid_targets_have_no_work() {
+    assert!(ChainWork::from_bits(0).is_zero());
+    assert!(ChainWork::from_bits(0x1d000000).is_zero());
+    // Negative
+    assert!(ChainWork::from_bits(0x04923456).is_zero());
+    // Overflows 256 bits
+    assert!(ChainWork::from_bits(0xff123456).is_zero());
+}
+
+#[test]
+fn test_chainwork_beyond_u128() {
+    // Target 256: work is 2^256 / 257
+    let work = ChainWork::from_bits(0x04000001);
+    assert_eq!(work.to_u128(), None);
+    let total = work + work;
+    assert!(total > work);
+    assert_eq!(
+        total.checked_sub(work).unwrap(),
+        work,
+        "addition must carry across all limbs"
+    );
+
+    assert_eq!(ChainWork::from_be_bytes(total.to_be_bytes()), total);
+    assert_eq!(ChainWork::MAX + work, ChainWork::MAX);
+    assert_eq!(ChainWork::MAX.checked_add(work), None);
+}
+
+#[test]
+fn test_legacy_values_decode() {
+    let legacy_work = 0x1_0001_0001u64.to_be_bytes();
+    assert_eq!(
+        ChainWork::from_be_slice(&legacy_work),
+        Some(ChainWork::from_u64(0x1_0001_0001))
+    );
+    let legacy_chainwork = (u64::MAX as u128 + 5).to_be_bytes();
+    assert_eq!(
+        ChainWork::from_be_slice(&legacy_chainwork),
+        Some(ChainWork::from_u128(u64::MAX as u128 + 5))
+    );
+    assert_eq!(ChainWork::from_be_slice(&[0u8; 33]), None);
+}
+
+#[test]
+fn test_tip_total_work_is_stored_chainwork() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = Storage::new(temp_dir.path()).unwrap();
+    let chain = store_headers(&storage, 3, 0x1d00ffff);
+    storage.chain().initialize(&chain[0].1).unwrap();
+    for (height, (hash, header)) in chain.iter().enumerate() {
+        storage
+            .chain()
+            .update_tip(hash, header, height as u64)
+            .unwrap();
+    }
+
+    let per_block = ChainWork::from_u64(0x1_0001_0001);
+    let tip = chain[2].0;
+    assert_eq!(storage.chain().get_work(&tip).unwrap(), Some(per_block));
+    assert_eq!(
+        storage.chain().get_chainwork(&tip).unwrap(),
+        Some(per_block + per_block + per_block)
+    );
+    assert_eq!(
+        storage.chain().calculate_total_work().unwrap(),
+        per_block + per_block + per_block
+    );
+}
+
+#[test]
+fn test_legacy_database_is_migrated_once() {
+    let temp_dir = TempDir::new().unwrap();
+    let chain = {
+        let storage = Storage::new(temp_dir.path()).unwrap();
+        store_headers(&storage, 4, 0x1d00ffff)
+    };
+    let (tip_hash, tip_header) = chain[3].clone();
+
+    // Rewrite the chain state as an older version would have left it
+    {
+        let db = create_database(temp_dir.path(), default_backend()).unwrap();
+        let chain_info = db.open_tree("chain_info").unwrap();
+        let work_cache = db.open_tree("work_cache").unwrap();
+        let chainwork_cache = db.open_tree("chainwork_cache").unwrap();
+        chain_info.remove(b"chainwork_version").unwrap();
+        let legacy = LegacyChainInfo {
+            tip_hash,
+            tip_header,
+            height: 3,
+            total_work: 0,
+            chain_params: ChainParams::for_network("mainnet"),
+        };
+        chain_info
+            .insert(b"current", &bincode::serialize(&legacy).unwrap())
+            .unwrap();
+        for (hash, _) in &chain {
+            work_cache.insert(hash, &12345u64.to_be_bytes()).unwrap();
+            chainwork_cache
+                .insert(hash, &6789u128.to_be_bytes())
+                .unwrap();
+        }
+        // A block whose header is gone cannot be recomputed
+        chainwork_cache
+            .insert(&[9u8; 32], &1u128.to_be_bytes())
+            .unwrap();
+    }
+
+    let storage = Arc::new(Storage::new(temp_dir.path()).unwrap());
+    let per_block = ChainWork::from_u64(0x1_0001_0001);
+    let mut expected = ChainWork::ZERO;
+    for (hash, _) in &chain {
+        expected += per_block;
+        assert_eq!(storage.chain().get_work(hash).unwrap(), Some(per_block));
+        assert_eq!(storage.chain().get_chainwork(hash).unwrap(), Some(expected));
+    }
+    assert_eq!(storage.chain().get_chainwork(&[9u8; 32]).unwrap(), None);
+
+    let info = storage.chain().load_chain_info().unwrap().unwrap();
+    assert_eq!(info.tip_hash, tip_hash);
+    assert_eq!(info.height, 3);
+    assert_eq!(info.total_work, expected);
+    assert_eq!(info.chain_params.network, "mainnet");
+
+    // Already migrated: a second run changes nothing
+    assert!(!storage
+        .chain()
+        .migrate_chainwork(&storage.blocks())
+        .unwrap());
+    assert_eq!(storage.chain().calculate_total_work().unwrap(), expected);
+}
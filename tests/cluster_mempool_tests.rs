@@ -0,0 +1,217 @@
+//! Tests for the cluster mempool: linearization, chunking, eviction and
+//! replacement fee-rate diagrams
+//!
+//! Uses proptest to check the cluster invariants over random transaction graphs.
+
+use bllvm_node::config::{EvictionStrategy, MempoolPolicyConfig};
+use bllvm_node::node::cluster::{diagram, improves_diagram, ClusterMempool, ClusterTx, FeeFrac};
+use bllvm_node::node::mempool::MempoolManager;
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{
+    Hash, OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoSet, UTXO,
+};
+use proptest::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+fn txid(i: usize) -> Hash {
+    let mut hash = [0u8; 32];
+    hash[..8].copy_from_slice(&(i as u64).to_le_bytes());
+    hash
+}
+
+/// Random acyclic graphs: transaction `i` may spend any transaction before it
+fn graph_strategy() -> impl Strategy<Value = Vec<(u64, u64, u64)>> {
+    prop::collection::vec((0..100_000u64, 1..2_000u64, any::<u64>()), 1..16)
+}
+
+fn build(graph: &[(u64, u64, u64)]) -> ClusterMempool {
+    let mut clusters = ClusterMempool::new();
+    for (i, (fee, size, mask)) in graph.iter().enumerate() {
+        let parents: HashSet<Hash> = (0..i).filter(|j| mask & (1 << j) != 0).map(txid).collect();
+        clusters.insert(txid(i), FeeFrac::new(*fee, *size), parents);
+    }
+    clusters
+}
+
+/// Every chunk comes after its parents' chunks, and each cluster's chunk
+/// rates never increase
+fn assert_consistent(clusters: &ClusterMempool) -> Result<(), TestCaseError> {
+    let chunks = clusters.chunks_by_feerate();
+    let mut seen = HashSet::new();
+    for chunk in &chunks {
+        let mut total = FeeFrac::default();
+        for id in &chunk.txids {
+            for parent in clusters.parents(id).unwrap() {
+                prop_assert!(seen.contains(parent), "parent placed after child");
+            }
+            seen.insert(*id);
+            total += clusters.feefrac(id).unwrap();
+        }
+        prop_assert_eq!(total, chunk.feefrac);
+    }
+    prop_assert_eq!(seen.len(), clusters.len());
+
+    for id in &seen {
+        let cluster = clusters.cluster_chunks(id).unwrap();
+        for pair in cluster.windows(2) {
+            prop_assert_ne!(pair[0].feefrac.cmp_rate(&pair[1].feefrac), Ordering::Less);
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn prop_chunks_are_topological_and_non_increasing(graph in graph_strategy()) {
+        let clusters = build(&graph);
+        assert_consistent(&clusters)?;
+    }
+
+    #[test]
+    fn prop_worst_chunk_has_no_outside_descendants(graph in graph_strategy()) {
+        let clusters = build(&graph);
+        let worst = clusters.worst_chunk().unwrap();
+        let members: HashSet<&Hash> = worst.txids.iter().collect();
+        for id in &worst.txids {
+            for child in clusters.children(id).unwrap() {
+                prop_assert!(members.contains(child));
+            }
+        }
+        for chunk in clusters.chunks_by_feerate() {
+            prop_assert_ne!(chunk.feefrac.cmp_rate(&worst.feefrac), Ordering::Less);
+        }
+    }
+
+    #[test]
+    fn prop_remove_keeps_clusters_consistent(graph in graph_strategy(), pick in any::<usize>()) {
+        let mut clusters = build(&graph);
+        let removed = txid(pick % graph.len());
+        prop_assert!(clusters.remove(&removed));
+        prop_assert!(!clusters.contains(&removed));
+        prop_assert_eq!(clusters.len(), graph.len() - 1);
+        assert_consistent(&clusters)?;
+    }
+
+    #[test]
+    fn prop_diagram_does_not_improve_on_itself(graph in graph_strategy()) {
+        let clusters = build(&graph);
+        let chunks = clusters.chunks_by_feerate().into_iter().cloned().collect();
+        let points = diagram(chunks);
+        prop_assert!(!improves_diagram(&points, &points));
+    }
+}
+
+#[test]
+fn test_replacement_must_outbid_evicted_descendants() {
+    let mut clusters = ClusterMempool::new();
+    clusters.insert(txid(1), FeeFrac::new(1_000, 100), HashSet::new());
+    clusters.insert(txid(2), FeeFrac::new(50_000, 100), HashSet::from([txid(1)]));
+    let conflicts = HashSet::from([txid(1)]);
+
+    // Outbids the replaced transaction but not its child
+    let replacement = ClusterTx {
+        feefrac: FeeFrac::new(2_000, 100),
+        parents: HashSet::new(),
+    };
+    let (old, new) = clusters.replacement_diagrams(&conflicts, &[(txid(3), replacement)]);
+    assert!(!improves_diagram(&new, &old));
+
+    let replacement = ClusterTx {
+        feefrac: FeeFrac::new(60_000, 100),
+        parents: HashSet::new(),
+    };
+    let (old, new) = clusters.replacement_diagrams(&conflicts, &[(txid(3), replacement)]);
+    assert!(improves_diagram(&new, &old));
+}
+
+fn spend(prev_hash: Hash, value: u64) -> Transaction {
+    Transaction {
+        version: 1,
+        inputs: bllvm_protocol::tx_inputs![TransactionInput {
+            prevout: OutPoint {
+                hash: prev_hash,
+                index: 0,
+            },
+            script_sig: vec![],
+            sequence: 0xffffffff,
+        }],
+        outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+            value: value as i64,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    }
+}
+
+fn utxo_set(coins: &[(Hash, u64)]) -> UtxoSet {
+    coins
+        .iter()
+        .map(|(hash, value)| {
+            (
+                OutPoint {
+                    hash: *hash,
+                    index: 0,
+                },
+                UTXO {
+                    value: *value as i64,
+                    script_pubkey: vec![0x51],
+                    height: 1,
+                    is_coinbase: false,
+                },
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_eviction_keeps_parent_paid_for_by_child() {
+    let utxos = utxo_set(&[([1; 32], 100_000), ([2; 32], 100_000)]);
+    let parent = spend([1; 32], 99_900);
+    let child = spend(calculate_tx_id(&parent), 89_900);
+    let standalone = spend([2; 32], 99_000);
+    let ids = [
+        calculate_tx_id(&parent),
+        calculate_tx_id(&child),
+        calculate_tx_id(&standalone),
+    ];
+
+    let mut mempool = MempoolManager::new();
+    mempool.set_policy_config(Some(MempoolPolicyConfig {
+        max_mempool_txs: 2,
+        eviction_strategy: EvictionStrategy::LowestFeeRate,
+        ..Default::default()
+    }));
+    for tx in [parent, child, standalone] {
+        assert!(mempool.add_transaction(tx, &utxos).await.unwrap());
+    }
+
+    // The parent pays the least on its own, but its chunk outbids the
+    // standalone transaction
+    let prioritized = mempool.get_prioritized_transactions(3, &utxos);
+    let order: Vec<Hash> = prioritized.iter().map(calculate_tx_id).collect();
+    assert_eq!(order, ids.to_vec());
+
+    mempool.process_once().await.unwrap();
+    assert_eq!(mempool.size(), 2);
+    assert!(mempool.get_transaction(&ids[0]).is_some());
+    assert!(mempool.get_transaction(&ids[1]).is_some());
+    assert!(mempool.get_transaction(&ids[2]).is_none());
+}
+
+#[tokio::test]
+async fn test_cluster_count_limit() {
+    let mempool = MempoolManager::new();
+    mempool.set_policy_config(Some(MempoolPolicyConfig {
+        max_cluster_count: 2,
+        ..Default::default()
+    }));
+    let parent = spend([1; 32], 90_000);
+    let child = spend(calculate_tx_id(&parent), 80_000);
+    let grandchild = spend(calculate_tx_id(&child), 70_000);
+    let utxos = utxo_set(&[([1; 32], 100_000)]);
+    assert!(mempool.add_transaction(parent, &utxos).await.unwrap());
+    assert!(mempool.add_transaction(child, &utxos).await.unwrap());
+    assert!(!mempool.add_transaction(grandchild, &utxos).await.unwrap());
+    assert_eq!(mempool.size(), 2);
+}
@@ -1,15 +1,23 @@
-use bllvm_node::storage::blockstore::BlockStore;
-use bllvm_node::storage::chainstate::ChainState;
+// Each test crate uses a different part of these fixtures
+#![allow(dead_code)]
+
+use bllvm_node::node::block_processor::commit_connected_block;
+use bllvm_node::node::reorg::{build_block_undo, ReorgEngine};
+use bllvm_node::storage::blockstore::{BlockStore, BlockUndo};
+use bllvm_node::storage::chainstate::{ChainParams, ChainState};
 use bllvm_node::storage::txindex::TxIndex;
 use bllvm_node::storage::utxostore::UtxoStore;
+use bllvm_node::storage::Storage;
 use bllvm_node::Block;
 use bllvm_node::BlockHeader;
 use bllvm_node::Hash;
 use bllvm_node::OutPoint;
 use bllvm_node::Transaction;
-use bllvm_node::{ByteString, TransactionInput, TransactionOutput};
-use bllvm_protocol::ProtocolVersion;
+use bllvm_node::{ByteString, TransactionInput, TransactionOutput, UtxoSet};
+use bllvm_protocol::segwit::Witness;
+use bllvm_protocol::{BitcoinProtocolEngine, ProtocolVersion};
 use std::collections::HashMap;
+use std::sync::Arc;
 use tempfile::TempDir;
 
 pub struct TempDb {
@@ -323,3 +331,234 @@ pub fn large_block(transaction_count: usize) -> Block {
 pub fn default_protocol_version() -> ProtocolVersion {
     ProtocolVersion::Regtest
 }
+
+/// Compact target of regtest blocks
+pub const REGTEST_BITS: u64 = 0x207fffff;
+
+/// Storage in a fresh temporary directory
+pub fn create_test_storage() -> (TempDir, Arc<Storage>) {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = Arc::new(Storage::new(temp_dir.path()).unwrap());
+    (temp_dir, storage)
+}
+
+/// Storage in a fresh temporary directory, set up for the regtest network
+pub fn create_regtest_storage() -> (TempDir, Arc<Storage>) {
+    let (temp_dir, storage) = create_test_storage();
+    storage
+        .chain()
+        .store_chain_params(&ChainParams::for_network("regtest"))
+        .unwrap();
+    (temp_dir, storage)
+}
+
+/// Coinbase for `height` paying 50 BTC to an `OP_TRUE` output
+///
+/// The coinbase of the unmined fixture chains ([`connect_chain`]), whose
+/// hashes the regtest AssumeUTXO table depends on.
+pub fn plain_coinbase(height: u64) -> Transaction {
+    let mut script_sig = vec![0x08];
+    script_sig.extend_from_slice(&height.to_le_bytes());
+    coinbase_with_script_sig(script_sig)
+}
+
+/// Coinbase for `height`, tagged so competing branches get distinct coinbases
+pub fn coinbase(height: u64, tag: u8) -> Transaction {
+    // BIP34-style height push plus the tag
+    let mut script_sig = vec![0x08];
+    script_sig.extend_from_slice(&height.to_le_bytes());
+    script_sig.push(tag);
+    coinbase_with_script_sig(script_sig)
+}
+
+fn coinbase_with_script_sig(script_sig: ByteString) -> Transaction {
+    Transaction {
+        version: 1,
+        inputs: bllvm_protocol::tx_inputs![TransactionInput {
+            prevout: OutPoint {
+                hash: [0u8; 32],
+                index: 0xffffffff,
+            },
+            script_sig,
+            sequence: 0xffffffff,
+        }],
+        outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+            value: 50_0000_0000,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    }
+}
+
+/// Spend `prevout` (an `OP_TRUE` output) to a single `OP_TRUE` output
+pub fn spend(prevout: OutPoint, value: i64) -> Transaction {
+    Transaction {
+        version: 1,
+        inputs: bllvm_protocol::tx_inputs![TransactionInput {
+            prevout,
+            script_sig: vec![0x51],
+            sequence: 0xffffffff,
+        }],
+        outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+            value,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    }
+}
+
+/// Build and mine a regtest block on top of `prev_hash`
+pub fn mine_regtest_block(prev_hash: Hash, height: u64, tag: u8) -> Block {
+    mine_regtest_block_with(prev_hash, height, tag, Vec::new())
+}
+
+/// Build and mine a regtest block on top of `prev_hash` holding `transactions`
+/// after its coinbase
+pub fn mine_regtest_block_with(
+    prev_hash: Hash,
+    height: u64,
+    tag: u8,
+    transactions: Vec<Transaction>,
+) -> Block {
+    use bllvm_protocol::mining::calculate_merkle_root;
+    use bllvm_protocol::pow::check_proof_of_work;
+
+    let mut transactions = transactions;
+    transactions.insert(0, coinbase(height, tag));
+    let merkle_root = calculate_merkle_root(&transactions).unwrap();
+    let mut block = Block {
+        header: BlockHeader {
+            version: 4,
+            prev_block_hash: prev_hash,
+            merkle_root,
+            timestamp: 1_296_688_602 + height * 600 + tag as u64,
+            bits: REGTEST_BITS,
+            nonce: 0,
+        },
+        transactions: transactions.into_boxed_slice(),
+    };
+    while !check_proof_of_work(&block.header).unwrap() {
+        block.header.nonce += 1;
+    }
+    block
+}
+
+/// Header of an unmined fixture block, its merkle root and timestamp derived
+/// from `nonce`
+pub fn make_header(prev_hash: Hash, nonce: u64, bits: u64) -> BlockHeader {
+    BlockHeader {
+        version: 4,
+        prev_block_hash: prev_hash,
+        merkle_root: [nonce as u8; 32],
+        timestamp: 1_296_688_602 + nonce * 600,
+        bits,
+        nonce,
+    }
+}
+
+/// Unmined block at `height` holding `transactions`
+///
+/// For storage tests that connect blocks without validating them.
+pub fn make_block(prev_hash: Hash, height: u64, transactions: Vec<Transaction>) -> Block {
+    Block {
+        header: make_header(prev_hash, height, REGTEST_BITS),
+        transactions: transactions.into_boxed_slice(),
+    }
+}
+
+/// Empty witnesses for every transaction of `block`
+pub fn empty_witnesses(block: &Block) -> Vec<Witness> {
+    block.transactions.iter().map(|_| Vec::new()).collect()
+}
+
+/// Connect `block` on top of the tip without validating it, through the
+/// storage's UTXO cache
+pub fn connect_block(storage: &Arc<Storage>, block: &Block, height: u64) -> BlockUndo {
+    let mut cache = storage.utxo_cache();
+    let undo = build_block_undo(block, &cache.fetch_view(block).unwrap());
+    commit_connected_block(
+        &storage.blocks(),
+        Some(storage),
+        block,
+        &[],
+        height,
+        &undo,
+        &mut cache,
+    )
+    .unwrap();
+    undo
+}
+
+/// Connect `count` unmined coinbase-only blocks from genesis without
+/// validating them
+pub fn connect_chain(storage: &Arc<Storage>, count: u64) -> Vec<Block> {
+    let mut prev = [0u8; 32];
+    let mut blocks = Vec::new();
+    for height in 0..count {
+        let block = make_block(prev, height, vec![plain_coinbase(height)]);
+        connect_block(storage, &block, height);
+        prev = storage.blocks().get_block_hash(&block);
+        blocks.push(block);
+    }
+    blocks
+}
+
+/// Mine and connect `count` regtest blocks on top of `tip`, validating each
+pub fn extend_main_chain(
+    engine: &ReorgEngine,
+    blockstore: &BlockStore,
+    tip: Hash,
+    start_height: u64,
+    count: u64,
+    tag: u8,
+    utxo_set: &mut UtxoSet,
+) -> Vec<Block> {
+    let mut prev = tip;
+    let mut blocks = Vec::new();
+    for height in start_height..start_height + count {
+        let block = mine_regtest_block(prev, height, tag);
+        assert!(engine
+            .connect_block(&block, &empty_witnesses(&block), height, utxo_set)
+            .unwrap());
+        prev = blockstore.get_block_hash(&block);
+        blocks.push(block);
+    }
+    blocks
+}
+
+/// Mine and connect `count` regtest blocks from genesis as the storage's
+/// active chain, validating each
+pub fn connect_regtest_chain(
+    storage: &Arc<Storage>,
+    protocol: &BitcoinProtocolEngine,
+    count: u64,
+) -> Vec<Block> {
+    let blocks = storage.blocks();
+    let engine = ReorgEngine::new(&blocks, protocol, Some(storage));
+    let mut utxos = storage.utxo_cache();
+    let mut prev = [0u8; 32];
+    let mut chain = Vec::new();
+    for height in 0..count {
+        let block = mine_regtest_block(prev, height, 0);
+        assert!(engine
+            .connect_block(&block, &empty_witnesses(&block), height, &mut utxos)
+            .unwrap());
+        prev = blocks.get_block_hash(&block);
+        chain.push(block);
+    }
+    storage.flush().unwrap();
+    chain
+}
+
+/// Serialize a block the way it appears on the wire
+pub fn serialize_block(block: &Block) -> Vec<u8> {
+    use bllvm_protocol::serialization::{serialize_block_header, serialize_transaction};
+
+    let mut data = serialize_block_header(&block.header).to_vec();
+    assert!(block.transactions.len() < 0xfd);
+    data.push(block.transactions.len() as u8);
+    for tx in block.transactions.iter() {
+        data.extend_from_slice(&serialize_transaction(tx));
+    }
+    data
+}
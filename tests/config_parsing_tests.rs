@@ -2,9 +2,11 @@
 
 use bllvm_node::config::{
     BanListSharingConfig, DosProtectionConfig, IndexingConfig, IndexingStrategy, ModuleConfig,
-    ModuleResourceLimitsConfig, NetworkTimingConfig, NodeConfig, PruningConfig, PruningMode,
-    RequestTimeoutConfig, RpcAuthConfig, StorageConfig, TransportPreferenceConfig,
+    ModuleResourceLimitsConfig, NetworkTimingConfig, NodeConfig, ProxyConfig, PruningConfig,
+    PruningMode, RequestTimeoutConfig, RpcAuthConfig, StorageConfig, TorControlConfig,
+    TransportPreferenceConfig,
 };
+use bllvm_node::network::netaddr::Network;
 use std::path::PathBuf;
 use tempfile::TempDir;
 
@@ -39,6 +41,7 @@ fn test_request_timeout_config_default() {
     assert!(config.storage_timeout_seconds > 0);
     assert!(config.network_timeout_seconds > 0);
     assert!(config.rpc_timeout_seconds > 0);
+    assert!(config.block_stall_timeout_seconds > 0);
 }
 
 #[test]
@@ -279,6 +282,8 @@ fn test_indexing_config_eager_strategy() {
         max_indexed_addresses: 0, // 0 = unlimited
         enable_compression: false,
         background_indexing: false,
+        enable_block_filter_index: false,
+        enable_coin_stats_index: false,
     };
 
     // Eager strategy should be valid
@@ -295,6 +300,8 @@ fn test_indexing_config_lazy_strategy() {
         max_indexed_addresses: 1000,
         enable_compression: true,
         background_indexing: true,
+        enable_block_filter_index: false,
+        enable_coin_stats_index: false,
     };
 
     // Lazy strategy should be valid
@@ -386,6 +393,47 @@ fn test_node_config_serialization_roundtrip_toml() {
     );
 }
 
+#[test]
+fn test_proxy_config_roundtrip_toml() {
+    let mut config = NodeConfig::default();
+    let mut proxy = ProxyConfig {
+        proxy: Some("127.0.0.1:9050".parse().unwrap()),
+        only_networks: vec![Network::Onion],
+        ..Default::default()
+    };
+    proxy
+        .network_proxies
+        .insert(Network::I2p, "127.0.0.1:4447".parse().unwrap());
+    config.proxy = Some(proxy);
+    config.tor_control = Some(TorControlConfig::default());
+
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("tor.toml");
+    config.to_toml_file(&config_path).unwrap();
+    let contents = std::fs::read_to_string(&config_path).unwrap();
+    assert!(contents.contains("only_networks = [\"onion\"]"));
+
+    let loaded = NodeConfig::from_toml_file(&config_path).unwrap();
+    let proxy = loaded.proxy.unwrap();
+    assert!(proxy.randomize_credentials);
+    let settings = proxy.proxy_settings();
+    for network in [Network::Ipv4, Network::Ipv6, Network::Onion] {
+        assert_eq!(
+            settings.proxy_for(network).unwrap().addr(),
+            "127.0.0.1:9050".parse().unwrap()
+        );
+    }
+    assert_eq!(
+        settings.proxy_for(Network::I2p).unwrap().addr(),
+        "127.0.0.1:4447".parse().unwrap()
+    );
+    assert!(settings.proxy_for(Network::Cjdns).is_none());
+    assert_eq!(
+        loaded.tor_control.unwrap().control_addr,
+        "127.0.0.1:9051".parse().unwrap()
+    );
+}
+
 #[test]
 fn test_node_config_invalid_json() {
     let temp_dir = TempDir::new().unwrap();
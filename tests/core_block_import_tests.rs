@@ -0,0 +1,202 @@
+//! Tests for importing blocks from Bitcoin Core block files
+//!
+//! The fixtures are regtest `blk?????.dat` files in Bitcoin Core's layout
+//! (magic, length, serialized block), written with blocks out of chain order,
+//! a stale block and zero padding like the files Bitcoin Core leaves behind.
+
+use bllvm_node::network::protocol::{BITCOIN_MAGIC_MAINNET, BITCOIN_MAGIC_REGTEST};
+use bllvm_node::node::import::{load_checkpoint, CoreBlockImporter, ImportPhase};
+use bllvm_node::storage::Storage;
+use bllvm_node::{Block, Hash};
+use bllvm_protocol::{BitcoinProtocolEngine, ProtocolVersion};
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+mod common;
+use common::{mine_regtest_block, serialize_block};
+
+/// Mine a chain of `count` blocks on top of `prev_hash`, starting at `height`
+fn mine_chain(
+    storage: &Storage,
+    mut prev_hash: Hash,
+    height: u64,
+    count: u64,
+    tag: u8,
+) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    for height in height..height + count {
+        let block = mine_regtest_block(prev_hash, height, tag);
+        prev_hash = storage.blocks().get_block_hash(&block);
+        blocks.push(block);
+    }
+    blocks
+}
+
+/// Write a `blk` file holding `blocks` in order, followed by zero padding
+fn write_block_file(dir: &Path, file: u32, magic: [u8; 4], blocks: &[&Block]) {
+    let mut data = Vec::new();
+    for block in blocks {
+        let bytes = serialize_block(block);
+        data.extend_from_slice(&magic);
+        data.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        data.extend_from_slice(&bytes);
+    }
+    data.extend_from_slice(&[0u8; 256]);
+    std::fs::write(dir.join(format!("blk{:05}.dat", file)), data).unwrap();
+}
+
+fn setup() -> (TempDir, TempDir, Arc<Storage>, BitcoinProtocolEngine) {
+    let data_dir = TempDir::new().unwrap();
+    let blocks_dir = TempDir::new().unwrap();
+    let storage = Arc::new(Storage::new(data_dir.path()).unwrap());
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    (data_dir, blocks_dir, storage, protocol)
+}
+
+/// Chain of 6 blocks and a stale block at height 3, split over two files with
+/// children stored before their parents
+fn write_fixture(storage: &Storage, dir: &Path) -> Vec<Block> {
+    let chain = mine_chain(storage, [0u8; 32], 0, 6, 0);
+    let stale = mine_regtest_block(storage.blocks().get_block_hash(&chain[2]), 3, 1);
+    write_block_file(
+        dir,
+        0,
+        BITCOIN_MAGIC_REGTEST,
+        &[&chain[0], &chain[2], &stale, &chain[4]],
+    );
+    write_block_file(
+        dir,
+        1,
+        BITCOIN_MAGIC_REGTEST,
+        &[&chain[5], &chain[1], &chain[3]],
+    );
+    chain
+}
+
+#[test]
+fn test_import_connects_out_of_order_blocks() {
+    let (_data_dir, blocks_dir, storage, protocol) = setup();
+    let chain = write_fixture(&storage, blocks_dir.path());
+
+    let importer = CoreBlockImporter::new(blocks_dir.path(), ProtocolVersion::Regtest)
+        .unwrap()
+        .with_progress_interval(1);
+    let mut phases = Vec::new();
+    let summary = importer
+        .import(&storage, &protocol, |progress| phases.push(progress.phase))
+        .unwrap();
+
+    assert_eq!(summary.blocks_scanned, 7);
+    assert_eq!(summary.blocks_connected, 6);
+    // The stale block is not connected
+    assert_eq!(summary.blocks_skipped, 1);
+    assert_eq!(summary.tip_height, Some(5));
+    assert!(summary.resumed_from.is_none());
+    assert!(phases.contains(&ImportPhase::Scanning));
+    assert_eq!(phases.last(), Some(&ImportPhase::Connecting));
+
+    let blocks = storage.blocks();
+    for (height, block) in chain.iter().enumerate() {
+        let hash = blocks.get_block_hash(block);
+        assert_eq!(
+            blocks.get_hash_by_height(height as u64).unwrap(),
+            Some(hash)
+        );
+    }
+    assert_eq!(storage.chain().get_height().unwrap(), Some(5));
+    assert_eq!(
+        storage.chain().get_tip_hash().unwrap(),
+        Some(blocks.get_block_hash(&chain[5]))
+    );
+}
+
+#[test]
+fn test_import_resumes_from_checkpoint() {
+    let (_data_dir, blocks_dir, storage, protocol) = setup();
+    let chain = write_fixture(&storage, blocks_dir.path());
+    let importer = CoreBlockImporter::new(blocks_dir.path(), ProtocolVersion::Regtest).unwrap();
+    importer.import(&storage, &protocol, |_| {}).unwrap();
+
+    // Everything has been read, so the checkpoint is the end of the last file
+    let checkpoint = load_checkpoint(&storage).unwrap().unwrap();
+    assert_eq!(checkpoint.position.file, 1);
+    assert_eq!(
+        checkpoint.tip_hash,
+        storage.blocks().get_block_hash(&chain[5])
+    );
+
+    // Bitcoin Core synced further into a new file
+    let more = mine_chain(
+        &storage,
+        storage.blocks().get_block_hash(&chain[5]),
+        6,
+        3,
+        0,
+    );
+    write_block_file(
+        blocks_dir.path(),
+        2,
+        BITCOIN_MAGIC_REGTEST,
+        &[&more[1], &more[0], &more[2]],
+    );
+
+    let summary = importer.import(&storage, &protocol, |_| {}).unwrap();
+    assert_eq!(summary.resumed_from, Some(checkpoint.position));
+    // Only the new file is scanned
+    assert_eq!(summary.blocks_scanned, 3);
+    assert_eq!(summary.blocks_connected, 3);
+    assert_eq!(summary.tip_height, Some(8));
+
+    // Nothing left to do
+    let summary = importer.import(&storage, &protocol, |_| {}).unwrap();
+    assert_eq!(summary.blocks_connected, 0);
+    assert_eq!(summary.tip_height, Some(8));
+}
+
+#[test]
+fn test_import_ignores_other_network_magic() {
+    let (_data_dir, blocks_dir, storage, protocol) = setup();
+    let chain = mine_chain(&storage, [0u8; 32], 0, 2, 0);
+    write_block_file(
+        blocks_dir.path(),
+        0,
+        BITCOIN_MAGIC_MAINNET,
+        &[&chain[0], &chain[1]],
+    );
+
+    let importer = CoreBlockImporter::new(blocks_dir.path(), ProtocolVersion::Regtest).unwrap();
+    let summary = importer.import(&storage, &protocol, |_| {}).unwrap();
+    assert_eq!(summary.blocks_scanned, 0);
+    assert_eq!(summary.blocks_connected, 0);
+    assert_eq!(storage.chain().get_height().unwrap(), None);
+}
+
+#[test]
+fn test_import_reads_obfuscated_files() {
+    let (_data_dir, blocks_dir, storage, protocol) = setup();
+    let chain = mine_chain(&storage, [0u8; 32], 0, 3, 0);
+    write_block_file(
+        blocks_dir.path(),
+        0,
+        BITCOIN_MAGIC_REGTEST,
+        &[&chain[0], &chain[1], &chain[2]],
+    );
+
+    // Obfuscate the file the way Bitcoin Core 28+ does
+    let key = [0x5a, 0x01, 0xff, 0x33, 0x90, 0x0c, 0x7e, 0xa4];
+    let path = blocks_dir.path().join("blk00000.dat");
+    let data: Vec<u8> = std::fs::read(&path)
+        .unwrap()
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ key[i % key.len()])
+        .collect();
+    std::fs::write(&path, data).unwrap();
+    std::fs::write(blocks_dir.path().join("xor.dat"), key).unwrap();
+
+    let importer = CoreBlockImporter::new(blocks_dir.path(), ProtocolVersion::Regtest).unwrap();
+    let summary = importer.import(&storage, &protocol, |_| {}).unwrap();
+    assert_eq!(summary.blocks_connected, 3);
+    assert_eq!(storage.chain().get_height().unwrap(), Some(2));
+}
@@ -0,0 +1,175 @@
+//! Tests for the confirmation-tracking fee estimator
+
+use bllvm_node::node::fee_estimation::{EstimateMode, FeeEstimator};
+use bllvm_node::node::mempool::MempoolManager;
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{
+    Hash, OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoSet, UTXO,
+};
+use tempfile::TempDir;
+
+const VSIZE: u64 = 200;
+
+fn txid(height: u64, i: u64) -> Hash {
+    let mut hash = [0u8; 32];
+    hash[..8].copy_from_slice(&height.to_le_bytes());
+    hash[8..16].copy_from_slice(&i.to_le_bytes());
+    hash
+}
+
+/// Run `blocks` blocks from `start`, each confirming the previous block's
+/// transactions paying `fast_rate` and leaving those paying 2 sat/vB
+/// unconfirmed. Returns the next height.
+fn run_blocks(estimator: &FeeEstimator, start: u64, blocks: u64, fast_rate: u64) -> u64 {
+    let mut pending: Vec<Hash> = Vec::new();
+    for height in start..start + blocks {
+        estimator.process_block(height, &pending);
+        pending.clear();
+        for i in 0..10 {
+            let fast = txid(height, i);
+            estimator.process_transaction(fast, fast_rate * VSIZE, VSIZE);
+            pending.push(fast);
+            estimator.process_transaction(txid(height, i + 100), 2 * VSIZE, VSIZE);
+        }
+    }
+    start + blocks
+}
+
+#[test]
+fn test_estimates_follow_confirmations() {
+    let estimator = FeeEstimator::new();
+    assert_eq!(
+        estimator.estimate_smart_fee(6, EstimateMode::Economical),
+        None
+    );
+
+    let next = run_blocks(&estimator, 1, 200, 50);
+    for mode in [EstimateMode::Economical, EstimateMode::Conservative] {
+        let estimate = estimator.estimate_smart_fee(6, mode).unwrap();
+        assert_eq!(estimate.blocks, 6);
+        assert!((estimate.fee_rate - 50.0).abs() < 1e-6, "{estimate:?}");
+    }
+
+    // A target of 1 is answered for 2 blocks; out of range targets are not
+    assert_eq!(
+        estimator
+            .estimate_smart_fee(1, EstimateMode::Economical)
+            .unwrap()
+            .blocks,
+        2
+    );
+    assert_eq!(
+        estimator.estimate_smart_fee(0, EstimateMode::Economical),
+        None
+    );
+    assert_eq!(
+        estimator.estimate_smart_fee(1009, EstimateMode::Economical),
+        None
+    );
+
+    // Fee rates fall; the economical estimate follows at least as fast
+    run_blocks(&estimator, next, 100, 20);
+    let economical = estimator
+        .estimate_smart_fee(6, EstimateMode::Economical)
+        .unwrap();
+    let conservative = estimator
+        .estimate_smart_fee(6, EstimateMode::Conservative)
+        .unwrap();
+    assert!(economical.fee_rate < 50.0, "{economical:?}");
+    assert!(conservative.fee_rate >= economical.fee_rate);
+}
+
+#[test]
+fn test_target_capped_by_history() {
+    let estimator = FeeEstimator::new();
+    run_blocks(&estimator, 1, 40, 30);
+
+    // 39 blocks of history answer targets up to 19
+    let estimate = estimator
+        .estimate_smart_fee(100, EstimateMode::Economical)
+        .unwrap();
+    assert_eq!(estimate.blocks, 19);
+}
+
+#[test]
+fn test_estimates_saved_and_loaded() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("fee_estimates.dat");
+    let estimator = FeeEstimator::new();
+    assert!(!estimator.load(&path).unwrap());
+
+    run_blocks(&estimator, 1, 100, 40);
+    estimator.save(&path).unwrap();
+
+    let loaded = FeeEstimator::new();
+    assert!(loaded.load(&path).unwrap());
+    assert_eq!(loaded.best_height(), estimator.best_height());
+    assert_eq!(loaded.tracked_count(), 0);
+    for mode in [EstimateMode::Economical, EstimateMode::Conservative] {
+        assert_eq!(
+            loaded.estimate_smart_fee(6, mode),
+            estimator.estimate_smart_fee(6, mode)
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_mempool_feeds_estimator() {
+    use bllvm_protocol::serialization::transaction::serialize_transaction;
+
+    let mempool = MempoolManager::new();
+    let estimator = mempool.fee_estimator();
+    estimator.process_block(1, &[]);
+    let utxos: UtxoSet = [(
+        OutPoint {
+            hash: [1; 32],
+            index: 0,
+        },
+        UTXO {
+            value: 100_000,
+            script_pubkey: vec![0x51],
+            height: 1,
+            is_coinbase: false,
+        },
+    )]
+    .into_iter()
+    .collect();
+
+    let tx = Transaction {
+        version: 2,
+        inputs: bllvm_protocol::tx_inputs![TransactionInput {
+            prevout: OutPoint {
+                hash: [1; 32],
+                index: 0,
+            },
+            script_sig: vec![],
+            sequence: 0xffffffff,
+        }],
+        outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+            value: 90_000,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    };
+    let id = calculate_tx_id(&tx);
+    let vsize = serialize_transaction(&tx).len() as u64;
+    assert!(mempool.add_transaction(tx, &utxos).await.unwrap());
+    assert_eq!(estimator.tracked_count(), 1);
+
+    // Recorded at the 10_000 sat fee it pays, in the same bucket as a
+    // transaction reported directly at that rate
+    let (fee_rate, bucket) = estimator.tracked_fee_rate(&id).unwrap();
+    assert!((fee_rate - 10_000.0 / vsize as f64).abs() < 1e-9);
+    let direct = FeeEstimator::new();
+    direct.process_block(1, &[]);
+    direct.process_transaction(id, 10_000, vsize);
+    assert_eq!(direct.tracked_fee_rate(&id), Some((fee_rate, bucket)));
+    // Not the lowest bucket, where a zero fee would land
+    assert!(bucket > 1.0);
+
+    // Confirmed: no longer tracked, and removing it afterwards changes nothing
+    estimator.process_block(2, &[id]);
+    assert_eq!(estimator.tracked_count(), 0);
+    assert!(mempool.remove_transaction(&id));
+    assert_eq!(estimator.tracked_count(), 0);
+}
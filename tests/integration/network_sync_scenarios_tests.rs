@@ -37,10 +37,11 @@ fn test_sync_state_machine_initial_to_synced() {
     // Transition through states
     machine.transition_to(SyncState::Headers);
     assert!(matches!(machine.state(), &SyncState::Headers));
-    assert_eq!(machine.progress(), 0.3);
+    assert_eq!(machine.progress(), 0.0);
     
     machine.transition_to(SyncState::Blocks);
     assert!(matches!(machine.state(), &SyncState::Blocks));
+    machine.update_heights(600, 1000);
     assert_eq!(machine.progress(), 0.6);
     
     machine.transition_to(SyncState::Synced);
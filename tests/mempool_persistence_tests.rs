@@ -0,0 +1,178 @@
+//! Tests for saving and loading the mempool
+
+use bllvm_node::node::mempool::{MempoolLoadStats, MempoolManager};
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{
+    Hash, OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoSet, UTXO,
+};
+use tempfile::TempDir;
+
+fn spend(prev_hash: Hash, value: u64) -> Transaction {
+    Transaction {
+        version: 2,
+        inputs: bllvm_protocol::tx_inputs![TransactionInput {
+            prevout: OutPoint {
+                hash: prev_hash,
+                index: 0,
+            },
+            script_sig: vec![],
+            sequence: 0xffffffff,
+        }],
+        outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+            value: value as i64,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    }
+}
+
+fn utxo_set(coins: &[Hash]) -> UtxoSet {
+    coins
+        .iter()
+        .map(|hash| {
+            (
+                OutPoint {
+                    hash: *hash,
+                    index: 0,
+                },
+                UTXO {
+                    value: 100_000,
+                    script_pubkey: vec![0x51],
+                    height: 1,
+                    is_coinbase: false,
+                },
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_mempool_round_trip_keeps_metadata() {
+    let utxos = utxo_set(&[[1; 32]]);
+    let parent = spend([1; 32], 90_000);
+    let child = spend(calculate_tx_id(&parent), 80_000);
+    let parent_id = calculate_tx_id(&parent);
+    let child_id = calculate_tx_id(&child);
+
+    let mempool = MempoolManager::new();
+    assert!(mempool.add_transaction(parent, &utxos).await.unwrap());
+    assert!(mempool.add_transaction(child, &utxos).await.unwrap());
+    mempool.prioritise_transaction(child_id, 5_000);
+    mempool.prioritise_transaction([9; 32], -1_000);
+    assert!(mempool.add_unbroadcast(parent_id));
+    assert!(!mempool.add_unbroadcast([8; 32]));
+    let entry_time = mempool.entry_time(&parent_id).unwrap();
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("mempool.dat");
+    mempool.save_to_disk(&path).unwrap();
+
+    let loaded = MempoolManager::new();
+    let stats = loaded.load_from_disk(&path, &utxos).await.unwrap();
+    assert_eq!(
+        stats,
+        MempoolLoadStats {
+            accepted: 2,
+            unbroadcast: 1,
+            ..Default::default()
+        }
+    );
+    assert_eq!(loaded.size(), 2);
+    assert_eq!(loaded.entry_time(&parent_id), Some(entry_time));
+    assert_eq!(loaded.fee_delta(&child_id), 5_000);
+    assert_eq!(loaded.fee_delta(&[9; 32]), -1_000);
+    assert_eq!(loaded.unbroadcast_txids(), vec![parent_id]);
+
+    // Loading again finds everything already there
+    let stats = loaded.load_from_disk(&path, &utxos).await.unwrap();
+    assert_eq!(stats.already_there, 2);
+    assert_eq!(stats.accepted, 0);
+}
+
+#[tokio::test]
+async fn test_mempool_load_revalidates_against_tip() {
+    let parent = spend([1; 32], 90_000);
+    let child = spend(calculate_tx_id(&parent), 80_000);
+    let unrelated = spend([2; 32], 90_000);
+
+    let utxos = utxo_set(&[[1; 32], [2; 32]]);
+    let mempool = MempoolManager::new();
+    for tx in [parent, child, unrelated.clone()] {
+        assert!(mempool.add_transaction(tx, &utxos).await.unwrap());
+    }
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("mempool.dat");
+    mempool.save_to_disk(&path).unwrap();
+
+    // The parent's coin has since been spent by a block
+    let loaded = MempoolManager::new();
+    let stats = loaded
+        .load_from_disk(&path, &utxo_set(&[[2; 32]]))
+        .await
+        .unwrap();
+    assert_eq!(stats.accepted, 1);
+    assert_eq!(stats.failed, 2);
+    assert_eq!(
+        loaded.transaction_hashes(),
+        vec![calculate_tx_id(&unrelated)]
+    );
+}
+
+#[tokio::test]
+async fn test_mempool_load_rejects_corrupt_file() {
+    let utxos = utxo_set(&[[1; 32]]);
+    let mempool = MempoolManager::new();
+    assert!(mempool
+        .add_transaction(spend([1; 32], 90_000), &utxos)
+        .await
+        .unwrap());
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("mempool.dat");
+    mempool.save_to_disk(&path).unwrap();
+
+    let mut data = std::fs::read(&path).unwrap();
+    let middle = data.len() / 2;
+    data[middle] ^= 0xff;
+    std::fs::write(&path, &data).unwrap();
+    let loaded = MempoolManager::new();
+    assert!(loaded
+        .load_from_disk(&path, &utxo_set(&[[1; 32]]))
+        .await
+        .is_err());
+
+    // Files from before the format was versioned are not read
+    std::fs::write(&path, 1u32.to_le_bytes()).unwrap();
+    assert!(loaded
+        .load_from_disk(&path, &utxo_set(&[[1; 32]]))
+        .await
+        .is_err());
+    assert_eq!(loaded.size(), 0);
+}
+
+#[tokio::test]
+async fn test_saved_prevouts_lists_spent_outputs() {
+    let parent = spend([1; 32], 90_000);
+    let child = spend(calculate_tx_id(&parent), 80_000);
+    let child_prevout = child.inputs[0].prevout.clone();
+
+    let utxos = utxo_set(&[[1; 32]]);
+    let mempool = MempoolManager::new();
+    assert!(mempool.add_transaction(parent, &utxos).await.unwrap());
+    assert!(mempool.add_transaction(child, &utxos).await.unwrap());
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("mempool.dat");
+    mempool.save_to_disk(&path).unwrap();
+
+    let mut prevouts = MempoolManager::saved_prevouts(&path).unwrap();
+    prevouts.sort_by_key(|prevout| prevout.hash);
+    let mut expected = vec![
+        OutPoint {
+            hash: [1; 32],
+            index: 0,
+        },
+        child_prevout,
+    ];
+    expected.sort_by_key(|prevout| prevout.hash);
+    assert_eq!(prevouts, expected);
+}
@@ -52,7 +52,7 @@ fn create_test_utxo_set() -> UtxoSet {
 
 #[tokio::test]
 async fn test_eviction_strategy_lowest_fee_rate() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
     let mut policy = MempoolPolicyConfig::default();
     policy.max_mempool_mb = 1; // 1 MB limit
     policy.max_mempool_txs = 10;
@@ -67,7 +67,7 @@ async fn test_eviction_strategy_lowest_fee_rate() {
 
 #[tokio::test]
 async fn test_eviction_strategy_oldest_first() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
     let mut policy = MempoolPolicyConfig::default();
     policy.max_mempool_mb = 1;
     policy.max_mempool_txs = 10;
@@ -81,7 +81,7 @@ async fn test_eviction_strategy_oldest_first() {
 
 #[tokio::test]
 async fn test_ancestor_count_limit() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
     let mut policy = MempoolPolicyConfig::default();
     policy.max_ancestor_count = 5; // Allow max 5 ancestors
 
@@ -93,7 +93,7 @@ async fn test_ancestor_count_limit() {
 
 #[tokio::test]
 async fn test_ancestor_size_limit() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
     let mut policy = MempoolPolicyConfig::default();
     policy.max_ancestor_size = 10_000; // 10 KB limit
 
@@ -105,7 +105,7 @@ async fn test_ancestor_size_limit() {
 
 #[tokio::test]
 async fn test_descendant_count_limit() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
     let mut policy = MempoolPolicyConfig::default();
     policy.max_descendant_count = 5; // Allow max 5 descendants
 
@@ -117,7 +117,7 @@ async fn test_descendant_count_limit() {
 
 #[tokio::test]
 async fn test_descendant_size_limit() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
     let mut policy = MempoolPolicyConfig::default();
     policy.max_descendant_size = 10_000; // 10 KB limit
 
@@ -129,7 +129,7 @@ async fn test_descendant_size_limit() {
 
 #[tokio::test]
 async fn test_mempool_size_limit() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
     let mut policy = MempoolPolicyConfig::default();
     policy.max_mempool_mb = 1; // 1 MB limit
     policy.max_mempool_txs = 100;
@@ -143,7 +143,7 @@ async fn test_mempool_size_limit() {
 
 #[tokio::test]
 async fn test_mempool_transaction_count_limit() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
     let mut policy = MempoolPolicyConfig::default();
     policy.max_mempool_txs = 10;
 
@@ -155,7 +155,7 @@ async fn test_mempool_transaction_count_limit() {
 
 #[tokio::test]
 async fn test_mempool_expiry() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
     let mut policy = MempoolPolicyConfig::default();
     policy.mempool_expiry_hours = 1; // 1 hour expiry
 
@@ -0,0 +1,201 @@
+//! Tests for keeping the mempool in step with connected and disconnected blocks
+
+use bllvm_node::node::mempool::{MempoolManager, MempoolRemovalReason};
+use bllvm_node::{
+    Block, BlockHeader, Hash, OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoSet,
+    UTXO,
+};
+use bllvm_protocol::block::calculate_tx_id;
+
+mod common;
+use common::plain_coinbase;
+
+fn spend(prev_hash: Hash, value: u64) -> Transaction {
+    Transaction {
+        version: 2,
+        inputs: bllvm_protocol::tx_inputs![TransactionInput {
+            prevout: OutPoint {
+                hash: prev_hash,
+                index: 0,
+            },
+            script_sig: vec![],
+            sequence: 0xffffffff,
+        }],
+        outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+            value: value as i64,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    }
+}
+
+fn block(height: u64, txs: Vec<Transaction>) -> Block {
+    let mut transactions = vec![plain_coinbase(height)];
+    transactions.extend(txs);
+    Block {
+        header: BlockHeader {
+            version: 4,
+            prev_block_hash: [0u8; 32],
+            merkle_root: [0u8; 32],
+            timestamp: 0,
+            bits: 0x207fffff,
+            nonce: 0,
+        },
+        transactions: transactions.into_boxed_slice(),
+    }
+}
+
+fn coin(height: u64, is_coinbase: bool) -> UTXO {
+    UTXO {
+        value: 100_000,
+        script_pubkey: vec![0x51],
+        height,
+        is_coinbase,
+    }
+}
+
+fn utxo_set(coins: &[Hash]) -> UtxoSet {
+    coins
+        .iter()
+        .map(|hash| {
+            (
+                OutPoint {
+                    hash: *hash,
+                    index: 0,
+                },
+                coin(1, false),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_connected_block_removes_confirmed_and_conflicts() {
+    let confirmed = spend([1; 32], 90_000);
+    let confirmed_child = spend(calculate_tx_id(&confirmed), 80_000);
+    let conflicted = spend([2; 32], 90_000);
+    let conflicted_child = spend(calculate_tx_id(&conflicted), 80_000);
+    let utxos = utxo_set(&[[1; 32], [2; 32]]);
+    let mempool = MempoolManager::new();
+    for tx in [
+        confirmed.clone(),
+        confirmed_child.clone(),
+        conflicted.clone(),
+        conflicted_child.clone(),
+    ] {
+        assert!(mempool.add_transaction(tx, &utxos).await.unwrap());
+    }
+
+    // The block confirms one transaction and double-spends the other's input
+    let block = block(10, vec![confirmed.clone(), spend([2; 32], 85_000)]);
+    let mut removed = mempool.remove_for_block(&block.transactions);
+    removed.sort_by_key(|(txid, _)| *txid);
+
+    let mut expected = vec![
+        (calculate_tx_id(&confirmed), MempoolRemovalReason::Block),
+        (calculate_tx_id(&conflicted), MempoolRemovalReason::Conflict),
+        (
+            calculate_tx_id(&conflicted_child),
+            MempoolRemovalReason::Conflict,
+        ),
+    ];
+    expected.sort_by_key(|(txid, _)| *txid);
+    assert_eq!(removed, expected);
+    assert_eq!(
+        mempool.transaction_hashes(),
+        vec![calculate_tx_id(&confirmed_child)]
+    );
+}
+
+#[tokio::test]
+async fn test_disconnected_block_transactions_return_before_children() {
+    let parent = spend([1; 32], 90_000);
+    let parent_id = calculate_tx_id(&parent);
+    let child = spend(parent_id, 80_000);
+    let child_id = calculate_tx_id(&child);
+
+    // The child entered the mempool while its parent was confirmed
+    let mempool = MempoolManager::new();
+    let utxos = utxo_set(&[parent_id]);
+    assert!(mempool.add_transaction(child, &utxos).await.unwrap());
+    let entry_time = mempool.entry_time(&child_id).unwrap();
+
+    let disconnected = block(10, vec![parent]);
+    let update = mempool
+        .add_for_reorg(&[disconnected], &utxo_set(&[[1; 32]]))
+        .await
+        .unwrap();
+    assert_eq!(update.added, vec![parent_id]);
+    assert!(update.removed.is_empty());
+    // The coinbase stays out
+    assert_eq!(mempool.size(), 2);
+    assert_eq!(mempool.entry_time(&child_id), Some(entry_time));
+}
+
+#[tokio::test]
+async fn test_disconnected_transactions_rechecked_against_tip() {
+    let parent = spend([1; 32], 90_000);
+    let child = spend(calculate_tx_id(&parent), 80_000);
+    let child_id = calculate_tx_id(&child);
+    let mempool = MempoolManager::new();
+    let utxos = utxo_set(&[calculate_tx_id(&parent)]);
+    assert!(mempool.add_transaction(child, &utxos).await.unwrap());
+
+    // The parent's input is gone at the new tip, so neither comes back
+    let update = mempool
+        .add_for_reorg(&[block(10, vec![parent])], &UtxoSet::new())
+        .await
+        .unwrap();
+    assert!(update.added.is_empty());
+    assert_eq!(
+        update.removed,
+        vec![(child_id, MempoolRemovalReason::Reorg)]
+    );
+    assert_eq!(mempool.size(), 0);
+}
+
+#[tokio::test]
+async fn test_remove_for_reorg_drops_non_final_and_immature_spends() {
+    let mut time_locked = spend([1; 32], 90_000);
+    time_locked.lock_time = 200;
+    time_locked.inputs[0].sequence = 0;
+    let time_locked_child = spend(calculate_tx_id(&time_locked), 80_000);
+    let immature = spend([2; 32], 90_000);
+    let missing_input = spend([3; 32], 90_000);
+    let valid = spend([4; 32], 90_000);
+
+    let mempool = MempoolManager::new();
+    let old_tip = utxo_set(&[[1; 32], [2; 32], [3; 32], [4; 32]]);
+    for tx in [
+        time_locked.clone(),
+        time_locked_child.clone(),
+        immature.clone(),
+        missing_input.clone(),
+        valid.clone(),
+    ] {
+        assert!(mempool.add_transaction(tx, &old_tip).await.unwrap());
+    }
+
+    let mut utxos = utxo_set(&[[1; 32], [4; 32]]);
+    utxos.insert(
+        OutPoint {
+            hash: [2; 32],
+            index: 0,
+        },
+        coin(100, true),
+    );
+    let mut removed = mempool.remove_for_reorg(150, 0, &utxos);
+    removed.sort_by_key(|(txid, _)| *txid);
+
+    let mut expected: Vec<_> = [&time_locked, &time_locked_child, &immature, &missing_input]
+        .into_iter()
+        .map(|tx| (calculate_tx_id(tx), MempoolRemovalReason::Reorg))
+        .collect();
+    expected.sort_by_key(|(txid, _)| *txid);
+    assert_eq!(removed, expected);
+    assert_eq!(mempool.transaction_hashes(), vec![calculate_tx_id(&valid)]);
+
+    // Once the chain is past the lock time and the coinbase has matured,
+    // nothing more goes
+    assert!(mempool.remove_for_reorg(250, 0, &utxos).is_empty());
+}
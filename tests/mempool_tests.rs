@@ -6,7 +6,7 @@ use std::collections::HashMap;
 
 #[tokio::test]
 async fn test_mempool_stores_full_transactions() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
 
     // Create a test transaction
     let tx = Transaction {
@@ -27,7 +27,10 @@ async fn test_mempool_stores_full_transactions() {
     };
 
     // Add transaction
-    let added = mempool.add_transaction(tx.clone()).await.unwrap();
+    let added = mempool
+        .add_transaction(tx.clone(), &UtxoSet::new())
+        .await
+        .unwrap();
     assert!(added);
 
     // Verify we can retrieve it
@@ -40,7 +43,7 @@ async fn test_mempool_stores_full_transactions() {
 
 #[tokio::test]
 async fn test_mempool_get_prioritized_transactions() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
     let mut utxo_set: UtxoSet = HashMap::new();
 
     // Create UTXO for input
@@ -93,8 +96,14 @@ async fn test_mempool_get_prioritized_transactions() {
     };
 
     // Add both transactions
-    mempool.add_transaction(low_fee_tx.clone()).await.unwrap();
-    mempool.add_transaction(high_fee_tx.clone()).await.unwrap();
+    mempool
+        .add_transaction(low_fee_tx.clone(), &utxo_set)
+        .await
+        .unwrap();
+    mempool
+        .add_transaction(high_fee_tx.clone(), &utxo_set)
+        .await
+        .unwrap();
 
     // Get prioritized (should return high fee first)
     let prioritized = mempool.get_prioritized_transactions(10, &utxo_set);
@@ -118,7 +127,7 @@ async fn test_mempool_get_prioritized_transactions() {
 
 #[tokio::test]
 async fn test_mempool_remove_transaction() {
-    let mut mempool = MempoolManager::new();
+    let mempool = MempoolManager::new();
 
     let tx = Transaction {
         version: 1,
@@ -137,7 +146,10 @@ async fn test_mempool_remove_transaction() {
         lock_time: 0,
     };
 
-    mempool.add_transaction(tx.clone()).await.unwrap();
+    mempool
+        .add_transaction(tx.clone(), &UtxoSet::new())
+        .await
+        .unwrap();
     assert_eq!(mempool.size(), 1);
 
     use bllvm_protocol::mempool::calculate_tx_id;
@@ -146,3 +158,56 @@ async fn test_mempool_remove_transaction() {
     assert!(removed);
     assert_eq!(mempool.size(), 0);
 }
+
+#[tokio::test]
+async fn test_mempool_stores_fee_of_confirmed_and_mempool_inputs() {
+    use bllvm_protocol::block::calculate_tx_id;
+
+    let mempool = MempoolManager::new();
+    let coin = OutPoint {
+        hash: [3u8; 32],
+        index: 0,
+    };
+    let mut utxo_set: UtxoSet = HashMap::new();
+    utxo_set.insert(
+        coin.clone(),
+        UTXO {
+            value: 100_000,
+            script_pubkey: vec![0x51],
+            height: 1,
+            is_coinbase: false,
+        },
+    );
+
+    let spend = |prevout: OutPoint, value: i64| Transaction {
+        version: 2,
+        inputs: bllvm_protocol::tx_inputs![TransactionInput {
+            prevout,
+            script_sig: vec![],
+            sequence: 0xffffffff,
+        }],
+        outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+            value,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    };
+
+    // Spends the confirmed coin
+    let parent = spend(coin, 90_000);
+    let parent_id = calculate_tx_id(&parent);
+    // Spends the parent's output in the mempool
+    let child = spend(
+        OutPoint {
+            hash: parent_id,
+            index: 0,
+        },
+        85_000,
+    );
+    let child_id = calculate_tx_id(&child);
+    assert!(mempool.add_transaction(parent, &utxo_set).await.unwrap());
+    assert!(mempool.add_transaction(child, &utxo_set).await.unwrap());
+
+    assert_eq!(mempool.entry_feefrac(&parent_id).unwrap().fee, 10_000);
+    assert_eq!(mempool.entry_feefrac(&child_id).unwrap().fee, 5_000);
+}
@@ -1,7 +1,7 @@
 //! Node orchestration tests
 
 use bllvm_node::node::*;
-use bllvm_node::{OutPoint, Transaction, TransactionInput, TransactionOutput};
+use bllvm_node::{OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoSet};
 use std::net::SocketAddr;
 use tempfile::TempDir;
 mod common;
@@ -48,7 +48,7 @@ async fn test_sync_coordinator() {
 
 #[tokio::test]
 async fn test_mempool_manager() {
-    let mut mempool = mempool::MempoolManager::new();
+    let mempool = mempool::MempoolManager::new();
 
     // Test initial state
     assert_eq!(mempool.size(), 0);
@@ -63,7 +63,7 @@ async fn test_mempool_manager() {
         lock_time: 0,
     };
 
-    let result = mempool.add_transaction(tx).await.unwrap();
+    let result = mempool.add_transaction(tx, &UtxoSet::new()).await.unwrap();
     assert!(result); // Simplified implementation always returns true
 }
 
@@ -310,7 +310,7 @@ async fn test_sync_coordinator_stalled_detection() {
 
 #[tokio::test]
 async fn test_mempool_manager_operations() {
-    let mut mempool = mempool::MempoolManager::new();
+    let mempool = mempool::MempoolManager::new();
 
     // Test initial state
     assert_eq!(mempool.size(), 0);
@@ -336,8 +336,8 @@ async fn test_mempool_manager_operations() {
         lock_time: 1, // Different lock time
     };
 
-    let result1 = mempool.add_transaction(tx1).await.unwrap();
-    let result2 = mempool.add_transaction(tx2).await.unwrap();
+    let result1 = mempool.add_transaction(tx1, &UtxoSet::new()).await.unwrap();
+    let result2 = mempool.add_transaction(tx2, &UtxoSet::new()).await.unwrap();
 
     assert!(result1);
     assert!(result2);
@@ -350,7 +350,7 @@ async fn test_mempool_manager_operations() {
 
 #[tokio::test]
 async fn test_mempool_manager_eviction() {
-    let mut mempool = mempool::MempoolManager::new();
+    let mempool = mempool::MempoolManager::new();
 
     // Add many transactions to test eviction
     for i in 0..100 {
@@ -362,7 +362,7 @@ async fn test_mempool_manager_eviction() {
             .add_output(1000, p2pkh_script(random_hash20()))
             .build();
 
-        mempool.add_transaction(tx).await.unwrap();
+        mempool.add_transaction(tx, &UtxoSet::new()).await.unwrap();
     }
 
     // Test that mempool size is within limits
@@ -371,7 +371,7 @@ async fn test_mempool_manager_eviction() {
 
 #[tokio::test]
 async fn test_mempool_manager_fee_prioritization() {
-    let mut mempool = mempool::MempoolManager::new();
+    let mempool = mempool::MempoolManager::new();
 
     // Test fee-based prioritization
     let high_fee_tx = TestTransactionBuilder::new()
@@ -390,8 +390,14 @@ async fn test_mempool_manager_fee_prioritization() {
         .add_output(1000, p2pkh_script(random_hash20()))
         .build();
 
-    mempool.add_transaction(high_fee_tx).await.unwrap();
-    mempool.add_transaction(low_fee_tx).await.unwrap();
+    mempool
+        .add_transaction(high_fee_tx, &UtxoSet::new())
+        .await
+        .unwrap();
+    mempool
+        .add_transaction(low_fee_tx, &UtxoSet::new())
+        .await
+        .unwrap();
 
     // Test that high-fee transactions are prioritized
     // Test get_prioritized_transactions (simplified - actual method may not exist)
@@ -402,7 +408,7 @@ async fn test_mempool_manager_fee_prioritization() {
 
 #[tokio::test]
 async fn test_mempool_manager_conflict_detection() {
-    let mut mempool = mempool::MempoolManager::new();
+    let mempool = mempool::MempoolManager::new();
 
     // Test conflict detection
     let outpoint = OutPoint {
@@ -421,10 +427,10 @@ async fn test_mempool_manager_conflict_detection() {
         .build();
 
     // Add first transaction
-    mempool.add_transaction(tx1).await.unwrap();
+    mempool.add_transaction(tx1, &UtxoSet::new()).await.unwrap();
 
     // Add conflicting transaction
-    let result = mempool.add_transaction(tx2).await.unwrap();
+    let result = mempool.add_transaction(tx2, &UtxoSet::new()).await.unwrap();
     assert!(!result); // Should be rejected due to conflict
 }
 
@@ -555,7 +561,7 @@ async fn test_mining_coordinator_mining_state() {
 #[tokio::test]
 async fn test_sync_mempool_interaction() {
     let sync = sync::SyncCoordinator::new();
-    let mut mempool = mempool::MempoolManager::new();
+    let mempool = mempool::MempoolManager::new();
 
     // Test interaction between sync and mempool
     // Test set_state (simplified - actual method may not exist)
@@ -565,7 +571,7 @@ async fn test_sync_mempool_interaction() {
 
     // When synced, mempool should accept transactions
     let tx = valid_transaction();
-    let result = mempool.add_transaction(tx).await.unwrap();
+    let result = mempool.add_transaction(tx, &UtxoSet::new()).await.unwrap();
     assert!(result);
 }
 
@@ -574,7 +580,7 @@ async fn test_mining_mempool_interaction() {
     use std::sync::Arc;
     let mempool = Arc::new(bllvm_node::node::mempool::MempoolManager::new());
     let mut miner = miner::MiningCoordinator::new(mempool, None);
-    let mut mempool = mempool::MempoolManager::new();
+    let mempool = mempool::MempoolManager::new();
 
     // Test interaction between mining and mempool
     miner.enable_mining();
@@ -582,7 +588,7 @@ async fn test_mining_mempool_interaction() {
 
     // Add transactions to mempool
     let tx = valid_transaction();
-    mempool.add_transaction(tx).await.unwrap();
+    mempool.add_transaction(tx, &UtxoSet::new()).await.unwrap();
 
     // Mining should be able to select transactions
     // Test select_transactions (simplified - actual method may not exist)
@@ -639,7 +645,7 @@ async fn test_mempool_processing_workflow() {
 
     // Add a transaction
     let tx = valid_transaction();
-    let result = mempool.add_transaction(tx).await;
+    let result = mempool.add_transaction(tx, &UtxoSet::new()).await;
     assert!(result.is_ok());
     assert_eq!(mempool.size(), 1);
 
@@ -659,8 +665,8 @@ async fn test_mempool_cleanup_workflow() {
     let tx1 = unique_transaction();
     let tx2 = unique_transaction();
 
-    mempool.add_transaction(tx1).await.unwrap();
-    mempool.add_transaction(tx2).await.unwrap();
+    mempool.add_transaction(tx1, &UtxoSet::new()).await.unwrap();
+    mempool.add_transaction(tx2, &UtxoSet::new()).await.unwrap();
 
     assert_eq!(mempool.size(), 2);
 
@@ -717,3 +723,192 @@ async fn test_node_health_check() {
     let result = node.run_once().await;
     assert!(result.is_ok());
 }
+
+/// Create a regtest node in `dir`
+fn regtest_node(dir: &TempDir) -> Node {
+    let network_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let rpc_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    Node::new(
+        dir.path().to_str().unwrap(),
+        network_addr,
+        rpc_addr,
+        Some(ProtocolVersion::Regtest),
+    )
+    .unwrap()
+}
+
+/// Connect `count` blocks from genesis, returning the tip and the coinbase
+/// outputs
+async fn connect_chain(node: &mut Node, count: u64) -> ([u8; 32], Vec<OutPoint>) {
+    use bllvm_protocol::block::calculate_tx_id;
+
+    let mut prev = [0u8; 32];
+    let mut coinbases = Vec::new();
+    for height in 0..count {
+        let block = mine_regtest_block(prev, height, 0);
+        let outcome = node.process_block(&serialize_block(&block)).await.unwrap();
+        assert!(matches!(
+            outcome,
+            sync::BlockProcessOutcome::Connected { .. }
+        ));
+        prev = node.storage().blocks().get_block_hash(&block);
+        coinbases.push(OutPoint {
+            hash: calculate_tx_id(&block.transactions[0]),
+            index: 0,
+        });
+    }
+    (prev, coinbases)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_connected_block_removes_mempool_transactions() {
+    use bllvm_protocol::block::calculate_tx_id;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut node = regtest_node(&temp_dir);
+
+    // Mature the first coinbases
+    let (prev, coinbases) = connect_chain(&mut node, 101).await;
+
+    // One transaction the block confirms, one it conflicts with and one
+    // unrelated to it
+    let confirmed = spend(coinbases[0].clone(), 49_0000_0000);
+    let conflicted = spend(coinbases[1].clone(), 49_0000_0000);
+    let unrelated = spend(coinbases[2].clone(), 49_0000_0000);
+    for tx in [&confirmed, &conflicted, &unrelated] {
+        assert!(node
+            .mempool()
+            .add_transaction(tx.clone(), &UtxoSet::new())
+            .await
+            .unwrap());
+    }
+    assert_eq!(node.mempool().size(), 3);
+
+    let double_spend = spend(coinbases[1].clone(), 48_0000_0000);
+    let block = mine_regtest_block_with(prev, 101, 0, vec![confirmed, double_spend]);
+    let outcome = node.process_block(&serialize_block(&block)).await.unwrap();
+    assert!(matches!(
+        outcome,
+        sync::BlockProcessOutcome::Connected { .. }
+    ));
+
+    assert_eq!(node.mempool().size(), 1);
+    assert!(!node.mempool().contains(&calculate_tx_id(&conflicted)));
+    assert!(node.mempool().contains(&calculate_tx_id(&unrelated)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_connected_block_accepts_orphans() {
+    use bllvm_protocol::block::calculate_tx_id;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut node = regtest_node(&temp_dir);
+    let (prev, coinbases) = connect_chain(&mut node, 101).await;
+
+    // The child arrives before its parent is confirmed
+    let parent = spend(coinbases[0].clone(), 49_0000_0000);
+    let child = spend(
+        OutPoint {
+            hash: calculate_tx_id(&parent),
+            index: 0,
+        },
+        48_0000_0000,
+    );
+    assert!(node.mempool().add_orphan(child.clone(), None));
+
+    let block = mine_regtest_block_with(prev, 101, 0, vec![parent]);
+    let outcome = node.process_block(&serialize_block(&block)).await.unwrap();
+    assert!(matches!(
+        outcome,
+        sync::BlockProcessOutcome::Connected { .. }
+    ));
+
+    assert_eq!(node.mempool().orphan_count(), 0);
+    assert!(node.mempool().contains(&calculate_tx_id(&child)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_side_branch_block_with_bad_header_is_not_stored() {
+    use bllvm_protocol::pow::check_proof_of_work;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut node = regtest_node(&temp_dir);
+    connect_chain(&mut node, 3).await;
+    let fork = node
+        .storage()
+        .blocks()
+        .get_hash_by_height(0)
+        .unwrap()
+        .unwrap();
+
+    // A branch block whose hash misses its target
+    let mut bad_pow = mine_regtest_block(fork, 1, 1);
+    while check_proof_of_work(&bad_pow.header).unwrap() {
+        bad_pow.header.nonce += 1;
+    }
+
+    // A branch block timestamped before its ancestors' median time
+    let mut too_old = mine_regtest_block(fork, 1, 2);
+    too_old.header.timestamp = 0;
+    while !check_proof_of_work(&too_old.header).unwrap() {
+        too_old.header.nonce += 1;
+    }
+
+    for block in [bad_pow, too_old] {
+        let outcome = node.process_block(&serialize_block(&block)).await.unwrap();
+        assert!(matches!(outcome, sync::BlockProcessOutcome::Rejected));
+
+        let hash = node.storage().blocks().get_block_hash(&block);
+        assert!(node.storage().blocks().get_block(&hash).unwrap().is_none());
+        assert!(node.storage().block_index().get(&hash).unwrap().is_none());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_network_transactions_checked_against_consensus() {
+    use bllvm_node::network::protocol::{ProtocolMessage, ProtocolParser, TxMessage};
+    use bllvm_protocol::block::calculate_tx_id;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut node = regtest_node(&temp_dir);
+    let (_, coinbases) = connect_chain(&mut node, 101).await;
+    let peer: SocketAddr = "127.0.0.1:18444".parse().unwrap();
+    let relay = |tx: &Transaction| {
+        ProtocolParser::serialize_message(&ProtocolMessage::Tx(TxMessage {
+            transaction: tx.clone(),
+        }))
+        .unwrap()
+    };
+
+    let valid = spend(coinbases[0].clone(), 49_0000_0000);
+    let _ = node
+        .network()
+        .handle_incoming_wire_tcp(peer, relay(&valid))
+        .await;
+    assert!(node.mempool().contains(&calculate_tx_id(&valid)));
+
+    // Its scriptSig fails its own OP_VERIFY
+    let mut bad_script = spend(coinbases[1].clone(), 49_0000_0000);
+    bad_script.inputs[0].script_sig = vec![0x00, 0x69];
+    // Spends an output its mempool parent does not have
+    let missing_input = spend(
+        OutPoint {
+            hash: calculate_tx_id(&valid),
+            index: 5,
+        },
+        48_0000_0000,
+    );
+    for tx in [bad_script, missing_input] {
+        let _ = node
+            .network()
+            .handle_incoming_wire_tcp(peer, relay(&tx))
+            .await;
+        assert!(!node.mempool().contains(&calculate_tx_id(&tx)));
+    }
+    assert_eq!(node.mempool().size(), 1);
+    assert_eq!(node.mempool().orphan_count(), 0);
+}
@@ -0,0 +1,212 @@
+//! Tests for the orphan transaction pool
+
+use bllvm_node::config::MempoolPolicyConfig;
+use bllvm_node::node::mempool::{MempoolManager, TxSubmission};
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{
+    Hash, OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoSet, UTXO,
+};
+use std::net::SocketAddr;
+
+fn spend(prev_hash: Hash, value: u64) -> Transaction {
+    Transaction {
+        version: 2,
+        inputs: bllvm_protocol::tx_inputs![TransactionInput {
+            prevout: OutPoint {
+                hash: prev_hash,
+                index: 0,
+            },
+            script_sig: vec![],
+            sequence: 0xffffffff,
+        }],
+        outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+            value: value as i64,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    }
+}
+
+fn utxo_set(coins: &[Hash]) -> UtxoSet {
+    coins
+        .iter()
+        .map(|hash| {
+            (
+                OutPoint {
+                    hash: *hash,
+                    index: 0,
+                },
+                UTXO {
+                    value: 100_000,
+                    script_pubkey: vec![0x51],
+                    height: 1,
+                    is_coinbase: false,
+                },
+            )
+        })
+        .collect()
+}
+
+fn peer(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+#[tokio::test]
+async fn test_orphans_accepted_when_parent_arrives() {
+    let utxos = utxo_set(&[[1; 32]]);
+    let parent = spend([1; 32], 90_000);
+    let child = spend(calculate_tx_id(&parent), 80_000);
+    let grandchild = spend(calculate_tx_id(&child), 70_000);
+    let mempool = MempoolManager::new();
+
+    for tx in [grandchild.clone(), child.clone()] {
+        let submission = mempool
+            .submit_transaction(tx, &utxos, 2, Some(peer(1)))
+            .await
+            .unwrap();
+        assert!(matches!(submission, TxSubmission::Orphan { .. }));
+    }
+    assert_eq!(mempool.orphan_count(), 2);
+    assert!(mempool.orphan_bytes() > 0);
+    assert_eq!(mempool.size(), 0);
+
+    let submission = mempool
+        .submit_transaction(parent, &utxos, 2, None)
+        .await
+        .unwrap();
+    let TxSubmission::Accepted { reprocessed } = submission else {
+        panic!("parent not accepted: {submission:?}");
+    };
+    assert_eq!(
+        reprocessed,
+        vec![calculate_tx_id(&child), calculate_tx_id(&grandchild)]
+    );
+    assert_eq!(mempool.size(), 3);
+    assert_eq!(mempool.orphan_count(), 0);
+    assert_eq!(mempool.orphan_bytes(), 0);
+}
+
+#[tokio::test]
+async fn test_orphan_pool_limits() {
+    let mempool = MempoolManager::new();
+    mempool.set_policy_config(Some(MempoolPolicyConfig {
+        max_orphan_txs: 5,
+        max_orphans_per_peer: 2,
+        ..Default::default()
+    }));
+
+    // Already known
+    let known = spend([0; 32], 90_000);
+    assert!(mempool.add_orphan(known.clone(), Some(peer(1))));
+    assert!(!mempool.add_orphan(known, Some(peer(1))));
+
+    for i in 1..4u8 {
+        assert!(mempool.add_orphan(spend([i; 32], 90_000), Some(peer(1))));
+    }
+    assert_eq!(mempool.orphan_count(), 2);
+
+    for i in 0..3u8 {
+        assert!(mempool.add_orphan(spend([i + 10; 32], 90_000), Some(peer(i as u16 + 2))));
+    }
+    assert_eq!(mempool.orphan_count(), 5);
+
+    // A random orphan, possibly the new one, makes room
+    mempool.add_orphan(spend([13; 32], 90_000), Some(peer(5)));
+    assert_eq!(mempool.orphan_count(), 5);
+
+    // Too large to keep
+    let mut large = spend([20; 32], 90_000);
+    large.outputs[0].script_pubkey = vec![0x51; 100_000];
+    assert!(!mempool.add_orphan(large, None));
+    assert_eq!(mempool.orphan_count(), 5);
+}
+
+#[tokio::test]
+async fn test_orphans_removed_for_peer_and_block() {
+    let mempool = MempoolManager::new();
+
+    let from_peer = spend([9; 32], 90_000);
+    assert!(mempool.add_orphan(from_peer, Some(peer(1))));
+    assert_eq!(mempool.remove_orphans_for_peer(peer(2)), 0);
+    assert_eq!(mempool.remove_orphans_for_peer(peer(1)), 1);
+    assert_eq!(mempool.orphan_count(), 0);
+
+    // One orphan conflicts with the block, the other spends it
+    let confirmed = spend([1; 32], 90_000);
+    let conflicting = Transaction {
+        inputs: bllvm_protocol::tx_inputs![
+            TransactionInput {
+                prevout: OutPoint {
+                    hash: [1; 32],
+                    index: 0,
+                },
+                script_sig: vec![],
+                sequence: 0xffffffff,
+            },
+            TransactionInput {
+                prevout: OutPoint {
+                    hash: [8; 32],
+                    index: 0,
+                },
+                script_sig: vec![],
+                sequence: 0xffffffff,
+            }
+        ],
+        ..spend([1; 32], 80_000)
+    };
+    let child = spend(calculate_tx_id(&confirmed), 80_000);
+    assert!(mempool.add_orphan(conflicting.clone(), Some(peer(1))));
+    assert!(mempool.add_orphan(child.clone(), Some(peer(1))));
+
+    let utxos = utxo_set(&[calculate_tx_id(&confirmed)]);
+    let accepted = mempool
+        .process_orphans_for_block(&[confirmed], &utxos, 2)
+        .await
+        .unwrap();
+    assert_eq!(accepted, vec![calculate_tx_id(&child)]);
+    assert!(!mempool.has_orphan(&calculate_tx_id(&conflicting)));
+    assert_eq!(mempool.orphan_count(), 0);
+    assert_eq!(mempool.size(), 1);
+}
+
+#[tokio::test]
+async fn test_submitted_transactions_checked_against_consensus() {
+    let utxos = utxo_set(&[[1; 32], [2; 32]]);
+    let mempool = MempoolManager::new();
+
+    // Its scriptSig fails its own OP_VERIFY
+    let mut bad_script = spend([1; 32], 90_000);
+    bad_script.inputs[0].script_sig = vec![0x00, 0x69];
+    let submission = mempool
+        .submit_transaction(bad_script, &utxos, 2, None)
+        .await
+        .unwrap();
+    assert!(matches!(submission, TxSubmission::Rejected));
+
+    // Spends an output its mempool parent does not have
+    let parent = spend([2; 32], 90_000);
+    let submission = mempool
+        .submit_transaction(parent.clone(), &utxos, 2, None)
+        .await
+        .unwrap();
+    assert!(matches!(submission, TxSubmission::Accepted { .. }));
+    let mut missing_output = spend(calculate_tx_id(&parent), 80_000);
+    missing_output.inputs[0].prevout.index = 5;
+    let submission = mempool
+        .submit_transaction(missing_output, &utxos, 2, None)
+        .await
+        .unwrap();
+    assert!(matches!(submission, TxSubmission::Rejected));
+
+    // An orphan is checked once its parent arrives
+    let mut bad_orphan = spend(calculate_tx_id(&parent), 80_000);
+    bad_orphan.inputs[0].script_sig = vec![0x00, 0x69];
+    assert!(mempool.add_orphan(bad_orphan, None));
+    let accepted = mempool
+        .process_orphans(&[calculate_tx_id(&parent)], &utxos, 2)
+        .await
+        .unwrap();
+    assert!(accepted.is_empty());
+    assert_eq!(mempool.orphan_count(), 0);
+    assert_eq!(mempool.transaction_hashes(), vec![calculate_tx_id(&parent)]);
+}
@@ -0,0 +1,172 @@
+//! Tests for package acceptance to the mempool (CPFP and package RBF)
+
+use bllvm_node::node::mempool::MempoolManager;
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{
+    Hash, OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoSet, UTXO,
+};
+
+/// Sequence number signalling BIP125 replaceability
+const RBF_SEQUENCE: u64 = 0xfffffffd;
+
+fn spend(prev_hash: Hash, value: u64, sequence: u64) -> Transaction {
+    Transaction {
+        version: 2,
+        inputs: bllvm_protocol::tx_inputs![TransactionInput {
+            prevout: OutPoint {
+                hash: prev_hash,
+                index: 0,
+            },
+            script_sig: vec![],
+            sequence,
+        }],
+        outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+            value: value as i64,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    }
+}
+
+fn utxo_set(coins: &[(Hash, u64)]) -> UtxoSet {
+    coins
+        .iter()
+        .map(|(hash, value)| {
+            (
+                OutPoint {
+                    hash: *hash,
+                    index: 0,
+                },
+                UTXO {
+                    value: *value as i64,
+                    script_pubkey: vec![0x51],
+                    height: 1,
+                    is_coinbase: false,
+                },
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_child_pays_for_zero_fee_parent() {
+    let utxos = utxo_set(&[([1; 32], 100_000)]);
+    let parent = spend([1; 32], 100_000, 0xffffffff);
+    let child = spend(calculate_tx_id(&parent), 90_000, 0xffffffff);
+    let mempool = MempoolManager::new();
+
+    // Alone, the parent pays nothing
+    let alone = mempool.check_package(std::slice::from_ref(&parent), &utxos);
+    assert!(alone.reject_reason.is_none());
+    assert!(!alone.all_allowed());
+    assert_eq!(
+        alone.tx_results[0].reject_reason.as_deref(),
+        Some("min relay fee not met")
+    );
+
+    let acceptance = mempool
+        .add_package(vec![parent.clone(), child.clone()], &utxos)
+        .await
+        .unwrap();
+    assert!(acceptance.all_allowed(), "{acceptance:?}");
+    assert_eq!(acceptance.tx_results[0].fee, Some(0));
+    assert_eq!(acceptance.tx_results[1].fee, Some(10_000));
+    assert!(acceptance.package_feerate.is_some());
+    assert_eq!(mempool.size(), 2);
+    assert!(mempool.get_transaction(&calculate_tx_id(&parent)).is_some());
+
+    // Submitting it again changes nothing
+    let again = mempool
+        .add_package(vec![parent, child], &utxos)
+        .await
+        .unwrap();
+    assert!(again.tx_results.iter().all(|r| r.already_in_mempool));
+    assert_eq!(mempool.size(), 2);
+}
+
+#[tokio::test]
+async fn test_package_shape_and_inputs_checked() {
+    let utxos = utxo_set(&[([1; 32], 100_000), ([2; 32], 100_000)]);
+    let mempool = MempoolManager::new();
+
+    let unrelated = [
+        spend([1; 32], 90_000, 0xffffffff),
+        spend([2; 32], 90_000, 0xffffffff),
+    ];
+    let acceptance = mempool.check_package(&unrelated, &utxos);
+    assert_eq!(
+        acceptance.reject_reason.as_deref(),
+        Some("package-not-child-with-parents")
+    );
+
+    let parent = spend([3; 32], 90_000, 0xffffffff);
+    let child = spend(calculate_tx_id(&parent), 80_000, 0xffffffff);
+    let acceptance = mempool.check_package(&[parent, child], &utxos);
+    assert_eq!(
+        acceptance.reject_reason.as_deref(),
+        Some("package-missing-inputs")
+    );
+    assert!(acceptance.tx_results.iter().all(|r| !r.allowed));
+}
+
+#[tokio::test]
+async fn test_package_replaces_conflicting_transaction() {
+    let utxos = utxo_set(&[([1; 32], 100_000)]);
+    let original = spend([1; 32], 99_000, RBF_SEQUENCE);
+    let original_id = calculate_tx_id(&original);
+    let mempool = MempoolManager::new();
+    assert!(mempool.add_transaction(original, &utxos).await.unwrap());
+
+    // Too little to pay for the replaced transaction and the relay
+    let parent = spend([1; 32], 100_000, RBF_SEQUENCE);
+    let cheap_child = spend(calculate_tx_id(&parent), 99_500, 0xffffffff);
+    let acceptance = mempool
+        .add_package(vec![parent.clone(), cheap_child], &utxos)
+        .await
+        .unwrap();
+    assert_eq!(
+        acceptance.reject_reason.as_deref(),
+        Some("insufficient fee")
+    );
+    assert!(mempool.get_transaction(&original_id).is_some());
+
+    let child = spend(calculate_tx_id(&parent), 80_000, 0xffffffff);
+    let acceptance = mempool
+        .add_package(vec![parent.clone(), child.clone()], &utxos)
+        .await
+        .unwrap();
+    assert!(acceptance.all_allowed(), "{acceptance:?}");
+    assert_eq!(acceptance.replaced, vec![original_id]);
+    assert!(mempool.get_transaction(&original_id).is_none());
+    assert!(mempool.get_transaction(&calculate_tx_id(&child)).is_some());
+    assert_eq!(mempool.size(), 2);
+}
+
+#[tokio::test]
+async fn test_submitpackage_adds_package_to_mempool() {
+    use bllvm_node::rpc::rawtx::RawTxRpc;
+    use bllvm_node::storage::Storage;
+    use bllvm_protocol::serialization::transaction::serialize_transaction;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let storage = Arc::new(Storage::new(temp_dir.path()).unwrap());
+    for (outpoint, utxo) in utxo_set(&[([1; 32], 100_000)]) {
+        storage.utxo_cache().add_utxo(&outpoint, &utxo).unwrap();
+    }
+    let mempool = Arc::new(MempoolManager::new());
+    let rpc = RawTxRpc::with_dependencies(storage, Arc::clone(&mempool), None, None);
+
+    let parent = spend([1; 32], 100_000, 0xffffffff);
+    let child = spend(calculate_tx_id(&parent), 90_000, 0xffffffff);
+    let rawtxs: Vec<String> = [&parent, &child]
+        .iter()
+        .map(|tx| hex::encode(serialize_transaction(tx)))
+        .collect();
+    let result = rpc.submitpackage(&json!([rawtxs])).await.unwrap();
+
+    assert_eq!(result["package_msg"], "success");
+    assert_eq!(mempool.size(), 2);
+    assert!(mempool.contains(&calculate_tx_id(&child)));
+}
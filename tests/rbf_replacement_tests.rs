@@ -0,0 +1,248 @@
+//! Tests for replacement evaluation against the whole mempool
+
+use bllvm_node::config::RbfConfig;
+use bllvm_node::node::mempool::{MempoolManager, Replacement};
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{
+    Hash, OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoSet, UTXO,
+};
+
+fn input(prev_hash: Hash) -> TransactionInput {
+    TransactionInput {
+        prevout: OutPoint {
+            hash: prev_hash,
+            index: 0,
+        },
+        script_sig: vec![],
+        sequence: 0xffffffff,
+    }
+}
+
+fn spend(prev_hash: Hash, value: u64) -> Transaction {
+    Transaction {
+        version: 2,
+        inputs: bllvm_protocol::tx_inputs![input(prev_hash)],
+        outputs: bllvm_protocol::tx_outputs![TransactionOutput {
+            value: value as i64,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    }
+}
+
+fn utxo_set(coins: &[Hash]) -> UtxoSet {
+    coins
+        .iter()
+        .map(|hash| {
+            (
+                OutPoint {
+                    hash: *hash,
+                    index: 0,
+                },
+                UTXO {
+                    value: 100_000,
+                    script_pubkey: vec![0x51],
+                    height: 1,
+                    is_coinbase: false,
+                },
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_replacement_pays_for_evicted_descendants() {
+    let utxos = utxo_set(&[[1; 32]]);
+    let original = spend([1; 32], 90_000);
+    let child = spend(calculate_tx_id(&original), 80_000);
+    let mempool = MempoolManager::new();
+    assert!(mempool
+        .add_transaction(original.clone(), &utxos)
+        .await
+        .unwrap());
+    assert!(mempool
+        .add_transaction(child.clone(), &utxos)
+        .await
+        .unwrap());
+
+    // Outbids the original's fee rate, but not the original and its child
+    let cheap = spend([1; 32], 85_000);
+    assert_eq!(
+        mempool.check_replacement(&cheap, &utxos, None),
+        Err("insufficient fee")
+    );
+
+    let replacement = spend([1; 32], 60_000);
+    let mut replaced = vec![calculate_tx_id(&original), calculate_tx_id(&child)];
+    replaced.sort();
+    assert_eq!(
+        mempool.check_replacement(&replacement, &utxos, None),
+        Ok(Replacement {
+            conflicts: vec![calculate_tx_id(&original)],
+            replaced,
+        })
+    );
+
+    // Without conflicts nothing is evicted
+    let unrelated = spend([2; 32], 90_000);
+    assert_eq!(
+        mempool.check_replacement(&unrelated, &utxo_set(&[[2; 32]]), None),
+        Ok(Replacement::default())
+    );
+}
+
+#[tokio::test]
+async fn test_full_rbf_replaces_non_signalling_transactions() {
+    let utxos = utxo_set(&[[1; 32]]);
+    let mempool = MempoolManager::new();
+    // Final sequence: does not signal BIP125 replaceability
+    assert!(mempool
+        .add_transaction(spend([1; 32], 90_000), &utxos)
+        .await
+        .unwrap());
+    let replacement = spend([1; 32], 70_000);
+    assert!(mempool
+        .check_replacement(&replacement, &utxos, None)
+        .is_ok());
+
+    mempool.set_rbf_config(Some(RbfConfig {
+        full_rbf: false,
+        ..RbfConfig::default()
+    }));
+    assert_eq!(
+        mempool.check_replacement(&replacement, &utxos, None),
+        Err("txn-mempool-conflict")
+    );
+}
+
+#[tokio::test]
+async fn test_replacement_rejects_new_unconfirmed_inputs() {
+    let utxos = utxo_set(&[[1; 32], [2; 32]]);
+    let original = spend([1; 32], 90_000);
+    let other = spend([2; 32], 90_000);
+    let mempool = MempoolManager::new();
+    assert!(mempool
+        .add_transaction(original.clone(), &utxos)
+        .await
+        .unwrap());
+    assert!(mempool
+        .add_transaction(other.clone(), &utxos)
+        .await
+        .unwrap());
+
+    let mut replacement = spend([1; 32], 50_000);
+    replacement.inputs.push(input(calculate_tx_id(&other)));
+    assert_eq!(
+        mempool.check_replacement(&replacement, &utxos, None),
+        Err("replacement-adds-unconfirmed")
+    );
+
+    // Nor may it spend what it evicts
+    let mut spends_conflict = spend(calculate_tx_id(&original), 50_000);
+    spends_conflict.inputs.push(input([1; 32]));
+    assert_eq!(
+        mempool.check_replacement(&spends_conflict, &utxos, None),
+        Err("bad-txns-spends-conflicting-tx")
+    );
+}
+
+#[tokio::test]
+async fn test_replacement_eviction_cap() {
+    let coins: Vec<Hash> = (1..=101u8).map(|i| [i; 32]).collect();
+    let utxos = utxo_set(&coins);
+    let mempool = MempoolManager::new();
+    for coin in &coins {
+        assert!(mempool
+            .add_transaction(spend(*coin, 99_000), &utxos)
+            .await
+            .unwrap());
+    }
+
+    let mut replacement = spend(coins[0], 100_000);
+    for coin in &coins[1..] {
+        replacement.inputs.push(input(*coin));
+    }
+    assert_eq!(
+        mempool.check_replacement(&replacement, &utxos, None),
+        Err("too many potential replacements")
+    );
+}
+
+#[tokio::test]
+async fn test_add_transaction_replaces_conflicts_that_it_outbids() {
+    let utxos = utxo_set(&[[1; 32]]);
+    let original = spend([1; 32], 90_000);
+    let child = spend(calculate_tx_id(&original), 80_000);
+    let mempool = MempoolManager::new();
+    assert!(mempool
+        .add_transaction(original.clone(), &utxos)
+        .await
+        .unwrap());
+    assert!(mempool
+        .add_transaction(child.clone(), &utxos)
+        .await
+        .unwrap());
+
+    // Does not pay for the original and its child
+    let cheap = spend([1; 32], 85_000);
+    assert!(!mempool
+        .add_transaction(cheap.clone(), &utxos)
+        .await
+        .unwrap());
+    assert!(mempool.contains(&calculate_tx_id(&original)));
+    assert!(mempool.contains(&calculate_tx_id(&child)));
+    assert!(!mempool.contains(&calculate_tx_id(&cheap)));
+
+    let replacement = spend([1; 32], 60_000);
+    assert!(mempool
+        .add_transaction(replacement.clone(), &utxos)
+        .await
+        .unwrap());
+    assert_eq!(
+        mempool.transaction_hashes(),
+        vec![calculate_tx_id(&replacement)]
+    );
+    assert_eq!(
+        mempool
+            .entry_feefrac(&calculate_tx_id(&replacement))
+            .unwrap()
+            .fee,
+        40_000
+    );
+}
+
+#[tokio::test]
+async fn test_add_transaction_respects_full_rbf_setting() {
+    let utxos = utxo_set(&[[1; 32]]);
+    let original = spend([1; 32], 90_000);
+    let replacement = spend([1; 32], 70_000);
+    let mempool = MempoolManager::new();
+    mempool.set_rbf_config(Some(RbfConfig {
+        full_rbf: false,
+        ..RbfConfig::default()
+    }));
+
+    // The original does not signal replaceability
+    assert!(mempool
+        .add_transaction(original.clone(), &utxos)
+        .await
+        .unwrap());
+    assert!(!mempool
+        .add_transaction(replacement.clone(), &utxos)
+        .await
+        .unwrap());
+    assert_eq!(
+        mempool.transaction_hashes(),
+        vec![calculate_tx_id(&original)]
+    );
+
+    mempool.set_rbf_config(Some(RbfConfig::default()));
+    assert!(mempool
+        .add_transaction(replacement.clone(), &utxos)
+        .await
+        .unwrap());
+    assert_eq!(
+        mempool.transaction_hashes(),
+        vec![calculate_tx_id(&replacement)]
+    );
+}
@@ -0,0 +1,187 @@
+//! Tests for reindex and reindex-chainstate on regtest chains
+
+use bllvm_node::config::{ReindexConfig, StorageConfig};
+use bllvm_node::node::metrics::MetricsCollector;
+use bllvm_node::storage::blockindex::BlockStatus;
+use bllvm_node::storage::database::default_backend;
+use bllvm_node::storage::reindex::ReindexMode;
+use bllvm_node::storage::Storage;
+use bllvm_node::OutPoint;
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{BitcoinProtocolEngine, ProtocolVersion};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+mod common;
+use common::{connect_regtest_chain, create_test_storage, mine_regtest_block};
+
+#[test]
+fn test_reindex_chainstate_rebuilds_utxo_set() {
+    let (_temp_dir, storage) = create_test_storage();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let chain = connect_regtest_chain(&storage, &protocol, 5);
+    let tip_hash = storage.chain().get_tip_hash().unwrap();
+
+    // Lose the UTXO set
+    storage.utxos().clear().unwrap();
+    storage.utxo_cache().reset().unwrap();
+    assert_eq!(storage.utxo_cache().utxo_count().unwrap(), 0);
+
+    let metrics = MetricsCollector::new();
+    let summary = storage
+        .reindex(ReindexMode::Chainstate, &protocol, Some(&metrics))
+        .unwrap();
+    assert_eq!(summary.blocks_connected, 5);
+    assert_eq!(summary.tip_height, Some(4));
+    assert!(summary.invalid_block.is_none());
+
+    assert_eq!(storage.chain().get_height().unwrap(), Some(4));
+    assert_eq!(storage.chain().get_tip_hash().unwrap(), tip_hash);
+    assert_eq!(storage.utxo_cache().utxo_count().unwrap(), 5);
+    for block in &chain {
+        let outpoint = OutPoint {
+            hash: calculate_tx_id(&block.transactions[0]),
+            index: 0,
+        };
+        assert!(storage.utxo_cache().has_utxo(&outpoint).unwrap());
+    }
+    // Startup replay finds nothing left to do
+    assert!(storage.check_consistency().unwrap().is_clean());
+
+    let progress = metrics.collect().storage.reindex.unwrap();
+    assert_eq!(progress.mode, "reindex-chainstate");
+    assert_eq!(progress.blocks_connected, 5);
+    assert_eq!(progress.target_height, Some(4));
+    assert!(progress.complete);
+}
+
+#[test]
+fn test_reindex_chainstate_requires_block_bodies() {
+    let (_temp_dir, storage) = create_test_storage();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let chain = connect_regtest_chain(&storage, &protocol, 3);
+    let hash = storage.blocks().get_block_hash(&chain[1]);
+    storage.blocks().remove_block_body(&hash).unwrap();
+
+    assert!(storage
+        .reindex(ReindexMode::Chainstate, &protocol, None)
+        .is_err());
+    // Nothing was cleared
+    assert_eq!(storage.chain().get_height().unwrap(), Some(2));
+    assert_eq!(storage.utxo_cache().utxo_count().unwrap(), 3);
+}
+
+#[test]
+fn test_reindex_rebuilds_indexes_from_block_bodies() {
+    let (_temp_dir, storage) = create_test_storage();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let chain = connect_regtest_chain(&storage, &protocol, 4);
+    let blocks = storage.blocks();
+
+    // A stale block whose body was stored but never connected
+    let stale = mine_regtest_block(blocks.get_block_hash(&chain[1]), 2, 1);
+    blocks.store_block(&stale).unwrap();
+    let stale_hash = blocks.get_block_hash(&stale);
+
+    // Corrupt the indexes
+    storage.transactions().clear().unwrap();
+    storage.block_index().clear().unwrap();
+    blocks.store_height(3, &stale_hash).unwrap();
+
+    let summary = storage.reindex(ReindexMode::Full, &protocol, None).unwrap();
+    assert_eq!(summary.blocks_indexed, 5);
+    assert_eq!(summary.blocks_connected, 4);
+    assert_eq!(summary.tip_height, Some(3));
+
+    for (height, block) in chain.iter().enumerate() {
+        let hash = blocks.get_block_hash(block);
+        assert_eq!(
+            blocks.get_hash_by_height(height as u64).unwrap(),
+            Some(hash)
+        );
+        let entry = storage.block_index().get(&hash).unwrap().unwrap();
+        assert!(entry.status.contains(BlockStatus::FULLY_VALID));
+        assert!(storage
+            .transactions()
+            .has_transaction(&calculate_tx_id(&block.transactions[0]))
+            .unwrap());
+    }
+    let stale_entry = storage.block_index().get(&stale_hash).unwrap().unwrap();
+    assert!(stale_entry.has_data());
+    assert!(!blocks.is_in_main_chain(&stale_hash).unwrap());
+    assert_eq!(storage.utxo_cache().utxo_count().unwrap(), 4);
+}
+
+#[test]
+fn test_old_block_hash_encoding_needs_full_reindex() {
+    let temp_dir = TempDir::new().unwrap();
+    let protocol = BitcoinProtocolEngine::new(ProtocolVersion::Regtest).unwrap();
+    let chain = {
+        let storage = Arc::new(Storage::with_backend(temp_dir.path(), default_backend()).unwrap());
+        let chain = connect_regtest_chain(&storage, &protocol, 3);
+
+        // Key the stored blocks the way databases from before the consensus
+        // hash encoding did: by hashes nothing else uses
+        for name in ["blocks", "headers", "block_metadata", "witnesses"] {
+            let tree = storage.open_tree(name).unwrap();
+            let entries: Vec<_> = tree.iter().map(|entry| entry.unwrap()).collect();
+            for (key, value) in entries {
+                let mut old_key = key.clone();
+                old_key[0] ^= 0xff;
+                tree.remove(&key).unwrap();
+                tree.insert(&old_key, &value).unwrap();
+            }
+        }
+        storage
+            .open_tree("chain_info")
+            .unwrap()
+            .remove(b"block_hash_version")
+            .unwrap();
+        storage.flush().unwrap();
+        chain
+    };
+
+    assert!(Storage::with_backend(temp_dir.path(), default_backend()).is_err());
+
+    let config = StorageConfig {
+        reindex: ReindexConfig::Reindex,
+        ..Default::default()
+    };
+    {
+        let storage = Storage::with_config(temp_dir.path(), default_backend(), &config).unwrap();
+        assert!(storage
+            .reindex(ReindexMode::Chainstate, &protocol, None)
+            .is_err());
+        let summary = storage.reindex(ReindexMode::Full, &protocol, None).unwrap();
+        assert_eq!(summary.blocks_indexed, 3);
+        assert_eq!(summary.tip_height, Some(2));
+
+        let blocks = storage.blocks();
+        for (height, block) in chain.iter().enumerate() {
+            let hash = blocks.get_block_hash(block);
+            assert_eq!(
+                blocks.get_hash_by_height(height as u64).unwrap(),
+                Some(hash)
+            );
+            assert!(blocks.get_block(&hash).unwrap().is_some());
+        }
+        assert_eq!(storage.blocks().stored_block_hashes().unwrap().len(), 3);
+        storage.flush().unwrap();
+    }
+
+    assert!(Storage::with_backend(temp_dir.path(), default_backend()).is_ok());
+}
+
+#[test]
+fn test_reindex_mode_from_storage_config() {
+    let config: StorageConfig = toml::from_str("reindex = \"reindex_chainstate\"").unwrap();
+    assert_eq!(config.reindex, ReindexConfig::ReindexChainstate);
+    assert_eq!(
+        ReindexMode::from_config(config.reindex),
+        Some(ReindexMode::Chainstate)
+    );
+    assert_eq!(
+        ReindexMode::from_config(StorageConfig::default().reindex),
+        None
+    );
+}
@@ -228,7 +228,7 @@ mod tests {
     #[tokio::test]
     async fn test_fees_estimate() {
         let mining = create_test_mining_rpc();
-        let result = fees::get_fee_estimate(&mining, Some(6)).await;
+        let result = fees::get_fee_estimate(&mining, Some(6), None).await;
 
         // Should return a result
         assert!(result.is_ok() || result.is_err());
@@ -237,7 +237,7 @@ mod tests {
     #[tokio::test]
     async fn test_fees_estimate_default() {
         let mining = create_test_mining_rpc();
-        let result = fees::get_fee_estimate(&mining, None).await;
+        let result = fees::get_fee_estimate(&mining, None, None).await;
 
         // Should return a result (uses default 6 blocks)
         assert!(result.is_ok() || result.is_err());
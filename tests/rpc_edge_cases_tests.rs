@@ -63,7 +63,7 @@ async fn test_rpc_large_result_sets() {
     assert!(result.is_ok());
 
     // gettxoutsetinfo might return large UTXO sets
-    let result = blockchain.get_txoutset_info().await;
+    let result = blockchain.get_txoutset_info(&json!([])).await;
     // May fail without storage, but should handle gracefully
     let _ = result;
 }
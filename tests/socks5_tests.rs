@@ -0,0 +1,246 @@
+//! Tests for outbound connections through a SOCKS5 proxy
+
+use bllvm_node::network::netaddr::{NetAddr, Network, ServiceAddr};
+use bllvm_node::network::protocol::{ProtocolParser, BITCOIN_MAGIC_MAINNET};
+use bllvm_node::network::socks5::{ProxySettings, Socks5Proxy};
+use bllvm_node::network::transport::{
+    Transport, TransportAddr, TransportConnection, TransportListener, TransportProtocol,
+};
+use bllvm_node::network::v2_transport::V2Transport;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+/// CONNECT request seen by the mock proxy
+#[derive(Debug)]
+struct ProxyRequest {
+    credentials: Option<(String, String)>,
+    /// Host name or IP address
+    host: String,
+    port: u16,
+}
+
+/// Mock SOCKS5 proxy that forwards every CONNECT to `backend`
+///
+/// Replies with `reply_code` to the CONNECT; anything but 0 refuses it.
+async fn mock_proxy(
+    backend: SocketAddr,
+    reply_code: u8,
+) -> (SocketAddr, mpsc::UnboundedReceiver<ProxyRequest>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (request_tx, request_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let (client, _) = listener.accept().await.unwrap();
+            let request_tx = request_tx.clone();
+            tokio::spawn(async move {
+                let _ = serve(client, backend, reply_code, request_tx).await;
+            });
+        }
+    });
+    (addr, request_rx)
+}
+
+async fn serve(
+    mut client: TcpStream,
+    backend: SocketAddr,
+    reply_code: u8,
+    request_tx: mpsc::UnboundedSender<ProxyRequest>,
+) -> std::io::Result<()> {
+    let mut header = [0u8; 2];
+    client.read_exact(&mut header).await?;
+    let mut methods = vec![0u8; header[1] as usize];
+    client.read_exact(&mut methods).await?;
+
+    let mut credentials = None;
+    if methods.contains(&2) {
+        client.write_all(&[5, 2]).await?;
+        let mut version = [0u8; 1];
+        client.read_exact(&mut version).await?;
+        let username = read_string(&mut client).await?;
+        let password = read_string(&mut client).await?;
+        client.write_all(&[1, 0]).await?;
+        credentials = Some((username, password));
+    } else {
+        client.write_all(&[5, 0]).await?;
+    }
+
+    let mut request = [0u8; 4];
+    client.read_exact(&mut request).await?;
+    let host = match request[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            client.read_exact(&mut ip).await?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        }
+        3 => read_string(&mut client).await?,
+        4 => {
+            let mut ip = [0u8; 16];
+            client.read_exact(&mut ip).await?;
+            std::net::Ipv6Addr::from(ip).to_string()
+        }
+        _ => return Ok(()),
+    };
+    let port = client.read_u16().await?;
+    let _ = request_tx.send(ProxyRequest {
+        credentials,
+        host,
+        port,
+    });
+
+    client
+        .write_all(&[5, reply_code, 0, 1, 0, 0, 0, 0, 0, 0])
+        .await?;
+    if reply_code != 0 {
+        return Ok(());
+    }
+    let mut upstream = TcpStream::connect(backend).await?;
+    tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+async fn read_string(stream: &mut TcpStream) -> std::io::Result<String> {
+    let len = stream.read_u8().await?;
+    let mut bytes = vec![0u8; len as usize];
+    stream.read_exact(&mut bytes).await?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// v1 wire message: magic, command, length, checksum, payload
+fn wire_message(command: &str, payload: &[u8]) -> Vec<u8> {
+    let mut message = BITCOIN_MAGIC_MAINNET.to_vec();
+    let mut command_bytes = [0u8; 12];
+    command_bytes[..command.len()].copy_from_slice(command.as_bytes());
+    message.extend_from_slice(&command_bytes);
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(&ProtocolParser::calculate_checksum(payload));
+    message.extend_from_slice(payload);
+    message
+}
+
+fn localhost() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+fn onion() -> ServiceAddr {
+    ServiceAddr::new(NetAddr::TorV3([0x5a; 32]), 8333)
+}
+
+#[tokio::test]
+async fn test_connect_onion_through_proxy() {
+    let node = V2Transport::default();
+    let mut listener = node.listen(localhost()).await.unwrap();
+    let (proxy_addr, mut requests) = mock_proxy(listener.local_addr().unwrap(), 0).await;
+
+    let transport = V2Transport::default().with_proxies(
+        ProxySettings::new().with_proxy(Network::Onion, Socks5Proxy::new(proxy_addr)),
+    );
+    let target = onion();
+    let (outbound, inbound) = tokio::join!(transport.connect_service(&target), listener.accept());
+    let mut outbound = outbound.unwrap();
+    let (mut inbound, _) = inbound.unwrap();
+    // The v2 handshake runs end to end through the proxy
+    assert_eq!(outbound.transport_protocol(), TransportProtocol::V2);
+    assert_eq!(outbound.session_id(), inbound.session_id());
+
+    // Onion addresses are passed to the proxy as host names
+    let request = requests.recv().await.unwrap();
+    assert_eq!(request.host, onion().addr.to_string());
+    assert_eq!(request.port, 8333);
+
+    // Without a socket address the peer is identified by our end of the proxy connection
+    match outbound.peer_addr() {
+        TransportAddr::Tcp(addr) => assert!(addr.ip().is_loopback()),
+        #[allow(unreachable_patterns)]
+        other => panic!("Expected a TCP address, got {:?}", other),
+    }
+
+    let message = wire_message("ping", &42u64.to_le_bytes());
+    outbound.send(&message).await.unwrap();
+    inbound.send(&message).await.unwrap();
+    assert_eq!(outbound.recv().await.unwrap(), message);
+}
+
+#[tokio::test]
+async fn test_proxied_ip_connection_keeps_target_address() {
+    let node = V2Transport::default().with_v2(false);
+    let mut listener = node.listen(localhost()).await.unwrap();
+    let (proxy_addr, mut requests) = mock_proxy(listener.local_addr().unwrap(), 0).await;
+
+    let proxy = Socks5Proxy::new(proxy_addr);
+    let transport = V2Transport::default().with_v2(false).with_proxies(
+        ProxySettings::new()
+            .with_proxy(Network::Ipv4, proxy.clone())
+            .with_proxy(Network::Ipv6, proxy),
+    );
+    let target: SocketAddr = "1.2.3.4:8333".parse().unwrap();
+    let (outbound, inbound) = tokio::join!(
+        transport.connect(TransportAddr::Tcp(target)),
+        listener.accept()
+    );
+    let mut outbound = outbound.unwrap();
+    let (mut inbound, _) = inbound.unwrap();
+    assert_eq!(outbound.transport_protocol(), TransportProtocol::V1);
+    assert_eq!(outbound.peer_addr(), TransportAddr::Tcp(target));
+
+    let request = requests.recv().await.unwrap();
+    assert_eq!((request.host.as_str(), request.port), ("1.2.3.4", 8333));
+
+    let message = wire_message("version", &[7u8; 100]);
+    outbound.send(&message).await.unwrap();
+    assert_eq!(inbound.recv().await.unwrap(), message);
+}
+
+#[tokio::test]
+async fn test_random_credentials_isolate_streams() {
+    let node = V2Transport::default().with_v2(false);
+    let mut listener = node.listen(localhost()).await.unwrap();
+    let (proxy_addr, mut requests) = mock_proxy(listener.local_addr().unwrap(), 0).await;
+    tokio::spawn(async move { while listener.accept().await.is_ok() {} });
+
+    let proxy = Socks5Proxy::new(proxy_addr);
+    assert!(proxy.randomizes_credentials());
+    let first = proxy.connect(&onion()).await.unwrap();
+    let second = proxy.connect(&onion()).await.unwrap();
+    let first_credentials = requests.recv().await.unwrap().credentials.unwrap();
+    let second_credentials = requests.recv().await.unwrap().credentials.unwrap();
+    assert_ne!(first_credentials, second_credentials);
+    drop((first, second));
+
+    // Without randomization no credentials are offered
+    let proxy = proxy.with_randomized_credentials(false);
+    let _stream = proxy.connect(&onion()).await.unwrap();
+    assert!(requests.recv().await.unwrap().credentials.is_none());
+}
+
+#[tokio::test]
+async fn test_proxy_errors() {
+    // The proxy refuses the connection: host unreachable
+    let (proxy_addr, _requests) = mock_proxy(localhost(), 4).await;
+    let err = Socks5Proxy::new(proxy_addr)
+        .connect(&onion())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("host unreachable"), "{}", err);
+
+    // Onion addresses are unreachable without a proxy
+    let transport = V2Transport::default();
+    assert!(transport.connect_service(&onion()).await.is_err());
+}
+
+#[test]
+fn test_reachable_networks() {
+    let proxy = Socks5Proxy::new("127.0.0.1:9050".parse().unwrap());
+    let direct = ProxySettings::new();
+    assert!(direct.is_empty());
+    assert!(direct.is_reachable(Network::Ipv4));
+    assert!(!direct.is_reachable(Network::Onion));
+
+    let tor = ProxySettings::new().with_proxy(Network::Onion, proxy.clone());
+    assert!(tor.is_reachable(Network::Onion));
+    assert!(!tor.is_reachable(Network::I2p));
+    assert_eq!(tor.proxy_for(Network::Onion), Some(&proxy));
+    assert!(tor.proxy_for(Network::Ipv4).is_none());
+}
@@ -0,0 +1,283 @@
+//! Tests for atomic write batches, batched block connect/disconnect and the
+//! startup consistency check
+
+use bllvm_node::config::{PruningConfig, PruningMode};
+use bllvm_node::storage::database::WriteBatch;
+use bllvm_node::storage::pruning::PruningManager;
+use bllvm_node::OutPoint;
+use bllvm_protocol::block::calculate_tx_id;
+
+mod common;
+use common::{
+    connect_block, connect_chain, create_test_storage, make_block, plain_coinbase, spend,
+};
+
+#[test]
+fn test_write_batch_spans_trees_and_keeps_order() {
+    let (_temp_dir, storage) = create_test_storage();
+
+    let mut batch = WriteBatch::new();
+    batch.insert("chain_info", b"batch-a", b"1");
+    batch.insert("block_metadata", b"batch-b", b"2");
+    batch.insert("block_metadata", b"batch-c", b"3");
+    batch.remove("block_metadata", b"batch-c");
+    assert_eq!(batch.len(), 4);
+    assert_eq!(batch.tree_names(), vec!["block_metadata", "chain_info"]);
+
+    storage.apply_batch(&batch).unwrap();
+
+    let chain_info = storage.open_tree("chain_info").unwrap();
+    let metadata = storage.open_tree("block_metadata").unwrap();
+    assert_eq!(chain_info.get(b"batch-a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(metadata.get(b"batch-b").unwrap(), Some(b"2".to_vec()));
+    // Later remove of the same key wins
+    assert!(metadata.get(b"batch-c").unwrap().is_none());
+}
+
+#[test]
+fn test_empty_batch_is_noop() {
+    let (_temp_dir, storage) = create_test_storage();
+    let batch = WriteBatch::new();
+    assert!(batch.is_empty());
+    storage.apply_batch(&batch).unwrap();
+}
+
+#[test]
+fn test_tree_iterates_in_key_order_past_one_chunk_and_clears() {
+    let (_temp_dir, storage) = create_test_storage();
+    let tree = storage.open_tree("block_metadata").unwrap();
+
+    // More entries than a backend reads per chunk, written out of order
+    let count = 10_000u32;
+    let mut batch = WriteBatch::new();
+    for i in (0..count).rev() {
+        batch.insert("block_metadata", &i.to_be_bytes(), &[1]);
+    }
+    storage.apply_batch(&batch).unwrap();
+
+    let keys: Vec<_> = tree.iter().map(|entry| entry.unwrap().0).collect();
+    let expected: Vec<_> = (0..count).map(|i| i.to_be_bytes().to_vec()).collect();
+    assert_eq!(keys, expected);
+
+    tree.clear().unwrap();
+    assert_eq!(tree.len().unwrap(), 0);
+    assert!(tree.iter().next().is_none());
+}
+
+#[test]
+fn test_connect_block_commits_all_state() {
+    let (_temp_dir, storage) = create_test_storage();
+    let blocks = connect_chain(&storage, 1);
+    let genesis_hash = storage.blocks().get_block_hash(&blocks[0]);
+
+    // Block 1 spends the genesis coinbase
+    let funding = OutPoint {
+        hash: calculate_tx_id(&blocks[0].transactions[0]),
+        index: 0,
+    };
+    let tx = spend(funding.clone(), 49_0000_0000);
+    let block = make_block(genesis_hash, 1, vec![plain_coinbase(1), tx.clone()]);
+    connect_block(&storage, &block, 1);
+    let hash = storage.blocks().get_block_hash(&block);
+
+    assert!(storage.blocks().has_block_body(&hash).unwrap());
+    assert_eq!(storage.blocks().get_hash_by_height(1).unwrap(), Some(hash));
+    assert_eq!(storage.blocks().get_height_by_hash(&hash).unwrap(), Some(1));
+    assert_eq!(
+        storage
+            .blocks()
+            .get_undo(&hash)
+            .unwrap()
+            .unwrap()
+            .spent_outputs
+            .len(),
+        1
+    );
+
+    assert_eq!(storage.chain().get_height().unwrap(), Some(1));
+    assert_eq!(storage.chain().get_tip_hash().unwrap(), Some(hash));
+    assert!(!storage
+        .chain()
+        .get_chainwork(&hash)
+        .unwrap()
+        .unwrap()
+        .is_zero());
+
+    let spend_outpoint = OutPoint {
+        hash: calculate_tx_id(&tx),
+        index: 0,
+    };
+    storage.flush().unwrap();
+    let utxos = storage.utxos();
+    assert!(!utxos.has_utxo(&funding).unwrap());
+    assert_eq!(
+        utxos.get_utxo(&spend_outpoint).unwrap().unwrap().value,
+        49_0000_0000
+    );
+    assert_eq!(utxos.utxo_count().unwrap(), 2);
+    assert_eq!(utxos.get_best_block().unwrap(), Some((hash, 1)));
+
+    assert!(storage
+        .transactions()
+        .get_transaction(&calculate_tx_id(&tx))
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn test_disconnect_block_reverts_connect() {
+    let (_temp_dir, storage) = create_test_storage();
+    let blocks = connect_chain(&storage, 1);
+    let genesis_hash = storage.blocks().get_block_hash(&blocks[0]);
+
+    let funding = OutPoint {
+        hash: calculate_tx_id(&blocks[0].transactions[0]),
+        index: 0,
+    };
+    let tx = spend(funding.clone(), 49_0000_0000);
+    let block = make_block(genesis_hash, 1, vec![plain_coinbase(1), tx.clone()]);
+    let undo = connect_block(&storage, &block, 1);
+    let hash = storage.blocks().get_block_hash(&block);
+
+    assert!(storage.utxo_cache().revert_block(&block, 1, &undo).unwrap());
+    storage.disconnect_block(&block, 1).unwrap();
+
+    // Body is kept, but the block is no longer part of the active chain
+    assert!(storage.blocks().has_block_body(&hash).unwrap());
+    assert!(storage.blocks().get_hash_by_height(1).unwrap().is_none());
+    assert!(!storage.blocks().is_in_main_chain(&hash).unwrap());
+    assert!(storage.blocks().get_undo(&hash).unwrap().is_none());
+
+    assert_eq!(storage.chain().get_height().unwrap(), Some(0));
+    assert_eq!(storage.chain().get_tip_hash().unwrap(), Some(genesis_hash));
+
+    // The UTXO cache is flushed with the disconnect
+    let utxos = storage.utxos();
+    assert!(utxos.has_utxo(&funding).unwrap());
+    assert_eq!(utxos.utxo_count().unwrap(), 1);
+    assert_eq!(utxos.get_best_block().unwrap(), Some((genesis_hash, 0)));
+
+    assert!(storage
+        .transactions()
+        .get_transaction(&calculate_tx_id(&tx))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_disconnect_genesis_fails() {
+    let (_temp_dir, storage) = create_test_storage();
+    let blocks = connect_chain(&storage, 1);
+    assert!(storage.disconnect_block(&blocks[0], 0).is_err());
+}
+
+#[test]
+fn test_consistency_check_clean_after_batched_connects() {
+    let (_temp_dir, storage) = create_test_storage();
+    connect_chain(&storage, 5);
+
+    let report = storage.check_consistency().unwrap();
+    assert!(report.is_clean());
+    assert_eq!(report.tip_height, Some(4));
+}
+
+#[test]
+fn test_consistency_check_rolls_back_index_above_tip() {
+    let (_temp_dir, storage) = create_test_storage();
+    let blocks = connect_chain(&storage, 3);
+    let tip_hash = storage.blocks().get_block_hash(&blocks[2]);
+
+    // Simulate an interrupted connect: block indexed but tip never updated
+    let orphaned = make_block(tip_hash, 3, vec![plain_coinbase(3)]);
+    let orphaned_hash = storage.blocks().get_block_hash(&orphaned);
+    storage.blocks().store_block(&orphaned).unwrap();
+    storage.blocks().store_height(3, &orphaned_hash).unwrap();
+
+    let report = storage.check_consistency().unwrap();
+    assert_eq!(report.heights_rolled_back, 1);
+    assert_eq!(report.heights_restored, 0);
+    assert!(storage.blocks().get_hash_by_height(3).unwrap().is_none());
+    assert!(storage
+        .blocks()
+        .get_height_by_hash(&orphaned_hash)
+        .unwrap()
+        .is_none());
+    assert_eq!(storage.chain().get_height().unwrap(), Some(2));
+
+    assert!(storage.check_consistency().unwrap().is_clean());
+}
+
+#[test]
+fn test_consistency_check_restores_index_below_tip() {
+    let (_temp_dir, storage) = create_test_storage();
+    let blocks = connect_chain(&storage, 4);
+
+    // Simulate an interrupted disconnect that removed index entries only
+    storage.blocks().remove_height(3).unwrap();
+    storage.blocks().remove_height(2).unwrap();
+
+    let report = storage.check_consistency().unwrap();
+    assert_eq!(report.heights_restored, 2);
+    for (height, block) in blocks.iter().enumerate() {
+        let hash = storage.blocks().get_block_hash(block);
+        assert_eq!(
+            storage.blocks().get_hash_by_height(height as u64).unwrap(),
+            Some(hash)
+        );
+    }
+}
+
+#[test]
+fn test_consistency_check_rebuilds_missing_tip() {
+    let (_temp_dir, storage) = create_test_storage();
+
+    // Blocks indexed without a chain tip record (as written by older versions)
+    let mut prev = [0u8; 32];
+    for height in 0..3 {
+        let block = make_block(prev, height, vec![plain_coinbase(height)]);
+        bllvm_node::node::block_processor::store_block_with_context(
+            &storage.blocks(),
+            &block,
+            &[],
+            height,
+        )
+        .unwrap();
+        prev = storage.blocks().get_block_hash(&block);
+    }
+    assert!(storage.chain().get_height().unwrap().is_none());
+
+    let report = storage.check_consistency().unwrap();
+    assert!(report.tip_rebuilt);
+    // No UTXO set was written with the blocks, so it is rebuilt from them
+    assert_eq!(report.utxo_blocks_replayed, 3);
+    assert_eq!(storage.utxo_cache().utxo_count().unwrap(), 3);
+    assert_eq!(storage.chain().get_height().unwrap(), Some(2));
+    assert_eq!(storage.chain().get_tip_hash().unwrap(), Some(prev));
+}
+
+#[test]
+fn test_pruning_removes_bodies_and_undo_in_one_batch() {
+    let (_temp_dir, storage) = create_test_storage();
+    let blocks = connect_chain(&storage, 6);
+
+    let config = PruningConfig {
+        mode: PruningMode::Normal {
+            keep_from_height: 3,
+            min_recent_blocks: 0,
+        },
+        min_blocks_to_keep: 1,
+        ..Default::default()
+    };
+    let manager = PruningManager::new(config, storage.blocks());
+    let stats = manager.prune_to_height(3, 5, false).unwrap();
+    assert_eq!(stats.blocks_pruned, 3);
+
+    for (height, block) in blocks.iter().enumerate() {
+        let hash = storage.blocks().get_block_hash(block);
+        let pruned = height < 3;
+        assert_eq!(storage.blocks().has_block_body(&hash).unwrap(), !pruned);
+        assert_eq!(storage.blocks().get_undo(&hash).unwrap().is_some(), !pruned);
+        // Headers are always kept
+        assert!(storage.blocks().get_header(&hash).unwrap().is_some());
+    }
+}
@@ -91,6 +91,25 @@ fn test_sync_state_machine_progress_updates() {
     assert!(machine.progress() > 0.0);
 }
 
+#[test]
+fn test_sync_state_machine_block_progress() {
+    let mut machine = SyncStateMachine::new();
+    machine.transition_to(SyncState::Blocks);
+    // Nothing known to download yet
+    assert_eq!(machine.progress(), 0.0);
+
+    // Progress is the fraction of the best header chain connected
+    machine.update_heights(250, 1000);
+    assert_eq!(machine.progress(), 0.25);
+    machine.update_heights(750, 1000);
+    assert_eq!(machine.progress(), 0.75);
+    machine.update_heights(1200, 1000);
+    assert_eq!(machine.progress(), 1.0);
+
+    machine.transition_to(SyncState::Synced);
+    assert_eq!(machine.progress(), 1.0);
+}
+
 #[test]
 fn test_sync_state_all_variants() {
     // Test that all SyncState variants can be created
@@ -0,0 +1,137 @@
+//! Tests for the Tor control port client against a mock control port
+
+use bllvm_node::network::netaddr::Network;
+use bllvm_node::network::tor_control::TorControl;
+use std::net::SocketAddr;
+use tempfile::TempDir;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// Service ID of a valid v3 onion address
+const SERVICE_ID: &str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid";
+
+/// Mock control port answering PROTOCOLINFO with `auth_line`
+///
+/// AUTHENTICATE succeeds only with `expected_auth`; ADD_ONION returns
+/// [`SERVICE_ID`]. Every command received is reported.
+async fn mock_control_port(
+    auth_line: String,
+    expected_auth: String,
+) -> (SocketAddr, mpsc::UnboundedReceiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(command)) = lines.next_line().await {
+            let reply = if command.starts_with("PROTOCOLINFO") {
+                format!(
+                    "250-PROTOCOLINFO 1\r\n250-{}\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n",
+                    auth_line
+                )
+            } else if command.starts_with("AUTHENTICATE") {
+                if command == expected_auth {
+                    "250 OK\r\n".to_string()
+                } else {
+                    "515 Authentication failed: Password did not match\r\n".to_string()
+                }
+            } else if command.starts_with("ADD_ONION") {
+                format!("250-ServiceID={}\r\n250 OK\r\n", SERVICE_ID)
+            } else if command.starts_with("GETINFO") {
+                "250+config-text=\r\nSocksPort 9050\r\n..dotted\r\n.\r\n250 OK\r\n".to_string()
+            } else {
+                "510 Unrecognized command\r\n".to_string()
+            };
+            let _ = command_tx.send(command);
+            if writer.write_all(reply.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+    (addr, command_rx)
+}
+
+#[tokio::test]
+async fn test_add_onion_with_null_auth() {
+    let (addr, mut commands) =
+        mock_control_port("AUTH METHODS=NULL".to_string(), "AUTHENTICATE".to_string()).await;
+    let mut control = TorControl::connect(addr).await.unwrap();
+    control.authenticate(None).await.unwrap();
+
+    let target: SocketAddr = "127.0.0.1:8333".parse().unwrap();
+    let service = control.add_onion(8333, target).await.unwrap();
+    assert_eq!(service.addr.network(), Network::Onion);
+    assert_eq!(service.port, 8333);
+    assert_eq!(service.addr.to_string(), format!("{}.onion", SERVICE_ID));
+
+    assert_eq!(commands.recv().await.unwrap(), "PROTOCOLINFO 1");
+    assert_eq!(commands.recv().await.unwrap(), "AUTHENTICATE");
+    assert_eq!(
+        commands.recv().await.unwrap(),
+        "ADD_ONION NEW:ED25519-V3 Flags=DiscardPK Port=8333,127.0.0.1:8333"
+    );
+}
+
+#[tokio::test]
+async fn test_password_auth() {
+    let (addr, _commands) = mock_control_port(
+        "AUTH METHODS=HASHEDPASSWORD".to_string(),
+        r#"AUTHENTICATE "se\"cret""#.to_string(),
+    )
+    .await;
+    let mut control = TorControl::connect(addr).await.unwrap();
+    control.authenticate(Some("se\"cret")).await.unwrap();
+
+    // A wrong password is reported, a missing one caught before sending
+    let (addr, _commands) = mock_control_port(
+        "AUTH METHODS=HASHEDPASSWORD".to_string(),
+        r#"AUTHENTICATE "secret""#.to_string(),
+    )
+    .await;
+    let mut control = TorControl::connect(addr).await.unwrap();
+    let err = control.authenticate(Some("wrong")).await.unwrap_err();
+    assert!(err.to_string().contains("515"), "{}", err);
+    assert!(!err.to_string().contains("wrong"));
+    let err = control.authenticate(None).await.unwrap_err();
+    assert!(err.to_string().contains("password"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_cookie_auth() {
+    let temp_dir = TempDir::new().unwrap();
+    let cookie_path = temp_dir.path().join("control_auth_cookie");
+    let cookie = [0x5au8; 32];
+    std::fs::write(&cookie_path, cookie).unwrap();
+
+    let (addr, _commands) = mock_control_port(
+        format!(
+            "AUTH METHODS=COOKIE,SAFECOOKIE COOKIEFILE=\"{}\"",
+            cookie_path.display()
+        ),
+        format!("AUTHENTICATE {}", hex::encode(cookie)),
+    )
+    .await;
+    let mut control = TorControl::connect(addr).await.unwrap();
+    control.authenticate(None).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_multiline_replies() {
+    let (addr, _commands) =
+        mock_control_port("AUTH METHODS=NULL".to_string(), "AUTHENTICATE".to_string()).await;
+    let mut control = TorControl::connect(addr).await.unwrap();
+
+    let reply = control.command("GETINFO config-text").await.unwrap();
+    assert_eq!(reply.code, 250);
+    assert_eq!(
+        reply.lines,
+        vec!["config-text=", "SocksPort 9050", ".dotted", "OK"]
+    );
+
+    let reply = control.command("BOGUS").await.unwrap();
+    assert_eq!(reply.code, 510);
+    assert_eq!(reply.lines, vec!["Unrecognized command"]);
+}
@@ -0,0 +1,189 @@
+//! Tests for TRUC (version 3) and ephemeral anchor mempool policy
+
+use bllvm_node::config::MempoolPolicyConfig;
+use bllvm_node::node::mempool::MempoolManager;
+use bllvm_node::node::truc::PolicyViolation;
+use bllvm_protocol::block::calculate_tx_id;
+use bllvm_protocol::{
+    Hash, OutPoint, Transaction, TransactionInput, TransactionOutput, UtxoSet, UTXO,
+};
+
+/// Transaction spending `(txid, index)` outputs, with one output per value
+fn tx(version: u64, spends: &[(Hash, u64)], values: &[u64]) -> Transaction {
+    Transaction {
+        version,
+        inputs: spends
+            .iter()
+            .map(|(hash, index)| TransactionInput {
+                prevout: OutPoint {
+                    hash: *hash,
+                    index: *index,
+                },
+                script_sig: vec![],
+                sequence: 0xffffffff,
+            })
+            .collect::<Vec<_>>()
+            .into(),
+        outputs: values
+            .iter()
+            .map(|value| TransactionOutput {
+                value: *value as i64,
+                script_pubkey: vec![0x51],
+            })
+            .collect::<Vec<_>>()
+            .into(),
+        lock_time: 0,
+    }
+}
+
+fn utxo_set(coins: &[(Hash, u64)]) -> UtxoSet {
+    coins
+        .iter()
+        .map(|(hash, value)| {
+            (
+                OutPoint {
+                    hash: *hash,
+                    index: 0,
+                },
+                UTXO {
+                    value: *value as i64,
+                    script_pubkey: vec![0x51],
+                    height: 1,
+                    is_coinbase: false,
+                },
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_truc_topology_limits() {
+    let utxos = utxo_set(&[([1; 32], 101_000)]);
+    let mempool = MempoolManager::new();
+    let parent = tx(3, &[([1; 32], 0)], &[50_000, 50_000]);
+    let parent_id = calculate_tx_id(&parent);
+    assert!(mempool.add_transaction(parent, &utxos).await.unwrap());
+
+    let non_truc_child = tx(2, &[(parent_id, 0)], &[49_000]);
+    assert_eq!(
+        mempool.check_truc_policy(&non_truc_child),
+        Err(PolicyViolation::NonTrucSpendsTruc)
+    );
+    assert!(!mempool
+        .add_transaction(non_truc_child, &utxos)
+        .await
+        .unwrap());
+
+    let mut large_child = tx(3, &[(parent_id, 0)], &[49_000]);
+    large_child.outputs[0].script_pubkey = vec![0x51; 1_000];
+    assert_eq!(
+        mempool.check_truc_policy(&large_child),
+        Err(PolicyViolation::TrucChildTooBig)
+    );
+    assert!(!mempool.add_transaction(large_child, &utxos).await.unwrap());
+
+    let child = tx(3, &[(parent_id, 0)], &[49_000]);
+    let child_id = calculate_tx_id(&child);
+    assert!(mempool.add_transaction(child, &utxos).await.unwrap());
+
+    let grandchild = tx(3, &[(child_id, 0)], &[48_000]);
+    assert_eq!(
+        mempool.check_truc_policy(&grandchild),
+        Err(PolicyViolation::TrucTooManyAncestors)
+    );
+    assert!(!mempool.add_transaction(grandchild, &utxos).await.unwrap());
+    assert_eq!(mempool.size(), 2);
+}
+
+#[tokio::test]
+async fn test_truc_sibling_eviction() {
+    let utxos = utxo_set(&[([1; 32], 101_000), ([2; 32], 10_000)]);
+    let mempool = MempoolManager::new();
+    let parent = tx(3, &[([1; 32], 0)], &[50_000, 50_000]);
+    let parent_id = calculate_tx_id(&parent);
+    // Also spends a confirmed coin, which counts towards its 11_000 fee
+    let first = tx(3, &[(parent_id, 0), ([2; 32], 0)], &[49_000]);
+    let first_id = calculate_tx_id(&first);
+    assert!(mempool.add_transaction(parent, &utxos).await.unwrap());
+    assert!(mempool.add_transaction(first, &utxos).await.unwrap());
+    assert_eq!(mempool.entry_feefrac(&first_id).unwrap().fee, 11_000);
+
+    // Pays less than the child it would evict
+    let cheap = tx(3, &[(parent_id, 1)], &[45_000]);
+    assert_eq!(mempool.check_truc_policy(&cheap), Ok(Some(first_id)));
+    assert!(!mempool.add_transaction(cheap, &utxos).await.unwrap());
+    assert!(mempool.get_transaction(&first_id).is_some());
+
+    let second = tx(3, &[(parent_id, 1)], &[30_000]);
+    let second_id = calculate_tx_id(&second);
+    assert!(mempool.add_transaction(second, &utxos).await.unwrap());
+    assert!(mempool.get_transaction(&first_id).is_none());
+    assert!(mempool.get_transaction(&second_id).is_some());
+    assert_eq!(mempool.size(), 2);
+}
+
+#[tokio::test]
+async fn test_ephemeral_anchor_spent_in_package() {
+    let utxos = utxo_set(&[([1; 32], 100_000)]);
+    let mempool = MempoolManager::new();
+    let parent = tx(3, &[([1; 32], 0)], &[100_000, 0]);
+    let parent_id = calculate_tx_id(&parent);
+
+    // Alone, or with a child leaving the anchor unspent
+    assert_eq!(
+        mempool.check_truc_policy(&parent),
+        Err(PolicyViolation::MissingEphemeralSpends)
+    );
+    assert!(!mempool
+        .add_transaction(parent.clone(), &utxos)
+        .await
+        .unwrap());
+    let skips_anchor = tx(3, &[(parent_id, 0)], &[90_000]);
+    let acceptance = mempool.check_package(&[parent.clone(), skips_anchor], &utxos);
+    assert_eq!(
+        acceptance.reject_reason.as_deref(),
+        Some("missing-ephemeral-spends")
+    );
+
+    // An anchor on a fee-paying transaction
+    let paying = tx(3, &[([1; 32], 0)], &[90_000, 0]);
+    let child = tx(
+        3,
+        &[(calculate_tx_id(&paying), 0), (calculate_tx_id(&paying), 1)],
+        &[80_000],
+    );
+    let acceptance = mempool.check_package(&[paying, child], &utxos);
+    assert_eq!(
+        acceptance.reject_reason.as_deref(),
+        Some("dust, tx with dust output must be 0-fee")
+    );
+
+    let child = tx(3, &[(parent_id, 0), (parent_id, 1)], &[90_000]);
+    let acceptance = mempool
+        .add_package(vec![parent, child], &utxos)
+        .await
+        .unwrap();
+    assert!(acceptance.all_allowed(), "{acceptance:?}");
+    assert_eq!(mempool.size(), 2);
+}
+
+#[tokio::test]
+async fn test_truc_rules_can_be_disabled() {
+    let utxos = utxo_set(&[([1; 32], 51_000)]);
+    let mempool = MempoolManager::new();
+    mempool.set_policy_config(Some(MempoolPolicyConfig {
+        truc_enabled: false,
+        ephemeral_anchors_enabled: false,
+        ..Default::default()
+    }));
+    let parent = tx(3, &[([1; 32], 0)], &[50_000]);
+    let child = tx(2, &[(calculate_tx_id(&parent), 0)], &[49_000]);
+    assert!(mempool.add_transaction(parent, &utxos).await.unwrap());
+    assert!(mempool.add_transaction(child, &utxos).await.unwrap());
+
+    let anchored = tx(2, &[([2; 32], 0)], &[50_000, 0]);
+    assert_eq!(
+        mempool.check_truc_policy(&anchored),
+        Err(PolicyViolation::Dust)
+    );
+}
@@ -0,0 +1,339 @@
+//! Tests for the UTXO cache: dirty/fresh tracking, flush triggers, and
+//! replaying unflushed blocks at startup
+
+use bllvm_node::config::{PruningConfig, PruningMode, StorageCacheConfig};
+use bllvm_node::node::block_processor::commit_connected_block;
+use bllvm_node::storage::pruning::PruningManager;
+use bllvm_node::storage::Storage;
+use bllvm_node::{Block, OutPoint, UTXO};
+use bllvm_protocol::block::calculate_tx_id;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+
+mod common;
+use common::{connect_chain, create_test_storage, make_block, plain_coinbase};
+
+fn outpoint(byte: u8, index: u64) -> OutPoint {
+    OutPoint {
+        hash: [byte; 32],
+        index,
+    }
+}
+
+fn utxo(value: i64) -> UTXO {
+    UTXO {
+        value,
+        script_pubkey: vec![0x51; 25],
+        height: 1,
+        is_coinbase: false,
+    }
+}
+
+/// Connect `count` more blocks on top of `parent` at `parent_height + 1`
+fn connect_chain_from(
+    storage: &Arc<Storage>,
+    parent: &Block,
+    parent_height: u64,
+    count: u64,
+) -> Vec<Block> {
+    let mut cache = storage.utxo_cache();
+    let mut prev = storage.blocks().get_block_hash(parent);
+    let mut blocks = Vec::new();
+    for height in (parent_height + 1)..=(parent_height + count) {
+        let block = make_block(prev, height, vec![plain_coinbase(height)]);
+        commit_connected_block(
+            &storage.blocks(),
+            Some(storage),
+            &block,
+            &[],
+            height,
+            &Default::default(),
+            &mut cache,
+        )
+        .unwrap();
+        prev = storage.blocks().get_block_hash(&block);
+        blocks.push(block);
+    }
+    blocks
+}
+
+fn coinbase_outpoint(block: &Block) -> OutPoint {
+    OutPoint {
+        hash: calculate_tx_id(&block.transactions[0]),
+        index: 0,
+    }
+}
+
+#[test]
+fn test_cache_reads_through_to_store() {
+    let (_temp_dir, storage) = create_test_storage();
+    storage
+        .utxos()
+        .add_utxo(&outpoint(1, 0), &utxo(1000))
+        .unwrap();
+
+    let cache = storage.utxo_cache();
+    assert!(cache.is_empty());
+    assert_eq!(
+        cache.get_utxo(&outpoint(1, 0)).unwrap().unwrap().value,
+        1000
+    );
+    // Loaded entries are cached but clean
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.dirty_count(), 0);
+    assert!(cache.memory_usage() > 0);
+    assert!(cache.get_utxo(&outpoint(2, 0)).unwrap().is_none());
+}
+
+#[test]
+fn test_fetch_coins_loads_only_requested_outpoints() {
+    let (_temp_dir, storage) = create_test_storage();
+    for byte in 1..=3 {
+        storage
+            .utxos()
+            .add_utxo(&outpoint(byte, 0), &utxo(1000 * byte as i64))
+            .unwrap();
+    }
+    let cache = storage.utxo_cache();
+    cache.add_utxo(&outpoint(4, 0), &utxo(4000)).unwrap();
+    cache.remove_utxo(&outpoint(2, 0)).unwrap();
+
+    let requested = [
+        outpoint(1, 0),
+        outpoint(2, 0),
+        outpoint(4, 0),
+        outpoint(5, 0),
+    ];
+    let coins = cache.fetch_coins(&requested).unwrap();
+    // Spent and unknown outpoints are left out, unflushed coins included
+    assert_eq!(coins.len(), 2);
+    assert_eq!(coins[&outpoint(1, 0)].value, 1000);
+    assert_eq!(coins[&outpoint(4, 0)].value, 4000);
+    assert!(!coins.contains_key(&outpoint(3, 0)));
+}
+
+#[test]
+fn test_fresh_coin_spent_before_flush_is_never_written() {
+    let (_temp_dir, storage) = create_test_storage();
+    let cache = storage.utxo_cache();
+
+    cache.add_utxo(&outpoint(1, 0), &utxo(1000)).unwrap();
+    assert_eq!(cache.dirty_count(), 1);
+    assert_eq!(
+        cache.remove_utxo(&outpoint(1, 0)).unwrap().unwrap().value,
+        1000
+    );
+    assert!(cache.is_empty());
+
+    cache.flush().unwrap();
+    assert_eq!(storage.utxos().utxo_count().unwrap(), 0);
+}
+
+#[test]
+fn test_flush_writes_additions_and_spends() {
+    let (_temp_dir, storage) = create_test_storage();
+    storage
+        .utxos()
+        .add_utxo(&outpoint(1, 0), &utxo(1000))
+        .unwrap();
+    let cache = storage.utxo_cache();
+
+    cache.remove_utxo(&outpoint(1, 0)).unwrap();
+    cache.add_utxo(&outpoint(2, 0), &utxo(2000)).unwrap();
+
+    // Unflushed changes are visible through the cache only
+    assert!(storage.utxos().has_utxo(&outpoint(1, 0)).unwrap());
+    assert!(!cache.has_utxo(&outpoint(1, 0)).unwrap());
+    assert_eq!(cache.utxo_count().unwrap(), 1);
+    let all = cache.get_all_utxos().unwrap();
+    assert_eq!(all.len(), 1);
+    assert!(all.contains_key(&outpoint(2, 0)));
+
+    cache.flush().unwrap();
+    assert_eq!(cache.dirty_count(), 0);
+    assert!(!storage.utxos().has_utxo(&outpoint(1, 0)).unwrap());
+    assert_eq!(
+        storage
+            .utxos()
+            .get_utxo(&outpoint(2, 0))
+            .unwrap()
+            .unwrap()
+            .value,
+        2000
+    );
+}
+
+#[test]
+fn test_for_each_utxo_merges_unflushed_changes_in_order() {
+    let (_temp_dir, storage) = create_test_storage();
+    for (outpoint, value) in [
+        (outpoint(1, 0), 1000),
+        (outpoint(1, 2), 1200),
+        (outpoint(3, 256), 3256),
+        (outpoint(3, 0), 3000),
+    ] {
+        storage.utxos().add_utxo(&outpoint, &utxo(value)).unwrap();
+    }
+    let cache = storage.utxo_cache();
+    cache.remove_utxo(&outpoint(1, 2)).unwrap();
+    cache.add_utxo(&outpoint(1, 1), &utxo(1100)).unwrap();
+    cache.add_utxo(&outpoint(2, 0), &utxo(2000)).unwrap();
+    cache.add_utxo(&outpoint(4, 0), &utxo(4000)).unwrap();
+
+    let mut visited = Vec::new();
+    cache
+        .for_each_utxo(|outpoint, utxo| {
+            visited.push((outpoint.clone(), utxo.value));
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(
+        visited,
+        vec![
+            (outpoint(1, 0), 1000),
+            (outpoint(1, 1), 1100),
+            (outpoint(2, 0), 2000),
+            (outpoint(3, 0), 3000),
+            (outpoint(3, 256), 3256),
+            (outpoint(4, 0), 4000),
+        ]
+    );
+}
+
+#[test]
+fn test_cache_over_budget_flushes_and_evicts() {
+    let (_temp_dir, storage) = create_test_storage();
+    let cache = storage.utxo_cache();
+    cache.set_limits(1024, Duration::from_secs(3600));
+
+    for index in 0..4 {
+        cache.add_utxo(&outpoint(1, index), &utxo(1000)).unwrap();
+    }
+    assert!(!cache.should_flush());
+    for index in 4..64 {
+        cache.add_utxo(&outpoint(1, index), &utxo(1000)).unwrap();
+    }
+    assert!(cache.should_flush());
+
+    cache.flush().unwrap();
+    assert!(cache.is_empty());
+    assert_eq!(cache.memory_usage(), 0);
+    assert_eq!(storage.utxos().utxo_count().unwrap(), 64);
+    assert!(!cache.should_flush());
+}
+
+#[test]
+fn test_flush_interval_triggers_flush() {
+    let (_temp_dir, storage) = create_test_storage();
+    let cache = storage.utxo_cache();
+    cache.set_limits(usize::MAX, Duration::ZERO);
+    assert!(cache.should_flush());
+
+    storage.configure_cache(&StorageCacheConfig::default());
+    assert!(!cache.should_flush());
+}
+
+#[test]
+fn test_connected_blocks_stay_in_cache_until_flush() {
+    let (_temp_dir, storage) = create_test_storage();
+    let blocks = connect_chain(&storage, 3);
+    let tip_hash = storage.blocks().get_block_hash(&blocks[2]);
+    let cache = storage.utxo_cache();
+
+    assert_eq!(cache.best_block(), Some((tip_hash, 2)));
+    assert_eq!(cache.dirty_count(), 3);
+    assert_eq!(storage.utxos().utxo_count().unwrap(), 0);
+    assert!(storage.utxos().get_best_block().unwrap().is_none());
+
+    let created = cache
+        .get_utxo(&coinbase_outpoint(&blocks[1]))
+        .unwrap()
+        .unwrap();
+    assert_eq!(created.height, 1);
+    assert!(created.is_coinbase);
+
+    storage.flush().unwrap();
+    assert_eq!(storage.utxos().utxo_count().unwrap(), 3);
+    assert_eq!(
+        storage.utxos().get_best_block().unwrap(),
+        Some((tip_hash, 2))
+    );
+}
+
+#[test]
+fn test_due_flush_is_committed_with_block() {
+    let (_temp_dir, storage) = create_test_storage();
+    storage.utxo_cache().set_limits(usize::MAX, Duration::ZERO);
+
+    let blocks = connect_chain(&storage, 2);
+    let tip_hash = storage.blocks().get_block_hash(&blocks[1]);
+    assert_eq!(storage.utxo_cache().dirty_count(), 0);
+    assert_eq!(storage.utxos().utxo_count().unwrap(), 2);
+    assert_eq!(
+        storage.utxos().get_best_block().unwrap(),
+        Some((tip_hash, 1))
+    );
+}
+
+#[test]
+fn test_unflushed_blocks_are_replayed_at_startup() {
+    let temp_dir = TempDir::new().unwrap();
+    let blocks = {
+        let storage = Arc::new(Storage::new(temp_dir.path()).unwrap());
+        let blocks = connect_chain(&storage, 2);
+        // Flush the first two blocks, as a periodic flush would
+        storage.utxo_cache().flush().unwrap();
+        let more = connect_chain_from(&storage, &blocks[1], 1, 2);
+        // Dropped without a final flush, like an unclean shutdown
+        blocks.into_iter().chain(more).collect::<Vec<_>>()
+    };
+
+    let storage = Arc::new(Storage::new(temp_dir.path()).unwrap());
+    assert_eq!(storage.utxos().utxo_count().unwrap(), 2);
+
+    let report = storage.check_consistency().unwrap();
+    assert_eq!(report.utxo_blocks_replayed, 2);
+    assert!(!report.is_clean());
+
+    let tip_hash = storage.blocks().get_block_hash(&blocks[3]);
+    assert_eq!(storage.utxos().utxo_count().unwrap(), 4);
+    assert_eq!(
+        storage.utxos().get_best_block().unwrap(),
+        Some((tip_hash, 3))
+    );
+    for block in &blocks {
+        assert!(storage
+            .utxo_cache()
+            .has_utxo(&coinbase_outpoint(block))
+            .unwrap());
+    }
+
+    assert!(storage.check_consistency().unwrap().is_clean());
+}
+
+#[test]
+fn test_pruning_flushes_cache_first() {
+    let (_temp_dir, storage) = create_test_storage();
+    let blocks = connect_chain(&storage, 6);
+    let tip_hash = storage.blocks().get_block_hash(&blocks[5]);
+
+    let config = PruningConfig {
+        mode: PruningMode::Normal {
+            keep_from_height: 3,
+            min_recent_blocks: 0,
+        },
+        min_blocks_to_keep: 1,
+        ..Default::default()
+    };
+    let manager =
+        PruningManager::new(config, storage.blocks()).with_utxo_cache(storage.utxo_cache());
+    manager.prune_to_height(3, 5, false).unwrap();
+
+    // Pruned bodies are no longer needed to rebuild the UTXO set
+    assert_eq!(
+        storage.utxos().get_best_block().unwrap(),
+        Some((tip_hash, 5))
+    );
+    assert!(storage.check_consistency().unwrap().is_clean());
+}
@@ -0,0 +1,118 @@
+//! Tests for the BIP324 v2 transport and its v1 fallback
+
+use bllvm_node::network::protocol::{ProtocolParser, BITCOIN_MAGIC_MAINNET};
+use bllvm_node::network::tcp_transport::TcpTransport;
+use bllvm_node::network::transport::{
+    Transport, TransportAddr, TransportConnection, TransportListener, TransportProtocol,
+};
+use bllvm_node::network::v2_transport::V2Transport;
+use std::net::SocketAddr;
+
+/// v1 wire message: magic, command, length, checksum, payload
+fn wire_message(command: &str, payload: &[u8]) -> Vec<u8> {
+    let mut message = BITCOIN_MAGIC_MAINNET.to_vec();
+    let mut command_bytes = [0u8; 12];
+    command_bytes[..command.len()].copy_from_slice(command.as_bytes());
+    message.extend_from_slice(&command_bytes);
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(&ProtocolParser::calculate_checksum(payload));
+    message.extend_from_slice(payload);
+    message
+}
+
+fn localhost() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+#[tokio::test]
+async fn test_v2_connection_exchanges_messages() {
+    let transport = V2Transport::default();
+    let mut listener = transport.listen(localhost()).await.unwrap();
+    let addr = TransportAddr::Tcp(listener.local_addr().unwrap());
+
+    let (outbound, inbound) = tokio::join!(transport.connect(addr), listener.accept());
+    let mut outbound = outbound.unwrap();
+    let (mut inbound, _) = inbound.unwrap();
+    assert_eq!(outbound.transport_protocol(), TransportProtocol::V2);
+    assert_eq!(inbound.transport_protocol(), TransportProtocol::V2);
+    assert!(outbound.session_id().is_some());
+    assert_eq!(outbound.session_id(), inbound.session_id());
+
+    // A command with a short ID, one without, and a large payload
+    let ping = wire_message("ping", &42u64.to_le_bytes());
+    let version = wire_message("version", &[7u8; 100]);
+    let block = wire_message("block", &vec![9u8; 20_000]);
+    for message in [&ping, &version, &block] {
+        outbound.send(message).await.unwrap();
+    }
+    inbound.send_decoy(50).await.unwrap();
+    inbound.send(&ping).await.unwrap();
+
+    assert_eq!(inbound.recv().await.unwrap(), ping);
+    assert_eq!(inbound.recv().await.unwrap(), version);
+    assert_eq!(inbound.recv().await.unwrap(), block);
+    // The decoy is skipped
+    assert_eq!(outbound.recv().await.unwrap(), ping);
+
+    outbound.close().await.unwrap();
+    assert!(inbound.recv().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_v2_listener_accepts_v1_peers() {
+    let mut listener = V2Transport::default().listen(localhost()).await.unwrap();
+    let addr = TransportAddr::Tcp(listener.local_addr().unwrap());
+
+    let mut outbound = TcpTransport::new().connect(addr).await.unwrap();
+    let version = wire_message("version", &[1u8; 80]);
+    let verack = wire_message("verack", &[]);
+    outbound.send(&version).await.unwrap();
+    outbound.send(&verack).await.unwrap();
+
+    let (mut inbound, _) = listener.accept().await.unwrap();
+    assert_eq!(inbound.transport_protocol(), TransportProtocol::V1);
+    assert_eq!(inbound.session_id(), None);
+    assert_eq!(inbound.recv().await.unwrap(), version);
+    assert_eq!(inbound.recv().await.unwrap(), verack);
+
+    inbound.send(&verack).await.unwrap();
+    assert_eq!(outbound.recv().await.unwrap(), verack);
+}
+
+#[tokio::test]
+async fn test_v2_connect_downgrades_to_v1() {
+    let mut listener = TcpTransport::new().listen(localhost()).await.unwrap();
+    let addr = TransportAddr::Tcp(listener.local_addr().unwrap());
+
+    // A v1 peer disconnects on the v2 key, then accepts the v1 retry
+    let peer = tokio::spawn(async move {
+        let (rejected, _) = listener.accept().await.unwrap();
+        drop(rejected);
+        let (mut conn, _) = listener.accept().await.unwrap();
+        conn.recv().await.unwrap()
+    });
+
+    let mut outbound = V2Transport::default().connect(addr).await.unwrap();
+    assert_eq!(outbound.transport_protocol(), TransportProtocol::V1);
+    let version = wire_message("version", &[1u8; 80]);
+    outbound.send(&version).await.unwrap();
+    assert_eq!(peer.await.unwrap(), version);
+}
+
+#[tokio::test]
+async fn test_v2_disabled_uses_v1() {
+    let transport = V2Transport::default().with_v2(false);
+    assert!(!transport.v2_enabled());
+    let mut listener = transport.listen(localhost()).await.unwrap();
+    let addr = TransportAddr::Tcp(listener.local_addr().unwrap());
+
+    let (outbound, inbound) = tokio::join!(transport.connect(addr), listener.accept());
+    let mut outbound = outbound.unwrap();
+    let (mut inbound, _) = inbound.unwrap();
+    assert_eq!(outbound.transport_protocol(), TransportProtocol::V1);
+    assert_eq!(inbound.transport_protocol(), TransportProtocol::V1);
+
+    let ping = wire_message("ping", &[0u8; 8]);
+    outbound.send(&ping).await.unwrap();
+    assert_eq!(inbound.recv().await.unwrap(), ping);
+}
@@ -0,0 +1,129 @@
+//! Offline block maintenance for a bllvm-node data directory
+//!
+//! Subcommands:
+//! - `import-core <blocks-dir>`: import blocks from a Bitcoin Core `blocks`
+//!   directory without the P2P network. Interrupted imports resume where they
+//!   left off when run again.
+
+use bllvm_node::config::NodeConfig;
+use bllvm_node::node::import::{CoreBlockImporter, ImportPhase};
+use bllvm_node::storage::chainstate::ChainParams;
+use bllvm_node::storage::database::default_backend;
+use bllvm_node::storage::Storage;
+use bllvm_protocol::{BitcoinProtocolEngine, ProtocolVersion};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Parser)]
+#[command(
+    name = "bllvm-blocks",
+    about = "Offline block maintenance for bllvm-node"
+)]
+struct Cli {
+    /// bllvm-node data directory
+    #[arg(long)]
+    datadir: PathBuf,
+
+    /// Network the data directory belongs to
+    #[arg(long, value_enum, default_value = "mainnet")]
+    network: Network,
+
+    /// bllvm-node config.toml; its [storage] section selects the block storage
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Import blocks from Bitcoin Core's blocks directory (blk*.dat)
+    ImportCore {
+        /// Bitcoin Core blocks directory, e.g. ~/.bitcoin/blocks
+        blocks_dir: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Network {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl Network {
+    fn name(self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Regtest => "regtest",
+        }
+    }
+
+    fn protocol_version(self) -> ProtocolVersion {
+        match self {
+            Network::Mainnet => ProtocolVersion::BitcoinV1,
+            Network::Testnet => ProtocolVersion::Testnet3,
+            Network::Regtest => ProtocolVersion::Regtest,
+        }
+    }
+}
+
+fn open_storage(cli: &Cli) -> anyhow::Result<Arc<Storage>> {
+    let storage_config = match &cli.config {
+        Some(path) => NodeConfig::from_file(path)?.storage,
+        None => None,
+    };
+    let storage = match storage_config {
+        Some(config) => Storage::with_config(&cli.datadir, default_backend(), &config)?,
+        None => Storage::with_backend(&cli.datadir, default_backend())?,
+    };
+    storage
+        .chain()
+        .store_chain_params(&ChainParams::for_network(cli.network.name()))?;
+    Ok(Arc::new(storage))
+}
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+
+    match &cli.command {
+        Command::ImportCore { blocks_dir } => {
+            let storage = open_storage(&cli)?;
+            let protocol = BitcoinProtocolEngine::new(cli.network.protocol_version())?;
+            let importer = CoreBlockImporter::new(blocks_dir, cli.network.protocol_version())?;
+
+            let summary =
+                importer.import(&storage, &protocol, |progress| match progress.phase {
+                    ImportPhase::Scanning => eprintln!(
+                        "Scanning blk{:05}.dat ({}/{}): {} blocks found",
+                        progress.file,
+                        progress.file + 1,
+                        progress.files,
+                        progress.blocks_scanned
+                    ),
+                    ImportPhase::Connecting => eprintln!(
+                        "Connecting: height {} of {}, {} blocks connected",
+                        progress.height.map_or("-".to_string(), |h| h.to_string()),
+                        progress
+                            .target_height
+                            .map_or("-".to_string(), |h| h.to_string()),
+                        progress.blocks_connected
+                    ),
+                })?;
+
+            println!("✓ Import complete");
+            println!("  Blocks scanned:   {}", summary.blocks_scanned);
+            println!("  Blocks connected: {}", summary.blocks_connected);
+            println!("  Blocks skipped:   {}", summary.blocks_skipped);
+            match summary.tip_height {
+                Some(height) => println!("  Tip height:       {}", height),
+                None => println!("  Tip height:       (empty chain)"),
+            }
+        }
+    }
+    Ok(())
+}